@@ -1,6 +1,7 @@
 use std::fs;
 use std::path::Path;
 use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 fn read_git_head_ref() -> Option<String> {
     // Try to read .git/HEAD and resolve the ref
@@ -14,25 +15,25 @@ fn read_git_head_ref() -> Option<String> {
     if head.starts_with("ref: ") {
         let ref_path = head.trim_start_matches("ref: ").trim();
         let ref_file = Path::new(".git").join(ref_path);
-        if ref_file.exists() {
-            if let Ok(hash) = fs::read_to_string(ref_file) {
-                return Some(hash.trim().to_string());
-            }
+        if ref_file.exists()
+            && let Ok(hash) = fs::read_to_string(ref_file)
+        {
+            return Some(hash.trim().to_string());
         }
 
         // Fallback: try packed-refs (simple scan)
         let packed = Path::new(".git/packed-refs");
-        if packed.exists() {
-            if let Ok(lines) = fs::read_to_string(packed) {
-                for line in lines.lines() {
-                    if line.starts_with('#') || line.trim().is_empty() {
-                        continue;
-                    }
-                    if let Some((hash, refname)) = line.split_once(' ') {
-                        if refname.trim() == ref_path {
-                            return Some(hash.trim().to_string());
-                        }
-                    }
+        if packed.exists()
+            && let Ok(lines) = fs::read_to_string(packed)
+        {
+            for line in lines.lines() {
+                if line.starts_with('#') || line.trim().is_empty() {
+                    continue;
+                }
+                if let Some((hash, refname)) = line.split_once(' ')
+                    && refname.trim() == ref_path
+                {
+                    return Some(hash.trim().to_string());
                 }
             }
         }
@@ -44,31 +45,82 @@ fn read_git_head_ref() -> Option<String> {
     None
 }
 
-fn main() {
-    let git_hash = Command::new("git")
-        .args(["rev-parse", "--short", "HEAD"])
+fn git_output(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
         .output()
         .ok()
-        .and_then(|o| {
-            if o.status.success() {
-                Some(o.stdout)
-            } else {
-                None
-            }
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_string())
+}
+
+/// Seconds since the epoch to stamp the build with. SOURCE_DATE_EPOCH wins
+/// so reproducible builds embed the same time.
+fn build_timestamp() -> u64 {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
         })
-        .and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+fn rustc_version() -> Option<String> {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8(o.stdout).ok())
         .map(|s| s.trim().to_string())
+}
+
+/// Enabled crate features, lowercased, comma-separated.
+fn crate_features() -> String {
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(k, _)| k.strip_prefix("CARGO_FEATURE_").map(|f| f.to_lowercase()))
+        .collect();
+    features.sort();
+    features.join(",")
+}
+
+fn main() {
+    let full_hash = git_output(&["rev-parse", "HEAD"]).or_else(read_git_head_ref);
+    let git_hash = git_output(&["rev-parse", "--short", "HEAD"])
         .or_else(|| {
-            // Try to read full hash from .git and shorten to 7 chars
-            read_git_head_ref().and_then(|full| {
+            // Shorten the full hash to 7 chars
+            full_hash.as_ref().map(|full| {
                 if full.len() >= 7 {
-                    Some(full[..7].to_string())
+                    full[..7].to_string()
                 } else {
-                    Some(full)
+                    full.clone()
                 }
             })
         })
         .unwrap_or_else(|| "unknown".to_string());
 
     println!("cargo:rustc-env=GIT_HASH_SHORT={}", git_hash);
+    println!(
+        "cargo:rustc-env=GIT_HASH_FULL={}",
+        full_hash.as_deref().unwrap_or("unknown")
+    );
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_timestamp());
+    println!(
+        "cargo:rustc-env=TARGET={}",
+        std::env::var("TARGET").unwrap_or_else(|_| "unknown".to_string())
+    );
+    println!(
+        "cargo:rustc-env=RUSTC_VERSION={}",
+        rustc_version().unwrap_or_else(|| "unknown".to_string())
+    );
+    println!("cargo:rustc-env=CRATE_FEATURES={}", crate_features());
+    println!(
+        "cargo:rustc-env=TARGET_FEATURES={}",
+        std::env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default()
+    );
 }
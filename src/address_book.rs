@@ -0,0 +1,140 @@
+//! Names for addresses. The address book is a JSON object of address to
+//! label in the config directory (`owonero wallet label` edits it). History
+//! output shows the labels next to addresses, and `--send --to` takes a
+//! label in place of an address.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AddressBook {
+    /// Lowercased address to label.
+    entries: BTreeMap<String, String>,
+}
+
+fn key(address: &str) -> String {
+    address.trim().to_lowercase()
+}
+
+impl AddressBook {
+    /// An empty book when the file doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("{} is not an address book", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Label `address`, or forget it when `label` is `None`.
+    pub fn set(&mut self, address: &str, label: Option<&str>) {
+        match label.map(str::trim).filter(|l| !l.is_empty()) {
+            Some(label) => {
+                self.entries.insert(key(address), label.to_string());
+            }
+            None => {
+                self.entries.remove(&key(address));
+            }
+        }
+    }
+
+    pub fn label(&self, address: &str) -> Option<&str> {
+        self.entries.get(&key(address)).map(String::as_str)
+    }
+
+    /// The address `to` stands for: the one labelled `to`, or `to` itself.
+    pub fn resolve(&self, to: &str) -> String {
+        self.entries
+            .iter()
+            .find(|(_, label)| label.as_str() == to.trim())
+            .map(|(address, _)| address.clone())
+            .unwrap_or_else(|| to.to_string())
+    }
+}
+
+/// Labels addresses as seen from one wallet.
+pub struct Labels<'a> {
+    own: String,
+    book: &'a AddressBook,
+}
+
+impl<'a> Labels<'a> {
+    pub fn new(own_address: &str, book: &'a AddressBook) -> Self {
+        Self {
+            own: key(own_address),
+            book,
+        }
+    }
+
+    /// "self" for the wallet's own address, "coinbase" for mining rewards,
+    /// else the address book's label.
+    pub fn label(&self, address: &str) -> Option<&str> {
+        if key(address) == self.own {
+            Some("self")
+        } else if address == "coinbase" {
+            Some("coinbase")
+        } else {
+            self.book.label(address)
+        }
+    }
+
+    /// Address prefix for one-line output, with its label when it has one.
+    pub fn short(&self, address: &str) -> String {
+        let prefix = &address[..address.len().min(8)];
+        match self.label(address) {
+            Some(label) => format!("{} ({})", prefix, label),
+            None => prefix.to_string(),
+        }
+    }
+
+    /// Labels of whichever of `addresses` have one, for JSON output next to
+    /// the raw addresses.
+    pub fn of<'b>(&self, addresses: impl IntoIterator<Item = &'b str>) -> BTreeMap<String, String> {
+        addresses
+            .into_iter()
+            .filter_map(|a| Some((a.to_string(), self.label(a)?.to_string())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn own_coinbase_and_booked_addresses_are_labelled() {
+        let mut book = AddressBook::default();
+        book.set("04EXCHANGE", Some("exchange"));
+        book.set("04pool", Some(" pool payouts "));
+        book.set("04gone", Some("old"));
+        book.set("04gone", None);
+        let labels = Labels::new("04ME", &book);
+
+        assert_eq!(labels.short("04me"), "04me (self)");
+        assert_eq!(labels.short("coinbase"), "coinbase (coinbase)");
+        assert_eq!(labels.short("04exchange0123"), "04exchan");
+        assert_eq!(labels.short("04Exchange"), "04Exchan (exchange)");
+        assert_eq!(labels.label("04pool"), Some("pool payouts"));
+        assert_eq!(labels.label("04gone"), None);
+        assert_eq!(
+            labels.of(["coinbase", "04stranger", "04me"]),
+            BTreeMap::from([
+                ("04me".to_string(), "self".to_string()),
+                ("coinbase".to_string(), "coinbase".to_string()),
+            ])
+        );
+
+        // Sending resolves labels and leaves anything else alone
+        assert_eq!(book.resolve("exchange"), "04exchange");
+        assert_eq!(book.resolve("04abcdef"), "04abcdef");
+    }
+}
@@ -0,0 +1,508 @@
+//! Batch payouts: `--send --batch payouts.csv` pays many addresses from one
+//! wallet in a single run. Every row is validated before anything is sent,
+//! and each outcome is written to a results file so an interrupted run can
+//! resume with `--skip-completed`.
+
+use crate::blockchain::Blockchain;
+use crate::error::AppError;
+use crate::interrupt::Interrupted;
+use crate::policy::Blocklist;
+use crate::wallet::Wallet;
+use anyhow::{Result, anyhow};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+
+/// `format` tag of results files written by a batch run.
+pub const RESULTS_FORMAT: &str = "owonero-batch-results";
+const RESULTS_VERSION: u32 = 1;
+
+/// One payout from the batch file. `row` is the line number in a CSV file
+/// or the 1-based position in a JSON array.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Payout {
+    pub row: usize,
+    pub address: String,
+    /// Amount in internal units (milli-OWE).
+    pub amount: i64,
+    /// Free text kept in the results file; it is not part of the transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RowStatus {
+    /// Accepted into the node's mempool.
+    Sent,
+    /// The node refused the transaction.
+    Rejected,
+    /// Not attempted, or the node could not be reached.
+    NotSent,
+    /// The address is on this node's `blocked_addresses` list.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RowResult {
+    #[serde(flatten)]
+    pub payout: Payout,
+    pub status: RowStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchResults {
+    pub format: String,
+    pub version: u32,
+    pub rows: Vec<RowResult>,
+}
+
+/// How `run_batch` should behave; built from the CLI flags.
+pub struct BatchOptions {
+    pub dry_run: bool,
+    pub skip_completed: Option<PathBuf>,
+    pub results: PathBuf,
+}
+
+/// Results file used when `--results` is not given: `payouts.csv` ->
+/// `payouts.results.json`.
+pub fn default_results_path(batch: &Path) -> PathBuf {
+    batch.with_extension("results.json")
+}
+
+/// Parse a decimal OWE amount into internal units. Must be positive.
+pub fn parse_owe(text: &str) -> Option<i64> {
+    let owe: f64 = text.trim().parse().ok()?;
+    if !owe.is_finite() {
+        return None;
+    }
+    let units = (owe * 1000.0).round();
+    (units >= 1.0 && units <= i64::MAX as f64).then_some(units as i64)
+}
+
+/// Parse a batch file's contents. JSON files hold an array of
+/// `{address, amount, memo?}`; anything else is CSV with rows of
+/// `address,amount[,memo]`, an optional `address,...` header, blank lines
+/// and `#` comments. Returns every problem found, one per entry.
+pub fn parse_payouts(text: &str, json: bool) -> Result<Vec<Payout>, Vec<String>> {
+    let mut payouts = Vec::new();
+    let mut problems = Vec::new();
+    let mut check = |row: usize, address: &str, amount: Option<i64>, memo: Option<String>| {
+        let address = address.trim();
+        if !crate::wallet::is_valid_address(address) {
+            problems.push(format!("row {}: invalid address {:?}", row, address));
+        } else if let Some(amount) = amount {
+            payouts.push(Payout {
+                row,
+                address: address.to_lowercase(),
+                amount,
+                memo: memo.filter(|m| !m.is_empty()),
+            });
+        } else {
+            problems.push(format!("row {}: amount must be a positive number", row));
+        }
+    };
+
+    if json {
+        let rows: Vec<serde_json::Value> = match serde_json::from_str(text) {
+            Ok(rows) => rows,
+            Err(e) => return Err(vec![format!("not a JSON array of payouts: {}", e)]),
+        };
+        for (i, row) in rows.iter().enumerate() {
+            let amount = match row.get("amount") {
+                Some(serde_json::Value::String(s)) => parse_owe(s),
+                Some(serde_json::Value::Number(n)) => parse_owe(&n.to_string()),
+                _ => None,
+            };
+            let memo = row.get("memo").and_then(|m| m.as_str()).map(str::to_string);
+            check(
+                i + 1,
+                row.get("address").and_then(|a| a.as_str()).unwrap_or(""),
+                amount,
+                memo,
+            );
+        }
+    } else {
+        let mut seen_data = false;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // The memo may itself contain commas
+            let mut fields = line.splitn(3, ',');
+            let address = fields.next().unwrap_or("");
+            if !seen_data && address.trim().eq_ignore_ascii_case("address") {
+                seen_data = true;
+                continue;
+            }
+            seen_data = true;
+            let amount = fields.next().and_then(parse_owe);
+            let memo = fields.next().map(|m| m.trim().to_string());
+            check(i + 1, address, amount, memo);
+        }
+    }
+
+    if payouts.is_empty() && problems.is_empty() {
+        problems.push("no payouts in file".to_string());
+    }
+    if problems.is_empty() {
+        Ok(payouts)
+    } else {
+        Err(problems)
+    }
+}
+
+pub fn load_payouts(path: &Path) -> Result<Vec<Payout>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?;
+    let json = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    parse_payouts(&text, json).map_err(|problems| {
+        anyhow!(
+            "{} has {} problem(s): {}",
+            path.display(),
+            problems.len(),
+            problems.join("; ")
+        )
+    })
+}
+
+pub fn write_results(path: &Path, rows: &[RowResult]) -> Result<()> {
+    let file = BatchResults {
+        format: RESULTS_FORMAT.to_string(),
+        version: RESULTS_VERSION,
+        rows: rows.to_vec(),
+    };
+    // Replaced whole, so a run stopped mid-write still leaves one to resume from
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(&file)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+pub fn read_results(path: &Path) -> Result<BatchResults> {
+    let data = std::fs::read_to_string(path)?;
+    let file: BatchResults = serde_json::from_str(&data)?;
+    if file.format != RESULTS_FORMAT || file.version != RESULTS_VERSION {
+        return Err(anyhow!(
+            "{} is not a version {} batch results file",
+            path.display(),
+            RESULTS_VERSION
+        ));
+    }
+    Ok(file)
+}
+
+/// Fresh results for `payouts`, carrying over rows a previous run already
+/// sent. A row only counts as done when its number, address and amount all
+/// still match, so an edited batch file never skips a changed payout.
+/// Rows paying a blocked address are skipped.
+pub fn plan_rows(
+    payouts: &[Payout],
+    previous: Option<&BatchResults>,
+    blocklist: &Blocklist,
+) -> Vec<RowResult> {
+    payouts
+        .iter()
+        .map(|payout| {
+            if blocklist.is_blocked(&payout.address) {
+                return RowResult {
+                    payout: payout.clone(),
+                    status: RowStatus::Skipped,
+                    txid: None,
+                    error: Some("blocked address".to_string()),
+                };
+            }
+            let done = previous.and_then(|prev| {
+                prev.rows.iter().find(|r| {
+                    r.status == RowStatus::Sent
+                        && r.payout.row == payout.row
+                        && r.payout.address == payout.address
+                        && r.payout.amount == payout.amount
+                })
+            });
+            RowResult {
+                payout: payout.clone(),
+                status: if done.is_some() {
+                    RowStatus::Sent
+                } else {
+                    RowStatus::NotSent
+                },
+                txid: done.and_then(|r| r.txid.clone()),
+                error: None,
+            }
+        })
+        .collect()
+}
+
+fn print_table(rows: &[RowResult], dry_run: bool) {
+    println!(
+        "{:>5}  {:<18}  {:>14}  {:<10}  txid / reason",
+        "row", "address", "amount", "status"
+    );
+    for r in rows {
+        let status = match (r.status, dry_run) {
+            (RowStatus::Sent, _) => "sent".green(),
+            (RowStatus::Rejected, _) => "rejected".red(),
+            (RowStatus::NotSent, true) => "would send".cyan(),
+            (RowStatus::NotSent, false) => "not sent".yellow(),
+            (RowStatus::Skipped, _) => "skipped".yellow(),
+        };
+        println!(
+            "{:>5}  {:<18}  {:>14}  {:<10}  {}",
+            r.payout.row,
+            format!("{}…", &r.payout.address[..16]),
+            Blockchain::format_owe(r.payout.amount),
+            status,
+            r.txid.as_deref().or(r.error.as_deref()).unwrap_or("")
+        );
+    }
+}
+
+/// Validate the whole batch against the wallet's balance on the first
+/// reachable node, then submit the rows in file order, saving the results
+/// file after every row. Once `cancel` fires no further row is started.
+/// Returns the node used.
+pub async fn run_batch(
+    batch: &Path,
+    options: &BatchOptions,
+    wallet: &Wallet,
+    nodes: &[String],
+    cancel: &CancellationToken,
+) -> Result<String, AppError> {
+    let payouts = load_payouts(batch).map_err(|e| AppError::usage(format!("{:#}", e)))?;
+    let previous = match &options.skip_completed {
+        Some(path) => Some(read_results(path).map_err(AppError::local_file)?),
+        None => None,
+    };
+    let mut rows = plan_rows(
+        &payouts,
+        previous.as_ref(),
+        &crate::policy::configured_blocklist(),
+    );
+    for r in rows.iter().filter(|r| r.status == RowStatus::Skipped) {
+        eprintln!(
+            "{}",
+            format!(
+                "Skipping row {}: {} is on the blocked_addresses list",
+                r.payout.row, r.payout.address
+            )
+            .yellow()
+        );
+    }
+
+    let address = wallet.address.as_str();
+    let (node, (_, balance)) = crate::with_node_fallback(nodes, |node| async move {
+        crate::ws_client::fetch_balance(&node, address, None).await
+    })
+    .await?;
+    let total: i64 = rows
+        .iter()
+        .filter(|r| r.status == RowStatus::NotSent)
+        .map(|r| r.payout.amount)
+        .sum();
+    if total > balance {
+        return Err(AppError::usage(format!(
+            "batch needs {} but the wallet holds {}",
+            Blockchain::format_owe(total),
+            Blockchain::format_owe(balance)
+        )));
+    }
+    if options.dry_run {
+        print_table(&rows, true);
+        println!(
+            "Dry run: {} to send via {}, nothing submitted",
+            Blockchain::format_owe(total),
+            node
+        );
+        return Ok(node);
+    }
+
+    let mut unreachable = None;
+    let mut interrupted = false;
+    for i in 0..rows.len() {
+        if rows[i].status != RowStatus::NotSent {
+            continue;
+        }
+        if cancel.is_cancelled() {
+            interrupted = true;
+            break;
+        }
+        let row = &mut rows[i];
+        let tx = wallet
+            .create_signed_transaction(&row.payout.address, row.payout.amount)
+            .map_err(AppError::Other)?;
+        match crate::ws_client::submit_tx(&node, &tx).await {
+            Ok(status) if status == "ok" => {
+                row.status = RowStatus::Sent;
+                row.txid = Some(tx.txid());
+            }
+            Ok(status) => {
+                row.status = RowStatus::Rejected;
+                row.error = Some(status);
+            }
+            Err(e) => {
+                row.error = Some(format!("{:#}", e));
+                unreachable = Some(row.payout.row);
+            }
+        }
+        write_results(&options.results, &rows).map_err(AppError::local_file)?;
+        if unreachable.is_some() {
+            break;
+        }
+    }
+    if interrupted {
+        write_results(&options.results, &rows).map_err(AppError::local_file)?;
+    }
+    print_table(&rows, false);
+    println!("Results written to {}", options.results.display());
+
+    let rejected = rows
+        .iter()
+        .filter(|r| r.status == RowStatus::Rejected)
+        .count();
+    if interrupted {
+        let sent = rows.iter().filter(|r| r.status == RowStatus::Sent).count();
+        return Err(Interrupted(format!(
+            "{} of {} payouts sent; rerun with --skip-completed {} to resume",
+            sent,
+            rows.len(),
+            options.results.display()
+        ))
+        .into());
+    }
+    if let Some(row) = unreachable {
+        return Err(AppError::network(anyhow!(
+            "lost node {} at row {}; rerun with --skip-completed {} to resume",
+            node,
+            row,
+            options.results.display()
+        )));
+    }
+    if rejected > 0 {
+        return Err(AppError::rejected(format!(
+            "{} of {} payouts rejected; see {}",
+            rejected,
+            rows.len(),
+            options.results.display()
+        )));
+    }
+    Ok(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(c: char) -> String {
+        format!("04{}", c.to_string().repeat(128))
+    }
+
+    #[test]
+    fn csv_rows_parse_with_header_comments_and_memos() {
+        let text = format!(
+            "address,amount,memo\n# weekly payouts\n\n{},1.5,pool, week 3\n{},0.001\n",
+            addr('a'),
+            addr('B')
+        );
+        let payouts = parse_payouts(&text, false).unwrap();
+        assert_eq!(payouts.len(), 2);
+        assert_eq!(payouts[0].row, 4);
+        assert_eq!(payouts[0].amount, 1500);
+        assert_eq!(payouts[0].memo.as_deref(), Some("pool, week 3"));
+        assert_eq!(payouts[1].address, addr('b'));
+        assert_eq!(payouts[1].amount, 1);
+        assert_eq!(payouts[1].memo, None);
+    }
+
+    #[test]
+    fn every_bad_row_is_reported() {
+        let text = format!(
+            "bob,1\n{},0\n{},lots\n{},2\n",
+            addr('a'),
+            addr('a'),
+            addr('c')
+        );
+        let problems = parse_payouts(&text, false).unwrap_err();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("row 1: invalid address"));
+        assert!(problems[1].starts_with("row 2: amount"));
+        assert!(problems[2].starts_with("row 3: amount"));
+        assert_eq!(parse_payouts("# nothing\n", false).unwrap_err().len(), 1);
+    }
+
+    #[test]
+    fn json_payouts_accept_numbers_and_strings() {
+        let text = serde_json::json!([
+            {"address": addr('a'), "amount": 2},
+            {"address": addr('b'), "amount": "0.25", "memo": "faucet"},
+        ])
+        .to_string();
+        let payouts = parse_payouts(&text, true).unwrap();
+        assert_eq!(payouts[0].amount, 2000);
+        assert_eq!(payouts[1].row, 2);
+        assert_eq!(payouts[1].amount, 250);
+        assert!(parse_payouts("{}", true).is_err());
+    }
+
+    #[test]
+    fn resume_skips_only_unchanged_sent_rows() {
+        let payouts = parse_payouts(
+            &format!("{},1\n{},2\n{},3\n", addr('a'), addr('b'), addr('c')),
+            false,
+        )
+        .unwrap();
+        let mut previous = plan_rows(&payouts, None, &Blocklist::default());
+        previous[0].status = RowStatus::Sent;
+        previous[0].txid = Some("t1".to_string());
+        previous[1].status = RowStatus::Rejected;
+        previous[2].status = RowStatus::Sent;
+        previous[2].payout.amount = 9000;
+        let previous = BatchResults {
+            format: RESULTS_FORMAT.to_string(),
+            version: RESULTS_VERSION,
+            rows: previous,
+        };
+
+        let rows = plan_rows(&payouts, Some(&previous), &Blocklist::default());
+        let statuses: Vec<RowStatus> = rows.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            vec![RowStatus::Sent, RowStatus::NotSent, RowStatus::NotSent]
+        );
+        assert_eq!(rows[0].txid.as_deref(), Some("t1"));
+    }
+
+    #[test]
+    fn payouts_to_blocked_addresses_are_skipped() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("blocked.txt");
+        std::fs::write(&file, format!("# sanctioned\n{}  # case 17\n\n", addr('B'))).unwrap();
+        let mut blocklist = Blocklist::new(vec![addr('a')], Some(file.clone()));
+        assert_eq!(blocklist.reload().unwrap(), 2);
+        let _ = std::fs::remove_file(&file);
+
+        let payouts = parse_payouts(
+            &format!("{},1\n{},2\n{},3\n", addr('a'), addr('b'), addr('c')),
+            false,
+        )
+        .unwrap();
+        let rows = plan_rows(&payouts, None, &blocklist);
+        let statuses: Vec<RowStatus> = rows.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            vec![RowStatus::Skipped, RowStatus::Skipped, RowStatus::NotSent]
+        );
+        assert_eq!(rows[1].error.as_deref(), Some("blocked address"));
+        // A missing file keeps the list that was loaded
+        assert!(blocklist.reload().is_err());
+        assert!(blocklist.is_blocked(&addr('b')));
+    }
+}
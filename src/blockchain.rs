@@ -1,7 +1,6 @@
 use anyhow::{Context, Result, anyhow};
 use chrono::{DateTime, Utc};
-use hex;
-use reqwest::Client;
+use crossbeam_queue::ArrayQueue;
 use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair};
 use serde::{Deserialize, Serialize};
 use sha3::Digest;
@@ -10,6 +9,8 @@ use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::path::Path;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
 // RandomX-inspired RX/OWO Parameters (module-level so they can be reused without reallocating)
@@ -20,27 +21,126 @@ const DEFAULT_ITERATIONS: usize = 2048; // Default iterations; tuned for reasona
 const L1_CACHE_SIZE: usize = 16 * 1024; // 16KB L1 cache simulation
 const L2_CACHE_SIZE: usize = 256 * 1024; // 256KB L2 cache simulation
 
+/// Known (height, hash) pairs a bootstrap snapshot must agree with.
+pub const CHECKPOINTS: &[(u64, &str)] = &[];
+/// Blocks the difficulty schedule averages block times over.
+pub const DIFFICULTY_WINDOW: usize = 10;
+/// Highest difficulty the schedule ever asks for.
+pub const MAX_DIFFICULTY: u32 = 7;
+
 thread_local! {
-    // Reusable per-thread scratchpad to avoid allocating 2MB each hash.
-    // We attempt to enable huge pages (transparent hugepages via madvise) when
-    // the environment variable `OWONERO_USE_HUGEPAGES=1` is set. If enabled but
-    // the kernel does not support it, we silently fall back to normal pages.
+    // Reusable per-thread scratchpad for mining threads, so hashing never
+    // waits on the shared pool. We attempt to enable huge pages (transparent
+    // hugepages via madvise) when the environment variable
+    // `OWONERO_USE_HUGEPAGES=1` is set. If enabled but the kernel does not
+    // support it, we silently fall back to normal pages.
     static SCRATCHPAD_BUF: RefCell<Vec<u8>> = RefCell::new(init_scratchpad());
 }
 
+/// Scratchpads kept for validation hashing (config `scratchpad_pool_size`).
+/// Only read when the pool is first used.
+static SCRATCHPAD_POOL_SIZE: AtomicUsize = AtomicUsize::new(2);
+static SCRATCHPAD_POOL: OnceLock<ScratchpadPool> = OnceLock::new();
+
+pub fn set_scratchpad_pool_size(size: usize) {
+    SCRATCHPAD_POOL_SIZE.store(size, Ordering::Relaxed);
+}
+
+/// Hash of `Blockchain::create_genesis_block`, worked out once. Nodes with
+/// another genesis are on another chain.
+pub fn genesis_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| Blockchain::create_genesis_block().hash)
+}
+
+/// Refuse legacy transaction formats (config `strict_consensus`).
+static STRICT_CONSENSUS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_strict_consensus(strict: bool) {
+    STRICT_CONSENSUS.store(strict, Ordering::Relaxed);
+}
+
+pub fn strict_consensus() -> bool {
+    STRICT_CONSENSUS.load(Ordering::Relaxed)
+}
+
+/// Scratchpads shared by every thread that hashes outside the miner.
+/// Borrowing allocates when the pool is empty; returning drops the buffer
+/// when the pool is full, so idle memory stays at the pool's capacity
+/// however many runtime threads have validated a block.
+struct ScratchpadPool {
+    free: Option<ArrayQueue<Vec<u8>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Validation scratchpad pool usage since start, for `getinfo`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScratchpadPoolStats {
+    pub capacity: usize,
+    pub pooled: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl ScratchpadPool {
+    fn global() -> &'static Self {
+        SCRATCHPAD_POOL.get_or_init(|| {
+            let size = SCRATCHPAD_POOL_SIZE.load(Ordering::Relaxed);
+            Self {
+                free: (size > 0).then(|| ArrayQueue::new(size)),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+            }
+        })
+    }
+
+    fn take(&self) -> Vec<u8> {
+        match self.free.as_ref().and_then(ArrayQueue::pop) {
+            Some(buf) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                buf
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                init_scratchpad()
+            }
+        }
+    }
+
+    fn give_back(&self, buf: Vec<u8>) {
+        if let Some(free) = &self.free {
+            let _ = free.push(buf);
+        }
+    }
+}
+
+pub fn scratchpad_pool_stats() -> ScratchpadPoolStats {
+    let pool = ScratchpadPool::global();
+    ScratchpadPoolStats {
+        capacity: pool.free.as_ref().map_or(0, ArrayQueue::capacity),
+        pooled: pool.free.as_ref().map_or(0, ArrayQueue::len),
+        hits: pool.hits.load(Ordering::Relaxed),
+        misses: pool.misses.load(Ordering::Relaxed),
+    }
+}
+
 // NOTE: the cancellable mining helper is implemented as an associated
 // function on `Blockchain` below. Keeping a single implementation avoids
 // duplication and potential name-resolution/visibility confusion.
 
-fn init_scratchpad() -> Vec<u8> {
-    // Allow adjusting scratchpad size with environment variable (bytes)
-    let size = std::env::var("OWONERO_SCRATCHPAD_SIZE")
+/// Bytes in each hashing scratchpad: `OWONERO_SCRATCHPAD_SIZE` when set to
+/// at least 1 KiB, else 2 MiB.
+pub fn scratchpad_size() -> usize {
+    std::env::var("OWONERO_SCRATCHPAD_SIZE")
         .ok()
         .and_then(|s| s.parse::<usize>().ok())
         .filter(|&v| v >= 1024)
-        .unwrap_or(SCRATCHPAD_SIZE);
+        .unwrap_or(SCRATCHPAD_SIZE)
+}
 
-    let mut buf = vec![0u8; size];
+fn init_scratchpad() -> Vec<u8> {
+    let mut buf = vec![0u8; scratchpad_size()];
 
     // Only attempt to enable transparent huge pages when user opts in.
     let try_huge = std::env::var("OWONERO_USE_HUGEPAGES")
@@ -92,6 +192,31 @@ pub struct Block {
     pub difficulty: u32,
 }
 
+/// A block without its transactions: enough to check that a chain links
+/// up and claims the work it should before downloading all of it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub index: u64,
+    pub timestamp: DateTime<Utc>,
+    pub prev_hash: String,
+    pub hash: String,
+    pub nonce: u32,
+    pub difficulty: u32,
+}
+
+impl From<&Block> for BlockHeader {
+    fn from(block: &Block) -> Self {
+        Self {
+            index: block.index,
+            timestamp: block.timestamp,
+            prev_hash: block.prev_hash.clone(),
+            hash: block.hash.clone(),
+            nonce: block.nonce,
+            difficulty: block.difficulty,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     pub from: String,
@@ -99,9 +224,42 @@ pub struct Transaction {
     pub to: String,
     pub amount: i64,
     pub signature: String,
+    /// Last block height this transaction may be included in. Covered by
+    /// the signature; transactions without it never expire.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_height: Option<u64>,
+}
+
+impl Transaction {
+    /// Bytes covered by the signature: `from|to|amount`, plus
+    /// `|expires_at_height` when set. Plain text, so no serializer is
+    /// involved.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        match self.expires_at_height {
+            Some(last) => format!("{}|{}|{}|{}", self.from, self.to, self.amount, last),
+            None => format!("{}|{}|{}", self.from, self.to, self.amount),
+        }
+        .into_bytes()
+    }
+
+    /// Whether the transaction can no longer go into a block at `height`.
+    pub fn expired_at(&self, height: u64) -> bool {
+        self.expires_at_height.is_some_and(|last| height > last)
+    }
+
+    /// Stable identifier for a transaction: SHA3-256 over the signed fields
+    /// plus the signature, hex encoded. Used by the daemon to track a
+    /// transaction from submission through confirmation or rejection.
+    pub fn txid(&self) -> String {
+        let data = format!(
+            "{}|{}|{}|{}",
+            self.from, self.to, self.amount, self.signature
+        );
+        hex::encode(sha3::Sha3_256::digest(data.as_bytes()))
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Blockchain {
     pub chain: Vec<Block>,
     pub target_block_time: i64,
@@ -115,91 +273,6 @@ impl Blockchain {
         }
     }
 
-    pub async fn sync(&mut self, peers: &[String]) -> Result<()> {
-        if peers.is_empty() {
-            return Err(anyhow!("No peers provided for syncing"));
-        }
-
-        let client = Client::new();
-        let mut max_height = self.chain.last().map(|b| b.index).unwrap_or(0);
-        let mut best_peer = None;
-
-        // Find the peer with the highest block height
-        for peer in peers {
-            let url = format!("{}/height", peer.trim_end_matches('/'));
-            match client.get(&url).send().await {
-                Ok(resp) => {
-                    if let Ok(json) = resp.json::<serde_json::Value>().await {
-                        if let Some(height) = json.get("height").and_then(|v| v.as_u64()) {
-                            if height > max_height {
-                                max_height = height;
-                                best_peer = Some(peer.clone());
-                            }
-                        }
-                    }
-                }
-                Err(e) => eprintln!("Failed to query height from {}: {}", peer, e),
-            }
-        }
-
-        if best_peer.is_none() {
-            return Err(anyhow!("No valid peers found for syncing"));
-        }
-
-        let best_peer = best_peer.unwrap();
-        let current_height = self.chain.last().map(|b| b.index).unwrap_or(0);
-
-        if max_height <= current_height {
-            eprintln!(
-                "Blockchain is already up to date (height: {})",
-                current_height
-            );
-            return Ok(());
-        }
-
-        eprintln!(
-            "Syncing from {} to {} using peer {}",
-            current_height + 1,
-            max_height,
-            best_peer
-        );
-
-        // Download and add missing blocks
-        for index in (current_height + 1)..=max_height {
-            let url = format!("{}/block/{}", best_peer.trim_end_matches('/'), index);
-            let resp =
-                client.get(&url).send().await.with_context(|| {
-                    format!("Failed to fetch block {} from {}", index, best_peer)
-                })?;
-
-            let block: Block = resp
-                .json()
-                .await
-                .with_context(|| format!("Failed to parse block {} from {}", index, best_peer))?;
-
-            // Validate and add the block
-            let difficulty = self.get_dynamic_difficulty();
-            if self.validate_block(&block, difficulty, false) {
-                self.chain.push(block);
-                eprintln!("Added block {}", index);
-            } else {
-                return Err(anyhow!(
-                    "Invalid block {} received from {}",
-                    index,
-                    best_peer
-                ));
-            }
-        }
-
-        self.save_to_file(crate::config::get_blockchain_path())?;
-        eprintln!(
-            "Sync complete. New height: {}",
-            self.chain.last().map(|b| b.index).unwrap_or(0)
-        );
-
-        Ok(())
-    }
-
     pub fn create_genesis_block() -> Block {
         let mut block = Block {
             index: 0,
@@ -210,6 +283,7 @@ impl Blockchain {
                 to: "network".to_string(),
                 amount: 0,
                 signature: "".to_string(),
+                expires_at_height: None,
             }],
             prev_hash: "".to_string(),
             hash: "".to_string(),
@@ -220,18 +294,33 @@ impl Blockchain {
         block
     }
 
+    /// Hash a block with a scratchpad borrowed from the shared validation
+    /// pool. Mining loops use `calculate_hash_mining` instead.
     pub fn calculate_hash(block: &Block) -> String {
+        Self::hash_preimage(&canonical_block_bytes(block))
+    }
+
+    /// RX/OWO hash of raw preimage bytes (`canonical_block_bytes`, or a
+    /// getwork preimage), using a pooled scratchpad.
+    pub fn hash_preimage(block_bytes: &[u8]) -> String {
+        let pool = ScratchpadPool::global();
+        let mut scratchpad = pool.take();
+        let hash = Self::rx_owo_hash(block_bytes, &mut scratchpad);
+        pool.give_back(scratchpad);
+        hash
+    }
+
+    /// Hash a block with this thread's own scratchpad, which stays allocated
+    /// for the life of the thread.
+    fn calculate_hash_mining(block: &Block) -> String {
+        let block_bytes = canonical_block_bytes(block);
+        SCRATCHPAD_BUF.with(|buf| Self::rx_owo_hash(&block_bytes, &mut buf.borrow_mut()))
+    }
+
+    fn rx_owo_hash(block_bytes: &[u8], scratchpad: &mut [u8]) -> String {
         // RX/OWO Algorithm - RandomX-inspired memory-hard PoW for Owonero
         // Features: 2MB scratchpad, complex memory access patterns, ASIC-resistant operations
         // Designed to be memory-hard and CPU-friendly for fair mining distribution
-        let block_for_hash = BlockForHash {
-            index: block.index,
-            timestamp: block.timestamp,
-            transactions: block.transactions.clone(),
-            prev_hash: block.prev_hash.clone(),
-            nonce: block.nonce,
-        };
-        let block_bytes = serde_json::to_vec(&block_for_hash).unwrap();
 
         // Determine iterations (configurable via OWONERO_MINING_ITERATIONS env var)
         let iterations = env::var("OWONERO_MINING_ITERATIONS")
@@ -239,111 +328,103 @@ impl Blockchain {
             .and_then(|s| s.parse::<usize>().ok())
             .unwrap_or(DEFAULT_ITERATIONS);
 
-        let seed = sha3::Sha3_256::digest(&block_bytes);
-
-        // Use a reusable thread-local scratchpad to avoid reallocations and operate
-        // on u64 words for better throughput.
-        let result_hash = SCRATCHPAD_BUF.with(|buf| {
-            let mut scratchpad = buf.borrow_mut();
+        let seed = sha3::Sha3_256::digest(block_bytes);
 
-            // Ensure scratchpad len is a multiple of 8 for safe u64 views
-            let sp_len = scratchpad.len();
+        // Operate on u64 words for better throughput.
+        // Ensure scratchpad len is a multiple of 8 for safe u64 views
+        let sp_len = scratchpad.len();
 
-            // Initialize RNG state from seed
-            let mut rng_state = u64::from_le_bytes(seed[0..8].try_into().unwrap());
+        // Initialize RNG state from seed
+        let mut rng_state = u64::from_le_bytes(seed[0..8].try_into().unwrap());
 
-            // Fill scratchpad with pseudo-random data (word-wise) for faster writes
-            unsafe {
-                let ptr = scratchpad.as_mut_ptr() as *mut u64;
-                let words = sp_len / 8;
-                for i in 0..words {
-                    rng_state = rng_state.wrapping_mul(6364136223846793005).wrapping_add(1);
-                    // spread bits to 64-bit value
-                    let v = (rng_state >> 1) ^ (rng_state << 33);
-                    ptr.add(i).write_unaligned(v.to_le());
-                }
-                // If there are leftover bytes (unlikely when size multiple of 8), leave them
+        // Fill scratchpad with pseudo-random data (word-wise) for faster writes
+        unsafe {
+            let ptr = scratchpad.as_mut_ptr() as *mut u64;
+            let words = sp_len / 8;
+            for i in 0..words {
+                rng_state = rng_state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                // spread bits to 64-bit value
+                let v = (rng_state >> 1) ^ (rng_state << 33);
+                ptr.add(i).write_unaligned(v.to_le());
             }
+            // If there are leftover bytes (unlikely when size multiple of 8), leave them
+        }
 
-            // RX/OWO Main Loop - Memory-hard computation
-            let mut a = u64::from_le_bytes(seed[8..16].try_into().unwrap());
-            let mut b = u64::from_le_bytes(seed[16..24].try_into().unwrap());
-            let mut c = u64::from_le_bytes(seed[24..32].try_into().unwrap());
-
-            // Operating on u64 words reduces bounds checks and increases throughput.
-            let sp_words = sp_len / 8;
-            unsafe {
-                let sp_ptr = scratchpad.as_mut_ptr() as *mut u64;
-                for iteration in 0..iterations {
-                    // Memory access pattern 1: Random word access
-                    let idx1 = ((a.wrapping_add(b).wrapping_mul(c)) % (sp_words as u64)) as usize;
-                    let mem_val1 = sp_ptr.add(idx1).read_unaligned();
-
-                    // Memory access pattern 2: Sequential with offset (byte-level offset folded into word index)
-                    let idx2 = (((iteration as usize * 8) + (a as usize % 1024)) % sp_len) / 8;
-                    let mem_val2 = sp_ptr.add(idx2).read_unaligned();
-
-                    // Prefetch hints where available
-                    #[cfg(target_arch = "x86_64")]
-                    {
-                        use core::arch::x86_64::_MM_HINT_T0;
-                        use core::arch::x86_64::_mm_prefetch;
-                        let p = sp_ptr.add(idx1) as *const i8;
-                        _mm_prefetch(p, _MM_HINT_T0);
-                    }
-
-                    // Touch L1/L2 simulated addresses
-                    let l1_idx = (a % (L1_CACHE_SIZE as u64 / 8)) as usize % sp_words;
-                    let l1_val = sp_ptr.add(l1_idx).read_unaligned();
-
-                    let l2_idx = ((b % (L2_CACHE_SIZE as u64 / 8)) as usize) % sp_words;
-                    let l2_val = sp_ptr.add(l2_idx).read_unaligned();
-
-                    // Mix operations - designed to keep CPU busy and to have memory-dependent
-                    // data-dependent addressing (RandomX-like)
-                    a = a.wrapping_mul(mem_val1).wrapping_add(l1_val);
-                    b = (b ^ mem_val2).wrapping_sub(l2_val);
-                    c = c.rotate_left((mem_val1 % 64) as u32).wrapping_add(a ^ b);
-
-                    // Non-linear mixing
-                    a ^= a.rotate_right(17);
-                    b ^= b.rotate_right(23);
-                    c ^= c.rotate_right(29);
-
-                    // Memory write-back (modify scratchpad)
-                    let write_idx = ((a ^ b ^ c) % (sp_words as u64)) as usize;
-                    let write_val = a.wrapping_add(b).wrapping_mul(c);
-                    sp_ptr.add(write_idx).write_unaligned(write_val.to_le());
-
-                    // Additional entropy from block data occasionally
-                    if iteration & 127 == 0 {
-                        let block_byte =
-                            *block_bytes.get(iteration % block_bytes.len()).unwrap_or(&0);
-                        a ^= block_byte as u64;
-                        b ^= (block_byte as u64).rotate_left(8);
-                        c ^= (block_byte as u64).rotate_left(16);
-                    }
+        // RX/OWO Main Loop - Memory-hard computation
+        let mut a = u64::from_le_bytes(seed[8..16].try_into().unwrap());
+        let mut b = u64::from_le_bytes(seed[16..24].try_into().unwrap());
+        let mut c = u64::from_le_bytes(seed[24..32].try_into().unwrap());
+
+        // Operating on u64 words reduces bounds checks and increases throughput.
+        let sp_words = sp_len / 8;
+        unsafe {
+            let sp_ptr = scratchpad.as_mut_ptr() as *mut u64;
+            for iteration in 0..iterations {
+                // Memory access pattern 1: Random word access
+                let idx1 = ((a.wrapping_add(b).wrapping_mul(c)) % (sp_words as u64)) as usize;
+                let mem_val1 = sp_ptr.add(idx1).read_unaligned();
+
+                // Memory access pattern 2: Sequential with offset (byte-level offset folded into word index)
+                let idx2 = (((iteration * 8) + (a as usize % 1024)) % sp_len) / 8;
+                let mem_val2 = sp_ptr.add(idx2).read_unaligned();
+
+                // Prefetch hints where available
+                #[cfg(target_arch = "x86_64")]
+                {
+                    use core::arch::x86_64::_MM_HINT_T0;
+                    use core::arch::x86_64::_mm_prefetch;
+                    let p = sp_ptr.add(idx1) as *const i8;
+                    _mm_prefetch(p, _MM_HINT_T0);
                 }
-            }
 
-            // Final hash computation - preallocate to avoid reallocations
-            let mut final_input = Vec::with_capacity(8 * 3 + block_bytes.len() + 32);
-            final_input.extend_from_slice(&a.to_le_bytes());
-            final_input.extend_from_slice(&b.to_le_bytes());
-            final_input.extend_from_slice(&c.to_le_bytes());
-            final_input.extend_from_slice(&block_bytes);
-
-            // Mix in some scratchpad data
-            for i in 0..32 {
-                let idx = (a.wrapping_add(i as u64) % SCRATCHPAD_SIZE as u64) as usize;
-                final_input.push(scratchpad[idx]);
+                // Touch L1/L2 simulated addresses
+                let l1_idx = (a % (L1_CACHE_SIZE as u64 / 8)) as usize % sp_words;
+                let l1_val = sp_ptr.add(l1_idx).read_unaligned();
+
+                let l2_idx = ((b % (L2_CACHE_SIZE as u64 / 8)) as usize) % sp_words;
+                let l2_val = sp_ptr.add(l2_idx).read_unaligned();
+
+                // Mix operations - designed to keep CPU busy and to have memory-dependent
+                // data-dependent addressing (RandomX-like)
+                a = a.wrapping_mul(mem_val1).wrapping_add(l1_val);
+                b = (b ^ mem_val2).wrapping_sub(l2_val);
+                c = c.rotate_left((mem_val1 % 64) as u32).wrapping_add(a ^ b);
+
+                // Non-linear mixing
+                a ^= a.rotate_right(17);
+                b ^= b.rotate_right(23);
+                c ^= c.rotate_right(29);
+
+                // Memory write-back (modify scratchpad)
+                let write_idx = ((a ^ b ^ c) % (sp_words as u64)) as usize;
+                let write_val = a.wrapping_add(b).wrapping_mul(c);
+                sp_ptr.add(write_idx).write_unaligned(write_val.to_le());
+
+                // Additional entropy from block data occasionally
+                if iteration & 127 == 0 {
+                    let block_byte = *block_bytes.get(iteration % block_bytes.len()).unwrap_or(&0);
+                    a ^= block_byte as u64;
+                    b ^= (block_byte as u64).rotate_left(8);
+                    c ^= (block_byte as u64).rotate_left(16);
+                }
             }
+        }
 
-            let hash = sha3::Sha3_256::digest(&final_input);
-            hex::encode(hash)
-        });
+        // Final hash computation - preallocate to avoid reallocations
+        let mut final_input = Vec::with_capacity(8 * 3 + block_bytes.len() + 32);
+        final_input.extend_from_slice(&a.to_le_bytes());
+        final_input.extend_from_slice(&b.to_le_bytes());
+        final_input.extend_from_slice(&c.to_le_bytes());
+        final_input.extend_from_slice(block_bytes);
+
+        // Mix in some scratchpad data
+        for i in 0..32 {
+            let idx = (a.wrapping_add(i as u64) % SCRATCHPAD_SIZE as u64) as usize;
+            final_input.push(scratchpad[idx]);
+        }
 
-        return result_hash;
+        let hash = sha3::Sha3_256::digest(&final_input);
+        hex::encode(hash)
     }
 
     pub fn verify_chain(&self) -> Result<()> {
@@ -374,33 +455,7 @@ impl Blockchain {
     }
 
     pub fn get_dynamic_difficulty(&self) -> u32 {
-        let min_difficulty = 1;
-        let max_difficulty = 7;
-        let window = 10;
-
-        if self.chain.len() <= window {
-            return min_difficulty;
-        }
-
-        let latest = &self.chain[self.chain.len() - 1];
-        let prev = &self.chain[self.chain.len() - window - 1];
-        let avg_block_time = (latest.timestamp - prev.timestamp).num_seconds() / window as i64;
-        let mut diff = latest.difficulty as i32;
-
-        if avg_block_time < self.target_block_time {
-            diff += 1;
-        } else if avg_block_time > self.target_block_time {
-            diff -= 1;
-        }
-
-        if diff < min_difficulty as i32 {
-            diff = min_difficulty as i32;
-        }
-        if diff > max_difficulty as i32 {
-            diff = max_difficulty as i32;
-        }
-
-        diff as u32
+        dynamic_difficulty_after(&self.chain, self.target_block_time)
     }
 
     pub fn validate_block(&self, block: &Block, difficulty: u32, skip_pow: bool) -> bool {
@@ -436,42 +491,56 @@ impl Blockchain {
             eprintln!("Block {} validation failed: Hash mismatch", block.index);
             return false;
         }
+        if !has_canonical_encoding(block) {
+            eprintln!(
+                "Block {} validation failed: non-canonical block encoding",
+                block.index
+            );
+            return false;
+        }
         if block.index != last.index + 1 {
             eprintln!("Block {} validation failed: Index mismatch", block.index);
             return false;
         }
 
-        if !skip_pow {
-            // Check PoW
-            let hash_bytes = hex::decode(&block.hash).unwrap_or_default();
-            let mut valid_pow = true;
-            for i in 0..((difficulty + 1) / 2) {
-                let byte_idx = i as usize;
-                if byte_idx >= hash_bytes.len() {
-                    break;
-                }
-                let byte_val = hash_bytes[byte_idx];
-                if difficulty > i * 2 && byte_val >> 4 != 0 {
-                    valid_pow = false;
-                    break;
-                }
-                if difficulty > i * 2 + 1 && (byte_val & 0x0F) != 0 {
-                    valid_pow = false;
-                    break;
-                }
-            }
-            if !valid_pow {
-                eprintln!("Block {} validation failed: PoW check failed", block.index);
-                return false;
-            }
+        // The claim is what chain work and the next difficulty are
+        // computed from, so it must be exactly what the schedule asks
+        if !skip_pow && block.difficulty != difficulty {
+            eprintln!(
+                "Block {} validation failed: claims difficulty {}, the schedule requires {}",
+                block.index, block.difficulty, difficulty
+            );
+            return false;
+        }
+        if !skip_pow && !hash_meets_difficulty(&block.hash, difficulty) {
+            eprintln!("Block {} validation failed: PoW check failed", block.index);
+            return false;
         }
 
         // Validate transaction signatures
         for tx in &block.transactions {
+            if tx.expired_at(block.index) {
+                eprintln!(
+                    "Block {} validation failed: Transaction from {} expired at height {}",
+                    block.index,
+                    tx.from,
+                    tx.expires_at_height.unwrap_or(0)
+                );
+                return false;
+            }
             if tx.from == "coinbase" {
                 // Coinbase transactions don't need signatures
                 continue;
             }
+            if strict_consensus()
+                && let Some(reason) = legacy_format(tx)
+            {
+                eprintln!(
+                    "Block {} validation failed: Legacy-format transaction from {} ({})",
+                    block.index, tx.from, reason
+                );
+                return false;
+            }
             if !verify_transaction_signature(tx, &tx.pub_key) {
                 eprintln!(
                     "Block {} validation failed: Invalid transaction signature for tx from {} to {}",
@@ -484,17 +553,7 @@ impl Blockchain {
         // Check for overspending: compute current balances from chain and
         // ensure applying this block's transactions in order does not make
         // any wallet go negative.
-        let mut balances: HashMap<String, i64> = HashMap::new();
-        for b in &self.chain {
-            for t in &b.transactions {
-                let to = t.to.trim().to_lowercase();
-                if t.from != "coinbase" {
-                    let from = t.from.trim().to_lowercase();
-                    *balances.entry(from).or_insert(0) -= t.amount;
-                }
-                *balances.entry(to).or_insert(0) += t.amount;
-            }
-        }
+        let mut balances = self.balances();
 
         for tx in &block.transactions {
             if tx.amount <= 0 {
@@ -563,6 +622,9 @@ impl Blockchain {
         if Self::calculate_hash(block) != block.hash {
             return Some("Hash mismatch".to_string());
         }
+        if !has_canonical_encoding(block) {
+            return Some("non-canonical block encoding".to_string());
+        }
         if block.index != last.index + 1 {
             return Some(format!(
                 "Index mismatch: expected {} got {}",
@@ -571,36 +633,37 @@ impl Blockchain {
             ));
         }
 
-        if !skip_pow {
-            // Check PoW
-            let hash_bytes = hex::decode(&block.hash).unwrap_or_default();
-            let mut valid_pow = true;
-            for i in 0..((difficulty + 1) / 2) {
-                let byte_idx = i as usize;
-                if byte_idx >= hash_bytes.len() {
-                    break;
-                }
-                let byte_val = hash_bytes[byte_idx];
-                if difficulty > i * 2 && byte_val >> 4 != 0 {
-                    valid_pow = false;
-                    break;
-                }
-                if difficulty > i * 2 + 1 && (byte_val & 0x0F) != 0 {
-                    valid_pow = false;
-                    break;
-                }
-            }
-            if !valid_pow {
-                return Some("PoW check failed".to_string());
-            }
+        if !skip_pow && block.difficulty != difficulty {
+            return Some(format!(
+                "claims difficulty {}, the schedule requires {}",
+                block.difficulty, difficulty
+            ));
+        }
+        if !skip_pow && !hash_meets_difficulty(&block.hash, difficulty) {
+            return Some("PoW check failed".to_string());
         }
 
         // Validate transaction signatures
         for tx in &block.transactions {
+            if tx.expired_at(block.index) {
+                return Some(format!(
+                    "Transaction from {} expired at height {}",
+                    tx.from,
+                    tx.expires_at_height.unwrap_or(0)
+                ));
+            }
             if tx.from == "coinbase" {
                 // Coinbase transactions don't need signatures
                 continue;
             }
+            if strict_consensus()
+                && let Some(reason) = legacy_format(tx)
+            {
+                return Some(format!(
+                    "Legacy-format transaction from {} ({})",
+                    tx.from, reason
+                ));
+            }
             if !verify_transaction_signature(tx, &tx.pub_key) {
                 return Some(format!(
                     "Invalid transaction signature for tx from {} to {}",
@@ -612,17 +675,7 @@ impl Blockchain {
         // Check for overspending: compute current balances from chain and
         // ensure applying this block's transactions in order does not make
         // any wallet go negative. Return a descriptive error when it fails.
-        let mut balances: HashMap<String, i64> = HashMap::new();
-        for b in &self.chain {
-            for t in &b.transactions {
-                let to = t.to.trim().to_lowercase();
-                if t.from != "coinbase" {
-                    let from = t.from.trim().to_lowercase();
-                    *balances.entry(from).or_insert(0) -= t.amount;
-                }
-                *balances.entry(to).or_insert(0) += t.amount;
-            }
-        }
+        let mut balances = self.balances();
 
         for tx in &block.transactions {
             if tx.amount <= 0 {
@@ -662,9 +715,51 @@ impl Blockchain {
         }
     }
 
+    /// Whether `branch`, which takes over from height `fork`, holds more
+    /// work than our blocks from there on. A tie keeps ours.
+    pub fn outweighed_by(&self, fork: usize, branch: &[Block]) -> bool {
+        chain_work(branch) > chain_work(&self.chain[fork.min(self.chain.len())..])
+    }
+
+    /// This chain rolled back to its first `fork` blocks with `branch`
+    /// applied on top, or why a block of `branch` doesn't validate there.
+    pub fn with_branch(
+        &self,
+        fork: usize,
+        branch: &[Block],
+        skip_pow: bool,
+    ) -> std::result::Result<Blockchain, String> {
+        let mut switched = self.clone();
+        switched.chain.truncate(fork);
+        for block in branch {
+            let difficulty = switched.get_dynamic_difficulty();
+            // Branches are weighed by what their blocks claim, so even
+            // without PoW checks the claims must follow the schedule
+            if block.difficulty != difficulty {
+                return Err(format!(
+                    "block {} claims difficulty {}, the schedule requires {}",
+                    block.index, block.difficulty, difficulty
+                ));
+            }
+            if let Some(reason) = switched.validate_block_verbose(block, difficulty, skip_pow) {
+                return Err(format!(
+                    "block {} does not validate here: {}",
+                    block.index, reason
+                ));
+            }
+            switched.chain.push(block.clone());
+        }
+        Ok(switched)
+    }
+
+    /// Write the chain to `path` through a temporary file, so an
+    /// interrupted save leaves the previous chain rather than half of one.
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
         let data = serde_json::to_string_pretty(self)?;
-        fs::write(path, data)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, path)?;
         Ok(())
     }
 
@@ -704,23 +799,34 @@ impl Blockchain {
     /// local chain version changes (so workers stop working on stale templates).
     ///
     /// Returns `Some(Block)` when a valid block is found, or `None` when
-    /// mining was aborted due to a chain version update.
+    /// mining was aborted due to a chain version update. An aborted search
+    /// is left in `resume`; when the next call gets the same template (see
+    /// `resumes`) it carries on from that nonce instead of starting over.
+    /// With a `duty` cycle below 100% it rests between slices of hashing.
+    #[allow(clippy::too_many_arguments)]
     pub fn mine_block_with_cancel(
         prev_block: &Block,
         transactions: Vec<Transaction>,
         difficulty: u32,
         attempts: &mut u64,
         attempts_atomic: Option<&std::sync::atomic::AtomicU64>,
-        chain_version: Option<&std::sync::atomic::AtomicU64>,
+        chain_version: Option<&crate::miner::ChainVersion>,
+        duty: Option<&crate::intensity::DutyCycle>,
+        resume: &mut Option<Block>,
     ) -> Option<Block> {
-        let mut block = Block {
-            index: prev_block.index + 1,
-            timestamp: Utc::now(),
-            transactions,
-            prev_hash: prev_block.hash.clone(),
-            hash: String::new(),
-            nonce: 0,
-            difficulty,
+        let mut block = match resume.take() {
+            Some(aborted) if Self::resumes(&aborted, prev_block, &transactions, difficulty) => {
+                aborted
+            }
+            _ => Block {
+                index: prev_block.index + 1,
+                timestamp: Utc::now(),
+                transactions,
+                prev_hash: prev_block.hash.clone(),
+                hash: String::new(),
+                nonce: 0,
+                difficulty,
+            },
         };
 
         // RX/OWO mining - memory-hard algorithm
@@ -750,50 +856,31 @@ impl Blockchain {
         let mut last_flush = Instant::now();
 
         // Snapshot chain version at start; if it changes we abort.
-        let start_version = chain_version.map(|v| v.load(std::sync::atomic::Ordering::Relaxed));
+        let start_version = chain_version.map(|v| v.current());
+        let mut slice_started = Instant::now();
 
         loop {
-            block.hash = Self::calculate_hash(&block);
+            block.hash = Self::calculate_hash_mining(&block);
             *attempts += 1;
             flush_chunk += 1;
 
             // Periodically flush into the shared atomic counter if provided.
-            if let Some(at) = attempts_atomic {
-                if flush_chunk >= flush_threshold
-                    || last_flush.elapsed() >= Duration::from_millis(flush_interval_ms)
-                {
-                    at.fetch_add(flush_chunk, std::sync::atomic::Ordering::Relaxed);
-                    // reset chunk after flushing
-                    flush_chunk = 0;
-                    last_flush = Instant::now();
-                }
+            if let Some(at) = attempts_atomic
+                && (flush_chunk >= flush_threshold
+                    || last_flush.elapsed() >= Duration::from_millis(flush_interval_ms))
+            {
+                at.fetch_add(flush_chunk, std::sync::atomic::Ordering::Relaxed);
+                // reset chunk after flushing
+                flush_chunk = 0;
+                last_flush = Instant::now();
             }
 
-            // Check if hash meets difficulty (match validate_block's PoW logic)
-            let hash_bytes = hex::decode(&block.hash).unwrap_or_default();
-            let mut valid_pow = true;
-            for i in 0..((difficulty + 1) / 2) {
-                let byte_idx = i as usize;
-                if byte_idx >= hash_bytes.len() {
-                    valid_pow = false;
-                    break;
-                }
-                let byte_val = hash_bytes[byte_idx];
-                if difficulty > i * 2 && byte_val >> 4 != 0 {
-                    valid_pow = false;
-                    break;
-                }
-                if difficulty > i * 2 + 1 && (byte_val & 0x0F) != 0 {
-                    valid_pow = false;
-                    break;
-                }
-            }
-            if valid_pow {
+            if hash_meets_difficulty(&block.hash, difficulty) {
                 // flush any remaining attempts
-                if let Some(at) = attempts_atomic {
-                    if flush_chunk > 0 {
-                        at.fetch_add(flush_chunk, std::sync::atomic::Ordering::Relaxed);
-                    }
+                if let Some(at) = attempts_atomic
+                    && flush_chunk > 0
+                {
+                    at.fetch_add(flush_chunk, std::sync::atomic::Ordering::Relaxed);
                 }
                 return Some(block);
             }
@@ -801,24 +888,51 @@ impl Blockchain {
             // Periodically check whether the chain version changed; if so,
             // abort mining this template to avoid PrevHash mismatches.
             if let Some(v) = chain_version {
-                let cur = v.load(std::sync::atomic::Ordering::Relaxed);
+                let cur = v.current();
                 if Some(cur) != start_version {
                     // Optionally flush remaining attempts before aborting
-                    if let Some(at) = attempts_atomic {
-                        if flush_chunk > 0 {
-                            at.fetch_add(flush_chunk, std::sync::atomic::Ordering::Relaxed);
-                        }
+                    if let Some(at) = attempts_atomic
+                        && flush_chunk > 0
+                    {
+                        at.fetch_add(flush_chunk, std::sync::atomic::Ordering::Relaxed);
                     }
+                    block.nonce = block.nonce.wrapping_add(1);
+                    *resume = Some(block);
                     return None;
                 }
             }
 
+            if let Some(duty) = duty
+                && slice_started.elapsed() >= crate::intensity::DUTY_SLICE
+            {
+                duty.rest(slice_started.elapsed(), || {
+                    chain_version.is_some_and(|v| Some(v.current()) != start_version)
+                });
+                slice_started = Instant::now();
+            }
+
             block.nonce += 1;
         }
     }
 
-    pub fn difficulty_to_target(diff: u64) -> String {
-        "0".repeat(diff as usize)
+    /// Whether `aborted`, a search `mine_block_with_cancel` gave up on, is
+    /// the template it would build from these: same parent, difficulty and
+    /// transactions.
+    pub fn resumes(
+        aborted: &Block,
+        prev_block: &Block,
+        transactions: &[Transaction],
+        difficulty: u32,
+    ) -> bool {
+        aborted.index == prev_block.index + 1
+            && aborted.prev_hash == prev_block.hash
+            && aborted.difficulty == difficulty
+            && aborted.transactions.len() == transactions.len()
+            && aborted
+                .transactions
+                .iter()
+                .zip(transactions)
+                .all(|(a, b)| a.txid() == b.txid())
     }
 
     /// Get the block reward (in internal units) for the given block height.
@@ -832,6 +946,48 @@ impl Blockchain {
         500
     }
 
+    /// Confirmed balances of every address seen on chain, keyed by the
+    /// trimmed, lowercased address. Coinbase outputs only credit.
+    pub fn balances(&self) -> HashMap<String, i64> {
+        let mut balances: HashMap<String, i64> = HashMap::new();
+        for b in &self.chain {
+            Self::apply_block_balances(&mut balances, b);
+        }
+        balances
+    }
+
+    /// Fold one block's transactions into `balances` (as kept by `balances`).
+    pub fn apply_block_balances(balances: &mut HashMap<String, i64>, block: &Block) {
+        for t in &block.transactions {
+            let to = t.to.trim().to_lowercase();
+            if t.from != "coinbase" {
+                let from = t.from.trim().to_lowercase();
+                *balances.entry(from).or_insert(0) -= t.amount;
+            }
+            *balances.entry(to).or_insert(0) += t.amount;
+        }
+    }
+
+    /// Coinbase totals and burns up to the tip.
+    pub fn supply(&self) -> Supply {
+        let mut supply = Supply {
+            height: self.chain.last().map(|b| b.index).unwrap_or(0),
+            minted: 0,
+            burned: 0,
+            circulating: 0,
+        };
+        for t in self.chain.iter().flat_map(|b| &b.transactions) {
+            if t.from == "coinbase" {
+                supply.minted += t.amount;
+            }
+            if crate::wallet::is_burn_address(&t.to) {
+                supply.burned += t.amount;
+            }
+        }
+        supply.circulating = supply.minted - supply.burned;
+        supply
+    }
+
     /// Format an internal-unit amount (i64) as a human-friendly OWE string.
     /// Example: 500 -> "0.500 OWE"
     pub fn format_owe(amount: i64) -> String {
@@ -839,17 +995,254 @@ impl Blockchain {
     }
 }
 
-#[derive(Serialize)]
-struct BlockForHash {
-    index: u64,
-    timestamp: DateTime<Utc>,
-    transactions: Vec<Transaction>,
-    prev_hash: String,
-    nonce: u32,
+/// Coins in existence, in internal units: everything coinbases paid, and
+/// how much of it was sent to the burn address, which nothing can spend.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Supply {
+    pub height: u64,
+    pub minted: i64,
+    pub burned: i64,
+    pub circulating: i64,
+}
+
+/// Difficulty the block after `chain` must meet: the last block's, one
+/// up or down depending on how the last 10 blocks compared with
+/// `target_block_time`. Checks that see only part of a chain use this
+/// directly rather than building a `Blockchain`.
+pub fn dynamic_difficulty_after(chain: &[Block], target_block_time: i64) -> u32 {
+    let min_difficulty = 1;
+    let max_difficulty = MAX_DIFFICULTY as i32;
+    let window = DIFFICULTY_WINDOW;
+
+    if chain.len() <= window {
+        return min_difficulty;
+    }
+
+    let latest = &chain[chain.len() - 1];
+    let prev = &chain[chain.len() - window - 1];
+    let avg_block_time = (latest.timestamp - prev.timestamp).num_seconds() / window as i64;
+    let mut diff = latest.difficulty.min(MAX_DIFFICULTY) as i32;
+
+    if avg_block_time < target_block_time {
+        diff += 1;
+    } else if avg_block_time > target_block_time {
+        diff -= 1;
+    }
+
+    if diff < min_difficulty as i32 {
+        diff = min_difficulty as i32;
+    }
+    if diff > max_difficulty {
+        diff = max_difficulty;
+    }
+
+    diff as u32
+}
+
+/// Consensus PoW check: the hex hash must start with `difficulty` zero
+/// nibbles. Used by block validation and by miners alike.
+pub fn hash_meets_difficulty(hash_hex: &str, difficulty: u32) -> bool {
+    let difficulty = difficulty as usize;
+    hash_hex.len() >= difficulty && hash_hex.bytes().take(difficulty).all(|b| b == b'0')
+}
+
+/// Difficulty pool shares must meet: two zero nibbles (256 times) easier
+/// than a block, and never below 1.
+pub fn share_difficulty(block_difficulty: u32) -> u32 {
+    block_difficulty.saturating_sub(2).max(1)
+}
+
+/// Average number of hashes needed to find a block at `difficulty`: each
+/// required zero nibble cuts the odds by 16. Claims past `MAX_DIFFICULTY`
+/// count as that, so no block can outweigh a chain on its own.
+pub fn expected_hashes(difficulty: u32) -> f64 {
+    16f64.powi(difficulty.min(MAX_DIFFICULTY) as i32)
+}
+
+/// Work behind `blocks`: the hashes expected to find each of them at the
+/// difficulty it claims. Competing branches are weighed by this, not by
+/// length.
+pub fn chain_work(blocks: &[Block]) -> f64 {
+    blocks.iter().map(|b| expected_hashes(b.difficulty)).sum()
+}
+
+/// Blocks found as a percentage of the blocks expected for the work done.
+/// `expected_blocks` is the sum of attempts / expected_hashes over the
+/// difficulties mined at. None until any work has been done.
+pub fn luck_percent(found: u64, expected_blocks: f64) -> Option<f64> {
+    (expected_blocks > 0.0).then(|| found as f64 / expected_blocks * 100.0)
+}
+
+/// Average time until the next block at the given hashrate, in seconds.
+pub fn expected_secs_to_block(difficulty: u32, hashrate: f64) -> Option<f64> {
+    (hashrate > 0.0).then(|| expected_hashes(difficulty) / hashrate)
+}
+
+/// What the accepted blocks cost: hashes per block and, when the miner's
+/// power draw is known, kWh per block and per OWE earned. All `None` until
+/// a block has been accepted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Efficiency {
+    pub hashes_per_block: Option<f64>,
+    pub kwh_per_block: Option<f64>,
+    pub kwh_per_owe: Option<f64>,
+}
+
+/// `earned` is in internal units; `watts` is the machine's average draw
+/// over the `secs` spent mining.
+pub fn mining_efficiency(
+    hashes: u64,
+    blocks: u64,
+    earned: i64,
+    secs: u64,
+    watts: Option<f64>,
+) -> Efficiency {
+    if blocks == 0 {
+        return Efficiency::default();
+    }
+    let kwh = watts
+        .filter(|w| *w > 0.0)
+        .map(|w| w * secs as f64 / 3_600_000.0);
+    Efficiency {
+        hashes_per_block: Some(hashes as f64 / blocks as f64),
+        kwh_per_block: kwh.map(|k| k / blocks as f64),
+        kwh_per_owe: kwh
+            .filter(|_| earned > 0)
+            .map(|k| k / (earned as f64 / 1000.0)),
+    }
+}
+
+/// The bytes a block's PoW hash is computed over: compact JSON of `index`,
+/// `timestamp`, `transactions`, `prev_hash` and `nonce`, in that order.
+/// Written out by hand so a serde or chrono upgrade can't change it; it is
+/// byte-for-byte what earlier releases produced through serde_json, which
+/// the pinned vectors in the tests check.
+pub fn canonical_block_bytes(block: &Block) -> Vec<u8> {
+    let mut out = String::with_capacity(256);
+    out.push_str("{\"index\":");
+    out.push_str(&block.index.to_string());
+    out.push_str(",\"timestamp\":");
+    push_json_str(&mut out, &canonical_timestamp(&block.timestamp));
+    out.push_str(",\"transactions\":[");
+    for (i, tx) in block.transactions.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str("{\"from\":");
+        push_json_str(&mut out, &tx.from);
+        out.push_str(",\"pub_key\":");
+        push_json_str(&mut out, &tx.pub_key);
+        out.push_str(",\"to\":");
+        push_json_str(&mut out, &tx.to);
+        out.push_str(",\"amount\":");
+        out.push_str(&tx.amount.to_string());
+        out.push_str(",\"signature\":");
+        push_json_str(&mut out, &tx.signature);
+        if let Some(last) = tx.expires_at_height {
+            out.push_str(",\"expires_at_height\":");
+            out.push_str(&last.to_string());
+        }
+        out.push('}');
+    }
+    out.push_str("],\"prev_hash\":");
+    push_json_str(&mut out, &block.prev_hash);
+    out.push_str(",\"nonce\":");
+    out.push_str(&block.nonce.to_string());
+    out.push('}');
+    out.into_bytes()
+}
+
+/// Whether `canonical_block_bytes` of `block` decode back to the same
+/// bytes. The hash commits to that encoding, so it must be one any node
+/// can read: a timestamp past year 9999, say, serializes but isn't RFC
+/// 3339 and would leave other implementations unable to check the hash.
+pub fn has_canonical_encoding(block: &Block) -> bool {
+    #[derive(Deserialize)]
+    struct Hashed {
+        index: u64,
+        timestamp: String,
+        transactions: Vec<Transaction>,
+        prev_hash: String,
+        nonce: u32,
+    }
+    let bytes = canonical_block_bytes(block);
+    let Ok(hashed) = serde_json::from_slice::<Hashed>(&bytes) else {
+        return false;
+    };
+    let Ok(timestamp) = DateTime::parse_from_rfc3339(&hashed.timestamp) else {
+        return false;
+    };
+    let decoded = Block {
+        index: hashed.index,
+        timestamp: timestamp.with_timezone(&Utc),
+        transactions: hashed.transactions,
+        prev_hash: hashed.prev_hash,
+        hash: String::new(),
+        nonce: hashed.nonce,
+        difficulty: block.difficulty,
+    };
+    canonical_block_bytes(&decoded) == bytes
+}
+
+/// A block's PoW preimage split around the parts a getwork miner fills in:
+/// `prefix ++ timestamp ++ middle ++ nonce (decimal ASCII) ++ "}"` equals
+/// `canonical_block_bytes` of the block with that timestamp and nonce.
+/// Returns `(prefix, middle)`; the block's own timestamp and nonce are
+/// ignored.
+pub fn work_preimage_parts(block: &Block) -> (Vec<u8>, Vec<u8>) {
+    let mut probe = block.clone();
+    probe.nonce = 0;
+    let bytes = canonical_block_bytes(&probe);
+    let prefix = format!("{{\"index\":{},\"timestamp\":\"", block.index).into_bytes();
+    let ts_len = canonical_timestamp(&probe.timestamp).len();
+    // Everything after the timestamp, minus the trailing `0}`
+    let middle = bytes[prefix.len() + ts_len..bytes.len() - 2].to_vec();
+    (prefix, middle)
+}
+
+/// Assemble a getwork preimage from `work_preimage_parts`.
+pub fn work_preimage(prefix: &[u8], timestamp: &str, middle: &[u8], nonce: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(prefix.len() + timestamp.len() + middle.len() + 11);
+    out.extend_from_slice(prefix);
+    out.extend_from_slice(timestamp.as_bytes());
+    out.extend_from_slice(middle);
+    out.extend_from_slice(nonce.to_string().as_bytes());
+    out.push(b'}');
+    out
+}
+
+/// RFC 3339 in UTC with a `Z` suffix and only as many fractional digits
+/// (none, 3, 6 or 9) as the value needs.
+pub fn canonical_timestamp(ts: &DateTime<Utc>) -> String {
+    ts.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+}
+
+/// JSON string literal with serde_json's escaping: `"` and `\`, the short
+/// forms for \b \f \n \r \t, `\u00xx` for other control characters, and
+/// everything else (including non-ASCII) as-is.
+fn push_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{08}' => out.push_str("\\b"),
+            '\u{0c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
 }
 
 // Transaction signing functions
-pub fn sign_transaction(tx: &mut Transaction, priv_key_hex: &str) -> Result<()> {
+/// Sign arbitrary bytes with a hex PKCS#8 key, returning the hex signature.
+/// Callers must give each kind of message its own encoding so a signature
+/// made for one purpose never verifies as another.
+pub fn sign_message(priv_key_hex: &str, message: &[u8]) -> Result<String> {
     let priv_key_bytes = hex::decode(priv_key_hex)?;
     let key_pair = EcdsaKeyPair::from_pkcs8(
         &ECDSA_P256_SHA256_FIXED_SIGNING,
@@ -858,38 +1251,319 @@ pub fn sign_transaction(tx: &mut Transaction, priv_key_hex: &str) -> Result<()>
     )
     .map_err(|_| anyhow!("Invalid private key"))?;
 
-    let message = format!("{}|{}|{}", tx.from, tx.to, tx.amount);
     let signature = key_pair
-        .sign(&ring::rand::SystemRandom::new(), message.as_bytes())
-        .map_err(|_| anyhow!("Failed to sign transaction"))?;
+        .sign(&ring::rand::SystemRandom::new(), message)
+        .map_err(|_| anyhow!("Failed to sign message"))?;
+    Ok(hex::encode(signature.as_ref()))
+}
+
+pub fn verify_message(pub_key_hex: &str, message: &[u8], signature_hex: &str) -> bool {
+    let (Ok(pub_key_bytes), Ok(sig_bytes)) = (hex::decode(pub_key_hex), hex::decode(signature_hex))
+    else {
+        return false;
+    };
+    ring::signature::UnparsedPublicKey::new(
+        &ring::signature::ECDSA_P256_SHA256_FIXED,
+        &pub_key_bytes,
+    )
+    .verify(message, &sig_bytes)
+    .is_ok()
+}
 
-    tx.signature = hex::encode(signature.as_ref());
+pub fn sign_transaction(tx: &mut Transaction, priv_key_hex: &str) -> Result<()> {
+    tx.signature = sign_message(priv_key_hex, &tx.signing_bytes())?;
     Ok(())
 }
 
 pub fn verify_transaction_signature(tx: &Transaction, pub_key_hex: &str) -> bool {
+    verify_transaction_signature_in(tx, pub_key_hex, strict_consensus())
+}
+
+/// `verify_transaction_signature` under an explicit consensus mode. Strict
+/// mode refuses what `legacy_format` describes. In either mode a `pub_key`
+/// only signs for the address it is, so one naming another sender fails;
+/// a coinbase has no sender and is signed by the rig that found it.
+pub fn verify_transaction_signature_in(tx: &Transaction, pub_key_hex: &str, strict: bool) -> bool {
     // If the provided pub_key_hex is empty (older format), fall back to using tx.from
     let key_hex = if pub_key_hex.is_empty() {
+        if strict {
+            return false;
+        }
         &tx.from
     } else {
+        if tx.from != "coinbase" && !pub_key_hex.eq_ignore_ascii_case(&tx.from) {
+            return false;
+        }
         pub_key_hex
     };
+    verify_message(key_hex, &tx.signing_bytes(), &tx.signature)
+}
 
-    let pub_key_bytes = match hex::decode(key_hex) {
-        Ok(bytes) => bytes,
-        Err(_) => return false,
-    };
+/// Why strict nodes refuse `tx` outright, when it has a shape older
+/// clients produced: no `pub_key`, so verification falls back to `from`.
+/// A `pub_key` other than the sender's address (addresses are keys) is
+/// no legacy shape; no node accepts it.
+pub fn legacy_format(tx: &Transaction) -> Option<&'static str> {
+    if tx.from != "coinbase" && tx.pub_key.is_empty() {
+        Some("no pub_key")
+    } else {
+        None
+    }
+}
 
-    let message = format!("{}|{}|{}", tx.from, tx.to, tx.amount);
-    let sig_bytes = match hex::decode(&tx.signature) {
-        Ok(bytes) => bytes,
-        Err(_) => return false,
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_difficulty_counts_leading_zero_nibbles() {
+        assert!(hash_meets_difficulty("0abc", 1));
+        assert!(hash_meets_difficulty("000f", 3));
+        assert!(!hash_meets_difficulty("00f0", 3));
+        assert!(hash_meets_difficulty("ffff", 0));
+        assert!(!hash_meets_difficulty("00", 3));
+    }
 
-    let public_key = ring::signature::UnparsedPublicKey::new(
-        &ring::signature::ECDSA_P256_SHA256_FIXED,
-        &pub_key_bytes,
-    );
+    #[test]
+    fn pooled_and_mining_scratchpads_hash_alike() {
+        let genesis = Blockchain::create_genesis_block();
+        let expected = Blockchain::calculate_hash_mining(&genesis);
+        let before = scratchpad_pool_stats();
+        let hashes: Vec<String> = std::thread::scope(|s| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| Blockchain::calculate_hash(&genesis)))
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+        assert!(hashes.iter().all(|h| *h == expected));
+        let after = scratchpad_pool_stats();
+        assert!(after.hits + after.misses >= before.hits + before.misses + 4);
+        assert!(after.pooled <= after.capacity);
+    }
+
+    #[test]
+    fn canonical_encoding_is_pinned() {
+        // Any change here forks the network: every block hash depends on it
+        assert_eq!(
+            Blockchain::create_genesis_block().hash,
+            "ef2397a2400cded7f139df4a49bb2141d4fda95aedab31e22c03a0e21d70a8b3"
+        );
+        let block = Block {
+            index: 7,
+            timestamp: "2025-10-12T01:02:03.120Z".parse().unwrap(),
+            transactions: vec![
+                Transaction {
+                    from: "coinbase".to_string(),
+                    pub_key: String::new(),
+                    to: "04ab".to_string(),
+                    amount: 500,
+                    signature: String::new(),
+                    expires_at_height: None,
+                },
+                Transaction {
+                    from: "a\"b\\c\n\u{1}é".to_string(),
+                    pub_key: "\t\r\u{8}\u{c}\u{7f}".to_string(),
+                    to: "bob".to_string(),
+                    amount: -1,
+                    signature: "sig".to_string(),
+                    expires_at_height: Some(9),
+                },
+            ],
+            prev_hash: "00ff".to_string(),
+            hash: "ignored".to_string(),
+            nonce: 42,
+            difficulty: 3,
+        };
+        assert_eq!(
+            String::from_utf8(canonical_block_bytes(&block)).unwrap(),
+            concat!(
+                r#"{"index":7,"timestamp":"2025-10-12T01:02:03.120Z","transactions":["#,
+                r#"{"from":"coinbase","pub_key":"","to":"04ab","amount":500,"signature":""},"#,
+                r#"{"from":"a\"b\\c\n\u0001é","pub_key":"\t\r\b\f"#,
+                "\u{7f}",
+                r#"","to":"bob","amount":-1,"signature":"sig","expires_at_height":9}],"#,
+                r#""prev_hash":"00ff","nonce":42}"#
+            )
+        );
+        assert_eq!(
+            block.transactions[1].signing_bytes(),
+            "a\"b\\c\n\u{1}é|bob|-1|9".as_bytes()
+        );
+
+        // Same bytes serde_json produced for the old hash input struct
+        #[derive(Serialize)]
+        struct BlockForHash<'a> {
+            index: u64,
+            timestamp: DateTime<Utc>,
+            transactions: &'a [Transaction],
+            prev_hash: &'a str,
+            nonce: u32,
+        }
+        let mut block = block;
+        for ts in [
+            "2025-10-11T00:00:00Z",
+            "2025-10-11T00:00:00.5Z",
+            "2025-10-11T00:00:00.000001Z",
+            "2025-10-11T00:00:00.123456789Z",
+            "2025-10-11T02:00:00+02:00",
+        ] {
+            block.timestamp = ts.parse().unwrap();
+            let legacy = BlockForHash {
+                index: block.index,
+                timestamp: block.timestamp,
+                transactions: &block.transactions,
+                prev_hash: &block.prev_hash,
+                nonce: block.nonce,
+            };
+            assert_eq!(
+                canonical_block_bytes(&block),
+                serde_json::to_vec(&legacy).unwrap(),
+                "{ts}"
+            );
+        }
+    }
+
+    #[test]
+    fn luck_and_expected_time() {
+        assert_eq!(expected_hashes(2), 256.0);
+        assert_eq!(expected_hashes(u32::MAX), expected_hashes(MAX_DIFFICULTY));
+        assert!(expected_hashes(u32::MAX).is_finite());
+        assert_eq!(luck_percent(0, 0.0), None);
+        assert_eq!(luck_percent(1, 2.0), Some(50.0));
+        assert_eq!(luck_percent(3, 2.0), Some(150.0));
+        assert_eq!(expected_secs_to_block(3, 0.0), None);
+        assert_eq!(expected_secs_to_block(3, 4096.0), Some(1.0));
+    }
+
+    #[test]
+    fn efficiency_needs_an_accepted_block() {
+        assert_eq!(
+            mining_efficiency(1_000, 0, 0, 3600, Some(100.0)),
+            Efficiency::default()
+        );
+        // 100 W for an hour is 0.1 kWh, over 2 blocks paying 0.5 OWE each
+        let e = mining_efficiency(1_000, 2, 1000, 3600, Some(100.0));
+        assert_eq!(e.hashes_per_block, Some(500.0));
+        assert_eq!(e.kwh_per_block, Some(0.05));
+        assert_eq!(e.kwh_per_owe, Some(0.1));
+        let no_power = mining_efficiency(1_000, 2, 1000, 3600, None);
+        assert_eq!(no_power.hashes_per_block, Some(500.0));
+        assert_eq!(no_power.kwh_per_block, None);
+        assert_eq!(no_power.kwh_per_owe, None);
+    }
+
+    #[test]
+    fn mined_block_passes_consensus_check() {
+        let bc = Blockchain::new();
+        let mut attempts = 0;
+        let block = Blockchain::mine_block_with_cancel(
+            &bc.chain[0],
+            vec![],
+            1,
+            &mut attempts,
+            None,
+            None,
+            None,
+            &mut None,
+        )
+        .unwrap();
+        assert!(hash_meets_difficulty(&block.hash, 1));
+        assert!(bc.validate_block(&block, 1, false));
+    }
 
-    public_key.verify(message.as_bytes(), &sig_bytes).is_ok()
+    #[test]
+    fn aborted_templates_resume_from_their_nonce_only_when_unchanged() {
+        let bc = Blockchain::new();
+        let genesis = &bc.chain[0];
+        let version = crate::miner::ChainVersion::new(std::time::Duration::ZERO);
+        let mut aborted = None;
+        let mut attempts = 0;
+        // Hashes until the tip moves a moment later
+        let mine = |aborted: &mut Option<Block>, difficulty, attempts: &mut u64| {
+            std::thread::scope(|s| {
+                s.spawn(|| {
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                    version.bump();
+                });
+                Blockchain::mine_block_with_cancel(
+                    genesis,
+                    vec![],
+                    difficulty,
+                    attempts,
+                    None,
+                    Some(&version),
+                    None,
+                    aborted,
+                )
+            })
+        };
+        // Difficulty no nonce within reach meets
+        assert!(mine(&mut aborted, 64, &mut attempts).is_none());
+        let stopped = aborted.clone().unwrap();
+        assert_eq!(stopped.nonce as u64, attempts);
+        assert!(Blockchain::resumes(&stopped, genesis, &[], 64));
+        assert!(!Blockchain::resumes(&stopped, genesis, &[], 63));
+
+        // Same template: carries on from the next nonce
+        let first_run = attempts;
+        assert!(mine(&mut aborted, 64, &mut attempts).is_none());
+        assert_eq!(aborted.as_ref().unwrap().nonce as u64, attempts);
+        assert_eq!(aborted.as_ref().unwrap().timestamp, stopped.timestamp);
+        assert!(attempts > first_run);
+
+        // Another template starts over
+        let before = attempts;
+        assert!(mine(&mut aborted, 63, &mut attempts).is_none());
+        let fresh = aborted.unwrap();
+        assert_eq!(fresh.difficulty, 63);
+        assert_eq!(fresh.nonce as u64, attempts - before);
+    }
+
+    #[test]
+    fn strict_mode_refuses_legacy_transaction_formats_and_no_mode_takes_a_foreign_key() {
+        let alice = crate::wallet::Wallet::new().unwrap();
+        let mallory = crate::wallet::Wallet::new().unwrap();
+        let tx_from = |from: &str, pub_key: &str, signer: &str| {
+            let mut tx = Transaction {
+                from: from.to_string(),
+                pub_key: pub_key.to_string(),
+                to: "bob".to_string(),
+                amount: 10,
+                signature: String::new(),
+                expires_at_height: None,
+            };
+            sign_transaction(&mut tx, signer).unwrap();
+            tx
+        };
+
+        let modern = tx_from(&alice.address, &alice.pub_key, &alice.priv_key);
+        let no_pub_key = tx_from(&alice.address, "", &alice.priv_key);
+        // Signed by another key than the one `from` names
+        let foreign_key = tx_from(&alice.address, &mallory.pub_key, &mallory.priv_key);
+
+        assert_eq!(legacy_format(&modern), None);
+        assert_eq!(legacy_format(&no_pub_key), Some("no pub_key"));
+        assert_eq!(legacy_format(&foreign_key), None);
+        // Lenient nodes still accept a missing pub_key, but no mode takes a
+        // key that isn't the sender's
+        for tx in [&modern, &no_pub_key] {
+            assert!(verify_transaction_signature_in(tx, &tx.pub_key, false));
+        }
+        assert!(!verify_transaction_signature_in(
+            &foreign_key,
+            &foreign_key.pub_key,
+            false
+        ));
+        assert!(verify_transaction_signature_in(
+            &modern,
+            &modern.pub_key,
+            true
+        ));
+        assert!(!verify_transaction_signature_in(&no_pub_key, "", true));
+        assert!(!verify_transaction_signature_in(
+            &foreign_key,
+            &foreign_key.pub_key,
+            true
+        ));
+    }
 }
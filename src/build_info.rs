@@ -0,0 +1,98 @@
+//! What went into this binary, as recorded by `build.rs`. Printed by
+//! `owonero version` and `--version --verbose`, and reported in the
+//! daemon's `getinfo`, so mixed-version network issues can be traced to
+//! the exact build on each node.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub git_hash_full: &'static str,
+    /// RFC 3339; SOURCE_DATE_EPOCH when the build set it.
+    pub build_timestamp: String,
+    pub target: &'static str,
+    pub rustc: &'static str,
+    /// Cargo features this crate was built with.
+    pub features: Vec<&'static str>,
+    /// CPU features the compiler could assume (SIMD extensions and so on).
+    pub target_features: Vec<&'static str>,
+}
+
+fn list(joined: &'static str) -> Vec<&'static str> {
+    joined.split(',').filter(|s| !s.is_empty()).collect()
+}
+
+pub fn current() -> BuildInfo {
+    let build_timestamp = env!("BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|secs| DateTime::<Utc>::from_timestamp(secs, 0))
+        .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        .unwrap_or_else(|| "unknown".to_string());
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("GIT_HASH_SHORT"),
+        git_hash_full: env!("GIT_HASH_FULL"),
+        build_timestamp,
+        target: env!("TARGET"),
+        rustc: env!("RUSTC_VERSION"),
+        features: list(env!("CRATE_FEATURES")),
+        target_features: list(env!("TARGET_FEATURES")),
+    }
+}
+
+impl BuildInfo {
+    /// `owonero <version>` and then one `key: value` line per field.
+    pub fn verbose(&self) -> String {
+        let joined = |items: &[&str]| match items {
+            [] => "none".to_string(),
+            items => items.join(","),
+        };
+        [
+            format!("owonero {}", self.version),
+            format!("commit: {}", self.git_hash_full),
+            format!("built: {}", self.build_timestamp),
+            format!("target: {}", self.target),
+            format!("rustc: {}", self.rustc),
+            format!("features: {}", joined(&self.features)),
+            format!("target-features: {}", joined(&self.target_features)),
+        ]
+        .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbose_version_lists_every_key() {
+        let info = current();
+        let text = info.verbose();
+        let mut lines = text.lines();
+        assert_eq!(
+            lines.next(),
+            Some(format!("owonero {}", env!("CARGO_PKG_VERSION")).as_str())
+        );
+        let keys: Vec<&str> = lines
+            .map(|l| l.split_once(": ").expect("key: value").0)
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "commit",
+                "built",
+                "target",
+                "rustc",
+                "features",
+                "target-features"
+            ]
+        );
+        assert!(text.contains(&format!("target: {}", env!("TARGET"))));
+        assert_ne!(info.build_timestamp, "unknown");
+        assert!(info.git_hash_full.starts_with(info.git_hash) || info.git_hash == "unknown");
+    }
+}
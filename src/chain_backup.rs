@@ -0,0 +1,224 @@
+//! Copies of local blocks about to be discarded. Adopting a node's chain
+//! or installing a snapshot overwrites the chain file; when that drops
+//! blocks the new chain doesn't share, they are written to
+//! `chain-backup-<time>.json` first so `owonero chain restore-backup` can
+//! put them back.
+
+use crate::blockchain::{Block, Blockchain};
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Chains up to this many blocks are backed up whole; longer ones only
+/// from where they leave the replacing chain.
+pub const WHOLE_CHAIN_MAX_BLOCKS: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainBackup {
+    pub created_at: DateTime<Utc>,
+    /// What replaced the chain, e.g. "adopted chain from node.example:6969".
+    pub reason: String,
+    /// Consecutive blocks, starting at genesis or at the first discarded one.
+    pub blocks: Vec<Block>,
+}
+
+/// Index of the first block of `local` that `incoming` doesn't have.
+pub fn fork_point(local: &Blockchain, incoming: &Blockchain) -> usize {
+    local
+        .chain
+        .iter()
+        .zip(&incoming.chain)
+        .take_while(|(a, b)| a.hash == b.hash)
+        .count()
+}
+
+/// The backup replacing `local` with `incoming` calls for, if it discards
+/// any block.
+pub fn backup_for(local: &Blockchain, incoming: &Blockchain, reason: &str) -> Option<ChainBackup> {
+    let fork = fork_point(local, incoming);
+    if fork >= local.chain.len() {
+        return None;
+    }
+    let start = if local.chain.len() <= WHOLE_CHAIN_MAX_BLOCKS {
+        0
+    } else {
+        fork
+    };
+    Some(ChainBackup {
+        created_at: Utc::now(),
+        reason: reason.to_string(),
+        blocks: local.chain[start..].to_vec(),
+    })
+}
+
+/// Write `backup` into `dir` and keep only the newest `keep` backups there.
+pub fn save_backup(dir: &Path, backup: &ChainBackup, keep: usize) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let path = dir.join(format!(
+        "chain-backup-{}.json",
+        backup.created_at.format("%Y%m%dT%H%M%S%3fZ")
+    ));
+    std::fs::write(&path, serde_json::to_string(backup)?)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    // Timestamps in the names sort oldest first
+    let mut backups: Vec<_> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("chain-backup-") && n.ends_with(".json"))
+        })
+        .collect();
+    backups.sort();
+    for old in &backups[..backups.len().saturating_sub(keep.max(1))] {
+        let _ = std::fs::remove_file(old);
+    }
+    Ok(path)
+}
+
+/// Replace the chain file at `path` (currently holding `local`) with
+/// `incoming`, backing up what it discards first. Returns the backup's
+/// path when one was written. A failed backup leaves the file untouched.
+pub fn replace_chain(
+    path: &Path,
+    local: &Blockchain,
+    incoming: &Blockchain,
+    reason: &str,
+    backups_dir: &Path,
+    keep: usize,
+) -> Result<Option<PathBuf>> {
+    let saved = match backup_for(local, incoming, reason) {
+        Some(backup) => Some(save_backup(backups_dir, &backup, keep)?),
+        None => None,
+    };
+    incoming.save_to_file(path)?;
+    Ok(saved)
+}
+
+pub fn load_backup(path: &Path) -> Result<ChainBackup> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("{} is not a chain backup", path.display()))
+}
+
+/// `current` with the backed-up blocks put back in place of whatever it
+/// has from the backup's first block on. The result must link and hash
+/// correctly and share `current`'s genesis.
+pub fn restore(backup: &ChainBackup, current: &Blockchain) -> Result<Blockchain> {
+    let Some(first) = backup.blocks.first() else {
+        bail!("backup holds no blocks");
+    };
+    let start = first.index as usize;
+    if start > current.chain.len() {
+        bail!(
+            "backup starts at block {} but the local chain ends at {}",
+            start,
+            current.chain.len().saturating_sub(1)
+        );
+    }
+    let mut restored = current.clone();
+    restored.chain.truncate(start);
+    restored.chain.extend(backup.blocks.iter().cloned());
+    if restored.chain.first().map(|b| &b.hash) != current.chain.first().map(|b| &b.hash) {
+        bail!("backup is from a chain with a different genesis block");
+    }
+    restored
+        .verify_chain()
+        .context("restored chain failed integrity check")?;
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(len: u64, tag: i64) -> Blockchain {
+        let mut bc = Blockchain::new();
+        extend(&mut bc, len, tag);
+        bc
+    }
+
+    fn hashes(bc: &Blockchain) -> Vec<&str> {
+        bc.chain.iter().map(|b| b.hash.as_str()).collect()
+    }
+
+    /// Add `len` empty blocks, with `tag` in their timestamps so blocks
+    /// added with different tags differ.
+    fn extend(bc: &mut Blockchain, len: u64, tag: i64) {
+        let start = bc.chain.last().unwrap().timestamp;
+        for _ in 0..len {
+            let last = bc.chain.last().unwrap();
+            let mut block = Block {
+                index: last.index + 1,
+                timestamp: start + chrono::Duration::seconds(30 * (last.index as i64 + 1) + tag),
+                transactions: vec![],
+                prev_hash: last.hash.clone(),
+                hash: String::new(),
+                nonce: 0,
+                difficulty: 1,
+            };
+            block.hash = Blockchain::calculate_hash(&block);
+            bc.chain.push(block);
+        }
+    }
+
+    #[test]
+    fn adopted_chain_can_be_rolled_back_from_the_backup() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("blockchain.json");
+        let backups = dir.join("backups");
+
+        // Local blocks 4-6 lose to a taller fork from block 4 on
+        let local = chain(6, 0);
+        let mut incoming = local.clone();
+        incoming.chain.truncate(4);
+        extend(&mut incoming, 5, 7);
+        local.save_to_file(&path).unwrap();
+
+        // Extending the local chain discards nothing
+        let mut longer = local.clone();
+        extend(&mut longer, 1, 0);
+        assert!(backup_for(&local, &longer, "sync").is_none());
+
+        let saved = replace_chain(&path, &local, &incoming, "adopted chain", &backups, 3)
+            .unwrap()
+            .expect("blocks were discarded");
+        let adopted = Blockchain::load_from_file(&path).unwrap();
+        assert_eq!(adopted.chain.len(), 9);
+
+        let backup = load_backup(&saved).unwrap();
+        assert_eq!(backup.reason, "adopted chain");
+        assert_eq!(backup.blocks.len(), 7, "small chains are kept whole");
+        let restored = restore(&backup, &adopted).unwrap();
+        assert_eq!(hashes(&restored), hashes(&local));
+
+        // A suffix-only backup goes back onto the shared prefix
+        let suffix = ChainBackup {
+            blocks: local.chain[4..].to_vec(),
+            ..backup.clone()
+        };
+        assert_eq!(hashes(&restore(&suffix, &adopted).unwrap()), hashes(&local));
+
+        // Broken backups, and ones that don't reach the local chain, are refused
+        let mut tampered = backup.clone();
+        tampered.blocks[5].nonce += 1;
+        assert!(restore(&tampered, &adopted).is_err());
+        let detached = ChainBackup {
+            blocks: local.chain[5..].to_vec(),
+            ..backup.clone()
+        };
+        assert!(restore(&detached, &chain(2, 0)).is_err());
+
+        // Only the newest backups are kept
+        for _ in 0..4 {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            let mut backup = backup.clone();
+            backup.created_at = Utc::now();
+            save_backup(&backups, &backup, 3).unwrap();
+        }
+        assert_eq!(std::fs::read_dir(&backups).unwrap().count(), 3);
+        assert!(!saved.exists());
+    }
+}
@@ -0,0 +1,652 @@
+//! Block-by-block chain download with progress and an integrity report.
+//! Headers come first: only the stretch of them that links up and meets
+//! its difficulty is downloaded in full. Each block is then checked as it
+//! arrives (index, link to its parent, hash, its header) and fetched again
+//! if the check fails, so a long sync shows how far it got and ends with a
+//! summary of what was verified. A cancelled sync stops between blocks and
+//! keeps the ones verified so far.
+
+use crate::blockchain::{Block, BlockHeader, Blockchain};
+use crate::ws_client::WsClient;
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Times a block that fails its checks is requested before giving up.
+const FETCH_ATTEMPTS: u32 = 3;
+
+/// How far a sync has got. Sent to subscribers as `syncprogress` events.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncProgress {
+    pub fetched: u64,
+    pub total: u64,
+    /// Blocks per second so far.
+    pub rate: f64,
+    /// Seconds left at the current rate, once there is one.
+    pub eta_secs: Option<u64>,
+}
+
+impl SyncProgress {
+    pub fn new(fetched: u64, total: u64, elapsed: Duration) -> Self {
+        let secs = elapsed.as_secs_f64();
+        let rate = if secs > 0.0 {
+            fetched as f64 / secs
+        } else {
+            0.0
+        };
+        let eta_secs = (rate > 0.0).then(|| (total.saturating_sub(fetched) as f64 / rate) as u64);
+        Self {
+            fetched,
+            total,
+            rate,
+            eta_secs,
+        }
+    }
+
+    pub fn done(&self) -> bool {
+        self.fetched >= self.total
+    }
+
+    /// "120/300 blocks (40%), 85.2 blocks/s, ETA 2s"
+    pub fn line(&self) -> String {
+        let percent = (self.fetched * 100).checked_div(self.total).unwrap_or(100);
+        let eta = match self.eta_secs {
+            Some(secs) => format!("ETA {}s", secs),
+            None => "ETA --".to_string(),
+        };
+        format!(
+            "{}/{} blocks ({}%), {:.1} blocks/s, {}",
+            self.fetched, self.total, percent, self.rate, eta
+        )
+    }
+
+    /// `line` behind a `width`-character bar.
+    pub fn bar(&self, width: usize) -> String {
+        let filled = (self.fetched as usize * width)
+            .checked_div(self.total as usize)
+            .unwrap_or(width)
+            .min(width);
+        format!(
+            "[{}{}] {}",
+            "#".repeat(filled),
+            " ".repeat(width - filled),
+            self.line()
+        )
+    }
+}
+
+/// What a finished sync verified.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncReport {
+    pub height: u64,
+    pub tip_hash: String,
+    /// Blocks downloaded and checked.
+    pub verified: u64,
+    pub elapsed: Duration,
+    /// Heights that failed their checks at first and were fetched again.
+    pub refetched: Vec<u64>,
+    /// Cancelled before the last block; `height` is as far as it got.
+    pub interrupted: bool,
+    /// Why the headers past `height` were not downloaded, when one of
+    /// them didn't check out.
+    pub bad_header: Option<String>,
+}
+
+impl std::fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Sync {}: height {}, tip {}, {} block(s) verified in {:.1}s, ",
+            if self.interrupted {
+                "interrupted"
+            } else {
+                "complete"
+            },
+            self.height,
+            &self.tip_hash[..self.tip_hash.len().min(16)],
+            self.verified,
+            self.elapsed.as_secs_f64()
+        )?;
+        match self.refetched.as_slice() {
+            [] => write!(f, "none re-fetched"),
+            heights => {
+                let heights: Vec<String> = heights.iter().map(u64::to_string).collect();
+                write!(f, "re-fetched block(s) {}", heights.join(", "))
+            }
+        }?;
+        match &self.bad_header {
+            Some(problem) => write!(f, "; stopped at {}", problem),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Why `header` can't follow the header hashed `prev_hash` (or start the
+/// chain) as block `index`. Proof of work is checked as far as a header
+/// allows, the hash meeting its difficulty; whether the hash matches the
+/// contents waits for the block.
+fn header_problem(
+    header: &BlockHeader,
+    index: u64,
+    prev_hash: &str,
+    check_pow: bool,
+) -> Option<String> {
+    if header.index != index {
+        return Some(format!("asked for block {}, got {}", index, header.index));
+    }
+    if header.prev_hash != prev_hash {
+        return Some("does not link to the header before it".to_string());
+    }
+    // The genesis block is fixed rather than mined
+    if check_pow
+        && index > 0
+        && !crate::blockchain::hash_meets_difficulty(&header.hash, header.difficulty)
+    {
+        return Some(format!(
+            "hash does not meet its difficulty {}",
+            header.difficulty
+        ));
+    }
+    None
+}
+
+/// Headers `from..=to` in `getheaders` slices, as far as they check out
+/// against each other and `parent`, with why the next one didn't. `None`
+/// when the node predates `getheaders`. Stops early once `cancel` fires.
+async fn fetch_headers(
+    client: &mut WsClient,
+    from: u64,
+    to: u64,
+    parent: Option<&Block>,
+    cancel: &CancellationToken,
+) -> Result<Option<(Vec<BlockHeader>, Option<String>)>> {
+    if !client.supports("getheaders").await? {
+        return Ok(None);
+    }
+    let check_pow = !client.greeting.as_ref().is_some_and(|g| g.is_dev());
+    let mut headers: Vec<BlockHeader> = Vec::with_capacity((to + 1).saturating_sub(from) as usize);
+    let mut start = from;
+    while start <= to && !cancel.is_cancelled() {
+        let end = to.min(start + crate::protocol::MAX_HEADERS_PER_REQUEST - 1);
+        let mut slice = client.headers(start, end).await?.into_iter();
+        for index in start..=end {
+            let prev_hash = headers
+                .last()
+                .map(|h| h.hash.as_str())
+                .or(parent.map(|p| p.hash.as_str()))
+                .unwrap_or("");
+            let problem = match slice.next() {
+                Some(header) => match header_problem(&header, index, prev_hash, check_pow) {
+                    None => {
+                        headers.push(header);
+                        continue;
+                    }
+                    Some(problem) => problem,
+                },
+                None => "missing from the node's answer".to_string(),
+            };
+            return Ok(Some((
+                headers,
+                Some(format!("header {}: {}", index, problem)),
+            )));
+        }
+        start = end + 1;
+    }
+    Ok(Some((headers, None)))
+}
+
+/// Why `block` can't follow `parent` (or start the chain) as block `index`.
+fn integrity_problem(block: &Block, index: u64, parent: Option<&Block>) -> Option<String> {
+    if block.index != index {
+        return Some(format!("asked for block {}, got {}", index, block.index));
+    }
+    let prev_hash = parent.map_or("", |p| p.hash.as_str());
+    if block.prev_hash != prev_hash {
+        return Some("does not link to the block before it".to_string());
+    }
+    if Blockchain::calculate_hash(block) != block.hash {
+        return Some("hash does not match its contents".to_string());
+    }
+    None
+}
+
+/// Fetch blocks `from..=to` over `client`, checking each against the one
+/// before it (`parent` for the first, none when starting at genesis).
+/// Their headers are fetched first, and blocks past the first header that
+/// doesn't check out are left alone. `on_progress` is called after every
+/// block. Once `cancel` fires the blocks fetched so far are returned, with
+/// the report marked interrupted.
+pub async fn fetch_blocks(
+    client: &mut WsClient,
+    from: u64,
+    to: u64,
+    parent: Option<&Block>,
+    cancel: &CancellationToken,
+    mut on_progress: impl FnMut(&SyncProgress),
+) -> Result<(Vec<Block>, SyncReport)> {
+    let started = Instant::now();
+    let (headers, bad_header) = match fetch_headers(client, from, to, parent, cancel).await? {
+        Some((headers, Some(problem))) if headers.is_empty() => {
+            bail!("nothing to download: {}", problem)
+        }
+        Some((headers, problem)) => (Some(headers), problem),
+        None => (None, None),
+    };
+    let to = match &headers {
+        Some(headers) if bad_header.is_some() => from + headers.len() as u64 - 1,
+        _ => to,
+    };
+    let total = (to + 1).saturating_sub(from);
+    let mut blocks: Vec<Block> = Vec::with_capacity(total as usize);
+    let mut refetched = Vec::new();
+    let mut interrupted = false;
+    for index in from..=to {
+        if cancel.is_cancelled() {
+            interrupted = true;
+            break;
+        }
+        let header = headers
+            .as_ref()
+            .and_then(|h| h.get((index - from) as usize));
+        let mut attempt = 1;
+        let block = loop {
+            let block = client.block(Some(index)).await?;
+            let parent = blocks.last().or(parent);
+            let problem = integrity_problem(&block, index, parent).or_else(|| {
+                header
+                    .filter(|h| h.hash != block.hash)
+                    .map(|_| "does not match its header".to_string())
+            });
+            match problem {
+                None => break block,
+                Some(problem) if attempt >= FETCH_ATTEMPTS => {
+                    bail!(
+                        "block {} failed verification {} times: {}",
+                        index,
+                        attempt,
+                        problem
+                    )
+                }
+                Some(_) => {
+                    if attempt == 1 {
+                        refetched.push(index);
+                    }
+                    attempt += 1;
+                }
+            }
+        };
+        blocks.push(block);
+        on_progress(&SyncProgress::new(
+            blocks.len() as u64,
+            total,
+            started.elapsed(),
+        ));
+    }
+    let tip = blocks.last().or(parent);
+    let report = SyncReport {
+        height: tip.map_or(0, |b| b.index),
+        tip_hash: tip.map(|b| b.hash.clone()).unwrap_or_default(),
+        verified: blocks.len() as u64,
+        elapsed: started.elapsed(),
+        refetched,
+        interrupted,
+        bad_header,
+    };
+    Ok((blocks, report))
+}
+
+/// Bring a copy of `local` up to `node_addr`'s tip. Only the blocks past
+/// the local tip are fetched while the node has that tip too; otherwise
+/// the node's whole chain is. `None` when the node is not ahead, or when
+/// `cancel` fired before the copy got past `local`.
+pub async fn sync_from_node(
+    node_addr: &str,
+    local: &Blockchain,
+    cancel: &CancellationToken,
+    on_progress: impl FnMut(&SyncProgress),
+) -> Result<Option<(Blockchain, SyncReport)>> {
+    let mut client = WsClient::connect(node_addr).await?;
+    let tip = client.block(None).await?;
+    let local_tip = local.chain.last();
+    if local_tip.is_some_and(|t| t.index >= tip.index) {
+        return Ok(None);
+    }
+    let shared_tip = match local_tip {
+        Some(t) => client.block(Some(t.index)).await?.hash == t.hash,
+        None => false,
+    };
+    let mut synced = local.clone();
+    if !shared_tip {
+        synced.chain.clear();
+    }
+    let from = synced.chain.len() as u64;
+    let (blocks, report) = fetch_blocks(
+        &mut client,
+        from,
+        tip.index,
+        synced.chain.last(),
+        cancel,
+        on_progress,
+    )
+    .await?;
+    synced.chain.extend(blocks);
+    // A partial download of a diverged chain is no better than ours
+    if synced.chain.len() <= local.chain.len() {
+        return Ok(None);
+    }
+    Ok(Some((synced, report)))
+}
+
+/// Progress on stderr: a bar redrawn in place on a terminal, a plain
+/// carriage-return line otherwise. Redraws at most every 100ms.
+pub struct ProgressDisplay {
+    tty: bool,
+    last: Option<Instant>,
+}
+
+impl ProgressDisplay {
+    pub fn stderr() -> Self {
+        Self {
+            tty: std::io::stderr().is_terminal(),
+            last: None,
+        }
+    }
+
+    pub fn show(&mut self, progress: &SyncProgress) {
+        let now = Instant::now();
+        if !progress.done()
+            && self
+                .last
+                .is_some_and(|t| now.duration_since(t) < Duration::from_millis(100))
+        {
+            return;
+        }
+        self.last = Some(now);
+        let text = if self.tty {
+            progress.bar(30)
+        } else {
+            progress.line()
+        };
+        let mut err = std::io::stderr();
+        let _ = write!(err, "\rSyncing: {}", text);
+        if progress.done() {
+            let _ = writeln!(err);
+        }
+        let _ = err.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{SinkExt, StreamExt};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn chain(len: u64) -> Blockchain {
+        // Difficulty 0, so headers pass the proof of work check unmined
+        crate::testkit::ChainBuilder::new(1)
+            .blocks(len)
+            .difficulty(0)
+            .build()
+            .unwrap()
+            .chain
+    }
+
+    /// How a stub node answers `getheaders`.
+    #[derive(Clone, Copy, PartialEq)]
+    enum Headers {
+        /// An older node: no capabilities, no headers.
+        Unsupported,
+        Served,
+        /// Served, but the header at this height links to nothing.
+        BrokenAt(u64),
+    }
+
+    /// A node serving `getlatest`, `getblock` and `getheaders` from `bc`,
+    /// and the heights of the blocks it was asked for. The first time each
+    /// height in `corrupt` is asked for, its nonce is off by one.
+    async fn stub_node(
+        bc: Blockchain,
+        corrupt: &[u64],
+        headers: Headers,
+    ) -> (String, Arc<parking_lot::Mutex<Vec<u64>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let corrupt = Arc::new(parking_lot::Mutex::new(
+            corrupt.iter().copied().collect::<HashSet<u64>>(),
+        ));
+        let requested = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let log = requested.clone();
+        let bc = Arc::new(bc);
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let (bc, corrupt, log) = (bc.clone(), corrupt.clone(), log.clone());
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+                    let greeting = serde_json::json!({"type": "greeting", "height": 0});
+                    ws.send(Message::Text(greeting.to_string())).await.unwrap();
+                    while let Some(Ok(Message::Text(text))) = ws.next().await {
+                        let cmd: serde_json::Value = serde_json::from_str(&text).unwrap();
+                        let params = &cmd["params"];
+                        let data = match cmd["method"].as_str() {
+                            Some("getcapabilities") if headers != Headers::Unsupported => {
+                                serde_json::json!({"protocol_version": 1, "methods": ["getheaders"]})
+                            }
+                            Some("getheaders") if headers != Headers::Unsupported => {
+                                let (from, to) = (params["from"].as_u64(), params["to"].as_u64());
+                                let range = from.unwrap() as usize..=to.unwrap() as usize;
+                                let slice: Vec<BlockHeader> = bc.chain[range]
+                                    .iter()
+                                    .map(|b| {
+                                        let mut header = BlockHeader::from(b);
+                                        if headers == Headers::BrokenAt(b.index) {
+                                            header.prev_hash = "0".repeat(64);
+                                        }
+                                        header
+                                    })
+                                    .collect();
+                                serde_json::json!(slice)
+                            }
+                            _ => {
+                                let mut block = match params["index"].as_u64() {
+                                    Some(index) => {
+                                        log.lock().push(index);
+                                        bc.chain[index as usize].clone()
+                                    }
+                                    None => bc.chain.last().unwrap().clone(),
+                                };
+                                if corrupt.lock().remove(&block.index) {
+                                    block.nonce += 1;
+                                }
+                                serde_json::json!(block)
+                            }
+                        };
+                        let reply = serde_json::json!({"type": "response", "data": data});
+                        let _ = ws.send(Message::Text(reply.to_string())).await;
+                    }
+                });
+            }
+        });
+        (addr, requested)
+    }
+
+    #[tokio::test]
+    async fn long_sync_reports_progress_and_refetches_bad_blocks() {
+        let remote = chain(200);
+        let (node, _) = stub_node(remote.clone(), &[150, 199], Headers::Served).await;
+        let go = CancellationToken::new();
+
+        // Partial: only the blocks past the shared tip
+        let mut local = remote.clone();
+        local.chain.truncate(51);
+        let mut seen = Vec::new();
+        let (synced, report) = sync_from_node(&node, &local, &go, |p| seen.push(p.clone()))
+            .await
+            .unwrap()
+            .expect("node is ahead");
+        assert_eq!(synced.chain.len(), 201);
+        assert_eq!(seen.len(), 150);
+        assert!(seen.windows(2).all(|w| w[0].fetched + 1 == w[1].fetched));
+        assert!(seen.iter().all(|p| p.total == 150));
+        let last = seen.last().unwrap();
+        assert!(last.done() && last.eta_secs.is_none_or(|s| s == 0));
+        assert_eq!(report.height, 200);
+        assert_eq!(report.tip_hash, remote.chain[200].hash);
+        assert_eq!(report.verified, 150);
+        assert_eq!(report.refetched, [150, 199]);
+        assert!(report.to_string().contains("re-fetched block(s) 150, 199"));
+
+        // A diverged local chain is replaced from genesis
+        let mut forked = chain(5);
+        forked.chain[5].hash = "f".repeat(64);
+        let (synced, report) = sync_from_node(&node, &forked, &go, |_| {})
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.verified, 201);
+        assert_eq!(synced.chain[5].hash, remote.chain[5].hash);
+        assert!(report.refetched.is_empty());
+
+        // Nothing to do when the node is not ahead
+        assert!(
+            sync_from_node(&node, &remote, &go, |_| {})
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // A block that never checks out stops the sync
+        let mut broken = remote.clone();
+        broken.chain[195].nonce += 1;
+        let (node, _) = stub_node(broken, &[], Headers::Served).await;
+        let mut local = remote.clone();
+        local.chain.truncate(191);
+        let err = sync_from_node(&node, &local, &go, |_| {})
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("block 195 failed verification 3 times")
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_sync_keeps_the_verified_blocks_and_saves_them_whole() {
+        let remote = chain(100);
+        let (node, _) = stub_node(remote.clone(), &[], Headers::Unsupported).await;
+        let mut local = remote.clone();
+        local.chain.truncate(21);
+
+        // Ctrl+C lands while block 30 is being fetched
+        let cancel = CancellationToken::new();
+        let (synced, report) = sync_from_node(&node, &local, &cancel, |p| {
+            if p.fetched == 10 {
+                cancel.cancel();
+            }
+        })
+        .await
+        .unwrap()
+        .expect("got past the local tip");
+        assert!(report.interrupted);
+        assert_eq!(report.verified, 10);
+        assert_eq!(report.height, 30);
+        assert_eq!(report.tip_hash, remote.chain[30].hash);
+        assert!(
+            report
+                .to_string()
+                .starts_with("Sync interrupted: height 30")
+        );
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("blockchain.json");
+        local.save_to_file(&path).unwrap();
+        synced.save_to_file(&path).unwrap();
+        let saved = Blockchain::load_from_file(&path).unwrap();
+        assert_eq!(saved.chain.len(), 31);
+        assert!(!path.with_extension("json.tmp").exists());
+
+        // Cancelled before the first block: nothing better than what we had
+        assert!(
+            sync_from_node(&node, &local, &cancel, |_| {})
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn only_blocks_whose_headers_check_out_are_downloaded() {
+        let remote = chain(60);
+        let go = CancellationToken::new();
+        let mut local = remote.clone();
+        local.chain.truncate(11);
+
+        // All headers check out: every block past the tip comes down
+        let (node, requested) = stub_node(remote.clone(), &[], Headers::Served).await;
+        let (synced, report) = sync_from_node(&node, &local, &go, |_| {})
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(synced.chain.len(), 61);
+        assert_eq!(report.bad_header, None);
+        assert_eq!(requested.lock().iter().filter(|&&i| i > 10).count(), 50);
+
+        // Header 40 breaks the chain: blocks 11..=39 are fetched, none after
+        let (node, requested) = stub_node(remote.clone(), &[], Headers::BrokenAt(40)).await;
+        let (synced, report) = sync_from_node(&node, &local, &go, |_| {})
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(synced.chain.len(), 40);
+        assert_eq!(report.height, 39);
+        assert_eq!(report.tip_hash, remote.chain[39].hash);
+        assert_eq!(
+            report.bad_header.as_deref(),
+            Some("header 40: does not link to the header before it")
+        );
+        assert!(
+            report
+                .to_string()
+                .ends_with("; stopped at header 40: does not link to the header before it")
+        );
+        assert_eq!(requested.lock().iter().max(), Some(&39));
+
+        // Nothing to download when the first header is already bad
+        let mut at_break = remote.clone();
+        at_break.chain.truncate(40);
+        let err = sync_from_node(&node, &at_break, &go, |_| {})
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("header 40"), "{}", err);
+
+        // A header that doesn't meet its difficulty is refused as well
+        let mut header = BlockHeader::from(&remote.chain[12]);
+        assert_eq!(
+            header_problem(&header, 12, &remote.chain[11].hash, true),
+            None
+        );
+        header.hash = format!("f{}", &header.hash[1..]);
+        header.difficulty = 1;
+        assert_eq!(
+            header_problem(&header, 12, &remote.chain[11].hash, true).as_deref(),
+            Some("hash does not meet its difficulty 1")
+        );
+        // Dev networks take blocks without proof of work
+        assert_eq!(
+            header_problem(&header, 12, &remote.chain[11].hash, false),
+            None
+        );
+    }
+
+    #[test]
+    fn progress_line_and_bar() {
+        let p = SyncProgress::new(50, 200, Duration::from_secs(2));
+        assert_eq!(p.line(), "50/200 blocks (25%), 25.0 blocks/s, ETA 6s");
+        assert!(p.bar(8).starts_with("[##      ] 50/200"));
+        assert_eq!(SyncProgress::new(0, 10, Duration::ZERO).eta_secs, None);
+    }
+}
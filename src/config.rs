@@ -1,8 +1,26 @@
+use crate::protocol::{
+    DEFAULT_DAEMON_PORT, DEFAULT_NODE, DEFAULT_WEB_PORT, MEMPOOL_POLL_MS, TIP_POLL_MS,
+};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::PathBuf;
 
+/// An `intensity_schedule` entry, e.g.
+/// `{"days": ["weekdays"], "from": "09:00", "to": "18:00", "intensity": 20}`.
+/// A `to` before `from` runs past midnight.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IntensityWindow {
+    /// `mon`..`sun`, `weekdays` or `weekends`; none for every day.
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// `HH:MM`, local time.
+    pub from: String,
+    pub to: String,
+    pub intensity: u8,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub node_address: String,
@@ -11,11 +29,238 @@ pub struct Config {
     pub wallet_path: String,
     pub mining_threads: usize,
     pub peers: Vec<String>,
+    /// Most peers a daemon learns from other nodes and `addpeer`, on top
+    /// of `peers`.
+    #[serde(default = "default_max_peers")]
+    pub max_peers: usize,
+    /// Peers saved in `peers.json` that haven't answered for this many
+    /// days are dropped when the daemon starts. 0 keeps them all.
+    #[serde(default = "default_peer_max_age_days")]
+    pub peer_max_age_days: u64,
+    /// Failed health checks in a row after which a learned peer is
+    /// dropped; configured peers are only skipped until they answer.
+    /// 0 keeps every peer.
+    #[serde(default = "default_peer_max_failures")]
+    pub peer_max_failures: u32,
+    /// Misbehavior points (invalid JSON 10, bad signature 20, invalid
+    /// block 50) within an hour after which an IP is banned. 0 never bans.
+    #[serde(default = "default_ban_threshold")]
+    pub ban_threshold: u32,
+    /// How long a ban lasts.
+    #[serde(default = "default_ban_duration_secs")]
+    pub ban_duration_secs: u64,
+    /// Inbound connections the daemon keeps open at once; more are
+    /// closed before the handshake. 0 is no limit.
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    /// The same for one remote IP. Loopback isn't limited. 0 is no limit.
+    #[serde(default = "default_max_connections_per_ip")]
+    pub max_connections_per_ip: usize,
+    /// Request tokens a connection earns per second; `getchain` costs 50,
+    /// `getheight` 1. Loopback isn't limited. 0 is no limit.
+    #[serde(default = "default_rate_limit_per_sec")]
+    pub rate_limit_per_sec: u32,
+    /// Tokens a connection can save up for a burst of requests.
+    #[serde(default = "default_rate_limit_burst")]
+    pub rate_limit_burst: u32,
+    /// Below this much free space on the data directory the daemon stops
+    /// accepting blocks and transactions until space frees up. 0 never
+    /// stops.
+    #[serde(default = "default_min_free_disk_mb")]
+    pub min_free_disk_mb: u64,
+    /// Deepest reorg the daemon performs on its own; deeper ones are
+    /// refused until `owonero chain adopt --force`. 0 is no limit.
+    #[serde(default = "default_max_reorg_depth")]
+    pub max_reorg_depth: u64,
     pub auto_update: bool,
     pub sync_on_startup: bool,
     pub target_block_time: i64,
     pub mining_intensity: u8,
+    /// Times of the week to mine at another intensity than
+    /// `mining_intensity`, in local time. Where entries overlap the lowest
+    /// intensity wins.
+    #[serde(default)]
+    pub intensity_schedule: Vec<IntensityWindow>,
+    /// Intensity caps for single worker threads, by thread index: a thread
+    /// runs at the lower of its cap and the rig's intensity.
+    #[serde(default)]
+    pub thread_intensity: BTreeMap<usize, u8>,
     pub pool: bool,
+    /// Enables the skip-PoW `dev` commands and puts the node on a separate
+    /// dev network. Never set this on a public node.
+    #[serde(default)]
+    pub dev_mode: bool,
+    /// Deflate large WebSocket responses (chain, mempool) when both ends
+    /// agree. Turn off on CPU-constrained nodes.
+    #[serde(default = "default_true")]
+    pub ws_compression: bool,
+    /// Largest WebSocket message the daemon reads from a remote client;
+    /// a bigger one gets an error and the connection is closed. 0 is no
+    /// limit.
+    #[serde(default = "default_max_message_bytes")]
+    pub max_message_bytes: usize,
+    /// Reject transactions with more unconfirmed ancestors than this.
+    #[serde(default = "default_max_mempool_depth")]
+    pub max_mempool_depth: usize,
+    /// Pause mining while the machine runs on battery (Linux and
+    /// Windows).
+    #[serde(default)]
+    pub pause_on_battery: bool,
+    /// How often a daemon rewrites its chain snapshot for `/api/snapshot`.
+    /// 0 disables snapshots.
+    #[serde(default = "default_snapshot_interval_secs")]
+    pub snapshot_interval_secs: u64,
+    /// 2MB scratchpads kept for hashing blocks outside the miner (validation,
+    /// RPC). Concurrent validations beyond this allocate temporarily.
+    #[serde(default = "default_scratchpad_pool_size")]
+    pub scratchpad_pool_size: usize,
+    /// How often the miner polls the node for a new tip, in milliseconds.
+    #[serde(default = "default_tip_poll_ms")]
+    pub tip_poll_ms: u64,
+    /// How often the miner refreshes its mempool copy, in milliseconds.
+    #[serde(default = "default_mempool_poll_ms")]
+    pub mempool_poll_ms: u64,
+    /// Address mined blocks pay instead of this wallet (`--mine-to`).
+    #[serde(default)]
+    pub mine_to: Option<String>,
+    /// Only mine mempool transactions from or to these addresses. Empty
+    /// means all of them.
+    #[serde(default)]
+    pub include_only_addresses: Vec<String>,
+    /// Never mine mempool transactions from or to these addresses; wins
+    /// over `include_only_addresses`.
+    #[serde(default)]
+    pub exclude_addresses: Vec<String>,
+    /// Shares of each mined block's coinbase paid to other addresses, as
+    /// `[address, percent]` pairs adding up to at most 100.
+    #[serde(default)]
+    pub coinbase_split: Vec<(String, f64)>,
+    /// Also append every mempool admission decision to
+    /// `mempool_journal.jsonl` in the config directory. The last few are
+    /// always kept in memory for `getmempooljournal`.
+    #[serde(default)]
+    pub mempool_journal: bool,
+    /// Mining session reports (`session-*.json`) kept in the config
+    /// directory. 0 stops writing them.
+    #[serde(default = "default_session_reports_keep")]
+    pub session_reports_keep: usize,
+    /// Write a receipt (`receipts/<txid>.json` in the config directory)
+    /// for every transaction this wallet gets a node to accept.
+    #[serde(default = "default_true")]
+    pub write_receipts: bool,
+    /// Chain backups (`backups/chain-backup-*.json`) kept in the config
+    /// directory; one is written before local blocks are discarded.
+    #[serde(default = "default_chain_backups_keep")]
+    pub chain_backups_keep: usize,
+    /// Addresses this node refuses in `submittx` and skips in batch
+    /// payouts. Operator policy only: blocks paying them stay valid.
+    #[serde(default)]
+    pub blocked_addresses: Vec<String>,
+    /// File with more blocked addresses, one per line; `reloadblocklist`
+    /// rereads it without a restart.
+    #[serde(default)]
+    pub blocked_addresses_file: Option<String>,
+    /// Refuse legacy transaction formats (no `pub_key`, or one other than
+    /// the sender's) in the mempool and in blocks. For new networks; on
+    /// the public one it would reject blocks other nodes accept.
+    #[serde(default)]
+    pub strict_consensus: bool,
+    /// Serve the stats API under this path (e.g. `/owonero`) when a
+    /// reverse proxy forwards it with the prefix intact.
+    #[serde(default)]
+    pub web_path_prefix: String,
+    /// The stats server sits behind a reverse proxy: take client addresses
+    /// from `Forwarded`/`X-Forwarded-For` instead of the connection.
+    #[serde(default)]
+    pub trust_proxy: bool,
+    /// Serve chain data only, e.g. for a public explorer mirror: refuse
+    /// blocks, transactions, shares and miner stats from clients while
+    /// still syncing from peers.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Set by `--standalone` for this run only; never saved.
+    #[serde(skip)]
+    pub standalone: bool,
+}
+
+fn default_tip_poll_ms() -> u64 {
+    TIP_POLL_MS
+}
+
+fn default_mempool_poll_ms() -> u64 {
+    MEMPOOL_POLL_MS
+}
+
+fn default_max_peers() -> usize {
+    crate::daemon::DEFAULT_MAX_PEERS
+}
+
+fn default_peer_max_age_days() -> u64 {
+    14
+}
+
+fn default_peer_max_failures() -> u32 {
+    crate::daemon::DEFAULT_PEER_MAX_FAILURES
+}
+
+fn default_ban_threshold() -> u32 {
+    crate::daemon::DEFAULT_BAN_THRESHOLD
+}
+
+fn default_ban_duration_secs() -> u64 {
+    crate::daemon::DEFAULT_BAN_DURATION_SECS
+}
+
+fn default_max_connections() -> usize {
+    crate::daemon::DEFAULT_MAX_CONNECTIONS
+}
+
+fn default_max_connections_per_ip() -> usize {
+    crate::daemon::DEFAULT_MAX_CONNECTIONS_PER_IP
+}
+
+fn default_rate_limit_per_sec() -> u32 {
+    crate::daemon::DEFAULT_RATE_LIMIT_PER_SEC
+}
+
+fn default_rate_limit_burst() -> u32 {
+    crate::daemon::DEFAULT_RATE_LIMIT_BURST
+}
+
+fn default_max_message_bytes() -> usize {
+    crate::protocol::DEFAULT_MAX_MESSAGE_BYTES
+}
+
+fn default_min_free_disk_mb() -> u64 {
+    crate::disk::DEFAULT_MIN_FREE_DISK_MB
+}
+
+fn default_max_reorg_depth() -> u64 {
+    crate::daemon::DEFAULT_MAX_REORG_DEPTH
+}
+
+fn default_session_reports_keep() -> usize {
+    10
+}
+
+fn default_chain_backups_keep() -> usize {
+    5
+}
+
+fn default_scratchpad_pool_size() -> usize {
+    2
+}
+
+fn default_snapshot_interval_secs() -> u64 {
+    3600
+}
+
+fn default_max_mempool_depth() -> usize {
+    5
+}
+
+fn default_true() -> bool {
+    true
 }
 
 impl Config {
@@ -26,15 +271,18 @@ impl Config {
         if self.mining_threads == 0 {
             anyhow::bail!("mining_threads must be at least 1");
         }
+        if self.tip_poll_ms == 0 || self.mempool_poll_ms == 0 {
+            anyhow::bail!("tip_poll_ms and mempool_poll_ms must be at least 1");
+        }
         if self.mining_intensity > 100 {
             anyhow::bail!("mining_intensity must be <= 100");
         }
         // Sprawdź ścieżkę portfela
         let wallet_path = std::path::Path::new(&self.wallet_path);
-        if let Some(parent) = wallet_path.parent() {
-            if !parent.exists() {
-                anyhow::bail!("Wallet path directory does not exist: {}", parent.display());
-            }
+        if let Some(parent) = wallet_path.parent()
+            && !parent.exists()
+        {
+            anyhow::bail!("Wallet path directory does not exist: {}", parent.display());
         }
         Ok(())
     }
@@ -46,17 +294,54 @@ impl Default for Config {
         let wallet_path = base_dir.join("wallet.json");
 
         Self {
-            node_address: "owonero.yabai.buzz:6969".to_string(),
-            daemon_port: 6969,
-            web_port: 6767,
+            node_address: DEFAULT_NODE.to_string(),
+            daemon_port: DEFAULT_DAEMON_PORT,
+            web_port: DEFAULT_WEB_PORT,
             wallet_path: wallet_path.to_string_lossy().to_string(),
             mining_threads: 1,
             peers: Vec::new(),
+            max_peers: default_max_peers(),
+            peer_max_age_days: default_peer_max_age_days(),
+            peer_max_failures: default_peer_max_failures(),
+            ban_threshold: default_ban_threshold(),
+            ban_duration_secs: default_ban_duration_secs(),
+            max_connections: default_max_connections(),
+            max_connections_per_ip: default_max_connections_per_ip(),
+            rate_limit_per_sec: default_rate_limit_per_sec(),
+            rate_limit_burst: default_rate_limit_burst(),
+            min_free_disk_mb: default_min_free_disk_mb(),
+            max_reorg_depth: default_max_reorg_depth(),
             auto_update: true,
             sync_on_startup: true,
             target_block_time: 30,
             mining_intensity: 100,
+            intensity_schedule: Vec::new(),
+            thread_intensity: BTreeMap::new(),
             pool: false,
+            dev_mode: false,
+            ws_compression: true,
+            max_message_bytes: default_max_message_bytes(),
+            max_mempool_depth: default_max_mempool_depth(),
+            pause_on_battery: false,
+            snapshot_interval_secs: default_snapshot_interval_secs(),
+            scratchpad_pool_size: default_scratchpad_pool_size(),
+            tip_poll_ms: default_tip_poll_ms(),
+            mempool_poll_ms: default_mempool_poll_ms(),
+            mine_to: None,
+            include_only_addresses: Vec::new(),
+            exclude_addresses: Vec::new(),
+            coinbase_split: Vec::new(),
+            mempool_journal: false,
+            session_reports_keep: default_session_reports_keep(),
+            write_receipts: true,
+            chain_backups_keep: default_chain_backups_keep(),
+            blocked_addresses: Vec::new(),
+            blocked_addresses_file: None,
+            strict_consensus: false,
+            web_path_prefix: String::new(),
+            trust_proxy: false,
+            read_only: false,
+            standalone: false,
         }
     }
 }
@@ -64,7 +349,7 @@ impl Default for Config {
 /// Determine the configuration directory for the current platform.
 /// Linux/macOS → `$HOME/.config/Owonero`
 /// Windows → `%APPDATA%\Owonero`
-fn get_config_dir() -> PathBuf {
+pub fn get_config_dir() -> PathBuf {
     if let Some(dir) = dirs::config_dir() {
         let owonero_dir: PathBuf = dir.join("Owonero");
         if !owonero_dir.exists() {
@@ -91,30 +376,70 @@ pub fn get_blockchain_path() -> PathBuf {
     get_config_dir().join("blockchain.json")
 }
 
+/// Returns the path of the compressed chain snapshot a daemon serves.
+pub fn get_snapshot_path() -> PathBuf {
+    get_config_dir().join("snapshot.json.gz")
+}
+
+/// Returns the path of the address book (`address_book`).
+pub fn get_address_book_path() -> PathBuf {
+    get_config_dir().join("address_book.json")
+}
+
+/// Returns the directory chain backups are written to.
+pub fn get_backups_dir() -> PathBuf {
+    get_config_dir().join("backups")
+}
+
+/// Returns the directory receipts of sent transactions are written to.
+pub fn get_receipts_dir() -> PathBuf {
+    get_config_dir().join("receipts")
+}
+
+/// Returns the full path to the daemon's saved pool share counts (`shares.json`)
+pub fn get_shares_path() -> PathBuf {
+    get_config_dir().join("shares.json")
+}
+
+/// Returns the path of the daemon's saved peer list (`peers.json`).
+pub fn get_peers_path() -> PathBuf {
+    get_config_dir().join("peers.json")
+}
+
+/// Returns the path of the mempool admission journal (`mempool_journal`).
+pub fn get_mempool_journal_path() -> PathBuf {
+    get_config_dir().join("mempool_journal.jsonl")
+}
+
 pub fn load_wallet() -> Result<crate::wallet::Wallet> {
     // Load config to respect any CLI or saved overrides to wallet_path.
     // If loading config fails, fall back to the default wallet path.
     let cfg = load_config().unwrap_or_default();
-    let path = std::path::PathBuf::from(cfg.wallet_path);
-    // Use the provided path as-is (absolute or relative to cwd). The
-    // wallet helper will create parent directories as needed.
-    crate::wallet::load_or_create_wallet(&path.to_string_lossy())
+    // Use the provided path as-is (absolute or relative to cwd). A missing
+    // wallet is an error; only `owonero wallet new` creates one.
+    crate::wallet::load_wallet_file(&cfg.wallet_path)
 }
 
 pub fn load_config() -> Result<Config> {
     let path = get_config_path();
 
     if path.exists() {
-        let data = fs::read_to_string(&path).context("reading config file")?;
-        let config: Config = serde_json::from_str(&data).context("parsing config JSON")?;
-        config.validate()?;
-        Ok(config)
+        load_config_from(&path)
     } else {
         let default = Config::default();
         Ok(default)
     }
 }
 
+/// Read and validate the config at `path`; unlike `load_config`, a missing
+/// file is an error.
+pub fn load_config_from(path: &std::path::Path) -> Result<Config> {
+    let data = fs::read_to_string(path).context("reading config file")?;
+    let config: Config = serde_json::from_str(&data).context("parsing config JSON")?;
+    config.validate()?;
+    Ok(config)
+}
+
 pub fn save_config(config: &Config) -> Result<()> {
     let path = get_config_path();
     let data = serde_json::to_string_pretty(config).context("serializing config")?;
@@ -133,9 +458,11 @@ mod tests {
         let _ = fs::create_dir_all(&temp_dir);
         let temp_file = temp_dir.join("config.json");
 
-        let mut cfg = Config::default();
-        cfg.node_address = "127.0.0.1:1234".to_string();
-        cfg.daemon_port = 1234;
+        let cfg = Config {
+            node_address: "127.0.0.1:1234".to_string(),
+            daemon_port: 1234,
+            ..Default::default()
+        };
 
         // save to temporary directory
         fs::write(&temp_file, serde_json::to_string_pretty(&cfg).unwrap()).unwrap();
@@ -1,376 +1,6985 @@
-use crate::blockchain::Blockchain;
+use crate::blockchain::{Blockchain, Transaction};
+use crate::protocol::{
+    DEFAULT_MAX_MESSAGE_BYTES, HASHRATE_SWEEP_SECS, HEARTBEAT_SECS, MIN_PROTOCOL_VERSION,
+    PEER_EXCHANGE_ROUNDS, PEER_HEALTH_INTERVAL_SECS, PEER_SYNC_BATCH_BLOCKS,
+    PEER_SYNC_INTERVAL_SECS, PROTOCOL_VERSION, TEMPLATE_TTL_SECS,
+};
+use crate::rpc::{Access, DaemonContext, method_names};
 use futures::SinkExt;
 use futures::stream::StreamExt;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use tokio::net::{TcpListener, TcpStream};
-use tokio_tungstenite::{accept_async, tungstenite::Message};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use tokio::net::TcpStream;
+use tokio_tungstenite::accept_async_with_config;
+use tokio_tungstenite::tungstenite::{Message, protocol::WebSocketConfig};
+
+/// A peer as `peers.json` holds it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct KnownPeer {
+    address: String,
+    /// When the peer was added or last answered, unix seconds.
+    last_seen: u64,
+    /// Health checks failed in a row since it last answered.
+    #[serde(default)]
+    failures: u32,
+    /// Its height at the last health check it answered.
+    #[serde(default)]
+    last_height: Option<u64>,
+    /// From `peers` in the config: kept when it stops answering.
+    #[serde(skip)]
+    configured: bool,
+    /// Why the handshake found it on another chain. Kept, so it isn't
+    /// learned and tried again, but never synced from or passed on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    incompatible: Option<String>,
+    /// What `address` resolved to when it was added; empty if it didn't.
+    #[serde(skip)]
+    resolved: Vec<std::net::SocketAddr>,
+}
+
+impl KnownPeer {
+    fn new(address: String, last_seen: u64) -> Self {
+        Self {
+            address,
+            last_seen,
+            failures: 0,
+            last_height: None,
+            configured: false,
+            incompatible: None,
+            resolved: Vec::new(),
+        }
+    }
+
+    /// Whether this is the peer at `address`, written that way or another
+    /// way that resolves to one of the same socket addresses.
+    fn is(&self, address: &str, resolved: &[std::net::SocketAddr]) -> bool {
+        self.address == address || self.resolved.iter().any(|a| resolved.contains(a))
+    }
+}
+
+/// A peer's standing as `getpeerinfo` reports it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerHealth {
+    pub peer: String,
+    pub last_seen: u64,
+    pub failures: u32,
+    pub last_height: Option<u64>,
+    /// Answered its last health check, or hasn't been checked yet.
+    pub healthy: bool,
+    pub configured: bool,
+    /// Why it's on another chain, when the handshake found it is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub incompatible: Option<String>,
+}
 
 pub struct PeerManager {
-    peers: Mutex<Vec<String>>,
+    peers: Mutex<Vec<KnownPeer>>,
+    /// Most peers `learn_peer` will hold; configured peers always count.
+    max: AtomicUsize,
+    /// Failed health checks in a row after which a learned peer is
+    /// dropped and a configured one no longer synced from; 0 never.
+    max_failures: AtomicU32,
+    /// This node's daemon port, to recognise its own address; 0 if unknown.
+    own_port: u16,
+    /// Where the list is saved whenever it changes; `None` keeps it in
+    /// memory only.
+    store: Option<std::path::PathBuf>,
+    /// Tells `host:6969` and `1.2.3.4:6969` apart, or not.
+    resolver: Arc<crate::net::Resolver>,
+}
+
+fn unix_now() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64
 }
 
 impl PeerManager {
     pub fn new() -> Self {
+        Self::for_node(0, DEFAULT_MAX_PEERS)
+    }
+
+    /// Peers of a daemon listening on `own_port`, learning at most `max`.
+    pub fn for_node(own_port: u16, max: usize) -> Self {
         Self {
             peers: Mutex::new(Vec::new()),
+            max: AtomicUsize::new(max),
+            max_failures: AtomicU32::new(DEFAULT_PEER_MAX_FAILURES),
+            own_port,
+            store: None,
+            resolver: crate::net::shared(),
         }
     }
 
-    pub fn add_peer(&self, addr: String) {
-        let mut peers = self.peers.lock().unwrap();
-        if !peers.contains(&addr) {
-            peers.push(addr);
-        }
+    /// Resolve peer host names with `resolver`.
+    #[cfg(test)]
+    pub fn with_resolver(mut self, resolver: crate::net::Resolver) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
     }
 
-    pub fn get_peers(&self) -> Vec<String> {
-        self.peers.lock().unwrap().clone()
+    /// Socket addresses of `addr`; none if it doesn't resolve, and then it
+    /// is only the same peer as the same string.
+    fn resolve(&self, addr: &str) -> Vec<std::net::SocketAddr> {
+        self.resolver.resolve(addr).unwrap_or_default()
     }
-}
 
-pub async fn run_daemon(
-    port: u16,
-    blockchain: Arc<Mutex<Blockchain>>,
-    pm: Arc<PeerManager>,
-    pool: bool,
-    standalone: bool,
-) -> anyhow::Result<()> {
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
-    println!("Daemon listening on :{} (WebSocket)", port);
+    /// Most peers the list holds.
+    pub fn max(&self) -> usize {
+        self.max.load(Ordering::Relaxed)
+    }
 
-    let shares: Arc<Mutex<HashMap<String, i64>>> = Arc::new(Mutex::new(HashMap::new()));
-    let mempool: Arc<Mutex<Vec<crate::blockchain::Transaction>>> = Arc::new(Mutex::new(Vec::new()));
-    let wallet_hashrates: Arc<Mutex<HashMap<String, (f64, u64)>>> =
-        Arc::new(Mutex::new(HashMap::new()));
+    /// Change the limit, e.g. on a config reload. Peers already beyond it
+    /// are kept; no more are learned until the list is below it.
+    pub fn set_max(&self, max: usize) {
+        self.max.store(max, Ordering::Relaxed);
+    }
 
-    // Background cleaner for stale hashrates
-    {
-        let wallet_hashrates_clean = wallet_hashrates.clone();
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(std::time::Duration::from_secs(5));
-            loop {
-                interval.tick().await;
-                let now = chrono::Utc::now().timestamp() as u64;
-                let mut map = wallet_hashrates_clean.lock().unwrap();
-                let stale: Vec<String> = map
-                    .iter()
-                    .filter_map(|(k, (_hr, ts))| {
-                        if now.saturating_sub(*ts) > 10 {
-                            Some(k.clone())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
-                for k in stale {
-                    map.remove(&k);
+    /// Change how many failed health checks in a row a peer may have.
+    pub fn set_max_failures(&self, max_failures: u32) {
+        self.max_failures.store(max_failures, Ordering::Relaxed);
+    }
+
+    /// Save the list to `path` (`peers.json`) from now on.
+    pub fn with_store(mut self, path: std::path::PathBuf) -> Self {
+        self.store = Some(path);
+        self
+    }
+
+    /// Learn the peers saved in the store, dropping those not seen for
+    /// more than `max_age_secs` (0 keeps them all). Returns how many were
+    /// loaded; a missing file is an empty list.
+    pub fn load_saved(&self, max_age_secs: u64, now: u64) -> anyhow::Result<usize> {
+        let Some(path) = &self.store else {
+            return Ok(0);
+        };
+        let saved: Vec<KnownPeer> = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("{} is not a peer list: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(anyhow::anyhow!("failed to read {}: {}", path.display(), e)),
+        };
+        let total = saved.len();
+        let mut loaded = 0;
+        {
+            let mut peers = self.peers.lock();
+            for peer in saved {
+                if max_age_secs > 0 && now.saturating_sub(peer.last_seen) > max_age_secs {
+                    continue;
+                }
+                let Ok(address) = normalize_peer_addr(&peer.address) else {
+                    continue;
+                };
+                let resolved = self.resolve(&address);
+                if self.is_self(&address, &resolved)
+                    || peers.len() >= self.max()
+                    || peers.iter().any(|p| p.is(&address, &resolved))
+                {
+                    continue;
                 }
+                peers.push(KnownPeer {
+                    address,
+                    resolved,
+                    ..peer
+                });
+                loaded += 1;
             }
-        });
+        }
+        if loaded < total {
+            self.save();
+        }
+        Ok(loaded)
     }
 
-    // Background sync from peers
-    let sync_interval_secs = std::env::var("OWONERO_SYNC_INTERVAL")
-        .ok()
-        .and_then(|v| v.parse::<u64>().ok())
-        .unwrap_or(10);
-    let _max_sync_attempts = 3;
-    if !standalone {
-        let _blockchain_sync = blockchain.clone();
-        let pm_sync = pm.clone();
-        tokio::spawn(async move {
-            let mut interval =
-                tokio::time::interval(std::time::Duration::from_secs(sync_interval_secs));
-            loop {
-                interval.tick().await;
-                let peers = pm_sync.get_peers();
-                if peers.is_empty() {
-                    continue;
+    /// Write the list to the store, if there is one. A failed write is
+    /// reported and otherwise ignored: the list in memory is what counts.
+    fn save(&self) {
+        let Some(path) = &self.store else {
+            return;
+        };
+        let result = serde_json::to_string_pretty(&*self.peers.lock())
+            .map_err(anyhow::Error::from)
+            .and_then(|json| {
+                let tmp = path.with_extension("json.tmp");
+                std::fs::write(&tmp, json)?;
+                std::fs::rename(&tmp, path)?;
+                Ok(())
+            });
+        if let Err(e) = result {
+            eprintln!("Warning: failed to save {}: {:#}", path.display(), e);
+        }
+    }
+
+    /// Add a configured peer, whatever the limit. One found incompatible
+    /// before gets another handshake. A peer already known under another
+    /// name for the same address is kept, under the configured name.
+    pub fn add_peer(&self, addr: String) {
+        let known = self.peers.lock().iter().any(|p| p.address == addr);
+        let resolved = if known {
+            Vec::new()
+        } else {
+            self.resolve(&addr)
+        };
+        {
+            let mut peers = self.peers.lock();
+            if let Some(known) = peers.iter_mut().find(|p| p.is(&addr, &resolved)) {
+                known.last_seen = unix_now();
+                known.configured = true;
+                known.incompatible = None;
+                if known.address != addr {
+                    known.address = addr;
+                    known.resolved = resolved;
                 }
-                // Sync logic remains same as before (uses TCP/WebSocket to fetch chain)
-                // For now, we'll skip peer-to-peer sync during WebSocket migration
+            } else {
+                peers.push(KnownPeer {
+                    configured: true,
+                    resolved,
+                    ..KnownPeer::new(addr, unix_now())
+                });
             }
+        }
+        self.save();
+    }
+
+    /// Add a peer learned from another node's `getpeers`, or announced
+    /// with `addpeer`. Returns why it wasn't added, if it wasn't; a peer
+    /// already known is not an error.
+    pub async fn learn_peer(&self, addr: &str) -> Result<bool, String> {
+        let Some(addr) = self.check_new_peer(addr)? else {
+            return Ok(false);
+        };
+        let resolved = self.resolver.resolve_async(&addr).await.unwrap_or_default();
+        self.insert_learned(addr, resolved)
+    }
+
+    /// `learn_peer` for callers that can't wait on DNS. A literal or
+    /// recently resolved address is added right away; otherwise the lookup
+    /// and the rest of `learn_peer` run in the background, and this
+    /// returns `Ok(None)`.
+    pub fn learn_peer_now(self: &Arc<Self>, addr: &str) -> Result<Option<bool>, String> {
+        let Some(addr) = self.check_new_peer(addr)? else {
+            return Ok(Some(false));
+        };
+        if let Some(known) = self.resolver.cached(&addr) {
+            return self
+                .insert_learned(addr, known.unwrap_or_default())
+                .map(Some);
+        }
+        let pm = self.clone();
+        tokio::spawn(async move {
+            // Nobody waits for the answer: a refusal just leaves it out
+            let _ = pm.learn_peer(&addr).await;
         });
+        Ok(None)
     }
 
-    loop {
-        let accept_res = listener.accept().await;
-        let (socket, _) = match accept_res {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("Listener accept error: {}", e);
-                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                continue;
+    /// `addr` normalized, or `None` if it is in the list already. Checked
+    /// before any lookup, so a full list or a known name costs no query.
+    fn check_new_peer(&self, addr: &str) -> Result<Option<String>, String> {
+        let addr = normalize_peer_addr(addr)?;
+        let peers = self.peers.lock();
+        // Names already in the list aren't looked up again
+        if peers.iter().any(|p| p.address == addr) {
+            return Ok(None);
+        }
+        let max = self.max();
+        if peers.len() >= max {
+            return Err(format!("peer list is full ({} peers)", max));
+        }
+        Ok(Some(addr))
+    }
+
+    /// Second half of `learn_peer`, once `addr` is resolved.
+    fn insert_learned(
+        &self,
+        addr: String,
+        resolved: Vec<std::net::SocketAddr>,
+    ) -> Result<bool, String> {
+        if self.is_self(&addr, &resolved) {
+            return Err(format!("{} is this node", addr));
+        }
+        {
+            let mut peers = self.peers.lock();
+            if peers.iter().any(|p| p.is(&addr, &resolved)) {
+                return Ok(false);
+            }
+            // Checked again: others may have been learned during the lookup
+            let max = self.max();
+            if peers.len() >= max {
+                return Err(format!("peer list is full ({} peers)", max));
+            }
+            peers.push(KnownPeer {
+                resolved,
+                ..KnownPeer::new(addr, unix_now())
+            });
+        }
+        self.save();
+        Ok(true)
+    }
+
+    /// Note that `addr` answered. Saved at most every `PEER_SEEN_SAVE_SECS`
+    /// per peer, so a busy sync loop doesn't rewrite the file each round.
+    pub fn mark_seen(&self, addr: &str) {
+        let now = unix_now();
+        let changed = match self.peers.lock().iter_mut().find(|p| p.address == addr) {
+            Some(known) if now.saturating_sub(known.last_seen) >= PEER_SEEN_SAVE_SECS => {
+                known.last_seen = now;
+                true
             }
+            _ => false,
         };
+        if changed {
+            self.save();
+        }
+    }
 
-        let blockchain = blockchain.clone();
-        let shares = shares.clone();
-        let mempool = mempool.clone();
-        let wallet_hashrates = wallet_hashrates.clone();
-        let pool = pool;
-        let pm = if standalone {
-            Arc::new(PeerManager::new())
-        } else {
-            pm.clone()
+    /// Record a health check `addr` answered at `height`. A peer that had
+    /// been failing is saved right away, so a restart doesn't forget it
+    /// recovered.
+    pub fn record_check_ok(&self, addr: &str, height: u64) {
+        let now = unix_now();
+        let changed = match self.peers.lock().iter_mut().find(|p| p.address == addr) {
+            Some(known) => {
+                let recovered = known.failures > 0;
+                known.failures = 0;
+                known.last_height = Some(height);
+                if recovered || now.saturating_sub(known.last_seen) >= PEER_SEEN_SAVE_SECS {
+                    known.last_seen = now;
+                    true
+                } else {
+                    false
+                }
+            }
+            None => false,
         };
+        if changed {
+            self.save();
+        }
+    }
 
-        tokio::spawn(async move {
-            if let Err(e) = handle_websocket_connection(
-                socket,
-                blockchain,
-                pm,
-                shares,
-                mempool,
-                wallet_hashrates,
-                pool,
-            )
-            .await
-            {
-                eprintln!("WebSocket connection error: {}", e);
+    /// Note that `addr` is at `height`, e.g. after it took blocks we
+    /// relayed or a sync round compared chains with it.
+    pub fn note_height(&self, addr: &str, height: u64) {
+        if let Some(known) = self.peers.lock().iter_mut().find(|p| p.address == addr) {
+            known.last_height = Some(height);
+        }
+    }
+
+    /// Healthy peers on our chain last known to be below `height`, with
+    /// the height each was at. Peers whose height is unknown aren't
+    /// included.
+    pub fn lagging_peers(&self, height: u64) -> Vec<(String, u64)> {
+        self.peers
+            .lock()
+            .iter()
+            .filter(|p| p.failures == 0 && p.incompatible.is_none())
+            .filter_map(|p| Some((p.address.clone(), p.last_height.filter(|&h| h < height)?)))
+            .collect()
+    }
+
+    /// Record a health check `addr` failed. A learned peer that reaches
+    /// `max_failures` in a row is dropped and `true` returned; a configured
+    /// one is kept but no longer synced from until it answers again.
+    pub fn record_check_failed(&self, addr: &str) -> bool {
+        let max_failures = self.max_failures.load(Ordering::Relaxed);
+        let dropped = {
+            let mut peers = self.peers.lock();
+            let Some(i) = peers.iter().position(|p| p.address == addr) else {
+                return false;
+            };
+            peers[i].failures = peers[i].failures.saturating_add(1);
+            let dead = max_failures > 0 && peers[i].failures >= max_failures;
+            if dead && !peers[i].configured {
+                peers.remove(i);
+                true
+            } else {
+                false
             }
-        });
+        };
+        if dropped {
+            self.save();
+        }
+        dropped
+    }
+
+    /// Note that the handshake found `addr` on another chain. Returns
+    /// false if it was known to be already.
+    pub fn mark_incompatible(&self, addr: &str, reason: &str) -> bool {
+        let marked = match self.peers.lock().iter_mut().find(|p| p.address == addr) {
+            Some(known) if known.incompatible.is_none() => {
+                known.incompatible = Some(reason.to_string());
+                true
+            }
+            _ => false,
+        };
+        if marked {
+            self.save();
+        }
+        marked
+    }
+
+    /// Whether `addr` is a configured peer past `max_failures`, which the
+    /// sync loop skips until a health check gets through.
+    pub fn is_demoted(&self, addr: &str) -> bool {
+        let max_failures = self.max_failures.load(Ordering::Relaxed);
+        max_failures > 0
+            && self
+                .peers
+                .lock()
+                .iter()
+                .any(|p| p.address == addr && p.failures >= max_failures)
+    }
+
+    /// Peers that answered their last health check or haven't had one.
+    pub fn healthy_peers(&self) -> Vec<String> {
+        self.peers
+            .lock()
+            .iter()
+            .filter(|p| p.failures == 0 && p.incompatible.is_none())
+            .map(|p| p.address.clone())
+            .collect()
+    }
+
+    pub fn health(&self) -> Vec<PeerHealth> {
+        self.peers
+            .lock()
+            .iter()
+            .map(|p| PeerHealth {
+                peer: p.address.clone(),
+                last_seen: p.last_seen,
+                failures: p.failures,
+                last_height: p.last_height,
+                healthy: p.failures == 0,
+                configured: p.configured,
+                incompatible: p.incompatible.clone(),
+            })
+            .collect()
+    }
+
+    /// Whether `addr`, which resolved to `resolved`, is a loopback or
+    /// unspecified address on our own port.
+    fn is_self(&self, addr: &str, resolved: &[std::net::SocketAddr]) -> bool {
+        let Some((host, port)) = addr.rsplit_once(':') else {
+            return false;
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let local = |ip: std::net::IpAddr| ip.is_loopback() || ip.is_unspecified();
+        port.parse() == Ok(self.own_port)
+            && (host == "localhost"
+                || host.parse::<std::net::IpAddr>().is_ok_and(local)
+                || resolved.iter().any(|a| local(a.ip())))
+    }
+
+    pub fn remove_peer(&self, addr: &str) {
+        let removed = {
+            let mut peers = self.peers.lock();
+            let before = peers.len();
+            peers.retain(|p| p.address != addr);
+            peers.len() < before
+        };
+        if removed {
+            self.save();
+        }
+    }
+
+    /// Peers on this node's chain, as far as it knows.
+    pub fn get_peers(&self) -> Vec<String> {
+        self.peers
+            .lock()
+            .iter()
+            .filter(|p| p.incompatible.is_none())
+            .map(|p| p.address.clone())
+            .collect()
     }
 }
 
-async fn handle_websocket_connection(
-    socket: TcpStream,
-    blockchain: Arc<Mutex<Blockchain>>,
-    _pm: Arc<PeerManager>,
-    _shares: Arc<Mutex<HashMap<String, i64>>>,
-    mempool: Arc<Mutex<Vec<crate::blockchain::Transaction>>>,
-    wallet_hashrates: Arc<Mutex<HashMap<String, (f64, u64)>>>,
-    _pool: bool,
-) -> anyhow::Result<()> {
-    let mut ws = accept_async(socket).await?;
+/// Least time between two saves of the same peer's `last_seen`.
+const PEER_SEEN_SAVE_SECS: u64 = 3600;
+
+/// Default for config `max_peers`.
+pub const DEFAULT_MAX_PEERS: usize = 32;
+
+/// Default for config `peer_max_failures`.
+pub const DEFAULT_PEER_MAX_FAILURES: u32 = 5;
+
+/// Time a peer has to answer a health check.
+const PEER_HEALTH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Ask a peer for its height.
+async fn ping_peer(addr: &str) -> anyhow::Result<u64> {
+    let mut client = crate::ws_client::WsClient::connect(addr).await?;
+    let resp = client.command("getheight", serde_json::json!({})).await?;
+    resp["height"]
+        .as_u64()
+        .ok_or_else(|| anyhow::anyhow!("getheight answered without a height"))
+}
 
-    // Send greeting
-    let height = {
-        let bc = blockchain.lock().unwrap();
-        bc.chain.last().map(|b| b.index).unwrap_or(0)
+/// Ping every peer at once with `getheight` and record the results.
+/// Returns the peers dropped for failing too often.
+pub async fn check_peer_health(pm: &PeerManager) -> Vec<String> {
+    let peers = pm.get_peers();
+    let results = futures::future::join_all(
+        peers
+            .iter()
+            .map(|peer| tokio::time::timeout(PEER_HEALTH_TIMEOUT, ping_peer(peer))),
+    )
+    .await;
+    let mut dropped = Vec::new();
+    for (peer, result) in peers.into_iter().zip(results) {
+        match result {
+            Ok(Ok(height)) => pm.record_check_ok(&peer, height),
+            _ => {
+                if pm.record_check_failed(&peer) {
+                    dropped.push(peer);
+                }
+            }
+        }
+    }
+    dropped
+}
+
+/// `addr` as a peer address, `host:port` with a lowercase host, or why it
+/// isn't one. IPv6 hosts go in brackets.
+pub fn normalize_peer_addr(addr: &str) -> Result<String, String> {
+    let addr = addr.trim();
+    let invalid = || format!("{:?} is not a host:port address", addr);
+    let (host, port) = addr.rsplit_once(':').ok_or_else(invalid)?;
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+    let bare = host.trim_start_matches('[').trim_end_matches(']');
+    let valid_host = if host.starts_with('[') {
+        bare.parse::<std::net::Ipv6Addr>().is_ok()
+    } else {
+        !host.is_empty()
+            && host.len() <= 253
+            && host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
     };
-    ws.send(Message::Text(format!(
-        "{{\"type\":\"greeting\",\"height\":{}}}",
-        height
-    )))
-    .await?;
+    if port == 0 || !valid_host {
+        return Err(invalid());
+    }
+    Ok(format!("{}:{}", host.to_lowercase(), port))
+}
 
-    while let Some(msg) = ws.next().await {
-        let msg = msg?;
+/// Ask a connected peer for its peers and learn them. Returns how many
+/// were new.
+pub async fn exchange_peers(
+    pm: &PeerManager,
+    client: &mut crate::ws_client::WsClient,
+) -> anyhow::Result<usize> {
+    let resp = client.command("getpeers", serde_json::json!({})).await?;
+    let peers: Vec<String> = serde_json::from_value(resp["data"].clone())
+        .map_err(|_| anyhow::anyhow!("getpeers answered without a peer list"))?;
+    let mut learned = 0;
+    for peer in &peers {
+        if pm.learn_peer(peer).await == Ok(true) {
+            learned += 1;
+        }
+    }
+    Ok(learned)
+}
 
-        if let Message::Text(text) = msg {
-            let response =
-                process_command(&text, &blockchain, &mempool, &wallet_hashrates, &_pm).await;
-            ws.send(Message::Text(response)).await?;
-        } else if let Message::Binary(_) = msg {
-            ws.send(Message::Text(
-                r#"{"type":"error","message":"binary messages not supported"}"#.to_string(),
-            ))
-            .await?;
-        } else if let Message::Close(_) = msg {
-            break;
+/// Responses shorter than this are always sent as plain text frames even when
+/// compression is on; deflate does not pay off for small messages.
+const COMPRESSION_MIN_BYTES: usize = 1024;
+
+/// Process-wide limit on a message from a remote client (config
+/// `max_message_bytes`); 0 is no limit. Read when a connection opens.
+static MAX_MESSAGE_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_MESSAGE_BYTES);
+
+pub fn set_max_message_bytes(bytes: usize) {
+    MAX_MESSAGE_BYTES.store(bytes, Ordering::Relaxed);
+}
+
+pub fn max_message_bytes() -> usize {
+    MAX_MESSAGE_BYTES.load(Ordering::Relaxed)
+}
+
+/// Process-wide limit on unconfirmed ancestors (config `max_mempool_depth`).
+static MAX_MEMPOOL_DEPTH: AtomicUsize = AtomicUsize::new(5);
+
+pub fn set_max_mempool_depth(depth: usize) {
+    MAX_MEMPOOL_DEPTH.store(depth, Ordering::Relaxed);
+}
+
+pub fn max_mempool_depth() -> usize {
+    MAX_MEMPOOL_DEPTH.load(Ordering::Relaxed)
+}
+
+/// Deflate a JSON message for a binary frame. tungstenite has no
+/// permessage-deflate, so compression is negotiated per connection with
+/// `setcompression` and applied to whole messages instead.
+pub fn compress_message(text: &str) -> Vec<u8> {
+    let mut enc = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+    // Writing into a Vec cannot fail
+    enc.write_all(text.as_bytes()).unwrap();
+    enc.finish().unwrap()
+}
+
+/// Inflate a binary frame from `compress_message`. One that inflates to
+/// more than `limit` bytes is an error, so a small frame can't make the
+/// reader allocate without bound.
+pub fn decompress_message(data: &[u8], limit: usize) -> anyhow::Result<String> {
+    let mut text = String::new();
+    flate2::read::DeflateDecoder::new(data)
+        .take(limit as u64 + 1)
+        .read_to_string(&mut text)?;
+    if text.len() > limit {
+        anyhow::bail!("message inflates to more than {} bytes", limit);
+    }
+    Ok(text)
+}
+
+/// Events buffered per subscriber before it is told it lagged.
+pub const EVENT_BUFFER: usize = 256;
+
+/// Least time between two saves of changed pool share counts.
+const SHARES_SAVE_SECS: u64 = 60;
+
+/// Least time between two `syncprogress` events for the same sync.
+const SYNC_EVENT_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Something a `subscribe`d client is told about as it happens.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum NodeEvent {
+    /// A block was added to this node's chain.
+    NewBlock { block: crate::blockchain::Block },
+    /// A transaction entered this node's mempool.
+    NewTx { txid: String, tx: Transaction },
+    /// Catching up from `peer`; sent as blocks arrive and once at the end.
+    SyncProgress {
+        peer: String,
+        progress: crate::chain_sync::SyncProgress,
+    },
+    /// This node switched to a peer's branch, or refused to for dropping
+    /// more than `max_reorg_depth` blocks.
+    Reorg(ReorgRecord),
+}
+
+/// Methods served by nodes that predate `getcapabilities`.
+const LEGACY_METHODS: &[&str] = &[
+    "getchain",
+    "getlatest",
+    "getheight",
+    "getblock",
+    "getmempool",
+    "submittx",
+    "submitblock",
+    "getpeers",
+];
+
+/// Network identifier advertised in the greeting. Dev-mode nodes form their
+/// own network so their skip-PoW blocks never reach real nodes.
+pub fn network_id(dev_mode: bool, standalone: bool) -> &'static str {
+    match (dev_mode, standalone) {
+        (false, false) => "owonero",
+        (true, false) => "owonero-dev",
+        (false, true) => "owonero-standalone",
+        (true, true) => "owonero-dev-standalone",
+    }
+}
+
+/// What a node tells others about itself, in its greeting and in answer
+/// to `handshake`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeIdentity {
+    pub network: String,
+    pub genesis_hash: String,
+    pub protocol_version: u32,
+    pub height: u64,
+    pub tip_hash: String,
+}
+
+impl NodeIdentity {
+    pub fn of(ctx: &DaemonContext) -> Self {
+        let bc = ctx.blockchain.lock();
+        let hash_at = |block: Option<&crate::blockchain::Block>| {
+            block.map(|b| b.hash.clone()).unwrap_or_default()
+        };
+        Self {
+            network: ctx.network().to_string(),
+            genesis_hash: hash_at(bc.chain.first()),
+            protocol_version: PROTOCOL_VERSION,
+            height: bc.chain.last().map_or(0, |b| b.index),
+            tip_hash: hash_at(bc.chain.last()),
         }
     }
 
-    Ok(())
+    /// Why a client speaking `version` can't be served, if it can't.
+    pub fn version_incompatibility(version: u64) -> Option<String> {
+        (version < MIN_PROTOCOL_VERSION as u64).then(|| {
+            format!(
+                "protocol version {} (this node needs {} or later)",
+                version, MIN_PROTOCOL_VERSION
+            )
+        })
+    }
+
+    /// Why a node on `network` with genesis `genesis_hash` (when it says)
+    /// is on another chain than this one, if it is.
+    pub fn incompatibility(&self, network: &str, genesis_hash: Option<&str>) -> Option<String> {
+        if network != self.network {
+            return Some(format!(
+                "network {} (this node is on {})",
+                network, self.network
+            ));
+        }
+        match genesis_hash {
+            Some(genesis) if !genesis.eq_ignore_ascii_case(&self.genesis_hash) => Some(format!(
+                "genesis {} (this node's is {})",
+                genesis, self.genesis_hash
+            )),
+            _ => None,
+        }
+    }
 }
 
-async fn process_command(
-    cmd_text: &str,
-    blockchain: &Arc<Mutex<Blockchain>>,
-    mempool: &Arc<Mutex<Vec<crate::blockchain::Transaction>>>,
-    _wallet_hashrates: &Arc<Mutex<HashMap<String, (f64, u64)>>>,
-    pm: &Arc<PeerManager>,
-) -> String {
-    // Parse as JSON RPC-like command: {"method":"...", "params":{...}}
-    match serde_json::from_str::<serde_json::Value>(cmd_text) {
-        Ok(json) => {
-            let method = json
-                .get("method")
-                .and_then(|m| m.as_str())
-                .unwrap_or("unknown");
-
-            match method {
-                "getchain" => {
-                    let bc = blockchain.lock().unwrap();
-                    match serde_json::to_value(&*bc) {
-                        Ok(data) => {
-                            serde_json::json!({"type": "response", "method": "getchain", "data": data})
-                                .to_string()
-                        }
-                        Err(_) => serde_json::json!({"type":"error","message":"failed to serialize chain"}).to_string(),
-                    }
-                }
-                "getlatest" => {
-                    let bc = blockchain.lock().unwrap();
-                    if let Some(latest) = bc.chain.last() {
-                        match serde_json::to_value(latest) {
-                            Ok(data) => serde_json::json!({"type": "response", "method": "getlatest", "data": data}).to_string(),
-                            Err(_) => serde_json::json!({"type":"error","message":"failed to serialize block"}).to_string(),
-                        }
-                    } else {
-                        serde_json::json!({"type": "response", "method": "getlatest", "data": null})
-                            .to_string()
-                    }
-                }
-                "getheight" => {
-                    let bc = blockchain.lock().unwrap();
-                    let height = bc.chain.last().map(|b| b.index).unwrap_or(0);
-                    serde_json::json!({"type": "response", "method": "getheight", "height": height})
-                        .to_string()
-                }
-                "getblock" => {
-                    let idx = json.get("index").and_then(|i| i.as_u64()).unwrap_or(0) as usize;
-                    let bc = blockchain.lock().unwrap();
-                    if idx < bc.chain.len() {
-                        match serde_json::to_value(&bc.chain[idx]) {
-                            Ok(data) => serde_json::json!({"type": "response", "method": "getblock", "data": data}).to_string(),
-                            Err(_) => serde_json::json!({"type":"error","message":"failed to serialize block"}).to_string(),
-                        }
-                    } else {
-                        serde_json::json!({"type": "error", "message": "block not found"})
-                            .to_string()
-                    }
-                }
-                "getmempool" => {
-                    let mp = mempool.lock().unwrap();
-                    match serde_json::to_value(&*mp) {
-                        Ok(data) => serde_json::json!({"type": "response", "method": "getmempool", "data": data}).to_string(),
-                        Err(_) => serde_json::json!({"type":"error","message":"failed to serialize mempool"}).to_string(),
-                    }
-                }
-                "submittx" => {
-                    // Check both top-level and params for backward compatibility
-                    let tx_val = json.get("tx").or_else(|| json.get("params").and_then(|p| p.get("tx")));
-                    if let Some(tx_val) = tx_val {
-                        match serde_json::from_value::<crate::blockchain::Transaction>(
-                            tx_val.clone(),
-                        ) {
-                            Ok(tx) => {
-                                let valid =
-                                    crate::blockchain::verify_transaction_signature(&tx, &tx.pub_key);
-                                if !valid {
-                                    return serde_json::json!({"type":"error","message":"rejected: invalid signature"}).to_string();
-                                }
-
-                                if tx.amount <= 0 {
-                                    return serde_json::json!({"type":"error","message":"rejected: invalid amount"}).to_string();
-                                }
-
-                                let balances = {
-                                    let bc = blockchain.lock().unwrap();
-                                    let mut map: HashMap<String, i64> = HashMap::new();
-                                    for b in &bc.chain {
-                                        for t in &b.transactions {
-                                            let to = t.to.trim().to_lowercase();
-                                            if t.from != "coinbase" {
-                                                let from = t.from.trim().to_lowercase();
-                                                *map.entry(from).or_insert(0) -= t.amount;
-                                            }
-                                            *map.entry(to).or_insert(0) += t.amount;
-                                        }
-                                    }
-                                    map
-                                };
-
-                                let pending_out: i64 = {
-                                    let mp = mempool.lock().unwrap();
-                                    mp.iter()
-                                        .filter(|t| {
-                                            t.from
-                                                .trim()
-                                                .eq_ignore_ascii_case(&tx.from.trim().to_lowercase())
-                                        })
-                                        .map(|t| t.amount)
-                                        .sum()
-                                };
-
-                                let from_key = tx.from.trim().to_lowercase();
-                                let onchain_bal = balances.get(&from_key).cloned().unwrap_or(0);
-                                if from_key != "coinbase" && onchain_bal - pending_out < tx.amount {
-                                    return serde_json::json!({"type":"error","message":"rejected: insufficient funds"}).to_string();
-                                }
-
-                                {
-                                    let mut mp = mempool.lock().unwrap();
-                                    mp.push(tx);
-                                }
-                                serde_json::json!({"type": "response", "method": "submittx", "status": "ok"})
-                                    .to_string()
-                            }
-                            Err(_) => serde_json::json!({"type":"error","message":"failed to parse transaction"}).to_string(),
-                        }
-                    } else {
-                        serde_json::json!({"type":"error","message":"missing tx field"}).to_string()
-                    }
-                }
-                "submitblock" => {
-                    // Check both top-level and params for backward compatibility
-                    let block_val = json.get("block").or_else(|| json.get("params").and_then(|p| p.get("block")));
-                    if let Some(block_val) = block_val {
-                        match serde_json::from_value::<crate::blockchain::Block>(block_val.clone())
-                        {
-                            Ok(block) => {
-                                let response = {
-                                    let mut bc = blockchain.lock().unwrap();
-                                    let dyn_diff = bc.get_dynamic_difficulty();
-
-                                    if let Some(last) = bc.chain.last() {
-                                        if block.index <= last.index {
-                                            format!(
-                                                "rejected: block index {} already exists (current height {})",
-                                                block.index, last.index
-                                            )
-                                        } else if let Some(err) =
-                                            bc.validate_block_verbose(&block, dyn_diff, false)
-                                        {
-                                            format!("rejected: {}", err)
-                                        } else {
-                                            let added = bc.add_block(block, dyn_diff);
-                                            if added {
-                                                let _ = bc.save_to_file("blockchain.json");
-                                                "ok".to_string()
-                                            } else {
-                                                "error: failed to add block".to_string()
-                                            }
-                                        }
-                                    } else {
-                                        if let Some(err) =
-                                            bc.validate_block_verbose(&block, dyn_diff, false)
-                                        {
-                                            format!("rejected: {}", err)
-                                        } else {
-                                            let added = bc.add_block(block, dyn_diff);
-                                            if added {
-                                                let _ = bc.save_to_file("blockchain.json");
-                                                "ok".to_string()
-                                            } else {
-                                                "error: failed to add block".to_string()
-                                            }
-                                        }
-                                    }
-                                };
-                                serde_json::json!({"type": "response", "method": "submitblock", "status": response})
-                                    .to_string()
-                            }
-                            Err(_) => serde_json::json!({"type":"error","message":"failed to parse block"}).to_string(),
-                        }
-                    } else {
-                        serde_json::json!({"type":"error","message":"missing block field"})
-                            .to_string()
-                    }
-                }
-                "getpeers" => {
-                    let peers = pm.get_peers();
-                    match serde_json::to_value(peers) {
-                        Ok(data) => serde_json::json!({"type": "response", "method": "getpeers", "data": data}).to_string(),
-                        Err(_) => serde_json::json!({"type":"error","message":"failed to serialize peers"}).to_string(),
-                    }
-                }
-                _ => serde_json::json!({"type":"error","message":"unknown method"}).to_string(),
+/// Protocol version and method list a node advertises to clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub protocol_version: u32,
+    pub methods: Vec<String>,
+}
+
+impl Capabilities {
+    pub fn current() -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            methods: crate::rpc::METHODS
+                .iter()
+                .filter(|m| m.access != Access::Dev)
+                .map(|m| m.name.to_string())
+                .collect(),
+        }
+    }
+
+    /// Capabilities of this node, including the dev methods when enabled.
+    pub fn for_node(dev_mode: bool) -> Self {
+        let mut caps = Self::current();
+        if dev_mode {
+            caps.methods
+                .extend(method_names(Access::Dev).map(|m| m.to_string()));
+        }
+        caps
+    }
+
+    /// What a pre-`getcapabilities` node (protocol version 0) can do.
+    pub fn legacy() -> Self {
+        Self {
+            protocol_version: 0,
+            methods: LEGACY_METHODS.iter().map(|m| m.to_string()).collect(),
+        }
+    }
+
+    pub fn supports(&self, method: &str) -> bool {
+        self.methods.iter().any(|m| m == method)
+    }
+}
+
+/// Maximum number of rejected/evicted transactions remembered for `gettxstatus`.
+const TX_REJECTION_LOG_CAP: usize = 10_000;
+
+/// Lifecycle state of a transaction as seen by this daemon.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum TxStatus {
+    Confirmed { height: u64 },
+    Pending,
+    Rejected { reason: String, timestamp: i64 },
+    Unknown,
+}
+
+/// Bounded, in-memory record of recent transaction rejections and mempool
+/// evictions keyed by txid. Oldest entries are dropped once the cap is hit;
+/// nothing here is persisted.
+pub struct TxRejectionLog {
+    order: VecDeque<String>,
+    entries: HashMap<String, (String, i64)>,
+    cap: usize,
+}
+
+impl TxRejectionLog {
+    pub fn new() -> Self {
+        Self::with_capacity(TX_REJECTION_LOG_CAP)
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        Self {
+            order: VecDeque::new(),
+            entries: HashMap::new(),
+            cap: cap.max(1),
+        }
+    }
+
+    pub fn record(&mut self, txid: &str, reason: &str) {
+        let now = chrono::Utc::now().timestamp();
+        if self
+            .entries
+            .insert(txid.to_string(), (reason.to_string(), now))
+            .is_none()
+        {
+            self.order.push_back(txid.to_string());
+        }
+        while self.order.len() > self.cap {
+            if let Some(old) = self.order.pop_front() {
+                self.entries.remove(&old);
+            }
+        }
+    }
+
+    pub fn get(&self, txid: &str) -> Option<&(String, i64)> {
+        self.entries.get(txid)
+    }
+}
+
+/// Something a connection sent that costs the node work for nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Misbehavior {
+    InvalidJson,
+    BadSignature,
+    InvalidBlock,
+}
+
+impl Misbehavior {
+    /// Points toward a ban: a block costs a full hash to reject.
+    pub fn points(self) -> u32 {
+        match self {
+            Misbehavior::InvalidJson => 10,
+            Misbehavior::BadSignature => 20,
+            Misbehavior::InvalidBlock => 50,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Misbehavior::InvalidJson => "invalid JSON",
+            Misbehavior::BadSignature => "transaction with a bad signature",
+            Misbehavior::InvalidBlock => "invalid block",
+        }
+    }
+}
+
+/// Default for config `ban_threshold`.
+pub const DEFAULT_BAN_THRESHOLD: u32 = 100;
+/// Default for config `ban_duration_secs`.
+pub const DEFAULT_BAN_DURATION_SECS: u64 = 3600;
+/// Misbehavior points are forgotten this long after the first one.
+const MISBEHAVIOR_WINDOW_SECS: i64 = 3600;
+
+/// `getbans` entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BanEntry {
+    pub ip: String,
+    /// What pushed it over the threshold.
+    pub reason: String,
+    pub banned_at: i64,
+    pub until: i64,
+}
+
+/// Misbehavior points per remote IP, and the IPs banned for reaching
+/// `threshold`. Bans lapse on their own after `duration_secs`.
+#[derive(Debug)]
+pub struct BanList {
+    /// Points and when the first of them was scored.
+    scores: HashMap<std::net::IpAddr, (u32, i64)>,
+    bans: BTreeMap<std::net::IpAddr, BanEntry>,
+    /// 0 never bans.
+    threshold: u32,
+    duration_secs: u64,
+}
+
+impl Default for BanList {
+    fn default() -> Self {
+        Self::new(DEFAULT_BAN_THRESHOLD, DEFAULT_BAN_DURATION_SECS)
+    }
+}
+
+impl BanList {
+    pub fn new(threshold: u32, duration_secs: u64) -> Self {
+        Self {
+            scores: HashMap::new(),
+            bans: BTreeMap::new(),
+            threshold,
+            duration_secs,
+        }
+    }
+
+    /// Change the limits, e.g. on a config reload. Current bans keep the
+    /// expiry they were given.
+    pub fn configure(&mut self, threshold: u32, duration_secs: u64) {
+        self.threshold = threshold;
+        self.duration_secs = duration_secs;
+    }
+
+    /// Score `offense` against `ip`. Returns the new ban when it crosses
+    /// the threshold.
+    pub fn record(
+        &mut self,
+        ip: std::net::IpAddr,
+        offense: Misbehavior,
+        now: i64,
+    ) -> Option<BanEntry> {
+        if self.threshold == 0 || self.is_banned(ip, now) {
+            return None;
+        }
+        self.scores
+            .retain(|_, (_, since)| now - *since < MISBEHAVIOR_WINDOW_SECS);
+        let score = self.scores.entry(ip).or_insert((0, now));
+        score.0 = score.0.saturating_add(offense.points());
+        if score.0 < self.threshold {
+            return None;
+        }
+        self.scores.remove(&ip);
+        let ban = BanEntry {
+            ip: ip.to_string(),
+            reason: offense.as_str().to_string(),
+            banned_at: now,
+            until: now.saturating_add(self.duration_secs as i64),
+        };
+        self.bans.insert(ip, ban.clone());
+        Some(ban)
+    }
+
+    pub fn is_banned(&self, ip: std::net::IpAddr, now: i64) -> bool {
+        self.bans.get(&ip).is_some_and(|ban| ban.until > now)
+    }
+
+    /// Bans still in force, dropping the ones that have lapsed.
+    pub fn current(&mut self, now: i64) -> Vec<BanEntry> {
+        self.bans.retain(|_, ban| ban.until > now);
+        self.bans.values().cloned().collect()
+    }
+}
+
+/// Admission decisions kept in memory for `getmempooljournal`.
+const MEMPOOL_JOURNAL_CAP: usize = 1000;
+
+/// One `submittx`/`importmempool` decision and what the node knew about
+/// the sender when it made it. Everything here is public on chain or in
+/// the mempool already.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdmissionEntry {
+    pub timestamp: i64,
+    pub txid: String,
+    /// Peer address of the submitting connection.
+    pub source: String,
+    /// The connection's `hello` agent, if it sent one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    pub method: String,
+    /// "accepted" or "rejected".
+    pub decision: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub sender: String,
+    /// Chain height the decision was made at.
+    pub height: u64,
+    /// Sender's confirmed balance at that height.
+    pub sender_balance: i64,
+    /// Sender's payments already waiting in the mempool.
+    pub pending_out: i64,
+}
+
+/// The most recent admission decisions, oldest dropped first. With a file
+/// (`mempool_journal: true`) every entry is also appended to it as a JSON
+/// line, so decisions survive the ring buffer and restarts.
+pub struct AdmissionJournal {
+    entries: VecDeque<AdmissionEntry>,
+    file: Option<std::path::PathBuf>,
+}
+
+impl AdmissionJournal {
+    pub fn new(file: Option<std::path::PathBuf>) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            file,
+        }
+    }
+
+    pub fn record(&mut self, entry: AdmissionEntry) {
+        if let Some(path) = &self.file {
+            let appended = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut f| writeln!(f, "{}", serde_json::json!(entry)));
+            if let Err(e) = appended {
+                eprintln!("Failed to append to {}: {}", path.display(), e);
+            }
+        }
+        self.entries.push_back(entry);
+        while self.entries.len() > MEMPOOL_JOURNAL_CAP {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Up to `limit` entries, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<AdmissionEntry> {
+        self.entries.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+/// How many issued templates the node remembers for classifying late
+/// submissions.
+const TEMPLATE_LOG_CAP: usize = 256;
+/// Accepted block hashes remembered for the duplicate fast path.
+const SEEN_BLOCKS_CAP: usize = 1024;
+/// Most side blocks kept at once; the oldest go first.
+const SIDE_BLOCKS_CAP: usize = 512;
+/// Most orphan blocks kept at once; the oldest go first.
+const ORPHAN_BLOCKS_CAP: usize = 64;
+/// How long an orphan block waits for its parent.
+pub const ORPHAN_TTL: std::time::Duration = std::time::Duration::from_secs(600);
+/// Outstanding `getwork` jobs remembered per node.
+const WORK_LOG_CAP: usize = 256;
+/// Relayed transaction signatures remembered, so relays don't loop.
+const RELAY_SEEN_CAP: usize = 10_000;
+/// Transactions waiting to be relayed; new ones are dropped past this.
+const RELAY_QUEUE_CAP: usize = 1000;
+/// How often queued transactions are sent to peers.
+const RELAY_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+/// Time allowed for connecting to a peer, or for handing it one
+/// transaction or block.
+const RELAY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+/// Most blocks pushed to a peer that is behind; one further behind is left
+/// to catch up by its own sync.
+const RELAY_BLOCKS_MAX: u64 = 16;
+
+/// A block template handed out by `getblocktemplate`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IssuedTemplate {
+    pub index: u64,
+    pub prev_hash: String,
+    pub expires_at: i64,
+}
+
+impl IssuedTemplate {
+    /// Past its expiry, or the chain has already moved beyond it.
+    pub fn is_expired(&self, tip: u64, now: i64) -> bool {
+        now >= self.expires_at || self.index <= tip
+    }
+}
+
+/// Bounded record of recently issued templates keyed by template id.
+pub struct TemplateLog {
+    order: VecDeque<String>,
+    entries: HashMap<String, IssuedTemplate>,
+}
+
+impl TemplateLog {
+    pub fn new() -> Self {
+        Self {
+            order: VecDeque::new(),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Remember a template for the block at `index` and return its id.
+    pub fn issue(&mut self, index: u64, prev_hash: &str, now: i64) -> (String, IssuedTemplate) {
+        let id = format!("{:016x}", rand::random::<u64>());
+        let template = IssuedTemplate {
+            index,
+            prev_hash: prev_hash.to_string(),
+            expires_at: now + TEMPLATE_TTL_SECS,
+        };
+        self.entries.insert(id.clone(), template.clone());
+        self.order.push_back(id.clone());
+        while self.order.len() > TEMPLATE_LOG_CAP {
+            if let Some(old) = self.order.pop_front() {
+                self.entries.remove(&old);
+            }
+        }
+        (id, template)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&IssuedTemplate> {
+        self.entries.get(id)
+    }
+}
+
+/// Hashes of recently accepted blocks. A block seen here (or already in the
+/// chain at its height) is answered without validating it again, so a block
+/// bouncing between relaying peers costs one lookup per hop.
+pub struct SeenBlocks {
+    order: VecDeque<String>,
+    hashes: HashSet<String>,
+}
+
+impl SeenBlocks {
+    pub fn new() -> Self {
+        Self {
+            order: VecDeque::new(),
+            hashes: HashSet::new(),
+        }
+    }
+
+    /// Remember `hash`, forgetting the least recently accepted past the cap.
+    pub fn insert(&mut self, hash: &str) {
+        if !self.hashes.insert(hash.to_string()) {
+            return;
+        }
+        self.order.push_back(hash.to_string());
+        while self.order.len() > SEEN_BLOCKS_CAP {
+            if let Some(old) = self.order.pop_front() {
+                self.hashes.remove(&old);
+            }
+        }
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.hashes.contains(hash)
+    }
+}
+
+/// Blocks on branches that split off our chain below its tip: submitted
+/// ones that lost a race for a height, and ours a reorg dropped. Each is
+/// kept until its branch holds more work than ours or newer ones push it
+/// out, so a network split by two miners finding the same height heals
+/// once either side mines on.
+pub struct SideBlocks {
+    order: VecDeque<String>,
+    blocks: HashMap<String, crate::blockchain::Block>,
+}
+
+impl SideBlocks {
+    pub fn new() -> Self {
+        Self {
+            order: VecDeque::new(),
+            blocks: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, block: crate::blockchain::Block) {
+        if self.blocks.contains_key(&block.hash) {
+            return;
+        }
+        self.order.push_back(block.hash.clone());
+        self.blocks.insert(block.hash.clone(), block);
+        while self.order.len() > SIDE_BLOCKS_CAP {
+            if let Some(old) = self.order.pop_front() {
+                self.blocks.remove(&old);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, hash: &str) {
+        if self.blocks.remove(hash).is_some() {
+            self.order.retain(|h| h != hash);
+        }
+    }
+
+    /// Whether `block` builds on a side block.
+    pub fn extended_by(&self, block: &crate::blockchain::Block) -> bool {
+        self.blocks
+            .get(&block.prev_hash)
+            .is_some_and(|parent| parent.index + 1 == block.index)
+    }
+
+    /// Difficulty the schedule asks of `block`, following the chain its
+    /// parent is on: ours up to a block below our tip, or a side branch.
+    /// `None` if the parent is neither.
+    pub fn difficulty_for(
+        &self,
+        main: &Blockchain,
+        block: &crate::blockchain::Block,
+    ) -> Option<u32> {
+        use crate::blockchain::{DIFFICULTY_WINDOW, dynamic_difficulty_after};
+        let parent_height = (block.index as usize).checked_sub(1)?;
+        let (fork, branch) = if parent_height + 1 < main.chain.len()
+            && main.chain[parent_height].hash == block.prev_hash
+        {
+            (parent_height + 1, Vec::new())
+        } else {
+            let parent = self
+                .blocks
+                .get(&block.prev_hash)
+                .filter(|p| p.index as usize == parent_height)?;
+            self.branch(main, parent)?
+        };
+        // The schedule only looks this far back
+        let recent = DIFFICULTY_WINDOW + 1;
+        let keep = branch.len().min(recent);
+        let mut history = main.chain[fork.saturating_sub(recent - keep)..fork].to_vec();
+        history.extend_from_slice(&branch[branch.len() - keep..]);
+        Some(dynamic_difficulty_after(&history, main.target_block_time))
+    }
+
+    /// The branch ending in `tip`, oldest block first, and the height it
+    /// takes over `main` from. `None` unless it gets back through side
+    /// blocks to a block of `main` below its tip.
+    pub fn branch(
+        &self,
+        main: &Blockchain,
+        tip: &crate::blockchain::Block,
+    ) -> Option<(usize, Vec<crate::blockchain::Block>)> {
+        let mut branch = vec![tip.clone()];
+        loop {
+            let oldest = &branch[branch.len() - 1];
+            let parent_height = oldest.index.checked_sub(1)? as usize;
+            if parent_height + 1 < main.chain.len()
+                && main.chain[parent_height].hash == oldest.prev_hash
+            {
+                branch.reverse();
+                return Some((parent_height + 1, branch));
+            }
+            let parent = self
+                .blocks
+                .get(&oldest.prev_hash)
+                .filter(|p| p.index as usize == parent_height)?;
+            branch.push(parent.clone());
+        }
+    }
+}
+
+/// Blocks past our tip whose parent hasn't arrived, keyed by the parent's
+/// hash: a miner's block N+2 that beat N+1 to the node, say. Each waits
+/// up to `ORPHAN_TTL` and is connected once its parent is; the cap keeps
+/// a flood of them from eating memory.
+pub struct OrphanBlocks {
+    /// Arrival, parent hash and hash of each, oldest first.
+    order: VecDeque<(std::time::Instant, String, String)>,
+    by_parent: HashMap<String, Vec<crate::blockchain::Block>>,
+}
+
+impl OrphanBlocks {
+    pub fn new() -> Self {
+        Self {
+            order: VecDeque::new(),
+            by_parent: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    fn forget(&mut self, prev_hash: &str, hash: &str) {
+        if let Some(children) = self.by_parent.get_mut(prev_hash) {
+            children.retain(|b| b.hash != hash);
+            if children.is_empty() {
+                self.by_parent.remove(prev_hash);
+            }
+        }
+    }
+
+    /// Drop the orphans that waited longer than `ORPHAN_TTL`.
+    pub fn expire(&mut self, now: std::time::Instant) {
+        while let Some((arrived, _, _)) = self.order.front() {
+            if now.duration_since(*arrived) < ORPHAN_TTL {
+                break;
+            }
+            if let Some((_, prev_hash, hash)) = self.order.pop_front() {
+                self.forget(&prev_hash, &hash);
+            }
+        }
+    }
+
+    /// Park `block`; `false` if it already was.
+    pub fn insert(&mut self, block: crate::blockchain::Block, now: std::time::Instant) -> bool {
+        self.expire(now);
+        if self.order.iter().any(|(_, _, hash)| *hash == block.hash) {
+            return false;
+        }
+        self.order
+            .push_back((now, block.prev_hash.clone(), block.hash.clone()));
+        self.by_parent
+            .entry(block.prev_hash.clone())
+            .or_default()
+            .push(block);
+        while self.order.len() > ORPHAN_BLOCKS_CAP {
+            if let Some((_, prev_hash, hash)) = self.order.pop_front() {
+                self.forget(&prev_hash, &hash);
+            }
+        }
+        true
+    }
+
+    /// Unpark the orphans that build on `hash`, oldest first.
+    pub fn take_children(
+        &mut self,
+        hash: &str,
+        now: std::time::Instant,
+    ) -> Vec<crate::blockchain::Block> {
+        self.expire(now);
+        let children = self.by_parent.remove(hash).unwrap_or_default();
+        if !children.is_empty() {
+            self.order.retain(|(_, prev_hash, _)| prev_hash != hash);
+        }
+        children
+    }
+}
+
+/// Whether `block` goes past our tip without building on it, so a block
+/// before it is still to come.
+pub fn awaits_parent(bc: &Blockchain, block: &crate::blockchain::Block) -> bool {
+    bc.chain.last().is_some_and(|tip| {
+        block.index > tip.index + 1 || (block.index == tip.index + 1 && block.prev_hash != tip.hash)
+    })
+}
+
+/// Append the parked orphans that build on our tip, then theirs in turn,
+/// as long as they validate. Returns how many were added; persisting the
+/// chain is up to the caller.
+pub fn connect_orphans(ctx: &DaemonContext, bc: &mut Blockchain) -> usize {
+    let mut added = 0;
+    while let Some(tip) = bc.chain.last().map(|b| b.hash.clone()) {
+        let children = ctx
+            .orphan_blocks
+            .lock()
+            .take_children(&tip, std::time::Instant::now());
+        let difficulty = bc.get_dynamic_difficulty();
+        let Some(child) = children
+            .into_iter()
+            .find(|b| bc.validate_block(b, difficulty, false))
+        else {
+            break;
+        };
+        if !bc.add_block(child, difficulty) {
+            break;
+        }
+        added += 1;
+        if let Some(tip) = bc.chain.last() {
+            ctx.seen_blocks.lock().insert(&tip.hash);
+            ctx.publish(NodeEvent::NewBlock { block: tip.clone() });
+        }
+        prune_mempool_after_block(bc, &mut ctx.mempool.lock(), &mut ctx.rejections.lock());
+    }
+    if added > 0 {
+        (ctx.log)(&format!(
+            "[daemon] connected {} orphan block(s), height now {}",
+            added,
+            bc.chain.len() - 1
+        ));
+    }
+    added
+}
+
+/// Admitted transactions waiting to be passed on to peers. Each signature
+/// is relayed once, so a transaction two nodes keep offering each other
+/// stops after one round.
+pub struct TxRelay {
+    queue: VecDeque<Transaction>,
+    seen_order: VecDeque<String>,
+    seen: HashSet<String>,
+    /// Transactions refused because the queue was full, since the last
+    /// `take`.
+    dropped: usize,
+}
+
+impl TxRelay {
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            seen_order: VecDeque::new(),
+            seen: HashSet::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Queue `tx` unless its signature was relayed before. Returns whether
+    /// it was queued.
+    pub fn offer(&mut self, tx: &Transaction) -> bool {
+        if self.seen.contains(&tx.signature) {
+            return false;
+        }
+        if self.queue.len() >= RELAY_QUEUE_CAP {
+            self.dropped += 1;
+            return false;
+        }
+        self.seen.insert(tx.signature.clone());
+        self.seen_order.push_back(tx.signature.clone());
+        while self.seen_order.len() > RELAY_SEEN_CAP {
+            if let Some(old) = self.seen_order.pop_front() {
+                self.seen.remove(&old);
+            }
+        }
+        self.queue.push_back(tx.clone());
+        true
+    }
+
+    /// Everything queued, and how many were dropped since the last call.
+    pub fn take(&mut self) -> (Vec<Transaction>, usize) {
+        let dropped = std::mem::take(&mut self.dropped);
+        (self.queue.drain(..).collect(), dropped)
+    }
+}
+
+/// Blocks handed out by `getwork`, keyed by job id. A job is only good
+/// while its parent is the tip; `retain_tip` drops the rest.
+pub struct WorkLog {
+    order: VecDeque<String>,
+    jobs: HashMap<String, crate::blockchain::Block>,
+}
+
+impl WorkLog {
+    pub fn new() -> Self {
+        Self {
+            order: VecDeque::new(),
+            jobs: HashMap::new(),
+        }
+    }
+
+    /// Remember `block` as a job and return its id.
+    pub fn issue(&mut self, block: crate::blockchain::Block) -> String {
+        let id = format!("{:016x}", rand::random::<u64>());
+        self.jobs.insert(id.clone(), block);
+        self.order.push_back(id.clone());
+        while self.order.len() > WORK_LOG_CAP {
+            if let Some(old) = self.order.pop_front() {
+                self.jobs.remove(&old);
+            }
+        }
+        id
+    }
+
+    pub fn get(&self, id: &str) -> Option<&crate::blockchain::Block> {
+        self.jobs.get(id)
+    }
+
+    /// Forget every job not built on `tip_hash`.
+    pub fn retain_tip(&mut self, tip_hash: &str) {
+        self.jobs.retain(|_, job| job.prev_hash == tip_hash);
+        let jobs = &self.jobs;
+        self.order.retain(|id| jobs.contains_key(id));
+    }
+}
+
+/// Whether `block` is one this node already has: the same hash at its
+/// height, or recently accepted. Only compares hashes, never computes one.
+pub fn is_duplicate_block(
+    bc: &Blockchain,
+    seen: &SeenBlocks,
+    block: &crate::blockchain::Block,
+) -> bool {
+    let at_height = bc
+        .chain
+        .get(block.index as usize)
+        .is_some_and(|b| b.hash == block.hash);
+    at_height || seen.contains(&block.hash)
+}
+
+/// Blocks between stored balance snapshots. A historical balance query
+/// replays at most this many blocks on top of the nearest snapshot.
+pub const BALANCE_SNAPSHOT_INTERVAL: u64 = 1000;
+
+/// Every address's balance after every `BALANCE_SNAPSHOT_INTERVAL`th block,
+/// extended incrementally as the chain grows. Kept in memory only: the
+/// first query after a restart rebuilds it from the chain.
+pub struct BalanceIndex {
+    snapshots: BTreeMap<u64, HashMap<String, i64>>,
+    /// Balances after the last folded-in block.
+    running: HashMap<String, i64>,
+    /// Number of blocks folded in, and the hash of the last one.
+    folded: usize,
+    folded_tip: String,
+}
+
+impl BalanceIndex {
+    pub fn new() -> Self {
+        Self {
+            snapshots: BTreeMap::new(),
+            running: HashMap::new(),
+            folded: 0,
+            folded_tip: String::new(),
+        }
+    }
+
+    /// Fold in blocks added since the last call. Starts over if the blocks
+    /// already folded in are no longer the chain's.
+    fn catch_up(&mut self, bc: &Blockchain) {
+        let still_ours = self.folded == 0
+            || bc
+                .chain
+                .get(self.folded - 1)
+                .is_some_and(|b| b.hash == self.folded_tip);
+        if !still_ours {
+            *self = Self::new();
+        }
+        for block in &bc.chain[self.folded..] {
+            Blockchain::apply_block_balances(&mut self.running, block);
+            if block.index % BALANCE_SNAPSHOT_INTERVAL == 0 {
+                self.snapshots.insert(block.index, self.running.clone());
+            }
+        }
+        self.folded = bc.chain.len();
+        if let Some(tip) = bc.chain.last() {
+            self.folded_tip = tip.hash.clone();
+        }
+    }
+
+    /// Balance of `address` after the current tip.
+    pub fn current(&mut self, bc: &Blockchain, address: &str) -> i64 {
+        self.catch_up(bc);
+        let address = address.trim().to_lowercase();
+        self.running.get(&address).copied().unwrap_or(0)
+    }
+
+    /// Balance of `address` after the block at `height` (at most the tip).
+    pub fn balance_at(&mut self, bc: &Blockchain, address: &str, height: u64) -> i64 {
+        self.catch_up(bc);
+        let address = address.trim().to_lowercase();
+        let (mut balance, start) = match self.snapshots.range(..=height).next_back() {
+            Some((&h, balances)) => (balances.get(&address).copied().unwrap_or(0), h + 1),
+            None => (0, 0),
+        };
+        let mut changes = HashMap::new();
+        for block in bc.chain.iter().skip(start as usize) {
+            if block.index > height {
+                break;
+            }
+            Blockchain::apply_block_balances(&mut changes, block);
+            balance += changes.remove(&address).unwrap_or(0);
+            changes.clear();
+        }
+        balance
+    }
+}
+
+/// Block submission outcomes since the node started.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SubmitCounters {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub stale: u64,
+    /// Blocks the node already had, answered without validation.
+    pub duplicate: u64,
+    /// Blocks parked until their parent arrives.
+    pub orphaned: u64,
+}
+
+/// Call count and timing for one method.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MethodStats {
+    pub count: u64,
+    pub total_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Per-method request counters since the node started, reported by
+/// `getinfo` and `/metrics`.
+#[derive(Debug)]
+pub struct DaemonMetrics {
+    started: std::time::Instant,
+    methods: std::collections::BTreeMap<&'static str, MethodStats>,
+}
+
+impl DaemonMetrics {
+    pub fn new() -> Self {
+        Self {
+            started: std::time::Instant::now(),
+            methods: Default::default(),
+        }
+    }
+
+    pub fn record(&mut self, method: &'static str, elapsed: std::time::Duration) {
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        let stats = self.methods.entry(method).or_default();
+        stats.count += 1;
+        stats.total_ms += ms;
+        stats.max_ms = stats.max_ms.max(ms);
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    pub fn methods(&self) -> &std::collections::BTreeMap<&'static str, MethodStats> {
+        &self.methods
+    }
+}
+
+/// `Some("stale: ...")` when a block the node would reject was simply built
+/// on an old tip: either from a known template that has expired, or on a
+/// block of ours that is no longer the tip. Anything else is a real error
+/// and keeps its rejection reason.
+pub fn stale_status(
+    bc: &Blockchain,
+    template: Option<&IssuedTemplate>,
+    block: &crate::blockchain::Block,
+    now: i64,
+) -> Option<String> {
+    let tip = bc.chain.last()?.index;
+    let built_on = match template {
+        Some(t) if t.is_expired(tip, now) => t.index.saturating_sub(1),
+        Some(_) => return None,
+        None => bc
+            .chain
+            .iter()
+            .rposition(|b| b.hash == block.prev_hash)
+            .map(|i| i as u64)
+            .filter(|&h| h < tip)?,
+    };
+    Some(format!(
+        "stale: built on height {}, current height {}",
+        built_on, tip
+    ))
+}
+
+/// Resolve the status of `txid`: confirmed blocks win over the mempool, which
+/// wins over the rejection log (a resubmitted tx may have been accepted later).
+pub fn lookup_tx_status(
+    bc: &Blockchain,
+    mempool: &[Transaction],
+    rejections: &TxRejectionLog,
+    txid: &str,
+) -> TxStatus {
+    for block in bc.chain.iter().rev() {
+        if block.transactions.iter().any(|t| t.txid() == txid) {
+            return TxStatus::Confirmed {
+                height: block.index,
+            };
+        }
+    }
+    if mempool.iter().any(|t| t.txid() == txid) {
+        return TxStatus::Pending;
+    }
+    match rejections.get(txid) {
+        Some((reason, timestamp)) => TxStatus::Rejected {
+            reason: reason.clone(),
+            timestamp: *timestamp,
+        },
+        None => TxStatus::Unknown,
+    }
+}
+
+/// Height and hash of one of the most recent blocks, from `gettiphistory`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TipEntry {
+    pub index: u64,
+    pub hash: String,
+}
+
+/// The last `depth` blocks of the chain, oldest first.
+pub fn tip_history(bc: &Blockchain, depth: usize) -> Vec<TipEntry> {
+    let start = bc.chain.len().saturating_sub(depth);
+    bc.chain[start..]
+        .iter()
+        .map(|b| TipEntry {
+            index: b.index,
+            hash: b.hash.clone(),
+        })
+        .collect()
+}
+
+/// Longest agent string `hello` keeps.
+const AGENT_MAX_LEN: usize = 64;
+
+/// One open WebSocket connection, as daemon log lines name it and
+/// `getconnections` lists it.
+#[derive(Debug)]
+pub struct Connection {
+    pub id: u64,
+    /// Remote socket address.
+    pub addr: String,
+    pub connected_at: i64,
+    /// What the client called itself in `hello`.
+    agent: Mutex<Option<String>>,
+    /// Trace id of the command being handled, if it sent one.
+    trace: Mutex<Option<String>>,
+    requests: AtomicU64,
+    /// Set by a handler to hang up once its reply is sent.
+    closing: AtomicBool,
+    /// Set once a compatible `hello` or `handshake` arrives.
+    greeted: AtomicBool,
+}
+
+/// `getconnections` entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub addr: String,
+    pub agent: Option<String>,
+    pub connected_at: i64,
+    pub requests: u64,
+}
+
+impl Connection {
+    pub fn new(id: u64, addr: &str) -> Self {
+        Self {
+            id,
+            addr: addr.to_string(),
+            connected_at: chrono::Utc::now().timestamp(),
+            agent: Mutex::new(None),
+            trace: Mutex::new(None),
+            requests: AtomicU64::new(0),
+            closing: AtomicBool::new(false),
+            greeted: AtomicBool::new(false),
+        }
+    }
+
+    pub fn agent(&self) -> Option<String> {
+        self.agent.lock().clone()
+    }
+
+    /// Keep a printable, length-capped copy of what the client sent; it
+    /// ends up in log lines.
+    pub fn set_agent(&self, agent: &str) {
+        let agent: String = agent
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(AGENT_MAX_LEN)
+            .collect();
+        let agent = agent.trim();
+        *self.agent.lock() = (!agent.is_empty()).then(|| agent.to_string());
+    }
+
+    /// Name the command being handled in log lines until the next call;
+    /// capped and printable like the agent.
+    pub fn set_trace(&self, trace: Option<&str>) {
+        *self.trace.lock() = trace
+            .map(|t| {
+                t.chars()
+                    .filter(|c| !c.is_control() && !c.is_whitespace())
+                    .take(AGENT_MAX_LEN)
+                    .collect::<String>()
+            })
+            .filter(|t| !t.is_empty());
+    }
+
+    /// Close the connection after the reply to the current command.
+    pub fn close_after_reply(&self) {
+        self.closing.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::Relaxed)
+    }
+
+    /// The client proved it speaks a protocol and chain this node serves.
+    pub fn greet(&self) {
+        self.greeted.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_greeted(&self) -> bool {
+        self.greeted.load(Ordering::Relaxed)
+    }
+
+    pub fn count_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            id: self.id,
+            addr: self.addr.clone(),
+            agent: self.agent(),
+            connected_at: self.connected_at,
+            requests: self.requests.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl std::fmt::Display for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{} {}", self.id, self.addr)?;
+        if let Some(agent) = self.agent.lock().as_deref() {
+            write!(f, " ({})", agent)?;
+        }
+        match self.trace.lock().as_deref() {
+            Some(trace) => write!(f, " trace={}", trace),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The daemon's open connections.
+#[derive(Debug, Default)]
+pub struct Connections {
+    next_id: AtomicU64,
+    open: Mutex<BTreeMap<u64, Arc<Connection>>>,
+}
+
+impl Connections {
+    pub fn open(&self, addr: &str) -> Arc<Connection> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let conn = Arc::new(Connection::new(id, addr));
+        self.open.lock().insert(id, conn.clone());
+        conn
+    }
+
+    pub fn close(&self, id: u64) {
+        self.open.lock().remove(&id);
+    }
+
+    pub fn count(&self) -> usize {
+        self.open.lock().len()
+    }
+
+    /// Oldest first.
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        self.open.lock().values().map(|c| c.info()).collect()
+    }
+}
+
+/// Default for config `max_connections`.
+pub const DEFAULT_MAX_CONNECTIONS: usize = 128;
+/// Default for config `max_connections_per_ip`.
+pub const DEFAULT_MAX_CONNECTIONS_PER_IP: usize = 16;
+
+/// Inbound sockets counted from `accept`, before the WebSocket handshake,
+/// so a flood is refused before it costs a task or a handshake. A limit
+/// of 0 is no limit; loopback only counts against the total, since a
+/// local pool or explorer may open many.
+#[derive(Debug)]
+pub struct ConnectionSlots {
+    used: Mutex<SlotsInUse>,
+    max_total: AtomicUsize,
+    max_per_ip: AtomicUsize,
+    refused: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct SlotsInUse {
+    total: usize,
+    per_ip: HashMap<std::net::IpAddr, usize>,
+}
+
+/// One taken slot, given back on drop.
+#[derive(Debug)]
+pub struct ConnectionSlot {
+    slots: Arc<ConnectionSlots>,
+    ip: std::net::IpAddr,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut used = self.slots.used.lock();
+        used.total = used.total.saturating_sub(1);
+        if let Some(count) = used.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                used.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+impl Default for ConnectionSlots {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_CONNECTIONS_PER_IP)
+    }
+}
+
+impl ConnectionSlots {
+    pub fn new(max_total: usize, max_per_ip: usize) -> Self {
+        Self {
+            used: Mutex::new(SlotsInUse::default()),
+            max_total: AtomicUsize::new(max_total),
+            max_per_ip: AtomicUsize::new(max_per_ip),
+            refused: AtomicU64::new(0),
+        }
+    }
+
+    /// Change the limits, e.g. on a config reload. Connections over a
+    /// lowered limit stay open.
+    pub fn configure(&self, max_total: usize, max_per_ip: usize) {
+        self.max_total.store(max_total, Ordering::Relaxed);
+        self.max_per_ip.store(max_per_ip, Ordering::Relaxed);
+    }
+
+    /// Take a slot for a socket from `ip`, or `None` when at capacity.
+    pub fn acquire(self: &Arc<Self>, ip: std::net::IpAddr) -> Option<ConnectionSlot> {
+        let max_total = self.max_total.load(Ordering::Relaxed);
+        let max_per_ip = self.max_per_ip.load(Ordering::Relaxed);
+        let mut used = self.used.lock();
+        let from_ip = used.per_ip.get(&ip).copied().unwrap_or(0);
+        if (max_total > 0 && used.total >= max_total)
+            || (max_per_ip > 0 && !ip.is_loopback() && from_ip >= max_per_ip)
+        {
+            self.refused.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        used.total += 1;
+        *used.per_ip.entry(ip).or_insert(0) += 1;
+        Some(ConnectionSlot {
+            slots: self.clone(),
+            ip,
+        })
+    }
+
+    pub fn max_total(&self) -> usize {
+        self.max_total.load(Ordering::Relaxed)
+    }
+
+    pub fn max_per_ip(&self) -> usize {
+        self.max_per_ip.load(Ordering::Relaxed)
+    }
+
+    /// Sockets closed for being over a limit since startup.
+    pub fn refused(&self) -> u64 {
+        self.refused.load(Ordering::Relaxed)
+    }
+}
+
+/// Default for config `rate_limit_per_sec`.
+pub const DEFAULT_RATE_LIMIT_PER_SEC: u32 = 20;
+/// Default for config `rate_limit_burst`.
+pub const DEFAULT_RATE_LIMIT_BURST: u32 = 100;
+
+/// What a request costs from its connection's token bucket. `getchain`
+/// serializes the whole chain under the blockchain lock; reads that walk
+/// the chain cost more than ones answered from the tip.
+pub fn method_cost(method: &str) -> u32 {
+    match method {
+        "getchain" => 50,
+        "getsupply" | "getburned" | "getbalanceat" | "getmempool" | "getblocks" => 5,
+        "getheight" | "getlatest" | "getwork" | "submitwork" | "submitshare" | "hello"
+        | "handshake" => 1,
+        _ => 2,
+    }
+}
+
+/// Request rate limits shared by every connection: each one's bucket
+/// holds up to `burst` tokens and refills at `per_sec`. A rate of 0 turns
+/// limiting off.
+#[derive(Debug)]
+pub struct RateLimits {
+    per_sec: AtomicU32,
+    burst: AtomicU32,
+    limited: AtomicU64,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self::new(DEFAULT_RATE_LIMIT_PER_SEC, DEFAULT_RATE_LIMIT_BURST)
+    }
+}
+
+impl RateLimits {
+    pub fn new(per_sec: u32, burst: u32) -> Self {
+        Self {
+            per_sec: AtomicU32::new(per_sec),
+            burst: AtomicU32::new(burst),
+            limited: AtomicU64::new(0),
+        }
+    }
+
+    /// Change the limits, e.g. on a config reload; open connections'
+    /// buckets follow from their next request.
+    pub fn configure(&self, per_sec: u32, burst: u32) {
+        self.per_sec.store(per_sec, Ordering::Relaxed);
+        self.burst.store(burst, Ordering::Relaxed);
+    }
+
+    pub fn per_sec(&self) -> u32 {
+        self.per_sec.load(Ordering::Relaxed)
+    }
+
+    pub fn burst(&self) -> u32 {
+        self.burst.load(Ordering::Relaxed)
+    }
+
+    /// Requests refused for an empty bucket since startup.
+    pub fn limited(&self) -> u64 {
+        self.limited.load(Ordering::Relaxed)
+    }
+}
+
+/// One connection's tokens.
+#[derive(Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    refilled: std::time::Instant,
+}
+
+impl TokenBucket {
+    pub fn full(limits: &RateLimits, now: std::time::Instant) -> Self {
+        Self {
+            tokens: limits.burst() as f64,
+            refilled: now,
+        }
+    }
+
+    /// Take `cost` tokens, or say how many milliseconds until there are
+    /// enough. A cost over the burst is capped at it, so a small burst
+    /// still allows every method.
+    pub fn take(
+        &mut self,
+        cost: u32,
+        limits: &RateLimits,
+        now: std::time::Instant,
+    ) -> Result<(), u64> {
+        let per_sec = limits.per_sec() as f64;
+        let burst = limits.burst() as f64;
+        if per_sec == 0.0 {
+            return Ok(());
+        }
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_sec).min(burst);
+        self.refilled = now;
+        let cost = (cost as f64).min(burst);
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            return Ok(());
+        }
+        limits.limited.fetch_add(1, Ordering::Relaxed);
+        Err(((cost - self.tokens) / per_sec * 1000.0).ceil() as u64)
+    }
+}
+
+/// What the last sync round learned about one peer's chain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerChainStatus {
+    pub peer: String,
+    pub height: u64,
+    pub tip_hash: String,
+    /// The lower of the two tips, where the chains were compared.
+    pub compared_height: u64,
+    pub local_hash: String,
+    pub peer_hash: String,
+    /// Different blocks at `compared_height`.
+    pub forked: bool,
+    pub last_seen: i64,
+}
+
+/// Per-peer chain view kept by the peer-sync loop, for `getpeerinfo`.
+#[derive(Debug, Default)]
+pub struct PeerChains {
+    peers: BTreeMap<String, PeerChainStatus>,
+    forks_detected: u64,
+}
+
+impl PeerChains {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store `status`. Returns a warning, and counts a fork, when it shows
+    /// a fork the peer's previous status didn't.
+    pub fn record(&mut self, status: PeerChainStatus) -> Option<String> {
+        let was_forked = self
+            .peers
+            .get(&status.peer)
+            .is_some_and(|previous| previous.forked);
+        let warning = (status.forked && !was_forked).then(|| {
+            self.forks_detected += 1;
+            format!(
+                "Peer {} is on a different chain: block {} is {} here, {} there",
+                status.peer, status.compared_height, status.local_hash, status.peer_hash
+            )
+        });
+        self.peers.insert(status.peer.clone(), status);
+        warning
+    }
+
+    /// Forget peers that are no longer configured.
+    pub fn retain(&mut self, peers: &[String]) {
+        self.peers.retain(|peer, _| peers.contains(peer));
+    }
+
+    pub fn statuses(&self) -> Vec<PeerChainStatus> {
+        self.peers.values().cloned().collect()
+    }
+
+    /// Peers seen switching to a fork since the node started.
+    pub fn forks_detected(&self) -> u64 {
+        self.forks_detected
+    }
+}
+
+/// Compare `peer`'s chain with ours at the lower of the two tips: its tip
+/// against our block at that height, or its block at our height against
+/// our tip.
+pub async fn check_peer_chain(
+    client: &mut crate::ws_client::WsClient,
+    peer: &str,
+    blockchain: &Mutex<Blockchain>,
+) -> anyhow::Result<PeerChainStatus> {
+    let tip = client.block(None).await?;
+    let (local_height, local_tip) = {
+        let bc = blockchain.lock();
+        let last = bc
+            .chain
+            .last()
+            .ok_or_else(|| anyhow::anyhow!("local chain is empty"))?;
+        (last.index, last.hash.clone())
+    };
+    let (compared_height, local_hash, peer_hash) = if tip.index <= local_height {
+        let ours = blockchain
+            .lock()
+            .chain
+            .get(tip.index as usize)
+            .map(|b| b.hash.clone())
+            .unwrap_or_default();
+        (tip.index, ours, tip.hash.clone())
+    } else {
+        let theirs = client.block(Some(local_height)).await?;
+        (local_height, local_tip, theirs.hash)
+    };
+    Ok(PeerChainStatus {
+        peer: peer.to_string(),
+        height: tip.index,
+        tip_hash: tip.hash,
+        compared_height,
+        forked: local_hash != peer_hash,
+        local_hash,
+        peer_hash,
+        last_seen: chrono::Utc::now().timestamp(),
+    })
+}
+
+/// Fetch the blocks `peer` has past our tip, at most
+/// `PEER_SYNC_BATCH_BLOCKS` of them, and add the ones that validate.
+/// Subscribers get `syncprogress` events while it runs. `None` when the
+/// peer is not ahead of us on the same chain.
+pub async fn catch_up_from_peer(
+    ctx: &DaemonContext,
+    client: &mut crate::ws_client::WsClient,
+    status: &PeerChainStatus,
+) -> anyhow::Result<Option<crate::chain_sync::SyncReport>> {
+    if status.forked || status.height <= status.compared_height {
+        return Ok(None);
+    }
+    let from = status.compared_height + 1;
+    let to = status.height.min(from + PEER_SYNC_BATCH_BLOCKS - 1);
+    let parent = ctx.blockchain.lock().chain.get(from as usize - 1).cloned();
+    let mut last_event: Option<std::time::Instant> = None;
+    // Stops between blocks on Ctrl+C; the ones fetched are still saved
+    let cancel = crate::interrupt::token();
+    let (blocks, mut report) =
+        crate::chain_sync::fetch_blocks(client, from, to, parent.as_ref(), cancel, |progress| {
+            if progress.done() || last_event.is_none_or(|t| t.elapsed() >= SYNC_EVENT_INTERVAL) {
+                last_event = Some(std::time::Instant::now());
+                ctx.publish(NodeEvent::SyncProgress {
+                    peer: status.peer.clone(),
+                    progress: progress.clone(),
+                });
+            }
+        })
+        .await?;
+
+    let mut bc = ctx.blockchain.lock();
+    let mut added = 0;
+    for block in blocks {
+        let difficulty = bc.get_dynamic_difficulty();
+        if !bc.add_block_skip_pow(block, difficulty, ctx.dev_mode) {
+            break;
+        }
+        added += 1;
+        if let Some(tip) = bc.chain.last() {
+            ctx.seen_blocks.lock().insert(&tip.hash);
+            ctx.publish(NodeEvent::NewBlock { block: tip.clone() });
+        }
+        prune_mempool_after_block(&bc, &mut ctx.mempool.lock(), &mut ctx.rejections.lock());
+    }
+    let connected = connect_orphans(ctx, &mut bc);
+    if added > 0 || connected > 0 {
+        ctx.persist_chain(&bc);
+    }
+    // Report what the chain holds now, which is less than was fetched if
+    // a block failed validation
+    if let Some(tip) = bc.chain.last() {
+        report.height = tip.index;
+        report.tip_hash = tip.hash.clone();
+    }
+    if added < report.verified {
+        eprintln!(
+            "Peer {}: only {} of {} fetched block(s) were valid here",
+            status.peer, added, report.verified
+        );
+    }
+    Ok(Some(report))
+}
+
+/// Backups of blocks dropped by switching branches kept next to the chain
+/// file.
+const REORG_BACKUPS_KEEP: usize = 5;
+
+/// Default for config `max_reorg_depth`.
+pub const DEFAULT_MAX_REORG_DEPTH: u64 = 50;
+
+/// A switch to another branch, or one refused for dropping too many of
+/// our blocks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReorgRecord {
+    pub timestamp: i64,
+    /// The peer the branch came from, or the address that submitted its
+    /// tip.
+    pub peer: String,
+    /// Blocks of ours the switch drops.
+    pub depth: u64,
+    /// Height of the last block both branches share.
+    pub fork_height: u64,
+    pub old_tip: String,
+    pub new_height: u64,
+    pub new_tip: String,
+    /// Hashes of the blocks dropped, oldest first.
+    pub orphaned: Vec<String>,
+    /// Deeper than `max_reorg_depth`, so not performed.
+    pub refused: bool,
+}
+
+/// The reorg depth limit and what the node did about reorgs since start.
+/// A reorg that deep usually means an attack or a badly split network, so
+/// it waits for the operator.
+#[derive(Debug)]
+pub struct Reorgs {
+    max_depth: AtomicU64,
+    performed: AtomicU64,
+    refused: AtomicU64,
+    last: Mutex<Option<ReorgRecord>>,
+}
+
+impl Default for Reorgs {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_REORG_DEPTH)
+    }
+}
+
+impl Reorgs {
+    pub fn new(max_depth: u64) -> Self {
+        Self {
+            max_depth: AtomicU64::new(max_depth),
+            performed: AtomicU64::new(0),
+            refused: AtomicU64::new(0),
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Change the limit, e.g. on a config reload; 0 allows any depth.
+    pub fn configure(&self, max_depth: u64) {
+        self.max_depth.store(max_depth, Ordering::Relaxed);
+    }
+
+    pub fn max_depth(&self) -> u64 {
+        self.max_depth.load(Ordering::Relaxed)
+    }
+
+    pub fn allows(&self, depth: u64) -> bool {
+        let max = self.max_depth();
+        max == 0 || depth <= max
+    }
+
+    /// Keep `record` as the last reorg. False when it is a refusal of the
+    /// same branch as the last one, which every sync round would repeat.
+    pub fn record(&self, record: ReorgRecord) -> bool {
+        let mut last = self.last.lock();
+        if record.refused
+            && last
+                .as_ref()
+                .is_some_and(|l| l.refused && l.new_tip == record.new_tip)
+        {
+            return false;
+        }
+        let counter = if record.refused {
+            &self.refused
+        } else {
+            &self.performed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        *last = Some(record);
+        true
+    }
+
+    pub fn last(&self) -> Option<ReorgRecord> {
+        self.last.lock().clone()
+    }
+
+    /// `getinfo`'s `reorgs` counts.
+    pub fn status(&self) -> serde_json::Value {
+        serde_json::json!({
+            "performed": self.performed.load(Ordering::Relaxed),
+            "refused": self.refused.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Where a branch the node may switch to came from.
+#[derive(Debug, Clone, Copy)]
+pub enum BranchSource<'a> {
+    /// A peer's chain, fetched while syncing.
+    Peer(&'a str),
+    /// A block `submitblock` got from this address.
+    Submitted(&'a str),
+}
+
+impl BranchSource<'_> {
+    fn address(&self) -> &str {
+        match self {
+            Self::Peer(addr) | Self::Submitted(addr) => addr,
+        }
+    }
+
+    /// What to pass `chain adopt --from` to switch anyway.
+    fn adopt_from(&self) -> &str {
+        match self {
+            Self::Peer(addr) => addr,
+            Self::Submitted(_) => "<a node on that branch>",
+        }
+    }
+}
+
+impl std::fmt::Display for BranchSource<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Peer(addr) => write!(f, "peer {}", addr),
+            Self::Submitted(addr) => write!(f, "a block submitted by {}", addr),
+        }
+    }
+}
+
+/// Switch our chain, locked in `bc`, to `adopted`, which shares its first
+/// `fork` blocks and holds more work. Refused, giving `None`, when that
+/// drops more than `max_reorg_depth` of our blocks. Otherwise the blocks
+/// dropped are backed up to `backups/` next to the chain file and kept as
+/// side blocks, and the transactions only they confirmed are returned to
+/// go back to the mempool once `bc` is unlocked.
+fn switch_branch(
+    ctx: &DaemonContext,
+    bc: &mut Blockchain,
+    adopted: Blockchain,
+    fork: usize,
+    source: BranchSource,
+) -> anyhow::Result<Option<Vec<Transaction>>> {
+    let tip_hash = |bc: &Blockchain| bc.chain.last().map(|b| b.hash.clone()).unwrap_or_default();
+    let depth = (bc.chain.len() - fork) as u64;
+    let record = ReorgRecord {
+        timestamp: chrono::Utc::now().timestamp(),
+        peer: source.address().to_string(),
+        depth,
+        fork_height: fork as u64 - 1,
+        old_tip: tip_hash(bc),
+        new_height: adopted.chain.len() as u64 - 1,
+        new_tip: tip_hash(&adopted),
+        orphaned: bc.chain[fork..].iter().map(|b| b.hash.clone()).collect(),
+        refused: !ctx.reorgs.allows(depth),
+    };
+    if record.refused {
+        if ctx.reorgs.record(record.clone()) {
+            (ctx.log)(&format!(
+                "[daemon] CRITICAL: refusing to drop {} block(s) for the branch of {} (max_reorg_depth {}): our tip {} at height {}, theirs {} at height {}; check why, then `owonero chain adopt --from {} --force` to switch",
+                depth,
+                source,
+                ctx.reorgs.max_depth(),
+                record.old_tip,
+                bc.chain.len() - 1,
+                record.new_tip,
+                record.new_height,
+                source.adopt_from()
+            ));
+            ctx.publish(NodeEvent::Reorg(record));
+        }
+        return Ok(None);
+    }
+
+    let reason = format!("switched to the branch of {}", source);
+    if let Some(backup) = crate::chain_backup::backup_for(bc, &adopted, &reason) {
+        crate::chain_backup::save_backup(
+            &ctx.chain_path.with_file_name("backups"),
+            &backup,
+            REORG_BACKUPS_KEEP,
+        )?;
+    }
+    let confirmed: HashSet<String> = adopted.chain[fork..]
+        .iter()
+        .flat_map(|b| b.transactions.iter().map(|t| t.txid()))
+        .collect();
+    let orphans = bc.chain[fork..]
+        .iter()
+        .flat_map(|b| b.transactions.iter())
+        .filter(|t| t.from != "coinbase" && !confirmed.contains(&t.txid()))
+        .cloned()
+        .collect();
+    let dropped = std::mem::replace(bc, adopted).chain.split_off(fork);
+    {
+        let mut side = ctx.side_blocks.lock();
+        for block in &bc.chain[fork..] {
+            side.remove(&block.hash);
+        }
+        for block in dropped {
+            side.insert(block);
+        }
+    }
+    ctx.persist_chain(bc);
+    (ctx.log)(&format!(
+        "[daemon] WARNING: reorg dropped {} block(s) above height {} for the branch of {}: {}",
+        depth,
+        record.fork_height,
+        source,
+        record.orphaned.join(", ")
+    ));
+    ctx.reorgs.record(record.clone());
+    ctx.publish(NodeEvent::Reorg(record));
+    if connect_orphans(ctx, bc) > 0 {
+        ctx.persist_chain(bc);
+    }
+    let mut mempool = ctx.mempool.lock();
+    mempool.retain(|t| !confirmed.contains(&t.txid()));
+    prune_mempool_after_block(bc, &mut mempool, &mut ctx.rejections.lock());
+    for block in &bc.chain[fork..] {
+        ctx.seen_blocks.lock().insert(&block.hash);
+        ctx.publish(NodeEvent::NewBlock {
+            block: block.clone(),
+        });
+    }
+    Ok(Some(orphans))
+}
+
+/// When `status` shows the peer on another branch that is taller than
+/// ours, fetch its whole chain in `getblocks` slices and switch to it if
+/// it holds more work than ours past where the branches split, every
+/// block from there validates here and it drops no more than
+/// `max_reorg_depth` of our blocks (see `switch_branch`). Returns the
+/// height the branches split at, or `None` when our chain stays.
+pub async fn adopt_peer_fork(
+    ctx: &DaemonContext,
+    client: &mut crate::ws_client::WsClient,
+    status: &PeerChainStatus,
+) -> anyhow::Result<Option<u64>> {
+    let local_height = ctx.blockchain.lock().chain.last().map_or(0, |b| b.index);
+    if !status.forked || status.height <= local_height {
+        return Ok(None);
+    }
+    let mut last_event: Option<std::time::Instant> = None;
+    let incoming = client
+        .chain(|progress| {
+            if progress.done() || last_event.is_none_or(|t| t.elapsed() >= SYNC_EVENT_INTERVAL) {
+                last_event = Some(std::time::Instant::now());
+                ctx.publish(NodeEvent::SyncProgress {
+                    peer: status.peer.clone(),
+                    progress: progress.clone(),
+                });
+            }
+        })
+        .await?;
+
+    let local = ctx.blockchain.lock().clone();
+    let fork = local
+        .chain
+        .iter()
+        .zip(&incoming.chain)
+        .take_while(|(a, b)| a.hash == b.hash)
+        .count();
+    if fork == 0 {
+        anyhow::bail!("its chain starts from a different genesis block");
+    }
+    let branch = &incoming.chain[fork..];
+    if !local.outweighed_by(fork, branch) {
+        return Ok(None);
+    }
+    let adopted = local
+        .with_branch(fork, branch, ctx.dev_mode)
+        .map_err(|reason| anyhow::anyhow!("its {}", reason))?;
+
+    let orphans = {
+        let mut bc = ctx.blockchain.lock();
+        if bc.chain.last().map(|b| &b.hash) != local.chain.last().map(|b| &b.hash) {
+            // Our chain moved while fetching; look again next round
+            return Ok(None);
+        }
+        match switch_branch(
+            ctx,
+            &mut bc,
+            adopted,
+            fork,
+            BranchSource::Peer(&status.peer),
+        )? {
+            Some(orphans) => orphans,
+            None => return Ok(None),
+        }
+    };
+    for tx in orphans {
+        let _ = admit_transaction(tx, &ctx.blockchain, &ctx.mempool, &ctx.rejections);
+    }
+    Ok(Some(fork as u64))
+}
+
+/// What came of a submitted block that builds on a side branch.
+#[derive(Debug)]
+pub enum SideBlock {
+    /// Its branch held more work, so the node switched to it. The
+    /// transactions only the dropped blocks confirmed, to go back to the
+    /// mempool.
+    Switched(Vec<Transaction>),
+    /// Kept in case its branch outgrows ours.
+    Kept,
+    /// Its branch holds more work but is deeper than `max_reorg_depth`.
+    Refused,
+    /// Its branch doesn't validate from where it splits off.
+    Invalid(String),
+}
+
+/// Take `block` as a side block when it builds on our chain below the tip
+/// or on another side block and its hash checks out, then switch to its
+/// branch if that now holds more work than ours. `None` when it is no
+/// side block, to be validated as the next block instead.
+pub fn accept_side_block(
+    ctx: &DaemonContext,
+    bc: &mut Blockchain,
+    block: &crate::blockchain::Block,
+    from: &str,
+) -> anyhow::Result<Option<SideBlock>> {
+    let below_tip = block.index > 0
+        && (block.index as usize) < bc.chain.len()
+        && bc.chain[block.index as usize - 1].hash == block.prev_hash;
+    let mut side = ctx.side_blocks.lock();
+    if !(below_tip || side.extended_by(block))
+        || Blockchain::calculate_hash(block) != block.hash
+        || !crate::blockchain::hash_meets_difficulty(&block.hash, block.difficulty)
+    {
+        return Ok(None);
+    }
+    // Like a parked orphan, a side block must carry the work its branch
+    // asks for, so the pool can't be filled with cheap blocks
+    if side
+        .difficulty_for(bc, block)
+        .is_none_or(|expected| block.difficulty != expected)
+    {
+        return Ok(None);
+    }
+    side.insert(block.clone());
+    let Some((fork, branch)) = side.branch(bc, block) else {
+        return Ok(Some(SideBlock::Kept));
+    };
+    if !bc.outweighed_by(fork, &branch) {
+        return Ok(Some(SideBlock::Kept));
+    }
+    let adopted = match bc.with_branch(fork, &branch, false) {
+        Ok(adopted) => adopted,
+        Err(reason) => {
+            side.remove(&block.hash);
+            return Ok(Some(SideBlock::Invalid(reason)));
+        }
+    };
+    drop(side);
+    Ok(Some(
+        match switch_branch(ctx, bc, adopted, fork, BranchSource::Submitted(from))? {
+            Some(orphans) => SideBlock::Switched(orphans),
+            None => SideBlock::Refused,
+        },
+    ))
+}
+
+/// A block whose coinbase paid the queried address.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MinedBlock {
+    pub height: u64,
+    pub timestamp: i64,
+    pub reward: i64,
+    pub hash: String,
+}
+
+/// One page of `getminedblocks`, newest first. `total` counts every block
+/// the address mined; pass `next_before` back as `before` for the next page.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MinedBlocksPage {
+    pub total: u64,
+    pub blocks: Vec<MinedBlock>,
+    pub next_before: Option<u64>,
+}
+
+/// Blocks whose coinbase pays `address`, below height `before` if given.
+pub fn mined_blocks(
+    bc: &Blockchain,
+    address: &str,
+    limit: usize,
+    before: Option<u64>,
+) -> MinedBlocksPage {
+    let address = address.trim().to_lowercase();
+    let mut page = MinedBlocksPage {
+        total: 0,
+        blocks: Vec::new(),
+        next_before: None,
+    };
+    for block in bc.chain.iter().rev() {
+        let Some(coinbase) = block.transactions.iter().find(|t| t.from == "coinbase") else {
+            continue;
+        };
+        if coinbase.to.trim().to_lowercase() != address {
+            continue;
+        }
+        page.total += 1;
+        if before.is_some_and(|b| block.index >= b) {
+            continue;
+        }
+        if page.blocks.len() < limit {
+            page.blocks.push(MinedBlock {
+                height: block.index,
+                timestamp: block.timestamp.timestamp(),
+                reward: coinbase.amount,
+                hash: block.hash.clone(),
+            });
+        } else if page.next_before.is_none() {
+            page.next_before = page.blocks.last().map(|b| b.height);
+        }
+    }
+    page
+}
+
+/// Run the standard `submittx` policy checks and, on success, add the
+/// transaction to the mempool. Rejections are recorded in the rejection log
+/// and returned as a short reason (without the "rejected: " prefix).
+pub(crate) fn admit_transaction(
+    tx: Transaction,
+    blockchain: &Arc<Mutex<Blockchain>>,
+    mempool: &Arc<Mutex<Vec<Transaction>>>,
+    rejections: &Arc<Mutex<TxRejectionLog>>,
+) -> Result<String, String> {
+    let txid = tx.txid();
+    let result = check_transaction(&tx, blockchain, mempool);
+    match result {
+        Ok(()) => {
+            mempool.lock().push(tx);
+            Ok(txid)
+        }
+        Err(reason) => {
+            rejections.lock().record(&txid, &reason);
+            Err(reason)
+        }
+    }
+}
+
+fn check_transaction(
+    tx: &Transaction,
+    blockchain: &Arc<Mutex<Blockchain>>,
+    mempool: &Arc<Mutex<Vec<Transaction>>>,
+) -> Result<(), String> {
+    if crate::blockchain::strict_consensus()
+        && let Some(reason) = crate::blockchain::legacy_format(tx)
+    {
+        return Err(format!("legacy format: {}", reason));
+    }
+    if !crate::blockchain::verify_transaction_signature(tx, &tx.pub_key) {
+        return Err("invalid signature".to_string());
+    }
+    if tx.amount <= 0 {
+        return Err("invalid amount".to_string());
+    }
+    // Relayed transactions come back around; one copy is enough
+    if mempool.lock().iter().any(|t| t.signature == tx.signature) {
+        return Err("already in mempool".to_string());
+    }
+
+    let (balances, next_height) = {
+        let bc = blockchain.lock();
+        (bc.balances(), bc.chain.len() as u64)
+    };
+    if tx.expired_at(next_height) {
+        return Err(format!(
+            "expired at height {}",
+            tx.expires_at_height.unwrap_or(0)
+        ));
+    }
+    let mut candidate = mempool.lock().clone();
+    candidate.push(tx.clone());
+    let (spendable, depths) = mempool_balances_and_depths(&balances, &candidate);
+
+    let from_key = tx.from.trim().to_lowercase();
+    if from_key != "coinbase" && spendable.get(&from_key).cloned().unwrap_or(0) < 0 {
+        return Err("insufficient funds".to_string());
+    }
+    if depths.last().cloned().unwrap_or(0) > max_mempool_depth() {
+        return Err("too many unconfirmed ancestors".to_string());
+    }
+    Ok(())
+}
+
+/// Apply `mempool` in order on top of the confirmed `balances`, crediting
+/// recipients so later entries may spend unconfirmed funds. Returns the
+/// resulting balances and each entry's unconfirmed-ancestor depth: 0 when
+/// the sender's confirmed funds cover it, otherwise one more than the
+/// deepest pending payment to the sender. The mempool only ever grows by
+/// appending, so parents always precede their children; miners keep that
+/// order in their templates, which makes every block apply cleanly.
+pub fn mempool_balances_and_depths(
+    balances: &HashMap<String, i64>,
+    mempool: &[Transaction],
+) -> (HashMap<String, i64>, Vec<usize>) {
+    let mut spendable = balances.clone();
+    let mut confirmed_left = balances.clone();
+    let mut credit_depth: HashMap<String, usize> = HashMap::new();
+    let mut depths = Vec::with_capacity(mempool.len());
+    for t in mempool {
+        let from = t.from.trim().to_lowercase();
+        let to = t.to.trim().to_lowercase();
+        let confirmed = confirmed_left.entry(from.clone()).or_insert(0);
+        let depth = if from == "coinbase" || *confirmed >= t.amount {
+            *confirmed -= t.amount;
+            0
+        } else {
+            *confirmed = 0;
+            credit_depth.get(&from).map_or(0, |d| d + 1)
+        };
+        *spendable.entry(from).or_insert(0) -= t.amount;
+        *spendable.entry(to.clone()).or_insert(0) += t.amount;
+        let d = credit_depth.entry(to).or_insert(0);
+        *d = (*d).max(depth);
+        depths.push(depth);
+    }
+    (spendable, depths)
+}
+
+/// Drop mempool entries confirmed by the newly accepted tip and evict any
+/// that can no longer be paid for, recording the eviction reason.
+pub(crate) fn prune_mempool_after_block(
+    bc: &Blockchain,
+    mempool: &mut Vec<Transaction>,
+    rejections: &mut TxRejectionLog,
+) {
+    let Some(tip) = bc.chain.last() else {
+        return;
+    };
+    let confirmed: Vec<String> = tip.transactions.iter().map(|t| t.txid()).collect();
+    mempool.retain(|t| !confirmed.contains(&t.txid()));
+    mempool.retain(|t| {
+        if t.expired_at(tip.index + 1) {
+            rejections.record(
+                &t.txid(),
+                &format!(
+                    "evicted: expired at height {}",
+                    t.expires_at_height.unwrap_or(0)
+                ),
+            );
+            return false;
+        }
+        true
+    });
+
+    let mut balances = bc.balances();
+    mempool.retain(|t| {
+        let from = t.from.trim().to_lowercase();
+        let bal = balances.entry(from).or_insert(0);
+        if *bal < t.amount {
+            rejections.record(
+                &t.txid(),
+                &format!("evicted: insufficient funds after block {}", tip.index),
+            );
+            false
+        } else {
+            *bal -= t.amount;
+            *balances.entry(t.to.trim().to_lowercase()).or_insert(0) += t.amount;
+            true
+        }
+    });
+}
+
+/// Append a block paying `amount` to `to` without proof of work, picking up
+/// whatever mempool transactions still apply. Only reachable in dev mode.
+pub(crate) fn dev_append_block(
+    bc: &mut Blockchain,
+    mempool: &mut Vec<Transaction>,
+    rejections: &mut TxRejectionLog,
+    to: &str,
+    amount: i64,
+) -> bool {
+    let coinbase = Transaction {
+        from: "coinbase".to_string(),
+        pub_key: String::new(),
+        to: to.to_string(),
+        amount,
+        signature: String::new(),
+        expires_at_height: None,
+    };
+    let mut with_mempool = vec![coinbase.clone()];
+    with_mempool.extend(mempool.iter().cloned());
+
+    let difficulty = bc.get_dynamic_difficulty();
+    for transactions in [with_mempool, vec![coinbase]] {
+        let Some(last) = bc.chain.last() else {
+            return false;
+        };
+        let mut block = crate::blockchain::Block {
+            index: last.index + 1,
+            timestamp: chrono::Utc::now(),
+            transactions,
+            prev_hash: last.hash.clone(),
+            hash: String::new(),
+            nonce: 0,
+            difficulty,
+        };
+        block.hash = Blockchain::calculate_hash(&block);
+        if bc.add_block_skip_pow(block, difficulty, true) {
+            prune_mempool_after_block(bc, mempool, rejections);
+            return true;
+        }
+    }
+    false
+}
+
+/// Exchange `NodeIdentity`s with a connected peer. Returns why it's on
+/// another chain, if it is. Peers that predate `handshake` are judged by
+/// their greeting, where a missing network means the main one.
+pub async fn handshake_peer(
+    ctx: &DaemonContext,
+    client: &mut crate::ws_client::WsClient,
+) -> anyhow::Result<Option<String>> {
+    let local = NodeIdentity::of(ctx);
+    let (network, genesis_hash) = if client.supports("handshake").await? {
+        let resp = client
+            .command("handshake", serde_json::to_value(&local)?)
+            .await?;
+        if resp["type"] == "error" {
+            anyhow::bail!("handshake refused: {}", resp["message"]);
+        }
+        let remote: NodeIdentity = serde_json::from_value(resp)?;
+        (remote.network, Some(remote.genesis_hash))
+    } else {
+        let greeting = client.greeting.clone().unwrap_or_default();
+        (
+            greeting
+                .network
+                .unwrap_or_else(|| network_id(false, false).to_string()),
+            greeting.genesis_hash,
+        )
+    };
+    Ok(local.incompatibility(&network, genesis_hash.as_deref()))
+}
+
+/// Connect to `peer` for a sync round. `None` when its greeting shows it's
+/// on another chain, which marks it incompatible.
+async fn connect_peer(
+    ctx: &DaemonContext,
+    peer: &str,
+) -> anyhow::Result<Option<crate::ws_client::WsClient>> {
+    match crate::ws_client::WsClient::connect(peer).await {
+        Ok(client) => Ok(Some(client)),
+        Err(e) => match e.downcast_ref::<crate::ws_client::IncompatibleNode>() {
+            Some(other) => {
+                let reason = format!("genesis {}", other.genesis_hash);
+                if ctx.peers.mark_incompatible(peer, &reason) {
+                    eprintln!("Not syncing with peer {}: {}", peer, e);
+                }
+                Ok(None)
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// One sync round with a connected peer: the handshake, then peers when
+/// it's time to `exchange` them, then catching up with or switching to its
+/// chain. Nothing past the handshake is asked of a peer on another chain.
+async fn sync_with_peer(
+    ctx: &DaemonContext,
+    peer: &str,
+    client: &mut crate::ws_client::WsClient,
+    exchange: bool,
+) {
+    match handshake_peer(ctx, client).await {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            if ctx.peers.mark_incompatible(peer, &reason) {
+                eprintln!("Not syncing with peer {}: it is on {}", peer, reason);
+            }
+            return;
+        }
+        Err(e) => {
+            eprintln!("Handshake with peer {} failed: {}", peer, e);
+            return;
+        }
+    }
+    ctx.peers.mark_seen(peer);
+    if exchange {
+        match exchange_peers(&ctx.peers, client).await {
+            Ok(0) => {}
+            Ok(n) => eprintln!("Learned {} peer(s) from {}", n, peer),
+            Err(e) => eprintln!("Could not get peers from {}: {}", peer, e),
+        }
+    }
+    let status = match check_peer_chain(client, peer, &ctx.blockchain).await {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("Could not compare chains with peer {}: {}", peer, e);
+            return;
+        }
+    };
+    if let Some(warning) = ctx.peer_chains.lock().record(status.clone()) {
+        eprintln!("WARNING: {}", warning);
+    }
+    ctx.peers.note_height(peer, status.height);
+    // Blocks fetched now couldn't be saved
+    if ctx.disk.is_read_only() {
+        return;
+    }
+    match catch_up_from_peer(ctx, client, &status).await {
+        Ok(Some(report)) => eprintln!("Peer {}: {}", peer, report),
+        Ok(None) => {}
+        Err(e) => eprintln!("Could not sync from peer {}: {}", peer, e),
+    }
+    match adopt_peer_fork(ctx, client, &status).await {
+        Ok(Some(fork)) => eprintln!(
+            "Peer {}: switched to its longer branch from block {}",
+            peer, fork
+        ),
+        Ok(None) => {}
+        Err(e) => eprintln!("Not switching to peer {}'s branch: {}", peer, e),
+    }
+}
+
+pub async fn run_daemon(
+    port: u16,
+    blockchain: Arc<Mutex<Blockchain>>,
+    pm: Arc<PeerManager>,
+    standalone: bool,
+    dev_mode: bool,
+    mempool_journal: Option<std::path::PathBuf>,
+    config: Option<crate::config::Config>,
+) -> anyhow::Result<()> {
+    let listener = crate::net::listen(port).await?;
+    println!("Daemon listening on :{} (WebSocket)", port);
+
+    // Standalone nodes never tell clients about peers
+    let peers = if standalone {
+        Arc::new(PeerManager::new())
+    } else {
+        pm.clone()
+    };
+    let mut ctx = DaemonContext::new(blockchain.clone(), peers, dev_mode);
+    ctx.standalone = standalone;
+    ctx.journal = Mutex::new(AdmissionJournal::new(mempool_journal));
+    let blocklist = crate::policy::configured_blocklist();
+    if !blocklist.is_empty() {
+        println!(
+            "Policy: refusing transactions for {} blocked address(es)",
+            blocklist.len()
+        );
+    }
+    ctx.blocklist = Mutex::new(blocklist);
+    // Without the config it was started from, there's nothing to reload
+    if let Some(config) = config {
+        ctx.bans = Mutex::new(BanList::new(config.ban_threshold, config.ban_duration_secs));
+        ctx.slots = Arc::new(ConnectionSlots::new(
+            config.max_connections,
+            config.max_connections_per_ip,
+        ));
+        ctx.rate_limits = RateLimits::new(config.rate_limit_per_sec, config.rate_limit_burst);
+        ctx.disk = crate::disk::DiskGuard::new(config.min_free_disk_mb);
+        ctx.reorgs = Reorgs::new(config.max_reorg_depth);
+        ctx.read_only = config.read_only;
+        ctx.config = Mutex::new(config);
+        ctx.config_path = Some(crate::config::get_config_path());
+    }
+    match crate::wallet_stats::WalletStats::load(
+        &crate::config::get_shares_path(),
+        crate::wallet_stats::MAX_TRACKED_WALLETS,
+    ) {
+        Ok(stats) => ctx.wallet_stats = Mutex::new(stats),
+        Err(e) => eprintln!("Warning: starting without saved pool shares: {:#}", e),
+    }
+    let ctx = Arc::new(ctx);
+    if standalone {
+        println!(
+            "STANDALONE: network {}, no peers, no sync",
+            network_id(dev_mode, true)
+        );
+    }
+    if ctx.read_only {
+        println!("READ-ONLY: serving chain data, refusing blocks, transactions, shares and stats");
+    }
+
+    // Sweep stale hashrates; save changed share counts now and then
+    {
+        let ctx = ctx.clone();
+        let shares_path = crate::config::get_shares_path();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs(HASHRATE_SWEEP_SECS));
+            let mut last_save = tokio::time::Instant::now();
+            loop {
+                interval.tick().await;
+                let now = chrono::Utc::now().timestamp() as u64;
+                let mut stats = ctx.wallet_stats.lock();
+                stats.sweep(now);
+                if stats.is_dirty() && last_save.elapsed().as_secs() >= SHARES_SAVE_SECS {
+                    if let Err(e) = stats.save(&shares_path) {
+                        eprintln!("Warning: failed to save pool shares: {:#}", e);
+                    }
+                    last_save = tokio::time::Instant::now();
+                }
+            }
+        });
+    }
+
+    // Go read-only while the disk is nearly full, and back when it isn't
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs(crate::disk::DISK_CHECK_SECS));
+            loop {
+                interval.tick().await;
+                ctx.check_disk();
+            }
+        });
+    }
+
+    // Reread config.json on SIGHUP
+    #[cfg(unix)]
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            use tokio::signal::unix::{SignalKind, signal};
+            let Ok(mut hangups) = signal(SignalKind::hangup()) else {
+                return;
+            };
+            while hangups.recv().await.is_some() {
+                match crate::reload::reload_from_file(&ctx) {
+                    Ok(report) => (ctx.log)(&format!("[daemon] config reloaded: {}", report)),
+                    Err(e) => (ctx.log)(&format!(
+                        "[daemon] config reload failed, nothing applied: {:#}",
+                        e
+                    )),
+                }
+            }
+        });
+    }
+
+    // Ping peers; drop learned ones that stop answering
+    if !standalone {
+        let ctx = ctx.clone();
+        let pm = pm.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs(PEER_HEALTH_INTERVAL_SECS));
+            loop {
+                interval.tick().await;
+                for peer in check_peer_health(&pm).await {
+                    (ctx.log)(&format!(
+                        "[daemon] dropped peer {}: no answer to {} health checks in a row",
+                        peer,
+                        pm.max_failures.load(Ordering::Relaxed)
+                    ));
+                }
+            }
+        });
+    }
+
+    // Pass admitted transactions on to peers
+    if !standalone {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RELAY_INTERVAL);
+            loop {
+                interval.tick().await;
+                relay_transactions(&ctx).await;
+            }
+        });
+    }
+
+    // Push new blocks to peers that are behind, so a miner polling one of
+    // them sees the tip without waiting for that node's next sync round
+    if !standalone {
+        let ctx = ctx.clone();
+        let mut events = ctx.events.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(NodeEvent::NewBlock { .. } | NodeEvent::Reorg(_))
+                    | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Ok(_) => continue,
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                }
+                // A sync batch goes out as one round
+                tokio::time::sleep(RELAY_INTERVAL).await;
+                while events.try_recv().is_ok() {}
+                relay_blocks(&ctx).await;
+            }
+        });
+    }
+
+    // Background sync from peers
+    let sync_interval_secs = std::env::var("OWONERO_SYNC_INTERVAL")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(PEER_SYNC_INTERVAL_SECS);
+    let _max_sync_attempts = 3;
+    if !standalone {
+        let pm_sync = pm.clone();
+        let ctx_sync = ctx.clone();
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs(sync_interval_secs));
+            // Per peer: its backoff and when it may be tried again
+            let mut backoffs: HashMap<String, (crate::retry::Backoff, tokio::time::Instant)> =
+                HashMap::new();
+            let mut round: u64 = 0;
+            loop {
+                interval.tick().await;
+                let exchange = round.is_multiple_of(PEER_EXCHANGE_ROUNDS);
+                round += 1;
+                let peers = pm_sync.get_peers();
+                backoffs.retain(|peer, _| peers.contains(peer));
+                if peers.is_empty() {
+                    continue;
+                }
+                for peer in &peers {
+                    if pm_sync.is_demoted(peer) {
+                        continue;
+                    }
+                    if backoffs
+                        .get(peer)
+                        .is_some_and(|(_, retry_at)| tokio::time::Instant::now() < *retry_at)
+                    {
+                        continue;
+                    }
+                    let (backoff, retry_at) = backoffs.entry(peer.clone()).or_insert_with(|| {
+                        (
+                            crate::retry::Backoff::for_node(),
+                            tokio::time::Instant::now(),
+                        )
+                    });
+                    let mut client = match connect_peer(&ctx_sync, peer).await {
+                        Ok(Some(client)) => {
+                            if let Some(line) = backoff.succeed_logged(&format!("Peer {}", peer)) {
+                                eprintln!("{}", line);
+                            }
+                            client
+                        }
+                        Ok(None) => continue,
+                        Err(e) => {
+                            let (delay, line) = backoff.fail_logged(&format!("Peer {}", peer), e);
+                            if let Some(line) = line {
+                                eprintln!("{}", line);
+                            }
+                            *retry_at = tokio::time::Instant::now() + delay;
+                            continue;
+                        }
+                    };
+                    sync_with_peer(&ctx_sync, peer, &mut client, exchange).await;
+                }
+                ctx_sync.peer_chains.lock().retain(&pm_sync.get_peers());
+            }
+        });
+    }
+
+    loop {
+        let accept_res = listener.accept().await;
+        let (socket, peer_addr) = match accept_res {
+            Ok((socket, addr)) => (socket, crate::net::canonical(addr)),
+            Err(e) => {
+                eprintln!("Listener accept error: {}", e);
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+
+        // Dropping the socket refuses the connection
+        if ctx
+            .bans
+            .lock()
+            .is_banned(peer_addr.ip(), chrono::Utc::now().timestamp())
+        {
+            continue;
+        }
+        let Some(slot) = ctx.slots.acquire(peer_addr.ip()) else {
+            continue;
+        };
+
+        let ctx = ctx.clone();
+        let admin = peer_addr.ip().is_loopback();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_websocket_connection(socket, ctx, admin).await {
+                eprintln!("WebSocket connection error from {}: {}", peer_addr, e);
+            }
+            drop(slot);
+        });
+    }
+}
+
+/// Send every queued transaction to every peer, over one connection per
+/// peer per round. A peer that already has one refuses it as a duplicate
+/// and doesn't relay it further. Returns how many transactions were sent.
+pub async fn relay_transactions(ctx: &DaemonContext) -> usize {
+    let (txs, dropped) = ctx.relay.lock().take();
+    if dropped > 0 {
+        (ctx.log)(&format!(
+            "[daemon] relay queue full: {} transaction(s) not relayed",
+            dropped
+        ));
+    }
+    if txs.is_empty() {
+        return 0;
+    }
+    for peer in ctx.peers.get_peers() {
+        let unreachable = || {
+            (ctx.log)(&format!(
+                "[daemon] could not relay transactions to {}",
+                peer
+            ))
+        };
+        let connected =
+            tokio::time::timeout(RELAY_TIMEOUT, crate::ws_client::WsClient::connect(&peer)).await;
+        let Ok(Ok(mut client)) = connected else {
+            unreachable();
+            continue;
+        };
+        for tx in &txs {
+            let sent = tokio::time::timeout(RELAY_TIMEOUT, client.submit_tx(tx)).await;
+            if !matches!(sent, Ok(Ok(_))) {
+                // Gone mid-round: skip the rest until the next one
+                unreachable();
+                break;
+            }
+        }
+    }
+    txs.len()
+}
+
+/// Offer the blocks each lagging peer lacks, oldest first, when it is at
+/// most `RELAY_BLOCKS_MAX` behind our tip. A peer that takes them, or has
+/// them already, is noted at its new height; one that refuses one isn't
+/// sent the rest. Each peer gets its blocks over one connection. Returns
+/// how many blocks peers took.
+pub async fn relay_blocks(ctx: &DaemonContext) -> usize {
+    let tip = (ctx.blockchain.lock().chain.len() as u64).saturating_sub(1);
+    let mut taken = 0;
+    for (peer, height) in ctx.peers.lagging_peers(tip) {
+        if tip - height > RELAY_BLOCKS_MAX {
+            continue;
+        }
+        let blocks = ctx
+            .blockchain
+            .lock()
+            .chain
+            .get(height as usize + 1..)
+            .map(<[_]>::to_vec)
+            .unwrap_or_default();
+        let connected =
+            tokio::time::timeout(RELAY_TIMEOUT, crate::ws_client::WsClient::connect(&peer)).await;
+        let Ok(Ok(mut client)) = connected else {
+            (ctx.log)(&format!("[daemon] could not relay blocks to {}", peer));
+            continue;
+        };
+        for block in blocks {
+            let sent = tokio::time::timeout(RELAY_TIMEOUT, client.submit_block(&block)).await;
+            match sent {
+                Ok(Ok(status)) if status.starts_with("ok") => {
+                    ctx.peers.note_height(&peer, block.index);
+                    if status == "ok" {
+                        taken += 1;
+                    }
+                }
+                Ok(Ok(status)) => {
+                    (ctx.log)(&format!(
+                        "[daemon] peer {} refused relayed block {}: {}",
+                        peer, block.index, status
+                    ));
+                    break;
+                }
+                _ => {
+                    (ctx.log)(&format!(
+                        "[daemon] could not relay block {} to {}",
+                        block.index, peer
+                    ));
+                    break;
+                }
+            }
+        }
+    }
+    taken
+}
+
+/// Lists a WebSocket connection in `getinfo` and `getconnections` for as
+/// long as it lives.
+struct OpenConnection<'a> {
+    registry: &'a Connections,
+    conn: Arc<Connection>,
+}
+
+impl<'a> OpenConnection<'a> {
+    fn new(registry: &'a Connections, addr: &str) -> Self {
+        Self {
+            registry,
+            conn: registry.open(addr),
+        }
+    }
+}
+
+impl Drop for OpenConnection<'_> {
+    fn drop(&mut self) {
+        self.registry.close(self.conn.id);
+    }
+}
+
+pub(crate) async fn handle_websocket_connection(
+    socket: TcpStream,
+    ctx: Arc<DaemonContext>,
+    admin: bool,
+) -> anyhow::Result<()> {
+    let source = socket.peer_addr().map_or_else(
+        |_| "unknown".to_string(),
+        |a| crate::net::canonical(a).to_string(),
+    );
+    // Local admin connections may send more, e.g. an `importmempool` export
+    let max_message = (!admin && max_message_bytes() > 0).then(max_message_bytes);
+    let config = WebSocketConfig {
+        max_message_size: max_message,
+        max_frame_size: max_message,
+        ..WebSocketConfig::default()
+    };
+    let mut ws = accept_async_with_config(socket, Some(config)).await?;
+    let open = OpenConnection::new(&ctx.connections, &source);
+
+    // Send greeting: who we are, so a client on another chain can hang up
+    let mut greeting = serde_json::to_value(NodeIdentity::of(&ctx))?;
+    greeting["type"] = "greeting".into();
+    ws.send(Message::Text(greeting.to_string())).await?;
+
+    // Whether this client asked for deflated responses via `setcompression`
+    let mut compress = false;
+    // Set once the client sends `subscribe`
+    let mut events: Option<tokio::sync::broadcast::Receiver<NodeEvent>> = None;
+    let mut heartbeat = tokio::time::interval(std::time::Duration::from_secs(HEARTBEAT_SECS));
+    // Local admin connections aren't rate limited
+    let mut bucket = TokenBucket::full(&ctx.rate_limits, std::time::Instant::now());
+    let mut warned_limited = false;
+
+    loop {
+        let msg = tokio::select! {
+            msg = ws.next() => match msg {
+                Some(Err(tokio_tungstenite::tungstenite::Error::Capacity(e))) => {
+                    ctx.warn(&open.conn, format_args!("{}; disconnecting", e));
+                    let reply = serde_json::json!({
+                        "type": "error",
+                        "message": "message too large",
+                        "max_bytes": max_message,
+                    });
+                    let _ = ws.send(Message::Text(reply.to_string())).await;
+                    break;
+                }
+                Some(msg) => msg?,
+                None => break,
+            },
+            event = next_event(&mut events) => {
+                let mut body = match event {
+                    Ok(event) => serde_json::to_value(&event)?,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                        serde_json::json!({"event": "lagged", "missed": missed})
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                        events = None;
+                        continue;
+                    }
+                };
+                body["type"] = "event".into();
+                ws.send(Message::Text(body.to_string())).await?;
+                continue;
+            }
+            _ = heartbeat.tick(), if events.is_some() => {
+                let height = ctx.blockchain.lock().chain.len().saturating_sub(1);
+                ws.send(Message::Text(
+                    serde_json::json!({"type": "heartbeat", "height": height}).to_string(),
+                ))
+                .await?;
+                continue;
+            }
+        };
+
+        if let Message::Text(text) = msg {
+            if !admin {
+                let (method, id) = request_head(&text);
+                let cost = method_cost(method.as_deref().unwrap_or(""));
+                if let Err(retry_ms) =
+                    bucket.take(cost, &ctx.rate_limits, std::time::Instant::now())
+                {
+                    if !warned_limited {
+                        ctx.warn(
+                            &open.conn,
+                            format_args!("rate limited; next request in {}ms", retry_ms),
+                        );
+                        warned_limited = true;
+                    }
+                    ws.send(Message::Text(crate::rpc::envelope(
+                        method.as_deref(),
+                        id.as_ref(),
+                        Err(crate::rpc::RpcError::new("rate limited").with("retry_ms", retry_ms)),
+                    )))
+                    .await?;
+                    continue;
+                }
+                // Nothing from an unknown protocol or chain gets processed
+                if !open.conn.is_greeted()
+                    && !matches!(method.as_deref(), Some("hello" | "handshake"))
+                {
+                    ctx.warn(
+                        &open.conn,
+                        format_args!(
+                            "sent {} before hello; disconnecting",
+                            method.as_deref().unwrap_or("a request")
+                        ),
+                    );
+                    ws.send(Message::Text(crate::rpc::envelope(
+                        method.as_deref(),
+                        id.as_ref(),
+                        Err(crate::rpc::RpcError::new("hello required")
+                            .with("protocol_version", PROTOCOL_VERSION)
+                            .with("network", ctx.network())),
+                    )))
+                    .await?;
+                    break;
+                }
+            }
+            if let Some(id) = parse_subscribe(&text) {
+                events = Some(ctx.events.subscribe());
+                heartbeat.reset();
+                let mut reply = serde_json::Map::new();
+                reply.insert(
+                    "events".to_string(),
+                    serde_json::json!(["newblock", "newtx", "reorg"]),
+                );
+                reply.insert("heartbeat_secs".to_string(), HEARTBEAT_SECS.into());
+                ws.send(Message::Text(crate::rpc::envelope(
+                    Some("subscribe"),
+                    id.as_ref(),
+                    Ok(reply),
+                )))
+                .await?;
+                continue;
+            }
+            if let Some((requested, id)) = parse_setcompression(&text) {
+                // Config `ws_compression`: when off, `setcompression` is refused
+                compress = requested && ctx.config.lock().ws_compression;
+                let mut reply = serde_json::Map::new();
+                reply.insert("enabled".to_string(), compress.into());
+                ws.send(Message::Text(crate::rpc::envelope(
+                    Some("setcompression"),
+                    id.as_ref(),
+                    Ok(reply),
+                )))
+                .await?;
+                continue;
+            }
+            let response = crate::rpc::dispatch(&ctx, &text, admin, &open.conn);
+            if compress && response.len() >= COMPRESSION_MIN_BYTES {
+                ws.send(Message::Binary(compress_message(&response)))
+                    .await?;
+            } else {
+                ws.send(Message::Text(response)).await?;
+            }
+            // Banned by what it just sent, or on another chain
+            if ctx.is_banned(&open.conn) || open.conn.is_closing() {
+                break;
+            }
+        } else if let Message::Binary(_) = msg {
+            ws.send(Message::Text(
+                r#"{"type":"error","message":"binary messages not supported"}"#.to_string(),
+            ))
+            .await?;
+        } else if let Message::Close(_) = msg {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// The next event for a subscribed connection; never resolves for
+/// connections that haven't subscribed.
+async fn next_event(
+    events: &mut Option<tokio::sync::broadcast::Receiver<NodeEvent>>,
+) -> Result<NodeEvent, tokio::sync::broadcast::error::RecvError> {
+    match events {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Method name and id of a request, for replies sent without dispatching
+/// it. Both are `None` for invalid JSON.
+fn request_head(text: &str) -> (Option<String>, Option<serde_json::Value>) {
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(text) else {
+        return (None, None);
+    };
+    let method = json
+        .get("method")
+        .and_then(|m| m.as_str())
+        .map(String::from);
+    (method, json.get("id").cloned())
+}
+
+/// `Some(id)` if `text` is a `subscribe` request. Like `setcompression` it
+/// changes what the connection sends, so connections handle it themselves.
+fn parse_subscribe(text: &str) -> Option<Option<serde_json::Value>> {
+    let json = serde_json::from_str::<serde_json::Value>(text).ok()?;
+    if json.get("method").and_then(|m| m.as_str()) != Some("subscribe") {
+        return None;
+    }
+    Some(json.get("id").cloned())
+}
+
+/// `Some((enabled, id))` if `text` is a `setcompression` request. Handled
+/// per connection rather than by the dispatcher since it changes framing.
+fn parse_setcompression(text: &str) -> Option<(bool, Option<serde_json::Value>)> {
+    let json = serde_json::from_str::<serde_json::Value>(text).ok()?;
+    if json.get("method").and_then(|m| m.as_str()) != Some("setcompression") {
+        return None;
+    }
+    let enabled = json
+        .get("params")
+        .and_then(|p| p.get("enabled"))
+        .and_then(|e| e.as_bool())
+        .unwrap_or(true);
+    Some((enabled, json.get("id").cloned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Block;
+    use crate::wallet::Wallet;
+    use tokio::net::TcpListener;
+
+    fn next_block(bc: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let last = bc.chain.last().unwrap();
+        let mut block = Block {
+            index: last.index + 1,
+            timestamp: chrono::Utc::now(),
+            transactions,
+            prev_hash: last.hash.clone(),
+            hash: String::new(),
+            nonce: 0,
+            difficulty: 1,
+        };
+        block.hash = Blockchain::calculate_hash(&block);
+        block
+    }
+
+    fn coinbase(to: &str, amount: i64) -> Transaction {
+        Transaction {
+            from: "coinbase".to_string(),
+            pub_key: String::new(),
+            to: to.to_string(),
+            amount,
+            signature: String::new(),
+            expires_at_height: None,
+        }
+    }
+
+    /// Block on `parent` at difficulty 1.
+    fn mine_on(parent: &Block, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block {
+            index: parent.index + 1,
+            timestamp: chrono::Utc::now(),
+            transactions,
+            prev_hash: parent.hash.clone(),
+            hash: String::new(),
+            nonce: 0,
+            difficulty: 1,
+        };
+        block.hash = Blockchain::calculate_hash(&block);
+        while !crate::blockchain::hash_meets_difficulty(&block.hash, 1) {
+            block.nonce += 1;
+            block.hash = Blockchain::calculate_hash(&block);
+        }
+        block
+    }
+
+    /// Chain with a single block paying `amount` to `wallet`.
+    fn funded_chain(wallet: &Wallet, amount: i64) -> Arc<Mutex<Blockchain>> {
+        let mut bc = Blockchain::new();
+        let block = next_block(&bc, vec![coinbase(&wallet.address, amount)]);
+        assert!(bc.add_block_skip_pow(block, 1, true));
+        Arc::new(Mutex::new(bc))
+    }
+
+    fn accept_block(
+        blockchain: &Arc<Mutex<Blockchain>>,
+        mempool: &Arc<Mutex<Vec<Transaction>>>,
+        rejections: &Arc<Mutex<TxRejectionLog>>,
+        txs: Vec<Transaction>,
+    ) {
+        let mut bc = blockchain.lock();
+        let block = next_block(&bc, txs);
+        assert!(bc.add_block_skip_pow(block, 1, true));
+        prune_mempool_after_block(&bc, &mut mempool.lock(), &mut rejections.lock());
+    }
+
+    fn status(
+        blockchain: &Arc<Mutex<Blockchain>>,
+        mempool: &Arc<Mutex<Vec<Transaction>>>,
+        rejections: &Arc<Mutex<TxRejectionLog>>,
+        txid: &str,
+    ) -> TxStatus {
+        lookup_tx_status(
+            &blockchain.lock(),
+            &mempool.lock(),
+            &rejections.lock(),
+            txid,
+        )
+    }
+
+    #[test]
+    fn expiring_tx_is_valid_up_to_its_height() {
+        let sender = Wallet::new().unwrap();
+        let bc = funded_chain(&sender, 1000).lock().clone();
+        let tx = sender
+            .create_expiring_transaction("bob", 100, Some(2))
+            .unwrap();
+
+        // Exactly at the expiry height
+        let at = next_block(&bc, vec![tx.clone()]);
+        assert_eq!(bc.validate_block_verbose(&at, 1, true), None);
+
+        // One block later
+        let mut later = bc.clone();
+        assert!(later.add_block_skip_pow(next_block(&bc, vec![]), 1, true));
+        let past = next_block(&later, vec![tx.clone()]);
+        let err = later.validate_block_verbose(&past, 1, true).unwrap();
+        assert!(err.contains("expired at height 2"), "{err}");
+        assert!(!later.validate_block(&past, 1, true));
+
+        // The expiry is signed: stretching it breaks the signature
+        let mut stretched = tx.clone();
+        stretched.expires_at_height = Some(10);
+        let err = later
+            .validate_block_verbose(&next_block(&later, vec![stretched]), 1, true)
+            .unwrap();
+        assert!(err.contains("Invalid transaction signature"), "{err}");
+
+        // Transactions without an expiry keep their old signature format
+        let old = sender.create_signed_transaction("bob", 100).unwrap();
+        assert!(
+            !serde_json::to_string(&old)
+                .unwrap()
+                .contains("expires_at_height")
+        );
+        assert_eq!(
+            later.validate_block_verbose(&next_block(&later, vec![old]), 1, true),
+            None
+        );
+    }
+
+    #[test]
+    fn expired_tx_is_refused_and_evicted() {
+        let sender = Wallet::new().unwrap();
+        let blockchain = funded_chain(&sender, 1000);
+        let mempool = Arc::new(Mutex::new(Vec::new()));
+        let rejections = Arc::new(Mutex::new(TxRejectionLog::new()));
+
+        // Tip is 1, so the next block is 2: an expiry of 1 is already past
+        let dead = sender
+            .create_expiring_transaction("bob", 100, Some(1))
+            .unwrap();
+        assert_eq!(
+            admit_transaction(dead, &blockchain, &mempool, &rejections),
+            Err("expired at height 1".to_string())
+        );
+
+        let expiring = sender
+            .create_expiring_transaction("bob", 100, Some(2))
+            .unwrap();
+        let lasting = sender.create_signed_transaction("carol", 100).unwrap();
+        let expiring_id = admit_transaction(expiring, &blockchain, &mempool, &rejections).unwrap();
+        admit_transaction(lasting, &blockchain, &mempool, &rejections).unwrap();
+
+        // Block 2 leaves it out; it can't go into block 3
+        accept_block(&blockchain, &mempool, &rejections, vec![]);
+        assert_eq!(mempool.lock().len(), 1);
+        match status(&blockchain, &mempool, &rejections, &expiring_id) {
+            TxStatus::Rejected { reason, .. } => {
+                assert_eq!(reason, "evicted: expired at height 2")
+            }
+            other => panic!("unexpected status {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tx_status_unknown_pending_confirmed() {
+        let sender = Wallet::new().unwrap();
+        let receiver = Wallet::new().unwrap();
+        let blockchain = funded_chain(&sender, 1000);
+        let mempool = Arc::new(Mutex::new(Vec::new()));
+        let rejections = Arc::new(Mutex::new(TxRejectionLog::new()));
+
+        let tx = sender
+            .create_signed_transaction(&receiver.address, 400)
+            .unwrap();
+        let txid = tx.txid();
+        assert_eq!(
+            status(&blockchain, &mempool, &rejections, &txid),
+            TxStatus::Unknown
+        );
+
+        admit_transaction(tx.clone(), &blockchain, &mempool, &rejections).unwrap();
+        assert_eq!(
+            status(&blockchain, &mempool, &rejections, &txid),
+            TxStatus::Pending
+        );
+
+        accept_block(&blockchain, &mempool, &rejections, vec![tx]);
+        assert_eq!(
+            status(&blockchain, &mempool, &rejections, &txid),
+            TxStatus::Confirmed { height: 2 }
+        );
+        assert!(mempool.lock().is_empty());
+    }
+
+    #[test]
+    fn tx_status_rejected_on_submit() {
+        let sender = Wallet::new().unwrap();
+        let receiver = Wallet::new().unwrap();
+        let blockchain = funded_chain(&sender, 1000);
+        let mempool = Arc::new(Mutex::new(Vec::new()));
+        let rejections = Arc::new(Mutex::new(TxRejectionLog::new()));
+
+        let mut tampered = sender
+            .create_signed_transaction(&receiver.address, 10)
+            .unwrap();
+        tampered.amount = 900;
+        let reason =
+            admit_transaction(tampered.clone(), &blockchain, &mempool, &rejections).unwrap_err();
+        assert_eq!(reason, "invalid signature");
+
+        let broke = sender
+            .create_signed_transaction(&receiver.address, 5000)
+            .unwrap();
+        admit_transaction(broke.clone(), &blockchain, &mempool, &rejections).unwrap_err();
+
+        for (tx, expected) in [
+            (tampered, "invalid signature"),
+            (broke, "insufficient funds"),
+        ] {
+            match status(&blockchain, &mempool, &rejections, &tx.txid()) {
+                TxStatus::Rejected { reason, .. } => assert_eq!(reason, expected),
+                other => panic!("expected rejected, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn tx_status_evicted_when_funds_spent_elsewhere() {
+        let sender = Wallet::new().unwrap();
+        let receiver = Wallet::new().unwrap();
+        let blockchain = funded_chain(&sender, 1000);
+        let mempool = Arc::new(Mutex::new(Vec::new()));
+        let rejections = Arc::new(Mutex::new(TxRejectionLog::new()));
+
+        let big = sender
+            .create_signed_transaction(&receiver.address, 600)
+            .unwrap();
+        let small = sender
+            .create_signed_transaction(&receiver.address, 300)
+            .unwrap();
+        admit_transaction(big.clone(), &blockchain, &mempool, &rejections).unwrap();
+        admit_transaction(small.clone(), &blockchain, &mempool, &rejections).unwrap();
+
+        // A block mined elsewhere spends 500 with a tx this node never saw.
+        let elsewhere = sender
+            .create_signed_transaction(&receiver.address, 500)
+            .unwrap();
+        accept_block(&blockchain, &mempool, &rejections, vec![elsewhere]);
+
+        match status(&blockchain, &mempool, &rejections, &big.txid()) {
+            TxStatus::Rejected { reason, .. } => assert!(reason.starts_with("evicted")),
+            other => panic!("expected evicted, got {:?}", other),
+        }
+        assert_eq!(
+            status(&blockchain, &mempool, &rejections, &small.txid()),
+            TxStatus::Pending
+        );
+    }
+
+    /// In-process daemon with its own state, driven through `rpc::dispatch`.
+    fn test_node(blockchain: Blockchain) -> DaemonContext {
+        let mut ctx = DaemonContext::new(
+            Arc::new(Mutex::new(blockchain)),
+            Arc::new(PeerManager::new()),
+            false,
+        );
+        let dir = tempfile::tempdir().unwrap();
+        ctx.chain_path = dir.path().join("blockchain.json");
+        ctx.scratch = Some(dir);
+        ctx
+    }
+
+    impl DaemonContext {
+        async fn call(&self, cmd: &serde_json::Value, admin: bool) -> serde_json::Value {
+            let conn = Connection::new(0, "test");
+            let resp = crate::rpc::dispatch(self, &cmd.to_string(), admin, &conn);
+            serde_json::from_str(&resp).unwrap()
+        }
+    }
+
+    async fn run_command(cmd: &str) -> serde_json::Value {
+        let node = test_node(Blockchain::new());
+        node.call(&serde_json::from_str(cmd).unwrap(), false).await
+    }
+
+    #[tokio::test]
+    async fn a_panic_while_holding_node_state_does_not_take_the_node_down() {
+        let sender = Wallet::new().unwrap();
+        let chain = funded_chain(&sender, 1000);
+        let node = Arc::new(test_node(chain.lock().clone()));
+        let crashing = node.clone();
+        let crashed = std::thread::spawn(move || {
+            let _bc = crashing.blockchain.lock();
+            let _mp = crashing.mempool.lock();
+            panic!("worker bug while holding the chain and mempool");
+        })
+        .join();
+        assert!(crashed.is_err());
+
+        let height = node
+            .call(&serde_json::json!({"method": "getheight"}), false)
+            .await;
+        assert_eq!(height["type"], "response", "{}", height);
+        let tx = sender.create_signed_transaction("bob", 100).unwrap();
+        let resp = node
+            .call(
+                &serde_json::json!({"method": "submittx", "params": {"tx": tx}}),
+                false,
+            )
+            .await;
+        assert_eq!(resp["status"], "ok");
+    }
+
+    #[tokio::test]
+    async fn pending_balance_subtracts_what_the_mempool_spends() {
+        let sender = Wallet::new().unwrap();
+        let node = test_node(funded_chain(&sender, 1000).lock().clone());
+        for amount in [100, 250] {
+            let tx = sender.create_signed_transaction("bob", amount).unwrap();
+            let resp = node
+                .call(
+                    &serde_json::json!({"method": "submittx", "params": {"tx": tx}}),
+                    false,
+                )
+                .await;
+            assert_eq!(resp["status"], "ok");
+        }
+
+        let resp = node
+            .call(
+                &serde_json::json!({"method": "getbalance", "params": {
+                    "address": sender.address.to_uppercase(),
+                    "include_pending": true,
+                }}),
+                false,
+            )
+            .await;
+        assert_eq!(resp["balance"], 1000);
+        assert_eq!(resp["balance_owe"], 1.0);
+        assert_eq!(resp["pending_out"], 350);
+        assert_eq!(resp["available"], 650);
+        assert_eq!(resp["available_owe"], 0.65);
+        // Incoming pending transactions don't count
+        let resp = node
+            .call(
+                &serde_json::json!({"method": "getbalance", "params": {
+                    "address": "bob",
+                    "include_pending": true,
+                }}),
+                false,
+            )
+            .await;
+        assert_eq!(resp["pending_out"], 0);
+        assert_eq!(resp["available"], 0);
+    }
+
+    #[tokio::test]
+    async fn getcapabilities_lists_every_method() {
+        let resp = run_command(r#"{"method":"getcapabilities"}"#).await;
+        let caps: Capabilities = serde_json::from_value(resp["data"].clone()).unwrap();
+        assert_eq!(caps.protocol_version, PROTOCOL_VERSION);
+        for spec in crate::rpc::METHODS
+            .iter()
+            .filter(|m| m.access != Access::Dev)
+        {
+            assert!(caps.supports(spec.name), "missing {}", spec.name);
+        }
+        assert!(!caps.supports("getfoo"));
+    }
+
+    #[tokio::test]
+    async fn unknown_method_error_names_method_and_version() {
+        let resp = run_command(r#"{"method":"getfoo","params":{}}"#).await;
+        assert_eq!(resp["type"], "error");
+        assert_eq!(resp["message"], "unknown method");
+        assert_eq!(resp["method"], "getfoo");
+        assert_eq!(resp["protocol_version"], PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn legacy_capabilities_gate_new_methods() {
+        let legacy = Capabilities::legacy();
+        assert!(legacy.supports("getchain"));
+        assert!(!legacy.supports("gettxstatus"));
+        assert!(!legacy.supports("getcapabilities"));
+    }
+
+    #[test]
+    fn rejection_log_is_bounded() {
+        let mut log = TxRejectionLog::with_capacity(3);
+        for i in 0..5 {
+            log.record(&format!("tx{}", i), "invalid amount");
+        }
+        assert!(log.get("tx0").is_none());
+        assert!(log.get("tx1").is_none());
+        assert!(log.get("tx4").is_some());
+        assert_eq!(log.order.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn dev_methods_refused_without_dev_mode() {
+        let resp = run_command(r#"{"method":"devmineblocks","params":{"to":"x","count":3}}"#).await;
+        assert_eq!(resp["type"], "error");
+        let resp = run_command(r#"{"method":"devfaucet","params":{"to":"x","amount":5}}"#).await;
+        assert_eq!(resp["type"], "error");
+
+        assert!(!Capabilities::for_node(false).supports("devfaucet"));
+        assert!(Capabilities::for_node(true).supports("devfaucet"));
+    }
+
+    #[test]
+    fn dev_blocks_credit_address_and_confirm_mempool() {
+        let sender = Wallet::new().unwrap();
+        let blockchain = funded_chain(&sender, 1000);
+        let mempool = Arc::new(Mutex::new(Vec::new()));
+        let rejections = Arc::new(Mutex::new(TxRejectionLog::new()));
+
+        let tx = sender.create_signed_transaction("bob", 400).unwrap();
+        admit_transaction(tx.clone(), &blockchain, &mempool, &rejections).unwrap();
+
+        let mut bc = blockchain.lock();
+        let mut mp = mempool.lock();
+        let mut log = rejections.lock();
+        for _ in 0..3 {
+            assert!(dev_append_block(&mut bc, &mut mp, &mut log, "alice", 500));
+        }
+        assert_eq!(bc.chain.len(), 5);
+        assert!(mp.is_empty());
+        let balances = bc.balances();
+        assert_eq!(balances["alice"], 1500);
+        assert_eq!(balances["bob"], 400);
+        assert!(matches!(
+            lookup_tx_status(&bc, &mp, &log, &tx.txid()),
+            TxStatus::Confirmed { height: 2 }
+        ));
+    }
+
+    #[tokio::test]
+    async fn mempool_depth_is_limited_and_reported() {
+        let wallets: Vec<Wallet> = (0..8).map(|_| Wallet::new().unwrap()).collect();
+        let mut bc = Blockchain::new();
+        let block = next_block(&bc, vec![coinbase(&wallets[0].address, 1000)]);
+        assert!(bc.add_block_skip_pow(block, 1, true));
+        let node = test_node(bc);
+
+        // Each wallet forwards what it just received: every hop is one deeper
+        for hop in 0..7 {
+            let tx = wallets[hop]
+                .create_signed_transaction(&wallets[hop + 1].address, 100)
+                .unwrap();
+            let resp = node
+                .call(
+                    &serde_json::json!({"method": "submittx", "params": {"tx": tx}}),
+                    false,
+                )
+                .await;
+            if hop <= 5 {
+                assert_eq!(resp["status"], "ok", "hop {}: {}", hop, resp);
+            } else {
+                assert_eq!(resp["message"], "rejected: too many unconfirmed ancestors");
+            }
+        }
+
+        let resp = node
+            .call(&serde_json::json!({"method": "getmempool"}), false)
+            .await;
+        let depths: Vec<u64> = resp["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["depth"].as_u64().unwrap())
+            .collect();
+        assert_eq!(depths, vec![0, 1, 2, 3, 4, 5]);
+
+        // In mempool order the whole chain fits in a single block
+        let mut bc = node.blockchain.lock();
+        assert!(dev_append_block(
+            &mut bc,
+            &mut node.mempool.lock(),
+            &mut node.rejections.lock(),
+            &wallets[0].address,
+            50,
+        ));
+        assert_eq!(bc.chain.last().unwrap().transactions.len(), 7);
+        assert!(node.mempool.lock().is_empty());
+    }
+
+    #[test]
+    fn dev_nodes_only_peer_with_dev_nodes() {
+        let main = NodeIdentity::of(&test_node(Blockchain::new()));
+        let mut dev = test_node(Blockchain::new());
+        dev.dev_mode = true;
+        let dev = NodeIdentity::of(&dev);
+        assert_eq!(main.incompatibility("owonero", None), None);
+        assert!(main.incompatibility("owonero-dev", None).is_some());
+        assert!(dev.incompatibility("owonero", None).is_some());
+        assert_eq!(
+            dev.incompatibility("owonero-dev", Some(&main.genesis_hash.to_uppercase())),
+            None
+        );
+        assert_eq!(
+            main.incompatibility("owonero", Some("00")),
+            Some(format!("genesis 00 (this node's is {})", main.genesis_hash))
+        );
+    }
+
+    #[tokio::test]
+    async fn peers_on_another_chain_are_never_synced_from() {
+        // Another coin built from this code: same rules, its own genesis
+        let mut fork = Blockchain::new();
+        fork.chain[0].timestamp += chrono::Duration::seconds(1);
+        fork.chain[0].hash = Blockchain::calculate_hash(&fork.chain[0]);
+        for _ in 0..5 {
+            let block = next_block(&fork, vec![coinbase("04aa", 500)]);
+            assert!(fork.add_block_skip_pow(block, 1, true));
+        }
+        let fork_node = Arc::new(test_node(fork.clone()));
+        let fork_addr = serve_node(fork_node.clone()).await;
+        let node = test_node(Blockchain::new());
+        node.peers.add_peer(fork_addr.clone());
+
+        // Miners and wallets hang up on its greeting
+        let err = crate::ws_client::WsClient::connect(&fork_addr)
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            err.downcast_ref::<crate::ws_client::IncompatibleNode>()
+                .is_some()
+        );
+        // So does the daemon, and stops trying
+        assert!(connect_peer(&node, &fork_addr).await.unwrap().is_none());
+        assert!(node.peers.get_peers().is_empty());
+        assert_eq!(node.blockchain.lock().chain.len(), 1);
+        assert_eq!(fork_node.blockchain.lock().chain.len(), 6);
+        let health = node.peers.health();
+        assert!(
+            health[0]
+                .incompatible
+                .as_deref()
+                .unwrap()
+                .starts_with("genesis ")
+        );
+        // Until the operator configures it again
+        node.peers.add_peer(fork_addr.clone());
+        assert_eq!(node.peers.get_peers(), [fork_addr]);
+
+        // Same genesis, other network: caught by the handshake, which
+        // tells the other side too
+        let mut dev = test_node(synthetic_chain(5));
+        dev.dev_mode = true;
+        let logged = Arc::new(Mutex::new(Vec::<String>::new()));
+        let sink = logged.clone();
+        dev.log = Box::new(move |line| sink.lock().push(line.to_string()));
+        let dev_addr = serve_node(Arc::new(dev)).await;
+        node.peers.add_peer(dev_addr.clone());
+        let mut client = connect_peer(&node, &dev_addr).await.unwrap().unwrap();
+        sync_with_peer(&node, &dev_addr, &mut client, true).await;
+        assert_eq!(node.blockchain.lock().chain.len(), 1);
+        assert!(!node.peers.get_peers().contains(&dev_addr));
+        assert!(
+            logged.lock()[0]
+                .contains("is on another chain: network owonero (this node is on owonero-dev)")
+        );
+        assert!(
+            client
+                .command("getheight", serde_json::json!({}))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn admission_decisions_are_journaled_with_sender_state() {
+        let sender = Wallet::new().unwrap();
+        let chain = funded_chain(&sender, 1000);
+        let mut node = test_node(chain.lock().clone());
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("journal.jsonl");
+        node.journal = Mutex::new(AdmissionJournal::new(Some(path.clone())));
+
+        for amount in [400, 700] {
+            let tx = sender.create_signed_transaction("bob", amount).unwrap();
+            node.call(
+                &serde_json::json!({"method": "submittx", "params": {"tx": tx}}),
+                false,
+            )
+            .await;
+        }
+        let query = serde_json::json!({"method": "getmempooljournal", "params": {"limit": 10}});
+        assert_eq!(node.call(&query, false).await["type"], "error");
+        let resp = node.call(&query, true).await;
+        let entries: Vec<AdmissionEntry> = serde_json::from_value(resp["data"].clone()).unwrap();
+
+        // Newest first; the second payment saw the first one pending
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].decision, "rejected");
+        assert_eq!(entries[0].reason.as_deref(), Some("insufficient funds"));
+        assert_eq!(
+            (entries[0].sender_balance, entries[0].pending_out),
+            (1000, 400)
+        );
+        assert_eq!(entries[1].decision, "accepted");
+        assert_eq!(
+            (entries[1].sender_balance, entries[1].pending_out),
+            (1000, 0)
+        );
+        assert_eq!(entries[1].sender, sender.address.to_lowercase());
+        assert_eq!(entries[1].source, "test");
+        assert_eq!(entries[1].height, 1);
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        let from_file: Vec<AdmissionEntry> = lines
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(from_file, entries.into_iter().rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn connection_context_shows_up_in_logs_journal_and_getconnections() {
+        let sender = Wallet::new().unwrap();
+        let chain = funded_chain(&sender, 1000);
+        let mut node = test_node(chain.lock().clone());
+        let logged = Arc::new(Mutex::new(Vec::<String>::new()));
+        let sink = logged.clone();
+        node.log = Box::new(move |line| sink.lock().push(line.to_string()));
+
+        let rig = node.connections.open("203.0.113.7:5100");
+        let wallet = node.connections.open("198.51.100.2:6000");
+        let send = |conn: &Connection, admin: bool, cmd: serde_json::Value| -> serde_json::Value {
+            let resp = crate::rpc::dispatch(&node, &cmd.to_string(), admin, conn);
+            serde_json::from_str(&resp).unwrap()
+        };
+
+        let hello = send(
+            &rig,
+            false,
+            serde_json::json!({"method": "hello", "params": {"protocol_version": 1, "agent": "rig-7/0.5.2\n"}}),
+        );
+        assert_eq!(hello["connection_id"], 1);
+        let mut orphan = next_block(&chain.lock(), vec![coinbase("rig", 50)]);
+        orphan.prev_hash = "00".repeat(32);
+        orphan.hash = Blockchain::calculate_hash(&orphan);
+        // Without proof of work it isn't parked to wait for its parent
+        while crate::blockchain::hash_meets_difficulty(&orphan.hash, orphan.difficulty) {
+            orphan.nonce += 1;
+            orphan.hash = Blockchain::calculate_hash(&orphan);
+        }
+        let resp = send(
+            &rig,
+            false,
+            serde_json::json!({"method": "submitblock", "params": {"block": orphan}}),
+        );
+        assert!(resp["status"].as_str().unwrap().starts_with("rejected"));
+        let overspend = sender.create_signed_transaction("bob", 5000).unwrap();
+        send(
+            &wallet,
+            false,
+            serde_json::json!({"method": "submittx", "params": {"tx": overspend}}),
+        );
+        send(
+            &wallet,
+            false,
+            serde_json::json!({"method": "getconnections"}),
+        );
+
+        let logged = logged.lock().clone();
+        assert_eq!(logged.len(), 3, "{:?}", logged);
+        assert!(
+            logged[0]
+                .starts_with("[daemon] #1 203.0.113.7:5100 (rig-7/0.5.2): submitblock rejected")
+        );
+        assert!(logged[1].starts_with("[daemon] #2 198.51.100.2:6000: submittx "));
+        assert!(logged[1].ends_with("rejected: insufficient funds"));
+        assert_eq!(
+            logged[2],
+            "[daemon] #2 198.51.100.2:6000: refused admin method getconnections"
+        );
+
+        let journal = node.journal.lock().recent(1);
+        assert_eq!(journal[0].source, "198.51.100.2:6000");
+        assert_eq!(journal[0].agent, None);
+
+        let resp = send(&rig, true, serde_json::json!({"method": "getconnections"}));
+        let listed: Vec<ConnectionInfo> = serde_json::from_value(resp["data"].clone()).unwrap();
+        let counts: Vec<_> = listed
+            .iter()
+            .map(|c| (c.id, c.addr.as_str(), c.agent.as_deref(), c.requests))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                (1, "203.0.113.7:5100", Some("rig-7/0.5.2"), 3),
+                (2, "198.51.100.2:6000", None, 2),
+            ]
+        );
+        node.connections.close(rig.id);
+        assert_eq!(node.connections.count(), 1);
+    }
+
+    #[test]
+    fn connections_over_a_limit_are_refused_until_a_slot_frees() {
+        let slots = Arc::new(ConnectionSlots::new(3, 2));
+        let rig: std::net::IpAddr = "203.0.113.7".parse().unwrap();
+        let other: std::net::IpAddr = "203.0.113.8".parse().unwrap();
+        let local: std::net::IpAddr = "127.0.0.1".parse().unwrap();
+
+        let first = slots.acquire(rig).unwrap();
+        let _second = slots.acquire(rig).unwrap();
+        assert!(slots.acquire(rig).is_none());
+        let _third = slots.acquire(other).unwrap();
+        // Full: even loopback waits
+        assert!(slots.acquire(local).is_none());
+        assert_eq!(slots.refused(), 2);
+
+        drop(first);
+        assert!(slots.acquire(rig).is_some());
+
+        // Loopback only counts against the total
+        slots.configure(0, 1);
+        let _locals: Vec<_> = (0..3).map(|_| slots.acquire(local).unwrap()).collect();
+        assert!(slots.acquire(other).is_none());
+        assert_eq!(slots.max_total(), 0);
+        assert_eq!(slots.max_per_ip(), 1);
+    }
+
+    #[test]
+    fn token_buckets_charge_by_method_and_refill_over_time() {
+        let limits = RateLimits::new(10, 60);
+        let start = std::time::Instant::now();
+        let mut bucket = TokenBucket::full(&limits, start);
+
+        assert_eq!(bucket.take(method_cost("getchain"), &limits, start), Ok(()));
+        assert_eq!(
+            bucket.take(method_cost("getchain"), &limits, start),
+            Err(4000)
+        );
+        for _ in 0..10 {
+            assert_eq!(
+                bucket.take(method_cost("getheight"), &limits, start),
+                Ok(())
+            );
+        }
+        assert_eq!(
+            bucket.take(method_cost("getheight"), &limits, start),
+            Err(100)
+        );
+        assert_eq!(limits.limited(), 2);
+
+        // Refills at the rate, up to the burst
+        let later = start + std::time::Duration::from_millis(500);
+        assert_eq!(bucket.take(method_cost("getblock"), &limits, later), Ok(()));
+        let much_later = later + std::time::Duration::from_secs(3600);
+        assert_eq!(bucket.take(60, &limits, much_later), Ok(()));
+        // More than the burst costs the burst
+        assert_eq!(bucket.take(100, &limits, much_later), Err(6000));
+
+        limits.configure(0, 60);
+        assert_eq!(bucket.take(100, &limits, much_later), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn remote_connections_are_rate_limited_and_local_ones_are_not() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut node = test_node(synthetic_chain(3));
+        node.rate_limits = RateLimits::new(1, 60);
+        let logged = Arc::new(Mutex::new(Vec::<String>::new()));
+        let sink = logged.clone();
+        node.log = Box::new(move |line| sink.lock().push(line.to_string()));
+        let ctx = Arc::new(node);
+        let served = ctx.clone();
+        tokio::spawn(async move {
+            let mut admin = false;
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(handle_websocket_connection(socket, served.clone(), admin));
+                admin = true;
+            }
+        });
+        type Socket = tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >;
+        async fn send(ws: &mut Socket, request: serde_json::Value) -> serde_json::Value {
+            ws.send(Message::Text(request.to_string())).await.unwrap();
+            loop {
+                let Some(Ok(Message::Text(text))) = ws.next().await else {
+                    panic!("no response to {}", request);
+                };
+                let msg: serde_json::Value = serde_json::from_str(&text).unwrap();
+                if msg["type"] != "greeting" {
+                    return msg;
+                }
+            }
+        }
+        async fn call(ws: &mut Socket, method: &str) -> serde_json::Value {
+            send(ws, serde_json::json!({"method": method, "id": 7})).await
+        }
+
+        let (mut remote, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+            .await
+            .unwrap();
+        let hello = serde_json::json!({"method": "hello", "params": {"protocol_version": 1}});
+        assert_eq!(send(&mut remote, hello).await["type"], "response");
+        assert_eq!(call(&mut remote, "getchain").await["type"], "response");
+        for _ in 0..7 {
+            assert_eq!(call(&mut remote, "getheight").await["type"], "response");
+        }
+        let limited = call(&mut remote, "getchain").await;
+        assert_eq!(limited["type"], "error");
+        assert_eq!(limited["message"], "rate limited");
+        assert_eq!(limited["method"], "getchain");
+        assert_eq!(limited["id"], 7);
+        let retry_ms = limited["retry_ms"].as_u64().unwrap();
+        assert!(retry_ms > 40_000 && retry_ms <= 50_000, "{}", retry_ms);
+        assert_eq!(
+            call(&mut remote, "getchain").await["message"],
+            "rate limited"
+        );
+        // Cheap calls still fit
+        assert_eq!(call(&mut remote, "getheight").await["type"], "response");
+        assert_eq!(ctx.rate_limits.limited(), 2);
+        // Warned about once per connection
+        let warnings = logged
+            .lock()
+            .iter()
+            .filter(|l| l.contains("rate limited"))
+            .count();
+        assert_eq!(warnings, 1);
+
+        let (mut local, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+            .await
+            .unwrap();
+        for _ in 0..5 {
+            assert_eq!(call(&mut local, "getchain").await["type"], "response");
+        }
+        let info = call(&mut local, "getinfo").await;
+        assert_eq!(
+            (&info["rate_limit_per_sec"], &info["rate_limit_burst"]),
+            (&1.into(), &60.into())
+        );
+        assert_eq!(info["rate_limited"], 2);
+    }
+
+    #[tokio::test]
+    async fn remote_connections_must_say_hello_before_anything_else() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ctx = Arc::new(test_node(synthetic_chain(3)));
+        let served = ctx.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(handle_websocket_connection(socket, served.clone(), false));
+            }
+        });
+        let open = || async {
+            let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+                .await
+                .unwrap();
+            let Some(Ok(Message::Text(greeting))) = ws.next().await else {
+                panic!("no greeting");
+            };
+            let greeting: serde_json::Value = serde_json::from_str(&greeting).unwrap();
+            assert_eq!(greeting["protocol_version"], PROTOCOL_VERSION);
+            assert_eq!(greeting["network"], "owonero");
+            ws
+        };
+        async fn reply_and_hang_up<S>(ws: &mut S, request: serde_json::Value) -> serde_json::Value
+        where
+            S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>
+                + SinkExt<Message>
+                + Unpin,
+        {
+            let _ = ws.send(Message::Text(request.to_string())).await;
+            let Some(Ok(Message::Text(text))) = ws.next().await else {
+                panic!("no reply to {}", request);
+            };
+            assert!(matches!(
+                ws.next().await,
+                None | Some(Ok(Message::Close(_))) | Some(Err(_))
+            ));
+            serde_json::from_str(&text).unwrap()
+        }
+
+        // A client that skips hello is told what this node speaks
+        let refused = reply_and_hang_up(
+            &mut open().await,
+            serde_json::json!({"method": "getheight", "id": 1}),
+        )
+        .await;
+        assert_eq!(refused["message"], "hello required");
+        assert_eq!(refused["id"], 1);
+        assert_eq!(refused["protocol_version"], PROTOCOL_VERSION);
+        assert_eq!(refused["network"], "owonero");
+
+        for (params, reason) in [
+            (
+                serde_json::json!({"protocol_version": 0}),
+                "protocol version 0",
+            ),
+            (
+                serde_json::json!({"protocol_version": 1, "network": "owonero-dev"}),
+                "network owonero-dev",
+            ),
+            (
+                serde_json::json!({"protocol_version": 1, "genesis_hash": "ab".repeat(32)}),
+                "genesis abab",
+            ),
+        ] {
+            let refused = reply_and_hang_up(
+                &mut open().await,
+                serde_json::json!({"method": "hello", "params": params}),
+            )
+            .await;
+            let message = refused["message"].as_str().unwrap();
+            assert!(message.starts_with("incompatible: "), "{}", message);
+            assert!(message.contains(reason), "{}", message);
+        }
+
+        // A compatible hello, or a peer's handshake, opens the connection
+        let mut ws = open().await;
+        let hello = serde_json::json!({
+            "method": "hello",
+            "params": {"protocol_version": 1, "network": "owonero"},
+        });
+        ws.send(Message::Text(hello.to_string())).await.unwrap();
+        ws.send(Message::Text(r#"{"method": "getheight"}"#.to_string()))
+            .await
+            .unwrap();
+        for expected in ["hello", "getheight"] {
+            let Some(Ok(Message::Text(text))) = ws.next().await else {
+                panic!("no reply");
+            };
+            let resp: serde_json::Value = serde_json::from_str(&text).unwrap();
+            assert_eq!(
+                (&resp["type"], &resp["method"]),
+                (&"response".into(), &expected.into())
+            );
+        }
+        let identity = NodeIdentity::of(&ctx);
+        let mut peer = open().await;
+        let handshake = serde_json::json!({"method": "handshake", "params": identity});
+        peer.send(Message::Text(handshake.to_string()))
+            .await
+            .unwrap();
+        peer.send(Message::Text(r#"{"method": "getheight"}"#.to_string()))
+            .await
+            .unwrap();
+        let _ = peer.next().await;
+        let Some(Ok(Message::Text(text))) = peer.next().await else {
+            panic!("no reply");
+        };
+        assert!(text.contains(r#""method":"getheight""#), "{}", text);
+
+        // The client library says hello on its own
+        let mut client = crate::ws_client::WsClient::connect(&addr.to_string())
+            .await
+            .unwrap();
+        let resp = client
+            .command("getheight", serde_json::json!({}))
+            .await
+            .unwrap();
+        assert_eq!(resp["type"], "response");
+        let agents: Vec<_> = ctx
+            .connections
+            .list()
+            .into_iter()
+            .filter_map(|c| c.agent)
+            .collect();
+        assert!(
+            agents.iter().any(|a| a.starts_with("owonero/")),
+            "{:?}",
+            agents
+        );
+    }
+
+    #[tokio::test]
+    async fn whole_chains_travel_in_capped_getblocks_slices() {
+        let chain = synthetic_chain(1200);
+        let node = test_node(chain.clone());
+        let slice = node
+            .call(
+                &serde_json::json!({"method": "getblocks", "params": {"from": 10, "to": 12}}),
+                false,
+            )
+            .await;
+        let blocks: Vec<Block> = serde_json::from_value(slice["data"].clone()).unwrap();
+        let hashes = |blocks: &[Block]| blocks.iter().map(|b| b.hash.clone()).collect::<Vec<_>>();
+        assert_eq!(hashes(&blocks), hashes(&chain.chain[10..=12]));
+
+        let too_many = node
+            .call(
+                &serde_json::json!({"method": "getblocks", "params": {"from": 0, "to": 500}}),
+                true,
+            )
+            .await;
+        assert_eq!(too_many["message"], "at most 500 blocks per getblocks");
+        assert_eq!(too_many["max_blocks"], 500);
+        for (from, to) in [(1199, 1201), (1300, 1310), (5, 4)] {
+            let resp = node
+                .call(
+                    &serde_json::json!({"method": "getblocks", "params": {"from": from, "to": to}}),
+                    false,
+                )
+                .await;
+            assert_eq!(
+                resp["message"],
+                format!("blocks {}..{} out of range", from, to)
+            );
+            assert_eq!(resp["height"], 1200);
+        }
+
+        // Headers are the same blocks without their transactions, in
+        // bigger slices
+        let headers = node
+            .call(
+                &serde_json::json!({"method": "getheaders", "params": {"from": 0, "to": 1199}}),
+                false,
+            )
+            .await;
+        let headers: Vec<crate::blockchain::BlockHeader> =
+            serde_json::from_value(headers["data"].clone()).unwrap();
+        assert_eq!(headers.len(), 1200);
+        assert_eq!(
+            headers[11],
+            crate::blockchain::BlockHeader::from(&chain.chain[11])
+        );
+        let too_many = node
+            .call(
+                &serde_json::json!({"method": "getheaders", "params": {"from": 0, "to": 2000}}),
+                false,
+            )
+            .await;
+        assert_eq!(too_many["message"], "at most 2000 headers per getheaders");
+        assert_eq!(too_many["max_headers"], 2000);
+
+        // Clients fetch the whole chain slice by slice
+        let addr = serve_node(Arc::new(node)).await;
+        let mut client = crate::ws_client::WsClient::connect(&addr).await.unwrap();
+        let mut seen = Vec::new();
+        let fetched = client.chain(|p| seen.push(p.fetched)).await.unwrap();
+        assert_eq!(hashes(&fetched.chain), hashes(&chain.chain));
+        assert_eq!(seen, [500, 1000, 1201]);
+    }
+
+    #[tokio::test]
+    async fn a_node_short_of_disk_refuses_writes_and_counts_failed_saves() {
+        let sender = Wallet::new().unwrap();
+        let chain = funded_chain(&sender, 1000);
+        let mut node = test_node(chain.lock().clone());
+        node.dev_mode = true;
+        let free = Arc::new(AtomicU64::new(10 * 1024 * 1024));
+        let reading = free.clone();
+        node.disk = crate::disk::DiskGuard::new(100)
+            .with_probe(move |_| Ok(reading.load(Ordering::Relaxed)));
+        // A directory where the chain file goes can't be written over
+        node.chain_path = node.chain_path.with_file_name("unwritable");
+        std::fs::create_dir_all(&node.chain_path).unwrap();
+        let logged = Arc::new(Mutex::new(Vec::<String>::new()));
+        let sink = logged.clone();
+        node.log = Box::new(move |line| sink.lock().push(line.to_string()));
+
+        let tx = sender.create_signed_transaction("04bob", 10).unwrap();
+        let submit_tx = serde_json::json!({"method": "submittx", "params": {"tx": tx}});
+        let block = next_block(&chain.lock(), vec![coinbase("rig", 50)]);
+        let submit_block = serde_json::json!({"method": "submitblock", "params": {"block": block}});
+        for request in [&submit_tx, &submit_block] {
+            let resp = node.call(request, false).await;
+            assert_eq!(resp["type"], "error");
+            assert_eq!(resp["message"], "node out of disk space");
+        }
+        assert!(node.mempool.lock().is_empty());
+        let info = node
+            .call(&serde_json::json!({"method": "getinfo"}), false)
+            .await;
+        assert_eq!(info["disk"]["read_only"], true);
+        assert_eq!(info["disk"]["free_bytes"], 10 * 1024 * 1024);
+        assert_eq!(info["disk"]["min_free_bytes"], 100 * 1024 * 1024);
+        assert!(logged.lock()[0].contains("DISK LOW: 10 MB free"));
+
+        // Back to normal once space frees up
+        free.store(500 * 1024 * 1024, Ordering::Relaxed);
+        let resp = node.call(&submit_tx, false).await;
+        assert_eq!(resp["status"], "ok", "{}", resp);
+        assert!(logged.lock()[1].contains("disk space recovered"));
+
+        // A save that fails is counted, not dropped
+        let mine = serde_json::json!({"method": "devmineblocks", "params": {"to": "04bob"}});
+        assert_eq!(node.call(&mine, true).await["blocks"], 1);
+        let info = node
+            .call(&serde_json::json!({"method": "getinfo"}), false)
+            .await;
+        assert_eq!(info["disk"]["read_only"], false);
+        assert_eq!(info["disk"]["persist_errors"], 1);
+        assert!(info["disk"]["last_persist_error"].is_string());
+        assert!(logged.lock()[2].contains("FAILED to save the chain"));
+    }
+
+    #[tokio::test]
+    async fn oversized_messages_and_submissions_are_refused_unparsed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ctx = Arc::new(test_node(synthetic_chain(3)));
+        let served = ctx.clone();
+        tokio::spawn(async move {
+            let mut admin = false;
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(handle_websocket_connection(socket, served.clone(), admin));
+                admin = true;
+            }
+        });
+        let huge = serde_json::json!({
+            "method": "submittx",
+            "params": {"tx": {"signature": "0".repeat(DEFAULT_MAX_MESSAGE_BYTES)}},
+        })
+        .to_string();
+        let reply = |text: Option<Result<Message, _>>| match text {
+            Some(Ok(Message::Text(text))) => {
+                serde_json::from_str::<serde_json::Value>(&text).unwrap()
+            }
+            other => panic!("expected a text reply, got {:?}", other),
+        };
+
+        let (mut remote, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+            .await
+            .unwrap();
+        assert_eq!(reply(remote.next().await)["type"], "greeting");
+        // The daemon hangs up without reading the rest
+        let _ = remote.send(Message::Text(huge.clone())).await;
+        let refused = reply(remote.next().await);
+        assert_eq!(refused["message"], "message too large");
+        assert_eq!(refused["max_bytes"], DEFAULT_MAX_MESSAGE_BYTES);
+        // ...and the connection is closed
+        assert!(matches!(
+            remote.next().await,
+            None | Some(Ok(Message::Close(_))) | Some(Err(_))
+        ));
+
+        // Local admin connections can send more, but fields are still capped
+        let (mut local, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+            .await
+            .unwrap();
+        assert_eq!(reply(local.next().await)["type"], "greeting");
+        local.send(Message::Text(huge)).await.unwrap();
+        let refused = reply(local.next().await);
+        assert_eq!(
+            refused["message"],
+            format!(
+                "rejected: field of {} bytes (max 1024)",
+                DEFAULT_MAX_MESSAGE_BYTES
+            )
+        );
+
+        let block = serde_json::json!({
+            "index": 4,
+            "transactions": vec![serde_json::Value::Null; 10_001],
+        });
+        let resp = ctx
+            .call(
+                &serde_json::json!({"method": "submitblock", "params": {"block": block}}),
+                true,
+            )
+            .await;
+        assert_eq!(resp["status"], "rejected: 10001 transactions (max 10000)");
+    }
+
+    #[test]
+    fn misbehaving_ips_are_banned_until_the_ban_lapses() {
+        let sender = Wallet::new().unwrap();
+        let chain = funded_chain(&sender, 1000);
+        let mut node = test_node(chain.lock().clone());
+        node.bans = Mutex::new(BanList::new(100, 60));
+        node.log = Box::new(|_| {});
+        let rig = node.connections.open("203.0.113.7:5100");
+        let local = node.connections.open("127.0.0.1:5101");
+        let send = |conn: &Connection, admin: bool, cmd: &str| -> serde_json::Value {
+            serde_json::from_str(&crate::rpc::dispatch(&node, cmd, admin, conn)).unwrap()
+        };
+        let submit_block = |block: &Block| {
+            serde_json::json!({"method": "submitblock", "params": {"block": block}}).to_string()
+        };
+
+        // Losing a race for a height costs nothing
+        let mut taken = next_block(&chain.lock(), vec![coinbase("rig", 50)]);
+        taken.index = 1;
+        taken.hash = Blockchain::calculate_hash(&taken);
+        let resp = send(&rig, false, &submit_block(&taken));
+        assert!(resp["status"].as_str().unwrap().contains("already exists"));
+
+        for _ in 0..3 {
+            send(&rig, false, "{not json");
+        }
+        let mut tampered = sender.create_signed_transaction("04bob", 10).unwrap();
+        tampered.amount = 900;
+        let resp = send(
+            &rig,
+            false,
+            &serde_json::json!({"method": "submittx", "params": {"tx": tampered}}).to_string(),
+        );
+        assert_eq!(resp["message"], "rejected: invalid signature");
+        assert!(!node.is_banned(&rig));
+
+        let mut orphan = next_block(&chain.lock(), vec![coinbase("rig", 50)]);
+        orphan.prev_hash = "00".repeat(32);
+        orphan.hash = Blockchain::calculate_hash(&orphan);
+        while crate::blockchain::hash_meets_difficulty(&orphan.hash, orphan.difficulty) {
+            orphan.nonce += 1;
+            orphan.hash = Blockchain::calculate_hash(&orphan);
+        }
+        send(&rig, false, &submit_block(&orphan));
+        assert!(node.is_banned(&rig));
+        assert!(!node.is_banned(&node.connections.open("203.0.113.8:5100")));
+
+        // Local connections are never banned
+        for _ in 0..20 {
+            send(&local, true, "{not json");
+        }
+        assert!(!node.is_banned(&local));
+
+        let bans = send(&rig, false, r#"{"method": "getbans"}"#);
+        assert_eq!(bans["type"], "error");
+        let bans: Vec<BanEntry> =
+            serde_json::from_value(send(&local, true, r#"{"method": "getbans"}"#)["data"].clone())
+                .unwrap();
+        assert_eq!(bans.len(), 1);
+        assert_eq!(
+            (bans[0].ip.as_str(), bans[0].reason.as_str()),
+            ("203.0.113.7", "invalid block")
+        );
+        assert_eq!(bans[0].until - bans[0].banned_at, 60);
+
+        // Lapsed bans let the IP back in and drop out of the list
+        let mut list = node.bans.lock();
+        let ip = "203.0.113.7".parse().unwrap();
+        assert!(list.is_banned(ip, bans[0].until - 1));
+        assert!(!list.is_banned(ip, bans[0].until));
+        assert!(list.current(bans[0].until).is_empty());
+    }
+
+    #[tokio::test]
+    async fn blocklist_is_policy_not_consensus_and_reloads_live() {
+        let flagged = Wallet::new().unwrap();
+        let honest = Wallet::new().unwrap();
+        let mut bc = funded_chain(&flagged, 1000).lock().clone();
+        let block = next_block(&bc, vec![coinbase(&honest.address, 1000)]);
+        assert!(bc.add_block_skip_pow(block, 1, true));
+        let mut node = test_node(bc);
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("blocked.txt");
+        std::fs::write(&file, format!("{}\n", flagged.address)).unwrap();
+        let mut blocklist = crate::policy::Blocklist::new(vec![], Some(file.clone()));
+        blocklist.reload().unwrap();
+        node.blocklist = Mutex::new(blocklist);
+        let submit =
+            |tx: &Transaction| serde_json::json!({"method": "submittx", "params": {"tx": tx}});
+
+        // Neither sending from nor paying a blocked address gets in
+        let from_flagged = flagged.create_signed_transaction("04bob", 100).unwrap();
+        let resp = node.call(&submit(&from_flagged), false).await;
+        assert_eq!(
+            resp["message"],
+            format!(
+                "rejected: policy: address {} is blocked by this node",
+                flagged.address
+            )
+        );
+        let to_flagged = honest
+            .create_signed_transaction(&flagged.address.to_uppercase(), 100)
+            .unwrap();
+        assert_eq!(
+            node.call(&submit(&to_flagged), false).await["type"],
+            "error"
+        );
+        assert_eq!(
+            node.journal.lock().recent(1)[0].reason.as_deref(),
+            Some(
+                format!(
+                    "policy: address {} is blocked by this node",
+                    flagged.address.to_uppercase()
+                )
+                .as_str()
+            )
+        );
+        assert!(node.mempool.lock().is_empty());
+
+        // Another miner's block spending from it is still valid
+        let mut mined = next_block(&node.blockchain.lock(), vec![from_flagged.clone()]);
+        while !crate::blockchain::hash_meets_difficulty(&mined.hash, 1) {
+            mined.nonce += 1;
+            mined.hash = Blockchain::calculate_hash(&mined);
+        }
+        let resp = node
+            .call(
+                &serde_json::json!({"method": "submitblock", "params": {"block": mined}}),
+                false,
+            )
+            .await;
+        assert_eq!(resp["status"], "ok");
+
+        // Emptying the file and reloading lets it through; blocking again
+        // drops what is already pending
+        std::fs::write(&file, "# cleared\n").unwrap();
+        let reload = serde_json::json!({"method": "reloadblocklist"});
+        assert_eq!(node.call(&reload, false).await["type"], "error");
+        let resp = node.call(&reload, true).await;
+        assert_eq!(
+            (resp["addresses"].as_u64(), resp["dropped"].as_u64()),
+            (Some(0), Some(0))
+        );
+        assert_eq!(node.call(&submit(&to_flagged), false).await["status"], "ok");
+        std::fs::write(&file, &flagged.address).unwrap();
+        let resp = node.call(&reload, true).await;
+        assert_eq!(
+            (resp["addresses"].as_u64(), resp["dropped"].as_u64()),
+            (Some(1), Some(1))
+        );
+        assert!(node.mempool.lock().is_empty());
+
+        // A broken file keeps the list in force
+        let _ = std::fs::remove_file(&file);
+        assert_eq!(node.call(&reload, true).await["type"], "error");
+        assert!(node.blocklist.lock().is_blocked(&flagged.address));
+    }
+
+    #[tokio::test]
+    async fn reloadconfig_applies_live_settings_and_reports_the_rest() {
+        let flagged = Wallet::new().unwrap();
+        let mut node = test_node(funded_chain(&flagged, 1000).lock().clone());
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("config.json");
+        node.config_path = Some(path.clone());
+        let submit =
+            |tx: &Transaction| serde_json::json!({"method": "submittx", "params": {"tx": tx}});
+        let reload = serde_json::json!({"method": "reloadconfig"});
+        let pending = flagged.create_signed_transaction("04bob", 100).unwrap();
+        assert_eq!(node.call(&submit(&pending), false).await["status"], "ok");
+
+        let mut edited = node.config.lock().clone();
+        edited.blocked_addresses = vec![flagged.address.clone()];
+        edited.peers = vec!["seed.example.org:6969".to_string()];
+        edited.max_peers = 4;
+        edited.daemon_port += 1;
+        std::fs::write(&path, serde_json::to_string(&edited).unwrap()).unwrap();
+
+        assert_eq!(node.call(&reload, false).await["type"], "error");
+        let resp = node.call(&reload, true).await;
+        assert_eq!(
+            resp["applied"],
+            serde_json::json!(["peers", "max_peers", "blocked_addresses"])
+        );
+        assert_eq!(resp["restart_required"], serde_json::json!(["daemon_port"]));
+        assert_eq!(resp["dropped"], 1);
+
+        // In force for the next request, and in getinfo
+        let another = flagged.create_signed_transaction("04bob", 50).unwrap();
+        assert!(
+            node.call(&submit(&another), false).await["message"]
+                .as_str()
+                .unwrap()
+                .contains("is blocked by this node")
+        );
+        let info = node
+            .call(&serde_json::json!({"method": "getinfo"}), false)
+            .await;
+        assert_eq!(
+            (
+                &info["peers"],
+                &info["max_peers"],
+                &info["blocked_addresses"]
+            ),
+            (&1.into(), &5.into(), &1.into())
+        );
+
+        // Applied fields are now the running ones; the port still isn't
+        let resp = node.call(&reload, true).await;
+        assert_eq!(resp["applied"], serde_json::json!([]));
+        assert_eq!(resp["restart_required"], serde_json::json!(["daemon_port"]));
+
+        // A file that doesn't load applies nothing
+        edited.blocked_addresses.clear();
+        edited.blocked_addresses_file = Some("/nonexistent/owonero_blocklist".to_string());
+        std::fs::write(&path, serde_json::to_string(&edited).unwrap()).unwrap();
+        let resp = node.call(&reload, true).await;
+        assert!(
+            resp["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("nothing applied")
+        );
+        assert!(node.blocklist.lock().is_blocked(&flagged.address));
+    }
+
+    #[tokio::test]
+    async fn mempool_export_import_roundtrip() {
+        let sender = Wallet::new().unwrap();
+        let chain = funded_chain(&sender, 1000);
+        let chain_json = serde_json::to_string(&*chain.lock()).unwrap();
+        let node_a = test_node(serde_json::from_str(&chain_json).unwrap());
+        let node_b = test_node(serde_json::from_str(&chain_json).unwrap());
+
+        for amount in [100, 200] {
+            let tx = sender.create_signed_transaction("bob", amount).unwrap();
+            let resp = node_a
+                .call(
+                    &serde_json::json!({"method": "submittx", "params": {"tx": tx}}),
+                    false,
+                )
+                .await;
+            assert_eq!(resp["status"], "ok");
+        }
+        // An entry that slipped past policy on A must still be checked on B
+        let mut forged = sender.create_signed_transaction("mallory", 300).unwrap();
+        forged.amount = 600;
+        node_a.mempool.lock().push(forged);
+
+        let export = node_a
+            .call(&serde_json::json!({"method": "getmempool"}), false)
+            .await;
+        let import =
+            serde_json::json!({"method": "importmempool", "params": {"txs": export["data"]}});
+
+        let refused = node_b.call(&import, false).await;
+        assert_eq!(refused["type"], "error");
+        assert!(node_b.mempool.lock().is_empty());
+
+        let resp = node_b.call(&import, true).await;
+        let results = resp["data"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["status"], "accepted");
+        assert_eq!(results[1]["status"], "accepted");
+        assert_eq!(results[2]["status"], "rejected");
+        assert_eq!(results[2]["reason"], "invalid signature");
+
+        let ids = |node: &DaemonContext| -> Vec<String> {
+            node.mempool.lock().iter().map(|t| t.txid()).collect()
+        };
+        let mut expected = ids(&node_a);
+        expected.pop();
+        assert_eq!(ids(&node_b), expected);
+    }
+
+    /// Chain with realistic-looking but unmined blocks, for size tests.
+    fn synthetic_chain(blocks: u64) -> Blockchain {
+        use sha3::Digest;
+        let hex_of = |seed: String| hex::encode(sha3::Sha3_256::digest(seed.as_bytes()));
+        let mut bc = Blockchain::new();
+        for i in 1..=blocks {
+            let prev_hash = bc.chain.last().unwrap().hash.clone();
+            bc.chain.push(Block {
+                index: i,
+                timestamp: chrono::Utc::now(),
+                transactions: vec![coinbase(
+                    &format!("04{}", hex_of(format!("miner{}", i % 7))),
+                    500,
+                )],
+                prev_hash,
+                hash: hex_of(format!("block{}", i)),
+                nonce: (i * 7919) as u32,
+                difficulty: 4,
+            });
+        }
+        bc
+    }
+
+    #[test]
+    fn compressed_getchain_is_much_smaller() {
+        let chain = serde_json::to_string(&synthetic_chain(3000)).unwrap();
+        let compressed = compress_message(&chain);
+        assert!(compressed.len() * 2 < chain.len());
+        assert_eq!(
+            decompress_message(&compressed, crate::protocol::MAX_INFLATED_MESSAGE_BYTES).unwrap(),
+            chain
+        );
+    }
+
+    #[test]
+    fn inflating_stops_at_the_limit() {
+        // Zeros deflate about a thousandfold
+        let bomb = compress_message(&"0".repeat(4 * 1024 * 1024));
+        assert!(bomb.len() < 64 * 1024);
+        let err = decompress_message(&bomb, 1024 * 1024).unwrap_err();
+        assert!(err.to_string().contains("more than 1048576 bytes"));
+        let exact = compress_message(&"0".repeat(1024));
+        assert_eq!(decompress_message(&exact, 1024).unwrap().len(), 1024);
+    }
+
+    /// Accept WebSocket connections for `blockchain` on a free local port.
+    async fn serve(blockchain: Blockchain) -> String {
+        serve_node(Arc::new(test_node(blockchain))).await
+    }
+
+    /// Accept admin connections to `ctx`; returns its address.
+    async fn serve_node(ctx: Arc<DaemonContext>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(handle_websocket_connection(socket, ctx.clone(), true));
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn peers_are_compared_at_the_lower_tip() {
+        let peer = serve(synthetic_chain(12)).await;
+        let check = |local: Blockchain| {
+            let peer = peer.clone();
+            async move {
+                let mut client = crate::ws_client::WsClient::connect(&peer).await.unwrap();
+                check_peer_chain(&mut client, &peer, &Mutex::new(local))
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // Peer ahead: its block at our height against our tip
+        let behind = check(synthetic_chain(10)).await;
+        assert_eq!((behind.height, behind.compared_height), (12, 10));
+        assert!(!behind.forked);
+        // Peer behind: its tip against our block at its height
+        let ahead = check(synthetic_chain(15)).await;
+        assert_eq!((ahead.height, ahead.compared_height), (12, 12));
+        assert_eq!(ahead.peer_hash, ahead.tip_hash);
+        assert!(!ahead.forked);
+
+        let mut fork = synthetic_chain(10);
+        fork.chain[10].hash = "f".repeat(64);
+        let forked = check(fork).await;
+        assert!(forked.forked);
+        assert_eq!(forked.local_hash, "f".repeat(64));
+        assert_eq!(forked.peer_hash, behind.local_hash);
+
+        // Warned about once per fork, not once per sync round
+        let node = test_node(synthetic_chain(1));
+        {
+            let mut chains = node.peer_chains.lock();
+            assert!(chains.record(behind.clone()).is_none());
+            let warning = chains.record(forked.clone()).unwrap();
+            assert!(warning.contains(&forked.local_hash) && warning.contains(&forked.peer_hash));
+            assert!(chains.record(forked.clone()).is_none());
+            assert!(chains.record(behind).is_none());
+            assert!(chains.record(forked).is_some());
+            assert_eq!(chains.forks_detected(), 2);
+        }
+
+        let info = node
+            .call(&serde_json::json!({"method": "getpeerinfo"}), false)
+            .await;
+        assert_eq!(info["data"][0]["peer"], peer.as_str());
+        assert_eq!(info["data"][0]["forked"], true);
+        let info = node
+            .call(&serde_json::json!({"method": "getinfo"}), false)
+            .await;
+        assert_eq!(info["peer_forks"], 2);
+    }
+
+    #[tokio::test]
+    async fn lagging_node_catches_up_from_a_peer_and_streams_progress() {
+        let mut chain = Blockchain::new();
+        for _ in 0..8 {
+            let mut mined = next_block(&chain, vec![]);
+            while !crate::blockchain::hash_meets_difficulty(&mined.hash, 1) {
+                mined.nonce += 1;
+                mined.hash = Blockchain::calculate_hash(&mined);
+            }
+            assert!(chain.add_block(mined, 1));
+        }
+        let peer = serve(chain.clone()).await;
+        let mut local = chain.clone();
+        local.chain.truncate(3);
+        let node = test_node(local);
+        let mut events = node.events.subscribe();
+
+        let mut client = crate::ws_client::WsClient::connect(&peer).await.unwrap();
+        let status = check_peer_chain(&mut client, &peer, &node.blockchain)
+            .await
+            .unwrap();
+        let report = catch_up_from_peer(&node, &mut client, &status)
+            .await
+            .unwrap()
+            .expect("peer is ahead");
+        assert_eq!((report.height, report.verified), (8, 6));
+        assert_eq!(report.tip_hash, chain.chain[8].hash);
+        assert_eq!(node.blockchain.lock().chain.len(), 9);
+
+        let mut progress = Vec::new();
+        let mut new_blocks = 0;
+        while let Ok(event) = events.try_recv() {
+            match event {
+                NodeEvent::SyncProgress {
+                    peer: from,
+                    progress: p,
+                } => {
+                    assert_eq!(from, peer);
+                    progress.push(p);
+                }
+                NodeEvent::NewBlock { .. } => new_blocks += 1,
+                NodeEvent::NewTx { .. } | NodeEvent::Reorg(_) => {}
+            }
+        }
+        assert_eq!(new_blocks, 6);
+        let last = progress.last().expect("at least the final event");
+        assert_eq!((last.fetched, last.total), (6, 6));
+        let json = serde_json::to_value(NodeEvent::SyncProgress {
+            peer: peer.clone(),
+            progress: last.clone(),
+        })
+        .unwrap();
+        assert_eq!(json["event"], "syncprogress");
+
+        // Level with the peer: nothing to do
+        let status = check_peer_chain(&mut client, &peer, &node.blockchain)
+            .await
+            .unwrap();
+        assert!(
+            catch_up_from_peer(&node, &mut client, &status)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn read_only_node_refuses_submissions_and_keeps_syncing() {
+        let mut chain = Blockchain::new();
+        for _ in 0..5 {
+            let mut mined = next_block(&chain, vec![]);
+            while !crate::blockchain::hash_meets_difficulty(&mined.hash, 1) {
+                mined.nonce += 1;
+                mined.hash = Blockchain::calculate_hash(&mined);
+            }
+            assert!(chain.add_block(mined, 1));
+        }
+        let peer = serve(chain.clone()).await;
+        let mut local = chain.clone();
+        local.chain.truncate(3);
+        let mut node = test_node(local);
+        node.read_only = true;
+
+        let schema = crate::rpc::protocol_schema();
+        for name in crate::rpc::SUBMISSION_METHODS {
+            let example = schema["methods"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|m| m["name"] == *name)
+                .unwrap()["example"]
+                .clone();
+            let resp = node.call(&example, false).await;
+            assert_eq!(resp["type"], "error", "{}", name);
+            assert_eq!(resp["message"], crate::rpc::READ_ONLY, "{}", name);
+        }
+        // Even a block that would extend the tip
+        let next =
+            serde_json::json!({"method": "submitblock", "params": {"block": chain.chain[3]}});
+        assert_eq!(
+            node.call(&next, false).await["message"],
+            crate::rpc::READ_ONLY
+        );
+        assert_eq!(node.blockchain.lock().chain.len(), 3);
+
+        // Reads are served, and say why submissions won't be
+        let height = node
+            .call(&serde_json::json!({"method": "getheight"}), false)
+            .await;
+        assert_eq!(height["height"], 2);
+        let block = node
+            .call(
+                &serde_json::json!({"method": "getblock", "params": {"index": 1}}),
+                false,
+            )
+            .await;
+        assert_eq!(block["data"]["hash"], chain.chain[1].hash.as_str());
+        let info = node
+            .call(&serde_json::json!({"method": "getinfo"}), false)
+            .await;
+        assert_eq!(info["read_only"], true);
+
+        // Blocks still come in from peers
+        let mut client = crate::ws_client::WsClient::connect(&peer).await.unwrap();
+        let status = check_peer_chain(&mut client, &peer, &node.blockchain)
+            .await
+            .unwrap();
+        catch_up_from_peer(&node, &mut client, &status)
+            .await
+            .unwrap()
+            .expect("peer is ahead");
+        assert_eq!(node.blockchain.lock().chain.len(), 6);
+
+        node.read_only = false;
+        let wallet = Wallet::new().unwrap().address;
+        let stats = serde_json::json!({"method": "updatestats", "params": {"wallet": wallet, "hashrate": 10.0}});
+        assert_eq!(node.call(&stats, false).await["status"], "ok");
+    }
+
+    #[tokio::test]
+    async fn compression_is_negotiated_per_connection() {
+        let addr = serve(synthetic_chain(50)).await;
+
+        let mut compressed = crate::ws_client::WsClient::connect(&addr).await.unwrap();
+        assert!(compressed.enable_compression().await.unwrap());
+        let mut plain = crate::ws_client::WsClient::connect(&addr).await.unwrap();
+
+        for client in [&mut compressed, &mut plain] {
+            let resp = client
+                .command("getchain", serde_json::json!({}))
+                .await
+                .unwrap();
+            let bc: Blockchain = serde_json::from_value(resp["data"].clone()).unwrap();
+            assert_eq!(bc.chain.len(), 51);
+        }
+
+        // A node with it off refuses, without changing what others grant
+        let node = test_node(synthetic_chain(5));
+        node.config.lock().ws_compression = false;
+        let refusing = serve_node(Arc::new(node)).await;
+        let mut client = crate::ws_client::WsClient::connect(&refusing)
+            .await
+            .unwrap();
+        assert!(!client.enable_compression().await.unwrap());
+        let mut again = crate::ws_client::WsClient::connect(&addr).await.unwrap();
+        assert!(again.enable_compression().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn subscribers_are_told_about_new_transactions() {
+        let sender = Wallet::new().unwrap();
+        let mut bc = Blockchain::new();
+        let block = next_block(&bc, vec![coinbase(&sender.address, 1000)]);
+        assert!(bc.add_block_skip_pow(block, 1, true));
+        let addr = serve(bc).await;
+
+        let mut watcher = crate::ws_client::WsClient::connect(&addr).await.unwrap();
+        assert_eq!(watcher.subscribe().await.unwrap(), HEARTBEAT_SECS);
+
+        let tx = sender.create_signed_transaction("bob", 250).unwrap();
+        let mut other = crate::ws_client::WsClient::connect(&addr).await.unwrap();
+        let resp = other
+            .command("submittx", serde_json::json!({"tx": tx}))
+            .await
+            .unwrap();
+        assert_eq!(resp["status"], "ok");
+
+        let msg = tokio::time::timeout(std::time::Duration::from_secs(5), watcher.next_message())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg["type"], "event");
+        let event: NodeEvent = serde_json::from_value(msg).unwrap();
+        let notices = crate::watch::notices(&event, "bob");
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].txid, tx.txid());
+        assert_eq!(notices[0].status, "pending");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn slow_reader_does_not_stall_other_clients() {
+        let addr = serve(synthetic_chain(3000)).await;
+
+        // Queue up large responses and never read them: the connection's
+        // send blocks once the socket buffers fill up
+        let (mut slow, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+            .await
+            .unwrap();
+        for _ in 0..20 {
+            slow.send(Message::Text(r#"{"method":"getchain"}"#.to_string()))
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let mut client = crate::ws_client::WsClient::connect(&addr).await.unwrap();
+        for _ in 0..10 {
+            let started = std::time::Instant::now();
+            let resp = client
+                .command("getheight", serde_json::json!({}))
+                .await
+                .unwrap();
+            assert_eq!(resp["height"], 3000);
+            assert!(
+                started.elapsed() < std::time::Duration::from_millis(500),
+                "getheight took {:?}",
+                started.elapsed()
+            );
+        }
+        drop(slow);
+    }
+
+    #[tokio::test]
+    async fn blocks_on_expired_templates_are_reported_stale() {
+        let node = test_node(snapshot_chain());
+        let template = node
+            .call(&serde_json::json!({"method": "getblocktemplate"}), false)
+            .await;
+        assert_eq!(template["index"], 3);
+        assert!(template["expires_at"].as_i64().unwrap() > chrono::Utc::now().timestamp());
+        assert!(template.get("coinbase").is_none());
+
+        // Paying a farm's cold address rather than whoever asks
+        let cold = Wallet::new().unwrap().address;
+        let paid = node
+            .call(
+                &serde_json::json!({"method": "getblocktemplate", "params": {"wallet": cold}}),
+                false,
+            )
+            .await;
+        assert_eq!(paid["coinbase"]["to"], cold.as_str());
+        assert_eq!(paid["coinbase"]["amount"], paid["reward"]);
+        let bad = serde_json::json!({"method": "getblocktemplate", "params": {"wallet": "x"}});
+        assert_eq!(
+            node.call(&bad, false).await["message"],
+            "invalid wallet address"
+        );
+
+        // A template for height 2, handed out before block 2 arrived
+        let (old_id, _) = node.templates.lock().issue(2, "irrelevant", 0);
+        let late = {
+            let bc = node.blockchain.lock();
+            let mut block = bc.chain[2].clone();
+            block.prev_hash = bc.chain[1].hash.clone();
+            block.hash = "f".repeat(64);
+            block
+        };
+        let submit = |block: &Block, template_id: Option<&str>| {
+            let mut cmd = serde_json::json!({"method": "submitblock", "params": {"block": block}});
+            if let Some(id) = template_id {
+                cmd["params"]["template_id"] = id.into();
+            }
+            cmd
+        };
+        let stale = "stale: built on height 1, current height 2";
+        let resp = node.call(&submit(&late, Some(&old_id)), false).await;
+        assert_eq!(resp["status"], stale);
+        // Without a template the parent still tells us which height it was
+        let resp = node.call(&submit(&late, None), false).await;
+        assert_eq!(resp["status"], stale);
+
+        // Unknown parent is a real rejection
+        let mut bogus = late.clone();
+        bogus.prev_hash = "e".repeat(64);
+        let resp = node.call(&submit(&bogus, None), false).await;
+        assert!(resp["status"].as_str().unwrap().starts_with("rejected"));
+
+        let stats = node
+            .call(&serde_json::json!({"method": "getsubmitstats"}), false)
+            .await;
+        assert_eq!(stats["data"]["stale"], 2);
+        assert_eq!(stats["data"]["rejected"], 1);
+        assert_eq!(stats["data"]["accepted"], 0);
+    }
+
+    #[tokio::test]
+    async fn getwork_jobs_are_mined_end_to_end() {
+        use crate::ws_client::{WsClient, fetch_work, submit_work};
+        let addr = serve(Blockchain::new()).await;
+        let mut client = WsClient::connect(&addr).await.unwrap();
+        let job = fetch_work(&mut client, "04ab").await.unwrap();
+        let rival = fetch_work(&mut client, "04cd").await.unwrap();
+        assert_eq!((job.height, job.difficulty), (1, 1));
+
+        let nonce = job.search(0..u32::MAX).unwrap();
+        assert_eq!(submit_work(&mut client, &job, nonce).await.unwrap(), "ok");
+        let block = crate::ws_client::fetch_latest_block(&addr).await.unwrap();
+        assert_eq!(block.index, 1);
+        assert_eq!(
+            crate::blockchain::canonical_block_bytes(&block),
+            job.preimage(nonce)
+        );
+
+        assert_eq!(
+            submit_work(&mut client, &rival, nonce).await.unwrap(),
+            "stale: job expired, tip moved"
+        );
+        let mut forged = job.clone();
+        forged.job_id = "0".repeat(16);
+        assert_eq!(
+            submit_work(&mut client, &forged, nonce).await.unwrap(),
+            "rejected: unknown or expired job"
+        );
+
+        // The reference miner gets through a whole block on its own
+        let mined = crate::ws_client::mine_getwork(&addr, "04ab", 1)
+            .await
+            .unwrap();
+        assert_eq!(mined, vec![2]);
+    }
+
+    #[test]
+    fn blocks_are_judged_by_their_canonical_encoding() {
+        use chrono::TimeZone;
+        let bc = Blockchain::new();
+        let mined = mine_on(&bc.chain[0], vec![coinbase("04ab", 500)]);
+        let block = serde_json::to_value(&mined).unwrap();
+        let node = test_node(bc);
+        let rig = Connection::new(1, "203.0.113.9:5200");
+        let submit = |block: &serde_json::Value| -> String {
+            let cmd = serde_json::json!({"method": "submitblock", "params": {"block": block}});
+            let resp: serde_json::Value =
+                serde_json::from_str(&crate::rpc::dispatch(&node, &cmd.to_string(), false, &rig))
+                    .unwrap();
+            resp["status"].as_str().unwrap().to_string()
+        };
+
+        // The hash covers the decoded block, so JSON spelling it differently
+        // is still that block
+        let mut extra = block.clone();
+        extra["junk"] = 1.into();
+        let mut offset = block.clone();
+        let ts = block["timestamp"].as_str().unwrap();
+        let shifted = chrono::DateTime::parse_from_rfc3339(ts)
+            .unwrap()
+            .with_timezone(&chrono::FixedOffset::east_opt(7200).unwrap());
+        offset["timestamp"] = shifted.to_rfc3339().into();
+        let mut null_expiry = block.clone();
+        null_expiry["transactions"][0]["expires_at_height"] = serde_json::Value::Null;
+        assert_eq!(submit(&offset), "ok");
+        for variant in [extra, null_expiry, block] {
+            assert_eq!(submit(&variant), "ok, duplicate");
+        }
+        assert_eq!(
+            crate::blockchain::canonical_block_bytes(&node.blockchain.lock().chain[1]),
+            crate::blockchain::canonical_block_bytes(&mined)
+        );
+
+        // A year past 9999 decodes here, but its canonical encoding isn't
+        // RFC 3339: refused, without counting against the sender
+        let mut far = mine_on(&mined, vec![coinbase("04ab", 500)]);
+        far.timestamp = chrono::Utc.with_ymd_and_hms(10000, 1, 1, 0, 0, 0).unwrap();
+        far.nonce = 0;
+        loop {
+            far.hash = Blockchain::calculate_hash(&far);
+            if crate::blockchain::hash_meets_difficulty(&far.hash, 1) {
+                break;
+            }
+            far.nonce += 1;
+        }
+        assert_eq!(
+            submit(&serde_json::to_value(&far).unwrap()),
+            "rejected: non-canonical block encoding"
+        );
+        assert!(node.bans.lock().scores.is_empty());
+    }
+
+    #[test]
+    fn blocks_must_claim_exactly_the_scheduled_difficulty() {
+        let bc = Blockchain::new();
+        let node = test_node(bc.clone());
+        let rig = Connection::new(1, "203.0.113.9:5200");
+        let submit = |block: &Block| -> String {
+            let cmd = serde_json::json!({"method": "submitblock", "params": {"block": block}});
+            let resp: serde_json::Value =
+                serde_json::from_str(&crate::rpc::dispatch(&node, &cmd.to_string(), false, &rig))
+                    .unwrap();
+            resp["status"].as_str().unwrap().to_string()
+        };
+
+        // Work for difficulty 1, which is all the schedule asks, but a claim
+        // of 200 would count as 16^200 hashes and set the next difficulty
+        let mut inflated = mine_on(&bc.chain[0], vec![coinbase("04ab", 500)]);
+        inflated.difficulty = 200;
+        loop {
+            inflated.hash = Blockchain::calculate_hash(&inflated);
+            if crate::blockchain::hash_meets_difficulty(&inflated.hash, 1) {
+                break;
+            }
+            inflated.nonce += 1;
+        }
+        assert_eq!(
+            submit(&inflated),
+            "rejected: claims difficulty 200, the schedule requires 1"
+        );
+        assert_eq!(node.blockchain.lock().chain.len(), 1);
+
+        let honest = mine_on(&bc.chain[0], vec![coinbase("04ab", 500)]);
+        assert_eq!(submit(&honest), "ok");
+        assert_eq!(node.blockchain.lock().get_dynamic_difficulty(), 1);
+    }
+
+    #[tokio::test]
+    async fn resubmitted_blocks_are_duplicates_not_revalidated() {
+        let bc = Blockchain::new();
+        let mut mined = next_block(&bc, vec![coinbase("04ab", 500)]);
+        while !crate::blockchain::hash_meets_difficulty(&mined.hash, 1) {
+            mined.nonce += 1;
+            mined.hash = Blockchain::calculate_hash(&mined);
+        }
+        let node = test_node(bc);
+        let submit = |block: &crate::blockchain::Block| serde_json::json!({"method": "submitblock", "params": {"block": block}});
+
+        let resp = node.call(&submit(&mined), false).await;
+        assert_eq!(resp["status"], "ok");
+        let resp = node.call(&submit(&mined), false).await;
+        assert_eq!(resp["status"], "ok, duplicate");
+        // Answered from the hash alone: the body is never looked at
+        let mut gutted = mined.clone();
+        gutted.transactions.clear();
+        gutted.nonce = 0;
+        let resp = node.call(&submit(&gutted), false).await;
+        assert_eq!(resp["status"], "ok, duplicate");
+        assert_eq!(node.blockchain.lock().chain.len(), 2);
+
+        let stats = node
+            .call(&serde_json::json!({"method": "getsubmitstats"}), false)
+            .await;
+        assert_eq!(stats["data"]["accepted"], 1);
+        assert_eq!(stats["data"]["duplicate"], 2);
+    }
+
+    #[test]
+    fn historical_balances_match_a_full_replay() {
+        // Alice mines every block; every 7th block she pays Bob 100
+        let mut bc = Blockchain::new();
+        for i in 1..=2500u64 {
+            let mut txs = vec![coinbase("alice", 500)];
+            if i % 7 == 0 {
+                txs.push(Transaction {
+                    from: "alice".to_string(),
+                    pub_key: String::new(),
+                    to: "bob".to_string(),
+                    amount: 100,
+                    signature: String::new(),
+                    expires_at_height: None,
+                });
+            }
+            bc.chain.push(crate::blockchain::Block {
+                index: i,
+                timestamp: chrono::Utc::now(),
+                transactions: txs,
+                prev_hash: bc.chain.last().unwrap().hash.clone(),
+                hash: format!("{:064x}", i),
+                nonce: 0,
+                difficulty: 1,
+            });
+        }
+        let mut index = BalanceIndex::new();
+        for height in [0, 1, 7, 999, 1000, 1001, 2000, 2499, 2500] {
+            let paid = height as i64 / 7 * 100;
+            assert_eq!(index.balance_at(&bc, "bob", height), paid, "{height}");
+            assert_eq!(
+                index.balance_at(&bc, "ALICE ", height),
+                height as i64 * 500 - paid
+            );
+            let replayed = Blockchain {
+                chain: bc.chain[..=height as usize].to_vec(),
+                target_block_time: bc.target_block_time,
+            };
+            assert_eq!(
+                index.balance_at(&bc, "bob", height),
+                replayed.balances().get("bob").copied().unwrap_or(0)
+            );
+        }
+        assert_eq!(
+            index.snapshots.keys().copied().collect::<Vec<_>>(),
+            vec![0, 1000, 2000]
+        );
+
+        // New blocks are folded in; a replaced chain starts the index over
+        bc.chain.truncate(1500);
+        bc.chain[1499].hash = "replaced".to_string();
+        assert_eq!(index.balance_at(&bc, "bob", 1499), 214 * 100);
+        assert_eq!(index.snapshots.len(), 2);
+    }
+
+    #[test]
+    fn seen_blocks_forget_the_oldest_past_the_cap() {
+        let mut seen = SeenBlocks::new();
+        for i in 0..SEEN_BLOCKS_CAP + 1 {
+            seen.insert(&i.to_string());
+        }
+        seen.insert("1");
+        assert!(!seen.contains("0"));
+        assert!(seen.contains("1"));
+        assert!(seen.contains(&SEEN_BLOCKS_CAP.to_string()));
+    }
+
+    #[tokio::test]
+    async fn getinfo_counts_calls_per_method() {
+        let node = test_node(Blockchain::new());
+        for _ in 0..3 {
+            node.call(&serde_json::json!({"method": "getheight"}), false)
+                .await;
+        }
+        node.call(&serde_json::json!({"method": "getchain"}), false)
+            .await;
+        node.call(&serde_json::json!({"method": "nosuchmethod"}), false)
+            .await;
+
+        let info = node
+            .call(&serde_json::json!({"method": "getinfo"}), false)
+            .await;
+        assert_eq!(info["height"], 0);
+        assert_eq!(info["network"], "owonero");
+        assert_eq!(info["build"]["version"], env!("CARGO_PKG_VERSION"));
+        let methods = &info["methods"];
+        assert_eq!(methods["getheight"]["count"], 3);
+        assert_eq!(methods["getchain"]["count"], 1);
+        assert!(methods["getchain"]["max_ms"].as_f64().unwrap() >= 0.0);
+        assert!(methods.get("nosuchmethod").is_none());
+        // getinfo is recorded after it replies
+        assert!(methods.get("getinfo").is_none());
+        let again = node
+            .call(&serde_json::json!({"method": "getinfo"}), false)
+            .await;
+        assert_eq!(again["methods"]["getinfo"]["count"], 1);
+    }
+
+    #[tokio::test]
+    async fn miner_footer_reads_the_node_overview_from_getinfo() {
+        let chain = synthetic_chain(3);
+        let tip_timestamp = chain.chain[3].timestamp.timestamp();
+        let addr = serve(chain).await;
+        let held = crate::ws_client::WsClient::connect(&addr).await.unwrap();
+        let info = crate::ws_client::fetch_info(&addr).await.unwrap();
+        // The held connection and fetch_info's own
+        assert_eq!(info["connections"], 2);
+        drop(held);
+
+        let status: crate::miner::NodeStatus = serde_json::from_value(info).unwrap();
+        assert_eq!(status.tip_timestamp, tip_timestamp);
+        assert_eq!(
+            status.summary(tip_timestamp + 75),
+            "Node: 2 connections · 0 in mempool · last block 1m 15s ago · 0 peers"
+        );
+        // Older nodes without the newer fields still show
+        let old: crate::miner::NodeStatus =
+            serde_json::from_value(serde_json::json!({"peers": 1, "mempool_size": 4})).unwrap();
+        assert_eq!((old.connections, old.mempool_size), (0, 4));
+    }
+
+    #[tokio::test]
+    async fn mined_blocks_are_paginated_newest_first() {
+        let miner = Wallet::new().unwrap();
+        let other = Wallet::new().unwrap();
+        let mut bc = Blockchain::new();
+        for i in 0..7 {
+            let to = if i % 3 == 2 { &other } else { &miner };
+            let block = next_block(&bc, vec![coinbase(&to.address, 500)]);
+            assert!(bc.add_block_skip_pow(block, 1, true));
+        }
+        let node = test_node(bc);
+
+        let mut heights = Vec::new();
+        let mut before: Option<u64> = None;
+        loop {
+            let resp = node
+                .call(
+                    &serde_json::json!({"method": "getminedblocks", "params": {
+                        "address": miner.address, "limit": 2, "before": before,
+                    }}),
+                    false,
+                )
+                .await;
+            let page: MinedBlocksPage = serde_json::from_value(resp["data"].clone()).unwrap();
+            assert_eq!(page.total, 5);
+            assert!(page.blocks.iter().all(|b| b.reward == 500));
+            heights.extend(page.blocks.iter().map(|b| b.height));
+            match page.next_before {
+                Some(next) => before = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(heights, vec![7, 5, 4, 2, 1]);
+    }
+
+    /// Deterministic node for response snapshots: fixed timestamps and
+    /// hashes, one coinbase per block.
+    fn snapshot_chain() -> Blockchain {
+        let mut bc = Blockchain::new();
+        for i in 1..=2u64 {
+            let prev_hash = bc.chain.last().unwrap().hash.clone();
+            bc.chain.push(Block {
+                index: i,
+                timestamp: "2025-10-12T00:00:00Z".parse().unwrap(),
+                transactions: vec![coinbase("04abcd", 500)],
+                prev_hash,
+                hash: format!("{:064}", i),
+                nonce: i as u32,
+                difficulty: 1,
+            });
+        }
+        bc
+    }
+
+    /// Request, admin flag, and the exact response the daemon gave before
+    /// commands were split into `rpc::METHODS`. Errors additionally carry
+    /// the method, and a request `id` is echoed back.
+    fn snapshot_cases() -> Vec<(&'static str, bool, &'static str)> {
+        vec![
+            (
+                r#"{not json"#,
+                false,
+                r#"{"message":"invalid JSON","type":"error"}"#,
+            ),
+            (
+                r#"{"params":{}}"#,
+                false,
+                r#"{"hint":"call getcapabilities for the list of supported methods","message":"unknown method","method":"unknown","protocol_version":1,"type":"error"}"#,
+            ),
+            (
+                r#"{"method":"getheight"}"#,
+                false,
+                r#"{"height":2,"method":"getheight","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getlatest"}"#,
+                false,
+                r#"{"data":{"difficulty":1,"hash":"0000000000000000000000000000000000000000000000000000000000000002","index":2,"nonce":2,"prev_hash":"0000000000000000000000000000000000000000000000000000000000000001","timestamp":"2025-10-12T00:00:00Z","transactions":[{"amount":500,"from":"coinbase","pub_key":"","signature":"","to":"04abcd"}]},"method":"getlatest","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getblock","params":{"index":1}}"#,
+                false,
+                r#"{"data":{"difficulty":1,"hash":"0000000000000000000000000000000000000000000000000000000000000001","index":1,"nonce":1,"prev_hash":"ef2397a2400cded7f139df4a49bb2141d4fda95aedab31e22c03a0e21d70a8b3","timestamp":"2025-10-12T00:00:00Z","transactions":[{"amount":500,"from":"coinbase","pub_key":"","signature":"","to":"04abcd"}]},"method":"getblock","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getblock","index":2}"#,
+                false,
+                r#"{"data":{"difficulty":1,"hash":"0000000000000000000000000000000000000000000000000000000000000002","index":2,"nonce":2,"prev_hash":"0000000000000000000000000000000000000000000000000000000000000001","timestamp":"2025-10-12T00:00:00Z","transactions":[{"amount":500,"from":"coinbase","pub_key":"","signature":"","to":"04abcd"}]},"method":"getblock","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getblock","params":{"index":99}}"#,
+                false,
+                r#"{"message":"block not found","method":"getblock","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"getchain"}"#,
+                false,
+                r#"{"data":{"chain":[{"difficulty":1,"hash":"ef2397a2400cded7f139df4a49bb2141d4fda95aedab31e22c03a0e21d70a8b3","index":0,"nonce":0,"prev_hash":"","timestamp":"2025-10-11T00:00:00Z","transactions":[{"amount":0,"from":"genesis","pub_key":"","signature":"","to":"network"}]},{"difficulty":1,"hash":"0000000000000000000000000000000000000000000000000000000000000001","index":1,"nonce":1,"prev_hash":"ef2397a2400cded7f139df4a49bb2141d4fda95aedab31e22c03a0e21d70a8b3","timestamp":"2025-10-12T00:00:00Z","transactions":[{"amount":500,"from":"coinbase","pub_key":"","signature":"","to":"04abcd"}]},{"difficulty":1,"hash":"0000000000000000000000000000000000000000000000000000000000000002","index":2,"nonce":2,"prev_hash":"0000000000000000000000000000000000000000000000000000000000000001","timestamp":"2025-10-12T00:00:00Z","transactions":[{"amount":500,"from":"coinbase","pub_key":"","signature":"","to":"04abcd"}]}],"target_block_time":30},"method":"getchain","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getmempool"}"#,
+                false,
+                r#"{"data":[],"method":"getmempool","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getpeers"}"#,
+                false,
+                r#"{"data":[],"method":"getpeers","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getcapabilities"}"#,
+                false,
+                r#"{"data":{"methods":["getcapabilities","getchain","getlatest","getheight","getblock","getmempool","submittx","submitblock","gettxstatus","getpeers","addpeer","getpeerinfo","importmempool","getmempooljournal","setcompression","getminedblocks","getbalance","getbalanceat","gettiphistory","getblocktemplate","getwork","submitwork","getsubmitstats","updatestats","submitshare","getinfo","subscribe","hello","getconnections","reloadblocklist","reloadconfig","getbans","getschema","getsupply","getburned","handshake","getblocks","getheaders"],"protocol_version":1},"method":"getcapabilities","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"gettxstatus"}"#,
+                false,
+                r#"{"message":"missing txid field","method":"gettxstatus","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"gettxstatus","params":{"txid":"ab12"}}"#,
+                false,
+                r#"{"data":{"status":"unknown"},"method":"gettxstatus","txid":"ab12","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"submittx"}"#,
+                false,
+                r#"{"message":"missing tx field","method":"submittx","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"submittx","params":{"tx":{"from":1}}}"#,
+                false,
+                r#"{"message":"failed to parse transaction","method":"submittx","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"submittx","params":{"tx":{"from":"04abcd","pub_key":"04abcd","to":"x","amount":5,"signature":"00"}}}"#,
+                false,
+                r#"{"message":"rejected: invalid signature","method":"submittx","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"submitblock"}"#,
+                false,
+                r#"{"message":"missing block field","method":"submitblock","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"submitblock","params":{"block":7}}"#,
+                false,
+                r#"{"message":"failed to parse block","method":"submitblock","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"submitblock","params":{"block":{"index":1,"timestamp":"2025-10-12T00:00:00Z","transactions":[],"prev_hash":"","hash":"","nonce":0,"difficulty":1}}}"#,
+                false,
+                r#"{"method":"submitblock","status":"rejected: block index 1 already exists (current height 2)","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getminedblocks"}"#,
+                false,
+                r#"{"message":"missing address field","method":"getminedblocks","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"getminedblocks","params":{"address":"04ABCD","limit":1}}"#,
+                false,
+                r#"{"data":{"blocks":[{"hash":"0000000000000000000000000000000000000000000000000000000000000002","height":2,"reward":500,"timestamp":1760227200}],"next_before":2,"total":2},"method":"getminedblocks","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"gettiphistory","params":{"depth":2}}"#,
+                false,
+                r#"{"data":[{"hash":"0000000000000000000000000000000000000000000000000000000000000001","index":1},{"hash":"0000000000000000000000000000000000000000000000000000000000000002","index":2}],"method":"gettiphistory","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getbalance","params":{"address":"04abcd"}}"#,
+                false,
+                r#"{"address":"04abcd","balance":1000,"balance_owe":1.0,"height":2,"method":"getbalance","tip":2,"type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getbalance","params":{"address":"04abcd","include_pending":true}}"#,
+                false,
+                r#"{"address":"04abcd","available":1000,"available_owe":1.0,"balance":1000,"balance_owe":1.0,"height":2,"method":"getbalance","pending_out":0,"tip":2,"type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getbalance","params":{"address":"04abcd","height":1,"include_pending":true}}"#,
+                false,
+                r#"{"message":"include_pending only applies at the tip","method":"getbalance","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"getbalance","params":{"address":"04abcd","height":1}}"#,
+                false,
+                r#"{"address":"04abcd","balance":500,"balance_owe":0.5,"height":1,"method":"getbalance","tip":2,"type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getbalance","params":{"address":"04abcd","height":9}}"#,
+                false,
+                r#"{"message":"height above chain tip","method":"getbalance","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"getbalance"}"#,
+                false,
+                r#"{"message":"missing address field","method":"getbalance","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"getbalanceat","params":{"address":"04abcd","height":1}}"#,
+                false,
+                r#"{"address":"04abcd","balance":500,"balance_owe":0.5,"height":1,"method":"getbalanceat","tip":2,"type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getbalanceat","params":{"address":"04abcd"}}"#,
+                false,
+                r#"{"message":"missing height field","method":"getbalanceat","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"importmempool","params":{"txs":[]}}"#,
+                false,
+                r#"{"message":"importmempool is restricted to local admin connections","method":"importmempool","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"importmempool"}"#,
+                true,
+                r#"{"message":"missing txs array","method":"importmempool","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"importmempool","params":{"txs":[{"bad":1}]}}"#,
+                true,
+                r#"{"data":[{"reason":"failed to parse transaction","status":"rejected"}],"method":"importmempool","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"devmineblocks","params":{"to":"x"}}"#,
+                false,
+                r#"{"message":"devmineblocks refused: dev_mode is off on this node","method":"devmineblocks","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"devfaucet"}"#,
+                false,
+                r#"{"message":"devfaucet refused: dev_mode is off on this node","method":"devfaucet","type":"error"}"#,
+            ),
+            (
+                r#"{"method":"frobnicate"}"#,
+                false,
+                r#"{"hint":"call getcapabilities for the list of supported methods","message":"unknown method","method":"frobnicate","protocol_version":1,"type":"error"}"#,
+            ),
+            (
+                r#"{"method":"getheight","id":7}"#,
+                false,
+                r#"{"height":2,"id":7,"method":"getheight","type":"response"}"#,
+            ),
+            (
+                r#"{"method":"getblock","params":{"index":99},"id":"x"}"#,
+                false,
+                r#"{"id":"x","message":"block not found","method":"getblock","type":"error"}"#,
+            ),
+        ]
+    }
+
+    #[tokio::test]
+    async fn responses_match_pre_refactor_snapshots() {
+        let node = test_node(snapshot_chain());
+        for (req, admin, expected) in snapshot_cases() {
+            let resp = crate::rpc::dispatch(&node, req, admin, &Connection::new(0, "test"));
+            assert_eq!(resp, expected, "request {}", req);
+        }
+    }
+
+    #[tokio::test]
+    async fn node_switches_to_a_taller_peer_branch_and_keeps_orphaned_txs() {
+        let sender = Wallet::new().unwrap();
+        let bob = Wallet::new().unwrap().address;
+        let mine = |bc: &mut Blockchain, txs: Vec<Transaction>| {
+            let mut mined = next_block(bc, txs);
+            while !crate::blockchain::hash_meets_difficulty(&mined.hash, 1) {
+                mined.nonce += 1;
+                mined.hash = Blockchain::calculate_hash(&mined);
+            }
+            assert!(bc.add_block(mined, 1));
+        };
+        // Both branches share block 1, which funds the sender
+        let mut shared = Blockchain::new();
+        mine(&mut shared, vec![coinbase(&sender.address, 1000)]);
+
+        // Ours: two blocks, one paying bob. The peer's: three other blocks
+        let mut ours = shared.clone();
+        let payment = sender.create_signed_transaction(&bob, 100).unwrap();
+        mine(&mut ours, vec![coinbase("04aa", 500), payment.clone()]);
+        mine(&mut ours, vec![coinbase("04aa", 500)]);
+        let mut theirs = shared.clone();
+        for _ in 0..3 {
+            mine(&mut theirs, vec![coinbase("04bb", 500)]);
+        }
+
+        let node = test_node(ours.clone());
+        let peer = serve(theirs.clone()).await;
+        let mut client = crate::ws_client::WsClient::connect(&peer).await.unwrap();
+        let status = check_peer_chain(&mut client, &peer, &node.blockchain)
+            .await
+            .unwrap();
+        assert!(status.forked);
+        // Not simply ahead on our chain, so plain catch-up leaves it alone
+        assert!(
+            catch_up_from_peer(&node, &mut client, &status)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let fork = adopt_peer_fork(&node, &mut client, &status).await.unwrap();
+        assert_eq!(fork, Some(2));
+        let hashes = |bc: &Blockchain| bc.chain.iter().map(|b| b.hash.clone()).collect::<Vec<_>>();
+        assert_eq!(hashes(&node.blockchain.lock()), hashes(&theirs));
+        assert_eq!(
+            hashes(&Blockchain::load_from_file(&node.chain_path).unwrap()),
+            hashes(&theirs)
+        );
+        // Bob's payment waits to be mined again; our blocks are backed up
+        let pending: Vec<String> = node.mempool.lock().iter().map(|t| t.txid()).collect();
+        assert_eq!(pending, vec![payment.txid()]);
+        let backups: Vec<_> = std::fs::read_dir(node.chain_path.with_file_name("backups"))
+            .unwrap()
+            .collect();
+        assert_eq!(backups.len(), 1);
+
+        // Same chain now: nothing to switch to
+        let status = check_peer_chain(&mut client, &peer, &node.blockchain)
+            .await
+            .unwrap();
+        assert_eq!(
+            adopt_peer_fork(&node, &mut client, &status).await.unwrap(),
+            None
+        );
+        // A shorter branch never replaces ours
+        let shorter = test_node(ours.clone());
+        let status = check_peer_chain(&mut client, &peer, &shorter.blockchain)
+            .await
+            .unwrap();
+        let mut taller = ours.clone();
+        mine(&mut taller, vec![coinbase("04aa", 500)]);
+        mine(&mut taller, vec![coinbase("04aa", 500)]);
+        *shorter.blockchain.lock() = taller.clone();
+        assert_eq!(
+            adopt_peer_fork(&shorter, &mut client, &status)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(hashes(&shorter.blockchain.lock()), hashes(&taller));
+    }
+
+    #[tokio::test]
+    async fn peer_branches_claiming_more_work_than_the_schedule_are_refused() {
+        let mine = |bc: &mut Blockchain, claim: u32| {
+            let mut mined = next_block(bc, vec![coinbase("04bb", 500)]);
+            mined.difficulty = claim;
+            loop {
+                mined.hash = Blockchain::calculate_hash(&mined);
+                if crate::blockchain::hash_meets_difficulty(&mined.hash, 1) {
+                    break;
+                }
+                mined.nonce += 1;
+            }
+            bc.chain.push(mined);
+        };
+        let mut shared = Blockchain::new();
+        mine(&mut shared, 1);
+        let mut ours = shared.clone();
+        for _ in 0..3 {
+            mine(&mut ours, 1);
+        }
+        // One block taller, each block only doing difficulty-1 work but
+        // claiming 6: a million times our work on paper
+        let mut theirs = shared.clone();
+        for _ in 0..4 {
+            mine(&mut theirs, 6);
+        }
+        assert!(ours.outweighed_by(2, &theirs.chain[2..]));
+
+        let peer = serve(theirs).await;
+        for dev_mode in [false, true] {
+            let mut node = test_node(ours.clone());
+            node.dev_mode = dev_mode;
+            let mut client = crate::ws_client::WsClient::connect(&peer).await.unwrap();
+            let status = check_peer_chain(&mut client, &peer, &node.blockchain)
+                .await
+                .unwrap();
+            assert!(status.forked);
+            let err = adopt_peer_fork(&node, &mut client, &status)
+                .await
+                .unwrap_err()
+                .to_string();
+            assert!(
+                err.contains("block 2 claims difficulty 6, the schedule requires 1"),
+                "{}",
+                err
+            );
+            assert_eq!(
+                node.blockchain.lock().chain.last().unwrap().hash,
+                ours.chain.last().unwrap().hash
+            );
+        }
+    }
+
+    /// Mine the next block on `node`'s tip from its mempool, at the
+    /// scheduled difficulty, and submit it there.
+    async fn mine_next(node: &DaemonContext, miner: &str) -> String {
+        let block = {
+            let bc = node.blockchain.lock();
+            let mut txs = vec![coinbase(miner, 500)];
+            txs.extend(node.mempool.lock().iter().cloned());
+            let mut block = next_block(&bc, txs);
+            block.difficulty = bc.get_dynamic_difficulty();
+            while !crate::blockchain::hash_meets_difficulty(&block.hash, block.difficulty) {
+                block.nonce += 1;
+                block.hash = Blockchain::calculate_hash(&block);
+            }
+            block
+        };
+        let resp = node
+            .call(
+                &serde_json::json!({"method": "submitblock", "params": {"block": block}}),
+                false,
+            )
+            .await;
+        resp["status"].as_str().unwrap_or_default().to_string()
+    }
+
+    /// One sync round of each node with the other, over WebSocket.
+    async fn sync_pair(nodes: &[(Arc<DaemonContext>, String)]) {
+        for (node, (_, peer)) in [(&nodes[0].0, &nodes[1]), (&nodes[1].0, &nodes[0])] {
+            let mut client = crate::ws_client::WsClient::connect(peer).await.unwrap();
+            sync_with_peer(node, peer, &mut client, false).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn two_peered_nodes_mining_at_once_converge_without_losing_transactions() {
+        let sender = Wallet::new().unwrap();
+        let funded = funded_chain(&sender, 1000).lock().clone();
+        let mut nodes = Vec::new();
+        for _ in 0..2 {
+            let node = Arc::new(test_node(funded.clone()));
+            let addr = serve_node(node.clone()).await;
+            nodes.push((node, addr));
+        }
+        nodes[0].0.peers.add_peer(nodes[1].1.clone());
+        nodes[1].0.peers.add_peer(nodes[0].1.clone());
+        assert_ne!(nodes[0].0.chain_path, nodes[1].0.chain_path);
+
+        let payment = sender.create_signed_transaction("04bob", 100).unwrap();
+        let submitted = nodes[0]
+            .0
+            .call(
+                &serde_json::json!({"method": "submittx", "params": {"tx": payment}}),
+                false,
+            )
+            .await;
+        assert_eq!(submitted["status"], "ok", "{}", submitted);
+
+        let tip = |i: usize| {
+            nodes[i]
+                .0
+                .blockchain
+                .lock()
+                .chain
+                .last()
+                .unwrap()
+                .hash
+                .clone()
+        };
+        let converged = tokio::time::timeout(std::time::Duration::from_secs(60), async {
+            for round in 0..4 {
+                // Both find a block at the same height: a fork
+                for (i, (node, _)) in nodes.iter().enumerate() {
+                    assert_eq!(mine_next(node, &format!("04miner{}", i)).await, "ok");
+                }
+                assert_ne!(tip(0), tip(1));
+                // Equal work keeps each on its own branch until one pulls
+                // ahead; the second node wins the first race, so the block
+                // with the payment is dropped from the first node's chain
+                let mut breaker = (round + 1) % 2;
+                loop {
+                    sync_pair(&nodes).await;
+                    if tip(0) == tip(1) {
+                        break;
+                    }
+                    let (node, _) = &nodes[breaker];
+                    assert_eq!(mine_next(node, &format!("04miner{}", breaker)).await, "ok");
+                    breaker = 1 - breaker;
+                }
+            }
+        })
+        .await;
+        assert!(converged.is_ok(), "nodes did not converge in time");
+
+        // The payment went back to a mempool and was mined again, once
+        for (node, _) in &nodes {
+            let bc = node.blockchain.lock();
+            let confirmed = bc
+                .chain
+                .iter()
+                .flat_map(|b| &b.transactions)
+                .filter(|t| t.txid() == payment.txid())
+                .count();
+            assert_eq!(confirmed, 1);
+            assert!(
+                node.mempool
+                    .lock()
+                    .iter()
+                    .all(|t| t.txid() != payment.txid())
+            );
+            assert_eq!(
+                Blockchain::load_from_file(&node.chain_path)
+                    .unwrap()
+                    .chain
+                    .last()
+                    .unwrap()
+                    .hash,
+                bc.chain.last().unwrap().hash
+            );
+        }
+        let reorgs: u64 = nodes
+            .iter()
+            .map(|(node, _)| node.reorgs.status()["performed"].as_u64().unwrap())
+            .sum();
+        assert!(reorgs >= 4, "{}", reorgs);
+    }
+
+    #[tokio::test]
+    async fn reorgs_up_to_max_reorg_depth_are_performed_and_deeper_ones_refused() {
+        let mine = |bc: &mut Blockchain, miner: &str| {
+            let mut mined = next_block(bc, vec![coinbase(miner, 500)]);
+            while !crate::blockchain::hash_meets_difficulty(&mined.hash, 1) {
+                mined.nonce += 1;
+                mined.hash = Blockchain::calculate_hash(&mined);
+            }
+            assert!(bc.add_block(mined, 1));
+        };
+        let hashes = |bc: &Blockchain| bc.chain.iter().map(|b| b.hash.clone()).collect::<Vec<_>>();
+        let mut shared = Blockchain::new();
+        mine(&mut shared, "04cc");
+
+        // Our branch is `depth` blocks past the shared one, the peer's one
+        // more; the limit is 2
+        for depth in 1..=3u64 {
+            let mut ours = shared.clone();
+            let mut theirs = shared.clone();
+            for _ in 0..depth {
+                mine(&mut ours, "04aa");
+            }
+            for _ in 0..=depth {
+                mine(&mut theirs, "04bb");
+            }
+            let mut node = test_node(ours.clone());
+            node.reorgs = Reorgs::new(2);
+            let logged = Arc::new(Mutex::new(Vec::<String>::new()));
+            let sink = logged.clone();
+            node.log = Box::new(move |line| sink.lock().push(line.to_string()));
+            let mut events = node.events.subscribe();
+            let peer = serve(theirs.clone()).await;
+            let mut client = crate::ws_client::WsClient::connect(&peer).await.unwrap();
+
+            let status = check_peer_chain(&mut client, &peer, &node.blockchain)
+                .await
+                .unwrap();
+            let fork = adopt_peer_fork(&node, &mut client, &status).await.unwrap();
+            let orphaned = hashes(&ours)[2..].to_vec();
+            let expected = ReorgRecord {
+                timestamp: 0,
+                peer: peer.clone(),
+                depth,
+                fork_height: 1,
+                old_tip: ours.chain.last().unwrap().hash.clone(),
+                new_height: depth + 2,
+                new_tip: theirs.chain.last().unwrap().hash.clone(),
+                orphaned,
+                refused: depth > 2,
+            };
+            let last = node.reorgs.last().expect("reorg recorded");
+            assert_eq!(
+                ReorgRecord {
+                    timestamp: 0,
+                    ..last.clone()
+                },
+                expected,
+                "depth {}",
+                depth
+            );
+            let info = crate::rpc::dispatch(
+                &node,
+                r#"{"method":"getinfo"}"#,
+                true,
+                &Connection::new(0, "test"),
+            );
+            let info: serde_json::Value = serde_json::from_str(&info).unwrap();
+            assert_eq!(info["last_reorg"], serde_json::to_value(&last).unwrap());
+            assert_eq!(info["max_reorg_depth"], 2);
+            let reorg_events: Vec<ReorgRecord> = std::iter::from_fn(|| events.try_recv().ok())
+                .filter_map(|e| match e {
+                    NodeEvent::Reorg(r) => Some(r),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(reorg_events, vec![last]);
+
+            if depth <= 2 {
+                assert_eq!(fork, Some(2));
+                assert_eq!(hashes(&node.blockchain.lock()), hashes(&theirs));
+                assert_eq!(info["reorgs"]["performed"], 1);
+                assert!(
+                    logged
+                        .lock()
+                        .iter()
+                        .any(|l| l.contains("WARNING: reorg dropped")
+                            && l.contains(&expected.orphaned.join(", ")))
+                );
+            } else {
+                assert_eq!(fork, None);
+                assert_eq!(hashes(&node.blockchain.lock()), hashes(&ours));
+                assert!(!node.chain_path.exists());
+                assert_eq!(info["reorgs"]["refused"], 1);
+                // Every sync round sees the same branch; it is reported once
+                let status = check_peer_chain(&mut client, &peer, &node.blockchain)
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    adopt_peer_fork(&node, &mut client, &status).await.unwrap(),
+                    None
+                );
+                let critical: Vec<String> = logged
+                    .lock()
+                    .iter()
+                    .filter(|l| l.contains("CRITICAL"))
+                    .cloned()
+                    .collect();
+                assert_eq!(critical.len(), 1, "{:?}", critical);
+                assert!(critical[0].contains(&expected.old_tip));
+                assert!(critical[0].contains(&expected.new_tip));
+                assert!(critical[0].contains("chain adopt"));
+                assert!(
+                    std::iter::from_fn(|| events.try_recv().ok())
+                        .all(|e| !matches!(e, NodeEvent::Reorg(_)))
+                );
+                assert_eq!(node.reorgs.status()["refused"], 1);
+            }
+        }
+    }
+
+    #[test]
+    fn submitted_side_branches_take_over_once_they_hold_more_work() {
+        let mine = mine_on;
+        let hashes = |bc: &Blockchain| bc.chain.iter().map(|b| b.hash.clone()).collect::<Vec<_>>();
+        let sender = Wallet::new().unwrap();
+        let mut ours = funded_chain(&sender, 1000).lock().clone();
+        let shared = ours.chain[1].clone();
+        let tx = sender.create_signed_transaction("04bob", 10).unwrap();
+        let ours_2 = mine(&shared, vec![coinbase("04aa", 500), tx.clone()]);
+        assert!(ours.add_block(ours_2.clone(), 1));
+        let mut node = test_node(ours.clone());
+        node.log = Box::new(|_| {});
+        let mut events = node.events.subscribe();
+        let rig = Connection::new(1, "203.0.113.9:5200");
+        let submit = |block: &Block| -> String {
+            let cmd = serde_json::json!({"method": "submitblock", "params": {"block": block}});
+            let resp: serde_json::Value =
+                serde_json::from_str(&crate::rpc::dispatch(&node, &cmd.to_string(), false, &rig))
+                    .unwrap();
+            resp["status"].as_str().unwrap().to_string()
+        };
+
+        // Two miners found height 2; theirs came second and waits
+        let theirs_2 = mine(&shared, vec![coinbase("04bb", 500)]);
+        assert_eq!(
+            submit(&theirs_2),
+            "stale: built on height 1, current height 2"
+        );
+        assert_eq!(hashes(&node.blockchain.lock()), hashes(&ours));
+
+        // A block on their side makes it the branch with more work
+        let theirs_3 = mine(&theirs_2, vec![coinbase("04bb", 500)]);
+        assert_eq!(submit(&theirs_3), "ok");
+        let tips = |bc: &Blockchain| hashes(bc)[2..].to_vec();
+        assert_eq!(
+            tips(&node.blockchain.lock()),
+            [theirs_2.hash.clone(), theirs_3.hash.clone()]
+        );
+        let reorg = std::iter::from_fn(|| events.try_recv().ok())
+            .find_map(|e| match e {
+                NodeEvent::Reorg(r) => Some(r),
+                _ => None,
+            })
+            .expect("reorg published");
+        assert_eq!(reorg.peer, "203.0.113.9:5200");
+        assert_eq!(reorg.orphaned, vec![ours_2.hash.clone()]);
+        // The dropped block's transaction is pending again
+        assert!(node.mempool.lock().iter().any(|t| t.txid() == tx.txid()));
+
+        // Our dropped block is a side block now, so our branch can win back
+        let ours_3 = mine(&ours_2, vec![coinbase("04aa", 500)]);
+        assert!(submit(&ours_3).starts_with("stale: kept on a side branch"));
+        let ours_4 = mine(&ours_3, vec![coinbase("04aa", 500)]);
+        assert_eq!(submit(&ours_4), "ok");
+        assert_eq!(
+            tips(&node.blockchain.lock()),
+            [
+                ours_2.hash.clone(),
+                ours_3.hash.clone(),
+                ours_4.hash.clone()
+            ]
+        );
+        assert!(node.mempool.lock().is_empty());
+
+        // A side branch is only validated when it would take over
+        let overspend = sender.create_signed_transaction("04bob", 5000).unwrap();
+        let bad_2 = mine(&shared, vec![overspend]);
+        assert!(submit(&bad_2).starts_with("stale"));
+        let bad_3 = mine(&bad_2, vec![]);
+        let bad_4 = mine(&bad_3, vec![]);
+        let bad_5 = mine(&bad_4, vec![]);
+        assert!(submit(&bad_3).starts_with("stale"));
+        assert!(submit(&bad_4).starts_with("stale"));
+        let status = submit(&bad_5);
+        assert!(
+            status.starts_with("rejected: block 2 does not validate here: Insufficient funds"),
+            "{}",
+            status
+        );
+        assert_eq!(node.blockchain.lock().chain.len(), 5);
+
+        let stats = node.submissions.lock().clone();
+        assert_eq!((stats.accepted, stats.stale, stats.rejected), (2, 5, 1));
+        assert_eq!(node.reorgs.status()["performed"], 2);
+    }
+
+    #[test]
+    fn side_blocks_below_the_schedule_are_not_kept() {
+        let mut node = test_node(funded_chain(&Wallet::new().unwrap(), 1000).lock().clone());
+        node.log = Box::new(|_| {});
+        let shared = node.blockchain.lock().chain[1].clone();
+        let ours_2 = mine_on(&shared, vec![coinbase("04aa", 500)]);
+        assert!(node.blockchain.lock().add_block(ours_2, 1));
+        let rig = Connection::new(1, "203.0.113.9:5200");
+        let submit = |block: &Block| -> String {
+            let cmd = serde_json::json!({"method": "submitblock", "params": {"block": block}});
+            let resp: serde_json::Value =
+                serde_json::from_str(&crate::rpc::dispatch(&node, &cmd.to_string(), false, &rig))
+                    .unwrap();
+            resp["status"].as_str().unwrap().to_string()
+        };
+        let unmined = |parent: &Block| {
+            let mut block = mine_on(parent, vec![coinbase("04bb", 500)]);
+            block.difficulty = 0;
+            block.hash = Blockchain::calculate_hash(&block);
+            block
+        };
+        let kept = |block: &Block| node.side_blocks.lock().blocks.contains_key(&block.hash);
+
+        // Difficulty 0 needs no work at all; it isn't kept below our tip
+        let free_2 = unmined(&shared);
+        submit(&free_2);
+        assert!(!kept(&free_2));
+
+        // Nor on top of a side block that did its work
+        let theirs_2 = mine_on(&shared, vec![coinbase("04bb", 500)]);
+        submit(&theirs_2);
+        assert!(kept(&theirs_2));
+        let free_3 = unmined(&theirs_2);
+        submit(&free_3);
+        assert!(!kept(&free_3));
+        assert_eq!(node.blockchain.lock().chain.len(), 3);
+    }
+
+    #[test]
+    fn orphans_are_parked_until_their_parent_arrives() {
+        let mut node = test_node(funded_chain(&Wallet::new().unwrap(), 1000).lock().clone());
+        node.log = Box::new(|_| {});
+        let rig = Connection::new(1, "203.0.113.9:5200");
+        let submit = |block: &Block| -> String {
+            let cmd = serde_json::json!({"method": "submitblock", "params": {"block": block}});
+            let resp: serde_json::Value =
+                serde_json::from_str(&crate::rpc::dispatch(&node, &cmd.to_string(), false, &rig))
+                    .unwrap();
+            resp["status"].as_str().unwrap().to_string()
+        };
+        let tip = node.blockchain.lock().chain[1].clone();
+        let b2 = mine_on(&tip, vec![coinbase("04aa", 500)]);
+        let b3 = mine_on(&b2, vec![coinbase("04aa", 500)]);
+        let b4 = mine_on(&b3, vec![coinbase("04aa", 500)]);
+
+        // Arriving out of order, each waits for the one before it
+        let parked = "orphan: parent not seen yet, parked for up to 600s, current height 1";
+        assert_eq!(submit(&b4), parked);
+        assert_eq!(submit(&b3), parked);
+        assert_eq!(node.orphan_blocks.lock().len(), 2);
+        assert_eq!(node.blockchain.lock().chain.len(), 2);
+
+        // Too easy to be worth keeping: rejected as before
+        let mut cheap = mine_on(&b3, vec![]);
+        cheap.difficulty = 0;
+        cheap.hash = Blockchain::calculate_hash(&cheap);
+        assert!(submit(&cheap).starts_with("rejected: PrevHash mismatch"));
+
+        // Their parent connects the whole run
+        assert_eq!(submit(&b2), "ok");
+        let bc = node.blockchain.lock().clone();
+        assert_eq!(bc.chain.len(), 5);
+        assert_eq!(bc.chain[4].hash, b4.hash);
+        assert!(node.seen_blocks.lock().contains(&b3.hash));
+        assert_eq!(node.orphan_blocks.lock().len(), 0);
+        assert_eq!(submit(&b4), "ok, duplicate");
+
+        let stats = node.submissions.lock().clone();
+        assert_eq!((stats.accepted, stats.orphaned, stats.rejected), (1, 2, 1));
+    }
+
+    #[test]
+    fn orphan_pool_is_capped_and_expires() {
+        let genesis = Blockchain::new().chain[0].clone();
+        let start = std::time::Instant::now();
+        let mut pool = OrphanBlocks::new();
+        let orphans: Vec<Block> = (0..ORPHAN_BLOCKS_CAP as u64 + 1)
+            .map(|i| {
+                let mut block = genesis.clone();
+                block.index = i + 2;
+                block.prev_hash = format!("{:064x}", i);
+                block.hash = format!("{:064x}", i + 1000);
+                block
+            })
+            .collect();
+        for (i, block) in orphans.iter().enumerate() {
+            let at = start + std::time::Duration::from_secs(i as u64);
+            assert!(pool.insert(block.clone(), at));
+        }
+        assert!(!pool.insert(orphans[5].clone(), start));
+        // The oldest made room for the newest
+        assert_eq!(pool.len(), ORPHAN_BLOCKS_CAP);
+        assert!(pool.take_children(&orphans[0].prev_hash, start).is_empty());
+        let children = pool.take_children(&orphans[1].prev_hash, start);
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].hash, orphans[1].hash);
+        assert_eq!(pool.len(), ORPHAN_BLOCKS_CAP - 1);
+
+        // Each leaves ORPHAN_TTL after it arrived
+        pool.expire(start + ORPHAN_TTL + std::time::Duration::from_secs(10));
+        assert_eq!(pool.len(), ORPHAN_BLOCKS_CAP - 10);
+        pool.expire(start + ORPHAN_TTL * 2);
+        assert_eq!(pool.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn schema_examples_round_trip_through_the_handlers() {
+        let schema = crate::rpc::protocol_schema();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut node = test_node(synthetic_chain(3));
+        node.dev_mode = true;
+        let ctx = Arc::new(node);
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(handle_websocket_connection(socket, ctx.clone(), true));
+            }
+        });
+
+        // Examples are made up, so signatures and proof of work fail; these
+        // may refuse them, but only for that
+        let refuses_made_up_data = ["submittx", "submitshare"];
+        // Test daemons have no config file to reload
+        let needs_config_file = ["reloadconfig"];
+        for method in schema["methods"].as_array().unwrap() {
+            let name = method["name"].as_str().unwrap();
+            let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+                .await
+                .unwrap();
+            ws.send(Message::Text(method["example"].to_string()))
+                .await
+                .unwrap();
+            // Past the greeting every connection opens with
+            let resp = loop {
+                let Some(Ok(Message::Text(text))) = ws.next().await else {
+                    panic!("{}: no text response", name);
+                };
+                let msg: serde_json::Value = serde_json::from_str(&text).unwrap();
+                if msg["type"] != "greeting" {
+                    break msg;
+                }
+            };
+            assert_eq!(resp["method"], name, "{}", resp);
+            if resp["type"] == "error" {
+                let message = resp["message"].as_str().unwrap();
+                assert!(
+                    (refuses_made_up_data.contains(&name) || needs_config_file.contains(&name))
+                        && !message.contains("missing")
+                        && !message.contains("failed to parse"),
+                    "{}: {}",
+                    name,
+                    message
+                );
+                continue;
+            }
+            assert_eq!(resp["type"], "response", "{}", name);
+            let fields = method["response"].as_array().unwrap();
+            for field in fields.iter().filter(|f| f["required"] == true) {
+                assert!(
+                    resp.get(field["name"].as_str().unwrap()).is_some(),
+                    "{}: {} missing",
+                    name,
+                    field["name"]
+                );
+            }
+            for key in resp.as_object().unwrap().keys() {
+                assert!(
+                    ["type", "method", "id"].contains(&key.as_str())
+                        || fields.iter().any(|f| f["name"] == key.as_str()),
+                    "{}: undocumented field {}",
+                    name,
+                    key
+                );
+            }
+        }
+
+        // The shared types list exactly what serializes
+        let keys = |fields: &serde_json::Value| -> Vec<String> {
+            let mut keys: Vec<String> = fields
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|f| f["name"].as_str().unwrap().to_string())
+                .collect();
+            keys.sort();
+            keys
+        };
+        let serialized = |value: serde_json::Value| -> Vec<String> {
+            let mut keys: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+        let block = synthetic_chain(1).chain[1].clone();
+        assert_eq!(
+            serialized(serde_json::to_value(&block).unwrap()),
+            keys(&schema["types"]["Block"])
+        );
+        let mut tx = coinbase("bob", 5);
+        tx.expires_at_height = Some(9);
+        assert_eq!(
+            serialized(serde_json::to_value(&tx).unwrap()),
+            keys(&schema["types"]["Transaction"])
+        );
+    }
+
+    #[tokio::test]
+    async fn burned_coins_leave_circulating_supply() {
+        let sender = Wallet::new().unwrap();
+        let node = test_node(funded_chain(&sender, 1000).lock().clone());
+        let burn = sender
+            .create_expiring_transaction(crate::wallet::BURN_ADDRESS, 300, None)
+            .unwrap();
+        let resp = node
+            .call(
+                &serde_json::json!({"method": "submittx", "params": {"tx": burn}}),
+                false,
+            )
+            .await;
+        assert_eq!(resp["status"], "ok", "{}", resp);
+        {
+            let mut bc = node.blockchain.lock();
+            let block = next_block(&bc, node.mempool.lock().clone());
+            assert!(bc.add_block_skip_pow(block, 1, true));
+        }
+
+        let supply = node
+            .call(&serde_json::json!({"method": "getsupply"}), false)
+            .await;
+        assert_eq!(
+            (&supply["minted"], &supply["burned"], &supply["circulating"]),
+            (&1000.into(), &300.into(), &700.into())
+        );
+        let burned = node
+            .call(&serde_json::json!({"method": "getburned"}), false)
+            .await;
+        assert_eq!(burned["burned"], 300);
+        assert_eq!(burned["address"], crate::wallet::BURN_ADDRESS);
+
+        // Nobody holds the key, so no wallet file may claim it
+        let mut claimed = sender.clone();
+        claimed.address = crate::wallet::BURN_ADDRESS.to_string();
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("wallet.json");
+        std::fs::write(&path, serde_json::to_string(&claimed).unwrap()).unwrap();
+        assert!(crate::wallet::load_wallet_file(&path.to_string_lossy()).is_err());
+    }
+
+    #[tokio::test]
+    async fn admitted_transactions_are_relayed_to_peers_once() {
+        let sender = Wallet::new().unwrap();
+        let chain = funded_chain(&sender, 1000).lock().clone();
+        let a = Arc::new(test_node(chain.clone()));
+        let b = Arc::new(test_node(chain));
+        let a_addr = serve_node(a.clone()).await;
+        let b_addr = serve_node(b.clone()).await;
+        a.peers.add_peer(b_addr);
+        b.peers.add_peer(a_addr);
+
+        let tx = sender
+            .create_expiring_transaction("bob", 100, None)
+            .unwrap();
+        let resp = a
+            .call(
+                &serde_json::json!({"method": "submittx", "params": {"tx": tx}}),
+                false,
+            )
+            .await;
+        assert_eq!(resp["status"], "ok", "{}", resp);
+        let opened = |node: &DaemonContext| node.connections.next_id.load(Ordering::Relaxed);
+        let before = opened(&b);
+        assert_eq!(relay_transactions(&a).await, 1);
+        assert_eq!(b.mempool.lock().len(), 1);
+        assert_eq!(opened(&b), before + 1);
+
+        // B relays it back once; A already has it, so it stops there
+        assert_eq!(relay_transactions(&b).await, 1);
+        assert_eq!(a.mempool.lock().len(), 1);
+        assert_eq!(relay_transactions(&a).await, 0);
+        assert!(!a.relay.lock().offer(&tx));
+
+        // A busy round goes to each peer over a single connection
+        let more: Vec<Transaction> = (0..3)
+            .map(|i| {
+                sender
+                    .create_expiring_transaction("bob", 10 + i, None)
+                    .unwrap()
+            })
+            .collect();
+        for tx in &more {
+            assert!(a.relay.lock().offer(tx));
+        }
+        let before = opened(&b);
+        assert_eq!(relay_transactions(&a).await, 3);
+        assert_eq!(opened(&b), before + 1);
+        assert_eq!(b.mempool.lock().len(), 4);
+
+        // A flood fills the queue and the rest is dropped, not buffered
+        let mut relay = TxRelay::new();
+        for i in 0..RELAY_QUEUE_CAP + 5 {
+            let mut flood = tx.clone();
+            flood.signature = format!("{:x}", i);
+            relay.offer(&flood);
+        }
+        let (queued, dropped) = relay.take();
+        assert_eq!((queued.len(), dropped), (RELAY_QUEUE_CAP, 5));
+    }
+
+    #[tokio::test]
+    async fn new_blocks_are_pushed_to_peers_that_are_behind() {
+        let chain = funded_chain(&Wallet::new().unwrap(), 1000).lock().clone();
+        let lines = Arc::new(Mutex::new(Vec::<String>::new()));
+        let mut a = test_node(chain.clone());
+        let captured = lines.clone();
+        a.log = Box::new(move |line| captured.lock().push(line.to_string()));
+        let a = Arc::new(a);
+        let b = Arc::new(test_node(chain.clone()));
+        let mut mirror = test_node(chain.clone());
+        mirror.read_only = true;
+        let unheard = Arc::new(test_node(chain.clone()));
+        let b_addr = serve_node(b.clone()).await;
+        let mirror_addr = serve_node(Arc::new(mirror)).await;
+        let unheard_addr = serve_node(unheard.clone()).await;
+        for peer in [&b_addr, &mirror_addr, &unheard_addr] {
+            a.peers.add_peer(peer.clone());
+        }
+        a.peers.record_check_ok(&b_addr, 1);
+        a.peers.record_check_ok(&mirror_addr, 1);
+        let height = |addr: &str| {
+            a.peers
+                .health()
+                .into_iter()
+                .find(|p| p.peer == addr)
+                .and_then(|p| p.last_height)
+        };
+
+        // Level with every peer it knows the height of
+        assert_eq!(relay_blocks(&a).await, 0);
+
+        let block = mine_on(&chain.chain[1], vec![coinbase("04aa", 500)]);
+        let submit = serde_json::json!({"method": "submitblock", "params": {"block": block}});
+        assert_eq!(a.call(&submit, false).await["status"], "ok");
+        assert_eq!(relay_blocks(&a).await, 1);
+        assert_eq!(b.blockchain.lock().chain[2].hash, block.hash);
+        assert_eq!(height(&b_addr), Some(2));
+        // A peer that refuses stays where it was
+        assert_eq!(height(&mirror_addr), Some(1));
+        assert!(
+            lines
+                .lock()
+                .iter()
+                .any(|l| l.contains("refused relayed block 2: rejected: node is read-only"))
+        );
+        // Nothing is pushed on a guess
+        assert_eq!(unheard.blockchain.lock().chain.len(), 2);
+
+        // B has it now
+        assert_eq!(relay_blocks(&a).await, 0);
+
+        // Several blocks behind: all of them over one connection
+        let mut tip = block;
+        for _ in 0..3 {
+            tip = mine_on(&tip, vec![coinbase("04aa", 500)]);
+            let submit = serde_json::json!({"method": "submitblock", "params": {"block": tip}});
+            assert_eq!(a.call(&submit, false).await["status"], "ok");
+        }
+        let before = b.connections.next_id.load(Ordering::Relaxed);
+        assert_eq!(relay_blocks(&a).await, 3);
+        assert_eq!(b.connections.next_id.load(Ordering::Relaxed), before + 1);
+        assert_eq!(height(&b_addr), Some(5));
+    }
+
+    #[tokio::test]
+    async fn peers_are_learned_from_other_nodes_and_addpeer() {
+        // B knows C; A only knows B
+        let b = Arc::new(test_node(synthetic_chain(1)));
+        b.peers.add_peer("c.example.org:6969".to_string());
+        let b_addr = serve_node(b.clone()).await;
+        let mut a = test_node(synthetic_chain(1));
+        a.peers = Arc::new(PeerManager::for_node(7000, 3));
+        a.peers.add_peer(b_addr.clone());
+
+        let addpeer = |address: &str| serde_json::json!({"method": "addpeer", "params": {"address": address}});
+        // Only local admin connections may add peers
+        let resp = a.call(&addpeer("198.51.100.1:6969"), false).await;
+        assert!(
+            resp["message"]
+                .as_str()
+                .unwrap()
+                .contains("restricted to local admin")
+        );
+        for bad in ["no-port", "host:0", "host:99999", "ws://host:1", "a b:1"] {
+            let resp = a.call(&addpeer(bad), true).await;
+            assert_eq!(resp["type"], "error", "{}", bad);
+        }
+        let resp = a.call(&addpeer("127.0.0.1:7000"), true).await;
+        assert!(resp["message"].as_str().unwrap().contains("this node"));
+        let resp = a.call(&addpeer(&b_addr), true).await;
+        assert_eq!(
+            (&resp["status"], &resp["added"]),
+            (&"ok".into(), &false.into())
+        );
+
+        // A learns C from B, but not itself or what it has already
+        b.peers.add_peer("LOCALHOST:7000".to_string());
+        let mut client = crate::ws_client::WsClient::connect(&b_addr).await.unwrap();
+        assert_eq!(exchange_peers(&a.peers, &mut client).await.unwrap(), 1);
+        assert_eq!(exchange_peers(&a.peers, &mut client).await.unwrap(), 0);
+        let merged = a
+            .call(&serde_json::json!({"method": "getpeers"}), false)
+            .await;
+        assert_eq!(
+            merged["data"],
+            serde_json::json!([b_addr, "c.example.org:6969"])
+        );
+
+        // Up to the limit
+        let resp = a.call(&addpeer("[::2]:6969"), true).await;
+        assert_eq!(resp["added"], true);
+        let resp = a.call(&addpeer("d.example.org:6969"), true).await;
+        assert!(resp["message"].as_str().unwrap().contains("full"));
+    }
+
+    #[tokio::test]
+    async fn peers_failing_health_checks_are_dropped_or_demoted() {
+        let live = serve(synthetic_chain(4)).await;
+        let dead = |_| {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+        let (dead_learned, dead_configured) = (dead(0), dead(1));
+        let mut node = test_node(synthetic_chain(1));
+        let pm = PeerManager::new();
+        pm.set_max_failures(2);
+        pm.add_peer(dead_configured.clone());
+        pm.learn_peer(&live).await.unwrap();
+        pm.learn_peer(&dead_learned).await.unwrap();
+
+        assert!(check_peer_health(&pm).await.is_empty());
+        assert_eq!(pm.healthy_peers(), [live.as_str()]);
+        assert!(!pm.is_demoted(&dead_configured));
+
+        // The learned one goes; the configured one stays but isn't synced
+        assert_eq!(check_peer_health(&pm).await, [dead_learned]);
+        assert_eq!(pm.get_peers(), [dead_configured.clone(), live.clone()]);
+        assert!(pm.is_demoted(&dead_configured));
+        assert!(!pm.is_demoted(&live));
+
+        node.peers = Arc::new(pm);
+        let resp = node
+            .call(
+                &serde_json::json!({"method": "getpeers", "params": {"healthy": true}}),
+                false,
+            )
+            .await;
+        assert_eq!(resp["data"], serde_json::json!([live]));
+        let info = node
+            .call(&serde_json::json!({"method": "getpeerinfo"}), false)
+            .await;
+        let health: Vec<PeerHealth> = serde_json::from_value(info["health"].clone()).unwrap();
+        assert_eq!(
+            health
+                .iter()
+                .map(|h| (h.failures, h.last_height, h.healthy, h.configured))
+                .collect::<Vec<_>>(),
+            [(2, None, false, true), (0, Some(4), true, false)]
+        );
+
+        // One answer is enough to be healthy again
+        node.peers.record_check_ok(&dead_configured, 7);
+        assert!(!node.peers.is_demoted(&dead_configured));
+        assert_eq!(node.peers.healthy_peers().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn peer_list_survives_a_restart_without_stale_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("peers.json");
+        let pm = PeerManager::for_node(7000, 8).with_store(path.clone());
+        assert_eq!(pm.load_saved(3600, 0).unwrap(), 0);
+        pm.add_peer("seed.example.org:6969".to_string());
+        pm.learn_peer("b.example.org:6969").await.unwrap();
+        pm.learn_peer("c.example.org:6969").await.unwrap();
+        pm.remove_peer("c.example.org:6969");
+
+        // Saved on every change; a restart gets the list back
+        let restarted = PeerManager::for_node(7000, 8).with_store(path.clone());
+        assert_eq!(restarted.load_saved(3600, unix_now()).unwrap(), 2);
+        assert_eq!(
+            restarted.get_peers(),
+            ["seed.example.org:6969", "b.example.org:6969"]
+        );
+
+        // Entries not seen for too long are dropped, and the file with them
+        let mut saved: Vec<KnownPeer> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        saved[0].last_seen = 0;
+        saved.push(KnownPeer::new("127.0.0.1:7000".to_string(), unix_now()));
+        std::fs::write(&path, serde_json::to_string(&saved).unwrap()).unwrap();
+        let pm = PeerManager::for_node(7000, 8).with_store(path.clone());
+        assert_eq!(pm.load_saved(3600, unix_now()).unwrap(), 1);
+        assert_eq!(pm.get_peers(), ["b.example.org:6969"]);
+        let kept: Vec<KnownPeer> =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(kept.len(), 1);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(pm.load_saved(3600, 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn peers_are_told_apart_by_what_they_resolve_to() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let counted = lookups.clone();
+        let resolver = crate::net::Resolver::with_lookup(move |name| {
+            counted.fetch_add(1, Ordering::Relaxed);
+            let addrs: &[&str] = match name {
+                "seed.example.org:6969" => &["198.51.100.1:6969", "[2001:db8::1]:6969"],
+                "mirror.example.org:6969" => &["[2001:db8::1]:6969"],
+                "other.example.org:6969" => &["198.51.100.2:6969"],
+                "me.example.org:7000" => &["127.0.0.1:7000"],
+                _ => return Err(std::io::ErrorKind::NotFound.into()),
+            };
+            Ok(addrs.iter().map(|a| a.parse().unwrap()).collect())
+        });
+        let pm = PeerManager::for_node(7000, 8).with_resolver(resolver);
+
+        assert_eq!(pm.learn_peer("seed.example.org:6969").await, Ok(true));
+        // The same node by address, by another name, or by its mapped
+        // IPv4 address is already known
+        for alias in [
+            "198.51.100.1:6969",
+            "[2001:db8::1]:6969",
+            "mirror.example.org:6969",
+            "[::ffff:198.51.100.1]:6969",
+        ] {
+            assert_eq!(pm.learn_peer(alias).await, Ok(false), "{}", alias);
+        }
+        // Another port is another node
+        assert_eq!(pm.learn_peer("198.51.100.1:7001").await, Ok(true));
+        assert_eq!(pm.learn_peer("other.example.org:6969").await, Ok(true));
+        // A name for this node is refused like its address
+        assert!(pm.learn_peer("me.example.org:7000").await.is_err());
+        assert_eq!(
+            pm.get_peers(),
+            [
+                "seed.example.org:6969",
+                "198.51.100.1:7001",
+                "other.example.org:6969"
+            ]
+        );
+
+        // Names that don't resolve are only the same as the same name, and
+        // are looked up once however often they come up
+        let before = lookups.load(Ordering::Relaxed);
+        assert_eq!(pm.learn_peer("gone.example.org:6969").await, Ok(true));
+        assert_eq!(pm.learn_peer("gone.example.org:6969").await, Ok(false));
+        pm.add_peer("gone.example.org:6969".to_string());
+        assert_eq!(pm.learn_peer("GONE.example.org:6969").await, Ok(false));
+        assert_eq!(lookups.load(Ordering::Relaxed), before + 1);
+
+        // Configuring a known peer under its name keeps one entry, shown
+        // as configured
+        let pm =
+            PeerManager::for_node(7000, 8).with_resolver(crate::net::Resolver::with_lookup(|_| {
+                Ok(vec!["198.51.100.1:6969".parse().unwrap()])
+            }));
+        pm.learn_peer("198.51.100.1:6969").await.unwrap();
+        pm.add_peer("seed.example.org:6969".to_string());
+        let health = pm.health();
+        assert_eq!(health.len(), 1);
+        assert_eq!(health[0].peer, "seed.example.org:6969");
+        assert!(health[0].configured);
+
+        // Without waiting on DNS, a literal is added at once and a name
+        // once it resolves; a full list looks nothing up
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let counted = lookups.clone();
+        let pm = Arc::new(PeerManager::for_node(7000, 2).with_resolver(
+            crate::net::Resolver::with_lookup(move |_| {
+                counted.fetch_add(1, Ordering::Relaxed);
+                Ok(vec!["198.51.100.2:6969".parse().unwrap()])
+            }),
+        ));
+        assert_eq!(pm.learn_peer_now("198.51.100.1:6969"), Ok(Some(true)));
+        assert_eq!(pm.learn_peer_now("later.example.org:6969"), Ok(None));
+        for _ in 0..200 {
+            if pm.get_peers().len() == 2 {
+                break;
             }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
         }
-        Err(_) => serde_json::json!({"type":"error","message":"invalid JSON"}).to_string(),
+        assert_eq!(
+            pm.get_peers(),
+            ["198.51.100.1:6969", "later.example.org:6969"]
+        );
+        assert_eq!(pm.learn_peer_now("later.example.org:6969"), Ok(Some(false)));
+        assert!(pm.learn_peer_now("more.example.org:6969").is_err());
+        assert_eq!(lookups.load(Ordering::Relaxed), 1);
     }
 }
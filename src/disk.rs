@@ -0,0 +1,227 @@
+//! Free space on the data directory. Below the operator's minimum the
+//! daemon turns read-only, refusing blocks and transactions it might not
+//! be able to persist instead of losing them at the next restart.
+
+use parking_lot::Mutex;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Default for config `min_free_disk_mb`.
+pub const DEFAULT_MIN_FREE_DISK_MB: u64 = 100;
+/// How often the daemon measures free space between writes.
+pub const DISK_CHECK_SECS: u64 = 30;
+
+/// What refused submissions are told.
+pub const OUT_OF_DISK: &str = "node out of disk space";
+
+type Probe = Box<dyn Fn(&Path) -> std::io::Result<u64> + Send + Sync>;
+
+/// Bytes an unprivileged process can still write on the filesystem
+/// holding `path`.
+#[cfg(unix)]
+pub fn available_bytes(path: &Path) -> std::io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // The field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn available_bytes(_path: &Path) -> std::io::Result<u64> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// A switch into or out of read-only mode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiskChange {
+    Low { free: u64, min_free: u64 },
+    Recovered { free: u64 },
+}
+
+impl std::fmt::Display for DiskChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Low { free, min_free } => write!(
+                f,
+                "DISK LOW: {} MB free, under min_free_disk_mb {}; refusing blocks and transactions until space frees up",
+                free / MB,
+                min_free / MB
+            ),
+            Self::Recovered { free } => write!(
+                f,
+                "disk space recovered ({} MB free); accepting blocks and transactions again",
+                free / MB
+            ),
+        }
+    }
+}
+
+const MB: u64 = 1024 * 1024;
+
+/// Read-only state and persist failures of one daemon.
+pub struct DiskGuard {
+    min_free: AtomicU64,
+    /// Last measurement; `None` until one succeeds.
+    free: Mutex<Option<u64>>,
+    read_only: AtomicBool,
+    persist_errors: AtomicU64,
+    last_persist_error: Mutex<Option<String>>,
+    probe: Probe,
+}
+
+impl std::fmt::Debug for DiskGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DiskGuard")
+            .field("min_free", &self.min_free)
+            .field("free", &self.free)
+            .field("read_only", &self.read_only)
+            .field("persist_errors", &self.persist_errors)
+            .finish_non_exhaustive()
+    }
+}
+
+/// No minimum until the daemon's config sets one.
+impl Default for DiskGuard {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl DiskGuard {
+    pub fn new(min_free_mb: u64) -> Self {
+        Self {
+            min_free: AtomicU64::new(min_free_mb.saturating_mul(MB)),
+            free: Mutex::new(None),
+            read_only: AtomicBool::new(false),
+            persist_errors: AtomicU64::new(0),
+            last_persist_error: Mutex::new(None),
+            probe: Box::new(available_bytes),
+        }
+    }
+
+    /// Measure free space with `probe` instead of asking the filesystem.
+    #[cfg(test)]
+    pub fn with_probe(
+        mut self,
+        probe: impl Fn(&Path) -> std::io::Result<u64> + Send + Sync + 'static,
+    ) -> Self {
+        self.probe = Box::new(probe);
+        self
+    }
+
+    /// Change the minimum, e.g. on a config reload; 0 never turns
+    /// read-only. Takes effect at the next check.
+    pub fn configure(&self, min_free_mb: u64) {
+        self.min_free
+            .store(min_free_mb.saturating_mul(MB), Ordering::Relaxed);
+    }
+
+    /// Measure free space for `dir` and switch modes if it crossed the
+    /// minimum. A failed measurement changes nothing.
+    pub fn check(&self, dir: &Path) -> Option<DiskChange> {
+        let free = (self.probe)(dir).ok()?;
+        *self.free.lock() = Some(free);
+        let min_free = self.min_free.load(Ordering::Relaxed);
+        let low = min_free > 0 && free < min_free;
+        if self.read_only.swap(low, Ordering::Relaxed) == low {
+            return None;
+        }
+        Some(if low {
+            DiskChange::Low { free, min_free }
+        } else {
+            DiskChange::Recovered { free }
+        })
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    pub fn record_persist_error(&self, error: &anyhow::Error) {
+        self.persist_errors.fetch_add(1, Ordering::Relaxed);
+        *self.last_persist_error.lock() = Some(format!("{:#}", error));
+    }
+
+    /// `getinfo`'s `disk` object.
+    pub fn status(&self) -> serde_json::Value {
+        serde_json::json!({
+            "free_bytes": *self.free.lock(),
+            "min_free_bytes": self.min_free.load(Ordering::Relaxed),
+            "read_only": self.is_read_only(),
+            "persist_errors": self.persist_errors.load(Ordering::Relaxed),
+            "last_persist_error": *self.last_persist_error.lock(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn read_only_below_the_minimum_until_space_frees_up() {
+        let free = Arc::new(AtomicU64::new(500 * MB));
+        let reading = free.clone();
+        let guard =
+            DiskGuard::new(100).with_probe(move |_| match reading.load(Ordering::Relaxed) {
+                u64::MAX => Err(std::io::ErrorKind::Other.into()),
+                bytes => Ok(bytes),
+            });
+        let dir = Path::new(".");
+
+        assert_eq!(guard.check(dir), None);
+        assert!(!guard.is_read_only());
+
+        free.store(20 * MB, Ordering::Relaxed);
+        assert_eq!(
+            guard.check(dir),
+            Some(DiskChange::Low {
+                free: 20 * MB,
+                min_free: 100 * MB
+            })
+        );
+        assert!(guard.is_read_only());
+        // Reported once, not on every check
+        assert_eq!(guard.check(dir), None);
+        // A failed measurement keeps the last state
+        free.store(u64::MAX, Ordering::Relaxed);
+        assert_eq!(guard.check(dir), None);
+        assert!(guard.is_read_only());
+        assert_eq!(guard.status()["free_bytes"], 20 * MB);
+
+        free.store(150 * MB, Ordering::Relaxed);
+        assert_eq!(
+            guard.check(dir),
+            Some(DiskChange::Recovered { free: 150 * MB })
+        );
+        assert!(!guard.is_read_only());
+
+        // 0 turns the minimum off
+        free.store(0, Ordering::Relaxed);
+        guard.configure(0);
+        assert_eq!(guard.check(dir), None);
+        assert!(!guard.is_read_only());
+    }
+
+    #[test]
+    fn free_space_is_measured_on_the_real_filesystem() {
+        if cfg!(unix) {
+            assert!(available_bytes(&std::env::temp_dir()).is_ok());
+            // A bare file name's directory is the working directory
+            assert!(available_bytes(Path::new("")).is_ok());
+            assert!(available_bytes(Path::new("/no/such/dir")).is_err());
+        }
+    }
+}
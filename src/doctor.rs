@@ -0,0 +1,440 @@
+use crate::blockchain::Blockchain;
+use crate::config::Config;
+use crate::miner::MemoryPlan;
+use crate::wallet::Wallet;
+use colored::Colorize;
+use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+use std::path::Path;
+
+/// Largest tolerated gap between the node's latest block timestamp and the
+/// local clock before we warn about clock skew.
+const MAX_CLOCK_SKEW_SECS: i64 = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// Outcome of a single doctor check. `hint` explains how to fix a WARN/FAIL.
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub hint: Option<String>,
+}
+
+impl CheckResult {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn print(&self) {
+        let tag = match self.status {
+            CheckStatus::Pass => "[PASS]".green(),
+            CheckStatus::Warn => "[WARN]".yellow(),
+            CheckStatus::Fail => "[FAIL]".red(),
+        };
+        println!("{} {}: {}", tag, self.name, self.detail);
+        if let Some(hint) = &self.hint {
+            println!("       -> {}", hint);
+        }
+    }
+}
+
+pub fn check_config(config: &Config) -> CheckResult {
+    match config.validate() {
+        Ok(()) => CheckResult::pass("config", "configuration is valid"),
+        Err(e) => CheckResult::fail(
+            "config",
+            e.to_string(),
+            format!("edit {}", crate::config::get_config_path().display()),
+        ),
+    }
+}
+
+/// Verify the wallet's private key matches its stored public key and address.
+pub fn check_wallet_keys(wallet: &Wallet) -> CheckResult {
+    let name = "wallet keys";
+    let pkcs8 = match hex::decode(&wallet.priv_key) {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            return CheckResult::fail(
+                name,
+                "priv_key is not valid hex",
+                "restore the wallet file from a backup",
+            );
+        }
+    };
+    let key_pair = match EcdsaKeyPair::from_pkcs8(
+        &ECDSA_P256_SHA256_FIXED_SIGNING,
+        &pkcs8,
+        &ring::rand::SystemRandom::new(),
+    ) {
+        Ok(kp) => kp,
+        Err(_) => {
+            return CheckResult::fail(
+                name,
+                "priv_key is not a valid PKCS#8 P-256 key",
+                "restore the wallet file from a backup",
+            );
+        }
+    };
+    let derived = hex::encode(key_pair.public_key().as_ref());
+    if derived != wallet.pub_key {
+        return CheckResult::fail(
+            name,
+            "pub_key does not match priv_key",
+            "the wallet file was edited or mixed with another wallet; restore it from a backup",
+        );
+    }
+    if wallet.address != wallet.pub_key {
+        return CheckResult::fail(
+            name,
+            "address does not match pub_key",
+            "the address field should equal pub_key; restore the wallet file from a backup",
+        );
+    }
+    CheckResult::pass(
+        name,
+        format!("key pair matches address {}", short(&wallet.address)),
+    )
+}
+
+/// Fast integrity check of the chain file: parseable JSON, contiguous indexes
+/// and intact prev_hash links. Hashes are not recomputed.
+pub fn check_chain_file(path: &Path) -> CheckResult {
+    let name = "chain file";
+    if !path.exists() {
+        return CheckResult::warn(
+            name,
+            format!("{} does not exist", path.display()),
+            "it will be created on the next sync",
+        );
+    }
+    let data = match std::fs::read_to_string(path) {
+        Ok(d) => d,
+        Err(e) => {
+            return CheckResult::fail(name, e.to_string(), "check file permissions");
+        }
+    };
+    let bc: Blockchain = match serde_json::from_str(&data) {
+        Ok(bc) => bc,
+        Err(e) => {
+            return CheckResult::fail(
+                name,
+                format!("unparseable: {}", e),
+                format!("delete {} and resync from the node", path.display()),
+            );
+        }
+    };
+    for pair in bc.chain.windows(2) {
+        let (prev, cur) = (&pair[0], &pair[1]);
+        if cur.index != prev.index + 1 || cur.prev_hash != prev.hash {
+            return CheckResult::fail(
+                name,
+                format!("broken link at block {}", cur.index),
+                format!("delete {} and resync from the node", path.display()),
+            );
+        }
+    }
+    CheckResult::pass(name, format!("{} blocks linked", bc.chain.len()))
+}
+
+/// Compare the node's latest block timestamp to the local clock. A block
+/// from the future means our clock is behind.
+pub fn check_clock(node_latest_ts: i64, now: i64) -> CheckResult {
+    let name = "clock";
+    let ahead = node_latest_ts - now;
+    if ahead > MAX_CLOCK_SKEW_SECS {
+        CheckResult::warn(
+            name,
+            format!("node's latest block is {}s in the future", ahead),
+            "enable NTP time synchronisation on this machine",
+        )
+    } else {
+        CheckResult::pass(name, "no skew detected against the node's latest block")
+    }
+}
+
+/// Try allocating one mining scratchpad and report on hugepage support when
+/// it was requested via OWONERO_USE_HUGEPAGES.
+pub fn check_scratchpad() -> CheckResult {
+    let name = "scratchpad";
+    let size = crate::blockchain::scratchpad_size();
+    let mut buf: Vec<u8> = Vec::new();
+    if buf.try_reserve_exact(size).is_err() {
+        return CheckResult::fail(
+            name,
+            format!("could not allocate {} bytes", size),
+            "free memory or lower OWONERO_SCRATCHPAD_SIZE",
+        );
+    }
+    let huge = std::env::var("OWONERO_USE_HUGEPAGES")
+        .map(|v| v != "0" && v.to_lowercase() != "false")
+        .unwrap_or(false);
+    if huge {
+        let thp = std::fs::read_to_string("/sys/kernel/mm/transparent_hugepage/enabled")
+            .unwrap_or_default();
+        if !thp.contains("[always]") && !thp.contains("[madvise]") {
+            return CheckResult::warn(
+                name,
+                "hugepages requested but transparent hugepages are unavailable",
+                "enable THP (madvise) or unset OWONERO_USE_HUGEPAGES",
+            );
+        }
+    }
+    CheckResult::pass(name, format!("allocated {} byte scratchpad", size))
+}
+
+/// Whether `threads` mining threads and a chain of `height` blocks fit in
+/// available memory; see `MemoryPlan`.
+fn check_memory(plan: Option<MemoryPlan>) -> CheckResult {
+    let name = "memory";
+    match plan {
+        None => CheckResult::warn(
+            name,
+            "available memory unknown",
+            "the miner will start without checking memory",
+        ),
+        Some(plan) if plan.threads == 0 => CheckResult::fail(
+            name,
+            format!("{}; not even one thread fits", plan.describe()),
+            "free memory or use a bigger machine; `--force` mines anyway",
+        ),
+        Some(plan) if plan.is_capped() => CheckResult::warn(
+            name,
+            format!("{}; the miner will use {}", plan.describe(), plan.threads),
+            "lower mining_threads to match, or free memory",
+        ),
+        Some(plan) => CheckResult::pass(name, plan.describe()),
+    }
+}
+
+/// Connect to the node and compare its view of the chain with ours.
+async fn check_node(node_addr: &str, local: Option<&Blockchain>) -> Vec<CheckResult> {
+    let mut results = Vec::new();
+    let mut client = match crate::ws_client::WsClient::connect(node_addr).await {
+        Ok(c) => c,
+        Err(e) => {
+            results.push(CheckResult::fail(
+                "node connectivity",
+                format!("cannot connect to {}: {}", node_addr, e),
+                "check --node-addr, the node's port and any firewall in between",
+            ));
+            return results;
+        }
+    };
+    let node_height = match &client.greeting {
+        Some(g) => {
+            results.push(CheckResult::pass(
+                "node connectivity",
+                format!("{} greeted at height {}", node_addr, g.height),
+            ));
+            g.height
+        }
+        None => {
+            results.push(CheckResult::warn(
+                "node connectivity",
+                format!("{} connected but sent no parseable greeting", node_addr),
+                "the node may run an incompatible version",
+            ));
+            return results;
+        }
+    };
+
+    if let Some(local) = local {
+        let genesis = client
+            .command("getblock", serde_json::json!({}))
+            .await
+            .ok()
+            .and_then(|r| r.get("data").and_then(|d| d.get("hash")).cloned());
+        let local_genesis = local.chain.first().map(|b| b.hash.clone());
+        match (genesis.as_ref().and_then(|g| g.as_str()), local_genesis) {
+            (Some(remote), Some(mine)) if remote != mine => results.push(CheckResult::fail(
+                "network",
+                "node genesis differs from the local chain",
+                "you are pointing at a different network; fix --node-addr or delete the local chain",
+            )),
+            (Some(_), Some(_)) => {
+                let local_height = local.chain.last().map(|b| b.index).unwrap_or(0);
+                let behind = node_height.saturating_sub(local_height);
+                if behind > 0 {
+                    results.push(CheckResult::warn(
+                        "height",
+                        format!("local {} vs node {}", local_height, node_height),
+                        "run owonero without flags to sync the local chain",
+                    ));
+                } else {
+                    results.push(CheckResult::pass(
+                        "height",
+                        format!("local {} vs node {}", local_height, node_height),
+                    ));
+                }
+            }
+            _ => results.push(CheckResult::warn(
+                "network",
+                "could not fetch the node's genesis block",
+                "the node may run an incompatible version",
+            )),
+        }
+    }
+
+    if let Ok(resp) = client.command("getlatest", serde_json::json!({})).await
+        && let Some(ts) = resp
+            .get("data")
+            .and_then(|d| d.get("timestamp"))
+            .and_then(|t| t.as_str())
+            .and_then(|t| t.parse::<chrono::DateTime<chrono::Utc>>().ok())
+    {
+        results.push(check_clock(ts.timestamp(), chrono::Utc::now().timestamp()));
+    }
+
+    results
+}
+
+/// Run every check, print PASS/WARN/FAIL lines and return an error when any
+/// check failed so the process exits non-zero.
+pub async fn run_doctor(config: &Config) -> anyhow::Result<()> {
+    let mut results = vec![check_config(config)];
+
+    match crate::wallet::load_wallet_file(&config.wallet_path) {
+        Ok(wallet) => results.push(check_wallet_keys(&wallet)),
+        Err(e) => results.push(CheckResult::fail(
+            "wallet keys",
+            e.to_string(),
+            "check --wallet-path, restore a backup with `owonero wallet restore` or create a wallet with `owonero wallet new`",
+        )),
+    }
+
+    let chain_path = crate::config::get_blockchain_path();
+    let chain_check = check_chain_file(&chain_path);
+    let local = if chain_check.status == CheckStatus::Pass {
+        std::fs::read_to_string(&chain_path)
+            .ok()
+            .and_then(|d| serde_json::from_str::<Blockchain>(&d).ok())
+    } else {
+        None
+    };
+    results.push(chain_check);
+    results.push(check_scratchpad());
+    let height = local
+        .as_ref()
+        .and_then(|bc| bc.chain.last())
+        .map_or(0, |b| b.index);
+    results.push(check_memory(MemoryPlan::for_system(
+        config.mining_threads,
+        height,
+    )));
+    results.extend(check_node(&config.node_address, local.as_ref()).await);
+
+    for r in &results {
+        r.print();
+    }
+    let failed = results
+        .iter()
+        .filter(|r| r.status == CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{} check(s) failed", failed);
+    }
+    Ok(())
+}
+
+fn short(s: &str) -> &str {
+    &s[..16.min(s.len())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(dir: &tempfile::TempDir, name: &str, contents: &str) -> std::path::PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn wallet_key_mismatch_fails() {
+        let wallet = Wallet::new().unwrap();
+        assert_eq!(check_wallet_keys(&wallet).status, CheckStatus::Pass);
+
+        let other = Wallet::new().unwrap();
+        let mut mixed = wallet.clone();
+        mixed.pub_key = other.pub_key.clone();
+        assert_eq!(check_wallet_keys(&mixed).status, CheckStatus::Fail);
+
+        let mut bad_hex = wallet;
+        bad_hex.priv_key = "zz".to_string();
+        assert_eq!(check_wallet_keys(&bad_hex).status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn chain_file_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let bc = Blockchain::new();
+        let good = temp_file(&dir, "good.json", &serde_json::to_string(&bc).unwrap());
+        assert_eq!(check_chain_file(&good).status, CheckStatus::Pass);
+
+        let mut broken = Blockchain::new();
+        let mut second = broken.chain[0].clone();
+        second.index = 1;
+        second.prev_hash = "deadbeef".to_string();
+        broken.chain.push(second);
+        let broken = temp_file(
+            &dir,
+            "broken.json",
+            &serde_json::to_string(&broken).unwrap(),
+        );
+        assert_eq!(check_chain_file(&broken).status, CheckStatus::Fail);
+
+        let garbage = temp_file(&dir, "garbage.json", "{not json");
+        assert_eq!(check_chain_file(&garbage).status, CheckStatus::Fail);
+
+        let missing = dir.path().join("missing.json");
+        assert_eq!(check_chain_file(&missing).status, CheckStatus::Warn);
+    }
+
+    #[test]
+    fn config_with_clashing_ports_fails() {
+        let cfg = Config {
+            web_port: 6969,
+            daemon_port: 6969,
+            ..Default::default()
+        };
+        assert_eq!(check_config(&cfg).status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn clock_skew_is_detected() {
+        assert_eq!(check_clock(1_000, 1_000).status, CheckStatus::Pass);
+        assert_eq!(check_clock(1_000 - 600, 1_000).status, CheckStatus::Pass);
+        assert_eq!(check_clock(1_000 + 600, 1_000).status, CheckStatus::Warn);
+    }
+}
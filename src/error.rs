@@ -0,0 +1,184 @@
+use thiserror::Error;
+
+/// Exit codes documented in `--help`. Scripts wrapping owonero rely on
+/// these, so never renumber them.
+pub const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  success
+  1  other error
+  2  usage error (invalid arguments)
+  3  network error (node unreachable or connection dropped)
+  4  validation error (transaction/block rejected, failed checks)
+  5  local file error (missing, unreadable or corrupt config/wallet/chain)
+  6  stale (--json wallet summary: no node answered, figures are from the local chain)
+  130  interrupted (Ctrl+C; the message says how far the saved state goes)";
+
+/// Top-level error returned by every CLI mode. `main` maps the variant to an
+/// exit code and prints it as a single line on stderr.
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("{0:#}")]
+    Network(anyhow::Error),
+    #[error("{0}")]
+    Rejected(String),
+    #[error("{0:#}")]
+    LocalFile(anyhow::Error),
+    #[error("{0}")]
+    Stale(String),
+    #[error("{0}")]
+    Interrupted(crate::interrupt::Interrupted),
+    #[error("{0:#}")]
+    Other(anyhow::Error),
+}
+
+impl AppError {
+    pub fn usage(msg: impl Into<String>) -> Self {
+        Self::Usage(msg.into())
+    }
+
+    pub fn network(err: impl Into<anyhow::Error>) -> Self {
+        Self::Network(err.into())
+    }
+
+    pub fn rejected(msg: impl Into<String>) -> Self {
+        Self::Rejected(msg.into())
+    }
+
+    pub fn local_file(err: impl Into<anyhow::Error>) -> Self {
+        Self::LocalFile(err.into())
+    }
+
+    pub fn stale(msg: impl Into<String>) -> Self {
+        Self::Stale(msg.into())
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Other(_) => 1,
+            Self::Usage(_) => 2,
+            Self::Network(_) => 3,
+            Self::Rejected(_) => 4,
+            Self::LocalFile(_) => 5,
+            Self::Stale(_) => 6,
+            Self::Interrupted(_) => crate::interrupt::EXIT_INTERRUPTED,
+        }
+    }
+
+    /// Short machine-readable name of the error class.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Other(_) => "error",
+            Self::Usage(_) => "usage",
+            Self::Network(_) => "network",
+            Self::Rejected(_) => "rejected",
+            Self::LocalFile(_) => "file",
+            Self::Stale(_) => "stale",
+            Self::Interrupted(_) => "interrupted",
+        }
+    }
+
+    /// One line for stderr: `error[kind]: message`, or a JSON object.
+    pub fn to_line(&self, json: bool) -> String {
+        let message = self.to_string().replace('\n', " ");
+        if json {
+            serde_json::json!({"error": self.kind(), "code": self.exit_code(), "message": message})
+                .to_string()
+        } else {
+            format!("error[{}]: {}", self.kind(), message)
+        }
+    }
+}
+
+/// Errors bubbling up without an explicit class are sorted by their cause:
+/// socket failures are network errors, other I/O and JSON failures are
+/// local file errors, anything else is a generic error.
+impl From<anyhow::Error> for AppError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<crate::interrupt::Interrupted>() {
+            Ok(interrupted) => return Self::Interrupted(interrupted),
+            Err(err) => err,
+        };
+        for cause in err.chain() {
+            if cause
+                .downcast_ref::<tokio_tungstenite::tungstenite::Error>()
+                .is_some()
+            {
+                return Self::Network(err);
+            }
+            if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+                use std::io::ErrorKind::*;
+                return match io.kind() {
+                    ConnectionRefused | ConnectionReset | ConnectionAborted | NotConnected
+                    | TimedOut | AddrNotAvailable | HostUnreachable | NetworkUnreachable => {
+                        Self::Network(err)
+                    }
+                    _ => Self::LocalFile(err),
+                };
+            }
+            if cause.downcast_ref::<serde_json::Error>().is_some() {
+                return Self::LocalFile(err);
+            }
+        }
+        Self::Other(err)
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        anyhow::Error::from(err).into()
+    }
+}
+
+impl From<crate::interrupt::Interrupted> for AppError {
+    fn from(interrupted: crate::interrupt::Interrupted) -> Self {
+        Self::Interrupted(interrupted)
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::LocalFile(err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn causes_are_classified() {
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        assert_eq!(AppError::from(refused).exit_code(), 3);
+
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        let err = anyhow::Error::from(missing).context("reading wallet");
+        assert_eq!(AppError::from(err).exit_code(), 5);
+
+        let corrupt = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(AppError::from(corrupt).exit_code(), 5);
+
+        assert_eq!(AppError::from(anyhow::anyhow!("boom")).exit_code(), 1);
+
+        let stopped = anyhow::Error::from(crate::interrupt::Interrupted::saved_through(7));
+        let err = AppError::from(stopped);
+        assert_eq!(err.exit_code(), 130);
+        assert_eq!(
+            err.to_line(false),
+            "error[interrupted]: interrupted — state saved through block 7"
+        );
+    }
+
+    #[test]
+    fn error_line_is_single_line_and_json_parsable() {
+        let err = AppError::rejected("rejected: insufficient funds");
+        assert_eq!(
+            err.to_line(false),
+            "error[rejected]: rejected: insufficient funds"
+        );
+        let json: serde_json::Value = serde_json::from_str(&err.to_line(true)).unwrap();
+        assert_eq!(json["code"], 4);
+        assert_eq!(json["error"], "rejected");
+    }
+}
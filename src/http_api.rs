@@ -1,24 +1,40 @@
 use axum::{
     Json, Router,
-    extract::{Query, State},
-    http::StatusCode,
+    body::Body,
+    extract::{ConnectInfo, Path, Query, Request, State},
+    http::{HeaderMap, Method, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::get,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
+use std::net::{IpAddr, SocketAddr};
 
 #[derive(Clone)]
 pub struct AppState {
     pub daemon_addr: String,
+    pub snapshot_path: std::path::PathBuf,
+    /// The node refuses submissions; told to clients in the stats.
+    pub read_only: bool,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct WalletBalanceQuery {
     pub addr: String,
+    /// Also report what the address's pending transactions spend.
+    #[serde(default)]
+    pub include_pending: bool,
+}
+
+#[derive(Deserialize)]
+pub struct AddressBalanceQuery {
+    /// Balance after this block; the tip when absent.
+    pub height: Option<u64>,
 }
 
 pub async fn get_stats(State(state): State<AppState>) -> Result<Json<Value>, StatusCode> {
-    let mut response = json!({});
+    let mut response = json!({ "read_only": state.read_only });
 
     // Get chain info (includes height)
     if let Ok(chain) = crate::ws_client::fetch_chain(&state.daemon_addr).await {
@@ -28,11 +44,10 @@ pub async fn get_stats(State(state): State<AppState>) -> Result<Json<Value>, Sta
     }
 
     // Get latest block
-    if let Ok(latest) = crate::ws_client::fetch_latest_block(&state.daemon_addr).await {
-        match serde_json::to_value(&latest) {
-            Ok(val) => response["latest_block"] = val,
-            Err(_) => {}
-        }
+    if let Ok(latest) = crate::ws_client::fetch_latest_block(&state.daemon_addr).await
+        && let Ok(val) = serde_json::to_value(&latest)
+    {
+        response["latest_block"] = val
     }
 
     // Get mempool
@@ -45,6 +60,11 @@ pub async fn get_stats(State(state): State<AppState>) -> Result<Json<Value>, Sta
         response["mempool_size"] = json!(mempool.len());
     }
 
+    // Minted, burned and circulating coins
+    if let Ok(supply) = crate::ws_client::fetch_supply(&state.daemon_addr).await {
+        response["supply"] = json!(supply);
+    }
+
     Ok(Json(response))
 }
 
@@ -52,58 +72,492 @@ pub async fn get_wallet_balance(
     State(state): State<AppState>,
     Query(query): Query<WalletBalanceQuery>,
 ) -> Result<Json<Value>, StatusCode> {
-    // Fetch blockchain and calculate balance for wallet
-    match crate::ws_client::fetch_chain(&state.daemon_addr).await {
-        Ok(chain) => {
-            let wallet = crate::wallet::Wallet {
-                address: query.addr.clone(),
-                pub_key: String::new(),
-                priv_key: String::new(),
-                node_address: None,
-            };
-            let balance = wallet.get_balance(&chain);
-            let balance_owe = (balance as f64) / 1000.0;
-
-            Ok(Json(json!({
-                "wallet": query.addr,
-                "balance_milli": balance,
-                "balance": balance_owe,
-                "currency": "OWE"
-            })))
-        }
-        Err(_) => Err(StatusCode::SERVICE_UNAVAILABLE),
+    // The node works the balance out; no need to download the chain
+    let (height, balance, pending_out) = if query.include_pending {
+        crate::ws_client::fetch_pending_balance(&state.daemon_addr, &query.addr)
+            .await
+            .map(|(height, balance, pending_out)| (height, balance, Some(pending_out)))
+    } else {
+        crate::ws_client::fetch_balance(&state.daemon_addr, &query.addr, None)
+            .await
+            .map(|(height, balance)| (height, balance, None))
+    }
+    .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    let mut response = json!({
+        "wallet": query.addr,
+        "height": height,
+        "balance_milli": balance,
+        "balance": (balance as f64) / 1000.0,
+        "currency": "OWE"
+    });
+    if let Some(pending_out) = pending_out {
+        response["pending_out_milli"] = json!(pending_out);
+        response["available_milli"] = json!(balance - pending_out);
+        response["available"] = json!(((balance - pending_out) as f64) / 1000.0);
     }
+    Ok(Json(response))
 }
 
 pub async fn get_chain(State(state): State<AppState>) -> Result<Json<Value>, StatusCode> {
     match crate::ws_client::fetch_chain(&state.daemon_addr).await {
-        Ok(chain) => {
-            match serde_json::to_value(&chain) {
-                Ok(val) => Ok(Json(val)),
-                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-            }
-        }
+        Ok(chain) => match serde_json::to_value(&chain) {
+            Ok(val) => Ok(Json(val)),
+            Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        },
+        Err(_) => Err(StatusCode::SERVICE_UNAVAILABLE),
+    }
+}
+
+/// Confirmed balance of an address, now or after a given block.
+pub async fn get_address_balance(
+    State(state): State<AppState>,
+    Path(addr): Path<String>,
+    Query(query): Query<AddressBalanceQuery>,
+) -> Result<Json<Value>, StatusCode> {
+    match crate::ws_client::fetch_balance(&state.daemon_addr, &addr, query.height).await {
+        Ok((height, balance)) => Ok(Json(json!({
+            "address": addr,
+            "height": height,
+            "balance_milli": balance,
+            "balance": (balance as f64) / 1000.0,
+            "currency": "OWE"
+        }))),
+        Err(e) if e.to_string().contains("height above chain tip") => Err(StatusCode::BAD_REQUEST),
         Err(_) => Err(StatusCode::SERVICE_UNAVAILABLE),
     }
 }
 
-pub fn create_router(daemon_addr: String) -> Router {
-    let state = AppState { daemon_addr };
+/// Uptime, per-method request counts and latency, validation scratchpad
+/// pool usage, peer forks seen, tracked miner wallets, disk state and
+/// reorgs, from the daemon.
+pub async fn get_metrics(State(state): State<AppState>) -> Result<Json<Value>, StatusCode> {
+    let info = crate::ws_client::fetch_info(&state.daemon_addr)
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    Ok(Json(json!({
+        "uptime_secs": info["uptime_secs"],
+        "methods": info["methods"],
+        "scratchpad_pool": info["scratchpad_pool"],
+        "peer_forks": info["peer_forks"],
+        "wallet_stats": info["wallet_stats"],
+        "disk": info["disk"],
+        "reorgs": {
+            "max_depth": info["max_reorg_depth"],
+            "performed": info["reorgs"]["performed"],
+            "refused": info["reorgs"]["refused"],
+            "last": info["last_reorg"],
+        },
+    })))
+}
+
+/// Each configured peer's height and tip hash, and whether it agrees with
+/// this node's chain, as of the daemon's last sync round.
+pub async fn get_peers(State(state): State<AppState>) -> Result<Json<Value>, StatusCode> {
+    let peers = crate::ws_client::fetch_peer_info(&state.daemon_addr)
+        .await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    Ok(Json(json!({ "peers": peers })))
+}
+
+/// Stream the latest chain snapshot written by the daemon, for
+/// `owonero chain bootstrap`.
+pub async fn get_snapshot(State(state): State<AppState>) -> Result<Response, StatusCode> {
+    let file = tokio::fs::File::open(&state.snapshot_path)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let body = Body::from_stream(tokio_util::io::ReaderStream::new(file));
+    Ok(([(header::CONTENT_TYPE, "application/gzip")], body).into_response())
+}
+
+/// How the stats server is mounted behind a reverse proxy.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyOptions {
+    /// Path the routes are nested under, e.g. `/owonero`; empty for `/`.
+    pub path_prefix: String,
+    /// Take client addresses from `Forwarded`/`X-Forwarded-For`.
+    pub trust_proxy: bool,
+}
+
+/// `prefix` as a nesting path: leading slash, no trailing one. `None` for
+/// the root.
+fn normalize_prefix(prefix: &str) -> Option<String> {
+    let trimmed = prefix.trim().trim_matches('/');
+    (!trimmed.is_empty()).then(|| format!("/{}", trimmed))
+}
+
+/// The client behind a request. With `trust_proxy`, the address the proxy
+/// in front of us reports, i.e. the last hop of `Forwarded` or else of
+/// `X-Forwarded-For`; earlier hops are whatever the client claimed.
+pub fn client_ip(headers: &HeaderMap, peer: IpAddr, trust_proxy: bool) -> IpAddr {
+    if !trust_proxy {
+        return peer;
+    }
+    let last_hop = |name: header::HeaderName| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .last()
+            .map(str::trim)
+            .map(str::to_string)
+    };
+    let forwarded = last_hop(header::FORWARDED).and_then(|hop| {
+        hop.split(';')
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(key, _)| key.eq_ignore_ascii_case("for"))
+            .and_then(|(_, node)| parse_node(node))
+    });
+    forwarded
+        .or_else(|| {
+            last_hop(header::HeaderName::from_static("x-forwarded-for"))
+                .and_then(|hop| parse_node(&hop))
+        })
+        .unwrap_or(peer)
+}
+
+/// An address from a forwarding header: `1.2.3.4`, `1.2.3.4:80`,
+/// `"[2001:db8::1]:4711"` or a bare IPv6 address.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    node.parse::<SocketAddr>().ok().map(|a| a.ip())
+}
+
+/// Header carrying a request's trace id.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
 
-    Router::new()
+/// Give every request a trace id, sent along with the daemon commands it
+/// makes and returned in the `x-request-id` header. Failed requests are
+/// logged with the id and the client, and answer with a JSON body naming
+/// the id, so one grep finds the daemon's side of the story too.
+async fn trace_requests(
+    State(trust_proxy): State<bool>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let id = format!("{:016x}", rand::random::<u64>());
+    let client = client_ip(request.headers(), peer.ip().to_canonical(), trust_proxy);
+    let path = request.uri().path().to_string();
+    let mut response = crate::ws_client::traced(id.clone(), next.run(request)).await;
+    let status = response.status();
+    if status.is_server_error() {
+        eprintln!(
+            "Stats request {} from {} failed: {} (trace={})",
+            path, client, status, id
+        );
+    }
+    if status.is_client_error() || status.is_server_error() {
+        let error = status.canonical_reason().unwrap_or("error");
+        response = (status, Json(json!({ "error": error, "request_id": id }))).into_response();
+    }
+    if let Ok(value) = header::HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// 403 for anything but reads on a `read_only` node.
+async fn refuse_writes(State(read_only): State<bool>, request: Request, next: Next) -> Response {
+    let method = request.method();
+    if read_only && method != Method::GET && method != Method::HEAD {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(request).await
+}
+
+pub fn create_router(daemon_addr: String, proxy: &ProxyOptions, read_only: bool) -> Router {
+    let state = AppState {
+        daemon_addr,
+        snapshot_path: crate::config::get_snapshot_path(),
+        read_only,
+    };
+
+    let routes = Router::new()
         .route("/stats", get(get_stats))
         .route("/api/stats", get(get_stats))
         .route("/api/chain", get(get_chain))
         .route("/api/walletbalance", get(get_wallet_balance))
-        .with_state(state)
+        .route("/api/address/{addr}/balance", get(get_address_balance))
+        .route("/api/peers", get(get_peers))
+        .route("/api/snapshot", get(get_snapshot))
+        .route("/metrics", get(get_metrics))
+        .with_state(state);
+    let routes = match normalize_prefix(&proxy.path_prefix) {
+        Some(prefix) => Router::new().nest(&prefix, routes),
+        None => routes,
+    };
+    routes
+        .layer(middleware::from_fn_with_state(read_only, refuse_writes))
+        .layer(middleware::from_fn_with_state(
+            proxy.trust_proxy,
+            trace_requests,
+        ))
 }
 
-pub async fn run_http_server(port: u16, daemon_addr: String) -> anyhow::Result<()> {
-    let app = create_router(daemon_addr);
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
-    println!("Stats server listening on :{}", port);
+pub async fn run_http_server(
+    port: u16,
+    daemon_addr: String,
+    proxy: ProxyOptions,
+    read_only: bool,
+) -> anyhow::Result<()> {
+    let app = create_router(daemon_addr, &proxy, read_only);
+    let listener = crate::net::listen(port).await?;
+    match normalize_prefix(&proxy.path_prefix) {
+        Some(prefix) => println!("Stats server listening on :{} under {}", port, prefix),
+        None => println!("Stats server listening on :{}", port),
+    }
 
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn client_address_comes_from_the_proxy_only_when_trusted() {
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "6.6.6.6, 203.0.113.7".parse().unwrap());
+        assert_eq!(client_ip(&headers, peer, false), peer);
+        assert_eq!(
+            client_ip(&headers, peer, true),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+
+        // Forwarded wins, with ports, quotes and brackets stripped
+        headers.insert(
+            header::FORWARDED,
+            r#"for=6.6.6.6, for="[2001:db8::1]:4711";proto=https"#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            client_ip(&headers, peer, true),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+        headers.insert(header::FORWARDED, "for=unknown".parse().unwrap());
+        assert_eq!(
+            client_ip(&headers, peer, true),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    async fn serve(app: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+        addr
+    }
+
+    /// Forwards every request, path untouched, like `proxy_pass` without a
+    /// URI part.
+    async fn reverse_proxy(
+        State(backend): State<String>,
+        ConnectInfo(peer): ConnectInfo<SocketAddr>,
+        request: Request,
+    ) -> Response {
+        let resp = reqwest::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap()
+            .get(format!("http://{}{}", backend, request.uri()))
+            .header("x-forwarded-for", peer.ip().to_string())
+            .send()
+            .await
+            .unwrap();
+        (resp.status(), resp.bytes().await.unwrap()).into_response()
+    }
+
+    #[tokio::test]
+    async fn stats_through_a_prefixing_proxy_match_direct_access() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        tokio::spawn(crate::daemon::run_daemon(
+            port,
+            Arc::new(parking_lot::Mutex::new(crate::blockchain::Blockchain::new())),
+            Arc::new(crate::daemon::PeerManager::new()),
+            true,
+            false,
+            None,
+            None,
+        ));
+        let daemon = format!("127.0.0.1:{}", port);
+        for _ in 0..50 {
+            if tokio::net::TcpStream::connect(&daemon).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        let direct = serve(create_router(
+            daemon.clone(),
+            &ProxyOptions::default(),
+            false,
+        ))
+        .await;
+        let mounted = ProxyOptions {
+            path_prefix: "/owonero/".to_string(),
+            trust_proxy: true,
+        };
+        let backend = serve(create_router(daemon, &mounted, false)).await;
+        let proxy = serve(
+            Router::new()
+                .fallback(reverse_proxy)
+                .with_state(backend.clone()),
+        )
+        .await;
+
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let get = |url: String| {
+            let client = client.clone();
+            async move { client.get(url).send().await.unwrap() }
+        };
+        let expected: Value = get(format!("http://{}/api/stats", direct))
+            .await
+            .json()
+            .await
+            .unwrap();
+        let proxied = get(format!("http://{}/owonero/api/stats", proxy)).await;
+        assert_eq!(proxied.status(), StatusCode::OK);
+        assert_eq!(proxied.json::<Value>().await.unwrap(), expected);
+        assert_eq!(expected["height"], 0);
+        assert_eq!(expected["read_only"], false);
+
+        // Only under the prefix
+        let unprefixed = get(format!("http://{}/api/stats", backend)).await;
+        assert_eq!(unprefixed.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn read_only_stats_server_refuses_everything_but_reads() {
+        // Nothing listens there, so reads fail further in
+        let daemon = "127.0.0.1:1".to_string();
+        let mirror = serve(create_router(
+            daemon.clone(),
+            &ProxyOptions::default(),
+            true,
+        ))
+        .await;
+        let normal = serve(create_router(daemon, &ProxyOptions::default(), false)).await;
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+
+        let posted = client
+            .post(format!("http://{}/api/stats", mirror))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(posted.status(), StatusCode::FORBIDDEN);
+        assert_eq!(posted.json::<Value>().await.unwrap()["error"], "Forbidden");
+        let read = client
+            .get(format!("http://{}/api/stats", mirror))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(read.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let posted = client
+            .post(format!("http://{}/api/stats", normal))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(posted.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn request_ids_reach_the_daemon_log_and_the_client() {
+        let lines = Arc::new(parking_lot::Mutex::new(Vec::<String>::new()));
+        let mut ctx = crate::rpc::DaemonContext::new(
+            Arc::new(parking_lot::Mutex::new(crate::blockchain::Blockchain::new())),
+            Arc::new(crate::daemon::PeerManager::new()),
+            false,
+        );
+        let captured = lines.clone();
+        ctx.log = Box::new(move |line| captured.lock().push(line.to_string()));
+        let ctx = Arc::new(ctx);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let daemon = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(crate::daemon::handle_websocket_connection(
+                    socket,
+                    ctx.clone(),
+                    false,
+                ));
+            }
+        });
+        let stats = serve(create_router(
+            daemon.clone(),
+            &ProxyOptions::default(),
+            false,
+        ))
+        .await;
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+
+        // The daemon refuses a height past its tip: 400, and its log line
+        // carries the id the client got
+        let resp = client
+            .get(format!(
+                "http://{}/api/address/04ab/balance?height=99",
+                stats
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let id = resp.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body: Value = resp.json().await.unwrap();
+        assert_eq!(body["request_id"], id.as_str());
+        let logged = lines.lock().clone();
+        assert!(
+            logged.iter().any(|l| l.contains(&format!("trace={}", id))
+                && l.contains("getbalance failed: height above chain tip")),
+            "{:?}",
+            logged
+        );
+
+        // Successful requests get an id too, and each one its own
+        let ok = client
+            .get(format!("http://{}/api/address/04ab/balance", stats))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(ok.status(), StatusCode::OK);
+        assert_ne!(ok.headers()[REQUEST_ID_HEADER].to_str().unwrap(), id);
+
+        // Other ws_client callers can trace too; the daemon echoes the id
+        let reply = crate::ws_client::traced(
+            "abc123".to_string(),
+            crate::ws_client::ws_command(&daemon, "getheight", json!({})),
+        )
+        .await
+        .unwrap();
+        assert_eq!(reply["trace"], "abc123");
+    }
+}
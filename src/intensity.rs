@@ -0,0 +1,422 @@
+//! Mining intensity: the share of time a worker thread spends hashing.
+//! `mining_intensity` sets it for the whole rig, `intensity_schedule`
+//! changes it for times of the week (full speed at night, a trickle during
+//! work hours) and `thread_intensity` caps single threads. Workers follow
+//! it by hashing in short slices and resting between them.
+
+use crate::config::IntensityWindow;
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+/// How long a throttled worker hashes before it rests.
+pub const DUTY_SLICE: Duration = Duration::from_millis(100);
+/// How often the schedule is looked at again.
+pub const SCHEDULE_CHECK: Duration = Duration::from_secs(60);
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const MINUTES_PER_WEEK: i64 = 7 * MINUTES_PER_DAY as i64;
+
+/// One `intensity_schedule` entry, parsed. Times are minutes since
+/// midnight; a window with `to` before `from` runs past midnight into the
+/// next day.
+#[derive(Debug, Clone, PartialEq)]
+struct Window {
+    /// Days the window starts on, Monday first.
+    days: [bool; 7],
+    from: u32,
+    to: u32,
+    intensity: u8,
+}
+
+impl Window {
+    /// Whether the window covers `minute` of the day `weekday` (Monday = 0).
+    fn covers(&self, weekday: usize, minute: u32) -> bool {
+        if self.from < self.to {
+            self.days[weekday] && (self.from..self.to).contains(&minute)
+        } else {
+            (self.days[weekday] && minute >= self.from)
+                || (self.days[(weekday + 6) % 7] && minute < self.to)
+        }
+    }
+}
+
+/// The intensity in force at a given time and when it next changes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting {
+    pub intensity: u8,
+    /// `None` when it never changes.
+    pub until: Option<NaiveDateTime>,
+}
+
+impl Setting {
+    /// E.g. "intensity 30% until 18:00", or "until Sat 09:00" when the
+    /// change isn't today.
+    pub fn describe(&self, now: NaiveDateTime) -> String {
+        match self.until {
+            Some(until) if until.date() == now.date() => {
+                format!(
+                    "intensity {}% until {}",
+                    self.intensity,
+                    until.format("%H:%M")
+                )
+            }
+            Some(until) => format!(
+                "intensity {}% until {}",
+                self.intensity,
+                until.format("%a %H:%M")
+            ),
+            None => format!("intensity {}%", self.intensity),
+        }
+    }
+}
+
+/// `intensity_schedule` and `thread_intensity`, checked.
+#[derive(Debug, Clone, Default)]
+pub struct IntensitySchedule {
+    windows: Vec<Window>,
+    threads: BTreeMap<usize, u8>,
+}
+
+impl IntensitySchedule {
+    /// Refuses unknown day names, times that aren't `HH:MM`, empty
+    /// windows and intensities over 100.
+    pub fn new(windows: &[IntensityWindow], threads: &BTreeMap<usize, u8>) -> Result<Self, String> {
+        let windows = windows
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                parse_window(w).map_err(|e| format!("intensity_schedule entry {}: {}", i + 1, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some((thread, percent)) = threads.iter().find(|(_, p)| **p > 100) {
+            return Err(format!(
+                "thread_intensity for thread {} is {}%, more than 100%",
+                thread, percent
+            ));
+        }
+        Ok(Self {
+            windows,
+            threads: threads.clone(),
+        })
+    }
+
+    /// Whether anything changes a thread's intensity from the global one.
+    pub fn is_active(&self) -> bool {
+        !self.windows.is_empty() || !self.threads.is_empty()
+    }
+
+    /// Intensity at local time `at`: the lowest of the windows covering
+    /// it, `base` when none does.
+    pub fn intensity_at(&self, base: u8, at: NaiveDateTime) -> u8 {
+        let weekday = at.weekday().num_days_from_monday() as usize;
+        let minute = at.hour() * 60 + at.minute();
+        self.windows
+            .iter()
+            .filter(|w| w.covers(weekday, minute))
+            .map(|w| w.intensity)
+            .min()
+            .unwrap_or(base)
+    }
+
+    /// Intensity at `at` and the minute it changes, looking up to a week
+    /// ahead.
+    pub fn setting_at(&self, base: u8, at: NaiveDateTime) -> Setting {
+        let intensity = self.intensity_at(base, at);
+        let start = at
+            .with_second(0)
+            .and_then(|t| t.with_nanosecond(0))
+            .unwrap_or(at);
+        let until = if self.windows.is_empty() {
+            None
+        } else {
+            (1..=MINUTES_PER_WEEK)
+                .map(|m| start + chrono::Duration::minutes(m))
+                .find(|t| self.intensity_at(base, *t) != intensity)
+        };
+        Setting { intensity, until }
+    }
+
+    /// Intensity of worker `thread` while the rig runs at `global`.
+    pub fn thread_intensity(&self, thread: usize, global: u8) -> u8 {
+        self.threads
+            .get(&thread)
+            .map_or(global, |cap| global.min(*cap))
+    }
+}
+
+fn parse_window(window: &IntensityWindow) -> Result<Window, String> {
+    if window.intensity > 100 {
+        return Err(format!(
+            "intensity is {}%, more than 100%",
+            window.intensity
+        ));
+    }
+    let from = parse_time(&window.from)?;
+    let to = parse_time(&window.to)?;
+    if from == MINUTES_PER_DAY {
+        return Err("a window can't start at 24:00".to_string());
+    }
+    if from == to {
+        return Err(format!("{} to {} is empty", window.from, window.to));
+    }
+    Ok(Window {
+        days: parse_days(&window.days)?,
+        from,
+        to,
+        intensity: window.intensity,
+    })
+}
+
+/// `HH:MM` as minutes since midnight; `24:00` is the end of the day.
+fn parse_time(s: &str) -> Result<u32, String> {
+    let bad = || format!("{:?} is not a time (HH:MM)", s);
+    let (h, m) = s.split_once(':').ok_or_else(bad)?;
+    let (h, m): (u32, u32) = (h.parse().map_err(|_| bad())?, m.parse().map_err(|_| bad())?);
+    match (h, m) {
+        (24, 0) => Ok(MINUTES_PER_DAY),
+        (0..=23, 0..=59) => Ok(h * 60 + m),
+        _ => Err(bad()),
+    }
+}
+
+/// Day names (`mon`, `Tuesday`, ...), `weekdays` and `weekends`; none
+/// means every day.
+fn parse_days(days: &[String]) -> Result<[bool; 7], String> {
+    if days.is_empty() {
+        return Ok([true; 7]);
+    }
+    let mut set = [false; 7];
+    for day in days {
+        match day.to_ascii_lowercase().as_str() {
+            "weekdays" => set[..5].fill(true),
+            "weekends" => set[5..].fill(true),
+            name => {
+                let weekday: chrono::Weekday = name
+                    .parse()
+                    .map_err(|_| format!("{:?} is not a day", day))?;
+                set[weekday.num_days_from_monday() as usize] = true;
+            }
+        }
+    }
+    Ok(set)
+}
+
+/// A worker's intensity, changed by the scheduler while it hashes.
+#[derive(Debug)]
+pub struct DutyCycle {
+    percent: AtomicU8,
+}
+
+impl DutyCycle {
+    pub fn new(percent: u8) -> Self {
+        Self {
+            percent: AtomicU8::new(percent),
+        }
+    }
+
+    pub fn percent(&self) -> u8 {
+        self.percent.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, percent: u8) {
+        self.percent.store(percent, Ordering::Relaxed);
+    }
+
+    /// Rest that `busy` of hashing earns at `percent`; `None` at 0%, which
+    /// rests until raised.
+    fn owed(percent: u8, busy: Duration) -> Option<Duration> {
+        match percent {
+            0 => None,
+            100.. => Some(Duration::ZERO),
+            p => Some(busy * u32::from(100 - p) / u32::from(p)),
+        }
+    }
+
+    /// Sleep off `busy` of hashing, in naps of at most `DUTY_SLICE` so a
+    /// new intensity or `interrupted` (the template is dropped) is noticed
+    /// promptly.
+    pub fn rest(&self, busy: Duration, interrupted: impl Fn() -> bool) {
+        let mut rested = Duration::ZERO;
+        loop {
+            let owed = Self::owed(self.percent(), busy);
+            if owed.is_some_and(|o| rested >= o) || interrupted() {
+                return;
+            }
+            let nap = owed.map_or(DUTY_SLICE, |o| (o - rested).min(DUTY_SLICE));
+            std::thread::sleep(nap);
+            rested += nap;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(days: &[&str], from: &str, to: &str, intensity: u8) -> IntensityWindow {
+        IntensityWindow {
+            days: days.iter().map(|d| d.to_string()).collect(),
+            from: from.to_string(),
+            to: to.to_string(),
+            intensity,
+        }
+    }
+
+    fn schedule(windows: &[IntensityWindow]) -> IntensitySchedule {
+        IntensitySchedule::new(windows, &BTreeMap::new()).unwrap()
+    }
+
+    /// 2024-01-01 was a Monday.
+    fn at(day: u32, time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(&format!("2024-01-{:02} {}", day, time), "%Y-%m-%d %H:%M")
+            .unwrap()
+    }
+
+    #[test]
+    fn schedule_entries_are_checked() {
+        let threads = BTreeMap::new();
+        let ok = |w: IntensityWindow| IntensitySchedule::new(&[w], &threads);
+        assert!(ok(window(&[], "09:00", "17:30", 20)).is_ok());
+        assert!(ok(window(&["Mon", "friday", "weekends"], "22:00", "06:00", 0)).is_ok());
+        assert!(ok(window(&[], "00:00", "24:00", 50)).is_ok());
+
+        let err = |w| ok(w).unwrap_err();
+        assert_eq!(
+            err(window(&["mon", "someday"], "09:00", "17:00", 20)),
+            "intensity_schedule entry 1: \"someday\" is not a day"
+        );
+        assert_eq!(
+            err(window(&[], "9am", "17:00", 20)),
+            "intensity_schedule entry 1: \"9am\" is not a time (HH:MM)"
+        );
+        assert!(err(window(&[], "24:30", "17:00", 20)).contains("not a time"));
+        assert!(err(window(&[], "09:60", "17:00", 20)).contains("not a time"));
+        assert_eq!(
+            err(window(&[], "09:00", "09:00", 20)),
+            "intensity_schedule entry 1: 09:00 to 09:00 is empty"
+        );
+        assert!(err(window(&[], "24:00", "06:00", 20)).contains("24:00"));
+        assert!(err(window(&[], "00:00", "24:00", 101)).contains("more than 100%"));
+
+        let greedy = BTreeMap::from([(3, 150)]);
+        assert_eq!(
+            IntensitySchedule::new(&[], &greedy).unwrap_err(),
+            "thread_intensity for thread 3 is 150%, more than 100%"
+        );
+    }
+
+    #[test]
+    fn windows_apply_on_their_days_and_overlaps_take_the_lowest() {
+        let s = schedule(&[
+            window(&["weekdays"], "09:00", "18:00", 30),
+            window(&["wed"], "12:00", "13:00", 10),
+        ]);
+        // Monday
+        assert_eq!(s.intensity_at(100, at(1, "08:59")), 100);
+        assert_eq!(s.intensity_at(100, at(1, "09:00")), 30);
+        assert_eq!(s.intensity_at(100, at(1, "17:59")), 30);
+        assert_eq!(s.intensity_at(100, at(1, "18:00")), 100);
+        // Wednesday lunch
+        assert_eq!(s.intensity_at(100, at(3, "12:30")), 10);
+        assert_eq!(s.intensity_at(100, at(3, "13:00")), 30);
+        // Saturday
+        assert_eq!(s.intensity_at(100, at(6, "12:00")), 100);
+        // A window may raise a lowered base, too
+        let nights = schedule(&[window(&[], "00:00", "06:00", 100)]);
+        assert_eq!(nights.intensity_at(40, at(2, "03:00")), 100);
+        assert_eq!(nights.intensity_at(40, at(2, "06:00")), 40);
+    }
+
+    #[test]
+    fn windows_crossing_midnight_belong_to_the_day_they_start() {
+        let s = schedule(&[window(&["fri"], "22:00", "06:00", 5)]);
+        // Thursday night isn't covered
+        assert_eq!(s.intensity_at(100, at(4, "23:00")), 100);
+        assert_eq!(s.intensity_at(100, at(5, "03:00")), 100);
+        // Friday night runs into Saturday morning
+        assert_eq!(s.intensity_at(100, at(5, "21:59")), 100);
+        assert_eq!(s.intensity_at(100, at(5, "22:00")), 5);
+        assert_eq!(s.intensity_at(100, at(6, "00:00")), 5);
+        assert_eq!(s.intensity_at(100, at(6, "05:59")), 5);
+        assert_eq!(s.intensity_at(100, at(6, "06:00")), 100);
+
+        // Sunday night wraps into Monday
+        let sunday = schedule(&[window(&["sun"], "23:00", "01:00", 0)]);
+        assert_eq!(sunday.intensity_at(100, at(1, "00:30")), 0);
+        assert_eq!(sunday.intensity_at(100, at(7, "23:30")), 0);
+        assert_eq!(sunday.intensity_at(100, at(2, "00:30")), 100);
+    }
+
+    #[test]
+    fn settings_say_when_they_end() {
+        let s = schedule(&[
+            window(&["weekdays"], "09:00", "18:00", 30),
+            window(&["weekdays"], "17:00", "19:00", 50),
+        ]);
+        let now = at(1, "10:15");
+        let setting = s.setting_at(100, now);
+        assert_eq!(setting.intensity, 30);
+        assert_eq!(setting.until, Some(at(1, "18:00")));
+        assert_eq!(setting.describe(now), "intensity 30% until 18:00");
+        // Then the second window until it ends
+        assert_eq!(
+            s.setting_at(100, at(1, "18:00")).until,
+            Some(at(1, "19:00"))
+        );
+
+        // Friday evening lasts until Monday morning
+        let now = at(5, "19:30");
+        let weekend = s.setting_at(100, now);
+        assert_eq!(weekend.intensity, 100);
+        assert_eq!(weekend.until, Some(at(8, "09:00")));
+        assert_eq!(weekend.describe(now), "intensity 100% until Mon 09:00");
+
+        // Without a schedule nothing ever changes
+        let constant = IntensitySchedule::default().setting_at(70, now);
+        assert_eq!(constant.until, None);
+        assert_eq!(constant.describe(now), "intensity 70%");
+        // Nor with one covering the whole week
+        let always = schedule(&[window(&[], "00:00", "24:00", 20)]);
+        assert_eq!(always.setting_at(100, now).until, None);
+    }
+
+    #[test]
+    fn threads_are_capped_below_the_global_intensity() {
+        let threads = BTreeMap::from([(0, 100), (2, 25)]);
+        let s = IntensitySchedule::new(&[], &threads).unwrap();
+        assert!(s.is_active());
+        assert!(!IntensitySchedule::default().is_active());
+        assert_eq!(s.thread_intensity(0, 60), 60);
+        assert_eq!(s.thread_intensity(1, 60), 60);
+        assert_eq!(s.thread_intensity(2, 60), 25);
+        assert_eq!(s.thread_intensity(2, 10), 10);
+    }
+
+    #[test]
+    fn duty_cycle_rests_in_proportion_to_the_work() {
+        let busy = Duration::from_millis(100);
+        assert_eq!(DutyCycle::owed(100, busy), Some(Duration::ZERO));
+        assert_eq!(DutyCycle::owed(50, busy), Some(busy));
+        assert_eq!(DutyCycle::owed(25, busy), Some(Duration::from_millis(300)));
+        assert_eq!(DutyCycle::owed(0, busy), None);
+
+        // Paused threads rest until they are raised or interrupted
+        let duty = DutyCycle::new(0);
+        let started = std::time::Instant::now();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(250));
+                duty.set(100);
+            });
+            duty.rest(busy, || false);
+        });
+        assert!(started.elapsed() >= Duration::from_millis(250));
+        assert_eq!(duty.percent(), 100);
+
+        let started = std::time::Instant::now();
+        DutyCycle::new(1).rest(Duration::from_secs(1), || true);
+        assert!(started.elapsed() < DUTY_SLICE);
+    }
+}
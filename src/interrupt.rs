@@ -0,0 +1,46 @@
+//! Ctrl+C for every CLI mode. The first one cancels a process-wide token
+//! that long-running loops (chain sync, batch sends, snapshot checks) look
+//! at between units of work, so they stop where everything they wrote is
+//! whole and say how far they got. A second one exits on the spot.
+
+use std::sync::OnceLock;
+use tokio_util::sync::CancellationToken;
+
+/// Exit status after Ctrl+C, as shells report a SIGINT death.
+pub const EXIT_INTERRUPTED: u8 = 130;
+
+static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+/// The token Ctrl+C cancels. Tests pass their own instead.
+pub fn token() -> &'static CancellationToken {
+    TOKEN.get_or_init(CancellationToken::new)
+}
+
+/// Take over Ctrl+C. Call once, early in `main`; from then on the process
+/// only stops where a mode checks the token, or on a second Ctrl+C.
+pub fn install() {
+    let token = token();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        eprintln!("\nInterrupted: finishing the current step (Ctrl+C again to quit now)");
+        token.cancel();
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("Interrupted twice: quitting");
+            std::process::exit(EXIT_INTERRUPTED.into());
+        }
+    });
+}
+
+/// What a mode stopped by Ctrl+C reports: how far its saved state goes.
+#[derive(Debug, thiserror::Error)]
+#[error("interrupted — {0}")]
+pub struct Interrupted(pub String);
+
+impl Interrupted {
+    /// Stopped with the local chain saved up to `height`.
+    pub fn saved_through(height: u64) -> Self {
+        Self(format!("state saved through block {}", height))
+    }
+}
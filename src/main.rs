@@ -1,20 +1,47 @@
+// Locks are std mutexes; holding one across an await stalls the runtime.
+#![deny(clippy::await_holding_lock)]
+
+mod address_book;
+mod batch;
 mod blockchain;
+mod build_info;
+mod chain_backup;
+mod chain_sync;
 mod completions;
 mod config;
 mod daemon;
+mod disk;
+mod doctor;
+mod error;
 mod http_api;
+mod intensity;
+mod interrupt;
 mod miner;
+mod miner_log;
 mod miner_ui;
+mod net;
+mod policy;
+mod power;
+mod protocol;
+mod receipts;
+mod receive;
+mod reload;
+mod retry;
+mod rpc;
+mod snapshot;
+#[cfg(any(test, feature = "testkit"))]
+mod testkit;
 mod update;
 mod wallet;
+mod wallet_stats;
+mod watch;
 mod ws_client;
 
-use clap::{Parser, ValueHint};
+use clap::{Parser, Subcommand, ValueHint};
 use colored::Colorize;
+use error::AppError;
 use std::sync::Arc;
 
-use serde_json;
-
 const ASCII_LOGO: &str = r#"⡰⠁⠀⠀⢀⢔⣔⣤⠐⠒⠒⠒⠒⠠⠄⢀⠀⠐⢀⠀⠀⠀⠀⠀⠀⠀
 ⠀⠀⠀⡐⢀⣾⣷⠪⠑⠛⠛⠛⠂⠠⠶⢶⣿⣦⡀⠀⠈⢐⢠⣑⠤⣀⠀⠀⠀
 ⠀⢀⡜⠀⢸⠟⢁⠔⠁⠀⠀⠀⠀⠀⠀⠀⠉⠻⢷⠀⠀⠀⡦⢹⣷⣄⠀⢀⣀⡀
@@ -34,10 +61,19 @@ const ASCII_LOGO: &str = r#"⡰⠁⠀⠀⢀⢔⣔⣤⠐⠒⠒⠒⠒⠠⠄⢀⠀
 
 #[derive(Parser)]
 #[command(name = "owonero")]
-#[command(version = env!("CARGO_PKG_VERSION"))]
+#[command(version = env!("CARGO_PKG_VERSION"), disable_version_flag = true)]
 #[command(about = "Owonero cryptocurrency miner/daemon")]
+#[command(after_help = error::EXIT_CODES_HELP)]
 struct Cli {
-    /// Run daemon in standalone mode (no peers)
+    /// Print version
+    #[arg(short = 'V', long)]
+    version: bool,
+
+    /// With --version: also print the commit, build time, target, rustc and features
+    #[arg(long, requires = "version")]
+    verbose: bool,
+
+    /// Standalone mode: own network id, no peers, never talks to the public network
     #[arg(short = 's', long)]
     standalone: bool,
     /// Run as daemon
@@ -49,13 +85,17 @@ struct Cli {
     #[arg(long)]
     miner_ui: bool,
 
+    /// Mine without the TUI, printing periodic stats lines instead
+    #[arg(long)]
+    no_ui: bool,
+
     /// Daemon port
-    #[arg(short = 'p', long, default_value = "6969", value_hint = ValueHint::Other)]
+    #[arg(short = 'p', long, default_value_t = protocol::DEFAULT_DAEMON_PORT, value_hint = ValueHint::Other)]
     // Hint for port numbers
     port: u16,
 
     /// Web stats server port
-    #[arg(long, default_value = "6767", value_hint = ValueHint::Other)]
+    #[arg(long, default_value_t = protocol::DEFAULT_WEB_PORT, value_hint = ValueHint::Other)]
     // Hint for port numbers
     web_port: u16,
 
@@ -82,7 +122,7 @@ struct Cli {
     intensity: u8,
 
     /// Node address (host:port)
-    #[arg(short = 'n', long, default_value = "owonero.yabai.buzz:6969", value_hint = ValueHint::Hostname)]
+    #[arg(short = 'n', long, global = true, default_value = protocol::DEFAULT_NODE, value_hint = ValueHint::Hostname)]
     // Hostname/port completion
     node_addr: String,
 
@@ -91,6 +131,32 @@ struct Cli {
     // Numeric hint
     threads: usize,
 
+    /// Treat --threads as a maximum and tune the active thread count for the best hashrate
+    #[arg(long)]
+    auto_threads: bool,
+
+    /// Mine even when the memory check says not one thread fits
+    #[arg(long)]
+    force: bool,
+
+    /// Average power draw while mining, in watts; enables kWh-per-block estimates
+    #[arg(long, value_hint = ValueHint::Other)]
+    power_watts: Option<f64>,
+
+    /// Pause mining while the machine runs on battery (saved as `pause_on_battery`)
+    #[arg(long)]
+    pause_on_battery: bool,
+
+    /// Pay mined blocks to this address instead of the local wallet, which
+    /// still identifies the rig in stats and logs (saved as `mine_to`)
+    #[arg(long, value_name = "ADDRESS")]
+    mine_to: Option<String>,
+
+    /// Mine even when there is no chain to build on but genesis; only for
+    /// starting a new network
+    #[arg(long)]
+    allow_genesis_mining: bool,
+
     #[arg(long = "install-completions", value_name = "SHELL")]
     pub install_completions: Option<String>,
 
@@ -114,7 +180,8 @@ struct Cli {
     #[arg(long = "tx-history")]
     tx_history: bool,
 
-    /// Destination address for sending OWE
+    /// Destination address for sending OWE, an address book name, or an
+    /// `owonero:` payment URI (whose amount is used when --amount is omitted)
     #[arg(long, value_hint = ValueHint::Other)]
     // Could be a wallet address; use Other for custom
     to: Option<String>,
@@ -122,19 +189,331 @@ struct Cli {
     /// Amount to send (can be decimal, e.g. 1.5)
     #[arg(long, value_hint = ValueHint::Other)] // Numeric/decimal hint
     amount: Option<f64>,
+
+    /// With --send: destroy this many OWE by paying the burn address; asks
+    /// for confirmation first
+    #[arg(long, value_name = "AMOUNT", value_hint = ValueHint::Other, conflicts_with_all = ["to", "amount", "batch", "broadcast"])]
+    burn: Option<f64>,
+
+    /// Ignore the wallet's cached balance and rescan the whole chain
+    #[arg(long)]
+    rescan: bool,
+
+    /// Don't contact any node (no sync, no update check); show what the
+    /// local chain says
+    #[arg(long)]
+    offline: bool,
+
+    /// After sending, wait until the transaction is confirmed or rejected
+    #[arg(long)]
+    wait: bool,
+
+    /// With --send: the transaction can only be mined within this many blocks
+    #[arg(long, value_name = "BLOCKS", conflicts_with_all = ["batch", "broadcast"])]
+    expires_in: Option<u64>,
+
+    /// With --send: write an unsigned transaction to --out instead of signing and submitting
+    #[arg(long, requires = "out")]
+    offline_create: bool,
+
+    /// Sender address for --offline-create (defaults to this wallet)
+    #[arg(long, value_hint = ValueHint::Other)]
+    from: Option<String>,
+
+    /// Output file for --offline-create
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    out: Option<std::path::PathBuf>,
+
+    /// Verify a file written by `wallet sign-tx` and submit it to the node
+    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with = "offline_create")]
+    broadcast: Option<std::path::PathBuf>,
+
+    /// With --send: pay every `address,amount[,memo]` row of a CSV or JSON file
+    #[arg(long, value_hint = ValueHint::FilePath, conflicts_with_all = ["to", "amount", "offline_create", "broadcast"])]
+    batch: Option<std::path::PathBuf>,
+
+    /// With --batch: validate and print the payouts without sending anything
+    #[arg(long, requires = "batch")]
+    dry_run: bool,
+
+    /// With --batch: skip rows already sent according to this results file
+    #[arg(long, requires = "batch", value_hint = ValueHint::FilePath)]
+    skip_completed: Option<std::path::PathBuf>,
+
+    /// With --batch: where to write per-row results (default: <batch>.results.json)
+    #[arg(long, requires = "batch", value_hint = ValueHint::FilePath)]
+    results: Option<std::path::PathBuf>,
+
+    /// Print errors as one-line JSON objects on stderr
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Skip the logo and the startup banner
+    #[arg(long, global = true)]
+    quiet: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn load_and_merge_config(cli: &Cli) -> anyhow::Result<config::Config> {
-    // Load config
-    let config_path: std::path::PathBuf = dirs::config_dir()
-        .unwrap_or_else(|| std::env::temp_dir())
-        .join("owonero")
-        .join("config.json");
-    let mut config = if config_path.exists() {
-        config::load_config()?
-    } else {
-        config::Config::default()
-    };
+#[derive(Subcommand)]
+enum Command {
+    /// Print the version with the commit, build time, target, rustc and features
+    Version,
+    /// Print every daemon RPC method with its parameters and response
+    /// fields as JSON, from the same table the daemon serves
+    ProtocolSchema,
+    /// Check config, wallet, chain file and node connectivity; exits non-zero on failure
+    Doctor,
+    /// Instant-mine helpers for local testing against a dev_mode node
+    Dev {
+        #[command(subcommand)]
+        action: DevCommand,
+    },
+    /// Node administration (only accepted over a local connection)
+    Admin {
+        #[command(subcommand)]
+        action: AdminCommand,
+    },
+    /// Offline wallet operations
+    Wallet {
+        #[command(subcommand)]
+        action: WalletCommand,
+    },
+    /// Local chain maintenance
+    Chain {
+        #[command(subcommand)]
+        action: ChainCommand,
+    },
+    /// Confirmed balance of an address, now or after a past block
+    Balance {
+        /// Address (defaults to this wallet)
+        address: Option<String>,
+        /// Balance as it was right after this block
+        #[arg(long, value_name = "HEIGHT")]
+        at_height: Option<u64>,
+    },
+    /// Print payments to or from an address as the node sees them; runs until Ctrl+C
+    Watch {
+        /// Address to watch (defaults to this wallet)
+        #[arg(long)]
+        address: Option<String>,
+    },
+    /// Print a line for every new block and transaction on the node (`--json`
+    /// for JSON lines); runs until Ctrl+C
+    Tail,
+    /// Look up sent transactions
+    Tx {
+        #[command(subcommand)]
+        action: TxCommand,
+    },
+    /// Receipts of transactions sent from this machine
+    Receipts {
+        #[command(subcommand)]
+        action: ReceiptsCommand,
+    },
+    /// Mine over the getwork protocol: one thread, no local chain. A
+    /// reference for third-party miners rather than a fast miner
+    Getwork {
+        /// Stop after this many accepted blocks
+        #[arg(long, default_value_t = 1)]
+        blocks: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum TxCommand {
+    /// Ask a node whether a transaction is pending, confirmed or rejected,
+    /// and note the answer on its receipt
+    Status { txid: String },
+}
+
+#[derive(Subcommand)]
+enum ReceiptsCommand {
+    /// List receipts, newest first (`--json` for a JSON array)
+    List,
+}
+
+#[derive(Subcommand)]
+enum ChainCommand {
+    /// Install a verified chain snapshot as the local chain
+    Bootstrap {
+        /// Snapshot URL (e.g. http://node:6767/api/snapshot) or file
+        #[arg(long)]
+        from: String,
+        /// Validate every block instead of only the last few
+        #[arg(long)]
+        full_verify: bool,
+    },
+    /// Put back blocks saved before a chain was replaced (see `backups/` in
+    /// the config directory)
+    RestoreBackup {
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: std::path::PathBuf,
+    },
+    /// Replace the local chain with a node's taller one, e.g. after the
+    /// daemon refused a reorg deeper than `max_reorg_depth`
+    Adopt {
+        /// Node to fetch the chain from
+        #[arg(long)]
+        from: String,
+        /// Adopt even if it drops more than `max_reorg_depth` local blocks
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum WalletCommand {
+    /// Sign a transaction written by `--send --offline-create`; needs no node
+    SignTx {
+        #[arg(long = "in", value_hint = ValueHint::FilePath)]
+        input: std::path::PathBuf,
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        out: std::path::PathBuf,
+    },
+    /// List the blocks this wallet mined, newest first
+    Mined {
+        /// Miner address (defaults to this wallet)
+        #[arg(long)]
+        address: Option<String>,
+        /// Blocks per page
+        #[arg(long, default_value = "20")]
+        limit: usize,
+        /// Only list blocks below this height (from the previous page)
+        #[arg(long)]
+        before: Option<u64>,
+    },
+    /// Sign a proof that this wallet holds its current balance
+    ProveReserve {
+        /// Challenge chosen by the party asking for the proof
+        #[arg(long)]
+        message: String,
+        /// Write the proof here instead of printing it
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        out: Option<std::path::PathBuf>,
+    },
+    /// Check a reserve proof's signature and its balance claim against the node
+    VerifyReserve {
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        proof: std::path::PathBuf,
+    },
+    /// Create a new wallet with a fresh key
+    New {
+        /// Wallet file to create (defaults to --wallet-path)
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        out: Option<std::path::PathBuf>,
+        /// Replace an existing wallet file, keeping a timestamped copy of it
+        #[arg(long)]
+        force: bool,
+    },
+    /// Rebuild a wallet file from a private key backup (prompted, or read from --file)
+    #[command(visible_alias = "restore")]
+    ImportKey {
+        /// File holding the hex private key; prompts on stdin when omitted
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        file: Option<std::path::PathBuf>,
+        /// Wallet file to create (defaults to --wallet-path)
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        out: Option<std::path::PathBuf>,
+        /// Replace an existing wallet file, keeping a timestamped copy of it
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print this wallet's private key after a typed confirmation
+    ExportKey,
+    /// Print this wallet's address with a QR code to scan, optionally as a
+    /// payment URI asking for an amount
+    Receive {
+        /// Amount to ask for (can be decimal, e.g. 1.5)
+        #[arg(long)]
+        amount: Option<f64>,
+        /// Note shown to the payer; not stored in the transaction
+        #[arg(long)]
+        memo: Option<String>,
+    },
+    /// Name an address in the address book, or forget it when no name is given
+    Label {
+        address: String,
+        name: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AdminCommand {
+    /// Export or import the node's pending transactions
+    Mempool {
+        #[command(subcommand)]
+        action: MempoolCommand,
+    },
+    /// Recent mempool admission decisions with the sender's balance at the time
+    MempoolJournal {
+        /// Entries to show, newest first
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum MempoolCommand {
+    /// Write the node's mempool to a JSON file
+    Export {
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        file: std::path::PathBuf,
+    },
+    /// Submit every transaction in a JSON file through the node's normal checks
+    Import {
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        file: std::path::PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum DevCommand {
+    /// Generate <n> blocks without PoW, paying the block reward to --to
+    MineBlocks {
+        n: u64,
+        /// Reward address (defaults to this wallet)
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Mint OWE to an address in a single block
+    Faucet {
+        /// Recipient address (defaults to this wallet)
+        #[arg(long)]
+        to: Option<String>,
+        /// Amount in OWE (can be decimal)
+        #[arg(long)]
+        amount: f64,
+    },
+    /// Write a deterministic fixture chain and its wallets; no node needed
+    #[cfg(feature = "testkit")]
+    GenerateChain {
+        /// Blocks after genesis
+        #[arg(long)]
+        blocks: u64,
+        /// Chain file; the wallets go next to it as <name>.wallets.json
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        out: std::path::PathBuf,
+        /// Same seed, same chain, byte for byte
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        #[arg(long, default_value_t = 4)]
+        wallets: usize,
+        /// Transfers per block, as far as mined balances allow
+        #[arg(long, default_value_t = 2)]
+        txs_per_block: usize,
+        #[arg(long, default_value_t = 1)]
+        difficulty: u32,
+        /// Mine every block to its difficulty instead of skipping PoW
+        #[arg(long)]
+        real_pow: bool,
+    },
+}
+
+fn load_and_merge_config(cli: &Cli) -> Result<config::Config, AppError> {
+    // Load config (falls back to defaults when no file exists)
+    let mut config = config::load_config().map_err(AppError::local_file)?;
 
     // Override config with CLI args
     config.node_address = cli.node_addr.clone();
@@ -147,22 +526,74 @@ fn load_and_merge_config(cli: &Cli) -> anyhow::Result<config::Config> {
         .as_ref()
         .map(|s| s.split(',').map(|s| s.trim().to_string()).collect())
         .unwrap_or_default();
-    config.auto_update = !cli.no_update;
-    config.sync_on_startup = true;
+    config.auto_update = !cli.no_update && !cli.offline;
+    config.sync_on_startup = !cli.offline;
     config.target_block_time = 30;
     config.mining_intensity = cli.intensity;
     config.pool = cli.pool;
+    if let Some(address) = &cli.mine_to {
+        config.mine_to = Some(address.clone());
+    }
+    if cli.pause_on_battery {
+        config.pause_on_battery = true;
+    }
 
     // Save updated config
-    config::save_config(&config)?;
+    config::save_config(&config).map_err(AppError::local_file)?;
 
+    if cli.standalone {
+        apply_standalone(&mut config);
+    }
     Ok(config)
 }
 
+/// Keep a standalone run off the public network: drop the default node
+/// from the node address and peers (the local daemon takes its place).
+/// Applied after saving so the config file is left as it was.
+fn apply_standalone(config: &mut config::Config) {
+    config.standalone = true;
+    if normalize_node_addr(&config.node_address) == protocol::DEFAULT_NODE {
+        config.node_address = format!("127.0.0.1:{}", config.daemon_port);
+    }
+    config
+        .peers
+        .retain(|p| normalize_node_addr(p) != protocol::DEFAULT_NODE);
+}
+
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> std::process::ExitCode {
     let cli = Cli::parse();
+    let version_command = matches!(cli.command, Some(Command::Version));
+    if cli.version || version_command {
+        let info = build_info::current();
+        if cli.verbose || version_command {
+            println!("{}", info.verbose());
+        } else {
+            println!("owonero {}", info.version);
+        }
+        return std::process::ExitCode::SUCCESS;
+    }
+    if let Some(Command::ProtocolSchema) = cli.command {
+        // Machine-readable on stdout: no logo, no config
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&rpc::protocol_schema()).unwrap_or_default()
+        );
+        return std::process::ExitCode::SUCCESS;
+    }
+    // Before any mode starts, so a Ctrl+C anywhere ends at a safe point
+    interrupt::install();
+    let json = cli.json;
+    match run(cli).await {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e.to_line(json));
+            std::process::ExitCode::from(e.exit_code())
+        }
+    }
+}
 
+async fn run(cli: Cli) -> Result<(), AppError> {
     // Handle completions installation/printing
     if let Some(shell) = &cli.install_completions {
         if shell == "stdout" {
@@ -180,77 +611,186 @@ async fn main() -> anyhow::Result<()> {
         env!("CARGO_PKG_VERSION"),
         option_env!("GIT_HASH_SHORT").unwrap_or("unknown")
     );
-    println!("{}", ASCII_LOGO.replace("%s", &full_version).purple());
+    if !cli.quiet {
+        println!("{}", ASCII_LOGO.replace("%s", &full_version).purple());
+    }
 
     let config = load_and_merge_config(&cli)?;
+    ws_client::set_request_compression(config.ws_compression);
+    daemon::set_max_message_bytes(config.max_message_bytes);
+    daemon::set_max_mempool_depth(config.max_mempool_depth);
+    ws_client::set_standalone_only(config.standalone);
+    blockchain::set_scratchpad_pool_size(config.scratchpad_pool_size);
+    blockchain::set_strict_consensus(config.strict_consensus);
+    policy::set_blocklist_source(
+        config.blocked_addresses.clone(),
+        config
+            .blocked_addresses_file
+            .as_ref()
+            .map(std::path::PathBuf::from),
+    );
 
-    // Ensure a wallet exists in the config directory. Try to load it; if it
-    // doesn't exist or loading fails, create a new wallet and save it so the
-    // rest of the program can assume a wallet file is present.
-    match config::load_wallet() {
-        Ok(_) => {
-            // wallet exists or was created by load_wallet()
-        }
-        Err(e) => {
-            eprintln!(
-                "Wallet not found or failed to load: {}. Creating a new wallet...",
-                e
-            );
-            match crate::wallet::Wallet::new() {
-                Ok(wallet) => {
-                    let path = &config.wallet_path;
-                    let p = std::path::Path::new(path);
-                    if let Some(parent) = p.parent() {
-                        if !parent.as_os_str().is_empty() {
-                            let _ = std::fs::create_dir_all(parent);
-                        }
-                    }
-                    if let Ok(data) = serde_json::to_string_pretty(&wallet) {
-                        if let Err(err) = std::fs::write(path, data) {
-                            eprintln!("Failed to write new wallet to {}: {}", path, err);
-                        } else {
-                            eprintln!("Created new wallet at {}", path);
-                        }
-                    }
-                }
-                Err(err) => eprintln!("Failed to generate new wallet: {}", err),
-            }
-        }
+    if let Some(Command::Doctor) = &cli.command {
+        return doctor::run_doctor(&config)
+            .await
+            .map_err(|e| AppError::rejected(e.to_string()));
     }
 
     // Check for updates if enabled
-    if config.auto_update {
-        if let Err(e) = update::check_for_updates().await {
-            eprintln!("{}", format!("Failed to check for updates: {}", e).red());
-        }
+    if config.auto_update
+        && let Err(e) = update::check_for_updates().await
+    {
+        eprintln!("{}", format!("Failed to check for updates: {}", e).red());
     }
 
-    println!("{}", format!("OWONERO-RS {}", full_version).green());
+    if !cli.quiet && !cli.json {
+        println!("{}", format!("OWONERO-RS {}", full_version).green());
+        let wallet = wallet::load_wallet_file(&config.wallet_path).ok();
+        print_banner(&resolve_settings(&config, wallet.as_ref()));
+    }
 
     // Route to appropriate command handler
-    if cli.daemon {
+    if let Some(Command::Dev { action }) = cli.command {
+        run_dev_mode(action, config).await
+    } else if let Some(Command::Admin { action }) = cli.command {
+        run_admin_mode(action, config).await
+    } else if let Some(Command::Wallet { action }) = cli.command {
+        run_wallet_command(action, config).await
+    } else if let Some(Command::Chain { action }) = cli.command {
+        run_chain_command(action, &config).await
+    } else if let Some(Command::Balance { address, at_height }) = cli.command {
+        run_balance_mode(address, at_height, config).await
+    } else if let Some(Command::Watch { address }) = cli.command {
+        run_watch_mode(address, cli.json, config).await
+    } else if let Some(Command::Tail) = cli.command {
+        run_tail_mode(cli.json, config).await
+    } else if let Some(Command::Tx { action }) = cli.command {
+        run_tx_command(action, config).await
+    } else if let Some(Command::Receipts { action }) = cli.command {
+        run_receipts_command(action, cli.json)
+    } else if let Some(Command::Getwork { blocks }) = cli.command {
+        run_getwork_mode(blocks, config).await
+    } else if cli.daemon {
         run_daemon_mode(cli, config).await
     } else if cli.mine {
         run_mining_mode(cli, config).await
-    } else if cli.send {
+    } else if cli.send || cli.broadcast.is_some() {
         // CLI send mode: owonero --send --amount <amt> --to <pubkey>
         run_send_mode(cli, config).await
     } else if cli.tx_history {
-        run_tx_history_mode(config).await
+        run_tx_history_mode(cli.json, config).await
     } else {
         // Default to wallet info if no mode flag is set
-        run_wallet_info_mode(config).await
+        run_wallet_info_mode(config, cli.rescan, cli.json).await
+    }
+}
+
+async fn run_balance_mode(
+    address: Option<String>,
+    at_height: Option<u64>,
+    config: config::Config,
+) -> Result<(), AppError> {
+    let wallet = wallet::load_wallet_file(&config.wallet_path);
+    let address = match (address, &wallet) {
+        (Some(address), _) => address,
+        (None, Ok(wallet)) => wallet.address.clone(),
+        (None, Err(e)) => return Err(AppError::local_file(anyhow::anyhow!("{}", e))),
+    };
+    let wallet_node = wallet.as_ref().ok().and_then(|w| w.node_address.as_deref());
+    let nodes = node_candidates(wallet_node, &config);
+    let (_, (height, balance)) = with_node_fallback(&nodes, |node| {
+        let address = address.clone();
+        async move { ws_client::fetch_balance(&node, &address, at_height).await }
+    })
+    .await?;
+    println!(
+        "Balance of {} at height {}: {}",
+        address,
+        height,
+        blockchain::Blockchain::format_owe(balance)
+    );
+    if wallet::is_burn_address(&address) {
+        println!("This is the burn address: these coins can never be spent.");
     }
+    Ok(())
+}
+
+async fn run_watch_mode(
+    address: Option<String>,
+    json: bool,
+    config: config::Config,
+) -> Result<(), AppError> {
+    let wallet = wallet::load_wallet_file(&config.wallet_path);
+    let address = match (address, &wallet) {
+        (Some(address), _) => address,
+        (None, Ok(wallet)) => wallet.address.clone(),
+        (None, Err(e)) => return Err(AppError::local_file(anyhow::anyhow!("{}", e))),
+    };
+    let wallet_node = wallet.as_ref().ok().and_then(|w| w.node_address.as_deref());
+    let nodes = node_candidates(wallet_node, &config);
+    tokio::select! {
+        _ = watch::run_watch(&nodes, &address, json) => Ok(()),
+        _ = interrupt::token().cancelled() => Ok(()),
+    }
+}
+
+/// Follows `-n` (then the configured peers) rather than the wallet's
+/// remembered node: no wallet is needed to tail a node.
+async fn run_tail_mode(json: bool, config: config::Config) -> Result<(), AppError> {
+    let nodes = node_candidates(None, &config);
+    tokio::select! {
+        _ = watch::run_tail(&nodes, json) => Ok(()),
+        _ = interrupt::token().cancelled() => Ok(()),
+    }
+}
+
+async fn run_getwork_mode(blocks: u64, config: config::Config) -> Result<(), AppError> {
+    let wallet = wallet::load_wallet_file(&config.wallet_path)
+        .map_err(|e| AppError::local_file(anyhow::anyhow!("{}", e)))?;
+    let nodes = node_candidates(wallet.node_address.as_deref(), &config);
+    // No attempt timeout here: mining runs as long as it takes
+    let mut failures = Vec::new();
+    for node in &nodes {
+        match ws_client::mine_getwork(node, &wallet.address, blocks).await {
+            Ok(heights) => {
+                for height in heights {
+                    println!("Mined block {} via {}", height, node);
+                }
+                return Ok(());
+            }
+            Err(e) => failures.push(format!("{} ({})", node, e)),
+        }
+    }
+    Err(AppError::network(anyhow::anyhow!(
+        "no node reachable, tried: {}",
+        failures.join(", ")
+    )))
 }
 
 // Blockchain path lives in the config directory, use `config::get_blockchain_path()`.
 
-async fn run_daemon_mode(cli: Cli, config: config::Config) -> anyhow::Result<()> {
+async fn run_daemon_mode(cli: Cli, config: config::Config) -> Result<(), AppError> {
     // Load local blockchain from file (daemon is authoritative)
     let loaded_chain = blockchain::Blockchain::load_from_file(crate::config::get_blockchain_path())
         .unwrap_or_else(|_| blockchain::Blockchain::new());
-    let blockchain = Arc::new(std::sync::Mutex::new(loaded_chain));
-    let pm = Arc::new(daemon::PeerManager::new());
+    let blockchain = Arc::new(parking_lot::Mutex::new(loaded_chain));
+    // Configured peers always count; the limit is for learned ones
+    let mut pm =
+        daemon::PeerManager::for_node(config.daemon_port, config.peers.len() + config.max_peers);
+    pm.set_max_failures(config.peer_max_failures);
+    // Peers learned on earlier runs, so losing the seed node isn't fatal
+    if !config.standalone {
+        pm = pm.with_store(config::get_peers_path());
+        match pm.load_saved(
+            config.peer_max_age_days * 24 * 3600,
+            chrono::Utc::now().timestamp().max(0) as u64,
+        ) {
+            Ok(0) => {}
+            Ok(n) => println!("Loaded {} saved peer(s)", n),
+            Err(e) => eprintln!("Warning: starting without saved peers: {:#}", e),
+        }
+    }
+    let pm = Arc::new(pm);
 
     // Add peers from config
     for peer in &config.peers {
@@ -260,17 +800,44 @@ async fn run_daemon_mode(cli: Cli, config: config::Config) -> anyhow::Result<()>
     let daemon_port = config.daemon_port;
     let web_port = config.web_port;
     let daemon_addr = format!("127.0.0.1:{}", daemon_port);
+    let read_only = config.read_only;
+    let proxy = http_api::ProxyOptions {
+        path_prefix: config.web_path_prefix.clone(),
+        trust_proxy: config.trust_proxy,
+    };
 
     println!(
         "{}",
-        format!("Starting daemon on :{} and stats server on :{}", daemon_port, web_port).cyan()
+        format!(
+            "Starting daemon on :{} and stats server on :{}",
+            daemon_port, web_port
+        )
+        .cyan()
     );
 
+    if config.snapshot_interval_secs > 0 {
+        snapshot::spawn_writer(
+            blockchain.clone(),
+            config::get_snapshot_path(),
+            std::time::Duration::from_secs(config.snapshot_interval_secs),
+        );
+    }
+
     // Spawn WebSocket daemon
     let standalone = cli.standalone;
     let daemon_handle = tokio::spawn(async move {
-        if let Err(e) =
-            daemon::run_daemon(daemon_port, blockchain, pm, config.pool, standalone).await
+        if let Err(e) = daemon::run_daemon(
+            daemon_port,
+            blockchain,
+            pm,
+            standalone,
+            config.dev_mode,
+            config
+                .mempool_journal
+                .then(config::get_mempool_journal_path),
+            Some(config),
+        )
+        .await
         {
             eprintln!("Daemon error: {}", e);
         }
@@ -278,7 +845,7 @@ async fn run_daemon_mode(cli: Cli, config: config::Config) -> anyhow::Result<()>
 
     // Spawn HTTP stats server
     let http_handle = tokio::spawn(async move {
-        if let Err(e) = http_api::run_http_server(web_port, daemon_addr).await {
+        if let Err(e) = http_api::run_http_server(web_port, daemon_addr, proxy, read_only).await {
             eprintln!("HTTP server error: {}", e);
         }
     });
@@ -287,7 +854,7 @@ async fn run_daemon_mode(cli: Cli, config: config::Config) -> anyhow::Result<()>
     tokio::select! {
         _ = daemon_handle => {},
         _ = http_handle => {},
-        _ = tokio::signal::ctrl_c() => {
+        _ = interrupt::token().cancelled() => {
             println!("Shutting down daemon...");
         }
     }
@@ -297,35 +864,485 @@ async fn run_daemon_mode(cli: Cli, config: config::Config) -> anyhow::Result<()>
 
 // TUI removed: use CLI --send or miner_ui instead of the previous TUI mode.
 
-async fn run_mining_mode(cli: Cli, config: config::Config) -> anyhow::Result<()> {
+async fn run_chain_command(action: ChainCommand, config: &config::Config) -> Result<(), AppError> {
+    match action {
+        ChainCommand::Bootstrap { from, full_verify } => {
+            run_chain_bootstrap(&from, full_verify, config).await
+        }
+        ChainCommand::RestoreBackup { file } => run_restore_backup(&file, config),
+        ChainCommand::Adopt { from, force } => run_chain_adopt(&from, force, config).await,
+    }
+}
+
+/// Write `incoming` to the local chain file, backing up the blocks of
+/// `local` it discards and saying where they went.
+fn replace_local_chain(
+    local: &blockchain::Blockchain,
+    incoming: &blockchain::Blockchain,
+    reason: &str,
+    config: &config::Config,
+) -> Result<(), AppError> {
+    let saved = chain_backup::replace_chain(
+        &config::get_blockchain_path(),
+        local,
+        incoming,
+        reason,
+        &config::get_backups_dir(),
+        config.chain_backups_keep,
+    )
+    .map_err(AppError::local_file)?;
+    if let Some(path) = saved {
+        println!(
+            "{}",
+            format!(
+                "Local blocks not in the new chain were saved to {}; `owonero chain restore-backup` puts them back",
+                path.display()
+            )
+            .yellow()
+        );
+    }
+    Ok(())
+}
+
+fn run_restore_backup(file: &std::path::Path, config: &config::Config) -> Result<(), AppError> {
+    let backup = chain_backup::load_backup(file).map_err(AppError::local_file)?;
+    let path = config::get_blockchain_path();
+    let current = blockchain::Blockchain::load_from_file(&path).map_err(AppError::local_file)?;
+    let restored = chain_backup::restore(&backup, &current)
+        .map_err(|e| AppError::rejected(format!("{:#}", e)))?;
+    replace_local_chain(
+        &current,
+        &restored,
+        &format!("restored {}", file.display()),
+        config,
+    )?;
+    println!(
+        "{}",
+        format!(
+            "Restored chain at height {} from a backup taken {} ({})",
+            restored.chain.len() - 1,
+            backup.created_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            backup.reason
+        )
+        .green()
+    );
+    Ok(())
+}
+
+async fn run_chain_adopt(from: &str, force: bool, config: &config::Config) -> Result<(), AppError> {
+    let node = normalize_node_addr(from);
+    let incoming = ws_client::fetch_chain(&node)
+        .await
+        .map_err(AppError::network)?;
+    incoming
+        .verify_chain()
+        .map_err(|e| AppError::rejected(format!("{}'s chain rejected: {}", node, e)))?;
+    let path = config::get_blockchain_path();
+    let local = blockchain::Blockchain::load_from_file(&path).map_err(AppError::local_file)?;
+    let fork = chain_backup::fork_point(&local, &incoming);
+    if fork == 0 {
+        return Err(AppError::rejected(format!(
+            "{}'s chain starts from a different genesis block",
+            node
+        )));
+    }
+    if incoming.chain.len() <= local.chain.len() {
+        println!(
+            "{}",
+            format!(
+                "{}'s chain is no taller than ours (height {}); keeping it",
+                node,
+                local.chain.len() - 1
+            )
+            .yellow()
+        );
+        return Ok(());
+    }
+    let depth = (local.chain.len() - fork) as u64;
+    if config.max_reorg_depth > 0 && depth > config.max_reorg_depth && !force {
+        return Err(AppError::rejected(format!(
+            "adopting {}'s chain drops {} local block(s), more than max_reorg_depth {}; pass --force if that is intended",
+            node, depth, config.max_reorg_depth
+        )));
+    }
+    replace_local_chain(
+        &local,
+        &incoming,
+        &format!("adopted chain from {}", node),
+        config,
+    )?;
+    println!(
+        "{}",
+        format!(
+            "Adopted {}'s chain at height {}, dropping {} local block(s) above height {}",
+            node,
+            incoming.chain.len() - 1,
+            depth,
+            fork - 1
+        )
+        .green()
+    );
+    Ok(())
+}
+
+async fn run_chain_bootstrap(
+    from: &str,
+    full_verify: bool,
+    config: &config::Config,
+) -> Result<(), AppError> {
+    let remote = from.starts_with("http://") || from.starts_with("https://");
+    let data = snapshot::fetch(from).await.map_err(|e| {
+        if remote {
+            AppError::network(e)
+        } else {
+            AppError::local_file(e)
+        }
+    })?;
+    let snap = snapshot::decode(&data).map_err(|e| AppError::rejected(format!("{:#}", e)))?;
+    println!(
+        "{}",
+        format!(
+            "Verifying snapshot at height {} (created {})...",
+            snap.height, snap.created_at
+        )
+        .cyan()
+    );
+    snapshot::verify(
+        &snap,
+        snapshot::SNAPSHOT_VERIFY_TAIL,
+        full_verify,
+        blockchain::CHECKPOINTS,
+        interrupt::token(),
+    )
+    .map_err(|e| match e.downcast::<interrupt::Interrupted>() {
+        Ok(interrupted) => AppError::from(interrupted),
+        Err(e) => AppError::rejected(format!("snapshot rejected: {}", e)),
+    })?;
+
+    let path = config::get_blockchain_path();
+    let local = if path.exists() {
+        let local = blockchain::Blockchain::load_from_file(&path).map_err(AppError::local_file)?;
+        let local_height = local.chain.len().saturating_sub(1) as u64;
+        if local_height >= snap.height {
+            println!(
+                "{}",
+                format!(
+                    "Local chain is already at height {}; keeping it",
+                    local_height
+                )
+                .yellow()
+            );
+            return Ok(());
+        }
+        local
+    } else {
+        blockchain::Blockchain::new()
+    };
+    replace_local_chain(
+        &local,
+        &snap.chain,
+        &format!("installed snapshot from {}", from),
+        config,
+    )?;
+    println!(
+        "{}",
+        format!(
+            "Installed chain at height {} (tip {}); sync will fetch the rest",
+            snap.height, snap.tip_hash
+        )
+        .green()
+    );
+    Ok(())
+}
+
+async fn run_admin_mode(action: AdminCommand, config: config::Config) -> Result<(), AppError> {
+    match action {
+        AdminCommand::Mempool {
+            action: MempoolCommand::Export { file },
+        } => {
+            let txs = ws_client::fetch_mempool(&config.node_address)
+                .await
+                .map_err(AppError::network)?;
+            std::fs::write(&file, serde_json::to_string_pretty(&txs)?)?;
+            println!(
+                "{}",
+                format!(
+                    "Exported {} transaction(s) to {}",
+                    txs.len(),
+                    file.display()
+                )
+                .green()
+            );
+        }
+        AdminCommand::Mempool {
+            action: MempoolCommand::Import { file },
+        } => {
+            let data = std::fs::read_to_string(&file)?;
+            let txs: Vec<blockchain::Transaction> = serde_json::from_str(&data)?;
+            let results = ws_client::import_mempool(&config.node_address, &txs)
+                .await
+                .map_err(AppError::network)?;
+            let mut accepted = 0;
+            for r in &results {
+                let txid = r.get("txid").and_then(|t| t.as_str()).unwrap_or("?");
+                if r.get("status").and_then(|s| s.as_str()) == Some("accepted") {
+                    accepted += 1;
+                } else {
+                    let reason = r
+                        .get("reason")
+                        .and_then(|s| s.as_str())
+                        .unwrap_or("unknown");
+                    println!("{}", format!("rejected {}: {}", txid, reason).yellow());
+                }
+            }
+            println!(
+                "{}",
+                format!("Imported {}/{} transaction(s)", accepted, results.len()).green()
+            );
+            if accepted < results.len() {
+                return Err(AppError::rejected(format!(
+                    "{} of {} transaction(s) rejected",
+                    results.len() - accepted,
+                    results.len()
+                )));
+            }
+        }
+        AdminCommand::MempoolJournal { limit } => {
+            let entries = ws_client::fetch_mempool_journal(&config.node_address, limit)
+                .await
+                .map_err(AppError::network)?;
+            for e in entries {
+                let when = chrono::DateTime::from_timestamp(e.timestamp, 0)
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                let decision = match &e.reason {
+                    Some(reason) => format!("{}: {}", e.decision, reason).yellow(),
+                    None => e.decision.green(),
+                };
+                println!(
+                    "{} {} {} from {} via {} ({}) at height {}: balance {}, pending out {}",
+                    when,
+                    e.txid,
+                    decision,
+                    e.sender,
+                    e.source,
+                    e.method,
+                    e.height,
+                    blockchain::Blockchain::format_owe(e.sender_balance),
+                    blockchain::Blockchain::format_owe(e.pending_out),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn run_dev_mode(action: DevCommand, config: config::Config) -> Result<(), AppError> {
+    let own_address = || {
+        wallet::load_wallet_file(&config.wallet_path)
+            .map(|w| w.address)
+            .map_err(AppError::local_file)
+    };
+    let (method, params) = match action {
+        DevCommand::MineBlocks { n, to } => {
+            let to = match to {
+                Some(t) => t,
+                None => own_address()?,
+            };
+            ("devmineblocks", serde_json::json!({"to": to, "count": n}))
+        }
+        DevCommand::Faucet { to, amount } => {
+            let to = match to {
+                Some(t) => t,
+                None => own_address()?,
+            };
+            let amount_units = (amount * 1000.0).round() as i64;
+            if amount_units <= 0 {
+                return Err(AppError::usage("amount must be > 0"));
+            }
+            (
+                "devfaucet",
+                serde_json::json!({"to": to, "amount": amount_units}),
+            )
+        }
+        #[cfg(feature = "testkit")]
+        DevCommand::GenerateChain {
+            blocks,
+            out,
+            seed,
+            wallets,
+            txs_per_block,
+            difficulty,
+            real_pow,
+        } => {
+            let fixture = testkit::ChainBuilder::new(seed)
+                .blocks(blocks)
+                .wallets(wallets)
+                .txs_per_block(txs_per_block)
+                .difficulty(difficulty)
+                .real_pow(real_pow)
+                .build()
+                .map_err(|e| AppError::usage(e.to_string()))?;
+            let wallets_path = out.with_extension("wallets.json");
+            fixture
+                .chain
+                .save_to_file(&out)
+                .map_err(AppError::local_file)?;
+            let wallets_json =
+                serde_json::to_string_pretty(&fixture.wallets).map_err(AppError::local_file)?;
+            std::fs::write(&wallets_path, wallets_json).map_err(AppError::local_file)?;
+            println!(
+                "{}",
+                format!(
+                    "Wrote {} block(s) to {} and {} wallet(s) to {}",
+                    blocks,
+                    out.display(),
+                    fixture.wallets.len(),
+                    wallets_path.display()
+                )
+                .green()
+            );
+            return Ok(());
+        }
+    };
+
+    let resp = ws_client::ws_command(&config.node_address, method, params)
+        .await
+        .map_err(AppError::network)?;
+    if resp.get("type").and_then(|t| t.as_str()) == Some("error") {
+        let message = resp
+            .get("message")
+            .and_then(|m| m.as_str())
+            .unwrap_or("unknown error");
+        return Err(AppError::rejected(format!(
+            "{} (is {} a local node with dev_mode: true?)",
+            message, config.node_address
+        )));
+    }
+    println!(
+        "{}",
+        format!(
+            "Added {} block(s), node height is now {}",
+            resp.get("blocks").and_then(|b| b.as_u64()).unwrap_or(0),
+            resp.get("height").and_then(|h| h.as_u64()).unwrap_or(0)
+        )
+        .green()
+    );
+    Ok(())
+}
+
+/// Time allowed for asking the node its height before the memory check.
+const MEMORY_CHECK_NODE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Threads to mine with: `mining_threads`, capped to what fits in memory
+/// alongside the chain copy. Refuses when not even one thread fits, unless
+/// `force`.
+async fn check_mining_memory(
+    config: &config::Config,
+    force: bool,
+) -> Result<(usize, Option<miner::MemoryPlan>), AppError> {
+    let height = tokio::time::timeout(
+        MEMORY_CHECK_NODE_TIMEOUT,
+        ws_client::fetch_latest_block(&config.node_address),
+    )
+    .await
+    .ok()
+    .and_then(Result::ok)
+    .map_or(0, |tip| tip.index);
+    let Some(plan) = miner::MemoryPlan::for_system(config.mining_threads, height) else {
+        return Ok((config.mining_threads, None));
+    };
+    // Shown before the TUI takes over the screen, too
+    if plan.threads == 0 {
+        if !force {
+            return Err(AppError::rejected(format!(
+                "not enough memory to mine: {}, and not even one thread fits; free memory or pass --force",
+                plan.describe()
+            )));
+        }
+        eprintln!("Memory: {}; mining anyway (--force)", plan.describe());
+        return Ok((1, Some(plan)));
+    }
+    if plan.is_capped() {
+        eprintln!(
+            "Memory: {}; using {} thread(s)",
+            plan.describe(),
+            plan.threads
+        );
+    }
+    Ok((plan.threads, Some(plan)))
+}
+
+async fn run_mining_mode(cli: Cli, config: config::Config) -> Result<(), AppError> {
+    // Before the TUI takes over the screen, so the reason stays readable
+    wallet::load_wallet_file(&config.wallet_path).map_err(AppError::local_file)?;
+    if let Some(address) = &config.mine_to
+        && !wallet::is_valid_address(address)
+    {
+        return Err(AppError::usage(format!(
+            "mine_to is not a valid address: {}",
+            address
+        )));
+    }
+    let coinbase_split = miner::CoinbaseSplit::new(&config.coinbase_split)
+        .map_err(|e| AppError::usage(format!("coinbase_split: {}", e)))?;
+    if coinbase_split.is_active() {
+        // Shown before the TUI takes over the screen, too
+        eprintln!("Coinbase split: {}", coinbase_split.describe());
+    }
+    let intensity_schedule =
+        intensity::IntensitySchedule::new(&config.intensity_schedule, &config.thread_intensity)
+            .map_err(AppError::usage)?;
+    let (threads, memory_plan) = check_mining_memory(&config, cli.force).await?;
     // Always start mining with UI
     let (stats_tx, stats_rx) = tokio::sync::mpsc::channel(10);
     let (log_tx, log_rx) = tokio::sync::mpsc::channel(100);
 
     // Create shutdown notifier so UI can request program shutdown
     let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let json = cli.json;
 
     // Start mining in background
     let mining_handle = tokio::spawn(async move {
-        if let Err(e) = miner::start_mining(
-            &config.node_address,
-            cli.blocks,
-            config.mining_threads,
-            config.pool,
-            config.mining_intensity,
-            Some(stats_tx),
-            Some(log_tx),
-            Some(shutdown_rx),
-        )
-        .await
-        {
-            eprintln!("Mining error: {}", e);
+        let opts = miner::MinerOptions {
+            node_addr: config.node_address.clone(),
+            blocks_to_mine: cli.blocks,
+            threads,
+            pool: config.pool,
+            intensity: config.mining_intensity,
+            intensity_schedule,
+            pause_on_battery: config.pause_on_battery,
+            auto_threads: cli.auto_threads,
+            power_watts: cli.power_watts,
+            tip_poll: std::time::Duration::from_millis(config.tip_poll_ms),
+            mempool_poll: std::time::Duration::from_millis(config.mempool_poll_ms),
+            mine_to: config.mine_to.clone(),
+            allow_genesis_mining: cli.allow_genesis_mining,
+            session_reports_keep: config.session_reports_keep,
+            tx_filter: miner::TxFilter::new(
+                &config.include_only_addresses,
+                &config.exclude_addresses,
+            ),
+            coinbase_split,
+            memory_plan,
+        };
+        match miner::start_mining(opts, Some(stats_tx), Some(log_tx), Some(shutdown_rx)).await {
+            Ok(report) => Some(report),
+            Err(e) => {
+                eprintln!("Mining error: {}", e);
+                None
+            }
         }
     });
 
-    // Start UI
+    // Start UI, or plain periodic stats lines with --no-ui
     let ui_shutdown_tx = shutdown_tx.clone();
+    let no_ui = cli.no_ui;
     let ui_handle = tokio::spawn(async move {
+        if no_ui {
+            print_mining_stats(stats_rx, log_rx).await;
+            return;
+        }
         match miner_ui::MinerUI::new() {
             Ok(mut ui) => {
                 if let Err(e) = ui.run(stats_rx, log_rx, Some(ui_shutdown_tx)).await {
@@ -336,133 +1353,300 @@ async fn run_mining_mode(cli: Cli, config: config::Config) -> anyhow::Result<()>
         }
     });
 
-    // Wait for UI to finish or Ctrl+C. When UI exits (user pressed 'q') it will send shutdown;
-    // on Ctrl+C we send shutdown and abort background tasks to exit promptly.
+    // Wait for UI to finish or Ctrl+C, then ask the miner to stop. It gets a
+    // few seconds to wind down and write its session report before being
+    // aborted.
     tokio::select! {
-        _ = ui_handle => {
-            // UI finished normally (user pressed 'q') - request shutdown
-            let _ = shutdown_tx.send(true);
-            // Abort mining task so the program can exit promptly
-            mining_handle.abort();
-            let _ = mining_handle.await;
+        _ = ui_handle => {}
+        _ = interrupt::token().cancelled() => eprintln!("Received Ctrl+C - shutting down"),
+    }
+    let _ = shutdown_tx.send(true);
+    let abort = mining_handle.abort_handle();
+    let report = match tokio::time::timeout(MINER_SHUTDOWN_GRACE, mining_handle).await {
+        Ok(Ok(report)) => report,
+        Ok(Err(_)) => None,
+        Err(_) => {
+            abort.abort();
+            eprintln!("Miner did not stop in time; no session report");
+            None
         }
-        _ = tokio::signal::ctrl_c() => {
-            eprintln!("Received Ctrl+C - shutting down");
-            let _ = shutdown_tx.send(true);
-            mining_handle.abort();
-            let _ = mining_handle.await;
+    };
+    if let Some(report) = report {
+        if json && no_ui {
+            println!(
+                "{}",
+                serde_json::to_string(&report).map_err(anyhow::Error::from)?
+            );
+        } else {
+            println!("{}", report.summary());
         }
     }
 
     Ok(())
 }
 
-async fn run_wallet_info_mode(config: config::Config) -> anyhow::Result<()> {
-    let wallet = crate::wallet::load_or_create_wallet(&config.wallet_path)?;
-    let node_to_use = wallet
-        .node_address
-        .clone()
-        .unwrap_or(config.node_address.clone());
+/// How long the miner gets to stop after the UI closes or Ctrl+C.
+const MINER_SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// `--no-ui` output: forward miner logs and print a stats line every 10s.
+/// Returns once the miner stops reporting stats.
+async fn print_mining_stats(
+    mut stats_rx: tokio::sync::mpsc::Receiver<miner::MinerStats>,
+    mut log_rx: tokio::sync::mpsc::Receiver<miner_log::LogLine>,
+) {
+    let mut last_print = std::time::Instant::now();
+    loop {
+        tokio::select! {
+            stats = stats_rx.recv() => match stats {
+                Some(stats) => {
+                    if last_print.elapsed() >= std::time::Duration::from_secs(10) {
+                        println!("{}", stats.summary());
+                        last_print = std::time::Instant::now();
+                    }
+                }
+                None => break,
+            },
+            Some(log) = log_rx.recv() => println!("{}", log),
+        }
+    }
+}
+
+/// Where the chain behind the wallet summary came from.
+#[derive(Debug, Clone, PartialEq)]
+enum ChainSource {
+    /// Synced from (or already level with) this node.
+    Node(String),
+    /// No node answered; the local chain may be behind.
+    Unreachable,
+    /// `--offline`: no node was asked.
+    Offline,
+}
+
+/// "as of block 812, 2d 3h ago", for figures taken from a local chain
+/// that may be behind.
+fn chain_age(bc: &blockchain::Blockchain, now: chrono::DateTime<chrono::Utc>) -> String {
+    match bc.chain.last() {
+        Some(tip) => format!(
+            "as of block {}, {} ago",
+            tip.index,
+            miner_ui::format_age((now - tip.timestamp).num_seconds().max(0) as u64)
+        ),
+        None => "with no local chain".to_string(),
+    }
+}
+
+/// The yellow line under the wallet summary when it wasn't checked
+/// against a node.
+fn staleness_note(
+    source: &ChainSource,
+    bc: &blockchain::Blockchain,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Option<String> {
+    match source {
+        ChainSource::Node(_) => None,
+        ChainSource::Unreachable => Some(format!(
+            "No node reachable: balance is {}",
+            chain_age(bc, now)
+        )),
+        ChainSource::Offline => Some(format!("Offline: balance is {}", chain_age(bc, now))),
+    }
+}
+
+/// The wallet summary for `--json`.
+fn wallet_info_json(
+    address: &str,
+    balance: i64,
+    source: &ChainSource,
+    bc: &blockchain::Blockchain,
+) -> serde_json::Value {
+    let tip = bc.chain.last();
+    let (source, node) = match source {
+        ChainSource::Node(node) => ("node", Some(node.as_str())),
+        ChainSource::Unreachable => ("local", None),
+        ChainSource::Offline => ("offline", None),
+    };
+    serde_json::json!({
+        "address": address,
+        "balance": balance,
+        "height": tip.map(|b| b.index),
+        "tip_timestamp": tip.map(|b| b.timestamp.timestamp()),
+        "source": source,
+        "node": node,
+    })
+}
+
+async fn run_wallet_info_mode(
+    config: config::Config,
+    rescan: bool,
+    json: bool,
+) -> Result<(), AppError> {
+    let mut wallet = wallet::load_wallet_file(&config.wallet_path).map_err(AppError::local_file)?;
 
     // Load local chain
     let mut blockchain =
-        blockchain::Blockchain::load_from_file(crate::config::get_blockchain_path())?;
+        blockchain::Blockchain::load_from_file(crate::config::get_blockchain_path())
+            .map_err(AppError::local_file)?;
 
+    let mut source = ChainSource::Offline;
     if config.sync_on_startup {
-        // Fetch chain via WebSocket
-        match crate::ws_client::fetch_chain(&node_to_use).await {
-            Ok(new_chain) => {
-                if new_chain.chain.len() > blockchain.chain.len() {
+        // Catch up from the first node that answers
+        let nodes = node_candidates(wallet.node_address.as_deref(), &config);
+        let local = &blockchain;
+        let cancel = interrupt::token();
+        match with_node_fallback(&nodes, |node| async move {
+            let mut display = chain_sync::ProgressDisplay::stderr();
+            chain_sync::sync_from_node(&node, local, cancel, |p| display.show(p)).await
+        })
+        .await
+        {
+            Ok((node, synced)) => {
+                remember_node(&config.wallet_path, &mut wallet, &node);
+                if let Some((new_chain, report)) = synced {
+                    let reason = format!("adopted chain from {}", node);
+                    if let Err(e) = replace_local_chain(&blockchain, &new_chain, &reason, &config) {
+                        eprintln!("Warning: failed to save synchronized chain: {}", e);
+                    }
                     blockchain = new_chain;
-                    let _ =
-                        blockchain.save_to_file(crate::config::get_blockchain_path());
-                    println!(
-                        "Synchronized blockchain from node {}",
-                        node_to_use
-                    );
+                    eprintln!("Synchronized blockchain from node {}", node);
+                    eprintln!("{}", report);
                 }
+                source = ChainSource::Node(node);
             }
             Err(e) => {
-                eprintln!(
-                    "Warning: failed to sync blockchain from node {}: {}",
-                    node_to_use, e
-                );
+                eprintln!("Warning: failed to sync blockchain: {}", e);
+                source = ChainSource::Unreachable;
             }
         }
+        // Whatever was verified before Ctrl+C is on disk by now
+        if cancel.is_cancelled() {
+            let height = blockchain.chain.len().saturating_sub(1) as u64;
+            return Err(interrupt::Interrupted::saved_through(height).into());
+        }
     }
 
-    let balance = wallet.get_balance(&blockchain);
+    if wallet.scan_balance(&blockchain, rescan) > 0
+        && let Err(e) = crate::wallet::save_wallet(&config.wallet_path, &wallet)
+    {
+        eprintln!("Warning: failed to save wallet balance cache: {}", e);
+    }
+    let balance = wallet.balance_milli;
+    let note = staleness_note(&source, &blockchain, chrono::Utc::now());
+
+    if json {
+        println!(
+            "{}",
+            wallet_info_json(&wallet.address, balance, &source, &blockchain)
+        );
+        // Scripts tell stale figures apart by the exit code
+        return match (source, note) {
+            (ChainSource::Unreachable, Some(note)) => Err(AppError::stale(note)),
+            _ => Ok(()),
+        };
+    }
 
     println!("{} {}", "Wallet:".blue(), wallet.address);
     // Display balance in human-friendly OWE (1 OWE == 1000 internal units)
     println!(
-        "{} {:.3} OWE",
+        "{} {}",
         "Balance:".yellow(),
-        (balance as f64) / 1000.0
+        blockchain::Blockchain::format_owe(balance)
     );
     println!("{} {}", "Chain height:".cyan(), blockchain.chain.len() - 1);
+    if let Some(note) = note {
+        println!("{}", note.yellow());
+    }
 
     Ok(())
 }
 
-async fn run_tx_history_mode(config: config::Config) -> anyhow::Result<()> {
-    let wallet = crate::wallet::load_or_create_wallet(&config.wallet_path)?;
+async fn run_tx_history_mode(json: bool, config: config::Config) -> Result<(), AppError> {
+    let wallet = wallet::load_wallet_file(&config.wallet_path).map_err(AppError::local_file)?;
+    let book = load_address_book()?;
+    let labels = address_book::Labels::new(&wallet.address, &book);
 
     // Load local chain
     let mut blockchain =
-        blockchain::Blockchain::load_from_file(crate::config::get_blockchain_path())?;
+        blockchain::Blockchain::load_from_file(crate::config::get_blockchain_path())
+            .map_err(AppError::local_file)?;
 
     // Optionally try to sync from the configured node to get up-to-date data
     if config.sync_on_startup {
-        let node_addr = wallet
-            .node_address
-            .clone()
-            .unwrap_or(config.node_address.clone());
-        
-        if let Ok(new_chain) = crate::ws_client::fetch_chain(&node_addr).await {
-            if new_chain.chain.len() > blockchain.chain.len() {
-                blockchain = new_chain;
-            }
+        let node_addr = node_candidates(wallet.node_address.as_deref(), &config)
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+
+        let mut display = chain_sync::ProgressDisplay::stderr();
+        let cancel = interrupt::token();
+        let on_disk = blockchain.chain.len().saturating_sub(1) as u64;
+        if let Ok(Some((new_chain, report))) =
+            chain_sync::sync_from_node(&node_addr, &blockchain, cancel, |p| display.show(p)).await
+        {
+            blockchain = new_chain;
+            eprintln!("{}", report);
+        }
+        // History of a half-synced chain would be misleading, and the
+        // chain on disk was never touched
+        if cancel.is_cancelled() {
+            return Err(interrupt::Interrupted::saved_through(on_disk).into());
         }
     }
 
     let my_addr = wallet.address.trim().to_lowercase();
-
-    println!("Transaction history for wallet: {}", wallet.address);
+    // One JSON object per transaction; raw addresses stay as they are and
+    // their labels go in a separate field
+    let entry =
+        |tx: &blockchain::Transaction, direction: &str, block: Option<&blockchain::Block>| {
+            serde_json::json!({
+                "status": if block.is_some() { "confirmed" } else { "pending" },
+                "direction": direction,
+                "height": block.map(|b| b.index),
+                "timestamp": block.map(|b| b.timestamp),
+                "from": tx.from,
+                "to": tx.to,
+                "amount": tx.amount,
+                "txid": tx.txid(),
+                "labels": labels.of([tx.from.as_str(), tx.to.as_str()]),
+            })
+        };
+
+    if !json {
+        println!("Transaction history for wallet: {}", wallet.address);
+    }
     // Try to fetch mempool from node and show pending txs involving this wallet
-    if let Some(node_addr) = wallet
-        .node_address
-        .clone()
-        .or(Some(config.node_address.clone()))
+    if let Some(node_addr) = node_candidates(wallet.node_address.as_deref(), &config)
+        .into_iter()
+        .next()
+        && let Ok(mempool_vec) = crate::ws_client::fetch_mempool(&node_addr).await
     {
-        if let Ok(mempool_vec) = crate::ws_client::fetch_mempool(&node_addr).await {
-            let mut found = false;
-            for tx in mempool_vec.iter() {
-                if tx.to.trim().to_lowercase() == my_addr
-                    || tx.from.trim().to_lowercase() == my_addr
-                {
-                    if !found {
-                        println!("PENDING transactions in mempool:");
-                        found = true;
-                    }
-                    let dir = if tx.to.trim().to_lowercase() == my_addr {
-                        "IN"
-                    } else {
-                        "OUT"
-                    };
-                    println!(
-                        "{} pending: {} -> {} amount: {} sig={}",
-                        dir,
-                        &tx.from[..std::cmp::min(8, tx.from.len())],
-                        &tx.to[..std::cmp::min(8, tx.to.len())],
-                        (tx.amount as f64) / 1000.0,
-                        &tx.signature[..std::cmp::min(16, tx.signature.len())]
-                    );
+        let mut found = false;
+        for tx in mempool_vec.iter() {
+            if tx.to.trim().to_lowercase() == my_addr || tx.from.trim().to_lowercase() == my_addr {
+                let dir = if tx.to.trim().to_lowercase() == my_addr {
+                    "IN"
+                } else {
+                    "OUT"
+                };
+                if json {
+                    println!("{}", entry(tx, dir, None));
+                    continue;
                 }
+                if !found {
+                    println!("PENDING transactions in mempool:");
+                    found = true;
+                }
+                println!(
+                    "{} pending: {} -> {} amount: {} sig={}",
+                    dir,
+                    labels.short(&tx.from),
+                    labels.short(&tx.to),
+                    (tx.amount as f64) / 1000.0,
+                    &tx.signature[..std::cmp::min(16, tx.signature.len())]
+                );
             }
-            if found {
-                println!("----");
-            }
+        }
+        if found {
+            println!("----");
         }
     }
     for block in &blockchain.chain {
@@ -471,13 +1655,17 @@ async fn run_tx_history_mode(config: config::Config) -> anyhow::Result<()> {
             let tx_from = tx.from.trim().to_lowercase();
             if tx_to == my_addr || tx_from == my_addr {
                 let direction = if tx_to == my_addr { "IN" } else { "OUT" };
+                if json {
+                    println!("{}", entry(tx, direction, Some(block)));
+                    continue;
+                }
                 println!(
                     "{} [#{:>5}] {} {} -> {}  amount: {}",
                     direction,
                     block.index,
                     block.timestamp,
-                    &tx.from[..std::cmp::min(8, tx.from.len())],
-                    &tx.to[..std::cmp::min(8, tx.to.len())],
+                    labels.short(&tx.from),
+                    labels.short(&tx.to),
                     (tx.amount as f64) / 1000.0
                 );
             }
@@ -487,34 +1675,503 @@ async fn run_tx_history_mode(config: config::Config) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn run_send_mode(cli: Cli, config: config::Config) -> anyhow::Result<()> {
+fn load_address_book() -> Result<address_book::AddressBook, AppError> {
+    address_book::AddressBook::load(&config::get_address_book_path()).map_err(AppError::local_file)
+}
+
+async fn run_send_mode(cli: Cli, config: config::Config) -> Result<(), AppError> {
+    if let Some(path) = &cli.broadcast {
+        return run_broadcast(path, &config, cli.wait).await;
+    }
     if !cli.send {
-        return Err(anyhow::anyhow!("send flag not set"));
+        return Err(AppError::usage("send flag not set"));
+    }
+    if let Some(path) = &cli.batch {
+        let options = batch::BatchOptions {
+            dry_run: cli.dry_run,
+            skip_completed: cli.skip_completed.clone(),
+            results: cli
+                .results
+                .clone()
+                .unwrap_or_else(|| batch::default_results_path(path)),
+        };
+        let mut wallet =
+            wallet::load_wallet_file(&config.wallet_path).map_err(AppError::local_file)?;
+        let nodes = node_candidates(wallet.node_address.as_deref(), &config);
+        let node = batch::run_batch(path, &options, &wallet, &nodes, interrupt::token()).await?;
+        remember_node(&config.wallet_path, &mut wallet, &node);
+        return Ok(());
     }
 
-    let to = match cli.to {
-        Some(t) if !t.is_empty() => t,
-        _ => return Err(anyhow::anyhow!("missing --to argument for send")),
+    let request = match cli.to.as_deref() {
+        Some(t) if receive::is_payment_uri(t) => Some(
+            receive::PaymentRequest::parse(t)
+                .map_err(|e| AppError::usage(format!("--to: {:#}", e)))?,
+        ),
+        _ => None,
+    };
+    let to = match (&request, cli.to) {
+        _ if cli.burn.is_some() => wallet::BURN_ADDRESS.to_string(),
+        (Some(request), _) => {
+            if let Some(memo) = &request.memo {
+                println!("{}", format!("Memo: {}", memo).cyan());
+            }
+            if wallet::is_burn_address(&request.address) {
+                return Err(AppError::usage(
+                    "that is the burn address; use --burn <amount> to destroy coins",
+                ));
+            }
+            request.address.clone()
+        }
+        (None, Some(t)) if !t.is_empty() => {
+            let address = load_address_book()?.resolve(&t);
+            if address != t {
+                println!("{}", format!("Sending to \"{}\" ({})", t, address).cyan());
+            }
+            if wallet::is_burn_address(&address) {
+                return Err(AppError::usage(
+                    "that is the burn address; use --burn <amount> to destroy coins",
+                ));
+            }
+            address
+        }
+        _ => return Err(AppError::usage("missing --to argument for send")),
     };
 
-    let amount_f = cli.amount.unwrap_or(0.0);
+    let requested = request
+        .as_ref()
+        .filter(|_| cli.burn.is_none())
+        .and_then(|r| r.amount);
+    let amount_f = cli
+        .burn
+        .or(cli.amount)
+        .or(requested.map(|units| units as f64 / 1000.0))
+        .unwrap_or(0.0);
     if amount_f <= 0.0 {
-        return Err(anyhow::anyhow!("amount must be > 0"));
+        return Err(AppError::usage("amount must be > 0"));
     }
 
     // Convert decimal amount to internal atomic units (milli-OWE)
     // e.g. 1.234 OWE -> 1234 units
     let amount_units = (amount_f * 1000.0).round() as i64;
     if amount_units <= 0 {
-        return Err(anyhow::anyhow!("amount too small after conversion"));
+        return Err(AppError::usage("amount too small after conversion"));
+    }
+    if let Some(requested) = requested
+        && amount_units != requested
+    {
+        return Err(AppError::usage(format!(
+            "--amount {} differs from the {} the payment URI asks for",
+            blockchain::Blockchain::format_owe(amount_units),
+            blockchain::Blockchain::format_owe(requested)
+        )));
+    }
+
+    if cli.burn.is_some() {
+        println!(
+            "{}",
+            format!(
+                "Burning {} sends it to {}, which no key can spend. It is gone for good.\nType \"{}\" to continue:",
+                blockchain::Blockchain::format_owe(amount_units),
+                wallet::BURN_ADDRESS,
+                BURN_CONFIRMATION
+            )
+            .yellow()
+        );
+        confirm_burn(&read_stdin_line()?)?;
+    }
+
+    let expires_at_height = match cli.expires_in {
+        Some(0) => return Err(AppError::usage("--expires-in must be at least 1 block")),
+        Some(blocks) => {
+            let nodes = node_candidates(None, &config);
+            let (_, tip) = with_node_fallback(&nodes, |node| async move {
+                ws_client::fetch_latest_block(&node).await
+            })
+            .await?;
+            Some(tip.index + blocks)
+        }
+        None => None,
+    };
+
+    if cli.offline_create {
+        let out = cli
+            .out
+            .ok_or_else(|| AppError::usage("--offline-create needs --out"))?;
+        let from = match cli.from {
+            Some(from) => from,
+            None => {
+                wallet::load_wallet_file(&config.wallet_path)
+                    .map_err(AppError::local_file)?
+                    .address
+            }
+        };
+        let mut tx = wallet::unsigned_transaction(&from, &to, amount_units);
+        tx.expires_at_height = expires_at_height;
+        wallet::write_tx_file(&out, wallet::UNSIGNED_TX_FORMAT, &tx)
+            .map_err(AppError::local_file)?;
+        println!(
+            "{}",
+            format!(
+                "Unsigned transaction written to {}; sign it with `owonero wallet sign-tx`",
+                out.display()
+            )
+            .green()
+        );
+        return Ok(());
     }
 
     // Load wallet and create signed transaction
-    let wallet = config::load_wallet()?;
-    let tx = wallet.create_signed_transaction(&to, amount_units)?;
+    let mut wallet = wallet::load_wallet_file(&config.wallet_path).map_err(AppError::local_file)?;
+    let tx = wallet.create_expiring_transaction(&to, amount_units, expires_at_height)?;
+    if let Some(last) = expires_at_height {
+        println!(
+            "{}",
+            format!("Transaction expires after block {}", last).cyan()
+        );
+    }
+    let nodes = node_candidates(wallet.node_address.as_deref(), &config);
+    let node = submit_signed_tx(&nodes, &tx, cli.wait, receipts_dir(&config).as_deref()).await?;
+    remember_node(&config.wallet_path, &mut wallet, &node);
+    Ok(())
+}
+
+/// Submit a transaction signed on another machine. The signature is checked
+/// locally first so a damaged or edited file never reaches the node.
+async fn run_broadcast(
+    path: &std::path::Path,
+    config: &config::Config,
+    wait: bool,
+) -> Result<(), AppError> {
+    let tx = wallet::read_tx_file(path, wallet::SIGNED_TX_FORMAT).map_err(AppError::local_file)?;
+    if !crate::blockchain::verify_transaction_signature(&tx, &tx.pub_key) {
+        return Err(AppError::rejected(format!(
+            "signature in {} does not verify",
+            path.display()
+        )));
+    }
+    submit_signed_tx(
+        &node_candidates(None, config),
+        &tx,
+        wait,
+        receipts_dir(config).as_deref(),
+    )
+    .await?;
+    Ok(())
+}
+
+async fn run_wallet_command(action: WalletCommand, config: config::Config) -> Result<(), AppError> {
+    match action {
+        WalletCommand::ProveReserve { message, out } => {
+            let mut wallet =
+                wallet::load_wallet_file(&config.wallet_path).map_err(AppError::local_file)?;
+            let bc = ws_client::fetch_chain(&config.node_address)
+                .await
+                .map_err(AppError::network)?;
+            if wallet.scan_balance(&bc, false) > 0
+                && let Err(e) = wallet::save_wallet(&config.wallet_path, &wallet)
+            {
+                eprintln!("Warning: failed to save wallet balance cache: {}", e);
+            }
+            let height = bc.chain.last().map(|b| b.index).unwrap_or(0);
+            let proof = wallet.prove_reserve(&message, height, wallet.balance_milli)?;
+            let json = serde_json::to_string_pretty(&proof)?;
+            match out {
+                Some(path) => {
+                    std::fs::write(&path, json)?;
+                    println!(
+                        "{}",
+                        format!(
+                            "Proof of {} at height {} written to {}",
+                            blockchain::Blockchain::format_owe(proof.balance),
+                            height,
+                            path.display()
+                        )
+                        .green()
+                    );
+                }
+                None => println!("{}", json),
+            }
+            Ok(())
+        }
+        WalletCommand::VerifyReserve { proof } => {
+            let data = std::fs::read_to_string(&proof)?;
+            let proof: wallet::ReserveProof = serde_json::from_str(&data)?;
+            if !proof.verify_signature() {
+                return Err(AppError::rejected(
+                    "proof signature does not match its address",
+                ));
+            }
+            println!("Signature OK for {}", proof.address);
+            println!("Challenge: {}", proof.message);
+            let (_, balance) =
+                ws_client::fetch_balance(&config.node_address, &proof.address, Some(proof.height))
+                    .await
+                    .map_err(AppError::network)?;
+            if balance != proof.balance {
+                return Err(AppError::rejected(format!(
+                    "claim did not hold: proof says {} at height {}, node says {}",
+                    blockchain::Blockchain::format_owe(proof.balance),
+                    proof.height,
+                    blockchain::Blockchain::format_owe(balance)
+                )));
+            }
+            println!(
+                "{}",
+                format!(
+                    "Claim held: {} at height {}",
+                    blockchain::Blockchain::format_owe(balance),
+                    proof.height
+                )
+                .green()
+            );
+            let (tip, now) = ws_client::fetch_balance(&config.node_address, &proof.address, None)
+                .await
+                .map_err(AppError::network)?;
+            if now < proof.balance {
+                println!(
+                    "{}",
+                    format!(
+                        "Balance has since dropped to {} (height {})",
+                        blockchain::Blockchain::format_owe(now),
+                        tip
+                    )
+                    .yellow()
+                );
+            }
+            Ok(())
+        }
+        WalletCommand::Mined {
+            address,
+            limit,
+            before,
+        } => {
+            let address = match address {
+                Some(address) => address,
+                None => {
+                    wallet::load_wallet_file(&config.wallet_path)
+                        .map_err(AppError::local_file)?
+                        .address
+                }
+            };
+            let page = ws_client::fetch_mined_blocks(&config.node_address, &address, limit, before)
+                .await
+                .map_err(AppError::network)?;
+            println!("Blocks mined by {}: {}", address, page.total);
+            for b in &page.blocks {
+                let when = chrono::DateTime::from_timestamp(b.timestamp, 0)
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                println!(
+                    "#{:<8} {}  {:>14}  {}",
+                    b.height,
+                    when,
+                    blockchain::Blockchain::format_owe(b.reward),
+                    b.hash
+                );
+            }
+            if let Some(next) = page.next_before {
+                println!("More: owonero wallet mined --before {}", next);
+            }
+            Ok(())
+        }
+        WalletCommand::New { out, force } => {
+            let dest = out.unwrap_or_else(|| config.wallet_path.clone().into());
+            let wallet = wallet::Wallet::new().map_err(AppError::local_file)?;
+            write_new_wallet(&wallet, &dest, force)?;
+            println!(
+                "{}",
+                format!("Created wallet {} in {}", wallet.address, dest.display()).green()
+            );
+            println!("Back up its key with `owonero wallet export-key`.");
+            Ok(())
+        }
+        WalletCommand::ImportKey { file, out, force } => {
+            let key = match file {
+                Some(file) => std::fs::read_to_string(&file).map_err(AppError::local_file)?,
+                None => {
+                    eprint!("Private key (hex): ");
+                    read_stdin_line()?
+                }
+            };
+            let dest = out.unwrap_or_else(|| config.wallet_path.clone().into());
+            let wallet = import_key(&key, &dest, force)?;
+            println!(
+                "{}",
+                format!("Imported wallet {} into {}", wallet.address, dest.display()).green()
+            );
+            Ok(())
+        }
+        WalletCommand::Receive { amount, memo } => {
+            let wallet =
+                wallet::load_wallet_file(&config.wallet_path).map_err(AppError::local_file)?;
+            let amount = match amount {
+                Some(owe) if owe <= 0.0 || (owe * 1000.0).round() < 1.0 => {
+                    return Err(AppError::usage("amount must be at least 0.001"));
+                }
+                Some(owe) => Some((owe * 1000.0).round() as i64),
+                None => None,
+            };
+            let request = receive::PaymentRequest {
+                address: wallet.address,
+                amount,
+                memo,
+            };
+            // A bare address scans with any wallet; a URI only with ours
+            let payload = if request.amount.is_none() && request.memo.is_none() {
+                request.address.clone()
+            } else {
+                request.to_uri()
+            };
+            println!("Address: {}", request.address);
+            println!("URI:     {}", request.to_uri());
+            // Leave room for the lines above and the prompt below
+            let (cols, rows) = if std::io::IsTerminal::is_terminal(&std::io::stdout()) {
+                crossterm::terminal::size()
+                    .map(|(c, r)| (c as usize, (r as usize).saturating_sub(3)))
+                    .unwrap_or((usize::MAX, usize::MAX))
+            } else {
+                (usize::MAX, usize::MAX)
+            };
+            match receive::render_qr(&payload, cols, rows) {
+                Some(qr) => println!("{}", qr),
+                None => println!(
+                    "{}",
+                    "Terminal too small for the QR code; enlarge it or share the address above."
+                        .yellow()
+                ),
+            }
+            Ok(())
+        }
+        WalletCommand::Label { address, name } => {
+            let path = config::get_address_book_path();
+            let mut book = load_address_book()?;
+            book.set(&address, name.as_deref());
+            book.save(&path).map_err(AppError::local_file)?;
+            match book.label(&address) {
+                Some(label) => println!("{} is now \"{}\"", address, label),
+                None => println!("{} removed from the address book", address),
+            }
+            Ok(())
+        }
+        WalletCommand::ExportKey => {
+            let wallet =
+                wallet::load_wallet_file(&config.wallet_path).map_err(AppError::local_file)?;
+            eprintln!(
+                "{}",
+                "Anyone with this key can spend every OWE in the wallet. Never share it or paste it into a website or chat."
+                    .red()
+            );
+            eprint!("Type \"{}\" to print it: ", EXPORT_KEY_CONFIRMATION);
+            let answer = read_stdin_line()?;
+            println!("{}", export_key(&wallet, &answer)?);
+            Ok(())
+        }
+        WalletCommand::SignTx { input, out } => {
+            let wallet =
+                wallet::load_wallet_file(&config.wallet_path).map_err(AppError::local_file)?;
+            let tx = wallet::read_tx_file(&input, wallet::UNSIGNED_TX_FORMAT)
+                .map_err(AppError::local_file)?;
+            println!(
+                "Signing: from={} to={} amount={}",
+                tx.from,
+                tx.to,
+                (tx.amount as f64) / 1000.0
+            );
+            let signed = wallet
+                .sign_unsigned(&tx)
+                .map_err(|e| AppError::rejected(e.to_string()))?;
+            wallet::write_tx_file(&out, wallet::SIGNED_TX_FORMAT, &signed)
+                .map_err(AppError::local_file)?;
+            println!(
+                "{}",
+                format!(
+                    "Signed transaction {} written to {}",
+                    signed.txid(),
+                    out.display()
+                )
+                .green()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// What `wallet export-key` asks the user to type before printing the key.
+const EXPORT_KEY_CONFIRMATION: &str = "I understand";
+
+/// What `--send --burn` asks the user to type before destroying coins.
+const BURN_CONFIRMATION: &str = "burn";
+
+fn confirm_burn(answer: &str) -> Result<(), AppError> {
+    if answer.trim() != BURN_CONFIRMATION {
+        return Err(AppError::usage(format!(
+            "not confirmed; type \"{}\" to burn coins",
+            BURN_CONFIRMATION
+        )));
+    }
+    Ok(())
+}
+
+fn read_stdin_line() -> Result<String, AppError> {
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .map_err(AppError::local_file)?;
+    Ok(line.trim().to_string())
+}
+
+/// The wallet's private key, once `answer` is the exact confirmation
+/// phrase. The key never appears in arguments, so it stays out of shell
+/// history.
+fn export_key<'a>(wallet: &'a wallet::Wallet, answer: &str) -> Result<&'a str, AppError> {
+    if answer.trim() != EXPORT_KEY_CONFIRMATION {
+        return Err(AppError::usage(format!(
+            "not confirmed; type \"{}\" to export the key",
+            EXPORT_KEY_CONFIRMATION
+        )));
+    }
+    Ok(&wallet.priv_key)
+}
+
+/// Build a wallet from a hex private key and write it to `dest`, which must
+/// not exist yet.
+fn import_key(
+    key_hex: &str,
+    dest: &std::path::Path,
+    force: bool,
+) -> Result<wallet::Wallet, AppError> {
+    let wallet =
+        wallet::Wallet::from_private_key(key_hex).map_err(|e| AppError::usage(e.to_string()))?;
+    write_new_wallet(&wallet, dest, force)?;
+    Ok(wallet)
+}
+
+/// Write `wallet` to `dest` for `wallet new` and `wallet import-key`. An
+/// existing file is a usage error unless `force`, which keeps a copy.
+fn write_new_wallet(
+    wallet: &wallet::Wallet,
+    dest: &std::path::Path,
+    force: bool,
+) -> Result<(), AppError> {
+    if dest.exists() && !force {
+        return Err(AppError::usage(format!(
+            "{} already exists; pass --out to write elsewhere, or --force to replace it \
+             (a timestamped copy is kept)",
+            dest.display()
+        )));
+    }
+    let backup = wallet::create_wallet_file(&dest.to_string_lossy(), wallet, force)
+        .map_err(AppError::local_file)?;
+    if let Some(backup) = backup {
+        eprintln!("Kept the previous wallet as {}", backup.display());
+    }
+    Ok(())
+}
 
-    // Normalize node address (allow passing http://host:port or host:port)
-    let mut node_addr = config.node_address.trim().to_string();
+/// Allow passing http://host:port or host:port, and strip any path.
+fn normalize_node_addr(addr: &str) -> String {
+    let mut node_addr = addr.trim().to_string();
     if node_addr.starts_with("http://") {
         node_addr = node_addr.trim_start_matches("http://").to_string();
     } else if node_addr.starts_with("https://") {
@@ -524,9 +2181,124 @@ async fn run_send_mode(cli: Cli, config: config::Config) -> anyhow::Result<()> {
     if let Some(pos) = node_addr.find('/') {
         node_addr = node_addr[..pos].to_string();
     }
+    node_addr
+}
+
+/// How long one node gets to answer before the next candidate is tried.
+const NODE_ATTEMPT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Nodes to try for wallet operations, most preferred first: the node the
+/// wallet last reached, the configured node, then the configured peers.
+/// Standalone runs never list the public default node.
+fn node_candidates(wallet_node: Option<&str>, config: &config::Config) -> Vec<String> {
+    let mut nodes: Vec<String> = Vec::new();
+    let preferred = wallet_node
+        .into_iter()
+        .chain([config.node_address.as_str()]);
+    for addr in preferred.chain(config.peers.iter().map(String::as_str)) {
+        let addr = normalize_node_addr(addr);
+        if config.standalone && addr == protocol::DEFAULT_NODE {
+            continue;
+        }
+        if !addr.is_empty() && !nodes.contains(&addr) {
+            nodes.push(addr);
+        }
+    }
+    nodes
+}
+
+/// Where a run reads and writes and which node it talks to first.
+#[derive(Debug)]
+struct Settings {
+    wallet_path: String,
+    wallet_address: Option<String>,
+    node: String,
+    data_dir: std::path::PathBuf,
+    network: &'static str,
+    standalone: bool,
+}
+
+/// Resolve the effective settings the way the wallet commands do: the node
+/// the wallet last reached wins over the configured one.
+fn resolve_settings(config: &config::Config, wallet: Option<&wallet::Wallet>) -> Settings {
+    let wallet_node = wallet.and_then(|w| w.node_address.as_deref());
+    Settings {
+        wallet_path: config.wallet_path.clone(),
+        wallet_address: wallet.map(|w| w.address.clone()),
+        node: node_candidates(wallet_node, config)
+            .into_iter()
+            .next()
+            .unwrap_or_default(),
+        data_dir: config::get_config_dir(),
+        network: daemon::network_id(config.dev_mode, config.standalone),
+        standalone: config.standalone,
+    }
+}
 
+fn print_banner(settings: &Settings) {
+    let address = match &settings.wallet_address {
+        Some(addr) => format!("{}…", &addr[..addr.len().min(16)]),
+        None => "(no wallet yet)".to_string(),
+    };
+    println!(
+        "{} {} ({})",
+        "Wallet file:".blue(),
+        settings.wallet_path,
+        address
+    );
+    println!("{} {}", "Node:".blue(), settings.node);
+    println!("{} {}", "Data dir:".blue(), settings.data_dir.display());
+    println!("{} {}", "Network:".blue(), settings.network);
+    if settings.standalone {
+        println!(
+            "{}",
+            "STANDALONE: only standalone nodes are contacted; the public network is off limits"
+                .yellow()
+                .bold()
+        );
+    }
+}
+
+/// Run `op` against each node in turn until one answers. Fails only when
+/// every node did, listing each one with its error.
+async fn with_node_fallback<T, F, Fut>(nodes: &[String], op: F) -> Result<(String, T), AppError>
+where
+    F: Fn(String) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<T>>,
+{
+    let mut failures = Vec::new();
+    for node in nodes {
+        match tokio::time::timeout(NODE_ATTEMPT_TIMEOUT, op(node.clone())).await {
+            Ok(Ok(value)) => return Ok((node.clone(), value)),
+            Ok(Err(e)) => failures.push(format!("{} ({})", node, e)),
+            Err(_) => failures.push(format!("{} (timed out)", node)),
+        }
+    }
+    Err(AppError::network(anyhow::anyhow!(
+        "no node reachable, tried: {}",
+        failures.join(", ")
+    )))
+}
+
+/// Make `node` the wallet's first choice next time.
+fn remember_node(wallet_path: &str, wallet: &mut wallet::Wallet, node: &str) {
+    if wallet.node_address.as_deref() == Some(node) {
+        return;
+    }
+    wallet.node_address = Some(node.to_string());
+    if let Err(e) = wallet::save_wallet(wallet_path, wallet) {
+        eprintln!("Warning: failed to save preferred node: {}", e);
+    }
+}
+
+/// Submit `tx` to the first reachable node in `nodes` and return that node.
+async fn submit_signed_tx(
+    nodes: &[String],
+    tx: &blockchain::Transaction,
+    wait: bool,
+    receipts_dir: Option<&std::path::Path>,
+) -> Result<String, AppError> {
     // Submit transaction via WebSocket
-    println!("Connecting to node at {}", node_addr);
     println!(
         "Sending tx: from={} to={} amount={} signature_prefix={}",
         &tx.from[..std::cmp::min(8, tx.from.len())],
@@ -534,48 +2306,878 @@ async fn run_send_mode(cli: Cli, config: config::Config) -> anyhow::Result<()> {
         (tx.amount as f64) / 1000.0,
         &tx.signature[..std::cmp::min(16, tx.signature.len())]
     );
+    let cancel = interrupt::token();
+    if cancel.is_cancelled() {
+        return Err(interrupt::Interrupted("nothing sent".to_string()).into());
+    }
 
-    match crate::ws_client::submit_tx(&node_addr, &tx).await {
-        Ok(status) if status == "ok" => {
-            println!("Node response: {}", status);
-            
-            // Probe mempool to confirm transaction is present
-            if let Ok(mempool_vec) = crate::ws_client::fetch_mempool(&node_addr).await {
-                let mut found = false;
-                for ptx in mempool_vec.iter() {
-                    if ptx.signature == tx.signature
-                        || (ptx.from == tx.from
-                            && ptx.to == tx.to
-                            && ptx.amount == tx.amount)
-                    {
-                        println!(
-                            "Probe: transaction is present in node mempool (signature prefix={})",
-                            &ptx.signature[..std::cmp::min(16, ptx.signature.len())]
-                        );
-                        found = true;
-                        break;
-                    }
-                }
-                if !found {
-                    println!("Probe: transaction NOT found in node mempool");
+    let node = match crate::ws_client::broadcast_tx_to_any(nodes, tx).await {
+        Ok(node) => node,
+        // Ask the nodes we know for peers we have not tried yet
+        Err(mut failure) => {
+            let peers = with_node_fallback(nodes, |node| async move {
+                crate::ws_client::fetch_peers(&node).await
+            })
+            .await
+            .map(|(_, peers)| peers)
+            .unwrap_or_default();
+            let untried: Vec<String> = peers
+                .iter()
+                .map(|p| normalize_node_addr(p))
+                .filter(|p| !nodes.contains(p))
+                .collect();
+            match crate::ws_client::broadcast_tx_to_any(&untried, tx).await {
+                Ok(node) => node,
+                Err(more) => {
+                    failure.rejected.extend(more.rejected);
+                    failure.unreachable.extend(more.unreachable);
+                    return Err(broadcast_error(tx, failure));
                 }
             }
+        }
+    };
+    let node_addr = node.as_str();
+    println!("Node {} accepted the transaction", node_addr);
+    println!("Transaction id: {}", tx.txid());
+    if let Some(dir) = receipts_dir {
+        match receipts::save(dir, &receipts::Receipt::new(tx, node_addr)) {
+            Ok(path) => println!("Receipt: {}", path.display()),
+            Err(e) => eprintln!("Warning: failed to write receipt: {:#}", e),
+        }
+    }
+
+    // Probe mempool to confirm transaction is present
+    if let Ok(mempool_vec) = crate::ws_client::fetch_mempool(node_addr).await {
+        let mut found = false;
+        for ptx in mempool_vec.iter() {
+            if ptx.signature == tx.signature
+                || (ptx.from == tx.from && ptx.to == tx.to && ptx.amount == tx.amount)
+            {
+                println!(
+                    "Probe: transaction is present in node mempool (signature prefix={})",
+                    &ptx.signature[..std::cmp::min(16, ptx.signature.len())]
+                );
+                found = true;
+                break;
+            }
+        }
+        if !found {
+            println!("Probe: transaction NOT found in node mempool");
+        }
+    }
+    if wait {
+        let txid = tx.txid();
+        tokio::select! {
+            waited = wait_for_tx_status(node_addr, &txid, receipts_dir) => waited?,
+            _ = cancel.cancelled() => {
+                return Err(interrupt::Interrupted(format!(
+                    "transaction {} was submitted to {}; it may still confirm",
+                    txid,
+                    node_addr
+                ))
+                .into());
+            }
+        }
+    }
+    Ok(node)
+}
+
+/// Where sends write receipts; `None` when `write_receipts` is off.
+fn receipts_dir(config: &config::Config) -> Option<std::path::PathBuf> {
+    config.write_receipts.then(config::get_receipts_dir)
+}
+
+/// Record `status` on the receipt for `txid`, if there is one. A receipt
+/// that can't be written is worth a warning, never a failed command.
+fn note_on_receipt(dir: &std::path::Path, txid: &str, status: &daemon::TxStatus) {
+    if let Err(e) = receipts::record_status(dir, txid, status) {
+        eprintln!("Warning: failed to update receipt: {:#}", e);
+    }
+}
+
+async fn run_tx_command(action: TxCommand, config: config::Config) -> Result<(), AppError> {
+    match action {
+        TxCommand::Status { txid } => {
+            let dir = config::get_receipts_dir();
+            let receipt = receipts::load(&dir, &txid).map_err(AppError::local_file)?;
+            // The node that took it knows it best
+            let mut nodes = node_candidates(None, &config);
+            if let Some(receipt) = &receipt {
+                let node = normalize_node_addr(&receipt.node);
+                nodes.retain(|n| *n != node);
+                nodes.insert(0, node);
+            }
+            let (node, status) = with_node_fallback(&nodes, |node| {
+                let txid = txid.clone();
+                async move { ws_client::fetch_tx_status(&node, &txid).await }
+            })
+            .await?;
+            println!("{} ({})", receipts::describe(&status), node);
+            if receipt.is_some() {
+                note_on_receipt(&dir, &txid, &status);
+            }
             Ok(())
         }
-        Ok(status) => {
-            println!("Node response: {}", status);
-            if status.starts_with("rejected") || status.starts_with("error") {
-                let valid = crate::blockchain::verify_transaction_signature(&tx, &tx.pub_key);
+    }
+}
+
+fn run_receipts_command(action: ReceiptsCommand, json: bool) -> Result<(), AppError> {
+    match action {
+        ReceiptsCommand::List => {
+            let dir = config::get_receipts_dir();
+            let receipts = receipts::list(&dir).map_err(AppError::local_file)?;
+            if json {
+                println!("{}", serde_json::to_string(&receipts)?);
+                return Ok(());
+            }
+            if receipts.is_empty() {
+                println!("No receipts in {}", dir.display());
+            }
+            for receipt in &receipts {
                 println!(
-                    "Local signature verification: {}",
-                    if valid { "OK" } else { "FAILED" }
+                    "{} {} {} -> {} {} via {}: {}",
+                    receipt.submitted_at.format("%Y-%m-%d %H:%M:%S"),
+                    &receipt.txid[..16.min(receipt.txid.len())],
+                    &receipt.tx.from[..8.min(receipt.tx.from.len())],
+                    &receipt.tx.to[..8.min(receipt.tx.to.len())],
+                    blockchain::Blockchain::format_owe(receipt.tx.amount),
+                    receipt.node,
+                    receipt.status
                 );
             }
             Ok(())
         }
-        Err(e) => {
-            eprintln!("Failed to submit transaction to node: {}", e);
-            Err(e)
+    }
+}
+
+/// A rejection from any node makes the send a validation error; only when
+/// no node answered at all is it a network error.
+fn broadcast_error(
+    tx: &blockchain::Transaction,
+    failure: crate::ws_client::BroadcastFailure,
+) -> AppError {
+    if failure.rejected.is_empty() {
+        return AppError::network(failure);
+    }
+    let valid = crate::blockchain::verify_transaction_signature(tx, &tx.pub_key);
+    println!(
+        "Local signature verification: {}",
+        if valid { "OK" } else { "FAILED" }
+    );
+    AppError::rejected(failure.to_string())
+}
+
+/// Poll the node for the status of `txid` until it is confirmed or rejected,
+/// printing each state change and noting it on the transaction's receipt in
+/// `receipts_dir`, if any. Nodes without `gettxstatus` fall back to
+/// watching the mempool until the transaction leaves it.
+async fn wait_for_tx_status(
+    node_addr: &str,
+    txid: &str,
+    receipts_dir: Option<&std::path::Path>,
+) -> Result<(), AppError> {
+    use crate::daemon::TxStatus;
+
+    println!(
+        "Waiting for transaction {} ...",
+        &txid[..16.min(txid.len())]
+    );
+    let mut client = crate::ws_client::WsClient::connect(node_addr).await?;
+    if !client.supports("gettxstatus").await? {
+        println!("Node does not support gettxstatus; watching its mempool instead");
+        loop {
+            let mempool = crate::ws_client::fetch_mempool(node_addr).await?;
+            if !mempool.iter().any(|t| t.txid() == txid) {
+                println!("Transaction left the node mempool; check --tx-history for the outcome");
+                return Ok(());
+            }
+            tokio::time::sleep(TX_WAIT_POLL).await;
         }
     }
+    drop(client);
+
+    let mut last: Option<TxStatus> = None;
+    let mut limits = TxWaitLimits::default();
+    loop {
+        let polled = crate::ws_client::fetch_tx_status(node_addr, txid).await;
+        limits.check(&polled, txid)?;
+        match polled {
+            Ok(status) => {
+                if last.as_ref() != Some(&status) {
+                    if let Some(dir) = receipts_dir {
+                        note_on_receipt(dir, txid, &status);
+                    }
+                    match &status {
+                        TxStatus::Confirmed { height } => {
+                            println!("{}", format!("Confirmed in block {}", height).green());
+                            return Ok(());
+                        }
+                        TxStatus::Rejected { reason, .. } => {
+                            println!("{}", format!("Rejected: {}", reason).red());
+                            return Err(AppError::rejected(reason.clone()));
+                        }
+                        TxStatus::Pending => println!("Pending in node mempool"),
+                        TxStatus::Unknown => println!("Node does not know this transaction"),
+                    }
+                    last = Some(status);
+                }
+            }
+            Err(e) => eprintln!("Failed to query transaction status: {}", e),
+        }
+        tokio::time::sleep(TX_WAIT_POLL).await;
+    }
+}
+
+/// Time between two polls of `--wait`.
+const TX_WAIT_POLL: std::time::Duration = std::time::Duration::from_secs(5);
+/// Failed `gettxstatus` polls in a row before `--wait` gives up.
+const TX_WAIT_MAX_FAILURES: u32 = 12;
+/// Polls in a row answered `unknown` before `--wait` gives up: the node
+/// dropped the transaction or never had it.
+const TX_WAIT_MAX_UNKNOWN: u32 = 12;
+
+/// Ends a `--wait` that isn't getting anywhere, so it doesn't poll forever.
+#[derive(Default)]
+struct TxWaitLimits {
+    failures: u32,
+    unknown: u32,
+}
+
+impl TxWaitLimits {
+    fn check(
+        &mut self,
+        polled: &anyhow::Result<crate::daemon::TxStatus>,
+        txid: &str,
+    ) -> Result<(), AppError> {
+        match polled {
+            Ok(status) => {
+                self.failures = 0;
+                if *status == crate::daemon::TxStatus::Unknown {
+                    self.unknown += 1;
+                } else {
+                    self.unknown = 0;
+                }
+            }
+            Err(_) => self.failures += 1,
+        }
+        if self.failures >= TX_WAIT_MAX_FAILURES {
+            return Err(AppError::network(anyhow::anyhow!(
+                "gave up waiting for transaction {}: {} status queries in a row failed",
+                txid,
+                self.failures
+            )));
+        }
+        if self.unknown >= TX_WAIT_MAX_UNKNOWN {
+            return Err(AppError::network(anyhow::anyhow!(
+                "gave up waiting for transaction {}: the node has not known it for {} polls",
+                txid,
+                self.unknown
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(dir: &tempfile::TempDir, name: &str) -> String {
+        dir.path().join(name).to_string_lossy().to_string()
+    }
+
+    fn send_cli(wallet_path: &str, amount: &str) -> Cli {
+        Cli::parse_from([
+            "owonero",
+            "--send",
+            "--to",
+            "someone",
+            "--amount",
+            amount,
+            "--wallet-path",
+            wallet_path,
+        ])
+    }
+
+    fn test_config(wallet_path: &str, node_address: &str) -> config::Config {
+        config::Config {
+            wallet_path: wallet_path.to_string(),
+            node_address: node_address.to_string(),
+            sync_on_startup: false,
+            write_receipts: false,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn send_with_bad_amount_is_usage_error() {
+        let tmp = tempfile::tempdir().unwrap();
+        let wallet = temp_path(&tmp, "usage_wallet.json");
+        let err = run_send_mode(send_cli(&wallet, "0"), test_config(&wallet, "127.0.0.1:1"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+    }
+
+    #[tokio::test]
+    async fn send_to_unreachable_node_is_network_error() {
+        let tmp = tempfile::tempdir().unwrap();
+        let wallet = temp_path(&tmp, "network_wallet.json");
+        wallet::save_wallet(&wallet, &wallet::Wallet::new().unwrap()).unwrap();
+        let err = run_send_mode(send_cli(&wallet, "1"), test_config(&wallet, "127.0.0.1:1"))
+            .await
+            .unwrap_err();
+        assert_eq!(err.exit_code(), 3, "{}", err);
+    }
+
+    #[test]
+    fn wallet_summary_says_when_it_comes_from_the_local_chain() {
+        let mut bc = blockchain::Blockchain::new();
+        let genesis = bc.chain[0].clone();
+        bc.chain.push(blockchain::Block {
+            index: 812,
+            timestamp: genesis.timestamp,
+            ..genesis
+        });
+        let now = bc.chain[1].timestamp + chrono::Duration::hours(51);
+
+        assert_eq!(
+            staleness_note(&ChainSource::Unreachable, &bc, now).as_deref(),
+            Some("No node reachable: balance is as of block 812, 2d 3h ago")
+        );
+        assert_eq!(
+            staleness_note(&ChainSource::Offline, &bc, now).as_deref(),
+            Some("Offline: balance is as of block 812, 2d 3h ago")
+        );
+        let node = ChainSource::Node("127.0.0.1:6969".to_string());
+        assert_eq!(staleness_note(&node, &bc, now), None);
+
+        let json = wallet_info_json("04me", 1500, &ChainSource::Unreachable, &bc);
+        assert_eq!(json["source"], "local");
+        assert_eq!(json["height"], 812);
+        assert_eq!(json["tip_timestamp"], bc.chain[1].timestamp.timestamp());
+        assert_eq!(
+            wallet_info_json("04me", 0, &node, &bc)["node"],
+            "127.0.0.1:6969"
+        );
+        assert_eq!(AppError::stale("stale").exit_code(), 6);
+
+        // --offline combines with the other top-level flags
+        let cli = Cli::parse_from(["owonero", "--offline", "--no-update"]);
+        assert!(cli.offline);
+        let cli = Cli::parse_from(["owonero"]);
+        assert!(!cli.offline);
+    }
+
+    #[test]
+    fn waiting_for_a_transaction_gives_up_eventually() {
+        use crate::daemon::TxStatus;
+        let mut limits = TxWaitLimits::default();
+        for _ in 1..TX_WAIT_MAX_UNKNOWN {
+            limits.check(&Ok(TxStatus::Unknown), "ab").unwrap();
+        }
+        let err = limits.check(&Ok(TxStatus::Unknown), "ab").unwrap_err();
+        assert_eq!(err.exit_code(), 3, "{}", err);
+
+        // Failures count only in a row, and pending waits as long as it takes
+        let mut limits = TxWaitLimits::default();
+        for _ in 0..TX_WAIT_MAX_FAILURES * 2 {
+            limits
+                .check(&Err(anyhow::anyhow!("connection refused")), "ab")
+                .unwrap();
+            limits.check(&Ok(TxStatus::Pending), "ab").unwrap();
+        }
+        for _ in 1..TX_WAIT_MAX_FAILURES {
+            limits
+                .check(&Err(anyhow::anyhow!("connection refused")), "ab")
+                .unwrap();
+        }
+        let err = limits
+            .check(&Err(anyhow::anyhow!("connection refused")), "ab")
+            .unwrap_err();
+        assert_eq!(err.exit_code(), 3, "{}", err);
+    }
+
+    /// Start a standalone daemon on a free port and wait until it accepts
+    /// connections.
+    async fn spawn_node(bc: blockchain::Blockchain) -> String {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        tokio::spawn(daemon::run_daemon(
+            port,
+            Arc::new(parking_lot::Mutex::new(bc)),
+            Arc::new(daemon::PeerManager::new()),
+            true,
+            false,
+            None,
+            None,
+        ));
+        let node = format!("127.0.0.1:{}", port);
+        for _ in 0..50 {
+            if tokio::net::TcpStream::connect(&node).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        node
+    }
+
+    #[tokio::test]
+    async fn send_rejected_by_node_is_validation_error() {
+        let node = spawn_node(blockchain::Blockchain::new()).await;
+
+        // Without a wallet nothing is sent, and none is made up
+        let tmp = tempfile::tempdir().unwrap();
+        let wallet = temp_path(&tmp, "rejected_wallet.json");
+        let err = run_send_mode(send_cli(&wallet, "1"), test_config(&wallet, &node))
+            .await
+            .unwrap_err();
+        assert_eq!(err.exit_code(), 5, "{}", err);
+        assert!(err.to_string().contains("owonero wallet new"), "{}", err);
+        assert!(!std::path::Path::new(&wallet).exists());
+
+        // A fresh wallet has no funds
+        wallet::save_wallet(&wallet, &wallet::Wallet::new().unwrap()).unwrap();
+        let err = run_send_mode(send_cli(&wallet, "1"), test_config(&wallet, &node))
+            .await
+            .unwrap_err();
+        assert_eq!(err.exit_code(), 4, "{}", err);
+    }
+
+    /// A chain whose only block pays `amount` to `address`.
+    fn funded_chain(address: &str, amount: i64) -> blockchain::Blockchain {
+        let mut bc = blockchain::Blockchain::new();
+        let last = bc.chain.last().unwrap().clone();
+        let mut block = blockchain::Block {
+            index: 1,
+            timestamp: chrono::Utc::now(),
+            transactions: vec![blockchain::Transaction {
+                from: "coinbase".to_string(),
+                pub_key: String::new(),
+                to: address.to_string(),
+                amount,
+                signature: String::new(),
+                expires_at_height: None,
+            }],
+            prev_hash: last.hash,
+            hash: String::new(),
+            nonce: 0,
+            difficulty: 1,
+        };
+        block.hash = blockchain::Blockchain::calculate_hash(&block);
+        assert!(bc.add_block_skip_pow(block, 1, true));
+        bc
+    }
+
+    #[tokio::test]
+    async fn send_falls_back_to_live_node_and_remembers_it() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = temp_path(&tmp, "fallback_wallet.json");
+        let mut funded = wallet::Wallet::new().unwrap();
+        funded.node_address = Some("127.0.0.1:1".to_string());
+        wallet::save_wallet(&path, &funded).unwrap();
+        let live = spawn_node(funded_chain(&funded.address, 5000)).await;
+
+        // The wallet's own node is dead; the configured one answers
+        run_send_mode(send_cli(&path, "1"), test_config(&path, &live))
+            .await
+            .unwrap();
+        let saved = wallet::load_wallet_file(&path).unwrap();
+        assert_eq!(saved.node_address.as_deref(), Some(live.as_str()));
+    }
+
+    #[test]
+    fn exported_key_imports_to_the_same_address() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = temp_path(&tmp, "key_roundtrip");
+        let original = wallet::Wallet::new().unwrap();
+
+        assert_eq!(export_key(&original, "yes").unwrap_err().exit_code(), 2);
+        let key = export_key(&original, "I understand\n").unwrap();
+
+        let dest = std::path::Path::new(&dir).join("restored.json");
+        let imported = import_key(&format!("{}\n", key), &dest, false).unwrap();
+        assert_eq!(imported.address, original.address);
+        let reloaded = wallet::load_wallet_file(&dest.to_string_lossy()).unwrap();
+        assert_eq!(reloaded.address, original.address);
+        assert_eq!(reloaded.priv_key, original.priv_key);
+
+        // Never overwrites, and rejects keys that aren't P-256 PKCS#8
+        let other = wallet::Wallet::new().unwrap();
+        assert_eq!(
+            import_key(&other.priv_key, &dest, false)
+                .unwrap_err()
+                .exit_code(),
+            2
+        );
+        assert_eq!(
+            wallet::load_wallet_file(&dest.to_string_lossy())
+                .unwrap()
+                .address,
+            original.address
+        );
+        let elsewhere = std::path::Path::new(&dir).join("bad.json");
+        assert!(import_key("zz", &elsewhere, false).is_err());
+        assert!(import_key(&original.pub_key, &elsewhere, false).is_err());
+        assert!(!elsewhere.exists());
+    }
+
+    #[test]
+    fn wallet_node_overrides_configured_node() {
+        let mut config = test_config("/tmp/w.json", "config-node:6969");
+        config.peers = vec!["peer:6969".to_string()];
+        let mut wallet = wallet::Wallet::new().unwrap();
+
+        let settings = resolve_settings(&config, None);
+        assert_eq!(settings.node, "config-node:6969");
+        assert_eq!(settings.wallet_address, None);
+        assert_eq!(settings.wallet_path, "/tmp/w.json");
+        assert_eq!(settings.network, "owonero");
+
+        let settings = resolve_settings(&config, Some(&wallet));
+        assert_eq!(settings.node, "config-node:6969");
+        assert_eq!(settings.wallet_address.as_deref(), Some(&*wallet.address));
+
+        wallet.node_address = Some("wallet-node:6969".to_string());
+        assert_eq!(
+            resolve_settings(&config, Some(&wallet)).node,
+            "wallet-node:6969"
+        );
+        // Send and info mode try nodes in the same order
+        assert_eq!(
+            node_candidates(wallet.node_address.as_deref(), &config)[0],
+            resolve_settings(&config, Some(&wallet)).node
+        );
+    }
+
+    #[test]
+    fn standalone_runs_never_reach_the_default_node() {
+        let mut config = test_config("/tmp/w.json", protocol::DEFAULT_NODE);
+        config.peers = vec![
+            format!("http://{}/", protocol::DEFAULT_NODE),
+            "peer:6969".to_string(),
+        ];
+        apply_standalone(&mut config);
+        assert_eq!(config.node_address, "127.0.0.1:6969");
+        assert_eq!(config.peers, vec!["peer:6969".to_string()]);
+
+        // Not even when the wallet remembers the public node
+        let nodes = node_candidates(Some(protocol::DEFAULT_NODE), &config);
+        assert_eq!(nodes, vec!["127.0.0.1:6969", "peer:6969"]);
+        let settings = resolve_settings(&config, None);
+        assert_eq!(settings.node, "127.0.0.1:6969");
+        assert_eq!(settings.network, "owonero-standalone");
+    }
+
+    #[tokio::test]
+    async fn failed_fallback_lists_every_node_tried() {
+        let tmp = tempfile::tempdir().unwrap();
+        let wallet = temp_path(&tmp, "all_dead_wallet.json");
+        wallet::save_wallet(&wallet, &wallet::Wallet::new().unwrap()).unwrap();
+        let mut config = test_config(&wallet, "127.0.0.1:1");
+        config.peers = vec!["127.0.0.1:2".to_string(), "127.0.0.1:1".to_string()];
+        let err = run_send_mode(send_cli(&wallet, "1"), config)
+            .await
+            .unwrap_err();
+        assert_eq!(err.exit_code(), 3);
+        let msg = err.to_string();
+        assert!(
+            msg.contains("127.0.0.1:1 (") && msg.contains("127.0.0.1:2 ("),
+            "{}",
+            msg
+        );
+        assert_eq!(msg.matches("127.0.0.1:1 (").count(), 1, "{}", msg);
+    }
+
+    #[tokio::test]
+    async fn batch_send_reports_partial_failure_and_resumes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let payer = wallet::Wallet::new().unwrap();
+        wallet::save_wallet(&path("payer.json"), &payer).unwrap();
+        let node = spawn_node(funded_chain(&payer.address, 5000)).await;
+        let config = || test_config(&path("payer.json"), &node);
+        let batch_cli = |extra: &[&str]| {
+            let mut args = vec!["owonero".to_string(), "--send".into(), "--batch".into()];
+            args.push(path("payouts.csv"));
+            args.extend(extra.iter().map(|a| a.to_string()));
+            Cli::parse_from(args)
+        };
+        let mempool_len = || async { ws_client::fetch_mempool(&node).await.unwrap().len() };
+
+        // 3 OWE already pending leaves 2 OWE, so the 1.5 row can't be paid
+        let pending = payer
+            .create_signed_transaction(&wallet::Wallet::new().unwrap().address, 3000)
+            .unwrap();
+        assert_eq!(ws_client::submit_tx(&node, &pending).await.unwrap(), "ok");
+        let payees: Vec<String> = (0..3)
+            .map(|_| wallet::Wallet::new().unwrap().address)
+            .collect();
+        std::fs::write(
+            path("payouts.csv"),
+            format!(
+                "address,amount,memo\n{},1,first\n{},1.5\n{},0.5\n",
+                payees[0], payees[1], payees[2]
+            ),
+        )
+        .unwrap();
+
+        // Dry run validates and sends nothing
+        run_send_mode(batch_cli(&["--dry-run"]), config())
+            .await
+            .unwrap();
+        assert_eq!(mempool_len().await, 1);
+        assert!(!std::path::Path::new(&path("payouts.results.json")).exists());
+
+        let err = run_send_mode(batch_cli(&[]), config()).await.unwrap_err();
+        assert_eq!(err.exit_code(), 4, "{}", err);
+        let results = batch::read_results(&dir.join("payouts.results.json")).unwrap();
+        let statuses: Vec<batch::RowStatus> = results.rows.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                batch::RowStatus::Sent,
+                batch::RowStatus::Rejected,
+                batch::RowStatus::Sent
+            ]
+        );
+        assert_eq!(results.rows[0].payout.memo.as_deref(), Some("first"));
+        assert_eq!(
+            results.rows[1].error.as_deref(),
+            Some("rejected: insufficient funds")
+        );
+        assert_eq!(mempool_len().await, 3);
+
+        // Resuming retries only the rejected row
+        let err = run_send_mode(
+            batch_cli(&[
+                "--skip-completed",
+                &path("payouts.results.json"),
+                "--results",
+                &path("retry.json"),
+            ]),
+            config(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.exit_code(), 4, "{}", err);
+        assert_eq!(mempool_len().await, 3);
+        let retry = batch::read_results(&dir.join("retry.json")).unwrap();
+        assert_eq!(retry.rows[0].txid, results.rows[0].txid);
+        assert_eq!(retry.rows[1].status, batch::RowStatus::Rejected);
+        assert_eq!(retry.rows[2].txid, results.rows[2].txid);
+    }
+
+    #[tokio::test]
+    async fn batch_over_balance_or_with_bad_rows_sends_nothing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let payer = wallet::Wallet::new().unwrap();
+        let wallet_path = dir.join("payer.json").to_string_lossy().to_string();
+        wallet::save_wallet(&wallet_path, &payer).unwrap();
+        let node = spawn_node(funded_chain(&payer.address, 1000)).await;
+        let payee = wallet::Wallet::new().unwrap().address;
+
+        for (name, contents, code) in [
+            ("over.csv", format!("{},0.6\n{},0.6\n", payee, payee), 2),
+            ("bad.csv", format!("{},0.1\nnot-an-address,0.1\n", payee), 2),
+        ] {
+            let file = dir.join(name);
+            std::fs::write(&file, contents).unwrap();
+            let cli = Cli::parse_from(["owonero", "--send", "--batch", file.to_str().unwrap()]);
+            let err = run_send_mode(cli, test_config(&wallet_path, &node))
+                .await
+                .unwrap_err();
+            assert_eq!(err.exit_code(), code, "{}: {}", name, err);
+        }
+        assert!(ws_client::fetch_mempool(&node).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn corrupt_wallet_is_local_file_error() {
+        let tmp = tempfile::tempdir().unwrap();
+        let wallet = temp_path(&tmp, "corrupt_wallet.json");
+        std::fs::write(&wallet, "{not a wallet").unwrap();
+        let err = run_wallet_info_mode(test_config(&wallet, "127.0.0.1:1"), false, false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.exit_code(), 5);
+    }
+
+    #[tokio::test]
+    async fn cold_signing_round_trip() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+
+        // The offline machine holds the keys; the online one only the address
+        let cold = wallet::Wallet::new().unwrap();
+        wallet::save_wallet(&path("cold.json"), &cold).unwrap();
+
+        let mut bc = blockchain::Blockchain::new();
+        let last = bc.chain.last().unwrap().clone();
+        let mut block = blockchain::Block {
+            index: last.index + 1,
+            timestamp: chrono::Utc::now(),
+            transactions: vec![blockchain::Transaction {
+                from: "coinbase".to_string(),
+                pub_key: String::new(),
+                to: cold.address.clone(),
+                amount: 5000,
+                signature: String::new(),
+                expires_at_height: None,
+            }],
+            prev_hash: last.hash.clone(),
+            hash: String::new(),
+            nonce: 0,
+            difficulty: 1,
+        };
+        block.hash = blockchain::Blockchain::calculate_hash(&block);
+        assert!(bc.add_block_skip_pow(block, 1, true));
+        let node = spawn_node(bc).await;
+
+        // 1. online: build the unsigned transaction
+        let create = Cli::parse_from([
+            "owonero",
+            "--send",
+            "--offline-create",
+            "--from",
+            &cold.address,
+            "--to",
+            "bob",
+            "--amount",
+            "1.5",
+            "--out",
+            &path("unsigned.json"),
+        ]);
+        run_send_mode(create, test_config(&path("online.json"), &node))
+            .await
+            .unwrap();
+        assert!(!std::path::Path::new(&path("online.json")).exists());
+
+        // 2. offline: sign it
+        run_wallet_command(
+            WalletCommand::SignTx {
+                input: path("unsigned.json").into(),
+                out: path("signed.json").into(),
+            },
+            test_config(&path("cold.json"), "127.0.0.1:1"),
+        )
+        .await
+        .unwrap();
+
+        // An unsigned file can't be broadcast
+        let err = run_send_mode(
+            Cli::parse_from(["owonero", "--broadcast", &path("unsigned.json")]),
+            test_config(&path("online.json"), &node),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.exit_code(), 5, "{}", err);
+
+        // 3. online: verify and submit
+        run_send_mode(
+            Cli::parse_from(["owonero", "--broadcast", &path("signed.json")]),
+            test_config(&path("online.json"), &node),
+        )
+        .await
+        .unwrap();
+        let signed =
+            wallet::read_tx_file(&dir.join("signed.json"), wallet::SIGNED_TX_FORMAT).unwrap();
+        assert_eq!(signed.amount, 1500);
+        let mempool = ws_client::fetch_mempool(&node).await.unwrap();
+        assert!(mempool.iter().any(|t| t.txid() == signed.txid()));
+    }
+
+    #[tokio::test]
+    async fn tampered_signed_tx_is_refused_before_submitting() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let cold = wallet::Wallet::new().unwrap();
+        let mut tx = cold
+            .sign_unsigned(&wallet::unsigned_transaction(&cold.address, "bob", 1000))
+            .unwrap();
+        tx.amount = 100_000;
+        let file = dir.join("signed.json");
+        wallet::write_tx_file(&file, wallet::SIGNED_TX_FORMAT, &tx).unwrap();
+
+        // Unreachable node: a network error would mean we tried to submit
+        let wallet_path = dir.join("wallet.json").to_string_lossy().to_string();
+        let err = run_send_mode(
+            Cli::parse_from(["owonero", "--broadcast", &file.to_string_lossy()]),
+            test_config(&wallet_path, "127.0.0.1:1"),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.exit_code(), 4, "{}", err);
+    }
+
+    #[tokio::test]
+    async fn reserve_proof_round_trip_and_tampering() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let wallet_path = dir.join("wallet.json").to_string_lossy().to_string();
+        let proof_path = dir.join("proof.json");
+
+        let holder = wallet::Wallet::new().unwrap();
+        wallet::save_wallet(&wallet_path, &holder).unwrap();
+        let mut bc = blockchain::Blockchain::new();
+        let last = bc.chain.last().unwrap().clone();
+        let mut block = blockchain::Block {
+            index: last.index + 1,
+            timestamp: chrono::Utc::now(),
+            transactions: vec![blockchain::Transaction {
+                from: "coinbase".to_string(),
+                pub_key: String::new(),
+                to: holder.address.clone(),
+                amount: 2500,
+                signature: String::new(),
+                expires_at_height: None,
+            }],
+            prev_hash: last.hash.clone(),
+            hash: String::new(),
+            nonce: 0,
+            difficulty: 1,
+        };
+        block.hash = blockchain::Blockchain::calculate_hash(&block);
+        assert!(bc.add_block_skip_pow(block, 1, true));
+        let node = spawn_node(bc).await;
+        let config = test_config(&wallet_path, &node);
+
+        run_wallet_command(
+            WalletCommand::ProveReserve {
+                message: "otc-desk-7f3a".to_string(),
+                out: Some(proof_path.clone()),
+            },
+            config.clone(),
+        )
+        .await
+        .unwrap();
+        let verify = || WalletCommand::VerifyReserve {
+            proof: proof_path.clone(),
+        };
+        run_wallet_command(verify(), config.clone()).await.unwrap();
+
+        // Editing the claimed balance breaks the signature
+        let proof: wallet::ReserveProof =
+            serde_json::from_str(&std::fs::read_to_string(&proof_path).unwrap()).unwrap();
+        assert_eq!(proof.balance, 2500);
+        let mut edited = proof.clone();
+        edited.balance = 25_000;
+        std::fs::write(&proof_path, serde_json::to_string(&edited).unwrap()).unwrap();
+        let err = run_wallet_command(verify(), config.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(err.exit_code(), 4, "{}", err);
+
+        // A validly signed but false claim is caught by the node check
+        let lie = holder.prove_reserve("otc-desk-7f3a", 1, 25_000).unwrap();
+        std::fs::write(&proof_path, serde_json::to_string(&lie).unwrap()).unwrap();
+        let err = run_wallet_command(verify(), config).await.unwrap_err();
+        assert_eq!(err.exit_code(), 4, "{}", err);
+    }
 }
@@ -1,13 +1,21 @@
 use crate::blockchain::{Block, Blockchain};
+use crate::miner_log::{LogLine, MinerLog};
+use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use tokio::sync::mpsc;
-use tokio::time::{Duration, sleep};
+use tokio::time::{Duration, MissedTickBehavior, sleep};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MinerStats {
+    /// The rig's own wallet, which identifies it even with `--mine-to`.
+    #[serde(default)]
+    pub wallet: String,
+    /// Where mined blocks are paid.
+    #[serde(default)]
+    pub payout: String,
     pub total_hps: u64,
     pub sols: u64,
     pub avg_min: f64,
@@ -20,87 +28,1124 @@ pub struct MinerStats {
     pub rejected: u64,
     pub uptime: u64,
     pub pool_mode: bool,
+    /// H/s while the workers are actually hashing, this session.
+    #[serde(default)]
+    pub hash_loop_hps: u64,
+    /// H/s over all the time workers spend mining, template building
+    /// included, this session.
+    #[serde(default)]
+    pub effective_hps: u64,
+    /// Effective H/s of each worker over the last second, indexed by thread.
+    #[serde(default)]
+    pub thread_hps: Vec<u64>,
+    /// Difficulty of the template currently being mined.
+    #[serde(default)]
+    pub difficulty: u32,
+    /// Blocks found vs expected for the work done, in percent.
+    #[serde(default)]
+    pub luck: Option<f64>,
+    /// Average seconds to the next block at the current hashrate.
+    #[serde(default)]
+    pub expected_secs_to_block: Option<f64>,
+    /// Blocks this wallet has mined over its lifetime, according to the node.
+    #[serde(default)]
+    pub lifetime_mined: Option<u64>,
+    /// Blocks the node turned away because they were built on an old tip.
+    #[serde(default)]
+    pub stale: u64,
+    /// Cost of this session's accepted blocks.
+    #[serde(default)]
+    pub efficiency: crate::blockchain::Efficiency,
+    /// Workers are idle until the local chain catches up with the node.
+    #[serde(default)]
+    pub syncing: bool,
+    /// The node's side, from its `getinfo`; `None` until it answers.
+    #[serde(default)]
+    pub node: Option<NodeStatus>,
+    /// Templates dropped because the tip moved, this session.
+    #[serde(default)]
+    pub aborts: u64,
+    /// Aborts whose template came back unchanged and resumed from the
+    /// nonce it stopped at.
+    #[serde(default)]
+    pub wasted_aborts: u64,
+    /// Tip changes folded into an earlier restart instead of one of their
+    /// own.
+    #[serde(default)]
+    pub coalesced_restarts: u64,
+    /// E.g. "intensity 30% until 18:00"; `None` at a steady 100%.
+    #[serde(default)]
+    pub intensity: Option<String>,
+    /// Workers are paused because the machine runs on battery.
+    #[serde(default)]
+    pub on_battery: bool,
+}
+
+/// What the miner shows of the node it mines against. Field names follow
+/// `getinfo`; nodes that predate a field report it as 0.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeStatus {
+    #[serde(default)]
+    pub connections: u64,
+    #[serde(default)]
+    pub mempool_size: u64,
+    #[serde(default)]
+    pub peers: u64,
+    /// Unix time of the tip block.
+    #[serde(default)]
+    pub tip_timestamp: i64,
+}
+
+impl NodeStatus {
+    /// One line for the TUI footer, e.g. "Node: 3 connections · 12 in
+    /// mempool · last block 45s ago · 2 peers".
+    pub fn summary(&self, now: i64) -> String {
+        format!(
+            "Node: {} connections · {} in mempool · last block {} ago · {} peers",
+            self.connections,
+            self.mempool_size,
+            crate::miner_ui::format_age(now.saturating_sub(self.tip_timestamp).max(0) as u64),
+            self.peers
+        )
+    }
+}
+
+impl MinerStats {
+    /// Effective H/s as a share of hash-loop H/s, in percent: what is left
+    /// after fetching templates, signing coinbases and waiting on locks.
+    pub fn hashing_efficiency(&self) -> Option<f64> {
+        (self.hash_loop_hps > 0)
+            .then(|| self.effective_hps as f64 / self.hash_loop_hps as f64 * 100.0)
+    }
+
+    /// One-line summary for the periodic `--no-ui` output.
+    pub fn summary(&self) -> String {
+        let mut rig = if self.payout == self.wallet {
+            String::new()
+        } else {
+            format!(
+                "rig {} paying {} | ",
+                short_address(&self.wallet),
+                short_address(&self.payout)
+            )
+        };
+        if self.syncing {
+            rig.push_str("waiting for chain sync | ");
+        }
+        if self.on_battery {
+            rig.push_str("paused on battery | ");
+        }
+        if let Some(intensity) = &self.intensity {
+            rig.push_str(&format!("{} | ", intensity));
+        }
+        format!(
+            "{}{} H/s ({} effective) | threads {} | mined {} | accepted {} rejected {} stale {} | restarts {} ({} resumed) | luck {} | next block ~{} | per block {} H, {} kWh, {} kWh/OWE",
+            rig,
+            self.total_hps,
+            self.hashing_efficiency()
+                .map(|p| format!("{:.0}%", p))
+                .unwrap_or_else(|| "n/a".to_string()),
+            self.threads,
+            self.mined,
+            self.accepted,
+            self.rejected,
+            self.stale,
+            self.aborts,
+            self.wasted_aborts,
+            self.luck
+                .map(|l| format!("{:.0}%", l))
+                .unwrap_or_else(|| "n/a".to_string()),
+            self.expected_secs_to_block
+                .map(|s| crate::miner_ui::format_uptime(s as u64))
+                .unwrap_or_else(|| "n/a".to_string()),
+            self.efficiency
+                .hashes_per_block
+                .map(|h| format!("{:.0}", h))
+                .unwrap_or_else(|| "n/a".to_string()),
+            format_kwh(self.efficiency.kwh_per_block),
+            format_kwh(self.efficiency.kwh_per_owe),
+        )
+    }
+}
+
+/// First and last few characters of an address.
+fn short_address(address: &str) -> String {
+    match (
+        address.get(..8),
+        address.get(address.len().saturating_sub(6)..),
+    ) {
+        (Some(head), Some(tail)) if address.len() > 16 => format!("{}…{}", head, tail),
+        _ => address.to_string(),
+    }
+}
+
+/// kWh figure for display, or "n/a" when unknown.
+pub fn format_kwh(kwh: Option<f64>) -> String {
+    kwh.map(|k| format!("{:.4}", k))
+        .unwrap_or_else(|| "n/a".to_string())
+}
+
+/// What to mine and how; built from the CLI/config by the caller.
+#[derive(Debug, Clone)]
+pub struct MinerOptions {
+    pub node_addr: String,
+    /// How many blocks to mine (0 = forever)
+    pub blocks_to_mine: u64,
+    /// Maximum number of worker threads
+    pub threads: usize,
+    pub pool: bool,
+    pub intensity: u8,
+    /// When the intensity changes, and caps for single threads.
+    pub intensity_schedule: crate::intensity::IntensitySchedule,
+    /// Pause the workers while the machine runs on battery.
+    pub pause_on_battery: bool,
+    /// Let a supervisor lower/raise the active thread count to whatever
+    /// gives the best total hashrate under current system load.
+    pub auto_threads: bool,
+    /// Average power draw of the machine while mining, for kWh estimates.
+    pub power_watts: Option<f64>,
+    /// How often to poll the node for a new tip.
+    pub tip_poll: Duration,
+    /// How often to refresh the mempool copy.
+    pub mempool_poll: Duration,
+    /// Coinbase recipient when it isn't the local wallet.
+    pub mine_to: Option<String>,
+    /// Session reports to keep in the config directory (0 = write none).
+    pub session_reports_keep: usize,
+    /// Mine on an empty chain instead of waiting for the node to have
+    /// blocks. Only useful when starting a new network.
+    pub allow_genesis_mining: bool,
+    /// Mempool transactions to leave out of mined blocks.
+    pub tx_filter: TxFilter,
+    /// Coinbase shares paid to other addresses.
+    pub coinbase_split: CoinbaseSplit,
+    /// Memory check made before starting, for the log.
+    pub memory_plan: Option<MemoryPlan>,
+}
+
+/// Workers pause while the local chain is more than this many blocks behind
+/// the node's tip: their templates would have the wrong difficulty.
+const FAR_BEHIND_BLOCKS: u64 = 10;
+
+/// Found blocks waiting for the submitter, at most this many; the oldest
+/// give way first.
+const SUBMIT_QUEUE_MAX: usize = 8;
+
+/// Found blocks waiting to be submitted, newest first. After a reconnect
+/// only the newest still matters: anything at or below the node's tip lost
+/// already.
+#[derive(Debug)]
+pub struct SubmitQueue {
+    blocks: Vec<Block>,
+    max: usize,
+}
+
+impl SubmitQueue {
+    pub fn new(max: usize) -> Self {
+        Self {
+            blocks: Vec::new(),
+            max: max.max(1),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Queue `block`; returns the lowest block when that overflows the queue.
+    pub fn push(&mut self, block: Block) -> Option<Block> {
+        self.blocks.push(block);
+        // Highest index last, so `pop` takes the newest
+        self.blocks.sort_by_key(|b| b.index);
+        (self.blocks.len() > self.max).then(|| self.blocks.remove(0))
+    }
+
+    /// The newest block above `tip_index`, and the blocks dropped for being
+    /// at or below it.
+    pub fn next(&mut self, tip_index: Option<u64>) -> (Option<Block>, Vec<Block>) {
+        let dropped = match tip_index {
+            Some(tip) => {
+                let viable = self.blocks.partition_point(|b| b.index <= tip);
+                self.blocks.drain(..viable).collect()
+            }
+            None => Vec::new(),
+        };
+        (self.blocks.pop(), dropped)
+    }
+}
+
+/// Restarts asked for within this long of the last one are held and land
+/// together when it ends.
+const TEMPLATE_RESTART_WINDOW: Duration = Duration::from_millis(200);
+
+/// Version of the chain the workers build templates on. Bumping it makes
+/// every worker drop its template and build a new one. The first bump
+/// lands at once; more within `window` of it are coalesced into one that
+/// lands when the window ends, so a burst of tip updates (a reconnect,
+/// fast polling) costs workers one extra restart rather than one each.
+#[derive(Debug)]
+pub struct ChainVersion {
+    version: AtomicU64,
+    /// A bump is waiting for the window to end.
+    held: std::sync::atomic::AtomicBool,
+    /// When the last bump landed, in ms since `origin`; 0 for never.
+    landed_ms: AtomicU64,
+    origin: std::time::Instant,
+    window: Duration,
+    requested: AtomicU64,
+}
+
+impl ChainVersion {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            version: AtomicU64::new(0),
+            held: std::sync::atomic::AtomicBool::new(false),
+            landed_ms: AtomicU64::new(0),
+            origin: std::time::Instant::now(),
+            window,
+            requested: AtomicU64::new(0),
+        }
+    }
+
+    /// Milliseconds since `origin`, offset by one window so that a first
+    /// bump is never held.
+    fn now_ms(&self) -> u64 {
+        (self.origin.elapsed() + self.window).as_millis() as u64
+    }
+
+    /// Make the workers restart their templates.
+    pub fn bump(&self) {
+        self.requested.fetch_add(1, Ordering::Relaxed);
+        let now = self.now_ms();
+        let landed = self.landed_ms.load(Ordering::Relaxed);
+        if now.saturating_sub(landed) >= self.window.as_millis() as u64
+            && self
+                .landed_ms
+                .compare_exchange(landed, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.held.store(false, Ordering::Relaxed);
+            self.version.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.held.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// The version workers compare against, landing a held bump once its
+    /// window is over.
+    pub fn current(&self) -> u64 {
+        if self.held.load(Ordering::Relaxed) {
+            let now = self.now_ms();
+            let landed = self.landed_ms.load(Ordering::Relaxed);
+            if now.saturating_sub(landed) >= self.window.as_millis() as u64
+                && self.held.swap(false, Ordering::Relaxed)
+            {
+                self.landed_ms.store(now, Ordering::Relaxed);
+                self.version.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// Bumps asked for that were folded into another one.
+    pub fn coalesced(&self) -> u64 {
+        let held = self.held.load(Ordering::Relaxed) as u64;
+        (self.requested.load(Ordering::Relaxed))
+            .saturating_sub(self.version.load(Ordering::Relaxed) + held)
+    }
+}
+
+/// Where one worker's time goes: hashing, or building the template it
+/// hashes. Readable while the worker runs; one start/stop pair per
+/// template costs two clock reads and three atomic operations.
+#[derive(Debug, Default)]
+struct WorkerClock {
+    hashing_nanos: AtomicU64,
+    overhead_nanos: AtomicU64,
+    /// When the current hash loop began, in nanoseconds since the session
+    /// started plus one; 0 between loops.
+    hashing_since: AtomicU64,
+}
+
+impl WorkerClock {
+    /// The template begun at `template_started` is ready; hashing starts.
+    fn start_hashing(&self, origin: std::time::Instant, template_started: std::time::Instant) {
+        let now = origin.elapsed();
+        let overhead = now.saturating_sub(template_started.duration_since(origin));
+        self.overhead_nanos
+            .fetch_add(overhead.as_nanos() as u64, Ordering::Relaxed);
+        self.hashing_since
+            .store(now.as_nanos() as u64 + 1, Ordering::Relaxed);
+    }
+
+    fn stop_hashing(&self, origin: std::time::Instant) {
+        let since = self.hashing_since.swap(0, Ordering::Relaxed);
+        if since > 0 {
+            let now = origin.elapsed().as_nanos() as u64;
+            self.hashing_nanos
+                .fetch_add(now.saturating_sub(since - 1), Ordering::Relaxed);
+        }
+    }
+
+    /// Time spent hashing and building templates so far.
+    fn times(&self, origin: std::time::Instant) -> (Duration, Duration) {
+        let since = self.hashing_since.load(Ordering::Relaxed);
+        let current = if since > 0 {
+            (origin.elapsed().as_nanos() as u64).saturating_sub(since - 1)
+        } else {
+            0
+        };
+        (
+            Duration::from_nanos(self.hashing_nanos.load(Ordering::Relaxed) + current),
+            Duration::from_nanos(self.overhead_nanos.load(Ordering::Relaxed)),
+        )
+    }
+}
+
+/// Hash-loop and effective H/s from each worker's attempts and clock,
+/// summed over the workers.
+fn worker_rates(
+    attempts: &[u64],
+    clocks: &[WorkerClock],
+    origin: std::time::Instant,
+) -> (u64, u64) {
+    let mut hash_loop = 0.0;
+    let mut effective = 0.0;
+    for (&attempts, clock) in attempts.iter().zip(clocks) {
+        let (hashing, overhead) = clock.times(origin);
+        if !hashing.is_zero() {
+            hash_loop += attempts as f64 / hashing.as_secs_f64();
+            effective += attempts as f64 / (hashing + overhead).as_secs_f64();
+        }
+    }
+    (hash_loop as u64, effective as u64)
+}
+
+/// How long a finished session waits for its background tasks before
+/// aborting the ones still running.
+const TASK_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tokio tasks a mining session spawns, so none outlives it. Pollers stop
+/// as soon as `stop` is called; submitters finish what is queued first.
+struct BackgroundTasks {
+    stop: tokio::sync::watch::Sender<bool>,
+    handles: Vec<(&'static str, tokio::task::JoinHandle<()>)>,
+    running: Arc<AtomicUsize>,
+}
+
+/// Counts a task as running until it ends or is aborted.
+struct RunningTask(Arc<AtomicUsize>);
+
+impl Drop for RunningTask {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl BackgroundTasks {
+    fn new() -> Self {
+        Self {
+            stop: tokio::sync::watch::channel(false).0,
+            handles: Vec::new(),
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Run `task` until it returns by itself; it is expected to end once
+    /// its input channel closes.
+    fn spawn_draining<F>(&mut self, name: &'static str, task: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.running.fetch_add(1, Ordering::Relaxed);
+        let running = RunningTask(self.running.clone());
+        let handle = tokio::spawn(async move {
+            let _running = running;
+            task.await;
+        });
+        self.handles.push((name, handle));
+    }
+
+    /// Run `task` until it returns or `stop` is called.
+    fn spawn<F>(&mut self, name: &'static str, task: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let mut stop = self.stop.subscribe();
+        self.spawn_draining(name, async move {
+            tokio::select! {
+                _ = stop.wait_for(|stopped| *stopped) => {}
+                _ = task => {}
+            }
+        });
+    }
+
+    /// Stop the pollers and wait up to `timeout` for every task; the rest
+    /// are aborted. Returns the names of the aborted tasks.
+    async fn shutdown(self, timeout: Duration) -> Vec<&'static str> {
+        self.stop.send_replace(true);
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut aborted = Vec::new();
+        for (name, mut handle) in self.handles {
+            if tokio::time::timeout_at(deadline, &mut handle)
+                .await
+                .is_err()
+            {
+                handle.abort();
+                let _ = handle.await;
+                aborted.push(name);
+            }
+        }
+        aborted
+    }
+}
+
+/// Which mempool transactions go into this miner's blocks, from the
+/// `include_only_addresses` and `exclude_addresses` config lists. The
+/// coinbase is added after filtering and is never affected.
+#[derive(Debug, Clone, Default)]
+pub struct TxFilter {
+    include_only: HashSet<String>,
+    exclude: HashSet<String>,
+}
+
+fn address_key(address: &str) -> String {
+    address.trim().to_lowercase()
+}
+
+impl TxFilter {
+    pub fn new(include_only: &[String], exclude: &[String]) -> Self {
+        Self {
+            include_only: include_only.iter().map(|a| address_key(a)).collect(),
+            exclude: exclude.iter().map(|a| address_key(a)).collect(),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.include_only.is_empty() || !self.exclude.is_empty()
+    }
+
+    /// Whether `tx` touches an address the lists allow. An address on
+    /// both lists is excluded.
+    fn allows(&self, tx: &crate::blockchain::Transaction) -> bool {
+        let (from, to) = (address_key(&tx.from), address_key(&tx.to));
+        if self.exclude.contains(&from) || self.exclude.contains(&to) {
+            return false;
+        }
+        self.include_only.is_empty()
+            || self.include_only.contains(&from)
+            || self.include_only.contains(&to)
+    }
+
+    /// The transactions to mine, in mempool order, and how many were left
+    /// out. A transaction spending from an address that a left-out one
+    /// paid is left out too: without the payment it may be unfunded.
+    pub fn apply(
+        &self,
+        txs: Vec<crate::blockchain::Transaction>,
+    ) -> (Vec<crate::blockchain::Transaction>, usize) {
+        if !self.is_active() {
+            return (txs, 0);
+        }
+        let total = txs.len();
+        let mut unfunded: HashSet<String> = HashSet::new();
+        let kept: Vec<_> = txs
+            .into_iter()
+            .filter(|tx| {
+                let keep = self.allows(tx) && !unfunded.contains(&address_key(&tx.from));
+                if !keep {
+                    unfunded.insert(address_key(&tx.to));
+                }
+                keep
+            })
+            .collect();
+        let left_out = total - kept.len();
+        (kept, left_out)
+    }
+}
+
+/// Shares of every block's coinbase paid to other addresses (a pool's fee,
+/// a community fund), from the `coinbase_split` config list of
+/// `[address, percent]` pairs. There are no multi-output transactions, so
+/// each share is a coinbase of its own after the miner's.
+#[derive(Debug, Clone, Default)]
+pub struct CoinbaseSplit {
+    shares: Vec<(String, f64)>,
+}
+
+impl CoinbaseSplit {
+    /// Refuses malformed addresses, non-positive percentages and splits
+    /// adding up to more than 100%.
+    pub fn new(shares: &[(String, f64)]) -> Result<Self, String> {
+        for (address, percent) in shares {
+            if !crate::wallet::is_valid_address(address) {
+                return Err(format!("{} is not a valid address", address));
+            }
+            if !percent.is_finite() || *percent <= 0.0 {
+                return Err(format!("percentage for {} must be above 0", address));
+            }
+        }
+        let total: f64 = shares.iter().map(|(_, p)| p).sum();
+        if total > 100.0 {
+            return Err(format!("percentages add up to {}%, more than 100%", total));
+        }
+        Ok(Self {
+            shares: shares.to_vec(),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.shares.is_empty()
+    }
+
+    /// E.g. "2.5% to 04abcdef…a1b2c3, 1% to 04fedcba…c3b2a1".
+    pub fn describe(&self) -> String {
+        self.shares
+            .iter()
+            .map(|(address, percent)| format!("{}% to {}", percent, short_address(address)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Who gets what of `reward`: `payout` first with whatever the shares
+    /// leave, then each share rounded down. The amounts add up to exactly
+    /// `reward`; zero amounts are left out, as consensus refuses them.
+    pub fn outputs(&self, payout: &str, reward: i64) -> Vec<(String, i64)> {
+        let mut left = reward;
+        let mut shares = Vec::new();
+        for (address, percent) in &self.shares {
+            let amount = ((reward as f64 * percent / 100.0).floor() as i64).clamp(0, left);
+            left -= amount;
+            shares.push((address.clone(), amount));
+        }
+        std::iter::once((payout.to_string(), left))
+            .chain(shares)
+            .filter(|(_, amount)| *amount > 0)
+            .collect()
+    }
+}
+
+/// Share of available memory the miner plans to use; the rest is left for
+/// the system and whatever else runs on the machine.
+const MEMORY_USABLE_PERCENT: u64 = 80;
+/// Runtime, TUI and buffers besides the chain and the scratchpads.
+const BASE_MEMORY_BYTES: u64 = 32 * 1024 * 1024;
+/// Assumed size of one block in the JSON the chain is fetched as.
+const AVG_BLOCK_BYTES: u64 = 1024;
+/// The fetched JSON and the parsed chain are in memory together, and the
+/// parsed copy is larger than its JSON.
+const CHAIN_MEMORY_FACTOR: u64 = 3;
+
+/// How many mining threads fit in memory: the chain copy and runtime once,
+/// plus one scratchpad per thread, within `MEMORY_USABLE_PERCENT` of what
+/// the system has available.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryPlan {
+    pub requested: usize,
+    /// Threads that fit, at most `requested`; 0 when not even one does.
+    pub threads: usize,
+    pub available: u64,
+    /// Bytes needed besides the scratchpads.
+    pub base: u64,
+    pub per_thread: u64,
+}
+
+impl MemoryPlan {
+    pub fn new(requested: usize, node_height: u64, scratchpad: u64, available: u64) -> Self {
+        let base = BASE_MEMORY_BYTES + node_height * AVG_BLOCK_BYTES * CHAIN_MEMORY_FACTOR;
+        let usable = available / 100 * MEMORY_USABLE_PERCENT;
+        let per_thread = scratchpad.max(1);
+        let fit = usable.saturating_sub(base) / per_thread;
+        Self {
+            requested,
+            threads: requested.min(fit as usize),
+            available,
+            base,
+            per_thread,
+        }
+    }
+
+    /// The plan for this machine, or `None` when the OS doesn't say how
+    /// much memory is available.
+    pub fn for_system(requested: usize, node_height: u64) -> Option<Self> {
+        let mut system = sysinfo::System::new();
+        system.refresh_memory();
+        let available = system.available_memory();
+        (available > 0).then(|| {
+            Self::new(
+                requested,
+                node_height,
+                crate::blockchain::scratchpad_size() as u64,
+                available,
+            )
+        })
+    }
+
+    pub fn is_capped(&self) -> bool {
+        self.threads < self.requested
+    }
+
+    /// Estimated bytes for `threads` threads.
+    pub fn needed(&self, threads: usize) -> u64 {
+        self.base + self.per_thread * threads as u64
+    }
+
+    /// E.g. "4 thread(s) need ~40 MiB of 512 MiB available".
+    pub fn describe(&self) -> String {
+        let mib = |bytes: u64| bytes / (1024 * 1024);
+        format!(
+            "{} thread(s) need ~{} MiB of {} MiB available",
+            self.requested,
+            mib(self.needed(self.requested)),
+            mib(self.available)
+        )
+    }
+}
+
+/// What a mining session did, written to `session-<timestamp>.json` in the
+/// config directory when `start_mining` returns.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionReport {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub ended_at: chrono::DateTime<chrono::Utc>,
+    pub duration_secs: u64,
+    pub node: String,
+    pub wallet: String,
+    pub payout: String,
+    pub attempts: u64,
+    pub avg_hashrate: u64,
+    /// Best one-second total hashrate seen.
+    pub peak_hashrate: u64,
+    /// Blocks found, whatever the node made of them.
+    pub mined: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub stale: u64,
+    /// Shares the node accepted (pool mode).
+    pub shares: u64,
+    /// Coinbase paid by accepted blocks.
+    pub earned: i64,
+    // Effective parameters
+    pub threads: usize,
+    pub auto_threads: bool,
+    pub pool: bool,
+    pub intensity: u8,
+    pub blocks_to_mine: u64,
+    /// Where the report was saved, if it was.
+    #[serde(skip)]
+    pub saved_to: Option<std::path::PathBuf>,
+}
+
+impl SessionReport {
+    /// Three lines for the terminal once the TUI has closed.
+    pub fn summary(&self) -> String {
+        let mut blocks = format!(
+            "Blocks: {} accepted, {} rejected, {} stale; earned {}",
+            self.accepted,
+            self.rejected,
+            self.stale,
+            crate::blockchain::Blockchain::format_owe(self.earned)
+        );
+        if self.pool {
+            blocks.push_str(&format!("; {} shares", self.shares));
+        }
+        format!(
+            "Mined for {} on {} with {} thread(s){}\n\
+             Hashrate: {} H/s average, {} H/s peak, {} attempts\n{}",
+            crate::miner_ui::format_uptime(self.duration_secs),
+            self.node,
+            self.threads,
+            self.saved_to
+                .as_ref()
+                .map(|p| format!(" (report: {})", p.display()))
+                .unwrap_or_default(),
+            self.avg_hashrate,
+            self.peak_hashrate,
+            self.attempts,
+            blocks
+        )
+    }
+}
+
+/// Write `report` into `dir` and delete all but the newest `keep` reports.
+pub fn save_session_report(
+    dir: &std::path::Path,
+    report: &SessionReport,
+    keep: usize,
+) -> anyhow::Result<std::path::PathBuf> {
+    let path = dir.join(format!(
+        "session-{}.json",
+        report.ended_at.format("%Y%m%dT%H%M%SZ")
+    ));
+    std::fs::write(&path, serde_json::to_string_pretty(report)?)?;
+    // Timestamps in the names sort oldest first
+    let mut reports: Vec<_> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("session-") && n.ends_with(".json"))
+        })
+        .collect();
+    reports.sort();
+    for old in &reports[..reports.len().saturating_sub(keep)] {
+        let _ = std::fs::remove_file(old);
+    }
+    Ok(path)
+}
+
+/// Relative improvement a trial thread count must show before it is kept,
+/// so measurement noise does not cause flapping.
+const TUNER_MARGIN: f64 = 0.02;
+/// Windows to stay on a settled thread count before probing again.
+const TUNER_SETTLE_WINDOWS: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TunerPhase {
+    /// Measuring the current count before trying a neighbour.
+    Baseline,
+    /// Measuring `from ± 1`; `from_rate` is what we had before.
+    Trial {
+        from: usize,
+        from_rate: f64,
+        down: bool,
+        improved: bool,
+    },
+    /// Keeping the best count found for a few windows.
+    Settled { windows: u32 },
+}
+
+/// Hill-climbing thread count tuner for `--auto-threads`. Fed one total
+/// hashrate measurement per window, it tries one thread fewer (or more),
+/// keeps whichever configuration was faster and periodically re-probes
+/// since system load changes over time.
+#[derive(Debug, Clone)]
+pub struct ThreadTuner {
+    max: usize,
+    current: usize,
+    phase: TunerPhase,
+}
+
+impl ThreadTuner {
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            max,
+            current: max,
+            phase: TunerPhase::Baseline,
+        }
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Record the hashrate measured at the current thread count and return
+    /// the new count when the tuner wants to change it.
+    pub fn observe(&mut self, hashrate: f64) -> Option<usize> {
+        let before = self.current;
+        match self.phase {
+            TunerPhase::Baseline => self.start_trial(self.current, hashrate, true, false),
+            TunerPhase::Trial {
+                from,
+                from_rate,
+                down,
+                improved,
+            } => {
+                if hashrate > from_rate * (1.0 + TUNER_MARGIN) {
+                    // Better: keep it and keep going the same way
+                    let cur = self.current;
+                    self.start_trial(cur, hashrate, down, true);
+                } else {
+                    // Worse: go back, and try the other direction if the first
+                    // step down did not help at all
+                    self.current = from;
+                    if down && !improved {
+                        self.start_trial(from, from_rate, false, false);
+                    } else {
+                        self.phase = TunerPhase::Settled { windows: 0 };
+                    }
+                }
+            }
+            TunerPhase::Settled { windows } => {
+                self.phase = if windows + 1 >= TUNER_SETTLE_WINDOWS {
+                    TunerPhase::Baseline
+                } else {
+                    TunerPhase::Settled {
+                        windows: windows + 1,
+                    }
+                };
+            }
+        }
+        (self.current != before).then_some(self.current)
+    }
+
+    fn start_trial(&mut self, from: usize, from_rate: f64, down: bool, improved: bool) {
+        let next = if down {
+            from.checked_sub(1).filter(|&n| n >= 1)
+        } else {
+            Some(from + 1).filter(|&n| n <= self.max)
+        };
+        match next {
+            Some(n) => {
+                self.current = n;
+                self.phase = TunerPhase::Trial {
+                    from,
+                    from_rate,
+                    down,
+                    improved,
+                };
+            }
+            None if down && !improved => self.start_trial(from, from_rate, false, false),
+            None => {
+                self.current = from;
+                self.phase = TunerPhase::Settled { windows: 0 };
+            }
+        }
+    }
 }
 
 pub async fn start_mining(
-    node_addr: &str,
-    blocks_to_mine: u64,
-    threads: usize,
-    pool: bool,
-    _intensity: u8,
+    opts: MinerOptions,
     stats_tx: Option<mpsc::Sender<MinerStats>>,
-    log_tx: Option<mpsc::Sender<String>>,
+    log_tx: Option<mpsc::Sender<LogLine>>,
     shutdown_rx: Option<tokio::sync::watch::Receiver<bool>>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<SessionReport> {
     let wallet = crate::config::load_wallet()?;
+    run_session(
+        opts,
+        wallet,
+        stats_tx,
+        log_tx,
+        shutdown_rx,
+        BackgroundTasks::new(),
+    )
+    .await
+}
 
-    if let Some(ref tx) = log_tx {
-        let _ = tx
-            .send(format!(
-                "Mining for wallet {} to node {}",
-                wallet.address, node_addr
-            ))
-            .await;
+/// `start_mining` with the rig's wallet loaded. Every task spawned here is
+/// tracked by `tasks` and has ended by the time this returns.
+async fn run_session(
+    opts: MinerOptions,
+    wallet: crate::wallet::Wallet,
+    stats_tx: Option<mpsc::Sender<MinerStats>>,
+    log_tx: Option<mpsc::Sender<LogLine>>,
+    shutdown_rx: Option<tokio::sync::watch::Receiver<bool>>,
+    mut tasks: BackgroundTasks,
+) -> anyhow::Result<SessionReport> {
+    let MinerOptions {
+        node_addr,
+        blocks_to_mine,
+        threads,
+        pool,
+        intensity,
+        intensity_schedule,
+        pause_on_battery,
+        auto_threads,
+        power_watts,
+        tip_poll,
+        mempool_poll,
+        mine_to,
+        session_reports_keep,
+        allow_genesis_mining,
+        tx_filter,
+        coinbase_split,
+        memory_plan,
+    } = opts;
+    let started_at = chrono::Utc::now();
+    let node_addr = node_addr.as_str();
+    let threads = threads.max(1);
+    // Stats and logs name the rig's own wallet; only the coinbase pays this
+    let payout = mine_to.unwrap_or_else(|| wallet.address.clone());
+
+    let log = MinerLog::new(log_tx);
+    log.info(format!(
+        "Mining for wallet {} to node {}",
+        wallet.address, node_addr
+    ));
+    if payout != wallet.address {
+        log.info(format!("Paying blocks to {}", payout));
+    }
+    if coinbase_split.is_active() {
+        log.info(format!(
+            "Coinbase split active: {}; the rest to {}",
+            coinbase_split.describe(),
+            short_address(&payout)
+        ));
+    }
+    match memory_plan {
+        Some(plan) if plan.threads == 0 => log.warn(format!(
+            "Memory: {}; not even one thread fits, mining anyway (--force)",
+            plan.describe()
+        )),
+        Some(plan) if plan.is_capped() => log.warn(format!(
+            "Memory: {}; capped to {} thread(s)",
+            plan.describe(),
+            plan.threads
+        )),
+        Some(plan) => log.info(format!("Memory: {}", plan.describe())),
+        None => log.info("Memory: available memory unknown, not checked"),
+    }
+    if tx_filter.is_active() {
+        log.info(format!(
+            "Transaction filter active: {} included-only and {} excluded address(es)",
+            tx_filter.include_only.len(),
+            tx_filter.exclude.len()
+        ));
     }
 
-    // Fetch blockchain via WebSocket
-    let blockchain: Blockchain = match crate::ws_client::fetch_chain(node_addr).await {
-        Ok(bc) => {
-            if let Some(ref tx) = log_tx {
-                let _ = tx
-                    .send(format!(
-                        "Connected to node via WebSocket, chain height: {}",
-                        bc.chain.len() - 1
-                    ))
-                    .await;
-            }
-            bc
-        }
-        Err(e) => {
-            if let Some(ref tx) = log_tx {
-                let _ = tx
-                    .send(format!(
-                        "Failed to fetch chain from node ({}); using local chain",
-                        e
-                    ))
-                    .await;
-            } else {
-                eprintln!("Could not connect to node {}: {}", node_addr, e);
+    let shutdown_flag = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    if let Some(mut rx) = shutdown_rx {
+        let flag = shutdown_flag.clone();
+        tasks.spawn("shutdown listener", async move {
+            loop {
+                if rx.changed().await.is_ok() {
+                    if *rx.borrow() {
+                        flag.store(true, std::sync::atomic::Ordering::Relaxed);
+                        break;
+                    }
+                } else {
+                    break;
+                }
             }
-            Blockchain::load_from_file("blockchain.json").unwrap_or_else(|_| Blockchain::new())
-        }
-    };
+        });
+    }
 
+    // Fetch blockchain via WebSocket
+    let waiting = MinerStats {
+        wallet: wallet.address.clone(),
+        payout: payout.clone(),
+        threads,
+        pool_mode: pool,
+        syncing: true,
+        ..Default::default()
+    };
+    let blockchain = initial_chain(
+        node_addr,
+        allow_genesis_mining,
+        &log,
+        stats_tx.as_ref().map(|tx| (tx, &waiting)),
+        &shutdown_flag,
+    )
+    .await;
     let blockchain = Arc::new(Mutex::new(blockchain));
     let latest_block: Arc<Mutex<Option<Block>>> = Arc::new(Mutex::new(None));
+    // Block we found but the node has not accepted yet. Workers mine on top
+    // of it instead of the old tip while the submission is in flight.
+    let pending_tip: Arc<Mutex<Option<Block>>> = Arc::new(Mutex::new(None));
     let mempool_shared: Arc<Mutex<Vec<crate::blockchain::Transaction>>> =
         Arc::new(Mutex::new(Vec::new()));
-    let attempts = Arc::new(AtomicU64::new(0));
+    // Per-worker attempt counters; the total is their sum.
+    let thread_attempts: Arc<Vec<AtomicU64>> =
+        Arc::new((0..threads).map(|_| AtomicU64::new(0)).collect());
+    let worker_clocks: Arc<Vec<WorkerClock>> =
+        Arc::new((0..threads).map(|_| WorkerClock::default()).collect());
+    let active_threads = Arc::new(AtomicUsize::new(threads));
+    // Blocks the work so far should have found on average, and the
+    // difficulty of the latest template, for luck reporting.
+    let expected_blocks: Arc<Mutex<f64>> = Arc::new(Mutex::new(0.0));
+    let current_difficulty = Arc::new(AtomicU32::new(0));
     let mined = Arc::new(AtomicU64::new(0));
 
     let (block_tx, mut block_rx) = mpsc::channel::<Block>(threads * 2);
     let (share_tx, mut share_rx) = mpsc::channel::<(String, u32, u64, Block)>(threads * 2);
     let (block_sync_tx, block_sync_rx) = std::sync::mpsc::channel::<Block>();
-    let (_share_sync_tx, share_sync_rx) = std::sync::mpsc::channel::<(String, u32, u64, Block)>();
+    let (share_sync_tx, share_sync_rx) = std::sync::mpsc::channel::<(String, u32, u64, Block)>();
 
     let attempts_history: Arc<Mutex<VecDeque<u64>>> = Arc::new(Mutex::new(VecDeque::new()));
     let accepted = Arc::new(AtomicU64::new(0));
     let rejected = Arc::new(AtomicU64::new(0));
-    let chain_version = Arc::new(std::sync::atomic::AtomicU64::new(0));
+    let stale = Arc::new(AtomicU64::new(0));
+    let shares = Arc::new(AtomicU64::new(0));
+    let peak_hps = Arc::new(AtomicU64::new(0));
+    // Blocks the node accepted this session and the coinbase they paid us.
+    let accepted_blocks = Arc::new(AtomicU64::new(0));
+    let earned = Arc::new(AtomicI64::new(0));
+    let chain_version = Arc::new(ChainVersion::new(TEMPLATE_RESTART_WINDOW));
+    // Templates dropped for a restart, and those that came back unchanged
+    let aborts = Arc::new(AtomicU64::new(0));
+    let wasted_aborts = Arc::new(AtomicU64::new(0));
+    // Set while catching up with a node that is far ahead
+    let syncing = Arc::new(std::sync::atomic::AtomicBool::new(false));
     let start_time = std::time::Instant::now();
 
-    let shutdown_flag = Arc::new(std::sync::atomic::AtomicBool::new(false));
-    if let Some(mut rx) = shutdown_rx {
-        let flag = shutdown_flag.clone();
-        tokio::spawn(async move {
+    // Each worker's intensity, following the schedule
+    let now = chrono::Local::now().naive_local();
+    let setting = intensity_schedule.setting_at(intensity, now);
+    let duties: Arc<Vec<crate::intensity::DutyCycle>> = Arc::new(
+        (0..threads)
+            .map(|id| {
+                crate::intensity::DutyCycle::new(
+                    intensity_schedule.thread_intensity(id, setting.intensity),
+                )
+            })
+            .collect(),
+    );
+    let intensity_status: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    if setting.intensity < 100 || intensity_schedule.is_active() {
+        let status = setting.describe(now);
+        log.info(format!("Mining at {}", status));
+        *intensity_status.lock() = Some(status);
+    }
+    if intensity_schedule.is_active() {
+        let duties = duties.clone();
+        let intensity_status = intensity_status.clone();
+        let log = log.clone();
+        tasks.spawn("intensity scheduler", async move {
+            let mut current = setting.intensity;
+            let mut interval = tokio::time::interval(crate::intensity::SCHEDULE_CHECK);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval.tick().await;
             loop {
-                if rx.changed().await.is_ok() {
-                    if *rx.borrow() {
-                        flag.store(true, std::sync::atomic::Ordering::Relaxed);
+                interval.tick().await;
+                let now = chrono::Local::now().naive_local();
+                let setting = intensity_schedule.setting_at(intensity, now);
+                let status = setting.describe(now);
+                if setting.intensity != current {
+                    for (id, duty) in duties.iter().enumerate() {
+                        duty.set(intensity_schedule.thread_intensity(id, setting.intensity));
+                    }
+                    log.info(format!("Schedule: {} (was {}%)", status, current));
+                    current = setting.intensity;
+                }
+                *intensity_status.lock() = Some(status);
+            }
+        });
+    }
+
+    // Set while paused by --pause-on-battery
+    let on_battery = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    if pause_on_battery {
+        let on_battery = on_battery.clone();
+        let chain_version = chain_version.clone();
+        let log = log.clone();
+        tasks.spawn("power monitor", async move {
+            let mut pause = crate::power::BatteryPause::default();
+            let mut interval = tokio::time::interval(crate::power::POWER_POLL);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let source = tokio::task::spawn_blocking(crate::power::power_source)
+                    .await
+                    .unwrap_or(crate::power::PowerSource::Unknown);
+                match pause.observe(source) {
+                    Some(crate::power::PowerChange::Pause) => {
+                        on_battery.store(true, Ordering::Relaxed);
+                        // Restart templates so the workers park promptly
+                        chain_version.bump();
+                        log.warn("On battery: mining paused until AC power returns");
+                    }
+                    Some(crate::power::PowerChange::Resume) => {
+                        on_battery.store(false, Ordering::Relaxed);
+                        log.info("On AC power: mining resumed");
+                    }
+                    Some(crate::power::PowerChange::Unsupported) => {
+                        log.warn(
+                            "--pause-on-battery: this system doesn't report its power source; ignoring it",
+                        );
                         break;
                     }
-                } else {
-                    break;
+                    None => {}
                 }
             }
         });
@@ -108,44 +1153,107 @@ pub async fn start_mining(
 
     // Block submitter via WebSocket
     let node_addr_clone = node_addr.to_string();
-    let log_tx_clone1 = log_tx.clone();
+    let log_submitter = log.clone();
     let accepted_clone1 = accepted.clone();
     let rejected_clone1 = rejected.clone();
+    let stale_clone1 = stale.clone();
+    let accepted_blocks_submitter = accepted_blocks.clone();
+    let earned_submitter = earned.clone();
+    let payout_submitter = payout.clone();
     let mempool_for_submitter = mempool_shared.clone();
     let latest_block_submitter = latest_block.clone();
+    let pending_tip_submitter = pending_tip.clone();
     let chain_version_submitter = chain_version.clone();
-    let submitter_handle = tokio::spawn(async move {
-        while let Some(block) = block_rx.recv().await {
-            let local_latest_opt = latest_block_submitter
-                .lock()
-                .unwrap()
-                .as_ref()
-                .map(|b| b.hash.clone());
-            if let Some(local_latest) = local_latest_opt {
-                if local_latest != block.prev_hash {
-                    chain_version_submitter.fetch_add(1, Ordering::Relaxed);
-                    continue;
+    tasks.spawn_draining("block submitter", async move {
+        // Forget our unconfirmed tip if it is `block`; workers then rebuild
+        // templates on the confirmed tip.
+        let drop_pending = |block: &Block| {
+            let mut pending = pending_tip_submitter.lock();
+            if pending.as_ref().is_some_and(|p| p.hash == block.hash) {
+                *pending = None;
+                chain_version_submitter.bump();
+            }
+        };
+        let mut backoff = crate::retry::Backoff::for_node();
+        let mut queue = SubmitQueue::new(SUBMIT_QUEUE_MAX);
+        loop {
+            // Wait only when nothing is queued, then take whatever else the
+            // workers found in the meantime
+            if queue.is_empty() {
+                match block_rx.recv().await {
+                    Some(block) => queue.push(block),
+                    None => break,
+                };
+            }
+            while let Ok(block) = block_rx.try_recv() {
+                if let Some(old) = queue.push(block) {
+                    log_submitter.debug(format!("Dropped block {}: queue full", old.index));
+                    drop_pending(&old);
                 }
             }
+            let (local_latest_opt, tip_index) = {
+                let latest = latest_block_submitter.lock();
+                let latest = latest.as_ref();
+                (latest.map(|b| b.hash.clone()), latest.map(|b| b.index))
+            };
+            let (next, dropped) = queue.next(tip_index);
+            for old in &dropped {
+                log_submitter.debug(format!("Dropped block {}: tip already past it", old.index));
+                drop_pending(old);
+            }
+            if !dropped.is_empty() {
+                chain_version_submitter.bump();
+            }
+            let Some(block) = next else {
+                continue;
+            };
+            if let Some(local_latest) = local_latest_opt
+                && local_latest != block.prev_hash
+            {
+                // Stale, or built on our own block that was rejected
+                log_submitter.debug(format!(
+                    "Dropped block {}: built on an old tip",
+                    block.index
+                ));
+                drop_pending(&block);
+                chain_version_submitter.bump();
+                continue;
+            }
 
-            match crate::ws_client::submit_block(&node_addr_clone, &block).await {
-                Ok(status) if status == "ok" => {
+            let submitted = crate::retry::until_ok(
+                "node",
+                &mut backoff,
+                || crate::ws_client::submit_block(&node_addr_clone, &block),
+                |line| log_submitter.warn(line),
+            )
+            .await;
+            match submitted {
+                status if status == "ok" => {
                     accepted_clone1.fetch_add(1, Ordering::Relaxed);
-                    if let Some(ref tx) = log_tx_clone1 {
-                        let _ = tx
-                            .send(format!(
-                                "Block accepted! Index={} Hash={}",
-                                block.index, block.hash
-                            ))
-                            .await;
-                    }
+                    accepted_blocks_submitter.fetch_add(1, Ordering::Relaxed);
+                    let reward: i64 = block
+                        .transactions
+                        .iter()
+                        .filter(|t| t.from == "coinbase" && t.to == payout_submitter)
+                        .map(|t| t.amount)
+                        .sum();
+                    earned_submitter.fetch_add(reward, Ordering::Relaxed);
+                    log_submitter.info(format!(
+                        "Block accepted! Index={} Hash={}",
+                        block.index, block.hash
+                    ));
                     {
-                        let mut latest_block_guard = latest_block_submitter.lock().unwrap();
+                        let mut latest_block_guard = latest_block_submitter.lock();
                         *latest_block_guard = Some(block.clone());
-                        chain_version_submitter.fetch_add(1, Ordering::Relaxed);
+                        // Confirmed: it is the tip now, no longer pending
+                        let mut pending = pending_tip_submitter.lock();
+                        if pending.as_ref().is_some_and(|p| p.hash == block.hash) {
+                            *pending = None;
+                        }
+                        chain_version_submitter.bump();
                     }
                     {
-                        let mut mp = mempool_for_submitter.lock().unwrap();
+                        let mut mp = mempool_for_submitter.lock();
                         mp.retain(|t| {
                             !block
                                 .transactions
@@ -154,78 +1262,137 @@ pub async fn start_mining(
                         });
                     }
                 }
-                Ok(status) => {
-                    rejected_clone1.fetch_add(1, Ordering::Relaxed);
-                    if let Some(ref tx) = log_tx_clone1 {
-                        let _ = tx.send(format!("Node rejected block: {}", status)).await;
-                    } else {
-                        eprintln!("Node rejected block: {}", status);
-                    }
+                status if status.starts_with("stale") => {
+                    // Bad luck rather than a bug: someone else's block won
+                    stale_clone1.fetch_add(1, Ordering::Relaxed);
+                    drop_pending(&block);
+                    log_submitter.warn(format!("Block went stale: {}", status));
                 }
-                Err(e) => {
+                status if status.starts_with("orphan") => {
+                    // Not lost: the node adds it once its parent arrives
+                    log_submitter.info(format!("Block {} parked: {}", block.index, status));
+                }
+                status => {
                     rejected_clone1.fetch_add(1, Ordering::Relaxed);
-                    if let Some(ref tx) = log_tx_clone1 {
-                        let _ = tx.send(format!("Failed to submit block: {}", e)).await;
-                    } else {
-                        eprintln!("Failed to submit block: {}", e);
-                    }
+                    drop_pending(&block);
+                    log_submitter.warn(format!("Node rejected block: {}", status));
                 }
             }
         }
-        Ok::<(), anyhow::Error>(())
     });
 
     // Share submitter via WebSocket
     let node_addr_clone2 = node_addr.to_string();
-    let log_tx_clone2 = log_tx.clone();
+    let log_shares = log.clone();
     let accepted_clone2 = accepted.clone();
     let rejected_clone2 = rejected.clone();
-    let share_submitter_handle = tokio::spawn(async move {
+    let shares_submitter = shares.clone();
+    tasks.spawn_draining("share submitter", async move {
+        let mut backoff = crate::retry::Backoff::for_node();
         while let Some((_wallet_addr, _nonce, _attempts_val, block)) = share_rx.recv().await {
-            match crate::ws_client::submit_block(&node_addr_clone2, &block).await {
-                Ok(status) if status == "ok" => {
+            let submitted = crate::retry::until_ok(
+                "node",
+                &mut backoff,
+                || crate::ws_client::submit_block(&node_addr_clone2, &block),
+                |line| log_shares.warn(line),
+            )
+            .await;
+            match submitted {
+                status if status == "ok" => {
                     accepted_clone2.fetch_add(1, Ordering::Relaxed);
-                    if let Some(ref tx) = log_tx_clone2 {
-                        let _ = tx.send("Share accepted".to_string()).await;
-                    }
+                    shares_submitter.fetch_add(1, Ordering::Relaxed);
+                    log_shares.debug("Share accepted");
                 }
-                Ok(status) => {
+                status => {
                     rejected_clone2.fetch_add(1, Ordering::Relaxed);
-                    if let Some(ref tx) = log_tx_clone2 {
-                        let _ = tx.send(format!("Node rejected share: {}", status)).await;
-                    } else {
-                        eprintln!("Node rejected share: {}", status);
-                    }
-                }
-                Err(e) => {
-                    rejected_clone2.fetch_add(1, Ordering::Relaxed);
-                    if let Some(ref tx) = log_tx_clone2 {
-                        let _ = tx.send(format!("Failed to submit share: {}", e)).await;
-                    }
+                    log_shares.warn(format!("Node rejected share: {}", status));
                 }
             }
         }
-        Ok::<(), anyhow::Error>(())
     });
 
     // Mempool poller via WebSocket
     {
         let node_addr = node_addr.to_string();
         let mempool_clone = mempool_shared.clone();
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(2));
+        let log = log.clone();
+        tasks.spawn("mempool poller", async move {
+            let mut interval = tokio::time::interval(mempool_poll);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut backoff = crate::retry::Backoff::for_node();
+            let mut last_left_out = 0;
             loop {
                 interval.tick().await;
-                if let Ok(mempool_vec) = crate::ws_client::fetch_mempool(&node_addr).await {
-                    let mut mp = mempool_clone.lock().unwrap();
-                    *mp = mempool_vec;
+                let mempool_vec = crate::retry::until_ok(
+                    "node (mempool)",
+                    &mut backoff,
+                    || crate::ws_client::fetch_mempool(&node_addr),
+                    |line| log.warn(line),
+                )
+                .await;
+                let total = mempool_vec.len();
+                let (mempool_vec, left_out) = tx_filter.apply(mempool_vec);
+                if left_out != last_left_out {
+                    log.info(format!(
+                        "Transaction filter: leaving out {} of {} mempool transaction(s)",
+                        left_out, total
+                    ));
+                    last_left_out = left_out;
                 }
+                *mempool_clone.lock() = mempool_vec;
+            }
+        });
+    }
+
+    // Lifetime block count comes from the chain so it survives restarts
+    let lifetime_mined: Arc<Mutex<Option<u64>>> = Arc::new(Mutex::new(None));
+    {
+        let node_addr = node_addr.to_string();
+        let address = payout.clone();
+        let lifetime_mined = lifetime_mined.clone();
+        let log = log.clone();
+        tasks.spawn("lifetime blocks poller", async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(
+                crate::protocol::LIFETIME_MINED_POLL_SECS,
+            ));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut backoff = crate::retry::Backoff::for_node();
+            loop {
+                interval.tick().await;
+                let page = crate::retry::until_ok(
+                    "node (mined blocks)",
+                    &mut backoff,
+                    || crate::ws_client::fetch_mined_blocks(&node_addr, &address, 0, None),
+                    |line| log.warn(line),
+                )
+                .await;
+                *lifetime_mined.lock() = Some(page.total);
+            }
+        });
+    }
+
+    // Node overview for the TUI footer; hidden while the node doesn't answer
+    let node_status: Arc<Mutex<Option<NodeStatus>>> = Arc::new(Mutex::new(None));
+    if stats_tx.is_some() {
+        let node_addr = node_addr.to_string();
+        let node_status = node_status.clone();
+        tasks.spawn("node status poller", async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(crate::protocol::NODE_INFO_POLL_SECS));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let status = crate::ws_client::fetch_info(&node_addr)
+                    .await
+                    .ok()
+                    .and_then(|info| serde_json::from_value(info).ok());
+                *node_status.lock() = status;
             }
         });
     }
 
     // Block forwarder
-    let _block_forwarder = {
+    let block_forwarder = {
         let block_tx = block_tx.clone();
         std::thread::spawn(move || {
             while let Ok(block) = block_sync_rx.recv() {
@@ -234,7 +1401,7 @@ pub async fn start_mining(
         })
     };
 
-    let _share_forwarder = {
+    let share_forwarder = {
         let share_tx = share_tx.clone();
         std::thread::spawn(move || {
             while let Ok(share) = share_sync_rx.recv() {
@@ -243,88 +1410,202 @@ pub async fn start_mining(
         })
     };
 
+    // Thread count supervisor
+    if auto_threads {
+        let window_secs = std::env::var("OWONERO_AUTO_THREADS_WINDOW")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|&v| v > 0)
+            .unwrap_or(30);
+        let thread_attempts = thread_attempts.clone();
+        let active_threads = active_threads.clone();
+        let chain_version = chain_version.clone();
+        let log = log.clone();
+        tasks.spawn("thread supervisor", async move {
+            let mut tuner = ThreadTuner::new(threads);
+            let mut interval = tokio::time::interval(Duration::from_secs(window_secs));
+            interval.tick().await;
+            let mut last_total = sum_attempts(&thread_attempts);
+            loop {
+                interval.tick().await;
+                let total = sum_attempts(&thread_attempts);
+                let rate = total.saturating_sub(last_total) as f64 / window_secs as f64;
+                last_total = total;
+                let before = tuner.current();
+                if let Some(n) = tuner.observe(rate) {
+                    active_threads.store(n, Ordering::Relaxed);
+                    // Restart templates so parked workers stop promptly
+                    chain_version.bump();
+                    log.info(format!(
+                        "auto-threads: {} -> {} threads ({:.0} H/s at {})",
+                        before, n, rate, before
+                    ));
+                }
+            }
+        });
+    }
+
     // Stats reporter
-    let stats_handle = if let Some(stats_tx) = stats_tx {
+    if let Some(stats_tx) = stats_tx {
         let stats_tx = stats_tx.clone();
-        let attempts_clone = attempts.clone();
+        let thread_attempts = thread_attempts.clone();
+        let worker_clocks = worker_clocks.clone();
+        let active_threads = active_threads.clone();
+        let expected_blocks = expected_blocks.clone();
+        let current_difficulty = current_difficulty.clone();
+        let lifetime_mined = lifetime_mined.clone();
         let accepted_clone = accepted.clone();
         let rejected_clone = rejected.clone();
+        let stale_clone = stale.clone();
+        let accepted_blocks = accepted_blocks.clone();
+        let earned = earned.clone();
         let mined_clone = mined.clone();
         let attempts_history_clone = attempts_history.clone();
+        let wallet_address = wallet.address.clone();
+        let payout = payout.clone();
+        let peak_hps = peak_hps.clone();
+        let syncing = syncing.clone();
+        let node_status = node_status.clone();
+        let chain_version = chain_version.clone();
+        let aborts = aborts.clone();
+        let wasted_aborts = wasted_aborts.clone();
+        let intensity_status = intensity_status.clone();
+        let on_battery = on_battery.clone();
 
-        Some(tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(1));
+        tasks.spawn("stats reporter", async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(
+                crate::protocol::MINER_STATS_INTERVAL_SECS,
+            ));
+            let mut last_per_thread: Vec<u64> = vec![0; threads];
             loop {
                 interval.tick().await;
 
-                let total_attempts = attempts_clone.load(Ordering::Relaxed);
+                let per_thread: Vec<u64> = thread_attempts
+                    .iter()
+                    .map(|a| a.load(Ordering::Relaxed))
+                    .collect();
+                let thread_hps: Vec<u64> = per_thread
+                    .iter()
+                    .zip(&last_per_thread)
+                    .map(|(now, before)| now.saturating_sub(*before))
+                    .collect();
+                let (hash_loop_hps, effective_hps) =
+                    worker_rates(&per_thread, &worker_clocks, start_time);
+                last_per_thread = per_thread;
+                peak_hps.fetch_max(thread_hps.iter().sum(), Ordering::Relaxed);
+                let total_attempts = sum_attempts(&thread_attempts);
                 let acc = accepted_clone.load(Ordering::Relaxed);
                 let rej = rejected_clone.load(Ordering::Relaxed);
                 let mined = mined_clone.load(Ordering::Relaxed);
                 let uptime = start_time.elapsed().as_secs();
 
                 let avg_min = {
-                    let hist = attempts_history_clone.lock().unwrap();
+                    let hist = attempts_history_clone.lock();
                     if hist.is_empty() {
                         0.0
                     } else {
                         hist.iter().sum::<u64>() as f64 / hist.len() as f64 / 60.0
                     }
                 };
-                let total_hps = if uptime > 0 {
-                    total_attempts / uptime
-                } else {
-                    0
-                };
+                let total_hps = total_attempts.checked_div(uptime).unwrap_or(0);
+                let difficulty = current_difficulty.load(Ordering::Relaxed);
+                let luck = crate::blockchain::luck_percent(mined, *expected_blocks.lock());
+                let expected_secs_to_block =
+                    crate::blockchain::expected_secs_to_block(difficulty, total_hps as f64);
 
                 let stats = MinerStats {
+                    wallet: wallet_address.clone(),
+                    payout: payout.clone(),
                     total_hps,
                     sols: mined,
                     avg_min,
                     avg_hour: 0.0,
                     avg_day: 0.0,
-                    threads,
+                    threads: active_threads.load(Ordering::Relaxed),
                     mined,
                     attempts: total_attempts,
                     accepted: acc,
                     rejected: rej,
                     uptime,
                     pool_mode: pool,
+                    hash_loop_hps,
+                    effective_hps,
+                    thread_hps,
+                    difficulty,
+                    luck,
+                    expected_secs_to_block,
+                    lifetime_mined: *lifetime_mined.lock(),
+                    stale: stale_clone.load(Ordering::Relaxed),
+                    efficiency: crate::blockchain::mining_efficiency(
+                        total_attempts,
+                        accepted_blocks.load(Ordering::Relaxed),
+                        earned.load(Ordering::Relaxed),
+                        uptime,
+                        power_watts,
+                    ),
+                    syncing: syncing.load(Ordering::Relaxed),
+                    node: node_status.lock().clone(),
+                    aborts: aborts.load(Ordering::Relaxed),
+                    wasted_aborts: wasted_aborts.load(Ordering::Relaxed),
+                    coalesced_restarts: chain_version.coalesced(),
+                    intensity: intensity_status.lock().clone(),
+                    on_battery: on_battery.load(Ordering::Relaxed),
                 };
 
                 let _ = stats_tx.send(stats).await;
             }
-        }))
-    } else {
-        None
-    };
+        });
+    }
 
     // Mining workers
     let mut worker_handles: Vec<std::thread::JoinHandle<()>> = Vec::new();
-    for _id in 0..threads {
-        let wallet_address = wallet.address.clone();
-        let wallet_pub_key = wallet.pub_key.clone();
-        let wallet_priv_key = wallet.priv_key.clone();
+    for id in 0..threads {
+        let wallet = wallet.clone();
+        let payout = payout.clone();
         let blockchain = blockchain.clone();
         let mempool_shared = mempool_shared.clone();
-        let attempts = attempts.clone();
+        let thread_attempts = thread_attempts.clone();
+        let worker_clocks = worker_clocks.clone();
+        let active_threads = active_threads.clone();
+        let expected_blocks = expected_blocks.clone();
+        let current_difficulty = current_difficulty.clone();
         let block_sync_tx = block_sync_tx.clone();
         let shutdown_flag = shutdown_flag.clone();
         let latest_block_worker = latest_block.clone();
+        let pending_tip_worker = pending_tip.clone();
         let chain_version_worker = chain_version.clone();
+        let aborts = aborts.clone();
+        let wasted_aborts = wasted_aborts.clone();
         let mined = mined.clone();
+        let syncing = syncing.clone();
+        let coinbase_split = coinbase_split.clone();
+        let duties = duties.clone();
+        let on_battery = on_battery.clone();
 
         let handle = std::thread::spawn(move || {
+            // The last template given up on, to pick up where it stopped
+            let mut aborted: Option<Block> = None;
             loop {
                 if shutdown_flag.load(std::sync::atomic::Ordering::Relaxed) {
                     break;
                 }
+                // Parked by the auto-threads supervisor, at 0% intensity,
+                // on battery, or until the local chain has caught up
+                if id >= active_threads.load(Ordering::Relaxed)
+                    || duties[id].percent() == 0
+                    || on_battery.load(Ordering::Relaxed)
+                    || syncing.load(Ordering::Relaxed)
+                {
+                    std::thread::sleep(Duration::from_millis(200));
+                    continue;
+                }
 
-                let prev_block = {
-                    if let Some(ref lb) = *latest_block_worker.lock().unwrap() {
+                let template_started = std::time::Instant::now();
+                let confirmed = {
+                    if let Some(ref lb) = *latest_block_worker.lock() {
                         lb.clone()
                     } else {
-                        let bc = blockchain.lock().unwrap();
+                        let bc = blockchain.lock();
                         if let Some(last) = bc.chain.last() {
                             last.clone()
                         } else {
@@ -333,56 +1614,87 @@ pub async fn start_mining(
                         }
                     }
                 };
+                let prev_block = {
+                    let pending = pending_tip_worker.lock();
+                    template_parent(&confirmed, pending.as_ref()).clone()
+                };
 
                 let diff = {
-                    let bc = blockchain.lock().unwrap();
+                    let bc = blockchain.lock();
                     let dyn_diff = bc.get_dynamic_difficulty();
                     if pool {
-                        dyn_diff.saturating_sub(2).max(1)
+                        crate::blockchain::share_difficulty(dyn_diff)
                     } else {
                         dyn_diff
                     }
                 };
 
-                let mempool_txs = {
-                    let mp = mempool_shared.lock().unwrap();
+                let mut mempool_txs = {
+                    let mp = mempool_shared.lock();
                     mp.clone()
                 };
+                // Our pending block already carries some of these, and some
+                // may expire before our block. Keep the node's order: a
+                // transaction may spend an earlier one's output.
+                mempool_txs.retain(|t| {
+                    !t.expired_at(prev_block.index + 1)
+                        && !prev_block
+                            .transactions
+                            .iter()
+                            .any(|bt| bt.signature == t.signature)
+                });
 
                 let mut mempool_with_coinbase: Vec<crate::blockchain::Transaction> = Vec::new();
                 let reward_amount: i64 = {
-                    let bc = blockchain.lock().unwrap();
+                    let bc = blockchain.lock();
                     bc.get_block_reward(prev_block.index + 1)
                 };
-                let mut coinbase_tx = crate::blockchain::Transaction {
-                    from: "coinbase".to_string(),
-                    pub_key: wallet_pub_key.clone(),
-                    to: wallet_address.clone(),
-                    amount: reward_amount,
-                    signature: String::new(),
-                };
-                let _ = crate::blockchain::sign_transaction(&mut coinbase_tx, &wallet_priv_key);
-                mempool_with_coinbase.push(coinbase_tx);
-                mempool_with_coinbase.extend(mempool_txs.into_iter());
+                mempool_with_coinbase.extend(coinbase_txs(
+                    &wallet,
+                    &payout,
+                    reward_amount,
+                    &coinbase_split,
+                ));
+                mempool_with_coinbase.extend(mempool_txs);
 
+                if aborted.as_ref().is_some_and(|b| {
+                    Blockchain::resumes(b, &prev_block, &mempool_with_coinbase, diff)
+                }) {
+                    wasted_aborts.fetch_add(1, Ordering::Relaxed);
+                }
                 let mut local_attempts = 0u64;
+                worker_clocks[id].start_hashing(start_time, template_started);
                 let block_opt = crate::blockchain::Blockchain::mine_block_with_cancel(
                     &prev_block,
                     mempool_with_coinbase,
                     diff,
                     &mut local_attempts,
-                    Some(&*attempts),
+                    Some(&thread_attempts[id]),
                     Some(&*chain_version_worker),
+                    Some(&duties[id]),
+                    &mut aborted,
                 );
+                worker_clocks[id].stop_hashing(start_time);
+                if block_opt.is_none() && !shutdown_flag.load(Ordering::Relaxed) {
+                    aborts.fetch_add(1, Ordering::Relaxed);
+                }
+                current_difficulty.store(diff, Ordering::Relaxed);
+                *expected_blocks.lock() +=
+                    local_attempts as f64 / crate::blockchain::expected_hashes(diff);
 
-                attempts.fetch_add(local_attempts, Ordering::Relaxed);
-
-                if let Some(block) = block_opt {
-                    let target = crate::blockchain::Blockchain::difficulty_to_target(diff as u64);
-                    if block.hash.starts_with(&target) {
-                        mined.fetch_add(1, Ordering::Relaxed);
-                        let _ = block_sync_tx.send(block);
+                if let Some(block) = block_opt
+                    && crate::blockchain::hash_meets_difficulty(&block.hash, diff)
+                {
+                    mined.fetch_add(1, Ordering::Relaxed);
+                    // Start on the next template right away instead of
+                    // hashing on the old tip until the node answers. Only
+                    // blocks on the confirmed tip qualify, so we never run
+                    // more than one unconfirmed block ahead.
+                    if block.prev_hash == confirmed.hash {
+                        *pending_tip_worker.lock() = Some(block.clone());
+                        chain_version_worker.bump();
                     }
+                    let _ = block_sync_tx.send(block);
                 }
             }
         });
@@ -395,19 +1707,85 @@ pub async fn start_mining(
         let node_addr = node_addr.to_string();
         let shutdown = shutdown_flag.clone();
         let latest_block_poller = latest_block.clone();
+        let pending_tip_poller = pending_tip.clone();
         let chain_version_poller = chain_version.clone();
-        tokio::spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_millis(500));
+        let blockchain_poller = blockchain.clone();
+        let log_poller = log.clone();
+        let syncing = syncing.clone();
+        tasks.spawn("tip poller", async move {
+            let mut interval = tokio::time::interval(tip_poll);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut backoff = crate::retry::Backoff::for_node();
             loop {
                 interval.tick().await;
                 if shutdown.load(std::sync::atomic::Ordering::Relaxed) {
                     break;
                 }
 
-                if let Ok(block) = crate::ws_client::fetch_latest_block(&node_addr).await {
-                    *latest_block_poller.lock().unwrap() = Some(block);
-                    chain_version_poller.fetch_add(1, Ordering::Relaxed);
+                let block = crate::retry::until_ok(
+                    "node",
+                    &mut backoff,
+                    || crate::ws_client::fetch_latest_block(&node_addr),
+                    |line| log_poller.warn(line),
+                )
+                .await;
+                let local_height = blockchain_poller.lock().chain.last().map_or(0, |b| b.index);
+                let behind = block.index.saturating_sub(local_height);
+                if behind > FAR_BEHIND_BLOCKS && !syncing.swap(true, Ordering::Relaxed) {
+                    log_poller.info(format!(
+                        "Node is {} blocks ahead; pausing workers to sync",
+                        behind
+                    ));
+                    // Abandon templates built on the old chain
+                    chain_version_poller.bump();
+                }
+                {
+                    let mut latest = latest_block_poller.lock();
+                    // Only restart workers when the tip actually moved
+                    if latest.as_ref().is_some_and(|l| l.hash == block.hash) {
+                        continue;
+                    }
+                    let mut pending = pending_tip_poller.lock();
+                    if pending
+                        .as_ref()
+                        .is_some_and(|p| p.hash == block.hash || p.prev_hash != block.hash)
+                    {
+                        // Ours got in, or someone else's block won
+                        *pending = None;
+                    }
+                    *latest = Some(block);
+                }
+                // Difficulty and rewards come from the local copy, so
+                // bring it in line before workers pick up the new tip
+                let synced = match sync_local_chain(&node_addr, &blockchain_poller).await {
+                    Ok(ChainRepair::Rewind(height)) => {
+                        log_poller.info(format!(
+                            "Node switched branches; resynced from height {}",
+                            height
+                        ));
+                        true
+                    }
+                    Ok(ChainRepair::FullResync) => {
+                        log_poller.info("Node switched branches; refetched the chain");
+                        true
+                    }
+                    Ok(_) => true,
+                    Err(e) => {
+                        log_poller.warn(format!("Failed to sync local chain: {}", e));
+                        false
+                    }
+                };
+                if syncing.load(Ordering::Relaxed) {
+                    if synced {
+                        syncing.store(false, Ordering::Relaxed);
+                        let height = blockchain_poller.lock().chain.last().map_or(0, |b| b.index);
+                        log_poller.info(format!("Caught up at height {}; resuming", height));
+                    } else {
+                        // Stay paused and retry on the next poll
+                        *latest_block_poller.lock() = None;
+                    }
                 }
+                chain_version_poller.bump();
             }
         });
     }
@@ -426,17 +1804,902 @@ pub async fn start_mining(
     }
 
     shutdown_flag.store(true, std::sync::atomic::Ordering::Relaxed);
+    // Abandon the templates in progress rather than finishing them
+    chain_version.bump();
     for handle in worker_handles {
         let _ = handle.join();
     }
+    // With every sender gone the submitters run out of work once they have
+    // sent what the workers found
+    drop(block_sync_tx);
+    drop(share_sync_tx);
     drop(block_tx);
     drop(share_tx);
 
-    submitter_handle.abort();
-    share_submitter_handle.abort();
-    if let Some(handle) = stats_handle {
-        handle.abort();
+    let aborted = tasks.shutdown(TASK_STOP_TIMEOUT).await;
+    if !aborted.is_empty() {
+        log.warn(format!("Stopped without finishing: {}", aborted.join(", ")));
+    }
+    let _ = block_forwarder.join();
+    let _ = share_forwarder.join();
+
+    let ended_at = chrono::Utc::now();
+    let duration_secs = (ended_at - started_at).num_seconds().max(0) as u64;
+    let attempts = sum_attempts(&thread_attempts);
+    let mut report = SessionReport {
+        started_at,
+        ended_at,
+        duration_secs,
+        node: node_addr.to_string(),
+        wallet: wallet.address.clone(),
+        payout,
+        attempts,
+        avg_hashrate: attempts.checked_div(duration_secs).unwrap_or(0),
+        peak_hashrate: peak_hps.load(Ordering::Relaxed),
+        mined: mined.load(Ordering::Relaxed),
+        accepted: accepted_blocks.load(Ordering::Relaxed),
+        rejected: rejected.load(Ordering::Relaxed),
+        stale: stale.load(Ordering::Relaxed),
+        shares: shares.load(Ordering::Relaxed),
+        earned: earned.load(Ordering::Relaxed),
+        threads,
+        auto_threads,
+        pool,
+        intensity,
+        blocks_to_mine,
+        saved_to: None,
+    };
+    if session_reports_keep > 0 {
+        match save_session_report(
+            &crate::config::get_config_dir(),
+            &report,
+            session_reports_keep,
+        ) {
+            Ok(path) => report.saved_to = Some(path),
+            Err(e) => eprintln!("Failed to save session report: {}", e),
+        }
+    }
+    Ok(report)
+}
+
+/// The coinbases paying `reward` to `payout` and the `split` addresses.
+fn coinbase_txs(
+    wallet: &crate::wallet::Wallet,
+    payout: &str,
+    reward: i64,
+    split: &CoinbaseSplit,
+) -> Vec<crate::blockchain::Transaction> {
+    split
+        .outputs(payout, reward)
+        .into_iter()
+        .map(|(to, amount)| coinbase_tx(wallet, &to, amount))
+        .collect()
+}
+
+/// Coinbase paying `reward` to `payout`, signed by the rig's own wallet so
+/// the block still shows which rig found it.
+fn coinbase_tx(
+    wallet: &crate::wallet::Wallet,
+    payout: &str,
+    reward: i64,
+) -> crate::blockchain::Transaction {
+    let mut tx = crate::blockchain::Transaction {
+        from: "coinbase".to_string(),
+        pub_key: wallet.pub_key.clone(),
+        to: payout.to_string(),
+        amount: reward,
+        signature: String::new(),
+        expires_at_height: None,
+    };
+    let _ = crate::blockchain::sign_transaction(&mut tx, &wallet.priv_key);
+    tx
+}
+
+/// Parent for the next template: our own not-yet-accepted block when it
+/// extends the confirmed tip, otherwise the confirmed tip itself.
+fn template_parent<'a>(confirmed: &'a Block, pending: Option<&'a Block>) -> &'a Block {
+    match pending {
+        Some(p) if p.prev_hash == confirmed.hash => p,
+        _ => confirmed,
+    }
+}
+
+/// How many recent block hashes the poller compares against the node.
+const TIP_HISTORY_WINDOW: usize = 64;
+
+/// What the local chain copy needs to match the node's recent history.
+#[derive(Debug, PartialEq)]
+enum ChainRepair {
+    /// Already matches the node.
+    InSync,
+    /// A prefix of the node's chain: append blocks from this height.
+    Extend(u64),
+    /// Diverged: drop blocks from this height and fetch the node's.
+    Rewind(u64),
+    /// No common block within the history window.
+    FullResync,
+}
+
+/// Compare the local chain with the node's latest blocks (oldest first).
+fn plan_chain_repair(local: &Blockchain, history: &[crate::daemon::TipEntry]) -> ChainRepair {
+    let Some(node_tip) = history.last() else {
+        return ChainRepair::InSync;
+    };
+    let common = history.iter().rev().find(|entry| {
+        local
+            .chain
+            .get(entry.index as usize)
+            .is_some_and(|b| b.hash == entry.hash)
+    });
+    match common {
+        Some(entry) if entry.index == node_tip.index => {
+            if local.chain.len() as u64 == node_tip.index + 1 {
+                ChainRepair::InSync
+            } else {
+                // We hold blocks past the node's tip
+                ChainRepair::Rewind(entry.index + 1)
+            }
+        }
+        Some(entry) if local.chain.len() as u64 == entry.index + 1 => {
+            ChainRepair::Extend(entry.index + 1)
+        }
+        Some(entry) => ChainRepair::Rewind(entry.index + 1),
+        None => match (history.first(), local.chain.last()) {
+            // Behind the window; appending checks the blocks link up
+            (Some(first), Some(tip)) if tip.index < first.index => {
+                ChainRepair::Extend(tip.index + 1)
+            }
+            _ => ChainRepair::FullResync,
+        },
+    }
+}
+
+/// Bring the miner's chain copy in line with the node, fetching only the
+/// blocks past the last one both agree on.
+async fn sync_local_chain(
+    node_addr: &str,
+    blockchain: &Mutex<Blockchain>,
+) -> anyhow::Result<ChainRepair> {
+    let history = crate::ws_client::fetch_tip_history(node_addr, TIP_HISTORY_WINDOW).await?;
+    let mut plan = plan_chain_repair(&blockchain.lock(), &history);
+    let node_tip = history.last().map(|e| e.index).unwrap_or(0);
+    if let ChainRepair::Extend(from) | ChainRepair::Rewind(from) = plan {
+        let blocks = if from <= node_tip {
+            crate::ws_client::fetch_block_range(node_addr, from, node_tip).await?
+        } else {
+            Vec::new()
+        };
+        let mut bc = blockchain.lock();
+        bc.chain.truncate(from as usize);
+        let links = match (blocks.first(), bc.chain.last()) {
+            (Some(first), Some(last)) => first.prev_hash == last.hash,
+            _ => true,
+        };
+        if links {
+            bc.chain.extend(blocks);
+        } else {
+            plan = ChainRepair::FullResync;
+        }
+    }
+    if plan == ChainRepair::FullResync {
+        let fresh = crate::ws_client::fetch_chain(node_addr).await?;
+        *blockchain.lock() = fresh;
+    }
+    Ok(plan)
+}
+
+/// The chain to start on: the node's, or the local copy while the node is
+/// unreachable. An empty chain is only mined with `allow_genesis`: blocks on
+/// it would fork from genesis and never be accepted, so otherwise the node
+/// is retried until it has blocks, with `waiting` shown meanwhile. Returns
+/// whatever it has once `shutdown` is set.
+async fn initial_chain(
+    node_addr: &str,
+    allow_genesis: bool,
+    log: &MinerLog,
+    waiting: Option<(&mpsc::Sender<MinerStats>, &MinerStats)>,
+    shutdown: &std::sync::atomic::AtomicBool,
+) -> Blockchain {
+    let mut unreachable = crate::retry::Backoff::for_node();
+    // Reachable, but no blocks yet
+    let mut empty = crate::retry::Backoff::for_node();
+    let mut announced = false;
+    loop {
+        let (chain, fetched, delay) = match crate::ws_client::fetch_chain(node_addr).await {
+            Ok(bc) => {
+                if let Some(line) = unreachable.succeed_logged("node") {
+                    log.info(line);
+                }
+                (bc, true, empty.fail())
+            }
+            Err(e) => {
+                let (delay, line) = unreachable.fail_logged("node", &e);
+                if let Some(line) = line {
+                    log.warn(line);
+                }
+                let local = Blockchain::load_from_file("blockchain.json")
+                    .unwrap_or_else(|_| Blockchain::new());
+                (local, false, delay)
+            }
+        };
+        let height = chain.chain.last().map_or(0, |b| b.index);
+        if height > 0 || allow_genesis || shutdown.load(Ordering::Relaxed) {
+            if fetched {
+                log.info(format!(
+                    "Connected to node via WebSocket, chain height: {}",
+                    height
+                ));
+            } else {
+                log.warn(format!(
+                    "Node {} unreachable; mining on local chain at height {}",
+                    node_addr, height
+                ));
+            }
+            return chain;
+        }
+        if !announced {
+            log.warn(
+                "Waiting for chain sync: no blocks to build on yet. Pass \
+                 --allow-genesis-mining to start a new chain instead",
+            );
+            announced = true;
+        }
+        if let Some((tx, stats)) = waiting {
+            let _ = tx.try_send(stats.clone());
+        }
+        let until = tokio::time::Instant::now() + delay;
+        while tokio::time::Instant::now() < until && !shutdown.load(Ordering::Relaxed) {
+            sleep(Duration::from_millis(200)).await;
+        }
+    }
+}
+
+fn sum_attempts(counters: &[AtomicU64]) -> u64 {
+    counters.iter().map(|a| a.load(Ordering::Relaxed)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tip_changes_in_a_burst_restart_the_workers_once_more() {
+        let version = ChainVersion::new(Duration::from_millis(50));
+        assert_eq!(version.current(), 0);
+
+        // The first change restarts right away
+        version.bump();
+        assert_eq!(version.current(), 1);
+        // The rest of the burst waits out the window as a single restart
+        version.bump();
+        version.bump();
+        version.bump();
+        assert_eq!(version.current(), 1);
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(version.current(), 2);
+        assert_eq!(version.current(), 2);
+        assert_eq!(version.coalesced(), 2);
+
+        // Long after, a change restarts right away again
+        std::thread::sleep(Duration::from_millis(60));
+        version.bump();
+        assert_eq!(version.current(), 3);
+        assert_eq!(version.coalesced(), 2);
+    }
+
+    /// Feed the tuner a hashrate curve (indexed by thread count) until it
+    /// settles and return the count it settled on.
+    fn converge(tuner: &mut ThreadTuner, curve: &[f64]) -> usize {
+        for _ in 0..20 {
+            let rate = curve[tuner.current()];
+            tuner.observe(rate);
+            if matches!(tuner.phase, TunerPhase::Settled { .. }) {
+                break;
+            }
+        }
+        tuner.current()
+    }
+
+    fn block(hash: &str, prev_hash: &str) -> Block {
+        Block {
+            index: 0,
+            timestamp: chrono::Utc::now(),
+            transactions: vec![],
+            prev_hash: prev_hash.to_string(),
+            hash: hash.to_string(),
+            nonce: 0,
+            difficulty: 1,
+        }
+    }
+
+    #[test]
+    fn tx_filter_excludes_over_includes_and_drops_dependent_spends() {
+        let tx = |from: &str, to: &str| crate::blockchain::Transaction {
+            from: from.to_string(),
+            pub_key: String::new(),
+            to: to.to_string(),
+            amount: 1,
+            signature: format!("{}->{}", from, to),
+            expires_at_height: None,
+        };
+        let mempool = vec![
+            tx("04alice", "04bob"),
+            tx("04SPAM", "04carol"),
+            tx("04carol", "04alice"),
+            tx("04dave", "04erin"),
+            tx("04bob", "04spam"),
+        ];
+        let sigs = |txs: &[crate::blockchain::Transaction]| {
+            txs.iter().map(|t| t.signature.clone()).collect::<Vec<_>>()
+        };
+
+        let (kept, left_out) = TxFilter::default().apply(mempool.clone());
+        assert_eq!((kept.len(), left_out), (5, 0));
+
+        // carol's payment came from the excluded address, so hers waits too
+        let exclude = TxFilter::new(&[], &["04spam".to_string()]);
+        let (kept, left_out) = exclude.apply(mempool.clone());
+        assert_eq!(sigs(&kept), ["04alice->04bob", "04dave->04erin"]);
+        assert_eq!(left_out, 3);
+
+        // On both lists: excluded
+        let both = TxFilter::new(
+            &["04alice".to_string(), " 04Spam ".to_string()],
+            &["04spam".to_string()],
+        );
+        assert!(both.is_active());
+        let (kept, left_out) = both.apply(mempool);
+        assert_eq!(sigs(&kept), ["04alice->04bob"]);
+        assert_eq!(left_out, 4);
+    }
+
+    #[test]
+    fn only_the_newest_viable_block_is_submitted_after_a_backlog() {
+        let at = |index: u64| Block {
+            index,
+            ..block(&format!("h{}", index), &format!("h{}", index - 1))
+        };
+        let mut queue = SubmitQueue::new(8);
+        // Five blocks pile up while the node is unreachable
+        for index in [11, 12, 10, 14, 13] {
+            assert!(queue.push(at(index)).is_none());
+        }
+        // Meanwhile the tip moved to 13 (someone else's block)
+        let (next, dropped) = queue.next(Some(13));
+        assert_eq!(next.map(|b| b.index), Some(14));
+        let dropped: Vec<u64> = dropped.iter().map(|b| b.index).collect();
+        assert_eq!(dropped, [10, 11, 12, 13]);
+        assert!(queue.is_empty());
+
+        // The tip keeps moving: ours at 15 lost to another block 15
+        queue.push(at(15));
+        let (next, dropped) = queue.next(Some(15));
+        assert!(next.is_none());
+        assert_eq!(dropped.len(), 1);
+
+        // A full queue gives up its oldest block
+        let mut queue = SubmitQueue::new(2);
+        queue.push(at(20));
+        queue.push(at(22));
+        assert_eq!(queue.push(at(21)).map(|b| b.index), Some(20));
+        assert_eq!(queue.next(None).0.map(|b| b.index), Some(22));
+        assert_eq!(queue.next(None).0.map(|b| b.index), Some(21));
+    }
+
+    #[test]
+    fn mine_to_changes_only_who_the_coinbase_pays() {
+        let rig = crate::wallet::Wallet::new().unwrap();
+        let cold = crate::wallet::Wallet::new().unwrap().address;
+        let tx = coinbase_tx(&rig, &cold, 500);
+        assert_eq!((tx.to.as_str(), tx.amount), (cold.as_str(), 500));
+        // Still signed by, and traceable to, the rig
+        assert_eq!(tx.pub_key, rig.pub_key);
+        assert!(crate::blockchain::verify_transaction_signature(
+            &tx,
+            &rig.pub_key
+        ));
+
+        let stats = MinerStats {
+            wallet: rig.address.clone(),
+            payout: cold.clone(),
+            ..Default::default()
+        };
+        assert!(
+            stats
+                .summary()
+                .starts_with(&format!("rig {}…", &rig.address[..8])),
+            "{}",
+            stats.summary()
+        );
+        let solo = MinerStats {
+            payout: rig.address.clone(),
+            wallet: rig.address,
+            ..Default::default()
+        };
+        assert!(solo.summary().starts_with("0 H/s"));
+    }
+
+    #[test]
+    fn memory_plan_caps_threads_to_what_fits() {
+        const MIB: u64 = 1024 * 1024;
+        let scratchpad = 2 * MIB;
+
+        // Plenty of memory: every requested thread
+        let roomy = MemoryPlan::new(8, 1000, scratchpad, 8192 * MIB);
+        assert_eq!(roomy.threads, 8);
+        assert!(!roomy.is_capped());
+
+        // A 512 MiB VPS with a long chain: 409 MiB usable, ~37 MiB of it
+        // for runtime and a 1000-block chain, the rest in 2 MiB scratchpads
+        let small = MemoryPlan::new(512, 1000, scratchpad, 512 * MIB);
+        assert!(small.is_capped());
+        assert_eq!(
+            small.threads as u64,
+            (512 * MIB / 100 * 80 - small.base) / scratchpad
+        );
+        assert!(small.needed(small.threads) <= 512 * MIB / 100 * 80);
+
+        // A chain too big for the machine leaves no room for any thread
+        let hopeless = MemoryPlan::new(4, 200_000, scratchpad, 512 * MIB);
+        assert_eq!(hopeless.threads, 0);
+        assert!(hopeless.describe().starts_with("4 thread(s) need ~"));
+    }
+
+    #[test]
+    fn coinbase_split_adds_up_to_the_reward_exactly() {
+        let rig = crate::wallet::Wallet::new().unwrap();
+        let pool = crate::wallet::Wallet::new().unwrap().address;
+        let fund = crate::wallet::Wallet::new().unwrap().address;
+
+        // Shares round down; the miner gets what they leave
+        let split = CoinbaseSplit::new(&[(pool.clone(), 2.5), (fund.clone(), 33.3)]).unwrap();
+        let outputs = split.outputs(&rig.address, 1001);
+        assert_eq!(
+            outputs,
+            vec![
+                (rig.address.clone(), 643),
+                (pool.clone(), 25),
+                (fund.clone(), 333)
+            ]
+        );
+        for reward in [1, 7, 99, 1001, 500_000, 123_456_789] {
+            let total: i64 = split
+                .outputs(&rig.address, reward)
+                .iter()
+                .map(|o| o.1)
+                .sum();
+            assert_eq!(total, reward);
+        }
+        // Zero amounts are left out rather than mined into an invalid block
+        assert_eq!(
+            split.outputs(&rig.address, 3),
+            vec![(rig.address.clone(), 3)]
+        );
+        let all = CoinbaseSplit::new(&[(pool.clone(), 100.0)]).unwrap();
+        assert_eq!(all.outputs(&rig.address, 500), vec![(pool.clone(), 500)]);
+        assert_eq!(
+            CoinbaseSplit::default().outputs(&rig.address, 500),
+            vec![(rig.address.clone(), 500)]
+        );
+
+        // The split block is valid, and the miner's coinbase comes first
+        let bc = Blockchain::new();
+        let mut block = Block {
+            index: 1,
+            timestamp: chrono::Utc::now(),
+            transactions: coinbase_txs(&rig, &rig.address, 1001, &split),
+            prev_hash: bc.chain[0].hash.clone(),
+            hash: String::new(),
+            nonce: 0,
+            difficulty: 1,
+        };
+        block.hash = Blockchain::calculate_hash(&block);
+        assert_eq!(bc.validate_block_verbose(&block, 1, true), None);
+        assert_eq!(block.transactions[0].to, rig.address);
+
+        assert!(CoinbaseSplit::new(&[(pool.clone(), 60.0), (fund.clone(), 40.5)]).is_err());
+        assert!(CoinbaseSplit::new(&[(pool, 0.0)]).is_err());
+        assert!(CoinbaseSplit::new(&[("04zz".to_string(), 1.0)]).is_err());
     }
 
-    Ok(())
+    fn report(ended_at: chrono::DateTime<chrono::Utc>) -> SessionReport {
+        SessionReport {
+            started_at: ended_at - chrono::Duration::seconds(3725),
+            ended_at,
+            duration_secs: 3725,
+            node: "127.0.0.1:6969".to_string(),
+            wallet: "OWOrig".to_string(),
+            payout: "OWOrig".to_string(),
+            attempts: 372_500,
+            avg_hashrate: 100,
+            peak_hashrate: 140,
+            mined: 3,
+            accepted: 2,
+            rejected: 1,
+            stale: 0,
+            shares: 0,
+            earned: 50_000,
+            threads: 4,
+            auto_threads: false,
+            pool: false,
+            intensity: 100,
+            blocks_to_mine: 0,
+            saved_to: None,
+        }
+    }
+
+    #[test]
+    fn session_summary_covers_time_rate_and_blocks() {
+        let summary = report(chrono::Utc::now()).summary();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "Mined for 1h 2m 5s on 127.0.0.1:6969 with 4 thread(s)",
+                "Hashrate: 100 H/s average, 140 H/s peak, 372500 attempts",
+                "Blocks: 2 accepted, 1 rejected, 0 stale; earned 50.000 OWE",
+            ]
+        );
+    }
+
+    #[test]
+    fn session_reports_are_pruned_to_the_newest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("config.json"), "{}").unwrap();
+        let start = chrono::Utc::now();
+        let mut saved = Vec::new();
+        for minute in 0..4 {
+            let ended = start + chrono::Duration::minutes(minute);
+            saved.push(save_session_report(dir, &report(ended), 2).unwrap());
+        }
+        let mut left: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            [dir.join("config.json"), saved[2].clone(), saved[3].clone()]
+        );
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&saved[3]).unwrap()).unwrap();
+        assert_eq!(written["accepted"], 2);
+        assert!(written.get("saved_to").is_none());
+    }
+
+    #[test]
+    fn templates_build_on_pending_tip_one_block_deep() {
+        let tip = block("t", "s");
+        let ours = block("p", "t");
+        let deeper = block("c", "p");
+        let stale = block("x", "old");
+
+        assert_eq!(template_parent(&tip, None).hash, "t");
+        assert_eq!(template_parent(&tip, Some(&ours)).hash, "p");
+        // Never chains on top of an unconfirmed block's child
+        assert_eq!(template_parent(&tip, Some(&deeper)).hash, "t");
+        // Someone else's block replaced the tip we built on
+        assert_eq!(template_parent(&tip, Some(&stale)).hash, "t");
+        // Ours was accepted and is now the confirmed tip
+        assert_eq!(template_parent(&ours, Some(&ours)).hash, "p");
+    }
+
+    #[test]
+    fn tuner_backs_off_under_contention() {
+        // Index = thread count; 3 threads is the sweet spot
+        let curve = [0.0, 400.0, 700.0, 1000.0, 800.0];
+        let mut tuner = ThreadTuner::new(4);
+        assert_eq!(converge(&mut tuner, &curve), 3);
+    }
+
+    #[test]
+    fn tuner_keeps_max_when_more_threads_help() {
+        let curve = [0.0, 250.0, 500.0, 750.0, 1000.0];
+        let mut tuner = ThreadTuner::new(4);
+        assert_eq!(converge(&mut tuner, &curve), 4);
+    }
+
+    #[test]
+    fn tuner_respects_bounds_and_reprobes() {
+        let curve = [0.0, 1000.0, 500.0];
+        let mut tuner = ThreadTuner::new(2);
+        assert_eq!(converge(&mut tuner, &curve), 1);
+        // Never goes below 1
+        for _ in 0..TUNER_SETTLE_WINDOWS * 3 {
+            tuner.observe(curve[tuner.current()]);
+            assert!((1..=2).contains(&tuner.current()));
+        }
+
+        // Load went away: after settling it probes upwards again
+        let curve = [0.0, 500.0, 1000.0];
+        for _ in 0..TUNER_SETTLE_WINDOWS * 2 {
+            tuner.observe(curve[tuner.current()]);
+            if tuner.current() == 2 && matches!(tuner.phase, TunerPhase::Settled { .. }) {
+                break;
+            }
+        }
+        assert_eq!(tuner.current(), 2);
+        assert!(matches!(tuner.phase, TunerPhase::Settled { .. }));
+
+        let mut single = ThreadTuner::new(1);
+        for _ in 0..5 {
+            assert_eq!(single.observe(100.0), None);
+        }
+    }
+
+    #[test]
+    fn tuner_ignores_noise() {
+        let mut tuner = ThreadTuner::new(4);
+        let flat = [0.0, 1000.0, 1005.0, 1010.0, 1000.0];
+        assert_eq!(converge(&mut tuner, &flat), 4);
+    }
+
+    /// Genesis followed by `n` blocks whose hashes are tagged with `branch`.
+    fn chain_with(base: &Blockchain, keep: usize, n: usize, branch: &str) -> Blockchain {
+        let mut bc = base.clone();
+        bc.chain.truncate(keep);
+        for _ in 0..n {
+            let last = bc.chain.last().unwrap();
+            let mut next = block(&format!("{}{}", branch, last.index + 1), &last.hash);
+            next.index = last.index + 1;
+            bc.chain.push(next);
+        }
+        bc
+    }
+
+    fn hashes(bc: &Blockchain) -> Vec<String> {
+        bc.chain.iter().map(|b| b.hash.clone()).collect()
+    }
+
+    #[test]
+    fn chain_repair_plans() {
+        let a = chain_with(&Blockchain::new(), 1, 10, "a");
+        let history = |bc: &Blockchain| crate::daemon::tip_history(bc, 4);
+
+        assert_eq!(plan_chain_repair(&a, &history(&a)), ChainRepair::InSync);
+        let longer = chain_with(&a, 11, 2, "a");
+        assert_eq!(
+            plan_chain_repair(&a, &history(&longer)),
+            ChainRepair::Extend(11)
+        );
+        let fork = chain_with(&a, 9, 3, "b");
+        assert_eq!(
+            plan_chain_repair(&a, &history(&fork)),
+            ChainRepair::Rewind(9)
+        );
+        let shorter = chain_with(&a, 8, 0, "a");
+        assert_eq!(
+            plan_chain_repair(&a, &history(&shorter)),
+            ChainRepair::Rewind(8)
+        );
+        let deep_fork = chain_with(&a, 2, 12, "c");
+        assert_eq!(
+            plan_chain_repair(&a, &history(&deep_fork)),
+            ChainRepair::FullResync
+        );
+    }
+
+    #[tokio::test]
+    async fn genesis_only_chain_is_not_mined_without_the_flag() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        tokio::spawn(crate::daemon::run_daemon(
+            port,
+            Arc::new(Mutex::new(Blockchain::new())),
+            Arc::new(crate::daemon::PeerManager::new()),
+            true,
+            false,
+            None,
+            None,
+        ));
+        let node = format!("127.0.0.1:{}", port);
+        for _ in 0..50 {
+            if tokio::net::TcpStream::connect(&node).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let log = MinerLog::new(None);
+        let shutdown = std::sync::atomic::AtomicBool::new(false);
+        let (stats_tx, mut stats_rx) = mpsc::channel(4);
+        let waiting = MinerStats {
+            syncing: true,
+            ..Default::default()
+        };
+
+        // Refuses by default: keeps waiting and says so
+        let refused = tokio::time::timeout(
+            Duration::from_millis(500),
+            initial_chain(&node, false, &log, Some((&stats_tx, &waiting)), &shutdown),
+        )
+        .await;
+        assert!(refused.is_err());
+        assert!(stats_rx.try_recv().unwrap().syncing);
+        assert!(waiting.summary().starts_with("waiting for chain sync"));
+
+        // Gives up once asked to stop
+        shutdown.store(true, Ordering::Relaxed);
+        let stopped = initial_chain(&node, false, &log, None, &shutdown).await;
+        assert_eq!(stopped.chain.len(), 1);
+
+        let shutdown = std::sync::atomic::AtomicBool::new(false);
+        let allowed = tokio::time::timeout(
+            Duration::from_secs(5),
+            initial_chain(&node, true, &log, None, &shutdown),
+        )
+        .await
+        .expect("--allow-genesis-mining starts right away");
+        assert_eq!(allowed.chain.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn miner_resyncs_divergent_suffix_in_one_poll() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let a = chain_with(&Blockchain::new(), 1, 10, "a");
+        let node_chain = Arc::new(Mutex::new(a.clone()));
+        tokio::spawn(crate::daemon::run_daemon(
+            port,
+            node_chain.clone(),
+            Arc::new(crate::daemon::PeerManager::new()),
+            true,
+            false,
+            None,
+            None,
+        ));
+        let node = format!("127.0.0.1:{}", port);
+        for _ in 0..50 {
+            if tokio::net::TcpStream::connect(&node).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let local = Mutex::new(a.clone());
+        assert_eq!(
+            sync_local_chain(&node, &local).await.unwrap(),
+            ChainRepair::InSync
+        );
+
+        // The node reorganizes onto a branch forking after block 7
+        let b = chain_with(&a, 8, 4, "b");
+        *node_chain.lock() = b.clone();
+        assert_eq!(
+            sync_local_chain(&node, &local).await.unwrap(),
+            ChainRepair::Rewind(8)
+        );
+        assert_eq!(hashes(&local.lock()), hashes(&b));
+    }
+
+    #[tokio::test]
+    async fn no_background_task_outlives_the_session() {
+        // A node keeping its chain file out of the working directory
+        let mut ctx = crate::rpc::DaemonContext::new(
+            Arc::new(Mutex::new(Blockchain::new())),
+            Arc::new(crate::daemon::PeerManager::new()),
+            false,
+        );
+        let tmp = tempfile::tempdir().unwrap();
+        ctx.chain_path = tmp.path().join("blockchain.json");
+        let ctx = Arc::new(ctx);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let node = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(crate::daemon::handle_websocket_connection(
+                    socket,
+                    ctx.clone(),
+                    false,
+                ));
+            }
+        });
+        let opts = MinerOptions {
+            node_addr: node,
+            blocks_to_mine: 1,
+            threads: 1,
+            pool: false,
+            intensity: 100,
+            intensity_schedule: Default::default(),
+            pause_on_battery: false,
+            auto_threads: true,
+            power_watts: None,
+            tip_poll: Duration::from_millis(50),
+            mempool_poll: Duration::from_millis(50),
+            mine_to: None,
+            session_reports_keep: 0,
+            allow_genesis_mining: true,
+            tx_filter: TxFilter::default(),
+            coinbase_split: CoinbaseSplit::default(),
+            memory_plan: None,
+        };
+        let (stats_tx, mut stats_rx) = mpsc::channel(1);
+        let (log_tx, mut log_rx) = mpsc::channel(1024);
+        // Never signalled: the session ends by reaching its block count
+        let (_shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let tasks = BackgroundTasks::new();
+        let running = tasks.running.clone();
+
+        let report = tokio::time::timeout(
+            Duration::from_secs(30),
+            run_session(
+                opts,
+                crate::wallet::Wallet::new().unwrap(),
+                Some(stats_tx),
+                Some(log_tx),
+                Some(shutdown_rx),
+                tasks,
+            ),
+        )
+        .await
+        .expect("session ends after one block")
+        .unwrap();
+        assert!(report.mined >= 1);
+        // Found blocks were still submitted on the way out
+        assert!(report.accepted >= 1);
+
+        assert_eq!(running.load(Ordering::Relaxed), 0);
+        // Nothing is left holding the session's channels
+        while let Ok(line) = log_rx.try_recv() {
+            assert!(
+                !line.text.starts_with("Stopped without finishing"),
+                "{}",
+                line.text
+            );
+        }
+        assert!(log_rx.recv().await.is_none());
+        while stats_rx.try_recv().is_ok() {}
+        assert!(stats_rx.recv().await.is_none());
+    }
+
+    #[test]
+    fn worker_time_splits_into_hashing_and_template_overhead() {
+        let origin = std::time::Instant::now();
+        let busy = WorkerClock::default();
+        busy.hashing_nanos.store(1_000_000_000, Ordering::Relaxed);
+        busy.overhead_nanos.store(250_000_000, Ordering::Relaxed);
+        // A parked worker has no time and no attempts
+        let clocks = [busy, WorkerClock::default()];
+        let (hash_loop_hps, effective_hps) = worker_rates(&[1000, 0], &clocks, origin);
+        assert_eq!((hash_loop_hps, effective_hps), (1000, 800));
+        let stats = MinerStats {
+            hash_loop_hps,
+            effective_hps,
+            ..Default::default()
+        };
+        assert_eq!(stats.hashing_efficiency(), Some(80.0));
+        assert_eq!(MinerStats::default().hashing_efficiency(), None);
+
+        // Benchmark: the instrumentation runs once per template, which takes
+        // at least one hash, and must cost under 1% of that hash
+        let block = Blockchain::new().chain[0].clone();
+        let hash_cost = (0..3)
+            .map(|_| {
+                let started = std::time::Instant::now();
+                Blockchain::calculate_hash(&block);
+                started.elapsed()
+            })
+            .min()
+            .unwrap();
+        const PAIRS: u32 = 10_000;
+        let clock = WorkerClock::default();
+        let started = std::time::Instant::now();
+        for _ in 0..PAIRS {
+            clock.start_hashing(origin, started);
+            clock.stop_hashing(origin);
+        }
+        let pair_cost = started.elapsed() / PAIRS;
+        assert!(
+            pair_cost * 100 < hash_cost,
+            "start/stop pair {:?} vs one hash {:?}",
+            pair_cost,
+            hash_cost
+        );
+    }
 }
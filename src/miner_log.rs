@@ -0,0 +1,245 @@
+//! The miner's activity log. While the node flaps the miner repeats the
+//! same line many times a second, so identical consecutive lines are
+//! collapsed into one with a repeat count, and debug lines give way when
+//! the UI falls behind.
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Repeats of a line within this long of its first occurrence are counted
+/// instead of sent.
+pub const COLLAPSE_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Routine detail; dropped when the log channel is over half full.
+    Debug,
+    Info,
+    Warn,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLine {
+    pub level: LogLevel,
+    pub text: String,
+    /// Occurrences this line stands for.
+    pub repeats: u32,
+}
+
+impl LogLine {
+    pub fn new(level: LogLevel, text: impl Into<String>) -> Self {
+        Self {
+            level,
+            text: text.into(),
+            repeats: 1,
+        }
+    }
+}
+
+impl std::fmt::Display for LogLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.repeats {
+            1 => write!(f, "{}", self.text),
+            n => write!(f, "{} (×{})", self.text, n),
+        }
+    }
+}
+
+/// Collapses identical consecutive lines: the first is passed through and
+/// the rest of the burst is counted, then sent as one line once the window
+/// has passed or a different line comes along.
+#[derive(Debug)]
+pub struct LogThrottle {
+    window: Duration,
+    /// Last line passed through, when, and repeats counted since.
+    last: Option<(LogLine, Instant, u32)>,
+}
+
+impl LogThrottle {
+    pub fn new(window: Duration) -> Self {
+        Self { window, last: None }
+    }
+
+    /// Lines to send for `line` arriving at `now`, oldest first.
+    pub fn push(&mut self, line: LogLine, now: Instant) -> Vec<LogLine> {
+        if let Some((last, since, counted)) = &mut self.last
+            && last.text == line.text
+        {
+            if now.duration_since(*since) < self.window {
+                *counted += 1;
+                return Vec::new();
+            }
+            // Same line after the window: report the burst with this one
+            let line = LogLine {
+                repeats: *counted + 1,
+                ..line
+            };
+            *since = now;
+            *counted = 0;
+            return vec![line];
+        }
+        let mut out: Vec<LogLine> = self.flush().into_iter().collect();
+        self.last = Some((line.clone(), now, 0));
+        out.push(line);
+        out
+    }
+
+    /// The repeats counted so far, once the window has passed.
+    pub fn flush_idle(&mut self, now: Instant) -> Option<LogLine> {
+        match &self.last {
+            Some((_, since, counted))
+                if *counted > 0 && now.duration_since(*since) >= self.window =>
+            {
+                self.flush()
+            }
+            _ => None,
+        }
+    }
+
+    fn flush(&mut self) -> Option<LogLine> {
+        let (last, _, counted) = self.last.as_mut()?;
+        let repeats = std::mem::take(counted);
+        (repeats > 0).then(|| LogLine {
+            repeats,
+            ..last.clone()
+        })
+    }
+}
+
+/// Handle the miner's tasks log through. Without a UI channel, warnings
+/// go to stderr and everything else is dropped.
+#[derive(Clone)]
+pub struct MinerLog {
+    tx: Option<mpsc::Sender<LogLine>>,
+    throttle: Arc<Mutex<LogThrottle>>,
+}
+
+impl MinerLog {
+    /// Must be called inside the runtime: a task sends out the counts of
+    /// bursts nothing followed, and stops once every handle is dropped.
+    pub fn new(tx: Option<mpsc::Sender<LogLine>>) -> Self {
+        let log = Self {
+            tx,
+            throttle: Arc::new(Mutex::new(LogThrottle::new(COLLAPSE_WINDOW))),
+        };
+        if let Some(tx) = log.tx.clone() {
+            let throttle = Arc::downgrade(&log.throttle);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(COLLAPSE_WINDOW);
+                loop {
+                    interval.tick().await;
+                    let Some(throttle) = throttle.upgrade() else {
+                        break;
+                    };
+                    let line = throttle.lock().flush_idle(Instant::now());
+                    if let Some(line) = line {
+                        send(&tx, line);
+                    }
+                }
+            });
+        }
+        log
+    }
+
+    pub fn debug(&self, text: impl Into<String>) {
+        self.log(LogLine::new(LogLevel::Debug, text));
+    }
+
+    pub fn info(&self, text: impl Into<String>) {
+        self.log(LogLine::new(LogLevel::Info, text));
+    }
+
+    pub fn warn(&self, text: impl Into<String>) {
+        self.log(LogLine::new(LogLevel::Warn, text));
+    }
+
+    fn log(&self, line: LogLine) {
+        let lines = self.throttle.lock().push(line, Instant::now());
+        for line in lines {
+            match &self.tx {
+                Some(tx) => send(tx, line),
+                None if line.level == LogLevel::Warn => eprintln!("{}", line),
+                None => {}
+            }
+        }
+    }
+}
+
+/// Never waits: a full channel drops the line, and debug lines are dropped
+/// early to leave room for the rest.
+fn send(tx: &mpsc::Sender<LogLine>, line: LogLine) {
+    if line.level == LogLevel::Debug && tx.capacity() < tx.max_capacity() / 2 {
+        return;
+    }
+    let _ = tx.try_send(line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warn(text: &str) -> LogLine {
+        LogLine::new(LogLevel::Warn, text)
+    }
+
+    fn shown(lines: &[LogLine]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn bursts_of_one_line_collapse_into_a_count() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut throttle = LogThrottle::new(Duration::from_secs(2));
+        let mut sent = throttle.push(warn("node unreachable"), at(0));
+        for ms in 1..=36 {
+            sent.extend(throttle.push(warn("node unreachable"), at(ms * 10)));
+        }
+        assert_eq!(shown(&sent), ["node unreachable"]);
+        assert!(throttle.flush_idle(at(1000)).is_none());
+        let idle = throttle.flush_idle(at(2000)).unwrap();
+        assert_eq!(idle.to_string(), "node unreachable (×36)");
+        assert!(throttle.flush_idle(at(4000)).is_none());
+
+        // A repeat after the window carries the count of the burst before it
+        let mut throttle = LogThrottle::new(Duration::from_secs(2));
+        throttle.push(warn("a"), at(0));
+        throttle.push(warn("a"), at(100));
+        assert_eq!(shown(&throttle.push(warn("a"), at(2500))), ["a (×2)"]);
+        assert!(throttle.push(warn("a"), at(2600)).is_empty());
+    }
+
+    #[test]
+    fn interleaved_lines_are_not_collapsed() {
+        let start = Instant::now();
+        let mut throttle = LogThrottle::new(Duration::from_secs(2));
+        let mut sent = Vec::new();
+        for (i, text) in ["a", "b", "a", "b", "b", "b", "a"].iter().enumerate() {
+            sent.extend(throttle.push(warn(text), start + Duration::from_millis(i as u64)));
+        }
+        // A different line flushes the count of the one before it
+        assert_eq!(shown(&sent), ["a", "b", "a", "b", "b (×2)", "a"]);
+    }
+
+    #[tokio::test]
+    async fn debug_lines_give_way_when_the_channel_fills() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let log = MinerLog::new(Some(tx));
+        log.info("one");
+        log.debug("kept: channel a quarter full");
+        log.info("three");
+        log.debug("dropped: channel over half full");
+        log.info("four");
+        log.warn("dropped: channel full");
+        let mut got = Vec::new();
+        while let Ok(line) = rx.try_recv() {
+            got.push(line.text);
+        }
+        assert_eq!(
+            got,
+            ["one", "kept: channel a quarter full", "three", "four"]
+        );
+    }
+}
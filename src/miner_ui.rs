@@ -1,16 +1,17 @@
 use crate::miner::MinerStats;
+use crate::miner_log::{LogLevel, LogLine};
 use crossterm::{
     event::{self, KeyCode, KeyModifiers},
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
 use ratatui::{
+    Terminal,
     backend::{Backend, CrosstermBackend},
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
-    Terminal,
+    widgets::{Block, Borders, List, ListItem, Paragraph, Wrap},
 };
 use std::io;
 use std::time::Duration;
@@ -18,7 +19,7 @@ use tokio::sync::mpsc;
 
 pub struct MinerUI {
     stats: Option<MinerStats>,
-    logs: Vec<String>,
+    logs: Vec<LogLine>,
 }
 
 impl MinerUI {
@@ -32,21 +33,19 @@ impl MinerUI {
     pub async fn run(
         &mut self,
         mut stats_rx: mpsc::Receiver<MinerStats>,
-        mut log_rx: mpsc::Receiver<String>,
+        mut log_rx: mpsc::Receiver<LogLine>,
         shutdown_tx: Option<tokio::sync::watch::Sender<bool>>,
     ) -> anyhow::Result<()> {
         // Setup terminal
         enable_raw_mode()?;
         let mut stdout = io::stdout();
-        execute!(
-            stdout,
-            EnterAlternateScreen,
-            event::EnableMouseCapture
-        )?;
+        execute!(stdout, EnterAlternateScreen, event::EnableMouseCapture)?;
         let backend = CrosstermBackend::new(stdout);
         let mut terminal = Terminal::new(backend)?;
 
-        let res = self.ui_loop(&mut terminal, &mut stats_rx, &mut log_rx, shutdown_tx).await;
+        let res = self
+            .ui_loop(&mut terminal, &mut stats_rx, &mut log_rx, shutdown_tx)
+            .await;
 
         // Restore terminal
         disable_raw_mode()?;
@@ -64,30 +63,30 @@ impl MinerUI {
         &mut self,
         terminal: &mut Terminal<B>,
         stats_rx: &mut mpsc::Receiver<MinerStats>,
-        log_rx: &mut mpsc::Receiver<String>,
+        log_rx: &mut mpsc::Receiver<LogLine>,
         shutdown_tx: Option<tokio::sync::watch::Sender<bool>>,
     ) -> anyhow::Result<()> {
         let mut update_interval = tokio::time::interval(Duration::from_millis(100));
 
         loop {
             // Handle events (non-blocking)
-            if event::poll(Duration::from_millis(10))? {
-                if let event::Event::Key(key) = event::read()? {
-                    match key.code {
-                        KeyCode::Char('q') | KeyCode::Esc => {
-                            if let Some(tx) = &shutdown_tx {
-                                let _ = tx.send(true);
-                            }
-                            break;
+            if event::poll(Duration::from_millis(10))?
+                && let event::Event::Key(key) = event::read()?
+            {
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => {
+                        if let Some(tx) = &shutdown_tx {
+                            let _ = tx.send(true);
                         }
-                        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                            if let Some(tx) = &shutdown_tx {
-                                let _ = tx.send(true);
-                            }
-                            break;
+                        break;
+                    }
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        if let Some(tx) = &shutdown_tx {
+                            let _ = tx.send(true);
                         }
-                        _ => {}
+                        break;
                     }
+                    _ => {}
                 }
             }
 
@@ -98,10 +97,7 @@ impl MinerUI {
 
             // Update logs
             while let Ok(log) = log_rx.try_recv() {
-                self.logs.push(log);
-                if self.logs.len() > 50 {
-                    self.logs.remove(0);
-                }
+                push_log(&mut self.logs, log);
             }
 
             // Render UI
@@ -113,24 +109,74 @@ impl MinerUI {
     }
 }
 
-fn draw_ui(f: &mut ratatui::Frame, stats: &Option<MinerStats>, logs: &[String]) {
+/// Lines kept in the activity log.
+const LOG_LINES: usize = 50;
+
+/// Append `line`, or add its count to the last entry when it repeats it.
+fn push_log(logs: &mut Vec<LogLine>, line: LogLine) {
+    if let Some(last) = logs.last_mut()
+        && last.text == line.text
+    {
+        last.repeats += line.repeats;
+        return;
+    }
+    logs.push(line);
+    if logs.len() > LOG_LINES {
+        logs.remove(0);
+    }
+}
+
+fn draw_ui(f: &mut ratatui::Frame, stats: &Option<MinerStats>, logs: &[LogLine]) {
+    // Header
+    let mut header_lines = vec![Line::styled(
+        "⛏  OWONERO MINER  ⛏",
+        Style::default()
+            .fg(Color::Cyan)
+            .add_modifier(Modifier::BOLD),
+    )];
+    if stats.as_ref().is_some_and(|s| s.syncing) {
+        header_lines.push(Line::styled(
+            "waiting for chain sync — not hashing",
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    if stats.as_ref().is_some_and(|s| s.on_battery) {
+        header_lines.push(Line::styled(
+            "on battery — paused",
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    if let Some(intensity) = stats.as_ref().and_then(|s| s.intensity.as_ref()) {
+        header_lines.push(Line::styled(
+            intensity.as_str(),
+            Style::default().fg(Color::Gray),
+        ));
+    }
+
+    // Node footer: one line, two when the terminal is too narrow for it
+    let node_line = stats
+        .as_ref()
+        .and_then(|s| s.node.as_ref())
+        .map(|n| n.summary(chrono::Utc::now().timestamp()));
+    let width = f.area().width.saturating_sub(2).max(1) as usize;
+    let footer_height = node_line
+        .as_ref()
+        .map_or(0, |l| l.chars().count().div_ceil(width).min(2) as u16);
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(1)
         .constraints(
             [
-                Constraint::Length(3),
+                Constraint::Length(header_lines.len().max(3) as u16),
                 Constraint::Min(10),
                 Constraint::Min(10),
+                Constraint::Length(footer_height),
             ]
             .as_ref(),
         )
-        .split(f.size());
+        .split(f.area());
 
-    // Header
-    let header = Paragraph::new("⛏  OWONERO MINER  ⛏")
-        .style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
-        .alignment(Alignment::Center);
+    let header = Paragraph::new(header_lines).alignment(Alignment::Center);
     f.render_widget(header, chunks[0]);
 
     // Stats section
@@ -140,6 +186,15 @@ fn draw_ui(f: &mut ratatui::Frame, stats: &Option<MinerStats>, logs: &[String])
 
     // Logs section
     render_logs(f, chunks[2], logs);
+
+    if let Some(node) = &node_line {
+        f.render_widget(
+            Paragraph::new(node.as_str())
+                .style(Style::default().fg(Color::Gray))
+                .wrap(Wrap { trim: true }),
+            chunks[3],
+        );
+    }
 }
 
 fn render_stats(f: &mut ratatui::Frame, area: Rect, stats: &MinerStats) {
@@ -147,9 +202,9 @@ fn render_stats(f: &mut ratatui::Frame, area: Rect, stats: &MinerStats) {
         .direction(Direction::Vertical)
         .constraints(
             [
-                Constraint::Length(4),
-                Constraint::Length(4),
-                Constraint::Length(4),
+                Constraint::Length(6),
+                Constraint::Length(7),
+                Constraint::Length(5),
                 Constraint::Length(4),
             ]
             .as_ref(),
@@ -179,6 +234,39 @@ fn render_stats(f: &mut ratatui::Frame, area: Rect, stats: &MinerStats) {
                 Style::default().fg(Color::Yellow),
             ),
         ]),
+        Line::from(vec![
+            Span::raw("Hash loop: "),
+            Span::styled(
+                format!("{} H/s", format_number(stats.hash_loop_hps)),
+                Style::default().fg(Color::Yellow),
+            ),
+            Span::raw(", "),
+            match stats.hashing_efficiency() {
+                Some(pct) => Span::styled(
+                    format!("{:.1}% effective", pct),
+                    if pct >= 95.0 {
+                        Style::default().fg(Color::Green)
+                    } else if pct >= 80.0 {
+                        Style::default().fg(Color::Yellow)
+                    } else {
+                        Style::default().fg(Color::Red)
+                    },
+                ),
+                None => Span::raw("n/a"),
+            },
+        ]),
+        Line::from(vec![
+            Span::raw("Per thread: "),
+            Span::styled(
+                stats
+                    .thread_hps
+                    .iter()
+                    .map(|h| format_number(*h))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                Style::default().fg(Color::Yellow),
+            ),
+        ]),
     ];
     f.render_widget(Paragraph::new(perf_lines), perf_inner);
 
@@ -199,13 +287,22 @@ fn render_stats(f: &mut ratatui::Frame, area: Rect, stats: &MinerStats) {
 
     let sol_lines = vec![
         Line::from(vec![
-            Span::raw("Mined: "),
+            Span::raw("Blocks found: "),
             Span::styled(
                 stats.mined.to_string(),
                 Style::default()
                     .fg(Color::Green)
                     .add_modifier(Modifier::BOLD),
             ),
+            Span::raw(" this session / "),
+            Span::styled(
+                stats
+                    .lifetime_mined
+                    .map(|n| n.to_string())
+                    .unwrap_or_else(|| "n/a".to_string()),
+                Style::default().fg(Color::Green),
+            ),
+            Span::raw(" lifetime"),
         ]),
         Line::from(vec![
             Span::raw("Accept Rate: "),
@@ -220,6 +317,41 @@ fn render_stats(f: &mut ratatui::Frame, area: Rect, stats: &MinerStats) {
                 },
             ),
         ]),
+        Line::from(vec![
+            Span::raw("Luck: "),
+            Span::styled(
+                stats
+                    .luck
+                    .map(|l| format!("{:.0}%", l))
+                    .unwrap_or_else(|| "n/a".to_string()),
+                Style::default().fg(Color::Magenta),
+            ),
+        ]),
+        Line::from(vec![
+            Span::raw("Next block: "),
+            Span::styled(
+                stats
+                    .expected_secs_to_block
+                    .map(|s| format!("~{} (diff {})", format_uptime(s as u64), stats.difficulty))
+                    .unwrap_or_else(|| "n/a".to_string()),
+                Style::default().fg(Color::Magenta),
+            ),
+        ]),
+        Line::from(vec![
+            Span::raw("Per block: "),
+            Span::styled(
+                match stats.efficiency.hashes_per_block {
+                    Some(h) => format!(
+                        "{} H, {} kWh ({} kWh/OWE)",
+                        format_number(h as u64),
+                        crate::miner::format_kwh(stats.efficiency.kwh_per_block),
+                        crate::miner::format_kwh(stats.efficiency.kwh_per_owe),
+                    ),
+                    None => "n/a".to_string(),
+                },
+                Style::default().fg(Color::Magenta),
+            ),
+        ]),
     ];
     f.render_widget(Paragraph::new(sol_lines), sol_inner);
 
@@ -241,10 +373,11 @@ fn render_stats(f: &mut ratatui::Frame, area: Rect, stats: &MinerStats) {
         ]),
         Line::from(vec![
             Span::raw("Rejected: "),
-            Span::styled(
-                stats.rejected.to_string(),
-                Style::default().fg(Color::Red),
-            ),
+            Span::styled(stats.rejected.to_string(), Style::default().fg(Color::Red)),
+        ]),
+        Line::from(vec![
+            Span::raw("Stale: "),
+            Span::styled(stats.stale.to_string(), Style::default().fg(Color::Yellow)),
         ]),
     ];
     f.render_widget(Paragraph::new(shares_lines), shares_inner);
@@ -260,10 +393,7 @@ fn render_stats(f: &mut ratatui::Frame, area: Rect, stats: &MinerStats) {
     let session_lines = vec![
         Line::from(vec![
             Span::raw("Threads: "),
-            Span::styled(
-                stats.threads.to_string(),
-                Style::default().fg(Color::Cyan),
-            ),
+            Span::styled(stats.threads.to_string(), Style::default().fg(Color::Cyan)),
         ]),
         Line::from(vec![
             Span::raw("Uptime: "),
@@ -283,7 +413,7 @@ fn render_stats(f: &mut ratatui::Frame, area: Rect, stats: &MinerStats) {
     f.render_widget(Paragraph::new(session_lines), session_inner);
 }
 
-fn render_logs(f: &mut ratatui::Frame, area: Rect, logs: &[String]) {
+fn render_logs(f: &mut ratatui::Frame, area: Rect, logs: &[LogLine]) {
     let logs_block = Block::default()
         .title(" Activity Log ")
         .borders(Borders::ALL)
@@ -295,8 +425,11 @@ fn render_logs(f: &mut ratatui::Frame, area: Rect, logs: &[String]) {
         .iter()
         .rev()
         .take((inner.height as usize).saturating_sub(1))
-        .map(|log| {
-            let style = if log.contains("accepted") || log.contains("Accepted") {
+        .map(|line| {
+            let log = &line.text;
+            let style = if line.level == LogLevel::Debug {
+                Style::default().fg(Color::DarkGray)
+            } else if log.contains("accepted") || log.contains("Accepted") {
                 Style::default().fg(Color::Green)
             } else if log.contains("rejected") || log.contains("Rejected") {
                 Style::default().fg(Color::Red)
@@ -305,7 +438,7 @@ fn render_logs(f: &mut ratatui::Frame, area: Rect, logs: &[String]) {
             } else {
                 Style::default().fg(Color::White)
             };
-            ListItem::new(Line::from(Span::styled(log.clone(), style)))
+            ListItem::new(Line::from(Span::styled(line.to_string(), style)))
         })
         .collect();
 
@@ -322,7 +455,17 @@ fn format_number(n: u64) -> String {
     }
 }
 
-fn format_uptime(seconds: u64) -> String {
+/// Short duration for "… ago": the two largest units.
+pub(crate) fn format_age(seconds: u64) -> String {
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m {}s", seconds / 60, seconds % 60),
+        3600..86400 => format!("{}h {}m", seconds / 3600, (seconds % 3600) / 60),
+        _ => format!("{}d {}h", seconds / 86400, (seconds % 86400) / 3600),
+    }
+}
+
+pub(crate) fn format_uptime(seconds: u64) -> String {
     let hours = seconds / 3600;
     let minutes = (seconds % 3600) / 60;
     let secs = seconds % 60;
@@ -0,0 +1,344 @@
+//! Peer addresses on the wire. A host name such as the default node's can
+//! stand for several IPv4 and IPv6 addresses: peers are told apart by what
+//! they resolve to, connecting tries every address in the happy eyeballs
+//! order (RFC 8305), and the daemon listens on both families.
+
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How long a name's addresses are reused before looking it up again.
+pub const RESOLVE_TTL: Duration = Duration::from_secs(300);
+/// How long a failed lookup is remembered, so a dead name in the peer list
+/// doesn't send a query on every sync round.
+pub const RESOLVE_FAILURE_TTL: Duration = Duration::from_secs(30);
+/// Most names the resolver remembers. Expired answers are dropped on
+/// every insert; past this, the oldest go too.
+pub const RESOLVE_CACHE_MAX: usize = 1024;
+/// Head start each connection attempt gets before the next address is
+/// tried alongside it.
+pub const CONNECT_STAGGER: Duration = Duration::from_millis(250);
+
+type Lookup = Box<dyn Fn(&str) -> io::Result<Vec<SocketAddr>> + Send + Sync>;
+
+struct Cached {
+    at: Instant,
+    result: Result<Vec<SocketAddr>, String>,
+}
+
+impl Cached {
+    fn is_fresh(&self) -> bool {
+        let ttl = match self.result {
+            Ok(_) => RESOLVE_TTL,
+            Err(_) => RESOLVE_FAILURE_TTL,
+        };
+        self.at.elapsed() < ttl
+    }
+}
+
+/// Resolves `host:port` strings, remembering answers and failures.
+pub struct Resolver {
+    lookup: Lookup,
+    cache: Mutex<HashMap<String, Cached>>,
+}
+
+impl std::fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Resolver")
+            .field("cached", &self.cache.lock().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Resolver {
+    /// Ask the system resolver.
+    pub fn system() -> Self {
+        Self::with_lookup(|addr| Ok(addr.to_socket_addrs()?.collect()))
+    }
+
+    /// Resolve names with `lookup` instead of the system resolver.
+    pub fn with_lookup(
+        lookup: impl Fn(&str) -> io::Result<Vec<SocketAddr>> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            lookup: Box::new(lookup),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Addresses of `addr` (`host:port`), IPv4-mapped IPv6 ones as plain
+    /// IPv4. Literal addresses skip the lookup. May block on DNS when
+    /// `addr` isn't cached.
+    pub fn resolve(&self, addr: &str) -> io::Result<Vec<SocketAddr>> {
+        if let Some(known) = self.cached(addr) {
+            return known;
+        }
+        let result = match (self.lookup)(addr) {
+            Ok(addrs) if addrs.is_empty() => Err(format!("{} has no addresses", addr)),
+            Ok(addrs) => {
+                let mut unique = Vec::with_capacity(addrs.len());
+                for a in addrs.into_iter().map(canonical) {
+                    if !unique.contains(&a) {
+                        unique.push(a);
+                    }
+                }
+                Ok(unique)
+            }
+            Err(e) => Err(format!("failed to resolve {}: {}", addr, e)),
+        };
+        {
+            let mut cache = self.cache.lock();
+            cache.retain(|_, cached| cached.is_fresh());
+            if cache.len() >= RESOLVE_CACHE_MAX
+                && !cache.contains_key(addr)
+                && let Some(oldest) = cache
+                    .iter()
+                    .min_by_key(|(_, cached)| cached.at)
+                    .map(|(name, _)| name.clone())
+            {
+                cache.remove(&oldest);
+            }
+            cache.insert(
+                addr.to_string(),
+                Cached {
+                    at: Instant::now(),
+                    result: result.clone(),
+                },
+            );
+        }
+        result.map_err(io::Error::other)
+    }
+
+    /// What `resolve` answers for `addr` without a lookup: the address
+    /// itself if it is a literal, else a cached answer still fresh. `None`
+    /// when resolving it would block on DNS.
+    pub fn cached(&self, addr: &str) -> Option<io::Result<Vec<SocketAddr>>> {
+        if let Ok(literal) = addr.parse::<SocketAddr>() {
+            return Some(Ok(vec![canonical(literal)]));
+        }
+        let cache = self.cache.lock();
+        let cached = cache.get(addr)?;
+        cached
+            .is_fresh()
+            .then(|| cached.result.clone().map_err(io::Error::other))
+    }
+
+    /// `resolve` for async callers: a lookup runs on a blocking thread.
+    pub async fn resolve_async(self: &Arc<Self>, addr: &str) -> io::Result<Vec<SocketAddr>> {
+        if let Some(known) = self.cached(addr) {
+            return known;
+        }
+        let resolver = self.clone();
+        let name = addr.to_string();
+        tokio::task::spawn_blocking(move || resolver.resolve(&name))
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+/// The resolver peers and connections share.
+pub fn shared() -> Arc<Resolver> {
+    static SHARED: OnceLock<Arc<Resolver>> = OnceLock::new();
+    SHARED.get_or_init(|| Arc::new(Resolver::system())).clone()
+}
+
+/// `addr` with an IPv4-mapped IPv6 address as plain IPv4, as dual-stack
+/// sockets report IPv4 clients.
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// `addrs` in the order to try them: alternating families, starting with
+/// the family the resolver listed first, so a broken IPv6 route costs one
+/// stagger rather than every IPv6 address.
+pub fn happy_eyeballs_order(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().is_some_and(|a| a.is_ipv6());
+    let (mut preferred, mut other): (VecDeque<SocketAddr>, VecDeque<SocketAddr>) =
+        addrs.iter().partition(|a| a.is_ipv6() == first_v6);
+    let mut ordered = Vec::with_capacity(addrs.len());
+    while !preferred.is_empty() || !other.is_empty() {
+        ordered.extend(preferred.pop_front());
+        ordered.extend(other.pop_front());
+    }
+    ordered
+}
+
+/// Connect to the first of `addrs` that answers. Each attempt gets
+/// `stagger` before the next address is tried alongside it; a failed one
+/// moves on right away. The error is the last attempt's.
+pub async fn connect_any(addrs: &[SocketAddr], stagger: Duration) -> io::Result<TcpStream> {
+    use futures::stream::{FuturesUnordered, StreamExt};
+    let mut pending = addrs.iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+    loop {
+        match pending.next() {
+            Some(addr) => attempts.push(TcpStream::connect(*addr)),
+            None if attempts.is_empty() => {
+                return Err(last_error.unwrap_or_else(|| io::Error::other("no addresses to try")));
+            }
+            None => {}
+        }
+        // Until an attempt finishes, or it is time to start the next one
+        tokio::select! {
+            Some(result) = attempts.next() => match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            },
+            _ = tokio::time::sleep(stagger), if pending.len() > 0 => {}
+            else => {}
+        }
+    }
+}
+
+/// Connect to `addr` (`host:port`), trying each of its addresses.
+pub async fn connect(addr: &str) -> io::Result<TcpStream> {
+    let addrs = shared().resolve_async(addr).await?;
+    connect_any(&happy_eyeballs_order(&addrs), CONNECT_STAGGER).await
+}
+
+/// Listen on `port` on every interface, IPv6 and IPv4 alike. Where IPv6
+/// is unavailable, IPv4 only.
+pub async fn listen(port: u16) -> io::Result<TcpListener> {
+    match listen_dual_stack(port) {
+        Ok(listener) => Ok(listener),
+        Err(_) => TcpListener::bind(("0.0.0.0", port)).await,
+    }
+}
+
+fn listen_dual_stack(port: u16) -> io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_only_v6(false)?;
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn lookups_and_failures_are_cached_and_mapped_addresses_unwrapped() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let resolver = Resolver::with_lookup(move |name| {
+            counted.fetch_add(1, Ordering::Relaxed);
+            match name {
+                "seed.test:6969" => Ok(vec![
+                    addr("[::ffff:192.0.2.7]:6969"),
+                    addr("192.0.2.7:6969"),
+                    addr("[2001:db8::7]:6969"),
+                ]),
+                _ => Err(io::ErrorKind::NotFound.into()),
+            }
+        });
+
+        let expected = vec![addr("192.0.2.7:6969"), addr("[2001:db8::7]:6969")];
+        assert_eq!(resolver.resolve("seed.test:6969").unwrap(), expected);
+        assert_eq!(resolver.resolve("seed.test:6969").unwrap(), expected);
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        // A failure is remembered too, so the name isn't hammered
+        assert!(resolver.resolve("gone.test:6969").is_err());
+        assert!(resolver.resolve("gone.test:6969").is_err());
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        // Literals never reach the lookup
+        assert_eq!(
+            resolver.resolve("[::ffff:10.0.0.1]:80").unwrap(),
+            vec![addr("10.0.0.1:80")]
+        );
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn cache_drops_expired_answers_and_stays_bounded() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let resolver = Resolver::with_lookup(move |_| {
+            counted.fetch_add(1, Ordering::Relaxed);
+            Ok(vec![addr("192.0.2.1:6969")])
+        });
+        resolver.resolve("stale.test:6969").unwrap();
+        resolver.cache.lock().get_mut("stale.test:6969").unwrap().at =
+            Instant::now() - RESOLVE_TTL * 2;
+        resolver.resolve("fresh.test:6969").unwrap();
+        assert!(!resolver.cache.lock().contains_key("stale.test:6969"));
+
+        // Full: each new name pushes out the oldest
+        for i in 0..RESOLVE_CACHE_MAX + 10 {
+            resolver.resolve(&format!("n{}.test:6969", i)).unwrap();
+        }
+        assert_eq!(resolver.cache.lock().len(), RESOLVE_CACHE_MAX);
+        let before = calls.load(Ordering::Relaxed);
+        let newest = format!("n{}.test:6969", RESOLVE_CACHE_MAX + 9);
+        resolver.resolve(&newest).unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), before);
+        resolver.resolve("fresh.test:6969").unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), before + 1);
+    }
+
+    #[test]
+    fn families_alternate_starting_with_the_first_one_listed() {
+        let v6a = addr("[2001:db8::1]:1");
+        let v6b = addr("[2001:db8::2]:1");
+        let v6c = addr("[2001:db8::3]:1");
+        let v4a = addr("192.0.2.1:1");
+        let v4b = addr("192.0.2.2:1");
+        assert_eq!(
+            happy_eyeballs_order(&[v6a, v6b, v6c, v4a, v4b]),
+            [v6a, v4a, v6b, v4b, v6c]
+        );
+        assert_eq!(
+            happy_eyeballs_order(&[v4a, v6a, v6b, v4b]),
+            [v4a, v6a, v4b, v6b]
+        );
+        assert_eq!(happy_eyeballs_order(&[v4a, v4b]), [v4a, v4b]);
+        assert!(happy_eyeballs_order(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn connecting_falls_back_past_addresses_that_refuse() {
+        let live = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live_addr = live.local_addr().unwrap();
+        // A port that was just free refuses connections
+        let dead_addr = {
+            let probe = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            probe.local_addr().unwrap()
+        };
+
+        let stream = connect_any(&[dead_addr, live_addr], Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), live_addr);
+
+        let err = connect_any(&[dead_addr], CONNECT_STAGGER)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(connect_any(&[], CONNECT_STAGGER).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dual_stack_listener_takes_ipv4_clients() {
+        let listener = listen(0).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (_, from) = listener.accept().await.unwrap();
+        assert_eq!(canonical(from), canonical(client.local_addr().unwrap()));
+    }
+}
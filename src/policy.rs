@@ -0,0 +1,93 @@
+//! Operator policy, as opposed to consensus. A node may refuse to relay or
+//! pay addresses on its `blocked_addresses` list, but blocks holding their
+//! transactions stay valid: other miners' blocks are accepted as usual.
+
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Addresses this node's operator refuses to process: the inline
+/// `blocked_addresses` list plus one address per line of
+/// `blocked_addresses_file` (`#` starts a comment).
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    inline: Vec<String>,
+    file: Option<PathBuf>,
+    addresses: HashSet<String>,
+}
+
+fn key(address: &str) -> String {
+    address.trim().to_lowercase()
+}
+
+impl Blocklist {
+    /// Blocks nothing until `reload` reads the sources.
+    pub fn new(inline: Vec<String>, file: Option<PathBuf>) -> Self {
+        Self {
+            inline,
+            file,
+            addresses: HashSet::new(),
+        }
+    }
+
+    /// Read the file again. On error the current list stays in force.
+    /// Returns how many addresses are blocked now.
+    pub fn reload(&mut self) -> Result<usize> {
+        let mut addresses: HashSet<String> = self.inline.iter().map(|a| key(a)).collect();
+        if let Some(path) = &self.file {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            addresses.extend(
+                text.lines()
+                    .map(|l| l.split('#').next().unwrap_or("").trim())
+                    .filter(|l| !l.is_empty())
+                    .map(key),
+            );
+        }
+        addresses.remove("");
+        self.addresses = addresses;
+        Ok(self.addresses.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    pub fn is_blocked(&self, address: &str) -> bool {
+        self.addresses.contains(&key(address))
+    }
+
+    /// Why a transaction between `from` and `to` is refused, if it is.
+    pub fn refusal(&self, from: &str, to: &str) -> Option<String> {
+        [from, to]
+            .into_iter()
+            .find(|a| self.is_blocked(a))
+            .map(|a| format!("policy: address {} is blocked by this node", a))
+    }
+}
+
+/// Where the blocklist comes from, set from the config at startup.
+static CONFIGURED: Mutex<Option<(Vec<String>, Option<PathBuf>)>> = Mutex::new(None);
+
+pub fn set_blocklist_source(inline: Vec<String>, file: Option<PathBuf>) {
+    *CONFIGURED.lock() = Some((inline, file));
+}
+
+/// The configured blocklist, or an empty one. An unreadable file is
+/// reported and leaves only the inline addresses blocked.
+pub fn configured_blocklist() -> Blocklist {
+    let Some((inline, file)) = CONFIGURED.lock().clone() else {
+        return Blocklist::default();
+    };
+    let mut list = Blocklist::new(inline, file);
+    if let Err(e) = list.reload() {
+        eprintln!("Warning: blocked_addresses_file: {:#}", e);
+        list.addresses = list.inline.iter().map(|a| key(a)).collect();
+    }
+    list
+}
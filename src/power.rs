@@ -0,0 +1,258 @@
+//! Whether the machine runs on AC or battery, for `--pause-on-battery`:
+//! the RX/OWO scratchpad loop flattens a laptop battery in under an hour.
+//! Linux reads sysfs (`/sys/class/power_supply`), Windows asks
+//! `GetSystemPowerStatus`; elsewhere the state is unknown.
+
+use std::path::Path;
+use std::time::Duration;
+
+/// How often the power state is read.
+pub const POWER_POLL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerSource {
+    Ac,
+    Battery,
+    /// No way to tell, e.g. a desktop without power supply information.
+    Unknown,
+}
+
+/// One entry of `/sys/class/power_supply`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Supply {
+    /// `Mains`, `USB`, `Battery`, `UPS`, ...
+    kind: String,
+    /// `online` of adapters.
+    online: Option<bool>,
+    /// `status` of batteries: `Charging`, `Discharging`, `Full`, ...
+    status: Option<String>,
+}
+
+/// The power source those supplies add up to: any adapter online is AC;
+/// otherwise a discharging battery, or one behind an offline adapter, is
+/// battery.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn source_of(supplies: &[Supply]) -> PowerSource {
+    let adapters: Vec<&Supply> = supplies
+        .iter()
+        .filter(|s| s.kind == "Mains" || s.kind.starts_with("USB"))
+        .collect();
+    let batteries: Vec<&Supply> = supplies.iter().filter(|s| s.kind == "Battery").collect();
+    if adapters.iter().any(|s| s.online == Some(true)) {
+        PowerSource::Ac
+    } else if batteries
+        .iter()
+        .any(|s| s.status.as_deref() == Some("Discharging"))
+        || (!batteries.is_empty() && adapters.iter().any(|s| s.online == Some(false)))
+    {
+        PowerSource::Battery
+    } else if !batteries.is_empty() {
+        PowerSource::Ac
+    } else {
+        PowerSource::Unknown
+    }
+}
+
+/// Supplies listed under `dir`; unreadable entries are skipped.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn read_supplies(dir: &Path) -> Vec<Supply> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let read = |path: &Path, name: &str| {
+        std::fs::read_to_string(path.join(name))
+            .ok()
+            .map(|s| s.trim().to_string())
+    };
+    entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter_map(|path| {
+            Some(Supply {
+                kind: read(&path, "type")?,
+                online: read(&path, "online").map(|v| v == "1"),
+                status: read(&path, "status"),
+            })
+        })
+        .collect()
+}
+
+/// The machine's power source right now.
+#[cfg(target_os = "linux")]
+pub fn power_source() -> PowerSource {
+    source_of(&read_supplies(Path::new("/sys/class/power_supply")))
+}
+
+#[cfg(windows)]
+pub fn power_source() -> PowerSource {
+    #[repr(C)]
+    #[derive(Default)]
+    struct SystemPowerStatus {
+        ac_line_status: u8,
+        battery_flag: u8,
+        battery_life_percent: u8,
+        system_status_flag: u8,
+        battery_life_time: u32,
+        battery_full_life_time: u32,
+    }
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetSystemPowerStatus(status: *mut SystemPowerStatus) -> i32;
+    }
+    let mut status = SystemPowerStatus::default();
+    // SAFETY: `status` is a valid SYSTEM_POWER_STATUS for the call to fill
+    if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+        return PowerSource::Unknown;
+    }
+    match status.ac_line_status {
+        0 => PowerSource::Battery,
+        1 => PowerSource::Ac,
+        _ => PowerSource::Unknown,
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn power_source() -> PowerSource {
+    PowerSource::Unknown
+}
+
+/// What the miner should do after a power reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerChange {
+    Pause,
+    Resume,
+    /// The first reading told nothing: the option can't work here.
+    Unsupported,
+}
+
+/// Turns power readings into pauses and resumes.
+#[derive(Debug, Default)]
+pub struct BatteryPause {
+    paused: bool,
+    /// A reading has told AC from battery before.
+    known: bool,
+}
+
+impl BatteryPause {
+    /// Take in a reading. An unknown one after known ones keeps the current
+    /// state; one before any is `Unsupported`.
+    pub fn observe(&mut self, source: PowerSource) -> Option<PowerChange> {
+        match source {
+            PowerSource::Unknown if !self.known => Some(PowerChange::Unsupported),
+            PowerSource::Unknown => None,
+            PowerSource::Battery => {
+                self.known = true;
+                (!std::mem::replace(&mut self.paused, true)).then_some(PowerChange::Pause)
+            }
+            PowerSource::Ac => {
+                self.known = true;
+                std::mem::replace(&mut self.paused, false).then_some(PowerChange::Resume)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supply(kind: &str, online: Option<bool>, status: Option<&str>) -> Supply {
+        Supply {
+            kind: kind.to_string(),
+            online,
+            status: status.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn adapters_and_batteries_decide_the_source() {
+        let ac = supply("Mains", Some(true), None);
+        let unplugged = supply("Mains", Some(false), None);
+        let usb_c = supply("USB", Some(true), None);
+        let charging = supply("Battery", None, Some("Charging"));
+        let discharging = supply("Battery", None, Some("Discharging"));
+        let unknown = supply("Battery", None, Some("Unknown"));
+
+        assert_eq!(source_of(&[ac.clone(), charging.clone()]), PowerSource::Ac);
+        assert_eq!(
+            source_of(&[unplugged.clone(), discharging.clone()]),
+            PowerSource::Battery
+        );
+        // Some batteries never say "Discharging"
+        assert_eq!(
+            source_of(&[unplugged.clone(), unknown]),
+            PowerSource::Battery
+        );
+        // A plugged-in adapter wins over a battery that hasn't caught up
+        assert_eq!(source_of(&[ac, discharging.clone()]), PowerSource::Ac);
+        assert_eq!(
+            source_of(&[unplugged, usb_c, discharging.clone()]),
+            PowerSource::Ac
+        );
+        // Batteries alone
+        assert_eq!(source_of(&[discharging]), PowerSource::Battery);
+        assert_eq!(source_of(&[charging]), PowerSource::Ac);
+        // Desktops: nothing, or only a UPS
+        assert_eq!(source_of(&[]), PowerSource::Unknown);
+        assert_eq!(
+            source_of(&[supply("UPS", Some(true), None)]),
+            PowerSource::Unknown
+        );
+    }
+
+    #[test]
+    fn supplies_are_read_from_sysfs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for (name, files) in [
+            ("AC", vec![("type", "Mains\n"), ("online", "0\n")]),
+            (
+                "BAT0",
+                vec![("type", "Battery\n"), ("status", "Discharging\n")],
+            ),
+            ("broken", vec![]),
+        ] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+            for (file, content) in files {
+                std::fs::write(dir.join(name).join(file), content).unwrap();
+            }
+        }
+        let mut supplies = read_supplies(dir);
+        supplies.sort_by(|a, b| a.kind.cmp(&b.kind));
+        assert_eq!(
+            supplies,
+            [
+                supply("Battery", None, Some("Discharging")),
+                supply("Mains", Some(false), None),
+            ]
+        );
+        assert_eq!(source_of(&supplies), PowerSource::Battery);
+        assert!(read_supplies(&dir.join("missing")).is_empty());
+    }
+
+    #[test]
+    fn workers_pause_on_battery_and_resume_on_ac() {
+        let mut pause = BatteryPause::default();
+        assert_eq!(pause.observe(PowerSource::Ac), None);
+        assert_eq!(
+            pause.observe(PowerSource::Battery),
+            Some(PowerChange::Pause)
+        );
+        assert!(pause.paused);
+        assert_eq!(pause.observe(PowerSource::Battery), None);
+        // A reading that fails now and then changes nothing
+        assert_eq!(pause.observe(PowerSource::Unknown), None);
+        assert!(pause.paused);
+        assert_eq!(pause.observe(PowerSource::Ac), Some(PowerChange::Resume));
+        assert!(!pause.paused);
+        assert_eq!(pause.observe(PowerSource::Ac), None);
+
+        // Nothing to go by from the start
+        let mut desktop = BatteryPause::default();
+        assert_eq!(
+            desktop.observe(PowerSource::Unknown),
+            Some(PowerChange::Unsupported)
+        );
+        assert!(!desktop.paused);
+    }
+}
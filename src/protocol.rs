@@ -0,0 +1,81 @@
+//! Values the miner, daemon and wallet have to agree on. Timing constants
+//! that depend on each other live side by side here, with the relationships
+//! between them checked at compile time, so changing one end can't silently
+//! break the other.
+
+/// Version of the JSON command protocol spoken by daemons. Bump when a
+/// method is removed or its request/response shape changes incompatibly.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest protocol version a daemon still serves. Remote clients name
+/// theirs in `hello` before anything else.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Most blocks one `getblocks` call returns. Whole chains are fetched in
+/// slices this big rather than as one `getchain` message.
+pub const MAX_BLOCKS_PER_REQUEST: u64 = 500;
+/// Most headers one `getheaders` call returns. Headers carry no
+/// transactions, so far more of them fit in a message than blocks.
+pub const MAX_HEADERS_PER_REQUEST: u64 = 2000;
+
+/// The public network's seed node.
+pub const DEFAULT_NODE: &str = "owonero.yabai.buzz:6969";
+/// WebSocket port a daemon listens on.
+pub const DEFAULT_DAEMON_PORT: u16 = 6969;
+/// HTTP stats server port.
+pub const DEFAULT_WEB_PORT: u16 = 6767;
+
+/// Seconds between heartbeats on a `subscribe`d connection. Clients treat a
+/// few missed heartbeats as a dead connection.
+pub const HEARTBEAT_SECS: u64 = 15;
+
+/// Seconds a `getblocktemplate` result stays current.
+pub const TEMPLATE_TTL_SECS: i64 = 60;
+/// How often the miner polls the node's tip (config `tip_poll_ms`).
+pub const TIP_POLL_MS: u64 = 500;
+/// How often the miner refreshes its copy of the mempool (config
+/// `mempool_poll_ms`).
+pub const MEMPOOL_POLL_MS: u64 = 2000;
+/// How often the miner refreshes its lifetime mined-block count.
+pub const LIFETIME_MINED_POLL_SECS: u64 = 30;
+
+/// How often the miner refreshes the node overview in its TUI (`getinfo`).
+pub const NODE_INFO_POLL_SECS: u64 = 5;
+
+/// How often the miner reports hashrate statistics.
+pub const MINER_STATS_INTERVAL_SECS: u64 = 1;
+/// A miner's reported hashrate is dropped after this long without a report.
+pub const HASHRATE_STALE_SECS: u64 = 10;
+/// How often the daemon sweeps stale hashrates.
+pub const HASHRATE_SWEEP_SECS: u64 = 5;
+
+/// Default seconds between peer sync rounds (`OWONERO_SYNC_INTERVAL`).
+pub const PEER_SYNC_INTERVAL_SECS: u64 = 10;
+/// Peers are asked for their peer lists every this many sync rounds.
+pub const PEER_EXCHANGE_ROUNDS: u64 = 6;
+/// Seconds between health checks (`getheight` pings) of every peer.
+pub const PEER_HEALTH_INTERVAL_SECS: u64 = 60;
+/// Most blocks fetched from one peer in a sync round; a node further
+/// behind catches up over several rounds.
+pub const PEER_SYNC_BATCH_BLOCKS: u64 = 500;
+
+/// Largest WebSocket message or frame a daemon reads from a remote client
+/// (config `max_message_bytes`). Bigger ones get an error and a hang-up.
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+/// Largest message a client inflates from a compressed binary frame; the
+/// same as tungstenite's default limit on a text message, so compression
+/// doesn't let a node send more than it otherwise could.
+pub const MAX_INFLATED_MESSAGE_BYTES: usize = 64 * 1024 * 1024;
+/// Most transactions a submitted block may carry. No template comes near
+/// it; a block over it is refused before it is parsed.
+pub const MAX_SUBMITTED_BLOCK_TXS: usize = 10_000;
+/// Longest string a submitted block or transaction may hold in any field.
+/// The longest valid one, a signature in hex, is under 150 characters.
+pub const MAX_SUBMITTED_FIELD_LEN: usize = 1024;
+
+// A live miner must never look stale between two of its own reports.
+const _: () = assert!(HASHRATE_STALE_SECS > MINER_STATS_INTERVAL_SECS);
+// A stale entry is swept within one cutoff.
+const _: () = assert!(HASHRATE_SWEEP_SECS <= HASHRATE_STALE_SECS);
+// The miner must notice a new tip well before its template expires.
+const _: () = assert!(TIP_POLL_MS < TEMPLATE_TTL_SECS as u64 * 1000);
+const _: () = assert!(DEFAULT_DAEMON_PORT != DEFAULT_WEB_PORT);
@@ -0,0 +1,169 @@
+//! Receipts of sent transactions: `receipts/<txid>.json` in the config
+//! directory, written when a node accepts a `--send` or `--broadcast` and
+//! updated as its status becomes known, so a payment can be looked up long
+//! after the scrollback is gone. Local convenience data; no node ever sees
+//! them.
+
+use crate::blockchain::Transaction;
+use crate::daemon::TxStatus;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Receipt {
+    pub txid: String,
+    pub tx: Transaction,
+    /// The node that accepted it.
+    pub node: String,
+    pub submitted_at: DateTime<Utc>,
+    /// Last known status, e.g. `pending` or `confirmed at height 1042`.
+    pub status: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Receipt {
+    /// Receipt of `tx` as just accepted by `node`.
+    pub fn new(tx: &Transaction, node: &str) -> Self {
+        let now = Utc::now();
+        Self {
+            txid: tx.txid(),
+            tx: tx.clone(),
+            node: node.to_string(),
+            submitted_at: now,
+            status: describe(&TxStatus::Pending),
+            updated_at: now,
+        }
+    }
+}
+
+/// How a receipt words a node's answer about the transaction.
+pub fn describe(status: &TxStatus) -> String {
+    match status {
+        TxStatus::Confirmed { height } => format!("confirmed at height {}", height),
+        TxStatus::Pending => "pending".to_string(),
+        TxStatus::Rejected { reason, .. } => format!("rejected: {}", reason),
+        TxStatus::Unknown => "unknown to the node".to_string(),
+    }
+}
+
+fn path_of(dir: &Path, txid: &str) -> PathBuf {
+    dir.join(format!("{}.json", txid))
+}
+
+/// Write `receipt` into `dir`, replacing any earlier one for its txid.
+pub fn save(dir: &Path, receipt: &Receipt) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let path = path_of(dir, &receipt.txid);
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(receipt)?)
+        .with_context(|| format!("failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, &path).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// The receipt for `txid`, if this machine sent it.
+pub fn load(dir: &Path, txid: &str) -> Result<Option<Receipt>> {
+    let path = path_of(dir, txid);
+    match std::fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text)
+            .map(Some)
+            .with_context(|| format!("{} is not a receipt", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// Record `status` on the receipt for `txid`. `false` when there is no
+/// receipt for it; an unchanged status isn't written again.
+pub fn record_status(dir: &Path, txid: &str, status: &TxStatus) -> Result<bool> {
+    let Some(mut receipt) = load(dir, txid)? else {
+        return Ok(false);
+    };
+    let status = describe(status);
+    if receipt.status != status {
+        receipt.status = status;
+        receipt.updated_at = Utc::now();
+        save(dir, &receipt)?;
+    }
+    Ok(true)
+}
+
+/// Every receipt in `dir`, newest send first. Files that aren't receipts
+/// are skipped.
+pub fn list(dir: &Path) -> Result<Vec<Receipt>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    };
+    let mut receipts: Vec<Receipt> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|p| serde_json::from_str(&std::fs::read_to_string(p).ok()?).ok())
+        .collect();
+    receipts.sort_by(|a, b| {
+        b.submitted_at
+            .cmp(&a.submitted_at)
+            .then_with(|| a.txid.cmp(&b.txid))
+    });
+    Ok(receipts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment(amount: i64) -> Transaction {
+        let wallet = crate::wallet::Wallet::new().unwrap();
+        wallet.create_signed_transaction("04bob", amount).unwrap()
+    }
+
+    #[test]
+    fn receipts_are_written_and_follow_the_status_to_confirmation() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let tx = payment(1500);
+        assert!(load(dir, &tx.txid()).unwrap().is_none());
+
+        let receipt = Receipt::new(&tx, "node.example:6969");
+        let path = save(dir, &receipt).unwrap();
+        assert_eq!(path, dir.join(format!("{}.json", tx.txid())));
+        let saved = load(dir, &tx.txid()).unwrap().unwrap();
+        assert_eq!(saved.txid, tx.txid());
+        assert_eq!(saved.tx.signature, tx.signature);
+        assert_eq!(saved.node, "node.example:6969");
+        assert_eq!(saved.submitted_at, receipt.submitted_at);
+        assert_eq!(saved.status, "pending");
+
+        let confirmed = TxStatus::Confirmed { height: 1042 };
+        assert!(record_status(dir, &tx.txid(), &confirmed).unwrap());
+        let updated = load(dir, &tx.txid()).unwrap().unwrap();
+        assert_eq!(updated.status, "confirmed at height 1042");
+        assert_eq!(updated.submitted_at, receipt.submitted_at);
+        assert!(updated.updated_at >= receipt.updated_at);
+
+        // Transactions sent from elsewhere have no receipt to update
+        assert!(!record_status(dir, "ab12", &confirmed).unwrap());
+        assert!(!dir.join("ab12.json").exists());
+    }
+
+    #[test]
+    fn receipts_are_listed_newest_first() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        assert!(list(dir).unwrap().is_empty());
+
+        for (minutes_ago, amount) in [(30, 1), (5, 2), (60, 3)] {
+            let mut receipt = Receipt::new(&payment(amount), "node.example:6969");
+            receipt.submitted_at = Utc::now() - chrono::Duration::minutes(minutes_ago);
+            save(dir, &receipt).unwrap();
+        }
+        std::fs::write(dir.join("notes.txt"), "not a receipt").unwrap();
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+
+        let amounts: Vec<i64> = list(dir).unwrap().iter().map(|r| r.tx.amount).collect();
+        assert_eq!(amounts, [2, 1, 3]);
+    }
+}
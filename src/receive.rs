@@ -0,0 +1,254 @@
+//! Receiving payments: `owonero:<address>?amount=..&memo=..` payment URIs
+//! and terminal QR codes of them for `owonero wallet receive`.
+
+use anyhow::{Result, anyhow, bail};
+
+/// URI scheme of a payment request.
+pub const URI_SCHEME: &str = "owonero";
+
+/// What a payee asks for. `amount` is in internal units (milli-OWE); the
+/// memo is for the payer's eyes only, transactions don't carry it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentRequest {
+    pub address: String,
+    pub amount: Option<i64>,
+    pub memo: Option<String>,
+}
+
+impl PaymentRequest {
+    /// `owonero:<address>`, with `amount` and `memo` when set.
+    pub fn to_uri(&self) -> String {
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount {
+            params.push(format!("amount={}", format_amount(amount)));
+        }
+        if let Some(memo) = &self.memo {
+            params.push(format!("memo={}", percent_encode(memo)));
+        }
+        let mut uri = format!("{}:{}", URI_SCHEME, self.address);
+        if !params.is_empty() {
+            uri.push('?');
+            uri.push_str(&params.join("&"));
+        }
+        uri
+    }
+
+    /// Parse a payment URI. Unknown parameters are ignored so newer
+    /// wallets can add some; a repeated or malformed one is an error.
+    pub fn parse(uri: &str) -> Result<Self> {
+        let rest = uri
+            .trim()
+            .strip_prefix(URI_SCHEME)
+            .and_then(|r| r.strip_prefix(':'))
+            .ok_or_else(|| anyhow!("not an {}: URI", URI_SCHEME))?;
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        if address.is_empty() {
+            bail!("payment URI has no address");
+        }
+        let mut request = Self {
+            address: address.to_string(),
+            amount: None,
+            memo: None,
+        };
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "amount" => {
+                    if request.amount.is_some() {
+                        bail!("amount given twice");
+                    }
+                    request.amount = Some(parse_amount(value)?);
+                }
+                "memo" => {
+                    if request.memo.is_some() {
+                        bail!("memo given twice");
+                    }
+                    request.memo = Some(percent_decode(value)?);
+                }
+                _ => {}
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// Whether `to` is a payment URI rather than an address or book name.
+pub fn is_payment_uri(to: &str) -> bool {
+    to.trim()
+        .strip_prefix(URI_SCHEME)
+        .is_some_and(|r| r.starts_with(':'))
+}
+
+/// `1234` units as `1.234`, without trailing zeros.
+fn format_amount(units: i64) -> String {
+    let whole = units / 1000;
+    let frac = units % 1000;
+    if frac == 0 {
+        whole.to_string()
+    } else {
+        format!("{}.{:03}", whole, frac)
+            .trim_end_matches('0')
+            .to_string()
+    }
+}
+
+/// A positive OWE amount with at most three decimals, in units.
+fn parse_amount(value: &str) -> Result<i64> {
+    let invalid = || anyhow!("invalid amount {:?}", value);
+    let (whole, frac) = value.split_once('.').unwrap_or((value, ""));
+    if whole.is_empty()
+        || frac.len() > 3
+        || !whole
+            .chars()
+            .chain(frac.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    let whole: i64 = whole.parse().map_err(|_| invalid())?;
+    let frac: i64 = format!("{:0<3}", frac).parse().map_err(|_| invalid())?;
+    let units = whole
+        .checked_mul(1000)
+        .and_then(|w| w.checked_add(frac))
+        .ok_or_else(invalid)?;
+    if units <= 0 {
+        bail!("amount must be > 0");
+    }
+    Ok(units)
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> Result<String> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = text
+                    .get(i + 1..i + 3)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| anyhow!("bad %-escape in {:?}", text))?;
+                out.push(hex);
+                i += 3;
+            }
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).map_err(|_| anyhow!("memo is not UTF-8"))
+}
+
+/// `data` as a QR code drawn with half blocks, two modules per character
+/// row, light on dark so it scans on a dark terminal. `None` when it
+/// wouldn't fit in `cols` x `rows`.
+pub fn render_qr(data: &str, cols: usize, rows: usize) -> Option<String> {
+    use qrcode::render::unicode::Dense1x2;
+    let code = qrcode::QrCode::with_error_correction_level(data, qrcode::EcLevel::L).ok()?;
+    let image = code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build();
+    let width = image.lines().map(|l| l.chars().count()).max().unwrap_or(0);
+    (width <= cols && image.lines().count() <= rows).then_some(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "04a1b2c3";
+
+    fn request(amount: Option<i64>, memo: Option<&str>) -> PaymentRequest {
+        PaymentRequest {
+            address: ADDRESS.to_string(),
+            amount,
+            memo: memo.map(String::from),
+        }
+    }
+
+    #[test]
+    fn every_parameter_combination_round_trips() {
+        for (amount, memo, uri) in [
+            (None, None, "owonero:04a1b2c3"),
+            (Some(1500), None, "owonero:04a1b2c3?amount=1.5"),
+            (Some(2000), None, "owonero:04a1b2c3?amount=2"),
+            (Some(1), None, "owonero:04a1b2c3?amount=0.001"),
+            (None, Some("rent"), "owonero:04a1b2c3?memo=rent"),
+            (
+                Some(12345),
+                Some("coffee & cake"),
+                "owonero:04a1b2c3?amount=12.345&memo=coffee%20%26%20cake",
+            ),
+        ] {
+            let expected = request(amount, memo);
+            assert_eq!(expected.to_uri(), uri);
+            assert_eq!(PaymentRequest::parse(uri).unwrap(), expected, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn parameters_parse_in_any_order_and_unknown_ones_are_ignored() {
+        let parsed =
+            PaymentRequest::parse("owonero:04a1b2c3?memo=a+b%C3%A9&label=shop&amount=3").unwrap();
+        assert_eq!(parsed, request(Some(3000), Some("a bé")));
+        assert_eq!(
+            PaymentRequest::parse("owonero:04a1b2c3?").unwrap(),
+            request(None, None)
+        );
+    }
+
+    #[test]
+    fn malformed_uris_are_refused() {
+        for uri in [
+            "04a1b2c3",
+            "bitcoin:04a1b2c3",
+            "owonero:",
+            "owonero:?amount=1",
+            "owonero:04a1b2c3?amount=",
+            "owonero:04a1b2c3?amount=0",
+            "owonero:04a1b2c3?amount=-1",
+            "owonero:04a1b2c3?amount=1.2345",
+            "owonero:04a1b2c3?amount=1e3",
+            "owonero:04a1b2c3?amount=1&amount=2",
+            "owonero:04a1b2c3?memo=a&memo=b",
+            "owonero:04a1b2c3?memo=100%",
+            "owonero:04a1b2c3?memo=%FF",
+        ] {
+            assert!(PaymentRequest::parse(uri).is_err(), "{}", uri);
+        }
+        assert!(is_payment_uri(" owonero:04a1b2c3"));
+        assert!(!is_payment_uri("owonero-shop"));
+    }
+
+    #[test]
+    fn qr_code_fits_or_is_left_out() {
+        let uri = request(Some(1500), Some("invoice 7")).to_uri();
+        let qr = render_qr(&uri, 200, 200).unwrap();
+        let width = qr.lines().next().unwrap().chars().count();
+        let height = qr.lines().count();
+        // Square, two modules per row
+        assert_eq!(height, width.div_ceil(2));
+        assert!(qr.chars().all(|c| " ▀▄█\n".contains(c)));
+        assert!(render_qr(&uri, width, height).is_some());
+        assert!(render_qr(&uri, width - 1, height).is_none());
+        assert!(render_qr(&uri, width, height - 1).is_none());
+    }
+}
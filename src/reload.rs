@@ -0,0 +1,260 @@
+//! Applying an edited `config.json` to a running daemon, on SIGHUP or the
+//! `reloadconfig` method. Settings the daemon looks up on every use are
+//! swapped in place, so connected miners and wallets stay connected;
+//! the rest are reported as needing a restart.
+
+use crate::config::Config;
+use crate::policy::Blocklist;
+use crate::rpc::DaemonContext;
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+/// What a reload changed.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReloadReport {
+    /// Fields now in force.
+    pub applied: Vec<&'static str>,
+    /// Fields that differ from what the daemon runs with but only take
+    /// effect on restart.
+    pub restart_required: Vec<&'static str>,
+    /// Pending transactions a changed blocklist dropped.
+    pub dropped: usize,
+}
+
+impl std::fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.applied.is_empty() && self.restart_required.is_empty() {
+            return write!(f, "no changes");
+        }
+        if !self.applied.is_empty() {
+            write!(f, "applied {}", self.applied.join(", "))?;
+            if self.dropped > 0 {
+                write!(f, " ({} pending transaction(s) dropped)", self.dropped)?;
+            }
+        }
+        if !self.restart_required.is_empty() {
+            if !self.applied.is_empty() {
+                write!(f, "; ")?;
+            }
+            write!(f, "restart needed for {}", self.restart_required.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn note_change<T: PartialEq>(list: &mut Vec<&'static str>, name: &'static str, old: &T, new: &T) {
+    if old != new {
+        list.push(name);
+    }
+}
+
+/// Reread the daemon's config file and apply it.
+pub fn reload_from_file(ctx: &DaemonContext) -> Result<ReloadReport> {
+    let path = ctx
+        .config_path
+        .as_ref()
+        .context("this daemon has no config file")?;
+    let config = crate::config::load_config_from(path)
+        .with_context(|| format!("failed to load {}", path.display()))?;
+    apply(ctx, config)
+}
+
+/// Apply the live settings of `new` to the running daemon and report the
+/// rest. Anything that can fail is checked first, so an error applies
+/// nothing.
+pub fn apply(ctx: &DaemonContext, new: Config) -> Result<ReloadReport> {
+    // Held throughout, so two reloads don't interleave
+    let mut running = ctx.config.lock();
+    let mut report = ReloadReport::default();
+
+    let restart = &mut report.restart_required;
+    note_change(
+        restart,
+        "daemon_port",
+        &running.daemon_port,
+        &new.daemon_port,
+    );
+    note_change(restart, "web_port", &running.web_port, &new.web_port);
+    note_change(restart, "dev_mode", &running.dev_mode, &new.dev_mode);
+    note_change(restart, "pool", &running.pool, &new.pool);
+    note_change(
+        restart,
+        "strict_consensus",
+        &running.strict_consensus,
+        &new.strict_consensus,
+    );
+    note_change(
+        restart,
+        "mempool_journal",
+        &running.mempool_journal,
+        &new.mempool_journal,
+    );
+    note_change(
+        restart,
+        "snapshot_interval_secs",
+        &running.snapshot_interval_secs,
+        &new.snapshot_interval_secs,
+    );
+    note_change(
+        restart,
+        "scratchpad_pool_size",
+        &running.scratchpad_pool_size,
+        &new.scratchpad_pool_size,
+    );
+    note_change(
+        restart,
+        "peer_max_age_days",
+        &running.peer_max_age_days,
+        &new.peer_max_age_days,
+    );
+    note_change(
+        restart,
+        "web_path_prefix",
+        &running.web_path_prefix,
+        &new.web_path_prefix,
+    );
+    note_change(
+        restart,
+        "trust_proxy",
+        &running.trust_proxy,
+        &new.trust_proxy,
+    );
+    note_change(restart, "read_only", &running.read_only, &new.read_only);
+
+    let blocklist = if running.blocked_addresses != new.blocked_addresses
+        || running.blocked_addresses_file != new.blocked_addresses_file
+    {
+        let mut list = Blocklist::new(
+            new.blocked_addresses.clone(),
+            new.blocked_addresses_file.as_ref().map(PathBuf::from),
+        );
+        list.reload().context("blocked_addresses_file")?;
+        Some(list)
+    } else {
+        None
+    };
+
+    let applied = &mut report.applied;
+    if running.max_mempool_depth != new.max_mempool_depth {
+        crate::daemon::set_max_mempool_depth(new.max_mempool_depth);
+        running.max_mempool_depth = new.max_mempool_depth;
+        applied.push("max_mempool_depth");
+    }
+    if running.ws_compression != new.ws_compression {
+        crate::ws_client::set_request_compression(new.ws_compression);
+        running.ws_compression = new.ws_compression;
+        applied.push("ws_compression");
+    }
+    if running.max_message_bytes != new.max_message_bytes {
+        crate::daemon::set_max_message_bytes(new.max_message_bytes);
+        running.max_message_bytes = new.max_message_bytes;
+        applied.push("max_message_bytes");
+    }
+    if running.ban_threshold != new.ban_threshold
+        || running.ban_duration_secs != new.ban_duration_secs
+    {
+        ctx.bans
+            .lock()
+            .configure(new.ban_threshold, new.ban_duration_secs);
+        note_change(
+            applied,
+            "ban_threshold",
+            &running.ban_threshold,
+            &new.ban_threshold,
+        );
+        note_change(
+            applied,
+            "ban_duration_secs",
+            &running.ban_duration_secs,
+            &new.ban_duration_secs,
+        );
+        running.ban_threshold = new.ban_threshold;
+        running.ban_duration_secs = new.ban_duration_secs;
+    }
+    if running.max_connections != new.max_connections
+        || running.max_connections_per_ip != new.max_connections_per_ip
+    {
+        ctx.slots
+            .configure(new.max_connections, new.max_connections_per_ip);
+        note_change(
+            applied,
+            "max_connections",
+            &running.max_connections,
+            &new.max_connections,
+        );
+        note_change(
+            applied,
+            "max_connections_per_ip",
+            &running.max_connections_per_ip,
+            &new.max_connections_per_ip,
+        );
+        running.max_connections = new.max_connections;
+        running.max_connections_per_ip = new.max_connections_per_ip;
+    }
+    if running.rate_limit_per_sec != new.rate_limit_per_sec
+        || running.rate_limit_burst != new.rate_limit_burst
+    {
+        ctx.rate_limits
+            .configure(new.rate_limit_per_sec, new.rate_limit_burst);
+        note_change(
+            applied,
+            "rate_limit_per_sec",
+            &running.rate_limit_per_sec,
+            &new.rate_limit_per_sec,
+        );
+        note_change(
+            applied,
+            "rate_limit_burst",
+            &running.rate_limit_burst,
+            &new.rate_limit_burst,
+        );
+        running.rate_limit_per_sec = new.rate_limit_per_sec;
+        running.rate_limit_burst = new.rate_limit_burst;
+    }
+    if running.min_free_disk_mb != new.min_free_disk_mb {
+        ctx.disk.configure(new.min_free_disk_mb);
+        running.min_free_disk_mb = new.min_free_disk_mb;
+        applied.push("min_free_disk_mb");
+    }
+    if running.max_reorg_depth != new.max_reorg_depth {
+        ctx.reorgs.configure(new.max_reorg_depth);
+        running.max_reorg_depth = new.max_reorg_depth;
+        applied.push("max_reorg_depth");
+    }
+    // A standalone node has no peers to change
+    if !ctx.standalone {
+        if running.peers != new.peers {
+            for gone in running.peers.iter().filter(|p| !new.peers.contains(p)) {
+                ctx.peers.remove_peer(gone);
+            }
+            for peer in &new.peers {
+                ctx.peers.add_peer(peer.clone());
+            }
+            applied.push("peers");
+        }
+        if running.max_peers != new.max_peers {
+            applied.push("max_peers");
+        }
+        if running.peer_max_failures != new.peer_max_failures {
+            ctx.peers.set_max_failures(new.peer_max_failures);
+            applied.push("peer_max_failures");
+        }
+        // Configured peers always count; the limit is for learned ones
+        ctx.peers.set_max(new.peers.len() + new.max_peers);
+        running.peers = new.peers;
+        running.max_peers = new.max_peers;
+        running.peer_max_failures = new.peer_max_failures;
+    }
+    if let Some(list) = blocklist {
+        let mut blocked = ctx.blocklist.lock();
+        let mut mempool = ctx.mempool.lock();
+        let before = mempool.len();
+        mempool.retain(|tx| list.refusal(&tx.from, &tx.to).is_none());
+        report.dropped = before - mempool.len();
+        *blocked = list;
+        running.blocked_addresses = new.blocked_addresses;
+        running.blocked_addresses_file = new.blocked_addresses_file;
+        applied.push("blocked_addresses");
+    }
+    Ok(report)
+}
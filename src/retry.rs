@@ -0,0 +1,172 @@
+//! Exponential backoff for loops that talk to a node. A loop keeps one
+//! `Backoff` per remote end, so while the node is down it is tried at a
+//! falling rate and the log gets one line when it goes away and one when
+//! it comes back, not one per attempt.
+
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+/// First retry delay for node connections.
+pub const BASE_DELAY: Duration = Duration::from_secs(1);
+/// Longest wait between attempts.
+pub const MAX_DELAY: Duration = Duration::from_secs(30);
+
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max: max.max(base),
+            failures: 0,
+        }
+    }
+
+    /// Backoff for node connections: 1s doubling up to 30s.
+    pub fn for_node() -> Self {
+        Self::new(BASE_DELAY, MAX_DELAY)
+    }
+
+    /// Undelayed wait after `failures` consecutive failures: `base`
+    /// doubling each time, capped at `max`.
+    fn ceiling(&self, failures: u32) -> Duration {
+        let doublings = failures.saturating_sub(1).min(31);
+        self.base.saturating_mul(1 << doublings).min(self.max)
+    }
+
+    /// Record a failed attempt and return how long to wait before the next
+    /// one. Up to a quarter is taken off at random so clients that lost the
+    /// same node don't all come back in lockstep.
+    pub fn fail(&mut self) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        let ceiling = self.ceiling(self.failures);
+        ceiling - ceiling.mul_f64(rand::random::<f64>() / 4.0)
+    }
+
+    /// Record a success. Returns how many failures in a row it ends.
+    pub fn succeed(&mut self) -> u32 {
+        std::mem::take(&mut self.failures)
+    }
+
+    /// Like `fail`, plus a log line the first time in a row.
+    pub fn fail_logged(&mut self, what: &str, err: impl Display) -> (Duration, Option<String>) {
+        let first = self.failures == 0;
+        let delay = self.fail();
+        let line = first.then(|| {
+            format!(
+                "{} unreachable ({}), backing off up to {}s",
+                what,
+                err,
+                self.max.as_secs()
+            )
+        });
+        (delay, line)
+    }
+
+    /// Like `succeed`, plus a log line if it ends a run of failures.
+    pub fn succeed_logged(&mut self, what: &str) -> Option<String> {
+        match self.succeed() {
+            0 => None,
+            n => Some(format!(
+                "{} reachable again after {} failed attempt(s)",
+                what, n
+            )),
+        }
+    }
+}
+
+/// Run `op` until it succeeds, sleeping per `backoff` between failures and
+/// passing state changes to `log`.
+pub async fn until_ok<T, E, F, Fut>(
+    what: &str,
+    backoff: &mut Backoff,
+    mut op: F,
+    log: impl Fn(String),
+) -> T
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    loop {
+        match op().await {
+            Ok(value) => {
+                if let Some(line) = backoff.succeed_logged(what) {
+                    log(line);
+                }
+                return value;
+            }
+            Err(e) => {
+                let (delay, line) = backoff.fail_logged(what, e);
+                if let Some(line) = line {
+                    log(line);
+                }
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn delays_double_up_to_the_cap_with_bounded_jitter() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(8));
+        for ceiling in [1, 2, 4, 8, 8, 8] {
+            let ceiling = Duration::from_secs(ceiling);
+            let delay = backoff.fail();
+            assert!(delay <= ceiling && delay >= ceiling * 3 / 4, "{:?}", delay);
+        }
+        assert_eq!(backoff.succeed(), 6);
+        assert!(backoff.fail() <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn only_state_changes_are_logged() {
+        let mut backoff = Backoff::for_node();
+        assert!(backoff.fail_logged("node", "refused").1.is_some());
+        assert!(backoff.fail_logged("node", "refused").1.is_none());
+        assert_eq!(
+            backoff.succeed_logged("node").as_deref(),
+            Some("node reachable again after 2 failed attempt(s)")
+        );
+        assert!(backoff.succeed_logged("node").is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn downed_node_gets_a_bounded_number_of_attempts_per_minute() {
+        // Nothing listens on port 1. The connect is blocking so the paused
+        // clock can't skip ahead while it is in flight.
+        let attempts = AtomicUsize::new(0);
+        let lines = Mutex::new(Vec::new());
+        let mut backoff = Backoff::for_node();
+        let polled = tokio::time::timeout(
+            Duration::from_secs(60),
+            until_ok(
+                "node",
+                &mut backoff,
+                || async {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    std::net::TcpStream::connect("127.0.0.1:1").map(drop)
+                },
+                |line| lines.lock().unwrap().push(line),
+            ),
+        )
+        .await;
+        assert!(polled.is_err());
+        // 1, 2, 4, 8, 16, 30 seconds between attempts, up to a quarter less
+        // with jitter: six or seven attempts, against 120 at the tip poll rate
+        let attempts = attempts.load(Ordering::SeqCst);
+        assert!((6..=7).contains(&attempts), "{} attempts", attempts);
+        assert_eq!(lines.lock().unwrap().len(), 1);
+    }
+}
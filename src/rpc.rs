@@ -0,0 +1,2205 @@
+//! Daemon command dispatch. Every WebSocket method is a plain function in
+//! `METHODS`; `dispatch` looks it up, enforces its access level and wraps
+//! the result in the common response or error envelope.
+
+use crate::blockchain::{BlockHeader, Blockchain, Transaction};
+use crate::daemon::{
+    AdmissionEntry, AdmissionJournal, BalanceIndex, BanList, Capabilities, Connection,
+    ConnectionSlots, Connections, DaemonMetrics, EVENT_BUFFER, IssuedTemplate, Misbehavior,
+    NodeEvent, NodeIdentity, ORPHAN_TTL, OrphanBlocks, PeerChains, PeerManager, RateLimits,
+    SeenBlocks, SideBlock, SideBlocks, SubmitCounters, TemplateLog, TxRejectionLog, TxRelay,
+    WorkLog, accept_side_block, admit_transaction, awaits_parent, connect_orphans,
+    dev_append_block, is_duplicate_block, lookup_tx_status, mempool_balances_and_depths,
+    mined_blocks, network_id, prune_mempool_after_block, stale_status, tip_history,
+};
+use crate::protocol::{
+    MAX_BLOCKS_PER_REQUEST, MAX_HEADERS_PER_REQUEST, MAX_SUBMITTED_BLOCK_TXS,
+    MAX_SUBMITTED_FIELD_LEN, PROTOCOL_VERSION,
+};
+use crate::wallet_stats::WalletStats;
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use std::sync::Arc;
+
+/// Default and maximum number of entries returned by `gettiphistory`.
+const TIP_HISTORY_DEPTH: usize = 16;
+const TIP_HISTORY_MAX_DEPTH: usize = 256;
+
+/// Default and maximum page size for `getminedblocks`.
+const MINED_BLOCKS_PAGE: usize = 50;
+const MINED_BLOCKS_MAX_PAGE: usize = 500;
+
+/// Default number of entries returned by `getmempooljournal`.
+const MEMPOOL_JOURNAL_PAGE: usize = 50;
+
+/// State shared by every connection and command handler.
+pub struct DaemonContext {
+    pub blockchain: Arc<Mutex<Blockchain>>,
+    pub mempool: Arc<Mutex<Vec<Transaction>>>,
+    /// Reported hashrates and pool shares per wallet.
+    pub wallet_stats: Mutex<WalletStats>,
+    pub rejections: Arc<Mutex<TxRejectionLog>>,
+    pub journal: Mutex<AdmissionJournal>,
+    pub peers: Arc<PeerManager>,
+    /// Each peer's height and tip as of the last sync round.
+    pub peer_chains: Mutex<PeerChains>,
+    pub templates: Mutex<TemplateLog>,
+    pub work: Mutex<WorkLog>,
+    pub seen_blocks: Mutex<SeenBlocks>,
+    /// Blocks on branches that split off below our tip.
+    pub side_blocks: Mutex<SideBlocks>,
+    /// Blocks past our tip waiting for their parent.
+    pub orphan_blocks: Mutex<OrphanBlocks>,
+    /// Admitted transactions waiting to be sent to peers.
+    pub relay: Mutex<TxRelay>,
+    pub balance_index: Mutex<BalanceIndex>,
+    pub submissions: Mutex<SubmitCounters>,
+    pub metrics: Mutex<DaemonMetrics>,
+    /// Open WebSocket connections.
+    pub connections: Connections,
+    /// Inbound socket limits, checked before the handshake.
+    pub slots: Arc<ConnectionSlots>,
+    /// Per-connection request rate limits.
+    pub rate_limits: RateLimits,
+    /// `max_reorg_depth` and the last reorg.
+    pub reorgs: crate::daemon::Reorgs,
+    /// Where accepted blocks are persisted.
+    pub chain_path: std::path::PathBuf,
+    /// Free space for `chain_path`, and whether the node is read-only.
+    pub disk: crate::disk::DiskGuard,
+    /// New blocks and mempool entries, for `subscribe`d connections.
+    pub events: tokio::sync::broadcast::Sender<NodeEvent>,
+    pub dev_mode: bool,
+    /// Started with `--standalone`: no peers, own network id.
+    pub standalone: bool,
+    /// Config `read_only`: `SUBMISSION_METHODS` are refused.
+    pub read_only: bool,
+    /// Operator's `blocked_addresses`; not a consensus rule.
+    pub blocklist: Mutex<crate::policy::Blocklist>,
+    /// Misbehavior scores and banned IPs.
+    pub bans: Mutex<BanList>,
+    /// The settings the daemon runs with, updated by `reloadconfig`.
+    pub config: Mutex<crate::config::Config>,
+    /// The file `reloadconfig` rereads; `None` refuses reloads.
+    pub config_path: Option<std::path::PathBuf>,
+    /// Where `warn` lines go: stderr, unless a test swaps it out.
+    pub log: Box<dyn Fn(&str) + Send + Sync>,
+    /// A test node's directory for `chain_path` and its backups, removed
+    /// with the node.
+    #[cfg(test)]
+    pub scratch: Option<tempfile::TempDir>,
+}
+
+impl DaemonContext {
+    pub fn new(
+        blockchain: Arc<Mutex<Blockchain>>,
+        peers: Arc<PeerManager>,
+        dev_mode: bool,
+    ) -> Self {
+        Self {
+            blockchain,
+            mempool: Arc::new(Mutex::new(Vec::new())),
+            wallet_stats: Mutex::new(WalletStats::default()),
+            rejections: Arc::new(Mutex::new(TxRejectionLog::new())),
+            journal: Mutex::new(AdmissionJournal::new(None)),
+            peers,
+            peer_chains: Mutex::new(PeerChains::new()),
+            templates: Mutex::new(TemplateLog::new()),
+            work: Mutex::new(WorkLog::new()),
+            seen_blocks: Mutex::new(SeenBlocks::new()),
+            side_blocks: Mutex::new(SideBlocks::new()),
+            orphan_blocks: Mutex::new(OrphanBlocks::new()),
+            relay: Mutex::new(TxRelay::new()),
+            balance_index: Mutex::new(BalanceIndex::new()),
+            submissions: Mutex::new(SubmitCounters::default()),
+            metrics: Mutex::new(DaemonMetrics::new()),
+            connections: Connections::default(),
+            slots: Arc::new(ConnectionSlots::default()),
+            rate_limits: RateLimits::default(),
+            reorgs: crate::daemon::Reorgs::default(),
+            chain_path: "blockchain.json".into(),
+            disk: crate::disk::DiskGuard::default(),
+            events: tokio::sync::broadcast::channel(EVENT_BUFFER).0,
+            dev_mode,
+            standalone: false,
+            read_only: false,
+            blocklist: Mutex::new(crate::policy::Blocklist::default()),
+            bans: Mutex::new(BanList::default()),
+            config: Mutex::new(crate::config::Config::default()),
+            config_path: None,
+            log: Box::new(|line| eprintln!("{}", line)),
+            #[cfg(test)]
+            scratch: None,
+        }
+    }
+
+    pub fn network(&self) -> &'static str {
+        network_id(self.dev_mode, self.standalone)
+    }
+
+    /// Measure free space for the chain file, logging a switch into or
+    /// out of read-only mode. Whether the node is read-only now.
+    pub fn check_disk(&self) -> bool {
+        let dir = self
+            .chain_path
+            .parent()
+            .unwrap_or(std::path::Path::new("."));
+        if let Some(change) = self.disk.check(dir) {
+            (self.log)(&format!("[daemon] {}", change));
+        }
+        self.disk.is_read_only()
+    }
+
+    /// Write `bc` to the chain file, checking free space first. The blocks
+    /// are already in the chain, so a failure is counted and logged rather
+    /// than returned.
+    pub fn persist_chain(&self, bc: &Blockchain) {
+        self.check_disk();
+        if let Err(e) = bc.save_to_file(&self.chain_path) {
+            self.disk.record_persist_error(&e);
+            (self.log)(&format!(
+                "[daemon] FAILED to save the chain to {}: {:#}",
+                self.chain_path.display(),
+                e
+            ));
+        }
+    }
+
+    /// Log a problem with what `conn` sent, naming the connection.
+    pub fn warn(&self, conn: &Connection, message: impl std::fmt::Display) {
+        (self.log)(&format!("[daemon] {}: {}", conn, message));
+    }
+
+    /// Score `offense` against the IP `conn` comes from, banning it past
+    /// the threshold. Local admin connections are never banned.
+    pub fn penalize(&self, conn: &Connection, offense: Misbehavior) {
+        let Ok(addr) = conn.addr.parse::<std::net::SocketAddr>() else {
+            return;
+        };
+        if addr.ip().is_loopback() {
+            return;
+        }
+        let now = chrono::Utc::now().timestamp();
+        if let Some(ban) = self.bans.lock().record(addr.ip(), offense, now) {
+            self.warn(
+                conn,
+                format_args!(
+                    "banned {} for {}s after {}",
+                    ban.ip,
+                    ban.until - ban.banned_at,
+                    ban.reason
+                ),
+            );
+        }
+    }
+
+    /// Whether the IP `conn` comes from is banned now.
+    pub fn is_banned(&self, conn: &Connection) -> bool {
+        conn.addr.parse::<std::net::SocketAddr>().is_ok_and(|addr| {
+            self.bans
+                .lock()
+                .is_banned(addr.ip(), chrono::Utc::now().timestamp())
+        })
+    }
+
+    /// Tell subscribers about `event`. Nobody listening is fine.
+    pub fn publish(&self, event: NodeEvent) {
+        let _ = self.events.send(event);
+    }
+}
+
+/// Who may call a method.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Public,
+    /// Loopback connections only.
+    Admin,
+    /// Only on nodes running with `dev_mode`.
+    Dev,
+}
+
+impl Access {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Access::Public => "public",
+            Access::Admin => "admin",
+            Access::Dev => "dev",
+        }
+    }
+}
+
+/// One parameter or response field. `kind` is a JSON type, `Block` or
+/// `Transaction`, or `[kind]` for a list.
+pub struct Field {
+    pub name: &'static str,
+    pub kind: &'static str,
+    pub required: bool,
+    pub doc: &'static str,
+}
+
+impl Field {
+    const fn required(name: &'static str, kind: &'static str, doc: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            required: true,
+            doc,
+        }
+    }
+
+    const fn optional(name: &'static str, kind: &'static str, doc: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            required: false,
+            doc,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "type": self.kind,
+            "required": self.required,
+            "doc": self.doc,
+        })
+    }
+}
+
+/// What a method takes and returns, declared next to its handler and
+/// published by `getschema`. `example` is a `params` object the method
+/// accepts.
+pub struct Schema {
+    pub summary: &'static str,
+    pub params: &'static [Field],
+    pub response: &'static [Field],
+    pub example: &'static str,
+}
+
+impl Schema {
+    pub fn example_params(&self) -> Value {
+        serde_json::from_str(self.example).unwrap_or_else(|_| json!({}))
+    }
+}
+
+type Handler = fn(&DaemonContext, &Request) -> Reply;
+
+pub struct MethodSpec {
+    pub name: &'static str,
+    pub access: Access,
+    handler: Handler,
+    pub schema: Schema,
+}
+
+const fn method(
+    name: &'static str,
+    access: Access,
+    handler: Handler,
+    schema: Schema,
+) -> MethodSpec {
+    MethodSpec {
+        name,
+        access,
+        handler,
+        schema,
+    }
+}
+
+/// Every method the daemon serves, in the order `getcapabilities` lists them.
+pub const METHODS: &[MethodSpec] = &[
+    method(
+        "getcapabilities",
+        Access::Public,
+        get_capabilities,
+        GET_CAPABILITIES,
+    ),
+    method("getchain", Access::Public, get_chain, GET_CHAIN),
+    method("getlatest", Access::Public, get_latest, GET_LATEST),
+    method("getheight", Access::Public, get_height, GET_HEIGHT),
+    method("getblock", Access::Public, get_block, GET_BLOCK),
+    method("getmempool", Access::Public, get_mempool, GET_MEMPOOL),
+    method("submittx", Access::Public, submit_tx, SUBMIT_TX),
+    method("submitblock", Access::Public, submit_block, SUBMIT_BLOCK),
+    method("gettxstatus", Access::Public, get_tx_status, GET_TX_STATUS),
+    method("getpeers", Access::Public, get_peers, GET_PEERS),
+    method("addpeer", Access::Admin, add_peer, ADD_PEER),
+    method("getpeerinfo", Access::Public, get_peer_info, GET_PEER_INFO),
+    method(
+        "importmempool",
+        Access::Admin,
+        import_mempool,
+        IMPORT_MEMPOOL,
+    ),
+    method(
+        "getmempooljournal",
+        Access::Admin,
+        get_mempool_journal,
+        GET_MEMPOOL_JOURNAL,
+    ),
+    method(
+        "setcompression",
+        Access::Public,
+        set_compression,
+        SET_COMPRESSION,
+    ),
+    method(
+        "getminedblocks",
+        Access::Public,
+        get_mined_blocks,
+        GET_MINED_BLOCKS,
+    ),
+    method("getbalance", Access::Public, get_balance, GET_BALANCE),
+    method("getbalanceat", Access::Public, get_balance, GET_BALANCE_AT),
+    method(
+        "gettiphistory",
+        Access::Public,
+        get_tip_history,
+        GET_TIP_HISTORY,
+    ),
+    method(
+        "getblocktemplate",
+        Access::Public,
+        get_block_template,
+        GET_BLOCK_TEMPLATE,
+    ),
+    method("getwork", Access::Public, get_work, GET_WORK),
+    method("submitwork", Access::Public, submit_work, SUBMIT_WORK),
+    method(
+        "getsubmitstats",
+        Access::Public,
+        get_submit_stats,
+        GET_SUBMIT_STATS,
+    ),
+    method("updatestats", Access::Public, update_stats, UPDATE_STATS),
+    method("submitshare", Access::Public, submit_share, SUBMIT_SHARE),
+    method("getinfo", Access::Public, get_info, GET_INFO),
+    method("subscribe", Access::Public, subscribe, SUBSCRIBE),
+    method("hello", Access::Public, hello, HELLO),
+    method(
+        "getconnections",
+        Access::Admin,
+        get_connections,
+        GET_CONNECTIONS,
+    ),
+    method(
+        "reloadblocklist",
+        Access::Admin,
+        reload_blocklist,
+        RELOAD_BLOCKLIST,
+    ),
+    method("reloadconfig", Access::Admin, reload_config, RELOAD_CONFIG),
+    method("getbans", Access::Admin, get_bans, GET_BANS),
+    method("getschema", Access::Public, get_schema, GET_SCHEMA),
+    method("getsupply", Access::Public, get_supply, GET_SUPPLY),
+    method("getburned", Access::Public, get_burned, GET_BURNED),
+    method("handshake", Access::Public, handshake, HANDSHAKE),
+    method("getblocks", Access::Public, get_blocks, GET_BLOCKS),
+    method("getheaders", Access::Public, get_headers, GET_HEADERS),
+    method("devmineblocks", Access::Dev, dev_mine, DEV_MINE_BLOCKS),
+    method("devfaucet", Access::Dev, dev_mine, DEV_FAUCET),
+];
+
+/// Methods that hand the node blocks, transactions or miner stats; a
+/// `read_only` node refuses them.
+pub const SUBMISSION_METHODS: &[&str] = &[
+    "submittx",
+    "submitblock",
+    "submitwork",
+    "submitshare",
+    "updatestats",
+];
+
+/// What a `read_only` node answers them.
+pub const READ_ONLY: &str = "rejected: node is read-only";
+
+/// Names of the methods with the given access level.
+pub fn method_names(access: Access) -> impl Iterator<Item = &'static str> {
+    METHODS
+        .iter()
+        .filter(move |m| m.access == access)
+        .map(|m| m.name)
+}
+
+const BLOCK_FIELDS: &[Field] = &[
+    Field::required("index", "integer", "height"),
+    Field::required("timestamp", "string", "RFC 3339"),
+    Field::required("transactions", "[Transaction]", "the coinbase first"),
+    Field::required("prev_hash", "string", "hex"),
+    Field::required("hash", "string", "hex"),
+    Field::required("nonce", "integer", ""),
+    Field::required("difficulty", "integer", "leading zero hex digits required"),
+];
+
+const TRANSACTION_FIELDS: &[Field] = &[
+    Field::required("from", "string", "address, or \"coinbase\""),
+    Field::required("pub_key", "string", "hex; empty for coinbase"),
+    Field::required("to", "string", "address"),
+    Field::required("amount", "integer", ""),
+    Field::required("signature", "string", "hex; empty for coinbase"),
+    Field::optional(
+        "expires_at_height",
+        "integer",
+        "dropped from the mempool once the chain passes this height",
+    ),
+];
+
+fn field_list(fields: &[Field]) -> Value {
+    fields.iter().map(Field::to_json).collect()
+}
+
+/// The protocol as data, built from `METHODS`: what `getschema` returns
+/// and `owonero protocol-schema` prints.
+pub fn protocol_schema() -> Value {
+    let methods: Vec<Value> = METHODS
+        .iter()
+        .map(|m| {
+            json!({
+                "name": m.name,
+                "access": m.access.as_str(),
+                "summary": m.schema.summary,
+                "params": field_list(m.schema.params),
+                "response": field_list(m.schema.response),
+                "example": {"method": m.name, "params": m.schema.example_params()},
+            })
+        })
+        .collect();
+    json!({
+        "protocol_version": PROTOCOL_VERSION,
+        "envelope": {
+            "request": "{\"method\": name, \"params\": {...}, \"id\"?: any, \"trace\"?: string}",
+            "response": "{\"type\": \"response\", \"method\": name, \"id\"?: any, \"trace\"?: string, ...response fields}",
+            "error": "{\"type\": \"error\", \"method\"?: name, \"id\"?: any, \"trace\"?: string, \"message\": string}",
+        },
+        "types": {
+            "Block": field_list(BLOCK_FIELDS),
+            "Transaction": field_list(TRANSACTION_FIELDS),
+        },
+        "methods": methods,
+    })
+}
+
+/// A parsed command: `{"method": ..., "params": {...}, "id": ...}`.
+pub struct Request<'a> {
+    pub method: &'a str,
+    /// The connection that sent it.
+    pub conn: &'a Connection,
+    json: &'a Value,
+}
+
+impl Request<'_> {
+    /// A parameter from `params`, or from the top level where older clients
+    /// put it.
+    pub fn param(&self, name: &str) -> Option<&Value> {
+        self.json
+            .get("params")
+            .and_then(|p| p.get(name))
+            .or_else(|| self.json.get(name))
+    }
+
+    fn str_param(&self, name: &str) -> Option<&str> {
+        self.param(name).and_then(|v| v.as_str())
+    }
+
+    fn u64_param(&self, name: &str) -> Option<u64> {
+        self.param(name).and_then(|v| v.as_u64())
+    }
+}
+
+/// Error half of a handler's result. `extra` fields are merged into the
+/// error envelope next to `message`.
+#[derive(Debug)]
+pub struct RpcError {
+    message: String,
+    extra: Map<String, Value>,
+}
+
+impl RpcError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            extra: Map::new(),
+        }
+    }
+
+    pub fn with(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.extra.insert(key.to_string(), value.into());
+        self
+    }
+}
+
+impl From<&str> for RpcError {
+    fn from(message: &str) -> Self {
+        Self::new(message)
+    }
+}
+
+impl From<String> for RpcError {
+    fn from(message: String) -> Self {
+        Self::new(message)
+    }
+}
+
+/// Fields of a successful response, without `type` and `method`.
+pub type Reply = Result<Map<String, Value>, RpcError>;
+
+/// Build a reply from `key: value` pairs.
+fn fields<const N: usize>(pairs: [(&str, Value); N]) -> Reply {
+    Ok(pairs.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+/// `{"data": value}`, or "failed to serialize <what>".
+fn data(value: &impl Serialize, what: &str) -> Reply {
+    match serde_json::to_value(value) {
+        Ok(v) => fields([("data", v)]),
+        Err(_) => Err(format!("failed to serialize {}", what).into()),
+    }
+}
+
+/// Wrap a reply in the envelope every response shares: `type`, `method`
+/// (when known) and the request's `id` when it sent one. `dispatch` echoes
+/// `trace` the same way.
+pub fn envelope(method: Option<&str>, id: Option<&Value>, reply: Reply) -> String {
+    let mut body = match reply {
+        Ok(mut body) => {
+            body.insert("type".to_string(), "response".into());
+            body
+        }
+        Err(err) => {
+            let mut body = err.extra;
+            body.insert("type".to_string(), "error".into());
+            body.insert("message".to_string(), err.message.into());
+            body
+        }
+    };
+    if let Some(method) = method {
+        body.insert("method".to_string(), method.into());
+    }
+    if let Some(id) = id {
+        body.insert("id".to_string(), id.clone());
+    }
+    Value::Object(body).to_string()
+}
+
+/// Run one command and return the serialized response. Synchronous on
+/// purpose: every lock taken here is released before the caller awaits on
+/// the socket, so a slow client only ever stalls itself.
+pub fn dispatch(ctx: &DaemonContext, text: &str, admin: bool, conn: &Connection) -> String {
+    conn.count_request();
+    let Ok(json) = serde_json::from_str::<Value>(text) else {
+        ctx.warn(conn, "sent invalid JSON");
+        ctx.penalize(conn, Misbehavior::InvalidJson);
+        return envelope(None, None, Err("invalid JSON".into()));
+    };
+    let name = json
+        .get("method")
+        .and_then(|m| m.as_str())
+        .unwrap_or("unknown");
+    let trace = json.get("trace").and_then(|t| t.as_str());
+    conn.set_trace(trace);
+    let request = Request {
+        method: name,
+        conn,
+        json: &json,
+    };
+    let reply = match METHODS.iter().find(|m| m.name == name) {
+        Some(m) if m.access == Access::Admin && !admin => {
+            ctx.warn(conn, format_args!("refused admin method {}", name));
+            Err(format!("{} is restricted to local admin connections", name).into())
+        }
+        Some(m) if m.access == Access::Dev && !ctx.dev_mode => {
+            ctx.warn(conn, format_args!("refused dev method {}", name));
+            Err(format!("{} refused: dev_mode is off on this node", name).into())
+        }
+        Some(m) if ctx.read_only && SUBMISSION_METHODS.contains(&m.name) => Err(READ_ONLY.into()),
+        Some(m) => {
+            let started = std::time::Instant::now();
+            let reply = (m.handler)(ctx, &request);
+            ctx.metrics.lock().record(m.name, started.elapsed());
+            reply
+        }
+        None => Err(RpcError::new("unknown method")
+            .with("protocol_version", PROTOCOL_VERSION)
+            .with(
+                "hint",
+                "call getcapabilities for the list of supported methods",
+            )),
+    };
+    let reply = match (trace, reply) {
+        (None, reply) => reply,
+        // Traced callers grep our log for the id, so failures go there too
+        (Some(trace), Ok(mut body)) => {
+            body.insert("trace".to_string(), trace.into());
+            Ok(body)
+        }
+        (Some(trace), Err(err)) => {
+            ctx.warn(conn, format_args!("{} failed: {}", name, err.message));
+            Err(err.with("trace", trace))
+        }
+    };
+    conn.set_trace(None);
+    envelope(Some(name), json.get("id"), reply)
+}
+
+const GET_CAPABILITIES: Schema = Schema {
+    summary: "Protocol version and the methods this node serves",
+    params: &[],
+    response: &[Field::required(
+        "data",
+        "object",
+        "`protocol_version` and `methods`",
+    )],
+    example: r#"{}"#,
+};
+
+fn get_capabilities(ctx: &DaemonContext, _: &Request) -> Reply {
+    data(&Capabilities::for_node(ctx.dev_mode), "capabilities")
+}
+
+const GET_CHAIN: Schema = Schema {
+    summary: "The whole chain",
+    params: &[],
+    response: &[Field::required(
+        "data",
+        "object",
+        "`chain`: every Block from genesis, and `target_block_time`",
+    )],
+    example: r#"{}"#,
+};
+
+fn get_chain(ctx: &DaemonContext, _: &Request) -> Reply {
+    // Serializing the whole chain is slow; don't hold the lock for it
+    let bc = ctx.blockchain.lock().clone();
+    data(&bc, "chain")
+}
+
+const GET_LATEST: Schema = Schema {
+    summary: "The tip block",
+    params: &[],
+    response: &[Field::required("data", "Block", "")],
+    example: r#"{}"#,
+};
+
+fn get_latest(ctx: &DaemonContext, _: &Request) -> Reply {
+    let bc = ctx.blockchain.lock();
+    data(&bc.chain.last(), "block")
+}
+
+const GET_HEIGHT: Schema = Schema {
+    summary: "Height of the tip block",
+    params: &[],
+    response: &[Field::required("height", "integer", "")],
+    example: r#"{}"#,
+};
+
+fn get_height(ctx: &DaemonContext, _: &Request) -> Reply {
+    let bc = ctx.blockchain.lock();
+    let height = bc.chain.last().map(|b| b.index).unwrap_or(0);
+    fields([("height", height.into())])
+}
+
+const GET_BLOCK: Schema = Schema {
+    summary: "The block at a height",
+    params: &[Field::optional("index", "integer", "Height; default 0")],
+    response: &[Field::required("data", "Block", "")],
+    example: r#"{"index": 0}"#,
+};
+
+fn get_block(ctx: &DaemonContext, req: &Request) -> Reply {
+    let idx = req.u64_param("index").unwrap_or(0) as usize;
+    let bc = ctx.blockchain.lock();
+    match bc.chain.get(idx) {
+        Some(block) => data(block, "block"),
+        None => Err("block not found".into()),
+    }
+}
+
+const GET_BLOCKS: Schema = Schema {
+    summary: "Blocks `from..=to`, at most 500 per call",
+    params: &[
+        Field::required("from", "integer", "first height"),
+        Field::required("to", "integer", "last height, at most the tip"),
+    ],
+    response: &[Field::required("data", "[Block]", "")],
+    example: r#"{"from": 0, "to": 0}"#,
+};
+
+/// The `from..=to` range `req` asks `method` for, if it is within the
+/// chain of `height` and at most `max` long.
+fn requested_range(
+    req: &Request,
+    method: &str,
+    max: u64,
+    height: u64,
+) -> Result<std::ops::RangeInclusive<usize>, RpcError> {
+    let from = req.u64_param("from").ok_or("missing from field")?;
+    let to = req.u64_param("to").ok_or("missing to field")?;
+    let what = method.trim_start_matches("get");
+    if to.saturating_sub(from) >= max {
+        return Err(
+            RpcError::new(format!("at most {} {} per {}", max, what, method))
+                .with(&format!("max_{}", what), max),
+        );
+    }
+    if from > to || to > height {
+        return Err(
+            RpcError::new(format!("{} {}..{} out of range", what, from, to)).with("height", height),
+        );
+    }
+    Ok(from as usize..=to as usize)
+}
+
+/// `getblocks {from, to}`: a slice of the chain, so whole chains travel in
+/// pieces a client and a socket can handle.
+fn get_blocks(ctx: &DaemonContext, req: &Request) -> Reply {
+    let bc = ctx.blockchain.lock();
+    let height = bc.chain.len().saturating_sub(1) as u64;
+    let range = requested_range(req, "getblocks", MAX_BLOCKS_PER_REQUEST, height)?;
+    data(&&bc.chain[range], "blocks")
+}
+
+const GET_HEADERS: Schema = Schema {
+    summary: "Headers of blocks `from..=to`, at most 2000 per call",
+    params: &[
+        Field::required("from", "integer", "first height"),
+        Field::required("to", "integer", "last height, at most the tip"),
+    ],
+    response: &[Field::required(
+        "data",
+        "[BlockHeader]",
+        "`{index, timestamp, prev_hash, hash, nonce, difficulty}`",
+    )],
+    example: r#"{"from": 0, "to": 0}"#,
+};
+
+/// `getheaders {from, to}`: blocks without their transactions, so a
+/// syncing node can check a chain links up before downloading it.
+fn get_headers(ctx: &DaemonContext, req: &Request) -> Reply {
+    let bc = ctx.blockchain.lock();
+    let height = bc.chain.len().saturating_sub(1) as u64;
+    let range = requested_range(req, "getheaders", MAX_HEADERS_PER_REQUEST, height)?;
+    let headers: Vec<BlockHeader> = bc.chain[range].iter().map(BlockHeader::from).collect();
+    data(&headers, "headers")
+}
+
+const GET_MEMPOOL: Schema = Schema {
+    summary: "Pending transactions in admission order",
+    params: &[],
+    response: &[Field::required(
+        "data",
+        "[Transaction]",
+        "each with `depth`: pending transactions from the same sender ahead of it",
+    )],
+    example: r#"{}"#,
+};
+
+fn get_mempool(ctx: &DaemonContext, _: &Request) -> Reply {
+    let balances = ctx.blockchain.lock().balances();
+    let mp = ctx.mempool.lock().clone();
+    let (_, depths) = mempool_balances_and_depths(&balances, &mp);
+    let entries: Vec<Value> = mp
+        .iter()
+        .zip(depths)
+        .filter_map(|(tx, depth)| {
+            let mut entry = serde_json::to_value(tx).ok()?;
+            entry["depth"] = depth.into();
+            Some(entry)
+        })
+        .collect();
+    data(&entries, "mempool")
+}
+
+/// What pending transactions from `sender` (lowercase) spend.
+fn pending_out(ctx: &DaemonContext, sender: &str) -> i64 {
+    ctx.mempool
+        .lock()
+        .iter()
+        .filter(|t| t.from.trim().to_lowercase() == sender)
+        .map(|t| t.amount)
+        .sum()
+}
+
+/// `admit_transaction`, recording the decision and the sender's state
+/// just before it in the admission journal.
+fn admit_and_journal(
+    ctx: &DaemonContext,
+    req: &Request,
+    tx: Transaction,
+) -> Result<String, String> {
+    let sender = tx.from.trim().to_lowercase();
+    let (height, sender_balance) = {
+        let bc = ctx.blockchain.lock();
+        let height = bc.chain.last().map(|b| b.index).unwrap_or(0);
+        (height, ctx.balance_index.lock().current(&bc, &sender))
+    };
+    let pending_out = pending_out(ctx, &sender);
+    let txid = tx.txid();
+    let refusal = ctx.blocklist.lock().refusal(&tx.from, &tx.to);
+    let result = match refusal {
+        Some(reason) => {
+            ctx.rejections.lock().record(&txid, &reason);
+            Err(reason)
+        }
+        None => {
+            let admitted =
+                admit_transaction(tx.clone(), &ctx.blockchain, &ctx.mempool, &ctx.rejections);
+            if admitted.is_ok() {
+                ctx.relay.lock().offer(&tx);
+            }
+            admitted
+        }
+    };
+    ctx.journal.lock().record(AdmissionEntry {
+        timestamp: chrono::Utc::now().timestamp(),
+        txid,
+        source: req.conn.addr.clone(),
+        agent: req.conn.agent(),
+        method: req.method.to_string(),
+        decision: if result.is_ok() {
+            "accepted"
+        } else {
+            "rejected"
+        }
+        .to_string(),
+        reason: result.as_ref().err().cloned(),
+        sender,
+        height,
+        sender_balance,
+        pending_out,
+    });
+    result
+}
+
+const SUBMIT_TX: Schema = Schema {
+    summary: "Add a signed transaction to the mempool",
+    params: &[Field::required("tx", "Transaction", "")],
+    response: &[
+        Field::required("status", "string", "\"ok\""),
+        Field::required("txid", "string", ""),
+    ],
+    example: r#"{"tx": {"from": "04abababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababab", "pub_key": "04abababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababab", "to": "04cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd", "amount": 1000, "signature": "00"}}"#,
+};
+
+fn submit_tx(ctx: &DaemonContext, req: &Request) -> Reply {
+    let tx_val = req.param("tx").ok_or("missing tx field")?;
+    if let Some(reason) = oversized(tx_val) {
+        ctx.warn(req.conn, format_args!("submittx rejected: {}", reason));
+        return Err(format!("rejected: {}", reason).into());
+    }
+    let tx = serde_json::from_value::<Transaction>(tx_val.clone())
+        .map_err(|_| "failed to parse transaction")?;
+    if ctx.check_disk() {
+        return Err(crate::disk::OUT_OF_DISK.into());
+    }
+    match admit_and_journal(ctx, req, tx.clone()) {
+        Ok(txid) => {
+            ctx.publish(NodeEvent::NewTx {
+                txid: txid.clone(),
+                tx,
+            });
+            fields([("status", "ok".into()), ("txid", txid.into())])
+        }
+        Err(reason) => {
+            ctx.warn(
+                req.conn,
+                format_args!("submittx {} rejected: {}", tx.txid(), reason),
+            );
+            if reason == "invalid signature" {
+                ctx.penalize(req.conn, Misbehavior::BadSignature);
+            }
+            Err(format!("rejected: {}", reason).into())
+        }
+    }
+}
+
+const SUBMIT_BLOCK: Schema = Schema {
+    summary: "Submit a mined block",
+    params: &[
+        Field::required("block", "Block", "in canonical encoding"),
+        Field::optional(
+            "template_id",
+            "string",
+            "from `getblocktemplate`, to tell stale blocks apart",
+        ),
+    ],
+    response: &[Field::required(
+        "status",
+        "string",
+        "\"ok\", \"ok, duplicate\", \"stale: ...\" or \"rejected: ...\"",
+    )],
+    example: r#"{"block": {"index": 1, "timestamp": "2025-10-11T00:00:30Z", "transactions": [], "prev_hash": "0000000000000000000000000000000000000000000000000000000000000000", "hash": "0000000000000000000000000000000000000000000000000000000000000000", "nonce": 0, "difficulty": 1}}"#,
+};
+
+/// `submitblock {block, template_id?}`. Blocks built on a tip that has
+/// since moved get a `stale: ...` status instead of the raw rejection.
+fn submit_block(ctx: &DaemonContext, req: &Request) -> Reply {
+    let block_val = req.param("block").ok_or("missing block field")?;
+    if let Some(reason) = oversized(block_val) {
+        ctx.submissions.lock().rejected += 1;
+        ctx.penalize(req.conn, Misbehavior::InvalidBlock);
+        return block_status(ctx, req, format!("rejected: {}", reason));
+    }
+    let block = serde_json::from_value::<crate::blockchain::Block>(block_val.clone())
+        .map_err(|_| "failed to parse block")?;
+    if ctx.check_disk() {
+        return Err(crate::disk::OUT_OF_DISK.into());
+    }
+    let template = req
+        .str_param("template_id")
+        .and_then(|id| ctx.templates.lock().get(id).cloned());
+    let status = accept_block(ctx, block, template.as_ref(), &req.conn.addr);
+    // Losing a race for a height is not misbehavior, nor is an encoding
+    // only this node's serializer can't read back; failing validation is
+    if status.starts_with("rejected")
+        && !status.contains("already exists")
+        && !status.contains("non-canonical")
+    {
+        ctx.penalize(req.conn, Misbehavior::InvalidBlock);
+    }
+    block_status(ctx, req, status)
+}
+
+/// Why a submitted block or transaction is too big to be worth parsing:
+/// more transactions than a block may carry, or a string longer than any
+/// valid field.
+fn oversized(submitted: &Value) -> Option<String> {
+    match submitted {
+        Value::String(s) if s.len() > MAX_SUBMITTED_FIELD_LEN => Some(format!(
+            "field of {} bytes (max {})",
+            s.len(),
+            MAX_SUBMITTED_FIELD_LEN
+        )),
+        Value::Object(map) => {
+            if let Some(txs) = map.get("transactions").and_then(|t| t.as_array())
+                && txs.len() > MAX_SUBMITTED_BLOCK_TXS
+            {
+                return Some(format!(
+                    "{} transactions (max {})",
+                    txs.len(),
+                    MAX_SUBMITTED_BLOCK_TXS
+                ));
+            }
+            map.values().find_map(oversized)
+        }
+        Value::Array(items) => items.iter().find_map(oversized),
+        _ => None,
+    }
+}
+
+/// `{"status": ...}` for a submitted block, logging rejections with the
+/// connection that sent it.
+fn block_status(ctx: &DaemonContext, req: &Request, status: String) -> Reply {
+    if status.starts_with("rejected") {
+        ctx.warn(req.conn, format_args!("{} {}", req.method, status));
+    }
+    fields([("status", status.into())])
+}
+
+/// Validate and append a submitted block, returning its status line. A
+/// block that builds below our tip is kept on a side branch, which
+/// replaces ours once it holds more work; `from` is who sent it.
+fn accept_block(
+    ctx: &DaemonContext,
+    block: crate::blockchain::Block,
+    template: Option<&IssuedTemplate>,
+    from: &str,
+) -> String {
+    let mut bc = ctx.blockchain.lock();
+    if is_duplicate_block(&bc, &ctx.seen_blocks.lock(), &block) {
+        ctx.submissions.lock().duplicate += 1;
+        return "ok, duplicate".to_string();
+    }
+    let side = match accept_side_block(ctx, &mut bc, &block, from) {
+        Ok(side) => side,
+        Err(e) => return format!("error: {:#}", e),
+    };
+    if let Some(side) = side {
+        let tip = bc.chain.last().map_or(0, |b| b.index);
+        let mut counters = ctx.submissions.lock();
+        return match side {
+            SideBlock::Switched(orphans) => {
+                counters.accepted += 1;
+                drop(counters);
+                drop(bc);
+                for tx in orphans {
+                    let _ = admit_transaction(tx, &ctx.blockchain, &ctx.mempool, &ctx.rejections);
+                }
+                "ok".to_string()
+            }
+            SideBlock::Kept => {
+                counters.stale += 1;
+                let now = chrono::Utc::now().timestamp();
+                stale_status(&bc, template, &block, now).unwrap_or_else(|| {
+                    format!(
+                        "stale: kept on a side branch with less work, current height {}",
+                        tip
+                    )
+                })
+            }
+            SideBlock::Refused => {
+                counters.stale += 1;
+                format!(
+                    "stale: its branch is deeper than max_reorg_depth, current height {}",
+                    tip
+                )
+            }
+            SideBlock::Invalid(reason) => {
+                counters.rejected += 1;
+                format!("rejected: {}", reason)
+            }
+        };
+    }
+    let dyn_diff = bc.get_dynamic_difficulty();
+    // Its parent may still be on the way; parking it takes real work at
+    // today's difficulty, so the pool can't be filled for free
+    if awaits_parent(&bc, &block)
+        && block.difficulty >= dyn_diff
+        && Blockchain::calculate_hash(&block) == block.hash
+        && crate::blockchain::hash_meets_difficulty(&block.hash, block.difficulty)
+    {
+        let tip = bc.chain.last().map_or(0, |b| b.index);
+        ctx.orphan_blocks
+            .lock()
+            .insert(block, std::time::Instant::now());
+        ctx.submissions.lock().orphaned += 1;
+        return format!(
+            "orphan: parent not seen yet, parked for up to {}s, current height {}",
+            ORPHAN_TTL.as_secs(),
+            tip
+        );
+    }
+    let rejection = match bc.chain.last() {
+        Some(last) if block.index <= last.index => Some(format!(
+            "rejected: block index {} already exists (current height {})",
+            block.index, last.index
+        )),
+        _ => bc
+            .validate_block_verbose(&block, dyn_diff, false)
+            .map(|err| format!("rejected: {}", err)),
+    };
+    let now = chrono::Utc::now().timestamp();
+    let mut counters = ctx.submissions.lock();
+    match rejection {
+        Some(reason) => match stale_status(&bc, template, &block, now) {
+            Some(stale) => {
+                counters.stale += 1;
+                stale
+            }
+            None => {
+                counters.rejected += 1;
+                reason
+            }
+        },
+        None if bc.add_block(block, dyn_diff) => {
+            counters.accepted += 1;
+            if let Some(tip) = bc.chain.last() {
+                ctx.seen_blocks.lock().insert(&tip.hash);
+            }
+            ctx.persist_chain(&bc);
+            if let Some(tip) = bc.chain.last() {
+                ctx.publish(NodeEvent::NewBlock { block: tip.clone() });
+            }
+            prune_mempool_after_block(&bc, &mut ctx.mempool.lock(), &mut ctx.rejections.lock());
+            if connect_orphans(ctx, &mut bc) > 0 {
+                ctx.persist_chain(&bc);
+            }
+            "ok".to_string()
+        }
+        None => {
+            counters.rejected += 1;
+            "error: failed to add block".to_string()
+        }
+    }
+}
+
+const GET_TX_STATUS: Schema = Schema {
+    summary: "Whether a transaction is pending, confirmed, rejected or unknown",
+    params: &[Field::required("txid", "string", "")],
+    response: &[
+        Field::required("txid", "string", ""),
+        Field::required("data", "object", "`status` and its details"),
+    ],
+    example: r#"{"txid": "0000000000000000000000000000000000000000000000000000000000000000"}"#,
+};
+
+fn get_tx_status(ctx: &DaemonContext, req: &Request) -> Reply {
+    let txid = req.str_param("txid").ok_or("missing txid field")?;
+    let status = {
+        let bc = ctx.blockchain.lock();
+        let mp = ctx.mempool.lock();
+        let log = ctx.rejections.lock();
+        lookup_tx_status(&bc, &mp, &log, txid)
+    };
+    let mut reply = data(&status, "tx status")?;
+    reply.insert("txid".to_string(), txid.into());
+    Ok(reply)
+}
+
+const GET_PEERS: Schema = Schema {
+    summary: "Configured peer addresses",
+    params: &[Field::optional(
+        "healthy",
+        "boolean",
+        "only peers that answered their last health check",
+    )],
+    response: &[Field::required("data", "[string]", "")],
+    example: r#"{"healthy": true}"#,
+};
+
+fn get_peers(ctx: &DaemonContext, req: &Request) -> Reply {
+    let healthy = req
+        .param("healthy")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    if healthy {
+        data(&ctx.peers.healthy_peers(), "peers")
+    } else {
+        data(&ctx.peers.get_peers(), "peers")
+    }
+}
+
+const ADD_PEER: Schema = Schema {
+    summary: "Announce a node for this one to sync with and pass on in `getpeers`",
+    params: &[Field::required(
+        "address",
+        "string",
+        "host:port of its daemon",
+    )],
+    response: &[
+        Field::required(
+            "status",
+            "string",
+            "\"ok\", or \"resolving\" while a host name is looked up; it is added once it resolves",
+        ),
+        Field::required(
+            "added",
+            "boolean",
+            "false if the peer was known already or is still resolving",
+        ),
+    ],
+    example: r#"{"address": "node.example.org:6969"}"#,
+};
+
+/// `addpeer {address}`: validated, and subject to `max_peers` like peers
+/// learned from other nodes. Admin only. A name that isn't cached is
+/// looked up in the background rather than holding up the connection.
+fn add_peer(ctx: &DaemonContext, req: &Request) -> Reply {
+    let address = req.str_param("address").ok_or("missing address field")?;
+    if ctx.standalone {
+        return Err("standalone node: no peers".into());
+    }
+    match ctx.peers.learn_peer_now(address)? {
+        Some(added) => fields([("status", "ok".into()), ("added", added.into())]),
+        None => fields([("status", "resolving".into()), ("added", false.into())]),
+    }
+}
+
+const HANDSHAKE: Schema = Schema {
+    summary: "Exchange network, genesis hash, protocol version and tip with a peer; one on another chain is told so and disconnected",
+    params: &[
+        Field::required("network", "string", ""),
+        Field::required("genesis_hash", "string", ""),
+        Field::optional("protocol_version", "integer", ""),
+        Field::optional("height", "integer", ""),
+        Field::optional("tip_hash", "string", ""),
+    ],
+    response: &[
+        Field::required("network", "string", "this node's, like the rest"),
+        Field::required("genesis_hash", "string", ""),
+        Field::required("protocol_version", "integer", ""),
+        Field::required("height", "integer", ""),
+        Field::required("tip_hash", "string", ""),
+        Field::required("compatible", "boolean", "same network and genesis"),
+        Field::optional("reason", "string", "why not, when not"),
+    ],
+    example: r#"{"network": "owonero", "genesis_hash": "0000000000000000000000000000000000000000000000000000000000000000", "protocol_version": 1, "height": 0, "tip_hash": "0000000000000000000000000000000000000000000000000000000000000000"}"#,
+};
+
+/// `handshake {network, genesis_hash, ...}`: answer with this node's
+/// identity. A peer on another network or genesis gets the answer and is
+/// then disconnected.
+fn handshake(ctx: &DaemonContext, req: &Request) -> Reply {
+    let network = req.str_param("network").ok_or("missing network field")?;
+    let genesis_hash = req
+        .str_param("genesis_hash")
+        .ok_or("missing genesis_hash field")?;
+    let local = NodeIdentity::of(ctx);
+    let reason = req
+        .u64_param("protocol_version")
+        .and_then(NodeIdentity::version_incompatibility)
+        .or_else(|| local.incompatibility(network, Some(genesis_hash)));
+    let mut reply = serde_json::to_value(&local)
+        .ok()
+        .and_then(|v| v.as_object().cloned())
+        .ok_or("failed to serialize node identity")?;
+    reply.insert("compatible".to_string(), reason.is_none().into());
+    if let Some(reason) = reason {
+        ctx.warn(
+            req.conn,
+            format_args!("is on another chain: {}; disconnecting", reason),
+        );
+        req.conn.close_after_reply();
+        reply.insert("reason".to_string(), reason.into());
+    } else {
+        req.conn.greet();
+    }
+    Ok(reply)
+}
+
+const GET_PEER_INFO: Schema = Schema {
+    summary: "Height, tip and fork status of each peer from the last sync round, and health check results",
+    params: &[],
+    response: &[
+        Field::required("data", "[object]", ""),
+        Field::required(
+            "health",
+            "[object]",
+            "per peer: `last_seen`, `failures` in a row, `last_height`, `healthy`, `configured`",
+        ),
+    ],
+    example: r#"{}"#,
+};
+
+/// Height, tip and fork status of each peer from the last sync round, and
+/// how each is doing on health checks.
+fn get_peer_info(ctx: &DaemonContext, _: &Request) -> Reply {
+    let statuses = serde_json::to_value(ctx.peer_chains.lock().statuses())
+        .map_err(|_| "failed to serialize peer info")?;
+    let health =
+        serde_json::to_value(ctx.peers.health()).map_err(|_| "failed to serialize peer info")?;
+    fields([("data", statuses), ("health", health)])
+}
+
+const IMPORT_MEMPOOL: Schema = Schema {
+    summary: "Submit many transactions at once",
+    params: &[Field::required("txs", "[Transaction]", "")],
+    response: &[Field::required(
+        "data",
+        "[object]",
+        "per transaction: `txid`, `status` (\"accepted\" or \"rejected\") and `reason`",
+    )],
+    example: r#"{"txs": []}"#,
+};
+
+fn import_mempool(ctx: &DaemonContext, req: &Request) -> Reply {
+    let txs = req
+        .param("txs")
+        .and_then(|t| t.as_array())
+        .ok_or("missing txs array")?;
+    let results: Vec<Value> = txs
+        .iter()
+        .map(|tx_val| match serde_json::from_value::<Transaction>(tx_val.clone()) {
+            Ok(tx) => {
+                let txid = tx.txid();
+                match admit_and_journal(ctx, req, tx.clone()) {
+                    Ok(_) => {
+                        ctx.publish(NodeEvent::NewTx {
+                            txid: txid.clone(),
+                            tx,
+                        });
+                        serde_json::json!({"txid": txid, "status": "accepted"})
+                    }
+                    Err(reason) => {
+                        serde_json::json!({"txid": txid, "status": "rejected", "reason": reason})
+                    }
+                }
+            }
+            Err(_) => {
+                serde_json::json!({"status": "rejected", "reason": "failed to parse transaction"})
+            }
+        })
+        .collect();
+    data(&results, "import results")
+}
+
+const GET_MEMPOOL_JOURNAL: Schema = Schema {
+    summary: "Recent mempool admission decisions, newest first",
+    params: &[Field::optional("limit", "integer", "default 50")],
+    response: &[Field::required("data", "[object]", "")],
+    example: r#"{"limit": 10}"#,
+};
+
+/// `getmempooljournal {limit?}`: recent admission decisions, newest first.
+fn get_mempool_journal(ctx: &DaemonContext, req: &Request) -> Reply {
+    let limit = req
+        .u64_param("limit")
+        .map_or(MEMPOOL_JOURNAL_PAGE, |l| l as usize);
+    data(&ctx.journal.lock().recent(limit), "mempool journal")
+}
+
+const SET_COMPRESSION: Schema = Schema {
+    summary: "Deflate large responses on this connection; they then arrive as binary frames",
+    params: &[Field::optional("enabled", "boolean", "default true")],
+    response: &[Field::required(
+        "enabled",
+        "boolean",
+        "whether compression is on",
+    )],
+    example: r#"{"enabled": false}"#,
+};
+
+/// Compression changes framing, so connections answer `setcompression`
+/// themselves. Anything reaching the dispatcher has no frames to compress.
+fn set_compression(_: &DaemonContext, _: &Request) -> Reply {
+    fields([("enabled", false.into())])
+}
+
+const SUBSCRIBE: Schema = Schema {
+    summary: "Turn the connection into an event stream",
+    params: &[],
+    response: &[
+        Field::required("events", "[string]", ""),
+        Field::required("heartbeat_secs", "integer", ""),
+    ],
+    example: r#"{}"#,
+};
+
+/// Connections answer `subscribe` themselves, since it turns them into an
+/// event stream; the dispatcher alone has nothing to stream to.
+fn subscribe(_: &DaemonContext, _: &Request) -> Reply {
+    Err("subscribe needs a WebSocket connection".into())
+}
+
+const GET_MINED_BLOCKS: Schema = Schema {
+    summary: "Blocks whose coinbase paid an address, newest first",
+    params: &[
+        Field::required("address", "string", ""),
+        Field::optional("limit", "integer", "default 50, at most 500"),
+        Field::optional(
+            "before",
+            "integer",
+            "only blocks below this height; pass `next_before` back for the next page",
+        ),
+    ],
+    response: &[Field::required(
+        "data",
+        "object",
+        "`total`, `blocks` (`height`, `timestamp`, `reward`, `hash`) and `next_before`",
+    )],
+    example: r#"{"address": "04abababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababab", "limit": 10}"#,
+};
+
+fn get_mined_blocks(ctx: &DaemonContext, req: &Request) -> Reply {
+    let address = req.str_param("address").ok_or("missing address field")?;
+    let limit = req
+        .u64_param("limit")
+        .map_or(MINED_BLOCKS_PAGE, |l| l as usize)
+        .min(MINED_BLOCKS_MAX_PAGE);
+    let before = req.u64_param("before");
+    let page = mined_blocks(&ctx.blockchain.lock(), address, limit, before);
+    data(&page, "mined blocks")
+}
+
+const GET_BALANCE: Schema = Schema {
+    summary: "Confirmed balance of an address",
+    params: &[
+        Field::required("address", "string", ""),
+        Field::optional(
+            "height",
+            "integer",
+            "balance after this block; default the tip",
+        ),
+        Field::optional(
+            "include_pending",
+            "boolean",
+            "also subtract pending transactions from the address; tip only",
+        ),
+    ],
+    response: &[
+        Field::required("address", "string", ""),
+        Field::required("height", "integer", ""),
+        Field::required("tip", "integer", ""),
+        Field::required("balance", "integer", ""),
+        Field::required("balance_owe", "number", "`balance` in OWE"),
+        Field::optional(
+            "pending_out",
+            "integer",
+            "sent by pending transactions; with `include_pending`",
+        ),
+        Field::optional(
+            "available",
+            "integer",
+            "`balance` less `pending_out`; with `include_pending`",
+        ),
+        Field::optional("available_owe", "number", "`available` in OWE"),
+    ],
+    example: r#"{"address": "04abababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababab", "include_pending": true}"#,
+};
+
+const GET_BALANCE_AT: Schema = Schema {
+    summary: "Confirmed balance of an address after a given block",
+    params: &[
+        Field::required("address", "string", ""),
+        Field::required("height", "integer", ""),
+    ],
+    response: &[
+        Field::required("address", "string", ""),
+        Field::required("height", "integer", ""),
+        Field::required("tip", "integer", ""),
+        Field::required("balance", "integer", ""),
+        Field::required("balance_owe", "number", "`balance` in OWE"),
+    ],
+    example: r#"{"address": "04abababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababab", "height": 0}"#,
+};
+
+/// `getbalance {address, height?, include_pending?}` and `getbalanceat
+/// {address, height}`: confirmed balance after the block at `height`
+/// (default: the tip), and with `include_pending` what is left of it once
+/// the address's pending transactions are mined.
+fn get_balance(ctx: &DaemonContext, req: &Request) -> Reply {
+    let address = req.str_param("address").ok_or("missing address field")?;
+    let height = req.u64_param("height");
+    if height.is_none() && req.method == "getbalanceat" {
+        return Err("missing height field".into());
+    }
+    let include_pending = req
+        .param("include_pending")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let bc = ctx.blockchain.lock();
+    let tip = bc.chain.last().map(|b| b.index).unwrap_or(0);
+    let height = height.unwrap_or(tip);
+    if height > tip {
+        return Err("height above chain tip".into());
+    }
+    if include_pending && height != tip {
+        return Err("include_pending only applies at the tip".into());
+    }
+    let balance = ctx.balance_index.lock().balance_at(&bc, address, height);
+    let mut reply = fields([
+        ("address", address.into()),
+        ("height", height.into()),
+        ("tip", tip.into()),
+        ("balance", balance.into()),
+        ("balance_owe", owe(balance)),
+    ])?;
+    if include_pending {
+        let pending_out = pending_out(ctx, &address.trim().to_lowercase());
+        reply.insert("pending_out".into(), pending_out.into());
+        reply.insert("available".into(), (balance - pending_out).into());
+        reply.insert("available_owe".into(), owe(balance - pending_out));
+    }
+    Ok(reply)
+}
+
+/// An amount in internal units as OWE.
+fn owe(amount: i64) -> Value {
+    (amount as f64 / 1000.0).into()
+}
+
+const GET_SUPPLY: Schema = Schema {
+    summary: "Coins minted by coinbases, burned, and left in circulation, in internal units",
+    params: &[],
+    response: &[
+        Field::required("height", "integer", ""),
+        Field::required("minted", "integer", ""),
+        Field::required("burned", "integer", "paid to the burn address"),
+        Field::required("circulating", "integer", "minted less burned"),
+    ],
+    example: r#"{}"#,
+};
+
+fn get_supply(ctx: &DaemonContext, _: &Request) -> Reply {
+    let supply = ctx.blockchain.lock().supply();
+    fields([
+        ("height", supply.height.into()),
+        ("minted", supply.minted.into()),
+        ("burned", supply.burned.into()),
+        ("circulating", supply.circulating.into()),
+    ])
+}
+
+const GET_BURNED: Schema = Schema {
+    summary: "Total paid to the burn address, which no key can spend",
+    params: &[],
+    response: &[
+        Field::required("address", "string", "the burn address"),
+        Field::required("height", "integer", ""),
+        Field::required("burned", "integer", ""),
+    ],
+    example: r#"{}"#,
+};
+
+fn get_burned(ctx: &DaemonContext, _: &Request) -> Reply {
+    let supply = ctx.blockchain.lock().supply();
+    fields([
+        ("address", crate::wallet::BURN_ADDRESS.into()),
+        ("height", supply.height.into()),
+        ("burned", supply.burned.into()),
+    ])
+}
+
+const GET_TIP_HISTORY: Schema = Schema {
+    summary: "Index and hash of the latest blocks, oldest first",
+    params: &[Field::optional(
+        "depth",
+        "integer",
+        "default 16, at most 256",
+    )],
+    response: &[Field::required("data", "[object]", "`index` and `hash`")],
+    example: r#"{"depth": 4}"#,
+};
+
+fn get_tip_history(ctx: &DaemonContext, req: &Request) -> Reply {
+    let depth = req
+        .u64_param("depth")
+        .map_or(TIP_HISTORY_DEPTH, |d| d as usize)
+        .min(TIP_HISTORY_MAX_DEPTH);
+    data(&tip_history(&ctx.blockchain.lock(), depth), "tip history")
+}
+
+const GET_BLOCK_TEMPLATE: Schema = Schema {
+    summary: "Everything needed to build the next block",
+    params: &[Field::optional(
+        "wallet",
+        "string",
+        "also return the coinbase paying this address",
+    )],
+    response: &[
+        Field::required("template_id", "string", "quote back in `submitblock`"),
+        Field::required("expires_at", "integer", ""),
+        Field::required("index", "integer", ""),
+        Field::required("prev_hash", "string", ""),
+        Field::required("difficulty", "integer", ""),
+        Field::required("reward", "integer", ""),
+        Field::required("transactions", "[Transaction]", ""),
+        Field::optional("coinbase", "Transaction", "only with `wallet`"),
+    ],
+    example: r#"{"wallet": "04abababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababab"}"#,
+};
+
+/// Work for the next block: its index, parent, difficulty, reward and the
+/// current mempool, plus an id to quote back in `submitblock`. With
+/// `wallet`, also the coinbase paying it; the caller needn't own it.
+fn get_block_template(ctx: &DaemonContext, req: &Request) -> Reply {
+    let wallet = req.str_param("wallet");
+    if wallet.is_some_and(|w| !crate::wallet::is_valid_address(w)) {
+        return Err("invalid wallet address".into());
+    }
+    let (index, prev_hash, difficulty, reward) = {
+        let bc = ctx.blockchain.lock();
+        let last = bc.chain.last().ok_or("chain is empty")?;
+        (
+            last.index + 1,
+            last.hash.clone(),
+            bc.get_dynamic_difficulty(),
+            bc.get_block_reward(last.index + 1),
+        )
+    };
+    let transactions = ctx.mempool.lock().clone();
+    let now = chrono::Utc::now().timestamp();
+    let (id, template) = ctx.templates.lock().issue(index, &prev_hash, now);
+    let mut reply = fields([
+        ("template_id", id.into()),
+        ("expires_at", template.expires_at.into()),
+        ("index", index.into()),
+        ("prev_hash", prev_hash.into()),
+        ("difficulty", difficulty.into()),
+        ("reward", reward.into()),
+        (
+            "transactions",
+            serde_json::to_value(&transactions).map_err(|_| "failed to serialize mempool")?,
+        ),
+    ])?;
+    if let Some(wallet) = wallet {
+        let coinbase = coinbase_to(wallet, reward);
+        reply.insert(
+            "coinbase".to_string(),
+            serde_json::to_value(&coinbase).map_err(|_| "failed to serialize coinbase")?,
+        );
+    }
+    Ok(reply)
+}
+
+/// Unsigned coinbase paying `reward` to `to`.
+fn coinbase_to(to: &str, reward: i64) -> Transaction {
+    Transaction {
+        from: "coinbase".to_string(),
+        pub_key: String::new(),
+        to: to.to_string(),
+        amount: reward,
+        signature: String::new(),
+        expires_at_height: None,
+    }
+}
+
+const GET_WORK: Schema = Schema {
+    summary: "A hashing job for miners that know nothing about blocks",
+    params: &[Field::required(
+        "wallet",
+        "string",
+        "address the coinbase pays",
+    )],
+    response: &[
+        Field::required("job_id", "string", ""),
+        Field::required("height", "integer", ""),
+        Field::required("difficulty", "integer", ""),
+        Field::required("timestamp", "string", ""),
+        Field::required(
+            "prefix",
+            "string",
+            "hex; the preimage is prefix, timestamp, middle, nonce in decimal, then \"}\"",
+        ),
+        Field::required("middle", "string", "hex"),
+    ],
+    example: r#"{"wallet": "04abababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababab"}"#,
+};
+
+/// `getwork {wallet}`: the next block paying `wallet`, reduced to what a
+/// miner that knows nothing about blocks needs. The PoW preimage is
+/// `prefix ++ timestamp ++ middle ++ nonce ++ "}"`, with `prefix` and
+/// `middle` hex-encoded and the nonce in decimal ASCII.
+fn get_work(ctx: &DaemonContext, req: &Request) -> Reply {
+    let wallet = req.str_param("wallet").ok_or("missing wallet field")?;
+    if wallet.trim().is_empty() {
+        return Err("missing wallet field".into());
+    }
+    let block = {
+        let bc = ctx.blockchain.lock();
+        let last = bc.chain.last().ok_or("chain is empty")?;
+        let index = last.index + 1;
+        let mut transactions = vec![coinbase_to(wallet, bc.get_block_reward(index))];
+        transactions.extend(ctx.mempool.lock().iter().cloned());
+        crate::blockchain::Block {
+            index,
+            timestamp: chrono::Utc::now(),
+            transactions,
+            prev_hash: last.hash.clone(),
+            hash: String::new(),
+            nonce: 0,
+            difficulty: bc.get_dynamic_difficulty(),
+        }
+    };
+    let (prefix, middle) = crate::blockchain::work_preimage_parts(&block);
+    let (height, difficulty) = (block.index, block.difficulty);
+    let timestamp = crate::blockchain::canonical_timestamp(&block.timestamp);
+    let job_id = {
+        let mut work = ctx.work.lock();
+        work.retain_tip(&block.prev_hash);
+        work.issue(block)
+    };
+    fields([
+        ("job_id", job_id.into()),
+        ("height", height.into()),
+        ("difficulty", difficulty.into()),
+        ("timestamp", timestamp.into()),
+        ("prefix", hex::encode(prefix).into()),
+        ("middle", hex::encode(middle).into()),
+    ])
+}
+
+const SUBMIT_WORK: Schema = Schema {
+    summary: "Finish a `getwork` job",
+    params: &[
+        Field::required("job_id", "string", ""),
+        Field::required("nonce", "integer", ""),
+        Field::optional(
+            "timestamp",
+            "string",
+            "if the miner rolled it, in the form `getwork` gave",
+        ),
+    ],
+    response: &[Field::required("status", "string", "as for `submitblock`")],
+    example: r#"{"job_id": "1", "nonce": 0}"#,
+};
+
+/// `submitwork {job_id, nonce, timestamp?}`: finish a `getwork` job and
+/// submit it like any other block. `timestamp`, if the miner rolled it,
+/// must be in the same canonical form `getwork` handed out.
+fn submit_work(ctx: &DaemonContext, req: &Request) -> Reply {
+    let job_id = req.str_param("job_id").ok_or("missing job_id field")?;
+    let nonce = req
+        .u64_param("nonce")
+        .and_then(|n| u32::try_from(n).ok())
+        .ok_or("missing nonce field")?;
+    if ctx.check_disk() {
+        return Err(crate::disk::OUT_OF_DISK.into());
+    }
+    let Some(mut block) = ctx.work.lock().get(job_id).cloned() else {
+        return block_status(ctx, req, "rejected: unknown or expired job".into());
+    };
+    let tip_hash = ctx.blockchain.lock().chain.last().map(|b| b.hash.clone());
+    if tip_hash.as_deref() != Some(block.prev_hash.as_str()) {
+        ctx.submissions.lock().stale += 1;
+        return fields([("status", "stale: job expired, tip moved".into())]);
+    }
+    if let Some(ts) = req.str_param("timestamp") {
+        let parsed = chrono::DateTime::parse_from_rfc3339(ts)
+            .map(|t| t.with_timezone(&chrono::Utc))
+            .ok()
+            .filter(|t| crate::blockchain::canonical_timestamp(t) == ts);
+        let Some(parsed) = parsed else {
+            ctx.submissions.lock().rejected += 1;
+            return block_status(ctx, req, "rejected: non-canonical timestamp".into());
+        };
+        block.timestamp = parsed;
+    }
+    block.nonce = nonce;
+    block.hash = Blockchain::calculate_hash(&block);
+    let status = accept_block(ctx, block, None, &req.conn.addr);
+    block_status(ctx, req, status)
+}
+
+const GET_SUBMIT_STATS: Schema = Schema {
+    summary: "Blocks accepted, rejected, stale, duplicate and orphaned since start",
+    params: &[],
+    response: &[Field::required("data", "object", "")],
+    example: r#"{}"#,
+};
+
+fn get_submit_stats(ctx: &DaemonContext, _: &Request) -> Reply {
+    data(&ctx.submissions.lock().clone(), "submit stats")
+}
+
+const UPDATE_STATS: Schema = Schema {
+    summary: "Report a miner's hashrate",
+    params: &[
+        Field::required("wallet", "string", ""),
+        Field::required("hashrate", "number", "H/s"),
+    ],
+    response: &[Field::required("status", "string", "\"ok\"")],
+    example: r#"{"wallet": "04abababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababab", "hashrate": 100.0}"#,
+};
+
+/// `updatestats {wallet, hashrate}`: a miner's own hashrate report (H/s).
+fn update_stats(ctx: &DaemonContext, req: &Request) -> Reply {
+    let wallet = req.str_param("wallet").ok_or("missing wallet field")?;
+    let hashrate = req
+        .param("hashrate")
+        .and_then(|v| v.as_f64())
+        .ok_or("missing hashrate field")?;
+    let now = chrono::Utc::now().timestamp() as u64;
+    if let Err(reason) = ctx
+        .wallet_stats
+        .lock()
+        .report_hashrate(wallet, hashrate, now)
+    {
+        ctx.warn(req.conn, format_args!("updatestats refused: {}", reason));
+        return Err(reason.into());
+    }
+    fields([("status", "ok".into())])
+}
+
+const SUBMIT_SHARE: Schema = Schema {
+    summary: "Credit a wallet with a share: a block on the tip meeting the share difficulty",
+    params: &[
+        Field::required("wallet", "string", ""),
+        Field::required("block", "Block", ""),
+    ],
+    response: &[
+        Field::required("status", "string", "\"ok\""),
+        Field::required("shares", "integer", "the wallet's share count"),
+    ],
+    example: r#"{"wallet": "04abababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababab", "block": {"index": 1, "timestamp": "2025-10-11T00:00:30Z", "transactions": [], "prev_hash": "0000000000000000000000000000000000000000000000000000000000000000", "hash": "0000000000000000000000000000000000000000000000000000000000000000", "nonce": 0, "difficulty": 1}}"#,
+};
+
+/// `submitshare {wallet, block}`: a block on the current tip that meets
+/// the share difficulty, credited to `wallet`. Returns its share count.
+fn submit_share(ctx: &DaemonContext, req: &Request) -> Reply {
+    let wallet = req.str_param("wallet").ok_or("missing wallet field")?;
+    let block_val = req.param("block").ok_or("missing block field")?;
+    let block = serde_json::from_value::<crate::blockchain::Block>(block_val.clone())
+        .map_err(|_| "failed to parse block")?;
+    let difficulty = {
+        let bc = ctx.blockchain.lock();
+        let tip = bc.chain.last().ok_or("chain is empty")?;
+        if block.prev_hash != tip.hash || block.index != tip.index + 1 {
+            return Err("stale: share is not built on the current tip".into());
+        }
+        crate::blockchain::share_difficulty(bc.get_dynamic_difficulty())
+    };
+    let refusal = if Blockchain::calculate_hash(&block) != block.hash {
+        Some("share hash does not match its contents".to_string())
+    } else if !crate::blockchain::hash_meets_difficulty(&block.hash, difficulty) {
+        Some(format!("share does not meet difficulty {}", difficulty))
+    } else {
+        None
+    };
+    let now = chrono::Utc::now().timestamp() as u64;
+    let recorded = match refusal {
+        Some(reason) => Err(reason),
+        None => ctx.wallet_stats.lock().record_share(
+            wallet,
+            &block.hash,
+            crate::blockchain::expected_hashes(difficulty),
+            now,
+        ),
+    };
+    match recorded {
+        Ok(shares) => fields([("status", "ok".into()), ("shares", shares.into())]),
+        Err(reason) => {
+            ctx.warn(req.conn, format_args!("submitshare refused: {}", reason));
+            Err(reason.into())
+        }
+    }
+}
+
+/// Absolute form of a path for display; relative paths are resolved
+/// against the daemon's working directory.
+fn path_value(path: &std::path::Path) -> Value {
+    std::path::absolute(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .display()
+        .to_string()
+        .into()
+}
+
+const HELLO: Schema = Schema {
+    summary: "Open a remote connection: state the protocol version and chain, and name the connection in logs and `getconnections`",
+    params: &[
+        Field::required("protocol_version", "integer", ""),
+        Field::optional("network", "string", "checked when given"),
+        Field::optional("genesis_hash", "string", "checked when given"),
+        Field::optional("agent", "string", ""),
+    ],
+    response: &[
+        Field::required("connection_id", "integer", ""),
+        Field::required("protocol_version", "integer", "this node's"),
+        Field::required("network", "string", "this node's"),
+    ],
+    example: r#"{"protocol_version": 1, "agent": "example-wallet/1.0"}"#,
+};
+
+/// `hello {protocol_version, network?, genesis_hash?, agent?}`: the first
+/// thing a remote connection must send. An old protocol or another chain
+/// gets an `incompatible: ...` error and is disconnected.
+fn hello(ctx: &DaemonContext, req: &Request) -> Reply {
+    let version = req
+        .u64_param("protocol_version")
+        .ok_or("missing protocol_version field")?;
+    let local = NodeIdentity::of(ctx);
+    let reason = NodeIdentity::version_incompatibility(version).or_else(|| {
+        local.incompatibility(
+            req.str_param("network").unwrap_or(&local.network),
+            req.str_param("genesis_hash"),
+        )
+    });
+    if let Some(reason) = reason {
+        ctx.warn(
+            req.conn,
+            format_args!("is incompatible: {}; disconnecting", reason),
+        );
+        req.conn.close_after_reply();
+        return Err(RpcError::new(format!("incompatible: {}", reason))
+            .with("protocol_version", PROTOCOL_VERSION)
+            .with("network", local.network));
+    }
+    if let Some(agent) = req.str_param("agent") {
+        req.conn.set_agent(agent);
+    }
+    req.conn.greet();
+    fields([
+        ("connection_id", req.conn.id.into()),
+        ("protocol_version", PROTOCOL_VERSION.into()),
+        ("network", local.network.into()),
+    ])
+}
+
+const GET_CONNECTIONS: Schema = Schema {
+    summary: "Open WebSocket connections",
+    params: &[],
+    response: &[Field::required("data", "[object]", "")],
+    example: r#"{}"#,
+};
+
+fn get_connections(ctx: &DaemonContext, _: &Request) -> Reply {
+    data(&ctx.connections.list(), "connections")
+}
+
+const RELOAD_BLOCKLIST: Schema = Schema {
+    summary: "Reread `blocked_addresses_file` and drop pending transactions it now refuses",
+    params: &[],
+    response: &[
+        Field::required("addresses", "integer", "addresses blocked now"),
+        Field::required("dropped", "integer", "pending transactions dropped"),
+    ],
+    example: r#"{}"#,
+};
+
+/// Reread `blocked_addresses_file` and drop pending transactions that
+/// are now refused. A failed read keeps the old list.
+fn reload_blocklist(ctx: &DaemonContext, _: &Request) -> Reply {
+    let mut list = ctx.blocklist.lock();
+    let addresses = list
+        .reload()
+        .map_err(|e| format!("keeping the old list: {:#}", e))?;
+    let mut mempool = ctx.mempool.lock();
+    let before = mempool.len();
+    mempool.retain(|tx| list.refusal(&tx.from, &tx.to).is_none());
+    fields([
+        ("addresses", addresses.into()),
+        ("dropped", (before - mempool.len()).into()),
+    ])
+}
+
+const RELOAD_CONFIG: Schema = Schema {
+    summary: "Reread config.json and apply the settings that don't need a restart",
+    params: &[],
+    response: &[
+        Field::required("applied", "[string]", "fields now in force"),
+        Field::required(
+            "restart_required",
+            "[string]",
+            "changed fields that take effect on restart",
+        ),
+        Field::required(
+            "dropped",
+            "integer",
+            "pending transactions a changed blocklist dropped",
+        ),
+    ],
+    example: r#"{}"#,
+};
+
+/// Reread `config.json` and apply limits, peers and the blocklist in
+/// place. A file that fails to load or validate applies nothing.
+fn reload_config(ctx: &DaemonContext, _: &Request) -> Reply {
+    let report =
+        crate::reload::reload_from_file(ctx).map_err(|e| format!("nothing applied: {:#}", e))?;
+    (ctx.log)(&format!("[daemon] config reloaded: {}", report));
+    fields([
+        ("applied", json!(report.applied)),
+        ("restart_required", json!(report.restart_required)),
+        ("dropped", report.dropped.into()),
+    ])
+}
+
+const GET_BANS: Schema = Schema {
+    summary: "IPs banned for misbehavior, and until when",
+    params: &[],
+    response: &[Field::required(
+        "data",
+        "[object]",
+        "per ban: `ip`, `reason`, `banned_at`, `until`",
+    )],
+    example: r#"{}"#,
+};
+
+fn get_bans(ctx: &DaemonContext, _: &Request) -> Reply {
+    let now = chrono::Utc::now().timestamp();
+    data(&ctx.bans.lock().current(now), "bans")
+}
+
+const GET_SCHEMA: Schema = Schema {
+    summary: "This description of the protocol",
+    params: &[],
+    response: &[Field::required("data", "object", "")],
+    example: r#"{}"#,
+};
+
+fn get_schema(_: &DaemonContext, _: &Request) -> Reply {
+    fields([("data", protocol_schema())])
+}
+
+const GET_INFO: Schema = Schema {
+    summary: "Node overview and per-method call counts and latency",
+    params: &[],
+    response: &[
+        Field::required("network", "string", ""),
+        Field::required("standalone", "boolean", ""),
+        Field::required(
+            "read_only",
+            "boolean",
+            "submissions are refused; chain data only",
+        ),
+        Field::required("protocol_version", "integer", ""),
+        Field::required("strict_consensus", "boolean", ""),
+        Field::required("height", "integer", ""),
+        Field::required("tip_hash", "string", ""),
+        Field::required("tip_timestamp", "integer", ""),
+        Field::required("connections", "integer", ""),
+        Field::required("max_connections", "integer", "0 is no limit"),
+        Field::required("max_connections_per_ip", "integer", "0 is no limit"),
+        Field::required(
+            "refused_connections",
+            "integer",
+            "closed over a limit since start",
+        ),
+        Field::required("rate_limit_per_sec", "integer", "0 is no limit"),
+        Field::required("rate_limit_burst", "integer", ""),
+        Field::required(
+            "rate_limited",
+            "integer",
+            "requests refused for an empty bucket since start",
+        ),
+        Field::required("peers", "integer", ""),
+        Field::required("max_peers", "integer", "including configured peers"),
+        Field::required("peer_forks", "integer", ""),
+        Field::required("mempool_size", "integer", ""),
+        Field::required(
+            "orphan_blocks",
+            "integer",
+            "blocks waiting for their parent",
+        ),
+        Field::required("max_mempool_depth", "integer", ""),
+        Field::required("blocked_addresses", "integer", ""),
+        Field::required("ws_compression", "boolean", ""),
+        Field::required(
+            "max_message_bytes",
+            "integer",
+            "for remote clients; 0 is no limit",
+        ),
+        Field::required(
+            "disk",
+            "object",
+            "`{free_bytes, min_free_bytes, read_only, persist_errors, last_persist_error}`",
+        ),
+        Field::required("max_reorg_depth", "integer", "0 is no limit"),
+        Field::optional(
+            "last_reorg",
+            "object",
+            "`{timestamp, peer, depth, fork_height, old_tip, new_height, new_tip, orphaned, refused}`; null before the first",
+        ),
+        Field::required("reorgs", "object", "`{performed, refused}` since start"),
+        Field::required("wallet_stats", "object", ""),
+        Field::required("uptime_secs", "integer", ""),
+        Field::required("data_dir", "string", ""),
+        Field::required("chain_path", "string", ""),
+        Field::required("snapshot_path", "string", ""),
+        Field::required("methods", "object", ""),
+        Field::required("scratchpad_pool", "object", ""),
+        Field::required("build", "object", ""),
+    ],
+    example: r#"{}"#,
+};
+
+/// Node overview plus per-method call counts and latency since start.
+fn get_info(ctx: &DaemonContext, _: &Request) -> Reply {
+    let (height, tip_hash, tip_timestamp) = {
+        let bc = ctx.blockchain.lock();
+        let tip = bc.chain.last().ok_or("chain is empty")?;
+        (tip.index, tip.hash.clone(), tip.timestamp.timestamp())
+    };
+    let mempool_size = ctx.mempool.lock().len();
+    let metrics = ctx.metrics.lock();
+    fields([
+        ("network", ctx.network().into()),
+        ("standalone", ctx.standalone.into()),
+        ("read_only", ctx.read_only.into()),
+        ("protocol_version", PROTOCOL_VERSION.into()),
+        (
+            "strict_consensus",
+            crate::blockchain::strict_consensus().into(),
+        ),
+        ("height", height.into()),
+        ("tip_hash", tip_hash.into()),
+        ("tip_timestamp", tip_timestamp.into()),
+        ("connections", ctx.connections.count().into()),
+        ("max_connections", ctx.slots.max_total().into()),
+        ("max_connections_per_ip", ctx.slots.max_per_ip().into()),
+        ("refused_connections", ctx.slots.refused().into()),
+        ("rate_limit_per_sec", ctx.rate_limits.per_sec().into()),
+        ("rate_limit_burst", ctx.rate_limits.burst().into()),
+        ("rate_limited", ctx.rate_limits.limited().into()),
+        ("peers", ctx.peers.get_peers().len().into()),
+        ("max_peers", ctx.peers.max().into()),
+        ("peer_forks", ctx.peer_chains.lock().forks_detected().into()),
+        ("mempool_size", mempool_size.into()),
+        ("orphan_blocks", ctx.orphan_blocks.lock().len().into()),
+        (
+            "max_mempool_depth",
+            crate::daemon::max_mempool_depth().into(),
+        ),
+        ("blocked_addresses", ctx.blocklist.lock().len().into()),
+        ("ws_compression", ctx.config.lock().ws_compression.into()),
+        (
+            "max_message_bytes",
+            crate::daemon::max_message_bytes().into(),
+        ),
+        ("disk", ctx.disk.status()),
+        ("max_reorg_depth", ctx.reorgs.max_depth().into()),
+        (
+            "last_reorg",
+            serde_json::to_value(ctx.reorgs.last()).unwrap_or_default(),
+        ),
+        ("reorgs", ctx.reorgs.status()),
+        (
+            "wallet_stats",
+            serde_json::to_value(ctx.wallet_stats.lock().sizes())
+                .map_err(|_| "failed to serialize metrics")?,
+        ),
+        ("uptime_secs", metrics.uptime_secs().into()),
+        ("data_dir", path_value(&crate::config::get_config_dir())),
+        ("chain_path", path_value(&ctx.chain_path)),
+        (
+            "snapshot_path",
+            path_value(&crate::config::get_snapshot_path()),
+        ),
+        (
+            "methods",
+            serde_json::to_value(metrics.methods()).map_err(|_| "failed to serialize metrics")?,
+        ),
+        (
+            "scratchpad_pool",
+            serde_json::to_value(crate::blockchain::scratchpad_pool_stats())
+                .map_err(|_| "failed to serialize metrics")?,
+        ),
+        (
+            "build",
+            serde_json::to_value(crate::build_info::current())
+                .map_err(|_| "failed to serialize build info")?,
+        ),
+    ])
+}
+
+const DEV_MINE_BLOCKS: Schema = Schema {
+    summary: "Append empty blocks paying an address (dev_mode only)",
+    params: &[
+        Field::required("to", "string", ""),
+        Field::optional("count", "integer", "default 1"),
+    ],
+    response: &[
+        Field::required("status", "string", ""),
+        Field::required("blocks", "integer", ""),
+        Field::required("height", "integer", ""),
+    ],
+    example: r#"{"to": "04abababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababab", "count": 1}"#,
+};
+
+const DEV_FAUCET: Schema = Schema {
+    summary: "Append one block paying an address a chosen amount (dev_mode only)",
+    params: &[
+        Field::required("to", "string", ""),
+        Field::required("amount", "integer", ""),
+    ],
+    response: &[
+        Field::required("status", "string", ""),
+        Field::required("blocks", "integer", ""),
+        Field::required("height", "integer", ""),
+    ],
+    example: r#"{"to": "04abababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababababab", "amount": 1000}"#,
+};
+
+/// `devmineblocks {to, count}` and `devfaucet {to, amount}`.
+fn dev_mine(ctx: &DaemonContext, req: &Request) -> Reply {
+    let to = req.str_param("to").unwrap_or("");
+    let (count, amount) = if req.method == "devmineblocks" {
+        let count = req.u64_param("count").unwrap_or(1);
+        (count, ctx.blockchain.lock().get_block_reward(0))
+    } else {
+        (1, req.param("amount").and_then(|a| a.as_i64()).unwrap_or(0))
+    };
+    if to.is_empty() {
+        return Err("missing to field".into());
+    }
+    if amount <= 0 {
+        return Err("amount must be > 0".into());
+    }
+    let mut bc = ctx.blockchain.lock();
+    let mut mp = ctx.mempool.lock();
+    let mut log = ctx.rejections.lock();
+    let mut added = 0;
+    while added < count && dev_append_block(&mut bc, &mut mp, &mut log, to, amount) {
+        added += 1;
+        if let Some(tip) = bc.chain.last() {
+            ctx.publish(NodeEvent::NewBlock { block: tip.clone() });
+        }
+    }
+    ctx.persist_chain(&bc);
+    let height = bc.chain.last().map(|b| b.index).unwrap_or(0);
+    fields([
+        ("status", "ok".into()),
+        ("blocks", added.into()),
+        ("height", height.into()),
+    ])
+}
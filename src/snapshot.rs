@@ -0,0 +1,339 @@
+use crate::blockchain::{Blockchain, dynamic_difficulty_after, hash_meets_difficulty};
+use crate::interrupt::Interrupted;
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+pub const SNAPSHOT_FORMAT: &str = "owonero-snapshot";
+pub const SNAPSHOT_VERSION: u32 = 1;
+/// Blocks at the tip that are fully validated (PoW, signatures, balances)
+/// on bootstrap. Everything below is checked for linkage and hashes only.
+pub const SNAPSHOT_VERIFY_TAIL: usize = 32;
+
+/// A whole chain plus the metadata a bootstrapping node checks before it
+/// trusts it. Stored and served gzip-compressed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub format: String,
+    pub version: u32,
+    pub height: u64,
+    pub tip_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub chain: Blockchain,
+}
+
+impl Snapshot {
+    pub fn of(bc: &Blockchain) -> Self {
+        let tip = bc.chain.last();
+        Self {
+            format: SNAPSHOT_FORMAT.to_string(),
+            version: SNAPSHOT_VERSION,
+            height: bc.chain.len().saturating_sub(1) as u64,
+            tip_hash: tip.map(|b| b.hash.clone()).unwrap_or_default(),
+            created_at: Utc::now(),
+            chain: bc.clone(),
+        }
+    }
+}
+
+pub fn encode(snapshot: &Snapshot) -> Result<Vec<u8>> {
+    let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    serde_json::to_writer(&mut enc, snapshot)?;
+    Ok(enc.finish()?)
+}
+
+pub fn decode(data: &[u8]) -> Result<Snapshot> {
+    let mut text = String::new();
+    flate2::read::GzDecoder::new(data)
+        .read_to_string(&mut text)
+        .context("snapshot is not gzip-compressed JSON")?;
+    let snapshot: Snapshot = serde_json::from_str(&text).context("parsing snapshot")?;
+    if snapshot.format != SNAPSHOT_FORMAT || snapshot.version != SNAPSHOT_VERSION {
+        bail!(
+            "unsupported snapshot format {} v{}",
+            snapshot.format,
+            snapshot.version
+        );
+    }
+    Ok(snapshot)
+}
+
+/// Write the snapshot next to the chain file. Goes through a temp file so
+/// the HTTP server never streams a half-written snapshot.
+pub fn write_snapshot(bc: &Blockchain, path: &Path) -> Result<()> {
+    let data = encode(&Snapshot::of(bc))?;
+    let tmp = path.with_extension("tmp");
+    let mut file = std::fs::File::create(&tmp)?;
+    file.write_all(&data)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Periodically snapshot the daemon's chain, skipping rounds where the
+/// height hasn't moved. The chain is cloned under the lock and encoded off
+/// the async runtime.
+pub fn spawn_writer(blockchain: Arc<Mutex<Blockchain>>, path: std::path::PathBuf, every: Duration) {
+    tokio::spawn(async move {
+        let mut written_height = None;
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            let bc = {
+                let bc = blockchain.lock();
+                let height = bc.chain.len();
+                if written_height == Some(height) {
+                    continue;
+                }
+                written_height = Some(height);
+                bc.clone()
+            };
+            let path = path.clone();
+            match tokio::task::spawn_blocking(move || write_snapshot(&bc, &path)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("Failed to write chain snapshot: {}", e),
+                Err(e) => eprintln!("Chain snapshot task failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Read a snapshot from an http(s) URL or a local file.
+pub async fn fetch(from: &str) -> Result<Vec<u8>> {
+    if from.starts_with("http://") || from.starts_with("https://") {
+        let resp = reqwest::get(from).await?.error_for_status()?;
+        Ok(resp.bytes().await?.to_vec())
+    } else {
+        std::fs::read(from).with_context(|| format!("reading {}", from))
+    }
+}
+
+/// Check a snapshot before it replaces the local chain. Cheap structural
+/// checks run first, then the last `tail` blocks are validated the way the
+/// daemon validates a new block, then every remaining hash is recomputed so
+/// no block's contents can differ from what the linkage commits to, and
+/// checked for the proof of work the difficulty schedule asks of it. With
+/// `full` every block is replayed through block validation.
+pub fn verify(
+    snapshot: &Snapshot,
+    tail: usize,
+    full: bool,
+    checkpoints: &[(u64, &str)],
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let chain = &snapshot.chain.chain;
+    let genesis = Blockchain::create_genesis_block();
+    let first = chain
+        .first()
+        .ok_or_else(|| anyhow!("snapshot chain is empty"))?;
+    if first.hash != genesis.hash || Blockchain::calculate_hash(first) != genesis.hash {
+        bail!("snapshot has a different genesis block");
+    }
+    for (i, pair) in chain.windows(2).enumerate() {
+        let (prev, cur) = (&pair[0], &pair[1]);
+        if cur.index != i as u64 + 1 {
+            bail!("block {} has index {}", i + 1, cur.index);
+        }
+        if cur.prev_hash != prev.hash {
+            bail!("chain broken at index {}", cur.index);
+        }
+    }
+    let tip = chain.last().unwrap();
+    if snapshot.height != tip.index || snapshot.tip_hash != tip.hash {
+        bail!(
+            "metadata claims height {} tip {}, chain has height {} tip {}",
+            snapshot.height,
+            snapshot.tip_hash,
+            tip.index,
+            tip.hash
+        );
+    }
+    for (height, hash) in checkpoints {
+        if let Some(block) = chain.get(*height as usize)
+            && block.hash != *hash
+        {
+            bail!("block {} does not match checkpoint {}", height, hash);
+        }
+    }
+
+    let start = if full {
+        1
+    } else {
+        chain.len().saturating_sub(tail).max(1)
+    };
+    replay(&snapshot.chain, start, cancel)?;
+    for (i, block) in chain[..start].iter().enumerate().skip(1) {
+        stop_if_cancelled(cancel, block.index)?;
+        if Blockchain::calculate_hash(block) != block.hash {
+            bail!("invalid hash at index {}", block.index);
+        }
+        let expected = dynamic_difficulty_after(&chain[..i], snapshot.chain.target_block_time);
+        if block.difficulty != expected {
+            bail!(
+                "block {} has difficulty {}, the schedule requires {}",
+                block.index,
+                block.difficulty,
+                expected
+            );
+        }
+        if !hash_meets_difficulty(&block.hash, block.difficulty) {
+            bail!("PoW check failed at index {}", block.index);
+        }
+    }
+    Ok(())
+}
+
+/// A full verify of a long chain takes a while; nothing is installed
+/// until it finishes, so stopping early loses nothing.
+fn stop_if_cancelled(cancel: &CancellationToken, index: u64) -> Result<()> {
+    if cancel.is_cancelled() {
+        return Err(Interrupted(format!(
+            "snapshot verification stopped at block {}; nothing installed, local chain unchanged",
+            index
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Validate blocks `start..` on top of the blocks before them.
+fn replay(bc: &Blockchain, start: usize, cancel: &CancellationToken) -> Result<()> {
+    let mut partial = Blockchain {
+        chain: bc.chain[..start].to_vec(),
+        target_block_time: bc.target_block_time,
+    };
+    for block in &bc.chain[start..] {
+        stop_if_cancelled(cancel, block.index)?;
+        let difficulty = partial.get_dynamic_difficulty();
+        if let Some(reason) = partial.validate_block_verbose(block, difficulty, false) {
+            bail!("block {} rejected: {}", block.index, reason);
+        }
+        partial.chain.push(block.clone());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Block;
+
+    /// Find a nonce meeting the block's own difficulty.
+    fn mine(block: &mut Block) {
+        loop {
+            block.hash = Blockchain::calculate_hash(block);
+            if hash_meets_difficulty(&block.hash, block.difficulty) {
+                break;
+            }
+            block.nonce += 1;
+        }
+    }
+
+    /// A chain of `len` mined empty blocks 30s apart.
+    fn synthetic_chain(len: u64) -> Blockchain {
+        let mut bc = Blockchain::new();
+        let start = bc.chain[0].timestamp;
+        for i in 1..=len {
+            let mut block = Block {
+                index: i,
+                timestamp: start + chrono::Duration::seconds(30 * i as i64),
+                transactions: vec![],
+                prev_hash: bc.chain.last().unwrap().hash.clone(),
+                hash: String::new(),
+                nonce: 0,
+                difficulty: 1,
+            };
+            mine(&mut block);
+            bc.chain.push(block);
+        }
+        bc
+    }
+
+    /// `snapshot` with block `index` changed by `edit`, and the blocks
+    /// after it relinked and mined again so that only it is wrong.
+    fn forge(snapshot: &Snapshot, index: usize, edit: impl Fn(&mut Block)) -> Snapshot {
+        let mut forged = snapshot.clone();
+        let chain = &mut forged.chain.chain;
+        edit(&mut chain[index]);
+        for i in index + 1..chain.len() {
+            chain[i].prev_hash = chain[i - 1].hash.clone();
+            mine(&mut chain[i]);
+        }
+        forged.tip_hash = chain.last().unwrap().hash.clone();
+        forged
+    }
+
+    #[test]
+    fn bootstrap_verification_rejects_bad_history() {
+        let tail = 8;
+        let good = decode(&encode(&Snapshot::of(&synthetic_chain(40))).unwrap()).unwrap();
+        assert_eq!(good.height, 40);
+        assert_eq!(good.tip_hash, good.chain.chain[40].hash);
+        let go = CancellationToken::new();
+        verify(&good, tail, false, &[], &go).unwrap();
+
+        let mut tampered = good.clone();
+        tampered.chain.chain[5].nonce += 1;
+        let err = verify(&tampered, tail, false, &[], &go).unwrap_err();
+        assert!(err.to_string().contains("invalid hash at index 5"), "{err}");
+
+        let mut relinked = good.clone();
+        relinked.chain.chain[5].hash = "00".repeat(32);
+        let err = verify(&relinked, tail, false, &[], &go).unwrap_err();
+        assert!(err.to_string().contains("chain broken at index 6"), "{err}");
+
+        let err = verify(&good, tail, false, &[(10, "beef")], &go).unwrap_err();
+        assert!(err.to_string().contains("checkpoint"), "{err}");
+
+        let mut weak = good.clone();
+        let last = weak.chain.chain.last_mut().unwrap();
+        while hash_meets_difficulty(&last.hash, 1) {
+            last.nonce += 1;
+            last.hash = Blockchain::calculate_hash(last);
+        }
+        weak.tip_hash = last.hash.clone();
+        let err = verify(&weak, tail, false, &[], &go).unwrap_err();
+        assert!(err.to_string().contains("PoW check failed"), "{err}");
+
+        // Below the tail, hashes alone don't do: a block must carry the
+        // work its difficulty asks for, and that difficulty the schedule's
+        let unmined = forge(&good, 5, |block| {
+            loop {
+                block.nonce += 1;
+                block.hash = Blockchain::calculate_hash(block);
+                if !hash_meets_difficulty(&block.hash, block.difficulty) {
+                    break;
+                }
+            }
+        });
+        let err = verify(&unmined, tail, false, &[], &go).unwrap_err();
+        assert!(
+            err.to_string().contains("PoW check failed at index 5"),
+            "{err}"
+        );
+        let free = forge(&good, 5, |block| {
+            block.difficulty = 0;
+            block.hash = Blockchain::calculate_hash(block);
+        });
+        let err = verify(&free, tail, false, &[], &go).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("block 5 has difficulty 0, the schedule requires 1"),
+            "{err}"
+        );
+
+        // Ctrl+C stops a verify before it passes judgement
+        let stopped = CancellationToken::new();
+        stopped.cancel();
+        let err = verify(&good, tail, true, &[], &stopped).unwrap_err();
+        assert!(err.downcast_ref::<Interrupted>().is_some(), "{err}");
+
+        assert!(decode(b"not a snapshot").is_err());
+    }
+}
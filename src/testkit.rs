@@ -0,0 +1,649 @@
+//! Deterministic fixture chains for tests, benchmarks and
+//! `owonero dev generate-chain`. A `ChainBuilder` seed fixes everything:
+//! wallet keys, who mines and pays whom, timestamps, nonces and signatures,
+//! so the same settings always give a byte-identical chain.
+
+use crate::blockchain::{Block, Blockchain, Transaction, hash_meets_difficulty};
+use crate::wallet::Wallet;
+use anyhow::{Result, bail};
+use sha3::Digest;
+use std::collections::HashMap;
+
+/// Settings for a generated chain.
+#[derive(Debug, Clone)]
+pub struct ChainBuilder {
+    seed: u64,
+    wallets: usize,
+    txs_per_block: usize,
+    difficulty: u32,
+    blocks: u64,
+    real_pow: bool,
+}
+
+/// A generated chain and the wallets that mine and transact on it.
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    pub chain: Blockchain,
+    pub wallets: Vec<Wallet>,
+}
+
+impl ChainBuilder {
+    /// 10 blocks by 4 wallets, 2 transfers a block, difficulty 1, no PoW.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            wallets: 4,
+            txs_per_block: 2,
+            difficulty: 1,
+            blocks: 10,
+            real_pow: false,
+        }
+    }
+
+    pub fn wallets(mut self, wallets: usize) -> Self {
+        self.wallets = wallets;
+        self
+    }
+
+    /// Transfers per block, as far as mined balances allow.
+    pub fn txs_per_block(mut self, txs: usize) -> Self {
+        self.txs_per_block = txs;
+        self
+    }
+
+    /// Difficulty recorded in the blocks, and mined to with `real_pow`.
+    pub fn difficulty(mut self, difficulty: u32) -> Self {
+        self.difficulty = difficulty;
+        self
+    }
+
+    /// Blocks after genesis.
+    pub fn blocks(mut self, blocks: u64) -> Self {
+        self.blocks = blocks;
+        self
+    }
+
+    /// Search nonces until each hash meets the difficulty, instead of
+    /// blocks that are only valid with PoW checks skipped.
+    pub fn real_pow(mut self, real_pow: bool) -> Self {
+        self.real_pow = real_pow;
+        self
+    }
+
+    pub fn build(&self) -> Result<Fixture> {
+        if self.wallets == 0 {
+            bail!("a chain needs at least one wallet");
+        }
+        let curve = p256::Curve::new();
+        let mut stream = SeedStream::new(self.seed);
+        let keys = (0..self.wallets)
+            .map(|i| p256::SigningKey::derive(&curve, self.seed, i as u64))
+            .collect::<Vec<_>>();
+        let wallets = keys
+            .iter()
+            .map(|key| Wallet::from_private_key(&hex::encode(key.pkcs8())))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut bc = Blockchain::new();
+        let mut balances: HashMap<usize, i64> = HashMap::new();
+        for index in 1..=self.blocks {
+            let last = bc.chain.last().expect("genesis");
+            let miner = stream.below(wallets.len());
+            let reward = bc.get_block_reward(index);
+            let mut transactions = vec![Transaction {
+                from: "coinbase".to_string(),
+                pub_key: String::new(),
+                to: wallets[miner].address.clone(),
+                amount: reward,
+                signature: String::new(),
+                expires_at_height: None,
+            }];
+            *balances.entry(miner).or_insert(0) += reward;
+
+            for _ in 0..self.txs_per_block {
+                // Wallet order, not map order, so the draw stays the same
+                let funded = (0..wallets.len())
+                    .filter(|w| balances.get(w).copied().unwrap_or(0) >= 2)
+                    .collect::<Vec<_>>();
+                if funded.is_empty() || wallets.len() < 2 {
+                    break;
+                }
+                let from = funded[stream.below(funded.len())];
+                let to = (from + 1 + stream.below(wallets.len() - 1)) % wallets.len();
+                let amount = 1 + stream.below((balances[&from] / 2) as usize) as i64;
+                let mut tx = Transaction {
+                    from: wallets[from].address.clone(),
+                    pub_key: wallets[from].pub_key.clone(),
+                    to: wallets[to].address.clone(),
+                    amount,
+                    signature: String::new(),
+                    expires_at_height: None,
+                };
+                tx.signature = hex::encode(keys[from].sign(&curve, &tx.signing_bytes()));
+                *balances.entry(from).or_insert(0) -= amount;
+                *balances.entry(to).or_insert(0) += amount;
+                transactions.push(tx);
+            }
+
+            let mut block = Block {
+                index,
+                timestamp: last.timestamp + chrono::Duration::seconds(bc.target_block_time),
+                transactions,
+                prev_hash: last.hash.clone(),
+                hash: String::new(),
+                nonce: 0,
+                difficulty: self.difficulty,
+            };
+            block.hash = Blockchain::calculate_hash(&block);
+            while self.real_pow && !hash_meets_difficulty(&block.hash, self.difficulty) {
+                block.nonce += 1;
+                block.hash = Blockchain::calculate_hash(&block);
+            }
+            bc.chain.push(block);
+        }
+        Ok(Fixture { chain: bc, wallets })
+    }
+}
+
+/// SHA3-256 of the seed and a counter, so the draws don't depend on which
+/// version of `rand` is linked.
+struct SeedStream {
+    seed: u64,
+    counter: u64,
+}
+
+impl SeedStream {
+    fn new(seed: u64) -> Self {
+        Self { seed, counter: 0 }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut hasher = sha3::Sha3_256::new();
+        hasher.update(b"owonero-testkit-stream");
+        hasher.update(self.seed.to_be_bytes());
+        hasher.update(self.counter.to_be_bytes());
+        self.counter += 1;
+        let digest = hasher.finalize();
+        u64::from_be_bytes(digest[..8].try_into().expect("8 bytes"))
+    }
+
+    /// Uniform enough in `0..n` for fixtures; `n` must be > 0.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Just enough P-256 to make keys from a seed and sign without a random
+/// nonce, which *ring* doesn't offer. Not constant time and the nonce is a
+/// plain hash of key and message: fixture keys only, never real wallets.
+/// *ring* still checks the results when it loads the keys and verifies
+/// the signatures.
+mod p256 {
+    type U256 = [u64; 4];
+
+    const P: U256 = [
+        0xFFFF_FFFF_FFFF_FFFF,
+        0x0000_0000_FFFF_FFFF,
+        0x0000_0000_0000_0000,
+        0xFFFF_FFFF_0000_0001,
+    ];
+    const N: U256 = [
+        0xF3B9_CAC2_FC63_2551,
+        0xBCE6_FAAD_A717_9E84,
+        0xFFFF_FFFF_FFFF_FFFF,
+        0xFFFF_FFFF_0000_0000,
+    ];
+    const GX: U256 = [
+        0xF4A1_3945_D898_C296,
+        0x7703_7D81_2DEB_33A0,
+        0xF8BC_E6E5_63A4_40F2,
+        0x6B17_D1F2_E12C_4247,
+    ];
+    const GY: U256 = [
+        0xCBB6_4068_37BF_51F5,
+        0x2BCE_3357_6B31_5ECE,
+        0x8EE7_EB4A_7C0F_9E16,
+        0x4FE3_42E2_FE1A_7F9B,
+    ];
+    const ZERO: U256 = [0; 4];
+    const ONE: U256 = [1, 0, 0, 0];
+
+    /// What *ring* writes around a P-256 key in PKCS#8: the header up to
+    /// the private key, then the tag of the public key that follows it.
+    const PKCS8_HEAD: [u8; 36] = [
+        0x30, 0x81, 0x87, 0x02, 0x01, 0x00, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d,
+        0x02, 0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x04, 0x6d, 0x30,
+        0x6b, 0x02, 0x01, 0x01, 0x04, 0x20,
+    ];
+    const PKCS8_MIDDLE: [u8; 5] = [0xa1, 0x44, 0x03, 0x42, 0x00];
+
+    fn from_be(bytes: &[u8]) -> U256 {
+        let mut out = ZERO;
+        for (i, chunk) in bytes.rchunks(8).take(4).enumerate() {
+            let mut word = [0u8; 8];
+            word[8 - chunk.len()..].copy_from_slice(chunk);
+            out[i] = u64::from_be_bytes(word);
+        }
+        out
+    }
+
+    fn to_be(a: &U256) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        for (i, word) in a.iter().rev().enumerate() {
+            out[i * 8..i * 8 + 8].copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    fn geq(a: &U256, b: &U256) -> bool {
+        for i in (0..4).rev() {
+            if a[i] != b[i] {
+                return a[i] > b[i];
+            }
+        }
+        true
+    }
+
+    fn sub(a: &U256, b: &U256) -> (U256, bool) {
+        let mut out = ZERO;
+        let mut borrow = false;
+        for i in 0..4 {
+            let (d, b1) = a[i].overflowing_sub(b[i]);
+            let (d, b2) = d.overflowing_sub(borrow as u64);
+            out[i] = d;
+            borrow = b1 || b2;
+        }
+        (out, borrow)
+    }
+
+    fn add(a: &U256, b: &U256) -> (U256, bool) {
+        let mut out = ZERO;
+        let mut carry = false;
+        for i in 0..4 {
+            let (s, c1) = a[i].overflowing_add(b[i]);
+            let (s, c2) = s.overflowing_add(carry as u64);
+            out[i] = s;
+            carry = c1 || c2;
+        }
+        (out, carry)
+    }
+
+    /// Arithmetic modulo an odd `m` above 2^255, in Montgomery form where
+    /// it multiplies.
+    struct Modulus {
+        m: U256,
+        /// -m^-1 mod 2^64.
+        inv: u64,
+        /// 2^512 mod m, to bring values into Montgomery form.
+        r2: U256,
+    }
+
+    impl Modulus {
+        fn new(m: U256) -> Self {
+            let mut inv = 1u64;
+            for _ in 0..6 {
+                inv = inv.wrapping_mul(2u64.wrapping_sub(m[0].wrapping_mul(inv)));
+            }
+            let mut modulus = Self {
+                m,
+                inv: inv.wrapping_neg(),
+                r2: ONE,
+            };
+            let mut r2 = ONE;
+            for _ in 0..512 {
+                r2 = modulus.add(&r2, &r2);
+            }
+            modulus.r2 = r2;
+            modulus
+        }
+
+        /// `a` below 2^256, reduced; fine since m > 2^255.
+        fn reduce(&self, a: &U256) -> U256 {
+            if geq(a, &self.m) {
+                sub(a, &self.m).0
+            } else {
+                *a
+            }
+        }
+
+        fn add(&self, a: &U256, b: &U256) -> U256 {
+            let (sum, carry) = add(a, b);
+            if carry || geq(&sum, &self.m) {
+                sub(&sum, &self.m).0
+            } else {
+                sum
+            }
+        }
+
+        fn sub(&self, a: &U256, b: &U256) -> U256 {
+            let (diff, borrow) = sub(a, b);
+            if borrow { add(&diff, &self.m).0 } else { diff }
+        }
+
+        /// a * b / 2^256 mod m.
+        fn mont_mul(&self, a: &U256, b: &U256) -> U256 {
+            let mut t = [0u64; 6];
+            for &bi in b {
+                let mut carry = 0u128;
+                for j in 0..4 {
+                    let v = t[j] as u128 + a[j] as u128 * bi as u128 + carry;
+                    t[j] = v as u64;
+                    carry = v >> 64;
+                }
+                let v = t[4] as u128 + carry;
+                t[4] = v as u64;
+                t[5] = (v >> 64) as u64;
+
+                let q = t[0].wrapping_mul(self.inv);
+                let mut carry = (t[0] as u128 + q as u128 * self.m[0] as u128) >> 64;
+                for j in 1..4 {
+                    let v = t[j] as u128 + q as u128 * self.m[j] as u128 + carry;
+                    t[j - 1] = v as u64;
+                    carry = v >> 64;
+                }
+                let v = t[4] as u128 + carry;
+                t[3] = v as u64;
+                t[4] = t[5] + (v >> 64) as u64;
+            }
+            let out = [t[0], t[1], t[2], t[3]];
+            if t[4] != 0 || geq(&out, &self.m) {
+                sub(&out, &self.m).0
+            } else {
+                out
+            }
+        }
+
+        fn enter_mont(&self, a: &U256) -> U256 {
+            self.mont_mul(&self.reduce(a), &self.r2)
+        }
+
+        fn leave_mont(&self, a: &U256) -> U256 {
+            self.mont_mul(a, &ONE)
+        }
+
+        /// a^-1 in Montgomery form, by Fermat: a^(m-2).
+        fn mont_inv(&self, a: &U256) -> U256 {
+            let exp = sub(&self.m, &[2, 0, 0, 0]).0;
+            let mut result = self.enter_mont(&ONE);
+            for i in (0..256).rev() {
+                result = self.mont_mul(&result, &result);
+                if (exp[i / 64] >> (i % 64)) & 1 == 1 {
+                    result = self.mont_mul(&result, a);
+                }
+            }
+            result
+        }
+
+        /// a * b mod m, for values not in Montgomery form.
+        fn mul(&self, a: &U256, b: &U256) -> U256 {
+            self.mont_mul(&self.mont_mul(&self.reduce(a), &self.reduce(b)), &self.r2)
+        }
+    }
+
+    /// Jacobian point with coordinates in Montgomery form; z = 0 is the
+    /// point at infinity.
+    #[derive(Clone, Copy)]
+    struct Point {
+        x: U256,
+        y: U256,
+        z: U256,
+    }
+
+    pub struct Curve {
+        p: Modulus,
+        n: Modulus,
+        g: Point,
+    }
+
+    impl Curve {
+        pub fn new() -> Self {
+            let p = Modulus::new(P);
+            let g = Point {
+                x: p.enter_mont(&GX),
+                y: p.enter_mont(&GY),
+                z: p.enter_mont(&ONE),
+            };
+            Self {
+                p,
+                n: Modulus::new(N),
+                g,
+            }
+        }
+
+        fn double(&self, a: &Point) -> Point {
+            let f = &self.p;
+            if a.z == ZERO {
+                return *a;
+            }
+            // dbl-2001-b, for a = -3
+            let delta = f.mont_mul(&a.z, &a.z);
+            let gamma = f.mont_mul(&a.y, &a.y);
+            let beta = f.mont_mul(&a.x, &gamma);
+            let t = f.mont_mul(&f.sub(&a.x, &delta), &f.add(&a.x, &delta));
+            let alpha = f.add(&f.add(&t, &t), &t);
+            let beta4 = f.add(&f.add(&beta, &beta), &f.add(&beta, &beta));
+            let beta8 = f.add(&beta4, &beta4);
+            let x = f.sub(&f.mont_mul(&alpha, &alpha), &beta8);
+            let yz = f.add(&a.y, &a.z);
+            let z = f.sub(&f.sub(&f.mont_mul(&yz, &yz), &gamma), &delta);
+            let gamma2 = f.mont_mul(&gamma, &gamma);
+            let gamma8 = {
+                let g2 = f.add(&gamma2, &gamma2);
+                let g4 = f.add(&g2, &g2);
+                f.add(&g4, &g4)
+            };
+            let y = f.sub(&f.mont_mul(&alpha, &f.sub(&beta4, &x)), &gamma8);
+            Point { x, y, z }
+        }
+
+        fn add(&self, a: &Point, b: &Point) -> Point {
+            let f = &self.p;
+            if a.z == ZERO {
+                return *b;
+            }
+            if b.z == ZERO {
+                return *a;
+            }
+            // add-2007-bl
+            let z1z1 = f.mont_mul(&a.z, &a.z);
+            let z2z2 = f.mont_mul(&b.z, &b.z);
+            let u1 = f.mont_mul(&a.x, &z2z2);
+            let u2 = f.mont_mul(&b.x, &z1z1);
+            let s1 = f.mont_mul(&f.mont_mul(&a.y, &b.z), &z2z2);
+            let s2 = f.mont_mul(&f.mont_mul(&b.y, &a.z), &z1z1);
+            let h = f.sub(&u2, &u1);
+            let r = f.sub(&s2, &s1);
+            if h == ZERO {
+                return if r == ZERO {
+                    self.double(a)
+                } else {
+                    Point {
+                        x: ZERO,
+                        y: ZERO,
+                        z: ZERO,
+                    }
+                };
+            }
+            let h2 = f.add(&h, &h);
+            let i = f.mont_mul(&h2, &h2);
+            let j = f.mont_mul(&h, &i);
+            let r = f.add(&r, &r);
+            let v = f.mont_mul(&u1, &i);
+            let x = f.sub(&f.sub(&f.mont_mul(&r, &r), &j), &f.add(&v, &v));
+            let s1j = f.mont_mul(&s1, &j);
+            let y = f.sub(&f.mont_mul(&r, &f.sub(&v, &x)), &f.add(&s1j, &s1j));
+            let zz = f.add(&a.z, &b.z);
+            let z = f.mont_mul(&f.sub(&f.sub(&f.mont_mul(&zz, &zz), &z1z1), &z2z2), &h);
+            Point { x, y, z }
+        }
+
+        fn mul_base(&self, k: &U256) -> Point {
+            let mut acc = Point {
+                x: ZERO,
+                y: ZERO,
+                z: ZERO,
+            };
+            for i in (0..256).rev() {
+                acc = self.double(&acc);
+                if (k[i / 64] >> (i % 64)) & 1 == 1 {
+                    acc = self.add(&acc, &self.g);
+                }
+            }
+            acc
+        }
+
+        /// Affine (x, y), not in Montgomery form.
+        fn affine(&self, a: &Point) -> (U256, U256) {
+            let f = &self.p;
+            let zinv = f.mont_inv(&a.z);
+            let zinv2 = f.mont_mul(&zinv, &zinv);
+            let x = f.mont_mul(&a.x, &zinv2);
+            let y = f.mont_mul(&a.y, &f.mont_mul(&zinv2, &zinv));
+            (f.leave_mont(&x), f.leave_mont(&y))
+        }
+    }
+
+    fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+        let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+        for part in parts {
+            ctx.update(part);
+        }
+        ctx.finish().as_ref().try_into().expect("32 bytes")
+    }
+
+    pub struct SigningKey {
+        d: U256,
+        public: [u8; 65],
+    }
+
+    impl SigningKey {
+        /// Key number `index` of `seed`.
+        pub fn derive(curve: &Curve, seed: u64, index: u64) -> Self {
+            let mut counter = 0u64;
+            let d = loop {
+                let digest = sha256(&[
+                    b"owonero-testkit-key",
+                    &seed.to_be_bytes(),
+                    &index.to_be_bytes(),
+                    &counter.to_be_bytes(),
+                ]);
+                let d = from_be(&digest);
+                if d != ZERO && !geq(&d, &N) {
+                    break d;
+                }
+                counter += 1;
+            };
+            let (x, y) = curve.affine(&curve.mul_base(&d));
+            let mut public = [0u8; 65];
+            public[0] = 0x04;
+            public[1..33].copy_from_slice(&to_be(&x));
+            public[33..].copy_from_slice(&to_be(&y));
+            Self { d, public }
+        }
+
+        /// The key as *ring* would have stored it.
+        pub fn pkcs8(&self) -> Vec<u8> {
+            let mut out = PKCS8_HEAD.to_vec();
+            out.extend(to_be(&self.d));
+            out.extend(PKCS8_MIDDLE);
+            out.extend(self.public);
+            out
+        }
+
+        /// ECDSA over SHA-256 as fixed-width r || s, with the nonce hashed
+        /// from the key and message.
+        pub fn sign(&self, curve: &Curve, message: &[u8]) -> [u8; 64] {
+            let n = &curve.n;
+            let digest = sha256(&[message]);
+            let e = n.reduce(&from_be(&digest));
+            let mut counter = 0u64;
+            loop {
+                let nonce = sha256(&[
+                    b"owonero-testkit-nonce",
+                    &to_be(&self.d),
+                    &digest,
+                    &counter.to_be_bytes(),
+                ]);
+                counter += 1;
+                let k = n.reduce(&from_be(&nonce));
+                if k == ZERO {
+                    continue;
+                }
+                let (x, _) = curve.affine(&curve.mul_base(&k));
+                let r = n.reduce(&x);
+                if r == ZERO {
+                    continue;
+                }
+                let k_inv = n.leave_mont(&n.mont_inv(&n.enter_mont(&k)));
+                let s = n.mul(&k_inv, &n.add(&e, &n.mul(&r, &self.d)));
+                if s == ZERO {
+                    continue;
+                }
+                let mut sig = [0u8; 64];
+                sig[..32].copy_from_slice(&to_be(&r));
+                sig[32..].copy_from_slice(&to_be(&s));
+                return sig;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_a_byte_identical_valid_chain() {
+        let builder = ChainBuilder::new(7).blocks(6).wallets(3).txs_per_block(3);
+        let fixture = builder.build().unwrap();
+        let json = serde_json::to_string(&fixture.chain).unwrap();
+        assert_eq!(
+            json,
+            serde_json::to_string(&builder.build().unwrap().chain).unwrap()
+        );
+        let other = ChainBuilder::new(8).blocks(6).wallets(3).txs_per_block(3);
+        assert_ne!(
+            json,
+            serde_json::to_string(&other.build().unwrap().chain).unwrap()
+        );
+
+        // Every block passes full validation, signatures included
+        let mut replay = Blockchain::new();
+        for block in &fixture.chain.chain[1..] {
+            assert!(replay.add_block_skip_pow(block.clone(), 1, true));
+        }
+        let transfers = fixture
+            .chain
+            .chain
+            .iter()
+            .flat_map(|b| &b.transactions)
+            .filter(|tx| tx.from != "coinbase" && tx.from != "genesis")
+            .count();
+        assert!(transfers >= 6, "only {} transfers", transfers);
+
+        // Keys sign like any other wallet's
+        let wallet = &fixture.wallets[0];
+        let tx = wallet.create_signed_transaction("04bob", 5).unwrap();
+        assert!(crate::blockchain::verify_transaction_signature(
+            &tx,
+            &wallet.pub_key
+        ));
+        assert_ne!(fixture.wallets[0].address, fixture.wallets[1].address);
+    }
+
+    #[test]
+    fn real_pow_blocks_meet_their_difficulty() {
+        let fixture = ChainBuilder::new(1)
+            .blocks(3)
+            .txs_per_block(1)
+            .difficulty(1)
+            .real_pow(true)
+            .build()
+            .unwrap();
+        let mut replay = Blockchain::new();
+        for block in &fixture.chain.chain[1..] {
+            assert_eq!(block.difficulty, 1);
+            assert!(replay.add_block(block.clone(), block.difficulty));
+        }
+    }
+}
@@ -1,5 +1,4 @@
 use anyhow::{Result, anyhow};
-use hex;
 use ring::rand::SystemRandom;
 use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
 use serde::{Deserialize, Serialize};
@@ -10,6 +9,15 @@ pub struct Wallet {
     pub pub_key: String,
     pub priv_key: String,
     pub node_address: Option<String>,
+    /// Height of the last block folded into `balance_milli`.
+    #[serde(default)]
+    pub last_scanned_height: Option<u64>,
+    /// Hash of the block at `last_scanned_height`, used to detect reorgs.
+    #[serde(default)]
+    pub last_scanned_hash: Option<String>,
+    /// Cached balance up to `last_scanned_height`, in internal units.
+    #[serde(default)]
+    pub balance_milli: i64,
 }
 
 impl Wallet {
@@ -34,14 +42,75 @@ impl Wallet {
             // zapis PKCS#8 jako hex (można też base64)
             priv_key: hex::encode(pkcs8_doc.as_ref()),
             node_address: None,
+            last_scanned_height: None,
+            last_scanned_hash: None,
+            balance_milli: 0,
         })
     }
 
-    pub fn get_balance(&self, blockchain: &crate::blockchain::Blockchain) -> i64 {
+    /// Rebuild a wallet from a backup of just its `priv_key` (PKCS#8, hex).
+    /// The public key and address are derived from the key itself.
+    pub fn from_private_key(priv_key_hex: &str) -> Result<Self> {
+        let pkcs8 =
+            hex::decode(priv_key_hex.trim()).map_err(|_| anyhow!("private key is not hex"))?;
+        let key_pair = EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &pkcs8,
+            &SystemRandom::new(),
+        )
+        .map_err(|_| anyhow!("not a P-256 PKCS#8 private key"))?;
+        let pub_key = hex::encode(key_pair.public_key().as_ref());
+        not_burn(Self {
+            address: pub_key.clone(),
+            pub_key,
+            priv_key: hex::encode(&pkcs8),
+            node_address: None,
+            last_scanned_height: None,
+            last_scanned_hash: None,
+            balance_milli: 0,
+        })
+    }
+
+    /// Bring the cached balance up to the tip of `blockchain`, scanning only
+    /// blocks above `last_scanned_height`. Falls back to a full scan when
+    /// `rescan` is set or the remembered block is no longer on the chain.
+    /// Returns the number of blocks scanned.
+    pub fn scan_balance(
+        &mut self,
+        blockchain: &crate::blockchain::Blockchain,
+        rescan: bool,
+    ) -> usize {
+        let cached = match (self.last_scanned_height, &self.last_scanned_hash) {
+            (Some(h), Some(hash)) if !rescan => blockchain
+                .chain
+                .get(h as usize)
+                .filter(|b| &b.hash == hash)
+                .map(|_| h as usize + 1),
+            _ => None,
+        };
+        let start = match cached {
+            Some(start) => start,
+            None => {
+                self.balance_milli = 0;
+                0
+            }
+        };
+
+        let new_blocks = &blockchain.chain[start.min(blockchain.chain.len())..];
+        self.balance_milli += self.balance_delta(new_blocks);
+        if let Some(tip) = blockchain.chain.last() {
+            self.last_scanned_height = Some(tip.index);
+            self.last_scanned_hash = Some(tip.hash.clone());
+        }
+        new_blocks.len()
+    }
+
+    /// Net effect of `blocks` on this wallet's balance.
+    fn balance_delta(&self, blocks: &[crate::blockchain::Block]) -> i64 {
         // Normalize address comparisons to be case-insensitive and trim whitespace.
         let my_addr = self.address.trim().to_lowercase();
         let mut balance = 0i64;
-        for block in &blockchain.chain {
+        for block in blocks {
             for tx in &block.transactions {
                 let tx_to = tx.to.trim().to_lowercase();
                 let tx_from = tx.from.trim().to_lowercase();
@@ -60,6 +129,17 @@ impl Wallet {
         &self,
         to: &str,
         amount: i64,
+    ) -> Result<crate::blockchain::Transaction> {
+        self.create_expiring_transaction(to, amount, None)
+    }
+
+    /// Like `create_signed_transaction`, but the transaction can't be mined
+    /// after block `expires_at_height`.
+    pub fn create_expiring_transaction(
+        &self,
+        to: &str,
+        amount: i64,
+        expires_at_height: Option<u64>,
     ) -> Result<crate::blockchain::Transaction> {
         let mut tx = crate::blockchain::Transaction {
             from: self.address.clone(),
@@ -67,46 +147,510 @@ impl Wallet {
             to: to.to_string(),
             amount,
             signature: String::new(),
+            expires_at_height,
         };
 
         crate::blockchain::sign_transaction(&mut tx, &self.priv_key)?;
         Ok(tx)
     }
+
+    /// Sign a transaction built elsewhere by `unsigned_transaction`. Refuses
+    /// transactions spending from another wallet or already carrying a
+    /// signature.
+    pub fn sign_unsigned(
+        &self,
+        tx: &crate::blockchain::Transaction,
+    ) -> Result<crate::blockchain::Transaction> {
+        if tx.from != self.address {
+            return Err(anyhow!(
+                "transaction spends from {} but this wallet is {}",
+                tx.from,
+                self.address
+            ));
+        }
+        if !tx.signature.is_empty() {
+            return Err(anyhow!("transaction is already signed"));
+        }
+        let mut signed = tx.clone();
+        signed.pub_key = self.pub_key.clone();
+        crate::blockchain::sign_transaction(&mut signed, &self.priv_key)?;
+        Ok(signed)
+    }
 }
 
-pub fn load_or_create_wallet(path: &str) -> Result<Wallet> {
-    // Expand ~ to home directory if present
-    let expanded_path = if path.starts_with("~") {
-        if let Some(home) = std::env::var("HOME").ok() {
-            let mut p = home;
-            p.push_str(&path[1..]);
-            p
-        } else {
-            path.to_string()
+/// Prefix of every reserve-proof signature. Transaction signatures cover
+/// `from|to|amount[|expires_at_height]` and no address starts with a NUL
+/// byte, so a proof can't be replayed as a transaction signature or the
+/// other way round.
+const RESERVE_PROOF_DOMAIN: &[u8] = b"\0owonero-reserve-proof-v1\0";
+
+/// Signed claim that `address` held `balance` after block `height`, bound
+/// to a challenge `message` chosen by whoever asked for the proof.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReserveProof {
+    pub address: String,
+    pub pub_key: String,
+    pub message: String,
+    pub height: u64,
+    pub balance: i64,
+    pub signature: String,
+}
+
+impl ReserveProof {
+    fn signing_bytes(message: &str, height: u64, balance: i64) -> Vec<u8> {
+        let mut bytes = RESERVE_PROOF_DOMAIN.to_vec();
+        bytes.extend((message.len() as u64).to_be_bytes());
+        bytes.extend(message.as_bytes());
+        bytes.extend(height.to_be_bytes());
+        bytes.extend(balance.to_be_bytes());
+        bytes
+    }
+
+    /// Whether the signature is valid and was made by the key behind `address`.
+    pub fn verify_signature(&self) -> bool {
+        self.pub_key.eq_ignore_ascii_case(&self.address)
+            && crate::blockchain::verify_message(
+                &self.pub_key,
+                &Self::signing_bytes(&self.message, self.height, self.balance),
+                &self.signature,
+            )
+    }
+}
+
+impl Wallet {
+    pub fn prove_reserve(&self, message: &str, height: u64, balance: i64) -> Result<ReserveProof> {
+        let signature = crate::blockchain::sign_message(
+            &self.priv_key,
+            &ReserveProof::signing_bytes(message, height, balance),
+        )?;
+        Ok(ReserveProof {
+            address: self.address.clone(),
+            pub_key: self.pub_key.clone(),
+            message: message.to_string(),
+            height,
+            balance,
+            signature,
+        })
+    }
+}
+
+/// Where coins go to be destroyed: `04` and 64 zero bytes has the form of
+/// an address but is not a point on P-256, so no private key can sign for
+/// it. Consensus treats payments to it like any other.
+pub const BURN_ADDRESS: &str = "0400000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000";
+
+pub fn is_burn_address(address: &str) -> bool {
+    address.trim().eq_ignore_ascii_case(BURN_ADDRESS)
+}
+
+/// Refuse a wallet that claims the burn address as its own.
+fn not_burn(wallet: Wallet) -> Result<Wallet> {
+    if is_burn_address(&wallet.address) {
+        return Err(anyhow!("the burn address cannot be a wallet"));
+    }
+    Ok(wallet)
+}
+
+/// Whether `address` looks like a wallet address: an uncompressed P-256
+/// public key, hex encoded.
+pub fn is_valid_address(address: &str) -> bool {
+    address.len() == 130
+        && address.starts_with("04")
+        && address.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Build a transaction for cold signing. Addresses are public keys, so the
+/// sender address is all the signer needs besides `to` and `amount`.
+pub fn unsigned_transaction(from: &str, to: &str, amount: i64) -> crate::blockchain::Transaction {
+    crate::blockchain::Transaction {
+        from: from.to_string(),
+        pub_key: from.to_string(),
+        to: to.to_string(),
+        amount,
+        signature: String::new(),
+        expires_at_height: None,
+    }
+}
+
+/// `format` tag of files written by `send --offline-create`.
+pub const UNSIGNED_TX_FORMAT: &str = "owonero-unsigned-tx";
+/// `format` tag of files written by `wallet sign-tx`.
+pub const SIGNED_TX_FORMAT: &str = "owonero-signed-tx";
+const TX_FILE_VERSION: u32 = 1;
+
+/// On-disk envelope passed between the online and the offline machine.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TxFile {
+    pub format: String,
+    pub version: u32,
+    pub tx: crate::blockchain::Transaction,
+}
+
+pub fn write_tx_file(
+    path: &std::path::Path,
+    format: &str,
+    tx: &crate::blockchain::Transaction,
+) -> Result<()> {
+    let file = TxFile {
+        format: format.to_string(),
+        version: TX_FILE_VERSION,
+        tx: tx.clone(),
+    };
+    std::fs::write(path, serde_json::to_string_pretty(&file)?)?;
+    Ok(())
+}
+
+/// Read a transaction file, checking it has the expected `format` tag so an
+/// unsigned file can't be broadcast or a signed one signed twice.
+pub fn read_tx_file(
+    path: &std::path::Path,
+    format: &str,
+) -> Result<crate::blockchain::Transaction> {
+    let data = std::fs::read_to_string(path)?;
+    let file: TxFile = serde_json::from_str(&data)?;
+    if file.format != format {
+        return Err(anyhow!(
+            "{} is a {} file, expected {}",
+            path.display(),
+            file.format,
+            format
+        ));
+    }
+    if file.version != TX_FILE_VERSION {
+        return Err(anyhow!(
+            "{} has unsupported version {}",
+            path.display(),
+            file.version
+        ));
+    }
+    Ok(file.tx)
+}
+
+/// Expand a leading ~ to the home directory.
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}{}", home, rest),
+        _ => path.to_string(),
+    }
+}
+
+/// Write the wallet back to `path`, e.g. after updating the balance cache.
+pub fn save_wallet(path: &str, wallet: &Wallet) -> Result<()> {
+    write_wallet_file(std::path::Path::new(&expand_home(path)), wallet)
+}
+
+/// Through a temporary file: a wallet cut off mid-write loses its key.
+fn write_wallet_file(path: &std::path::Path, wallet: &Wallet) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(wallet)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// What to run when there is no usable wallet. Nothing creates one
+/// behind the user's back: a key they never backed up is a key they lose.
+pub const NO_WALLET_HINT: &str =
+    "create one with `owonero wallet new` or restore a backup with `owonero wallet restore`";
+
+/// Load an existing wallet. Never creates one; the error says what is
+/// wrong with the file and how to get a wallet.
+pub fn load_wallet_file(path: &str) -> Result<Wallet> {
+    let expanded = expand_home(path);
+    let data = std::fs::read_to_string(&expanded).map_err(|e| read_error(&expanded, &e))?;
+    let wallet: Wallet = serde_json::from_str(&data).map_err(|e| {
+        anyhow!(
+            "{} is not a valid wallet file ({}). It was not replaced; restore a \
+             backup with `owonero wallet restore --force` or start over with \
+             `owonero wallet new --force`, which keep a copy of it",
+            expanded,
+            e
+        )
+    })?;
+    not_burn(wallet)
+}
+
+fn read_error(path: &str, err: &std::io::Error) -> anyhow::Error {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => anyhow!("no wallet at {}; {}", path, NO_WALLET_HINT),
+        std::io::ErrorKind::PermissionDenied => anyhow!(
+            "cannot read wallet {}: permission denied. Check the file's owner and \
+             mode; it was not replaced",
+            path
+        ),
+        _ => anyhow!("cannot read wallet {}: {}", path, err),
+    }
+}
+
+/// Write `wallet` to `path` as a new wallet file. An existing file is only
+/// replaced with `force`, and then first copied to
+/// `<path>.<UTC time>.bak`, whose path is returned.
+pub fn create_wallet_file(
+    path: &str,
+    wallet: &Wallet,
+    force: bool,
+) -> Result<Option<std::path::PathBuf>> {
+    let expanded = expand_home(path);
+    let p = std::path::Path::new(&expanded);
+    let backup = if p.exists() {
+        if !force {
+            return Err(anyhow!(
+                "{} already exists; pass --force to replace it (a timestamped copy is kept)",
+                expanded
+            ));
         }
+        let stem = format!(
+            "{}.{}",
+            expanded,
+            chrono::Utc::now().format("%Y%m%dT%H%M%SZ")
+        );
+        let backup =
+            backup_copy(p, &stem).map_err(|e| anyhow!("failed to back up {}: {}", expanded, e))?;
+        Some(backup)
     } else {
-        path.to_string()
+        None
     };
-    let p = std::path::Path::new(&expanded_path);
-    if p.exists() {
-        let data = std::fs::read_to_string(&expanded_path)?;
-        let wallet: Wallet = serde_json::from_str(&data)?;
-        Ok(wallet)
-    } else {
-        // Ensure parent directories exist for the target path.
-        if let Some(parent) = p.parent() {
-            if !parent.as_os_str().is_empty() {
-                let _ = std::fs::create_dir_all(parent);
+    if let Some(parent) = p.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_wallet_file(p, wallet)?;
+    Ok(backup)
+}
+
+/// Copy `path` to `<stem>.bak`, or `<stem>-2.bak`, `<stem>-3.bak` and so
+/// on when that is taken: two replacements within a second must not
+/// overwrite the first one's backup.
+fn backup_copy(path: &std::path::Path, stem: &str) -> std::io::Result<std::path::PathBuf> {
+    let mut n = 1;
+    loop {
+        let backup = std::path::PathBuf::from(if n == 1 {
+            format!("{}.bak", stem)
+        } else {
+            format!("{}-{}.bak", stem, n)
+        });
+        // Claim the name first; only then is copying over it safe
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup)
+        {
+            Ok(_) => {
+                std::fs::copy(path, &backup)?;
+                return Ok(backup);
             }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(e),
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{Block, Blockchain, Transaction};
+
+    fn append(bc: &mut Blockchain, to: &str, amount: i64, nonce: u32) {
+        let last = bc.chain.last().unwrap();
+        let mut block = Block {
+            index: last.index + 1,
+            timestamp: chrono::Utc::now(),
+            transactions: vec![Transaction {
+                from: "coinbase".to_string(),
+                pub_key: String::new(),
+                to: to.to_string(),
+                amount,
+                signature: String::new(),
+                expires_at_height: None,
+            }],
+            prev_hash: last.hash.clone(),
+            hash: String::new(),
+            nonce,
+            difficulty: 1,
+        };
+        block.hash = Blockchain::calculate_hash(&block);
+        assert!(bc.add_block_skip_pow(block, 1, true));
+    }
+
+    #[test]
+    fn reserve_proof_detects_tampering() {
+        let wallet = Wallet::new().unwrap();
+        let proof = wallet
+            .prove_reserve("exchange-challenge-42", 10, 5000)
+            .unwrap();
+        assert!(proof.verify_signature());
+
+        let mut inflated = proof.clone();
+        inflated.balance = 50_000;
+        assert!(!inflated.verify_signature());
+
+        // Someone else's key signing a claim about this address
+        let other = Wallet::new().unwrap();
+        let mut forged = other
+            .prove_reserve("exchange-challenge-42", 10, 5000)
+            .unwrap();
+        forged.address = wallet.address.clone();
+        assert!(!forged.verify_signature());
+        forged.pub_key = wallet.pub_key.clone();
+        assert!(!forged.verify_signature());
+
+        // A proof signature is not a transaction signature
+        let tx = Transaction {
+            from: wallet.address.clone(),
+            pub_key: wallet.pub_key.clone(),
+            to: "exchange-challenge-42".to_string(),
+            amount: 5000,
+            signature: proof.signature.clone(),
+            expires_at_height: None,
+        };
+        assert!(!crate::blockchain::verify_transaction_signature(
+            &tx,
+            &tx.pub_key
+        ));
+    }
+
+    #[test]
+    fn balance_cache_hit_and_incremental_scan() {
+        let mut wallet = Wallet::new().unwrap();
+        let mut bc = Blockchain::new();
+        append(&mut bc, &wallet.address, 500, 0);
+        append(&mut bc, "someone", 500, 0);
+
+        assert_eq!(wallet.scan_balance(&bc, false), 3);
+        assert_eq!(wallet.balance_milli, 500);
+
+        // Nothing new: cache hit, no blocks scanned
+        assert_eq!(wallet.scan_balance(&bc, false), 0);
+        assert_eq!(wallet.balance_milli, 500);
+
+        append(&mut bc, &wallet.address, 250, 0);
+        assert_eq!(wallet.scan_balance(&bc, false), 1);
+        assert_eq!(wallet.balance_milli, 750);
+        assert_eq!(wallet.balance_milli, wallet.balance_delta(&bc.chain));
+
+        assert_eq!(wallet.scan_balance(&bc, true), 4);
+        assert_eq!(wallet.balance_milli, 750);
+    }
+
+    #[test]
+    fn balance_cache_invalidated_by_reorg() {
+        let mut wallet = Wallet::new().unwrap();
+        let mut bc = Blockchain::new();
+        append(&mut bc, &wallet.address, 500, 0);
+        append(&mut bc, &wallet.address, 500, 0);
+        wallet.scan_balance(&bc, false);
+        assert_eq!(wallet.balance_milli, 1000);
 
-        let mut wallet = Wallet::new()?;
-        if let Ok(cfg) = crate::config::load_config() {
-            wallet.node_address = Some(cfg.node_address);
+        // Replace the tip with a competing block that pays someone else
+        bc.chain.pop();
+        append(&mut bc, "someone", 500, 1);
+        append(&mut bc, "someone", 500, 1);
+        assert_eq!(wallet.scan_balance(&bc, false), 4);
+        assert_eq!(wallet.balance_milli, 500);
+    }
+
+    #[test]
+    fn missing_and_corrupted_wallets_are_errors_not_new_wallets() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("wallets");
+        let path = dir.join("wallet.json").to_string_lossy().into_owned();
+
+        let missing = load_wallet_file(&path).unwrap_err().to_string();
+        assert!(missing.starts_with("no wallet at"), "{}", missing);
+        assert!(missing.contains("owonero wallet new"), "{}", missing);
+        assert!(!dir.exists());
+
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&path, r#"{"address": "04ab", "pub_"#).unwrap();
+        let corrupted = load_wallet_file(&path).unwrap_err().to_string();
+        assert!(
+            corrupted.contains("is not a valid wallet file"),
+            "{}",
+            corrupted
+        );
+        assert!(corrupted.contains("--force"), "{}", corrupted);
+        // Left for the user to recover
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            r#"{"address": "04ab", "pub_"#
+        );
+    }
+
+    #[test]
+    fn unreadable_wallets_say_so() {
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let message = read_error("/srv/wallet.json", &denied).to_string();
+        assert!(message.contains("permission denied"), "{}", message);
+        assert!(message.contains("not replaced"), "{}", message);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let tmp = tempfile::tempdir().unwrap();
+            let path = tmp.path().join("wallet.json");
+            save_wallet(&path.to_string_lossy(), &Wallet::new().unwrap()).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o000)).unwrap();
+            // Root reads the file anyway
+            if std::fs::read(&path).is_err() {
+                let err = load_wallet_file(&path.to_string_lossy()).unwrap_err();
+                assert!(err.to_string().contains("permission denied"), "{}", err);
+            }
         }
+    }
+
+    #[test]
+    fn new_wallets_replace_old_ones_only_by_force_and_keep_a_copy() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("nested").join("wallet.json");
+        let path_str = path.to_string_lossy().into_owned();
+        let first = Wallet::new().unwrap();
+        assert_eq!(create_wallet_file(&path_str, &first, false).unwrap(), None);
+        assert_eq!(load_wallet_file(&path_str).unwrap().address, first.address);
+
+        let second = Wallet::new().unwrap();
+        let refused = create_wallet_file(&path_str, &second, false).unwrap_err();
+        assert!(
+            refused.to_string().contains("already exists"),
+            "{}",
+            refused
+        );
+        assert_eq!(load_wallet_file(&path_str).unwrap().address, first.address);
+
+        let backup = create_wallet_file(&path_str, &second, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(load_wallet_file(&path_str).unwrap().address, second.address);
+        let name = backup.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(
+            name.starts_with("wallet.json.") && name.ends_with("Z.bak"),
+            "{}",
+            name
+        );
+        assert_eq!(
+            load_wallet_file(&backup.to_string_lossy())
+                .unwrap()
+                .priv_key,
+            first.priv_key
+        );
+    }
+
+    #[test]
+    fn backups_in_the_same_second_do_not_overwrite_each_other() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("wallet.json");
+        let stem = format!("{}.20260101T000000Z", path.display());
+        std::fs::write(&path, "first").unwrap();
+        let first = backup_copy(&path, &stem).unwrap();
+        std::fs::write(&path, "second").unwrap();
+        let second = backup_copy(&path, &stem).unwrap();
 
-        let data = serde_json::to_string_pretty(&wallet)?;
-        std::fs::write(&expanded_path, data)?;
-        Ok(wallet)
+        assert_ne!(first, second);
+        assert!(
+            second.to_string_lossy().ends_with("Z-2.bak"),
+            "{:?}",
+            second
+        );
+        assert_eq!(std::fs::read_to_string(&first).unwrap(), "first");
+        assert_eq!(std::fs::read_to_string(&second).unwrap(), "second");
     }
 }
@@ -0,0 +1,280 @@
+//! Per-wallet mining bookkeeping on the daemon: hashrates miners report
+//! with `updatestats` and shares they submit with `submitshare`. Clients
+//! name the wallet, so the map is bounded: only well-formed addresses are
+//! tracked, the least recently seen wallet gives way beyond
+//! `MAX_TRACKED_WALLETS`, and a claimed hashrate far above what the
+//! wallet's shares show is refused. Share counts are what pool payouts
+//! are based on, so they are saved to `shares.json` and survive restarts;
+//! hashrates are only kept for `HASHRATE_STALE_SECS`.
+
+use crate::protocol::HASHRATE_STALE_SECS;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+
+/// Most wallets tracked at once.
+pub const MAX_TRACKED_WALLETS: usize = 10_000;
+/// Hashrate (H/s) a wallet may claim before its shares say otherwise.
+const UNPROVEN_HASHRATE: f64 = 10_000.0;
+/// How far a claimed hashrate may exceed the one its shares show.
+const HASHRATE_SLACK: f64 = 10.0;
+/// Shares are averaged over at least this long, so one early share can't
+/// vouch for a huge hashrate.
+const MIN_SHARE_WINDOW_SECS: u64 = 60;
+/// Share hashes remembered to refuse resubmissions.
+const RECENT_SHARES: usize = 4096;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct WalletEntry {
+    /// Last reported H/s and when; never saved.
+    #[serde(skip)]
+    hashrate: Option<(f64, u64)>,
+    shares: u64,
+    /// Hashes the shares took on average, summed.
+    share_work: f64,
+    first_share_at: u64,
+    last_seen: u64,
+}
+
+impl WalletEntry {
+    /// The most H/s this wallet may claim at `now`.
+    fn plausible_hashrate(&self, now: u64) -> f64 {
+        let window = now
+            .saturating_sub(self.first_share_at)
+            .max(MIN_SHARE_WINDOW_SECS);
+        let shown = if self.shares == 0 {
+            0.0
+        } else {
+            self.share_work / window as f64
+        };
+        (shown * HASHRATE_SLACK).max(UNPROVEN_HASHRATE)
+    }
+}
+
+/// Sizes for `getinfo` and the stats server's `/metrics`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct WalletStatsSizes {
+    pub wallets: usize,
+    pub hashrates: usize,
+    pub share_wallets: usize,
+}
+
+#[derive(Debug)]
+pub struct WalletStats {
+    entries: HashMap<String, WalletEntry>,
+    recent_shares: VecDeque<String>,
+    max: usize,
+    /// Shares changed since the last save.
+    dirty: bool,
+}
+
+impl Default for WalletStats {
+    fn default() -> Self {
+        Self::new(MAX_TRACKED_WALLETS)
+    }
+}
+
+impl WalletStats {
+    pub fn new(max: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recent_shares: VecDeque::new(),
+            max: max.max(1),
+            dirty: false,
+        }
+    }
+
+    /// Share counts saved by `save`; empty when the file doesn't exist yet.
+    pub fn load(path: &Path, max: usize) -> Result<Self> {
+        let mut stats = Self::new(max);
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                stats.entries = serde_json::from_str(&text)
+                    .with_context(|| format!("{} is not a share file", path.display()))?;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+        Ok(stats)
+    }
+
+    /// Write every wallet with shares to `path`.
+    pub fn save(&mut self, path: &Path) -> Result<()> {
+        let shares: HashMap<&String, &WalletEntry> =
+            self.entries.iter().filter(|(_, e)| e.shares > 0).collect();
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_string(&shares)?)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// The entry for `wallet`, made room for if it is new.
+    fn entry(&mut self, wallet: &str, now: u64) -> Result<&mut WalletEntry, String> {
+        if !crate::wallet::is_valid_address(wallet) {
+            return Err("wallet is not a valid address".to_string());
+        }
+        let key = wallet.to_lowercase();
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.max {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_seen)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest
+                && self.entries.remove(&oldest).is_some_and(|e| e.shares > 0)
+            {
+                self.dirty = true;
+            }
+        }
+        let entry = self.entries.entry(key).or_default();
+        entry.last_seen = now;
+        Ok(entry)
+    }
+
+    /// Credit `wallet` with a share worth `work` hashes. Returns its share
+    /// count, or why the share was refused.
+    pub fn record_share(
+        &mut self,
+        wallet: &str,
+        hash: &str,
+        work: f64,
+        now: u64,
+    ) -> Result<u64, String> {
+        if self.recent_shares.iter().any(|h| h == hash) {
+            return Err("duplicate share".to_string());
+        }
+        let entry = self.entry(wallet, now)?;
+        if entry.shares == 0 {
+            entry.first_share_at = now;
+        }
+        entry.shares += 1;
+        entry.share_work += work;
+        let shares = entry.shares;
+        self.recent_shares.push_back(hash.to_string());
+        if self.recent_shares.len() > RECENT_SHARES {
+            self.recent_shares.pop_front();
+        }
+        self.dirty = true;
+        Ok(shares)
+    }
+
+    /// Store `wallet`'s reported hashrate unless its shares make it
+    /// implausible.
+    pub fn report_hashrate(&mut self, wallet: &str, hashrate: f64, now: u64) -> Result<(), String> {
+        if !hashrate.is_finite() || hashrate < 0.0 {
+            return Err("hashrate must be a non-negative number".to_string());
+        }
+        let entry = self.entry(wallet, now)?;
+        let plausible = entry.plausible_hashrate(now);
+        if hashrate > plausible {
+            return Err(format!(
+                "implausible hashrate {:.0} H/s: shares support at most {:.0}",
+                hashrate, plausible
+            ));
+        }
+        entry.hashrate = Some((hashrate, now));
+        Ok(())
+    }
+
+    /// Forget stale hashrates, and wallets left with nothing to track.
+    pub fn sweep(&mut self, now: u64) {
+        self.entries.retain(|_, e| {
+            if e.hashrate
+                .is_some_and(|(_, at)| now.saturating_sub(at) > HASHRATE_STALE_SECS)
+            {
+                e.hashrate = None;
+            }
+            e.hashrate.is_some() || e.shares > 0
+        });
+    }
+
+    pub fn sizes(&self) -> WalletStatsSizes {
+        WalletStatsSizes {
+            wallets: self.entries.len(),
+            hashrates: self
+                .entries
+                .values()
+                .filter(|e| e.hashrate.is_some())
+                .count(),
+            share_wallets: self.entries.values().filter(|e| e.shares > 0).count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wallet(n: u8) -> String {
+        format!("04{}", format!("{:02x}", n).repeat(64))
+    }
+
+    #[test]
+    fn tracked_wallets_are_bounded_checked_and_persisted() {
+        let mut stats = WalletStats::new(3);
+
+        // Made-up wallet strings are refused outright
+        assert!(stats.report_hashrate("not-a-wallet", 10.0, 0).is_err());
+        assert!(stats.record_share("04zz", "h0", 1.0, 0).is_err());
+
+        // The least recently seen wallet makes room for a new one
+        for n in 1..=3 {
+            stats.report_hashrate(&wallet(n), 100.0, n as u64).unwrap();
+        }
+        stats.report_hashrate(&wallet(1), 100.0, 10).unwrap();
+        stats.report_hashrate(&wallet(4), 100.0, 11).unwrap();
+        assert_eq!(stats.sizes().wallets, 3);
+        assert!(!stats.entries.contains_key(&wallet(2)));
+        assert!(stats.entries.contains_key(&wallet(1)));
+
+        // No shares: modest claims only. Shares raise the ceiling
+        let miner = wallet(9);
+        assert!(stats.report_hashrate(&miner, 50_000.0, 100).is_err());
+        for i in 0..60 {
+            stats
+                .record_share(&miner, &format!("share{}", i), 65_536.0, 100 + i)
+                .unwrap();
+        }
+        assert_eq!(
+            stats.record_share(&miner, "share3", 65_536.0, 200),
+            Err("duplicate share".to_string())
+        );
+        // 60 shares of 65536 hashes over a minute show ~65k H/s
+        stats.report_hashrate(&miner, 50_000.0, 160).unwrap();
+        let err = stats.report_hashrate(&miner, 5e6, 160).unwrap_err();
+        assert!(err.starts_with("implausible hashrate"));
+
+        // Stale hashrates go; wallets with shares stay
+        stats.sweep(160 + HASHRATE_STALE_SECS + 1);
+        assert_eq!(
+            stats.sizes(),
+            WalletStatsSizes {
+                wallets: 1,
+                hashrates: 0,
+                share_wallets: 1
+            }
+        );
+
+        // Shares survive a restart
+        assert!(stats.is_dirty());
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("shares.json");
+        stats.save(&path).unwrap();
+        assert!(!stats.is_dirty());
+        let reloaded = WalletStats::load(&path, 3).unwrap();
+        assert_eq!(reloaded.entries[&miner].shares, 60);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            WalletStats::load(&path, 3).unwrap().sizes().share_wallets,
+            0
+        );
+    }
+}
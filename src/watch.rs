@@ -0,0 +1,337 @@
+//! `owonero watch` and `owonero tail`: follow a node's event stream,
+//! reconnecting whenever it dies, and print a line for every payment to or
+//! from one address, or for every new block and transaction. `tail` is the
+//! smallest complete `subscribe` client.
+
+use crate::blockchain::{Blockchain, Transaction};
+use crate::daemon::NodeEvent;
+use crate::ws_client::WsClient;
+use anyhow::{anyhow, bail};
+use colored::Colorize;
+use serde::Serialize;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Heartbeats that may go missing before the connection is considered dead.
+const MISSED_HEARTBEATS: u64 = 2;
+
+/// One payment touching the watched address.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notice {
+    /// "received" or "sent".
+    pub direction: &'static str,
+    /// "confirmed" or "pending".
+    pub status: &'static str,
+    pub amount: i64,
+    pub counterparty: String,
+    /// Block height, for confirmed payments.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u64>,
+    pub txid: String,
+}
+
+impl std::fmt::Display for Notice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let amount = Blockchain::format_owe(self.amount);
+        match (self.direction, self.height) {
+            ("received", Some(h)) => write!(f, "Received {} in block {}", amount, h),
+            ("received", None) => write!(f, "Incoming {} (pending, tx {})", amount, self.txid),
+            (_, Some(h)) => write!(f, "Sent {} in block {}", amount, h),
+            (_, None) => write!(f, "Sending {} (pending, tx {})", amount, self.txid),
+        }
+    }
+}
+
+fn notice(tx: &Transaction, txid: String, address: &str, height: Option<u64>) -> Option<Notice> {
+    let me = address.trim().to_lowercase();
+    let (direction, counterparty) = if tx.to.trim().to_lowercase() == me {
+        ("received", &tx.from)
+    } else if tx.from.trim().to_lowercase() == me {
+        ("sent", &tx.to)
+    } else {
+        return None;
+    };
+    Some(Notice {
+        direction,
+        status: if height.is_some() {
+            "confirmed"
+        } else {
+            "pending"
+        },
+        amount: tx.amount,
+        counterparty: counterparty.clone(),
+        height,
+        txid,
+    })
+}
+
+/// Payments to or from `address` in one event. Filtering happens here, on
+/// the client: the node streams every event to every subscriber.
+pub fn notices(event: &NodeEvent, address: &str) -> Vec<Notice> {
+    match event {
+        NodeEvent::NewBlock { block } => block
+            .transactions
+            .iter()
+            .filter_map(|tx| notice(tx, tx.txid(), address, Some(block.index)))
+            .collect(),
+        NodeEvent::NewTx { txid, tx } => notice(tx, txid.clone(), address, None)
+            .into_iter()
+            .collect(),
+        NodeEvent::SyncProgress { .. } | NodeEvent::Reorg(_) => Vec::new(),
+    }
+}
+
+/// Short form of a hash or address for one-line output.
+fn prefix(s: &str) -> &str {
+    &s[..s.len().min(8)]
+}
+
+/// One line for `owonero tail`: height, hash prefix, transaction count and
+/// reward recipient for blocks; sender, recipient and amount for
+/// transactions.
+pub fn tail_line(event: &NodeEvent) -> String {
+    match event {
+        NodeEvent::NewBlock { block } => {
+            let coinbase = block.transactions.iter().find(|t| t.from == "coinbase");
+            format!(
+                "block {} {} {} tx(s), reward {} to {}",
+                block.index,
+                prefix(&block.hash),
+                block.transactions.len(),
+                Blockchain::format_owe(coinbase.map_or(0, |t| t.amount)),
+                coinbase.map_or("nobody", |t| prefix(&t.to))
+            )
+        }
+        NodeEvent::NewTx { txid, tx } => format!(
+            "tx {} {} -> {} {}",
+            prefix(txid),
+            prefix(&tx.from),
+            prefix(&tx.to),
+            Blockchain::format_owe(tx.amount)
+        ),
+        NodeEvent::SyncProgress { peer, progress } => {
+            format!("sync from {}: {}", peer, progress.line())
+        }
+        NodeEvent::Reorg(r) if r.refused => format!(
+            "reorg REFUSED: {} block(s) deep to {}'s tip {} at {}",
+            r.depth,
+            r.peer,
+            prefix(&r.new_tip),
+            r.new_height
+        ),
+        NodeEvent::Reorg(r) => format!(
+            "reorg {} block(s) deep to {}'s tip {} at {}, dropped {}",
+            r.depth,
+            r.peer,
+            prefix(&r.new_tip),
+            r.new_height,
+            r.orphaned
+                .iter()
+                .map(|h| prefix(h))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    }
+}
+
+/// `tail_line` as JSON for `owonero tail --json`, with full hashes and
+/// addresses. Blocks are summarized rather than sent whole.
+pub fn tail_json(event: &NodeEvent) -> serde_json::Value {
+    match event {
+        NodeEvent::NewBlock { block } => {
+            let coinbase = block.transactions.iter().find(|t| t.from == "coinbase");
+            serde_json::json!({
+                "event": "newblock",
+                "height": block.index,
+                "hash": block.hash,
+                "txs": block.transactions.len(),
+                "reward": coinbase.map(|t| t.amount),
+                "miner": coinbase.map(|t| &t.to),
+            })
+        }
+        NodeEvent::NewTx { txid, tx } => serde_json::json!({
+            "event": "newtx",
+            "txid": txid,
+            "from": tx.from,
+            "to": tx.to,
+            "amount": tx.amount,
+        }),
+        other => serde_json::to_value(other).unwrap_or_default(),
+    }
+}
+
+/// Follow the first reachable node in `nodes` forever, moving on to the
+/// next one whenever the connection drops or heartbeats stop. `what` says
+/// what is being followed; `on_event` gets every event.
+pub async fn follow(
+    nodes: &[String],
+    what: &str,
+    mut on_event: impl FnMut(&NodeEvent) -> anyhow::Result<()>,
+) -> ! {
+    let mut backoff = Duration::from_secs(1);
+    for node in nodes.iter().cycle() {
+        match follow_node(node, what, &mut on_event, &mut backoff).await {
+            Ok(()) => eprintln!("{}", format!("{} closed the connection", node).yellow()),
+            Err(e) => eprintln!("{}", format!("Lost {}: {}", node, e).yellow()),
+        }
+        eprintln!("Reconnecting in {}s...", backoff.as_secs());
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+    unreachable!("node list is never empty")
+}
+
+/// Print payments to or from `address` as `follow` sees them.
+pub async fn run_watch(nodes: &[String], address: &str, json: bool) -> ! {
+    follow(nodes, address, |event| {
+        for n in notices(event, address) {
+            if json {
+                println!("{}", serde_json::to_string(&n)?);
+            } else {
+                println!("{}", n);
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Print every block and transaction event as `follow` sees them.
+pub async fn run_tail(nodes: &[String], json: bool) -> ! {
+    follow(nodes, "new blocks and transactions", |event| {
+        if json {
+            println!("{}", tail_json(event));
+        } else {
+            println!("{}", tail_line(event));
+        }
+        Ok(())
+    })
+    .await
+}
+
+async fn follow_node(
+    node: &str,
+    what: &str,
+    on_event: &mut impl FnMut(&NodeEvent) -> anyhow::Result<()>,
+    backoff: &mut Duration,
+) -> anyhow::Result<()> {
+    let mut client = tokio::time::timeout(CONNECT_TIMEOUT, WsClient::connect(node))
+        .await
+        .map_err(|_| anyhow!("timed out connecting"))??;
+    if !client.supports("subscribe").await? {
+        bail!("node does not support subscribe");
+    }
+    let heartbeat = client.subscribe().await?;
+    let deadline = Duration::from_secs(heartbeat * (MISSED_HEARTBEATS + 1));
+    *backoff = Duration::from_secs(1);
+    eprintln!("{}", format!("Watching {} via {}", what, node).green());
+
+    loop {
+        let Some(msg) = tokio::time::timeout(deadline, client.next_message())
+            .await
+            .map_err(|_| anyhow!("no heartbeat for {}s", deadline.as_secs()))??
+        else {
+            return Ok(());
+        };
+        if msg.get("type").and_then(|t| t.as_str()) != Some("event") {
+            continue;
+        }
+        if msg.get("event").and_then(|e| e.as_str()) == Some("lagged") {
+            eprintln!(
+                "{}",
+                "Missed some events; the node was sending faster than we read".yellow()
+            );
+            continue;
+        }
+        let Ok(event) = serde_json::from_value::<NodeEvent>(msg) else {
+            continue;
+        };
+        on_event(&event)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Block;
+
+    fn tx(from: &str, to: &str, amount: i64) -> Transaction {
+        Transaction {
+            from: from.to_string(),
+            pub_key: String::new(),
+            to: to.to_string(),
+            amount,
+            signature: format!("{}{}", from, to),
+            expires_at_height: None,
+        }
+    }
+
+    #[test]
+    fn only_payments_touching_the_address_are_reported() {
+        let block = Block {
+            index: 1042,
+            timestamp: chrono::Utc::now(),
+            transactions: vec![
+                tx("coinbase", "04ME", 2500),
+                tx("04me", "bob", 700),
+                tx("carol", "bob", 1),
+            ],
+            prev_hash: String::new(),
+            hash: String::new(),
+            nonce: 0,
+            difficulty: 1,
+        };
+        let found = notices(&NodeEvent::NewBlock { block }, "04me");
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].to_string(), "Received 2.500 OWE in block 1042");
+        assert_eq!(found[1].to_string(), "Sent 0.700 OWE in block 1042");
+
+        let pending = NodeEvent::NewTx {
+            txid: "abc".to_string(),
+            tx: tx("carol", "04me", 1500),
+        };
+        let found = notices(&pending, "04me");
+        assert_eq!(
+            serde_json::to_value(&found[0]).unwrap(),
+            serde_json::json!({
+                "direction": "received",
+                "status": "pending",
+                "amount": 1500,
+                "counterparty": "carol",
+                "txid": "abc",
+            })
+        );
+        assert!(notices(&pending, "bob").is_empty());
+    }
+
+    #[test]
+    fn tail_prints_one_line_per_block_and_transaction() {
+        let block = Block {
+            index: 7,
+            timestamp: chrono::Utc::now(),
+            transactions: vec![tx("coinbase", "04minerabcdef", 2500), tx("a", "b", 1)],
+            prev_hash: String::new(),
+            hash: "0000abcdef123456".to_string(),
+            nonce: 0,
+            difficulty: 1,
+        };
+        let event = NodeEvent::NewBlock { block };
+        assert_eq!(
+            tail_line(&event),
+            "block 7 0000abcd 2 tx(s), reward 2.500 OWE to 04minera"
+        );
+        assert_eq!(tail_json(&event)["miner"], "04minerabcdef");
+        assert_eq!(tail_json(&event)["txs"], 2);
+
+        let event = NodeEvent::NewTx {
+            txid: "deadbeef0011".to_string(),
+            tx: tx("04alice0000", "04bob000000", 1500),
+        };
+        assert_eq!(
+            tail_line(&event),
+            "tx deadbeef 04alice0 -> 04bob000 1.500 OWE"
+        );
+        assert_eq!(tail_json(&event)["txid"], "deadbeef0011");
+    }
+}
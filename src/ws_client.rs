@@ -1,56 +1,432 @@
+use crate::daemon::Capabilities;
 use anyhow::anyhow;
 use futures::SinkExt;
 use futures::stream::StreamExt;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, client_async, tungstenite::Message};
 
-/// Connect to a WebSocket server and send a JSON-RPC-like command, returning the response.
-pub async fn ws_command(
-    addr: &str,
-    method: &str,
-    params: serde_json::Value,
-) -> anyhow::Result<serde_json::Value> {
-    let url = format!("ws://{}", addr);
-    let (ws_stream, _) = connect_async(&url).await?;
-    let (mut write, mut read) = ws_stream.split();
-
-    // Skip greeting if present
-    if let Some(greeting_msg) = read.next().await {
-        if let Ok(Message::Text(text)) = greeting_msg {
-            if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text) {
-                if json.get("type").and_then(|t| t.as_str()) == Some("greeting") {
-                    // Greeting received and skipped, continue
-                } else {
-                    // Not a greeting, we need to parse it as a response
-                    // This shouldn't happen in normal flow, but just in case
-                }
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Greeting a daemon sends as the first frame of every connection.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct Greeting {
+    pub height: u64,
+    /// Network the node belongs to; absent on nodes that predate it.
+    #[serde(default)]
+    pub network: Option<String>,
+    /// Absent on nodes that predate the handshake.
+    #[serde(default)]
+    pub genesis_hash: Option<String>,
+}
+
+impl Greeting {
+    /// On a dev network, whose daemons take blocks without proof of work.
+    pub fn is_dev(&self) -> bool {
+        self.network
+            .as_deref()
+            .is_some_and(|n| n.starts_with("owonero-dev"))
+    }
+}
+
+/// A node that greeted with another genesis block: a different coin built
+/// from this code. Kept apart from other connect errors so the daemon can
+/// stop retrying it.
+#[derive(Debug, thiserror::Error)]
+#[error("{addr} is on another chain: genesis {genesis_hash} (expected {expected})")]
+pub struct IncompatibleNode {
+    pub addr: String,
+    pub genesis_hash: String,
+    pub expected: String,
+}
+
+/// Set by `--standalone`: connections to nodes outside a standalone network
+/// are dropped right after the greeting, so a local test setup never pulls
+/// in the public chain.
+static STANDALONE_ONLY: AtomicBool = AtomicBool::new(false);
+
+pub fn set_standalone_only(enabled: bool) {
+    STANDALONE_ONLY.store(enabled, Ordering::Relaxed);
+}
+
+/// Config `ws_compression` on the asking side: when off, this process
+/// never asks a node for deflated responses. What a daemon grants is its
+/// own `DaemonContext` config.
+static REQUEST_COMPRESSION: AtomicBool = AtomicBool::new(true);
+
+pub fn set_request_compression(enabled: bool) {
+    REQUEST_COMPRESSION.store(enabled, Ordering::Relaxed);
+}
+
+tokio::task_local! {
+    /// Trace id of the request being served, sent with every command so
+    /// the node's log lines for it carry the same id.
+    static TRACE_ID: String;
+}
+
+/// Run `fut` with every command it sends tagged with `trace`.
+pub async fn traced<F: std::future::Future>(trace: String, fut: F) -> F::Output {
+    TRACE_ID.scope(trace, fut).await
+}
+
+fn current_trace() -> Option<String> {
+    TRACE_ID.try_with(|t| t.clone()).ok()
+}
+
+/// Refuse a node that isn't on a standalone network. Nodes that predate the
+/// greeting's `network` field are on the main network.
+fn check_standalone_peer(addr: &str, greeting: Option<&Greeting>) -> anyhow::Result<()> {
+    let network = greeting
+        .and_then(|g| g.network.as_deref())
+        .unwrap_or(crate::daemon::network_id(false, false));
+    if !network.ends_with("-standalone") {
+        return Err(anyhow!(
+            "refusing {} in standalone mode: it is on network {}",
+            addr,
+            network
+        ));
+    }
+    Ok(())
+}
+
+/// A single WebSocket connection to a node. Capabilities are fetched lazily
+/// once per connection so callers can gate optional protocol features.
+pub struct WsClient {
+    stream: WsStream,
+    capabilities: Option<Capabilities>,
+    /// Greeting received on connect, if the node sent a parseable one.
+    pub greeting: Option<Greeting>,
+}
+
+impl WsClient {
+    /// Connect to `addr` (host:port), trying each address a host name
+    /// resolves to, consume the greeting if present and say `hello`. A
+    /// node whose greeting names another genesis block is refused with
+    /// `IncompatibleNode`.
+    pub async fn connect(addr: &str) -> anyhow::Result<Self> {
+        let url = format!("ws://{}", addr);
+        let socket = crate::net::connect(addr).await?;
+        let (mut stream, _) = client_async(&url, MaybeTlsStream::Plain(socket)).await?;
+
+        // Consume the greeting if present
+        let mut greeting = None;
+        if let Some(greeting_msg) = stream.next().await
+            && let Ok(Message::Text(text)) = greeting_msg
+            && let Ok(json) = serde_json::from_str::<serde_json::Value>(&text)
+            && json.get("type").and_then(|t| t.as_str()) == Some("greeting")
+        {
+            greeting = serde_json::from_value(json).ok();
+        }
+        if STANDALONE_ONLY.load(Ordering::Relaxed) {
+            check_standalone_peer(addr, greeting.as_ref())?;
+        }
+        let expected = crate::blockchain::genesis_hash();
+        if let Some(genesis) = greeting.as_ref().and_then(|g| g.genesis_hash.as_deref())
+            && !genesis.eq_ignore_ascii_case(expected)
+        {
+            return Err(IncompatibleNode {
+                addr: addr.to_string(),
+                genesis_hash: genesis.to_string(),
+                expected: expected.to_string(),
             }
+            .into());
         }
+
+        let mut client = Self {
+            stream,
+            capabilities: None,
+            greeting,
+        };
+        client.hello(addr).await?;
+        Ok(client)
     }
 
-    // Send command
-    let cmd = serde_json::json!({"method": method, "params": params});
-    write.send(Message::Text(cmd.to_string())).await?;
+    /// Remote daemons process nothing before a compatible `hello`. Nodes
+    /// that predate it answer with an error, which is fine; one that finds
+    /// this client incompatible says why and hangs up.
+    async fn hello(&mut self, addr: &str) -> anyhow::Result<()> {
+        let params = serde_json::json!({
+            "protocol_version": crate::protocol::PROTOCOL_VERSION,
+            "genesis_hash": crate::blockchain::genesis_hash(),
+            "agent": format!("owonero/{}", env!("CARGO_PKG_VERSION")),
+        });
+        let resp = self.command("hello", params).await?;
+        if let Some(reason) = resp
+            .get("message")
+            .and_then(|m| m.as_str())
+            .filter(|m| m.starts_with("incompatible"))
+        {
+            return Err(anyhow!("{} refused this client: {}", addr, reason));
+        }
+        Ok(())
+    }
 
-    // Read response
-    if let Some(msg) = read.next().await {
-        match msg? {
-            Message::Text(text) => serde_json::from_str(&text)
-                .map_err(|e| anyhow!("Failed to parse WebSocket response: {}", e)),
-            _ => Err(anyhow!("Unexpected WebSocket message type")),
+    /// Send a JSON-RPC-like command and wait for its response.
+    pub async fn command(
+        &mut self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> anyhow::Result<serde_json::Value> {
+        let mut cmd = serde_json::json!({"method": method, "params": params});
+        if let Some(trace) = current_trace() {
+            cmd["trace"] = trace.into();
         }
-    } else {
-        Err(anyhow!("No response from server"))
+        self.stream.send(Message::Text(cmd.to_string())).await?;
+
+        let text = match self.stream.next().await {
+            Some(msg) => match msg? {
+                Message::Text(text) => text,
+                // Deflated response after `setcompression`
+                Message::Binary(data) => crate::daemon::decompress_message(
+                    &data,
+                    crate::protocol::MAX_INFLATED_MESSAGE_BYTES,
+                )
+                .map_err(|e| anyhow!("Failed to inflate WebSocket response: {}", e))?,
+                _ => return Err(anyhow!("Unexpected WebSocket message type")),
+            },
+            None => return Err(anyhow!("No response from server")),
+        };
+        serde_json::from_str(&text)
+            .map_err(|e| anyhow!("Failed to parse WebSocket response: {}", e))
+    }
+
+    /// `submittx` on this connection: the node's status, or its message
+    /// when it refused the transaction.
+    pub async fn submit_tx(
+        &mut self,
+        tx: &crate::blockchain::Transaction,
+    ) -> anyhow::Result<String> {
+        let resp = self
+            .command("submittx", serde_json::json!({"tx": tx}))
+            .await?;
+        submission_status(&resp, "Transaction rejected")
+    }
+
+    /// `submitblock` on this connection: the node's status, or its message
+    /// when it refused the block.
+    pub async fn submit_block(
+        &mut self,
+        block: &crate::blockchain::Block,
+    ) -> anyhow::Result<String> {
+        let resp = self
+            .command("submitblock", serde_json::json!({"block": block}))
+            .await?;
+        submission_status(&resp, "Block rejected")
+    }
+
+    /// Ask the node to deflate large responses on this connection. Returns
+    /// whether compression is on; false when disabled locally, refused by
+    /// the node or unsupported by it.
+    pub async fn enable_compression(&mut self) -> anyhow::Result<bool> {
+        if !REQUEST_COMPRESSION.load(Ordering::Relaxed) || !self.supports("setcompression").await? {
+            return Ok(false);
+        }
+        let resp = self
+            .command("setcompression", serde_json::json!({"enabled": true}))
+            .await?;
+        Ok(resp
+            .get("enabled")
+            .and_then(|e| e.as_bool())
+            .unwrap_or(false))
+    }
+
+    /// Capabilities advertised by the node. Nodes that predate
+    /// `getcapabilities` are reported with the legacy method set.
+    pub async fn capabilities(&mut self) -> anyhow::Result<&Capabilities> {
+        if self.capabilities.is_none() {
+            let resp = self
+                .command("getcapabilities", serde_json::json!({}))
+                .await?;
+            let caps = resp
+                .get("data")
+                .and_then(|d| serde_json::from_value::<Capabilities>(d.clone()).ok())
+                .unwrap_or_else(Capabilities::legacy);
+            self.capabilities = Some(caps);
+        }
+        Ok(self.capabilities.as_ref().unwrap())
+    }
+
+    /// Turn this connection into an event stream (`newblock`, `newtx` and
+    /// heartbeats, read with `next_message`). Returns the node's heartbeat
+    /// interval in seconds. Don't send commands on a subscribed connection:
+    /// events can arrive before their responses.
+    pub async fn subscribe(&mut self) -> anyhow::Result<u64> {
+        let resp = self.command("subscribe", serde_json::json!({})).await?;
+        if resp.get("type").and_then(|t| t.as_str()) != Some("response") {
+            let msg = resp
+                .get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("unknown");
+            return Err(anyhow!("subscribe refused: {}", msg));
+        }
+        Ok(resp
+            .get("heartbeat_secs")
+            .and_then(|h| h.as_u64())
+            .unwrap_or(crate::protocol::HEARTBEAT_SECS))
+    }
+
+    /// Next JSON message from the node, or `None` once it closes the
+    /// connection.
+    pub async fn next_message(&mut self) -> anyhow::Result<Option<serde_json::Value>> {
+        loop {
+            let text = match self.stream.next().await {
+                None => return Ok(None),
+                Some(msg) => match msg? {
+                    Message::Text(text) => text,
+                    Message::Binary(data) => crate::daemon::decompress_message(
+                        &data,
+                        crate::protocol::MAX_INFLATED_MESSAGE_BYTES,
+                    )?,
+                    Message::Close(_) => return Ok(None),
+                    _ => continue,
+                },
+            };
+            return Ok(Some(serde_json::from_str(&text)?));
+        }
+    }
+
+    /// Whether the node supports `method`.
+    pub async fn supports(&mut self, method: &str) -> anyhow::Result<bool> {
+        Ok(self.capabilities().await?.supports(method))
     }
+
+    /// The block at `index`, or the tip with `None`.
+    pub async fn block(&mut self, index: Option<u64>) -> anyhow::Result<crate::blockchain::Block> {
+        let resp = match index {
+            Some(index) => {
+                self.command("getblock", serde_json::json!({"index": index}))
+                    .await?
+            }
+            None => self.command("getlatest", serde_json::json!({})).await?,
+        };
+        match resp.get("data") {
+            Some(data) if !data.is_null() => serde_json::from_value(data.clone())
+                .map_err(|e| anyhow!("Failed to parse block: {}", e)),
+            _ => Err(anyhow!("No block in response")),
+        }
+    }
+
+    /// Blocks `from..=to`; the node serves at most `MAX_BLOCKS_PER_REQUEST`
+    /// per call.
+    pub async fn blocks(
+        &mut self,
+        from: u64,
+        to: u64,
+    ) -> anyhow::Result<Vec<crate::blockchain::Block>> {
+        let resp = self
+            .command("getblocks", serde_json::json!({"from": from, "to": to}))
+            .await?;
+        match resp.get("data") {
+            Some(data) => serde_json::from_value(data.clone())
+                .map_err(|e| anyhow!("Failed to parse blocks {}..{}: {}", from, to, e)),
+            None => Err(anyhow!(
+                "blocks {}..{} refused: {}",
+                from,
+                to,
+                resp.get("message")
+                    .and_then(|m| m.as_str())
+                    .unwrap_or("no data")
+            )),
+        }
+    }
+
+    /// Headers of blocks `from..=to`; the node serves at most
+    /// `MAX_HEADERS_PER_REQUEST` per call.
+    pub async fn headers(
+        &mut self,
+        from: u64,
+        to: u64,
+    ) -> anyhow::Result<Vec<crate::blockchain::BlockHeader>> {
+        let resp = self
+            .command("getheaders", serde_json::json!({"from": from, "to": to}))
+            .await?;
+        match resp.get("data") {
+            Some(data) => serde_json::from_value(data.clone())
+                .map_err(|e| anyhow!("Failed to parse headers {}..{}: {}", from, to, e)),
+            None => Err(anyhow!(
+                "headers {}..{} refused: {}",
+                from,
+                to,
+                resp.get("message")
+                    .and_then(|m| m.as_str())
+                    .unwrap_or("no data")
+            )),
+        }
+    }
+
+    /// The node's whole chain up to its tip when asked, in `getblocks`
+    /// slices, with `on_progress` called after each. Nodes that predate
+    /// `getblocks` send it as one `getchain` message.
+    pub async fn chain(
+        &mut self,
+        mut on_progress: impl FnMut(&crate::chain_sync::SyncProgress),
+    ) -> anyhow::Result<crate::blockchain::Blockchain> {
+        if !self.supports("getblocks").await? {
+            let resp = self.command("getchain", serde_json::json!({})).await?;
+            let data = resp
+                .get("data")
+                .ok_or_else(|| anyhow!("No data in response"))?;
+            return serde_json::from_value(data.clone())
+                .map_err(|e| anyhow!("Failed to parse chain: {}", e));
+        }
+        let started = std::time::Instant::now();
+        let height = self.block(None).await?.index;
+        let mut chain = crate::blockchain::Blockchain::new();
+        chain.chain.clear();
+        let mut from = 0;
+        while from <= height {
+            let to = height.min(from + crate::protocol::MAX_BLOCKS_PER_REQUEST - 1);
+            for block in self.blocks(from, to).await? {
+                // A reorg between slices would splice two branches
+                if chain
+                    .chain
+                    .last()
+                    .is_some_and(|p| p.hash != block.prev_hash)
+                {
+                    return Err(anyhow!(
+                        "chain changed at block {} while downloading",
+                        block.index
+                    ));
+                }
+                chain.chain.push(block);
+            }
+            from = to + 1;
+            on_progress(&crate::chain_sync::SyncProgress::new(
+                chain.chain.len() as u64,
+                height + 1,
+                started.elapsed(),
+            ));
+        }
+        Ok(chain)
+    }
+}
+
+/// Connect to a WebSocket server and send a JSON-RPC-like command, returning the response.
+pub async fn ws_command(
+    addr: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    WsClient::connect(addr).await?.command(method, params).await
+}
+
+/// Like `ws_command`, but negotiates compression first. Worth the extra
+/// round trips only for large responses such as the chain or mempool.
+pub async fn ws_command_compressed(
+    addr: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> anyhow::Result<serde_json::Value> {
+    let mut client = WsClient::connect(addr).await?;
+    client.enable_compression().await?;
+    client.command(method, params).await
 }
 
 /// Convenience: fetch chain from node via WebSocket
 pub async fn fetch_chain(node_addr: &str) -> anyhow::Result<crate::blockchain::Blockchain> {
-    let resp = ws_command(node_addr, "getchain", serde_json::json!({})).await?;
-    if let Some(data) = resp.get("data") {
-        serde_json::from_value(data.clone()).map_err(|e| anyhow!("Failed to parse chain: {}", e))
-    } else {
-        Err(anyhow!("No data in response"))
-    }
+    let mut client = WsClient::connect(node_addr).await?;
+    client.enable_compression().await?;
+    client.chain(|_| {}).await
 }
 
 /// Convenience: fetch latest block from node via WebSocket
@@ -63,9 +439,51 @@ pub async fn fetch_latest_block(node_addr: &str) -> anyhow::Result<crate::blockc
     }
 }
 
+/// Convenience: hashes of the node's last `depth` blocks, oldest first
+pub async fn fetch_tip_history(
+    node_addr: &str,
+    depth: usize,
+) -> anyhow::Result<Vec<crate::daemon::TipEntry>> {
+    let resp = ws_command(
+        node_addr,
+        "gettiphistory",
+        serde_json::json!({"depth": depth}),
+    )
+    .await?;
+    if let Some(data) = resp.get("data") {
+        serde_json::from_value(data.clone())
+            .map_err(|e| anyhow!("Failed to parse tip history: {}", e))
+    } else {
+        Err(anyhow!("No data in response"))
+    }
+}
+
+/// Convenience: fetch blocks `start..=end` over a single connection
+pub async fn fetch_block_range(
+    node_addr: &str,
+    start: u64,
+    end: u64,
+) -> anyhow::Result<Vec<crate::blockchain::Block>> {
+    let mut client = WsClient::connect(node_addr).await?;
+    let mut blocks = Vec::new();
+    for index in start..=end {
+        let resp = client
+            .command("getblock", serde_json::json!({"index": index}))
+            .await?;
+        let data = resp
+            .get("data")
+            .ok_or_else(|| anyhow!("Block {} not available", index))?;
+        blocks.push(
+            serde_json::from_value(data.clone())
+                .map_err(|e| anyhow!("Failed to parse block {}: {}", index, e))?,
+        );
+    }
+    Ok(blocks)
+}
+
 /// Convenience: fetch mempool from node via WebSocket
 pub async fn fetch_mempool(node_addr: &str) -> anyhow::Result<Vec<crate::blockchain::Transaction>> {
-    let resp = ws_command(node_addr, "getmempool", serde_json::json!({})).await?;
+    let resp = ws_command_compressed(node_addr, "getmempool", serde_json::json!({})).await?;
     if let Some(data) = resp.get("data") {
         serde_json::from_value(data.clone()).map_err(|e| anyhow!("Failed to parse mempool: {}", e))
     } else {
@@ -73,41 +491,490 @@ pub async fn fetch_mempool(node_addr: &str) -> anyhow::Result<Vec<crate::blockch
     }
 }
 
+/// Convenience: bulk-import transactions into a node's mempool (admin only).
+/// Returns the per-transaction accept/reject results.
+pub async fn import_mempool(
+    node_addr: &str,
+    txs: &[crate::blockchain::Transaction],
+) -> anyhow::Result<Vec<serde_json::Value>> {
+    let resp = ws_command(node_addr, "importmempool", serde_json::json!({"txs": txs})).await?;
+    if let Some(data) = resp.get("data").and_then(|d| d.as_array()) {
+        Ok(data.clone())
+    } else {
+        let message = resp
+            .get("message")
+            .and_then(|m| m.as_str())
+            .unwrap_or("no data in response");
+        Err(anyhow!("Import failed: {}", message))
+    }
+}
+
 /// Convenience: submit transaction to node via WebSocket
 pub async fn submit_tx(
     node_addr: &str,
     tx: &crate::blockchain::Transaction,
 ) -> anyhow::Result<String> {
-    let resp = ws_command(node_addr, "submittx", serde_json::json!({"tx": tx})).await?;
+    WsClient::connect(node_addr).await?.submit_tx(tx).await
+}
+
+/// The status line of a `submittx`/`submitblock` response: its `message`
+/// or `status`, `rejected` for a bare error.
+fn submission_status(resp: &serde_json::Value, rejected: &str) -> anyhow::Result<String> {
     if let Some(msg) = resp.get("message") {
         Ok(msg.as_str().unwrap_or("error").to_string())
     } else if let Some(status) = resp.get("status") {
         Ok(status.as_str().unwrap_or("error").to_string())
     } else if resp.get("type").and_then(|t| t.as_str()) == Some("error") {
-        Err(anyhow!("Transaction rejected"))
+        Err(anyhow!("{}", rejected))
     } else {
         Ok("ok".to_string())
     }
 }
 
-/// Convenience: submit block to node via WebSocket
-pub async fn submit_block(
+/// Bounds for `broadcast_tx_to_any`: nodes tried, time per node and time
+/// overall.
+const BROADCAST_MAX_ATTEMPTS: usize = 8;
+const BROADCAST_ATTEMPT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+const BROADCAST_DEADLINE: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Why `broadcast_tx_to_any` found no node to take a transaction.
+#[derive(Debug, Default)]
+pub struct BroadcastFailure {
+    /// Nodes that answered with a rejection, and what they said.
+    pub rejected: Vec<(String, String)>,
+    /// Nodes that could not be reached in time.
+    pub unreachable: Vec<(String, String)>,
+}
+
+impl std::fmt::Display for BroadcastFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |entries: &[(String, String)]| {
+            entries
+                .iter()
+                .map(|(node, why)| format!("{} ({})", node, why))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(f, "no node accepted the transaction")?;
+        if !self.rejected.is_empty() {
+            write!(f, "; rejected by: {}", list(&self.rejected))?;
+        }
+        if !self.unreachable.is_empty() {
+            write!(f, "; unreachable: {}", list(&self.unreachable))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BroadcastFailure {}
+
+/// Submit `tx` to each node in turn until one accepts it, skipping
+/// duplicates, and return the node that did. A rejection moves on to the
+/// next node too, since another node may know blocks this one lacks.
+pub async fn broadcast_tx_to_any(
+    nodes: &[String],
+    tx: &crate::blockchain::Transaction,
+) -> Result<String, BroadcastFailure> {
+    let deadline = tokio::time::Instant::now() + BROADCAST_DEADLINE;
+    let mut tried: Vec<&String> = Vec::new();
+    let mut failure = BroadcastFailure::default();
+    for node in nodes {
+        if tried.contains(&node) {
+            continue;
+        }
+        let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+        if tried.len() >= BROADCAST_MAX_ATTEMPTS || left.is_zero() {
+            break;
+        }
+        tried.push(node);
+        let attempt =
+            tokio::time::timeout(BROADCAST_ATTEMPT_TIMEOUT.min(left), submit_tx(node, tx));
+        match attempt.await {
+            Ok(Ok(status)) if status == "ok" => return Ok(node.clone()),
+            Ok(Ok(status)) => failure.rejected.push((node.clone(), status)),
+            Ok(Err(e)) => failure.unreachable.push((node.clone(), format!("{:#}", e))),
+            Err(_) => failure
+                .unreachable
+                .push((node.clone(), "timed out".to_string())),
+        }
+    }
+    Err(failure)
+}
+
+/// Convenience: fetch the peers a node knows about
+pub async fn fetch_peers(node_addr: &str) -> anyhow::Result<Vec<String>> {
+    let resp = ws_command(node_addr, "getpeers", serde_json::json!({})).await?;
+    if let Some(data) = resp.get("data") {
+        serde_json::from_value(data.clone()).map_err(|e| anyhow!("Failed to parse peers: {}", e))
+    } else {
+        Err(anyhow!("No data in response"))
+    }
+}
+
+/// Convenience: each peer's height, tip and fork status as last seen by the
+/// node (`getpeerinfo`)
+pub async fn fetch_peer_info(
     node_addr: &str,
-    block: &crate::blockchain::Block,
-) -> anyhow::Result<String> {
+) -> anyhow::Result<Vec<crate::daemon::PeerChainStatus>> {
+    let resp = ws_command(node_addr, "getpeerinfo", serde_json::json!({})).await?;
+    if let Some(data) = resp.get("data") {
+        serde_json::from_value(data.clone())
+            .map_err(|e| anyhow!("Failed to parse peer info: {}", e))
+    } else {
+        Err(anyhow!("No data in response"))
+    }
+}
+
+/// Convenience: node overview and per-method request metrics (`getinfo`)
+pub async fn fetch_info(node_addr: &str) -> anyhow::Result<serde_json::Value> {
+    let mut resp = ws_command(node_addr, "getinfo", serde_json::json!({})).await?;
+    if resp.get("type").and_then(|t| t.as_str()) == Some("error") {
+        let msg = resp
+            .get("message")
+            .and_then(|m| m.as_str())
+            .unwrap_or("unknown");
+        return Err(anyhow!("getinfo failed: {}", msg));
+    }
+    if let Some(obj) = resp.as_object_mut() {
+        obj.remove("type");
+        obj.remove("method");
+    }
+    Ok(resp)
+}
+
+/// Convenience: coins minted, burned and in circulation (`getsupply`)
+pub async fn fetch_supply(node_addr: &str) -> anyhow::Result<crate::blockchain::Supply> {
+    let resp = ws_command(node_addr, "getsupply", serde_json::json!({})).await?;
+    serde_json::from_value(resp.clone()).map_err(|_| {
+        anyhow!(
+            "getsupply failed: {}",
+            resp.get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("no supply in response")
+        )
+    })
+}
+
+/// Convenience: query the lifecycle status of a transaction by txid
+pub async fn fetch_tx_status(
+    node_addr: &str,
+    txid: &str,
+) -> anyhow::Result<crate::daemon::TxStatus> {
+    let resp = ws_command(node_addr, "gettxstatus", serde_json::json!({"txid": txid})).await?;
+    if let Some(data) = resp.get("data") {
+        serde_json::from_value(data.clone())
+            .map_err(|e| anyhow!("Failed to parse tx status: {}", e))
+    } else {
+        Err(anyhow!("No data in response"))
+    }
+}
+
+/// Convenience: the node's last `limit` mempool admission decisions (admin only)
+pub async fn fetch_mempool_journal(
+    node_addr: &str,
+    limit: usize,
+) -> anyhow::Result<Vec<crate::daemon::AdmissionEntry>> {
     let resp = ws_command(
         node_addr,
-        "submitblock",
-        serde_json::json!({"block": block}),
+        "getmempooljournal",
+        serde_json::json!({"limit": limit}),
     )
     .await?;
-    if let Some(msg) = resp.get("message") {
-        Ok(msg.as_str().unwrap_or("error").to_string())
-    } else if let Some(status) = resp.get("status") {
-        Ok(status.as_str().unwrap_or("error").to_string())
-    } else if resp.get("type").and_then(|t| t.as_str()) == Some("error") {
-        Err(anyhow!("Block rejected"))
+    if let Some(data) = resp.get("data") {
+        serde_json::from_value(data.clone())
+            .map_err(|e| anyhow!("Failed to parse mempool journal: {}", e))
     } else {
-        Ok("ok".to_string())
+        Err(anyhow!(
+            "getmempooljournal failed: {}",
+            resp.get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("no data in response")
+        ))
+    }
+}
+
+/// Convenience: one page of blocks whose coinbase paid `address`
+pub async fn fetch_mined_blocks(
+    node_addr: &str,
+    address: &str,
+    limit: usize,
+    before: Option<u64>,
+) -> anyhow::Result<crate::daemon::MinedBlocksPage> {
+    let resp = ws_command(
+        node_addr,
+        "getminedblocks",
+        serde_json::json!({"address": address, "limit": limit, "before": before}),
+    )
+    .await?;
+    if let Some(data) = resp.get("data") {
+        serde_json::from_value(data.clone())
+            .map_err(|e| anyhow!("Failed to parse mined blocks: {}", e))
+    } else {
+        Err(anyhow!("No data in response"))
+    }
+}
+
+/// Convenience: confirmed balance of `address` after block `height`
+/// (the tip when `None`). Returns `(height, balance)`.
+pub async fn fetch_balance(
+    node_addr: &str,
+    address: &str,
+    height: Option<u64>,
+) -> anyhow::Result<(u64, i64)> {
+    let resp = ws_command(
+        node_addr,
+        "getbalance",
+        serde_json::json!({"address": address, "height": height}),
+    )
+    .await?;
+    match (
+        resp.get("height").and_then(|h| h.as_u64()),
+        resp.get("balance").and_then(|b| b.as_i64()),
+    ) {
+        (Some(height), Some(balance)) => Ok((height, balance)),
+        _ => Err(anyhow!(
+            "getbalance failed: {}",
+            resp.get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("no balance in response")
+        )),
+    }
+}
+
+/// Convenience: confirmed balance of `address` at the tip and what its
+/// pending transactions spend of it. Returns `(height, balance,
+/// pending_out)`.
+pub async fn fetch_pending_balance(
+    node_addr: &str,
+    address: &str,
+) -> anyhow::Result<(u64, i64, i64)> {
+    let resp = ws_command(
+        node_addr,
+        "getbalance",
+        serde_json::json!({"address": address, "include_pending": true}),
+    )
+    .await?;
+    match (
+        resp.get("height").and_then(|h| h.as_u64()),
+        resp.get("balance").and_then(|b| b.as_i64()),
+        resp.get("pending_out").and_then(|p| p.as_i64()),
+    ) {
+        (Some(height), Some(balance), Some(pending_out)) => Ok((height, balance, pending_out)),
+        _ => Err(anyhow!(
+            "getbalance failed: {}",
+            resp.get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("no pending balance in response")
+        )),
+    }
+}
+
+/// Convenience: submit block to node via WebSocket
+pub async fn submit_block(
+    node_addr: &str,
+    block: &crate::blockchain::Block,
+) -> anyhow::Result<String> {
+    WsClient::connect(node_addr)
+        .await?
+        .submit_block(block)
+        .await
+}
+
+/// Nonces tried per `getwork` job before asking for a fresh one, which also
+/// picks up a moved tip.
+const GETWORK_NONCES_PER_JOB: u32 = 4096;
+
+/// A `getwork` job: everything needed to search for a nonce without
+/// knowing what a block is.
+#[derive(Debug, Clone)]
+pub struct WorkJob {
+    pub job_id: String,
+    pub height: u64,
+    pub difficulty: u32,
+    pub timestamp: String,
+    pub prefix: Vec<u8>,
+    pub middle: Vec<u8>,
+}
+
+impl WorkJob {
+    /// Bytes to hash for `nonce`: `prefix ++ timestamp ++ middle ++ nonce ++ "}"`.
+    pub fn preimage(&self, nonce: u32) -> Vec<u8> {
+        crate::blockchain::work_preimage(&self.prefix, &self.timestamp, &self.middle, nonce)
+    }
+
+    /// First nonce in `range` whose hash meets the job's difficulty.
+    pub fn search(&self, range: std::ops::Range<u32>) -> Option<u32> {
+        range.into_iter().find(|&nonce| {
+            let hash = crate::blockchain::Blockchain::hash_preimage(&self.preimage(nonce));
+            crate::blockchain::hash_meets_difficulty(&hash, self.difficulty)
+        })
+    }
+}
+
+/// Convenience: ask for a job paying `wallet`
+pub async fn fetch_work(client: &mut WsClient, wallet: &str) -> anyhow::Result<WorkJob> {
+    let resp = client
+        .command("getwork", serde_json::json!({"wallet": wallet}))
+        .await?;
+    let text = |name: &str| {
+        resp.get(name)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("getwork reply has no {}", name))
+    };
+    let bytes = |name: &str| {
+        text(name).and_then(|h| hex::decode(h).map_err(|e| anyhow!("bad {}: {}", name, e)))
+    };
+    Ok(WorkJob {
+        job_id: text("job_id")?.to_string(),
+        height: resp
+            .get("height")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| anyhow!("getwork reply has no height"))?,
+        difficulty: resp
+            .get("difficulty")
+            .and_then(|v| v.as_u64())
+            .and_then(|d| u32::try_from(d).ok())
+            .ok_or_else(|| anyhow!("getwork reply has no difficulty"))?,
+        timestamp: text("timestamp")?.to_string(),
+        prefix: bytes("prefix")?,
+        middle: bytes("middle")?,
+    })
+}
+
+/// Convenience: hand in a solved job, returning the node's status line
+pub async fn submit_work(
+    client: &mut WsClient,
+    job: &WorkJob,
+    nonce: u32,
+) -> anyhow::Result<String> {
+    let resp = client
+        .command(
+            "submitwork",
+            serde_json::json!({"job_id": job.job_id, "nonce": nonce, "timestamp": job.timestamp}),
+        )
+        .await?;
+    resp.get("status")
+        .or_else(|| resp.get("message"))
+        .and_then(|s| s.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("submitwork reply has no status"))
+}
+
+/// Reference `getwork` miner: one thread, one connection, no chain copy.
+/// Mines until `blocks` blocks are accepted and returns their heights.
+pub async fn mine_getwork(node_addr: &str, wallet: &str, blocks: u64) -> anyhow::Result<Vec<u64>> {
+    let mut client = WsClient::connect(node_addr).await?;
+    let mut mined = Vec::new();
+    while (mined.len() as u64) < blocks {
+        let job = fetch_work(&mut client, wallet).await?;
+        let searched = job.clone();
+        let found =
+            tokio::task::spawn_blocking(move || searched.search(0..GETWORK_NONCES_PER_JOB)).await?;
+        let Some(nonce) = found else {
+            continue;
+        };
+        let status = submit_work(&mut client, &job, nonce).await?;
+        if status == "ok" {
+            mined.push(job.height);
+        } else if !status.starts_with("stale") {
+            return Err(anyhow!("block {} {}", job.height, status));
+        }
+    }
+    Ok(mined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A node that answers every command with `reply` and counts requests.
+    async fn stub_node(reply: serde_json::Value) -> (String, Arc<AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let reply = reply.clone();
+                let counter = counter.clone();
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+                    let greeting = serde_json::json!({"type": "greeting", "height": 0});
+                    ws.send(Message::Text(greeting.to_string())).await.unwrap();
+                    while let Some(Ok(Message::Text(text))) = ws.next().await {
+                        if text.contains(r#""method":"hello""#) {
+                            let ok = serde_json::json!({"type": "response", "method": "hello"});
+                            let _ = ws.send(Message::Text(ok.to_string())).await;
+                            continue;
+                        }
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let _ = ws.send(Message::Text(reply.to_string())).await;
+                    }
+                });
+            }
+        });
+        (addr, hits)
+    }
+
+    #[tokio::test]
+    async fn broadcast_stops_at_the_first_node_that_accepts() {
+        let tx = crate::wallet::Wallet::new()
+            .unwrap()
+            .create_signed_transaction("bob", 1)
+            .unwrap();
+        let rejected =
+            serde_json::json!({"type": "error", "message": "rejected: insufficient funds"});
+        let accepted =
+            serde_json::json!({"type": "response", "method": "submittx", "status": "ok"});
+        let (refuser, refuser_hits) = stub_node(rejected).await;
+        let (taker, taker_hits) = stub_node(accepted.clone()).await;
+        let (spare, spare_hits) = stub_node(accepted).await;
+
+        let dead = "127.0.0.1:1".to_string();
+        let nodes = vec![
+            dead.clone(),
+            refuser.clone(),
+            dead,
+            refuser,
+            taker.clone(),
+            spare,
+        ];
+        assert_eq!(broadcast_tx_to_any(&nodes, &tx).await.unwrap(), taker);
+        // Duplicates are tried once and nothing after the taker is contacted
+        assert_eq!(refuser_hits.load(Ordering::SeqCst), 1);
+        assert_eq!(taker_hits.load(Ordering::SeqCst), 1);
+        assert_eq!(spare_hits.load(Ordering::SeqCst), 0);
+
+        let failure = broadcast_tx_to_any(&nodes[..4], &tx).await.unwrap_err();
+        assert_eq!(failure.rejected.len(), 1);
+        assert_eq!(failure.unreachable.len(), 1);
+        let msg = failure.to_string();
+        assert!(
+            msg.contains("rejected by:") && msg.contains("insufficient funds"),
+            "{}",
+            msg
+        );
+        assert!(msg.contains("unreachable: 127.0.0.1:1 ("), "{}", msg);
+    }
+
+    #[test]
+    fn standalone_clients_only_accept_standalone_nodes() {
+        let greeting = |network: Option<&str>| Greeting {
+            network: network.map(str::to_string),
+            ..Greeting::default()
+        };
+        assert!(check_standalone_peer("n", Some(&greeting(Some("owonero-standalone")))).is_ok());
+        assert!(
+            check_standalone_peer("n", Some(&greeting(Some("owonero-dev-standalone")))).is_ok()
+        );
+        let err = check_standalone_peer("n:1", Some(&greeting(Some("owonero")))).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "refusing n:1 in standalone mode: it is on network owonero"
+        );
+        assert!(check_standalone_peer("n", Some(&greeting(None))).is_err());
+        assert!(check_standalone_peer("n", None).is_err());
     }
 }